@@ -12,6 +12,9 @@
 use std::io::SeekFrom;
 use std::io::{prelude::*, Result};
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
 extern "C" {
     #[no_mangle]
@@ -120,6 +123,8 @@ pub struct tt_bridge_api_t {
         ) -> rust_input_handle_t,
     >,
     pub input_open_primary: Option<unsafe fn(_: *mut libc::c_void) -> rust_input_handle_t>,
+    pub input_open_memory:
+        Option<unsafe fn(_: *mut libc::c_void, _: *const u8, _: size_t) -> rust_input_handle_t>,
     pub input_get_size: Option<unsafe fn(_: *mut libc::c_void, _: rust_input_handle_t) -> size_t>,
     pub input_seek: Option<
         unsafe fn(
@@ -137,6 +142,7 @@ pub struct tt_bridge_api_t {
     pub input_ungetc:
         Option<unsafe fn(_: *mut libc::c_void, _: rust_input_handle_t, _: i32) -> i32>,
     pub input_close: Option<unsafe fn(_: *mut libc::c_void, _: rust_input_handle_t) -> i32>,
+    pub page_output: Option<unsafe fn(_: *mut libc::c_void, _: i32) -> ()>,
 }
 
 #[repr(C)]
@@ -176,8 +182,49 @@ pub enum TTInputFormat {
     TECTONIC_PRIMARY = 59, /* quasi-hack to get the primary input */
 }
 
+/// Resource limits that the engines check while they run. The caller
+/// installs them with `tt_set_engine_limits` before entering an engine; the
+/// page and byte counters are reset every time an engine is entered.
+#[derive(Clone, Debug, Default)]
+pub struct tt_engine_limits_t {
+    pub cancel: Option<Arc<AtomicBool>>,
+    pub deadline: Option<Instant>,
+    pub max_pages: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+}
+
+/// The reason that an engine run was stopped early.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TTLimitViolation {
+    Cancelled,
+    WallTime,
+    Pages,
+    OutputBytes,
+}
+
+impl TTLimitViolation {
+    fn message(self) -> &'static str {
+        match self {
+            TTLimitViolation::Cancelled => "processing was cancelled",
+            TTLimitViolation::WallTime => "wall-time limit exceeded",
+            TTLimitViolation::Pages => "output page limit exceeded",
+            TTLimitViolation::OutputBytes => "output size limit exceeded",
+        }
+    }
+}
+
 static mut tectonic_global_bridge: *const tt_bridge_api_t = std::ptr::null();
 
+static mut tectonic_global_limits: Option<tt_engine_limits_t> = None;
+static mut limit_violation: Option<TTLimitViolation> = None;
+static mut limit_pages_out: usize = 0;
+static mut limit_bytes_out: usize = 0;
+static mut limit_check_count: u32 = 0;
+
+/* Looking at the clock is a lot more expensive than looking at the
+ * cancellation flag, so we only do it every so often. */
+const DEADLINE_CHECK_INTERVAL: u32 = 4096;
+
 pub unsafe fn tt_with_bridge<F, T>(bridge: *const tt_bridge_api_t, f: F) -> Option<T>
 where
     F: std::panic::UnwindSafe + std::ops::FnOnce() -> T,
@@ -185,11 +232,94 @@ where
     use std::panic;
     use std::ptr::null;
     tectonic_global_bridge = bridge;
+    limit_violation = None;
+    limit_pages_out = 0;
+    limit_bytes_out = 0;
+    limit_check_count = 0;
     let r = panic::catch_unwind(|| (f)()).ok();
     tectonic_global_bridge = null();
     r
 }
 
+pub unsafe fn tt_set_engine_limits(limits: Option<tt_engine_limits_t>) {
+    tectonic_global_limits = limits;
+}
+
+/// Returns the limit that stopped the most recent engine run, if any, and
+/// resets it.
+pub unsafe fn tt_take_limit_violation() -> Option<TTLimitViolation> {
+    limit_violation.take()
+}
+
+unsafe fn limit_abort(v: TTLimitViolation) -> ! {
+    let msg = v.message().as_bytes();
+    error_buf[..msg.len()].copy_from_slice(msg);
+    error_buf[msg.len()] = 0;
+    limit_violation = Some(v);
+    panic!("{}", v.message());
+}
+
+/// Called periodically by the engines' main loops. If the run has been
+/// cancelled or has run out of time, this unwinds out of the engine.
+pub unsafe fn ttstub_check_limits() {
+    let limits = match tectonic_global_limits {
+        Some(ref l) => l,
+        None => return,
+    };
+
+    if let Some(ref c) = limits.cancel {
+        if c.load(Ordering::Relaxed) {
+            limit_abort(TTLimitViolation::Cancelled);
+        }
+    }
+
+    if let Some(deadline) = limits.deadline {
+        limit_check_count = limit_check_count.wrapping_add(1);
+        if limit_check_count % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+            limit_abort(TTLimitViolation::WallTime);
+        }
+    }
+}
+
+/// Called by the engines every time they emit a page of output. For the TeX
+/// engine `number` is the page's `\count0`; for xdvipdfmx it is the
+/// (1-based) number of the DVI page that was just written.
+pub unsafe fn ttstub_note_page_output(number: i32) {
+    if let Some(f) = (*tectonic_global_bridge).page_output {
+        f((*tectonic_global_bridge).context, number);
+    }
+
+    limit_pages_out += 1;
+
+    if let Some(ref limits) = tectonic_global_limits {
+        if let Some(max) = limits.max_pages {
+            if limit_pages_out > max {
+                limit_abort(TTLimitViolation::Pages);
+            }
+        }
+
+        if let Some(deadline) = limits.deadline {
+            if Instant::now() >= deadline {
+                limit_abort(TTLimitViolation::WallTime);
+            }
+        }
+    }
+
+    ttstub_check_limits();
+}
+
+unsafe fn note_output_bytes(n: usize) {
+    limit_bytes_out += n;
+
+    if let Some(ref limits) = tectonic_global_limits {
+        if let Some(max) = limits.max_output_bytes {
+            if limit_bytes_out > max {
+                limit_abort(TTLimitViolation::OutputBytes);
+            }
+        }
+    }
+}
+
 pub(crate) unsafe fn tt_get_current_bridge() -> Option<&'static tt_bridge_api_t> {
     tectonic_global_bridge.as_ref()
 }
@@ -282,6 +412,7 @@ pub unsafe fn ttstub_output_open_stdout() -> Option<OutputHandleWrapper> {
 }
 
 pub unsafe fn ttstub_output_putc(handle: &mut OutputHandleWrapper, mut c: i32) -> i32 {
+    note_output_bytes(1);
     (*tectonic_global_bridge)
         .output_putc
         .expect("non-null function pointer")(
@@ -296,6 +427,7 @@ pub(crate) unsafe fn ttstub_output_write(
     mut data: *const i8,
     mut len: size_t,
 ) -> size_t {
+    note_output_bytes(len);
     (*tectonic_global_bridge)
         .output_write
         .expect("non-null function pointer")(
@@ -340,6 +472,18 @@ pub unsafe fn ttstub_input_open_primary() -> Option<InputHandleWrapper> {
     ))
 }
 
+/// Open a read-only input handle over a copy of `data`, for handing decoded
+/// file contents to code that expects to read from a handle.
+pub unsafe fn ttstub_input_open_memory(data: &[u8]) -> Option<InputHandleWrapper> {
+    InputHandleWrapper::new((*tectonic_global_bridge)
+        .input_open_memory
+        .expect("non-null function pointer")(
+        (*tectonic_global_bridge).context,
+        data.as_ptr(),
+        data.len(),
+    ))
+}
+
 pub unsafe fn ttstub_input_get_size(handle: &mut InputHandleWrapper) -> size_t {
     (*tectonic_global_bridge)
         .input_get_size
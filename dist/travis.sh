@@ -210,6 +210,15 @@ else
     travis_fold_start cargo_test "cargo test" verbose
     cargo test
     travis_fold_end cargo_test
+
+    # Without the "icu" feature, Unicode text processing is done in Rust
+    # rather than by ICU; make sure that configuration keeps working too.
+    if [[ "$TRAVIS_OS_NAME" == linux ]] ; then
+        travis_fold_start cargo_test_no_icu "cargo test (without ICU)" verbose
+        (cd engine && cargo test --no-default-features)
+        cargo test --no-default-features --features serialization
+        travis_fold_end cargo_test_no_icu
+    fi
 fi
 
 # OK! If we got this far, we think we made a functional set of (debug-mode)
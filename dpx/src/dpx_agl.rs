@@ -36,7 +36,7 @@ use super::dpx_dpxutil::{ht_append_table, ht_clear_table, ht_init_table, ht_look
 use super::dpx_mem::new;
 use super::dpx_mfileio::tt_mfgets;
 use super::dpx_pdfparse::{parse_ident, skip_white};
-use super::dpx_unicode::{UC_UTF16BE_encode_char, UC_is_valid};
+use super::dpx_unicode::{UC_UTF16BE_encode_char, UC_UTF16BE_to_string, UC_is_valid};
 use crate::bridge::ttstub_input_close;
 use libc::{free, memcpy, strchr, strlen, strtol};
 
@@ -720,6 +720,22 @@ pub(crate) unsafe fn agl_sput_UTF16BE(
     len
 }
 
+/// Get the Unicode text of a glyph name, as agl_sput_UTF16BE() would put
+/// it into a ToUnicode CMap. Returns `None` if any part of the name can't
+/// be mapped. The glyph list must have been loaded with agl_init_map().
+pub(crate) unsafe fn agl_name_to_unicode(glyphname: &str) -> Option<String> {
+    let glyphname = CString::new(glyphname).ok()?;
+    let mut wbuf: [u8; 1024] = [0; 1024];
+    let mut p = wbuf.as_mut_ptr();
+    let endptr = wbuf.as_mut_ptr().offset(1024);
+    let mut fail_count: i32 = 0i32;
+    let len = agl_sput_UTF16BE(glyphname.as_ptr(), &mut p, endptr, &mut fail_count);
+    if len < 1i32 || fail_count != 0 {
+        return None;
+    }
+    UC_UTF16BE_to_string(&wbuf[..len as usize])
+}
+
 pub(crate) unsafe fn agl_get_unicodes(
     glyphstr: *const i8,
     unicodes: *mut i32,
@@ -1027,6 +1027,63 @@ pub(crate) unsafe fn cff_get_index_header(cff: &mut cff_font) -> *mut cff_index
     idx
 }
 
+/// Reads the offsets of the CharStrings INDEX, returning the INDEX and
+/// the position of its data in the file. The charstrings themselves are
+/// then fetched one at a time by `cff_read_charstring()`. A font built in
+/// memory hands over its complete CharStrings INDEX instead.
+pub(crate) unsafe fn cff_get_charstrings_header(cff: &mut cff_font) -> (*mut cff_index, u64) {
+    if cff.handle.is_none() {
+        let idx = cff.cstrings;
+        cff.cstrings = ptr::null_mut();
+        return (idx, 0);
+    }
+    let offset = cff_dict_get(
+        cff.topdict,
+        b"CharStrings\x00" as *const u8 as *const i8,
+        0i32,
+    ) as u64;
+    cff.handle
+        .as_mut()
+        .unwrap()
+        .seek(SeekFrom::Start(cff.offset as u64 + offset))
+        .unwrap();
+    let idx = cff_get_index_header(cff);
+    /* offset is now absolute offset ... bad */
+    let data_offset = cff
+        .handle
+        .as_mut()
+        .unwrap()
+        .seek(SeekFrom::Current(0))
+        .unwrap();
+    (idx, data_offset)
+}
+
+/// Copies the `size` bytes of charstring `gid` of an INDEX obtained from
+/// `cff_get_charstrings_header()` to `dest`.
+pub(crate) unsafe fn cff_read_charstring(
+    cff: &mut cff_font,
+    idx: *mut cff_index,
+    data_offset: u64,
+    gid: u16,
+    dest: *mut u8,
+    size: usize,
+) {
+    let start = *(*idx).offset.offset(gid as isize) as u64 - 1;
+    match cff.handle.as_mut() {
+        Some(handle) => {
+            handle.seek(SeekFrom::Start(data_offset + start)).unwrap();
+            ttstub_input_read(handle.as_ptr(), dest as *mut i8, size as size_t);
+        }
+        None => {
+            memcpy(
+                dest as *mut libc::c_void,
+                (*idx).data.offset(start as isize) as *const libc::c_void,
+                size,
+            );
+        }
+    }
+}
+
 pub(crate) unsafe fn cff_get_index(cff: &mut cff_font) -> *mut cff_index {
     let idx = new((1_u64).wrapping_mul(::std::mem::size_of::<cff_index>() as u64) as u32)
         as *mut cff_index;
@@ -1590,6 +1647,10 @@ pub(crate) unsafe fn cff_read_charsets(cff: &mut cff_font) -> i32 {
     if cff.topdict.is_null() {
         panic!("Top DICT not available");
     }
+    if cff.handle.is_none() {
+        /* fonts built in memory come with all their data */
+        return 0i32;
+    }
     if !cff_dict_known(cff.topdict, b"charset\x00" as *const u8 as *const i8) {
         cff.flag |= 1i32 << 5i32;
         cff.charsets = ptr::null_mut();
@@ -1977,6 +2038,10 @@ pub(crate) unsafe fn cff_read_fdselect(cff: &mut cff_font) -> i32 {
     if cff.topdict.is_null() {
         panic!("Top DICT not available");
     }
+    if cff.handle.is_none() {
+        /* fonts built in memory come with all their data */
+        return 0i32;
+    }
     if cff.flag & 1i32 << 0i32 == 0 {
         return 0i32;
     }
@@ -2113,6 +2178,10 @@ pub(crate) unsafe fn cff_fdselect_lookup(cff: &cff_font, gid: u16) -> u8 {
 
 pub(crate) unsafe fn cff_read_subrs(cff: &mut cff_font) -> i32 {
     let mut len: i32 = 0i32;
+    if cff.handle.is_none() {
+        /* fonts built in memory come with all their data */
+        return 0i32;
+    }
     if cff.flag & 1i32 << 0i32 != 0 && cff.fdarray.is_null() {
         cff_read_fdarray(cff);
     }
@@ -2195,6 +2264,10 @@ pub(crate) unsafe fn cff_read_fdarray(cff: &mut cff_font) -> i32 {
     if cff.topdict.is_null() {
         panic!("in cff_read_fdarray(): Top DICT not found");
     }
+    if cff.handle.is_none() {
+        /* fonts built in memory come with all their data */
+        return 0i32;
+    }
     if cff.flag & 1i32 << 0i32 == 0 {
         return 0i32;
     }
@@ -2272,6 +2345,10 @@ pub(crate) unsafe fn cff_read_fdarray(cff: &mut cff_font) -> i32 {
 pub(crate) unsafe fn cff_read_private(cff: &mut cff_font) -> i32 {
     let mut len: i32 = 0i32;
     let mut size: i32 = 0;
+    if cff.handle.is_none() {
+        /* fonts built in memory come with all their data */
+        return 0i32;
+    }
     if cff.flag & 1i32 << 0i32 != 0 {
         if cff.fdarray.is_null() {
             cff_read_fdarray(cff);
@@ -0,0 +1,778 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2020 the Tectonic Project
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_camel_case_types)]
+
+//! CFF2 outlines.
+//!
+//! CFF2 is the successor of CFF used by OpenType fonts with PostScript
+//! outlines, and the only one of the two that supports font variations.
+//! PDF knows nothing about it, so a `CFF2` table is converted when the font
+//! is opened: the charstrings are evaluated at the selected instance, which
+//! resolves `blend` and drops `vsindex`, subroutines are inlined and each
+//! charstring gets its `endchar` back. The result is presented as an
+//! in-memory, CID-keyed `cff_font` in Adobe-Identity ordering (so that
+//! CIDs are glyph indices) and embedded by the CIDFontType0 code as
+//! FontFile3.
+
+use crate::warn;
+
+use super::dpx_cff::{
+    cff_add_string, cff_charsets, cff_dict, cff_fdselect, cff_font, cff_get_name, cff_index,
+    cff_new_index, cff_range2, cff_set_name, cff_update_string,
+};
+use super::dpx_cff_dict::{cff_dict_add, cff_dict_set, cff_new_dict};
+use super::dpx_mem::new;
+use super::dpx_sfnt::sfnt;
+use super::dpx_tt_table::tt_get_ps_fontname;
+use super::dpx_tt_var::{read_table, tt_item_variation_store};
+
+use std::ffi::CString;
+use std::ptr;
+
+fn get_u16(data: &[u8], pos: usize) -> Option<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| (b[0] as u16) << 8 | b[1] as u16)
+}
+
+fn get_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+}
+
+/* Two-byte operators are numbered 12 << 8 | b1. */
+const OP_CHARSTRINGS: u16 = 17;
+const OP_PRIVATE: u16 = 18;
+const OP_SUBRS: u16 = 19;
+const OP_VSINDEX: u16 = 22;
+const OP_VSTORE: u16 = 24;
+const OP_FONTMATRIX: u16 = 12 << 8 | 7;
+const OP_FDARRAY: u16 = 12 << 8 | 36;
+const OP_FDSELECT: u16 = 12 << 8 | 37;
+
+/// A DICT as a list of operators with their operands, blends applied.
+type cff2_dict = Vec<(u16, Vec<f64>)>;
+
+fn dict_get(dict: &[(u16, Vec<f64>)], op: u16) -> Option<&[f64]> {
+    dict.iter().find(|e| e.0 == op).map(|e| &e.1[..])
+}
+
+/// The CFF2 outlines of a font, converted to Type 2 charstrings for one
+/// instance.
+pub(crate) struct cff2_outlines {
+    pub(crate) font_matrix: Option<Vec<f64>>,
+    /// The Private DICT of each Font DICT, without `Subrs` and `vsindex`.
+    pub(crate) private: Vec<cff2_dict>,
+    /// The Font DICT of each glyph.
+    pub(crate) fd_select: Vec<u8>,
+    pub(crate) charstrings: Vec<Vec<u8>>,
+}
+
+/// Read a CFF2 INDEX, which differs from a CFF one in having a 32-bit count.
+fn read_index(data: &[u8], pos: usize) -> Option<Vec<&[u8]>> {
+    let count = get_u32(data, pos)? as usize;
+    if count == 0 {
+        return Some(Vec::new());
+    }
+    let off_size = *data.get(pos + 4)? as usize;
+    if !(1..=4).contains(&off_size) {
+        return None;
+    }
+    let read_offset = |i: usize| -> Option<usize> {
+        let start = pos + 5 + i * off_size;
+        let bytes = data.get(start..start + off_size)?;
+        Some(bytes.iter().fold(0, |v, &b| v << 8 | b as usize))
+    };
+    /* offsets are relative to the byte preceding the object data */
+    let base = (pos + 5 + (count + 1) * off_size).checked_sub(1)?;
+    let mut items = Vec::with_capacity(count);
+    let mut prev = read_offset(0)?;
+    for i in 1..=count {
+        let next = read_offset(i)?;
+        items.push(data.get(base + prev..base + next)?);
+        prev = next;
+    }
+    Some(items)
+}
+
+fn read_real(data: &[u8], pos: &mut usize) -> Option<f64> {
+    let mut s = String::new();
+    loop {
+        let b = *data.get(*pos)?;
+        *pos += 1;
+        for &nibble in &[b >> 4, b & 0xf] {
+            match nibble {
+                0..=9 => s.push((b'0' + nibble) as char),
+                0xa => s.push('.'),
+                0xb => s.push('E'),
+                0xc => s.push_str("E-"),
+                0xe => s.push('-'),
+                0xf => return s.parse().ok(),
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Applies a `blend`: the stack ends with n default values, their n * k
+/// deltas (k being the number of regions) and n itself, which are
+/// replaced by the n blended values.
+fn blend(stack: &mut Vec<f64>, scalars: &[f64]) -> Option<()> {
+    let n = stack.pop()? as usize;
+    let k = scalars.len();
+    let total = n.checked_mul(k + 1)?;
+    let base = stack.len().checked_sub(total)?;
+    let mut values = stack[base..base + n].to_vec();
+    for (i, v) in values.iter_mut().enumerate() {
+        let deltas = &stack[base + n + i * k..base + n + (i + 1) * k];
+        *v += deltas.iter().zip(scalars).map(|(d, s)| d * s).sum::<f64>();
+    }
+    stack.truncate(base);
+    stack.extend(values);
+    Some(())
+}
+
+/// Parse a DICT. `scalars` gives the region scalars for a `vsindex`; it is
+/// only consulted when the DICT contains blends.
+fn parse_dict(data: &[u8], scalars: &dyn Fn(u16) -> Option<Vec<f64>>) -> Option<cff2_dict> {
+    let mut entries = Vec::new();
+    let mut stack = Vec::new();
+    let mut vsindex = 0;
+    let mut pos = 0;
+    while pos < data.len() {
+        let b0 = data[pos];
+        pos += 1;
+        match b0 {
+            12 => {
+                let b1 = *data.get(pos)?;
+                pos += 1;
+                entries.push((12 << 8 | b1 as u16, std::mem::take(&mut stack)));
+            }
+            22 => {
+                vsindex = *stack.last()? as u16;
+                entries.push((OP_VSINDEX, std::mem::take(&mut stack)));
+            }
+            23 => blend(&mut stack, &scalars(vsindex)?)?,
+            0..=21 | 24 => entries.push((b0 as u16, std::mem::take(&mut stack))),
+            28 => {
+                stack.push(get_u16(data, pos)? as i16 as f64);
+                pos += 2;
+            }
+            29 => {
+                stack.push(get_u32(data, pos)? as i32 as f64);
+                pos += 4;
+            }
+            30 => stack.push(read_real(data, &mut pos)?),
+            32..=246 => stack.push(b0 as f64 - 139.0),
+            247..=254 => {
+                let b1 = *data.get(pos)? as f64;
+                pos += 1;
+                stack.push(if b0 < 251 {
+                    (b0 as f64 - 247.0) * 256.0 + b1 + 108.0
+                } else {
+                    -(b0 as f64 - 251.0) * 256.0 - b1 - 108.0
+                });
+            }
+            _ => return None,
+        }
+    }
+    Some(entries)
+}
+
+fn subr_bias(count: usize) -> i32 {
+    if count < 1240 {
+        107
+    } else if count < 33900 {
+        1131
+    } else {
+        32768
+    }
+}
+
+/// Append a Type 2 charstring number, using the 16.16 fixed form only for
+/// values that aren't integers.
+fn encode_number(out: &mut Vec<u8>, v: f64) {
+    let r = v.round();
+    if (v - r).abs() >= 0.5 / 65536.0 || !(-32768.0..=32767.0).contains(&r) {
+        out.push(255);
+        out.extend_from_slice(&((v * 65536.0).round() as i32).to_be_bytes());
+        return;
+    }
+    let i = r as i32;
+    match i {
+        -107..=107 => out.push((i + 139) as u8),
+        108..=1131 => {
+            let j = i - 108;
+            out.push((j >> 8) as u8 + 247);
+            out.push(j as u8);
+        }
+        -1131..=-108 => {
+            let j = -i - 108;
+            out.push((j >> 8) as u8 + 251);
+            out.push(j as u8);
+        }
+        _ => {
+            out.push(28);
+            out.extend_from_slice(&(i as i16).to_be_bytes());
+        }
+    }
+}
+
+/// Rewrites CFF2 charstrings as self-contained Type 2 charstrings.
+struct cs_converter<'a> {
+    global_subrs: &'a [&'a [u8]],
+    local_subrs: &'a [&'a [u8]],
+    scalars: &'a dyn Fn(u16) -> Option<Vec<f64>>,
+    /* region scalars already computed, by vsindex */
+    scalar_cache: Vec<(u16, Vec<f64>)>,
+    vsindex: u16,
+    stack: Vec<f64>,
+    num_stems: usize,
+    ended: bool,
+    out: Vec<u8>,
+}
+
+impl<'a> cs_converter<'a> {
+    fn convert(&mut self, charstring: &[u8], vsindex: u16) -> Option<Vec<u8>> {
+        self.vsindex = vsindex;
+        self.stack.clear();
+        self.num_stems = 0;
+        self.ended = false;
+        self.out = Vec::with_capacity(charstring.len() + 1);
+        self.run(charstring, 0)?;
+        if !self.ended {
+            self.emit(&[14]);
+        }
+        Some(std::mem::take(&mut self.out))
+    }
+
+    fn emit(&mut self, op: &[u8]) {
+        for &v in &self.stack {
+            encode_number(&mut self.out, v);
+        }
+        self.out.extend_from_slice(op);
+        self.stack.clear();
+    }
+
+    fn blend_scalars(&mut self) -> Option<Vec<f64>> {
+        let vsindex = self.vsindex;
+        if let Some((_, s)) = self.scalar_cache.iter().find(|c| c.0 == vsindex) {
+            return Some(s.clone());
+        }
+        let s = (self.scalars)(vsindex)?;
+        self.scalar_cache.push((vsindex, s.clone()));
+        Some(s)
+    }
+
+    fn run(&mut self, cs: &[u8], depth: usize) -> Option<()> {
+        if depth > 10 {
+            return None;
+        }
+        let mut pos = 0;
+        while pos < cs.len() && !self.ended {
+            let b0 = cs[pos];
+            pos += 1;
+            match b0 {
+                10 | 29 => {
+                    let subrs = if b0 == 10 {
+                        self.local_subrs
+                    } else {
+                        self.global_subrs
+                    };
+                    let index = self.stack.pop()? as i32 + subr_bias(subrs.len());
+                    let subr = subrs.get(index as usize)?;
+                    self.run(subr, depth + 1)?;
+                }
+                /* return and endchar are CFF 1 only; tolerate them */
+                11 => return Some(()),
+                14 => {
+                    self.emit(&[14]);
+                    self.ended = true;
+                }
+                15 => self.vsindex = self.stack.pop()? as u16,
+                16 => {
+                    let scalars = self.blend_scalars()?;
+                    blend(&mut self.stack, &scalars)?;
+                }
+                1 | 3 | 18 | 23 => {
+                    self.num_stems += self.stack.len() / 2;
+                    self.emit(&[b0]);
+                }
+                19 | 20 => {
+                    /* operands before a hintmask are implicit vstems */
+                    self.num_stems += self.stack.len() / 2;
+                    self.emit(&[b0]);
+                    let len = (self.num_stems + 7) >> 3;
+                    self.out.extend_from_slice(cs.get(pos..pos + len)?);
+                    pos += len;
+                }
+                12 => {
+                    let b1 = *cs.get(pos)?;
+                    pos += 1;
+                    self.emit(&[12, b1]);
+                }
+                28 => {
+                    self.stack.push(get_u16(cs, pos)? as i16 as f64);
+                    pos += 2;
+                }
+                0..=31 => self.emit(&[b0]),
+                32..=246 => self.stack.push(b0 as f64 - 139.0),
+                247..=254 => {
+                    let b1 = *cs.get(pos)? as f64;
+                    pos += 1;
+                    self.stack.push(if b0 < 251 {
+                        (b0 as f64 - 247.0) * 256.0 + b1 + 108.0
+                    } else {
+                        -(b0 as f64 - 251.0) * 256.0 - b1 - 108.0
+                    });
+                }
+                255 => {
+                    self.stack.push(get_u32(cs, pos)? as i32 as f64 / 65536.0);
+                    pos += 4;
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+fn read_fd_select(data: &[u8], pos: usize, num_glyphs: usize) -> Option<Vec<u8>> {
+    let mut fds = Vec::with_capacity(num_glyphs);
+    match *data.get(pos)? {
+        0 => fds.extend_from_slice(data.get(pos + 1..pos + 1 + num_glyphs)?),
+        3 => {
+            let count = get_u16(data, pos + 1)? as usize;
+            for i in 0..count {
+                let first = get_u16(data, pos + 3 + 3 * i)? as usize;
+                let fd = *data.get(pos + 5 + 3 * i)?;
+                let next = get_u16(data, pos + 6 + 3 * i)? as usize;
+                fds.resize(next.max(first).min(num_glyphs), fd);
+            }
+        }
+        4 => {
+            let count = get_u32(data, pos + 1)? as usize;
+            for i in 0..count {
+                let first = get_u32(data, pos + 5 + 6 * i)? as usize;
+                let fd = get_u16(data, pos + 9 + 6 * i)?;
+                let next = get_u32(data, pos + 11 + 6 * i)? as usize;
+                if fd > 255 {
+                    return None;
+                }
+                fds.resize(next.max(first).min(num_glyphs), fd as u8);
+            }
+        }
+        _ => return None,
+    }
+    if fds.len() != num_glyphs {
+        return None;
+    }
+    Some(fds)
+}
+
+/// Convert a `CFF2` table to Type 2 charstrings and CFF 1 style DICT data
+/// at the normalized design coordinates `coords` (empty for the default
+/// instance).
+pub(crate) fn cff2_convert(data: &[u8], coords: &[f64]) -> Option<cff2_outlines> {
+    if *data.first()? != 2 {
+        return None;
+    }
+    let header_size = *data.get(2)? as usize;
+    let top_size = get_u16(data, 3)? as usize;
+    let no_blend = |_: u16| -> Option<Vec<f64>> { None };
+    let top = parse_dict(data.get(header_size..header_size + top_size)?, &no_blend)?;
+    let global_subrs = read_index(data, header_size + top_size)?;
+
+    let store = match dict_get(&top, OP_VSTORE) {
+        /* the store is preceded by its length */
+        Some(offset) => Some(tt_item_variation_store::new(
+            data.get(*offset.first()? as usize + 2..)?,
+        )),
+        None => None,
+    };
+    let scalars = |vsindex: u16| store.as_ref()?.region_scalars(coords, vsindex);
+
+    let charstrings = read_index(data, *dict_get(&top, OP_CHARSTRINGS)?.first()? as usize)?;
+    let font_dicts = read_index(data, *dict_get(&top, OP_FDARRAY)?.first()? as usize)?;
+    if font_dicts.is_empty() || font_dicts.len() > 255 {
+        return None;
+    }
+    let fd_select = match dict_get(&top, OP_FDSELECT) {
+        Some(offset) => read_fd_select(data, *offset.first()? as usize, charstrings.len())?,
+        None => vec![0; charstrings.len()],
+    };
+
+    let mut private = Vec::with_capacity(font_dicts.len());
+    let mut local_subrs = Vec::with_capacity(font_dicts.len());
+    let mut vsindices = Vec::with_capacity(font_dicts.len());
+    for font_dict in &font_dicts {
+        let font_dict = parse_dict(font_dict, &no_blend)?;
+        let (dict, subrs, vsindex) = match dict_get(&font_dict, OP_PRIVATE) {
+            Some(&[size, offset]) => {
+                let (size, offset) = (size as usize, offset as usize);
+                let dict = parse_dict(data.get(offset..offset + size)?, &scalars)?;
+                let subrs = match dict_get(&dict, OP_SUBRS) {
+                    Some(&[subrs]) => read_index(data, offset + subrs as usize)?,
+                    _ => Vec::new(),
+                };
+                let vsindex = dict_get(&dict, OP_VSINDEX).map_or(0, |v| v[0] as u16);
+                (dict, subrs, vsindex)
+            }
+            _ => (Vec::new(), Vec::new(), 0),
+        };
+        private.push(
+            dict.into_iter()
+                .filter(|e| e.0 != OP_SUBRS && e.0 != OP_VSINDEX)
+                .collect(),
+        );
+        local_subrs.push(subrs);
+        vsindices.push(vsindex);
+    }
+
+    let mut converted = Vec::with_capacity(charstrings.len());
+    let mut scalar_cache = Vec::new();
+    for (charstring, &fd) in charstrings.iter().zip(&fd_select) {
+        let fd = fd as usize;
+        if fd >= font_dicts.len() {
+            return None;
+        }
+        let mut converter = cs_converter {
+            global_subrs: &global_subrs,
+            local_subrs: &local_subrs[fd],
+            scalars: &scalars,
+            scalar_cache: std::mem::take(&mut scalar_cache),
+            vsindex: 0,
+            stack: Vec::new(),
+            num_stems: 0,
+            ended: false,
+            out: Vec::new(),
+        };
+        converted.push(converter.convert(charstring, vsindices[fd])?);
+        scalar_cache = converter.scalar_cache;
+    }
+
+    Some(cff2_outlines {
+        font_matrix: dict_get(&top, OP_FONTMATRIX).map(|m| m.to_vec()),
+        private,
+        fd_select,
+        charstrings: converted,
+    })
+}
+
+/// The CFF 1 name of a Private DICT operator, for those CFF2 keeps.
+fn private_dict_key(op: u16) -> Option<&'static [u8]> {
+    Some(match op {
+        6 => b"BlueValues\x00",
+        7 => b"OtherBlues\x00",
+        8 => b"FamilyBlues\x00",
+        9 => b"FamilyOtherBlues\x00",
+        10 => b"StdHW\x00",
+        11 => b"StdVW\x00",
+        0x0c09 => b"BlueScale\x00",
+        0x0c0a => b"BlueShift\x00",
+        0x0c0b => b"BlueFuzz\x00",
+        0x0c0c => b"StemSnapH\x00",
+        0x0c0d => b"StemSnapV\x00",
+        0x0c11 => b"LanguageGroup\x00",
+        0x0c12 => b"ExpansionFactor\x00",
+        _ => return None,
+    })
+}
+
+unsafe fn dict_add(dict: *mut cff_dict, key: &[u8], values: &[f64]) {
+    let key = key.as_ptr() as *const i8;
+    cff_dict_add(dict, key, values.len() as i32);
+    for (i, &v) in values.iter().enumerate() {
+        cff_dict_set(dict, key, i as i32, v);
+    }
+}
+
+/// Open the `CFF2` table of an OpenType font as a CID-keyed CFF font,
+/// instanced at the font's selected design coordinates, if any. Unlike
+/// `cff_open()` the whole font is read at once; the result has no handle.
+pub(crate) unsafe fn cff2_open<'a>(sfont: *mut sfnt) -> *mut cff_font<'a> {
+    let data = match read_table(sfont, b"CFF2") {
+        Some(data) => data,
+        None => return ptr::null_mut(),
+    };
+    let coords = if (*sfont).instance.is_null() {
+        &[][..]
+    } else {
+        (*(*sfont).instance).coords()
+    };
+    let outlines = match cff2_convert(&data, coords) {
+        Some(outlines) if outlines.charstrings.len() > 1 && outlines.charstrings.len() < 65536 => {
+            outlines
+        }
+        _ => {
+            warn!("Invalid or unsupported CFF2 table.");
+            return ptr::null_mut();
+        }
+    };
+    let num_glyphs = outlines.charstrings.len();
+    let num_fds = outlines.private.len();
+    let fontname = tt_get_ps_fontname(sfont).unwrap_or_else(|| "Untitled".to_owned());
+
+    let cff = new(::std::mem::size_of::<cff_font>() as u32) as *mut cff_font;
+    /* we only ever write CFF 1 */
+    (*cff).header.major = 1;
+    (*cff).header.minor = 0;
+    (*cff).header.hdr_size = 4;
+    (*cff).header.offsize = 4;
+    (*cff).name = ptr::null_mut();
+    cff_set_name(cff, &fontname);
+    (*cff).index = 0;
+    (*cff).fontname = cff_get_name(&*cff);
+    (*cff).handle = None;
+    (*cff).offset = 0;
+    (*cff).gsubr_offset = 0;
+    (*cff).filter = 0;
+    (*cff).flag = 1 << 0; /* CID-keyed */
+    (*cff).is_notdef_notzero = 0;
+    (*cff).encoding = ptr::null_mut();
+    (*cff).string = ptr::null_mut();
+    (*cff)._string = ptr::null_mut();
+    let registry = CString::new("Adobe").unwrap();
+    let ordering = CString::new("Identity").unwrap();
+    let registry = cff_add_string(&mut *cff, registry.as_ptr(), 1);
+    let ordering = cff_add_string(&mut *cff, ordering.as_ptr(), 1);
+    cff_update_string(&mut *cff);
+
+    let topdict = cff_new_dict();
+    dict_add(
+        topdict,
+        b"ROS\x00",
+        &[registry as f64, ordering as f64, 0.0],
+    );
+    dict_add(topdict, b"CIDCount\x00", &[num_glyphs as f64]);
+    if let Some(matrix) = &outlines.font_matrix {
+        dict_add(topdict, b"FontMatrix\x00", matrix);
+    }
+    /* offsets are filled in when the font is written */
+    for key in &[
+        &b"charset\x00"[..],
+        b"FDSelect\x00",
+        b"FDArray\x00",
+        b"CharStrings\x00",
+    ] {
+        dict_add(topdict, key, &[0.0]);
+    }
+    (*cff).topdict = topdict;
+
+    (*cff).num_fds = num_fds as u8;
+    (*cff).fdarray =
+        new((num_fds * ::std::mem::size_of::<*mut cff_dict>()) as u32) as *mut *mut cff_dict;
+    (*cff).private =
+        new((num_fds * ::std::mem::size_of::<*mut cff_dict>()) as u32) as *mut *mut cff_dict;
+    (*cff).subrs =
+        new((num_fds * ::std::mem::size_of::<*mut cff_index>()) as u32) as *mut *mut cff_index;
+    for (i, entries) in outlines.private.iter().enumerate() {
+        let fd = cff_new_dict();
+        dict_add(fd, b"Private\x00", &[0.0, 0.0]);
+        *(*cff).fdarray.add(i) = fd;
+        let private = cff_new_dict();
+        for (op, values) in entries {
+            if let Some(key) = private_dict_key(*op) {
+                dict_add(private, key, values);
+            }
+        }
+        *(*cff).private.add(i) = private;
+        *(*cff).subrs.add(i) = ptr::null_mut();
+    }
+    (*cff).gsubr = cff_new_index(0);
+
+    /* CID n is GID n */
+    let charset = new(::std::mem::size_of::<cff_charsets>() as u32) as *mut cff_charsets;
+    (*charset).format = 2;
+    (*charset).num_entries = 1;
+    (*charset).data.range2 = new(::std::mem::size_of::<cff_range2>() as u32) as *mut cff_range2;
+    (*(*charset).data.range2).first = 1;
+    (*(*charset).data.range2).n_left = (num_glyphs - 2) as u16;
+    (*cff).charsets = charset;
+
+    let fdselect = new(::std::mem::size_of::<cff_fdselect>() as u32) as *mut cff_fdselect;
+    (*fdselect).format = 0;
+    (*fdselect).num_entries = 0;
+    (*fdselect).data.fds = new(num_glyphs as u32) as *mut u8;
+    ptr::copy_nonoverlapping(
+        outlines.fd_select.as_ptr(),
+        (*fdselect).data.fds,
+        num_glyphs,
+    );
+    (*cff).fdselect = fdselect;
+
+    let total: usize = outlines.charstrings.iter().map(|cs| cs.len()).sum();
+    let cstrings = cff_new_index(num_glyphs as u16);
+    (*cstrings).data = new(total as u32) as *mut u8;
+    let mut offset = 0;
+    for (gid, cs) in outlines.charstrings.iter().enumerate() {
+        *(*cstrings).offset.add(gid) = (offset + 1) as u32;
+        ptr::copy_nonoverlapping(cs.as_ptr(), (*cstrings).data.add(offset), cs.len());
+        offset += cs.len();
+    }
+    *(*cstrings).offset.add(num_glyphs) = (offset + 1) as u32;
+    (*cff).cstrings = cstrings;
+    (*cff).num_glyphs = num_glyphs as u16;
+    cff
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dict_numbers() {
+        /* 100, -1000, 32767, 1.5e-3 then op 6 */
+        let data = [239, 254, 124, 28, 0x7f, 0xff, 30, 0x1a, 0x5c, 0x3f, 6];
+        let dict = parse_dict(&data, &|_| None).unwrap();
+        assert_eq!(dict, vec![(6, vec![100.0, -1000.0, 32767.0, 1.5e-3])]);
+    }
+
+    #[test]
+    fn blends() {
+        /* two values with two regions each */
+        let mut stack = vec![7.0, 10.0, 20.0, 2.0, 4.0, 6.0, 8.0, 2.0];
+        blend(&mut stack, &[0.5, 0.25]).unwrap();
+        assert_eq!(stack, vec![7.0, 10.0 + 1.0 + 1.0, 20.0 + 3.0 + 2.0]);
+        assert!(blend(&mut vec![1.0, 2.0], &[1.0]).is_none());
+    }
+
+    #[test]
+    fn numbers_roundtrip() {
+        for &v in &[
+            0.0, 107.0, -107.0, 108.0, 1131.0, -1131.0, 2000.0, -32768.0, 0.5,
+        ] {
+            let mut out = Vec::new();
+            encode_number(&mut out, v);
+            let mut converter = cs_converter {
+                global_subrs: &[],
+                local_subrs: &[],
+                scalars: &|_| None,
+                scalar_cache: Vec::new(),
+                vsindex: 0,
+                stack: Vec::new(),
+                num_stems: 0,
+                ended: false,
+                out: Vec::new(),
+            };
+            converter.run(&out, 0).unwrap();
+            assert_eq!(converter.stack, vec![v]);
+        }
+    }
+
+    #[test]
+    fn charstrings() {
+        /* a subroutine drawing a line, called after a blended moveto */
+        let subr: &[u8] = &[139 + 10, 139 + 20, 5];
+        let subrs = [subr];
+        let scalars = |vsindex: u16| if vsindex == 0 { Some(vec![0.5]) } else { None };
+        let mut converter = cs_converter {
+            global_subrs: &[],
+            local_subrs: &subrs,
+            scalars: &scalars,
+            scalar_cache: Vec::new(),
+            vsindex: 0,
+            stack: Vec::new(),
+            num_stems: 0,
+            ended: false,
+            out: Vec::new(),
+        };
+        /* 100 + 0.5 * 10 and 50 + 0.5 * -10 blended, rmoveto, callsubr 0 */
+        let cs = [
+            239,
+            139 + 50,
+            139 + 10,
+            139 - 10,
+            139 + 2,
+            16,
+            21,
+            139 - 107,
+            10,
+        ];
+        let out = converter.convert(&cs, 0).unwrap();
+        assert_eq!(
+            out,
+            vec![139 + 105, 139 + 45, 21, 139 + 10, 139 + 20, 5, 14]
+        );
+    }
+
+    #[test]
+    fn convert_table() {
+        fn int(v: u32) -> Vec<u8> {
+            let mut b = vec![29];
+            b.extend_from_slice(&v.to_be_bytes());
+            b
+        }
+        let mut data = vec![2, 0, 5, 0, 19];
+        /* Top DICT: CharStrings, FDArray, vstore */
+        data.extend(int(60));
+        data.push(17);
+        data.extend(int(74));
+        data.extend_from_slice(&[12, 36]);
+        data.extend(int(28));
+        data.push(24);
+        /* no global subrs */
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        /* one axis, one region peaking at 1.0, one ItemVariationData */
+        data.extend_from_slice(&[0, 30, 0, 1, 0, 0, 0, 12, 0, 1, 0, 0, 0, 22]);
+        data.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0x40, 0, 0x40, 0]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0, 0]);
+        /* CharStrings: an empty .notdef and "100+10 0 rmoveto" */
+        data.extend_from_slice(&[0, 0, 0, 2, 1, 1, 1, 7]);
+        data.extend_from_slice(&[239, 149, 140, 16, 139, 21]);
+        /* FDArray with one Font DICT */
+        data.extend_from_slice(&[0, 0, 0, 1, 1, 1, 12]);
+        data.extend(int(6));
+        data.extend(int(92));
+        data.push(18);
+        /* Private DICT: BlueValues -10+4 0 */
+        data.extend_from_slice(&[129, 143, 140, 23, 139, 6]);
+        assert_eq!(data.len(), 98);
+
+        let outlines = cff2_convert(&data, &[0.5]).unwrap();
+        assert_eq!(outlines.charstrings, vec![vec![14], vec![244, 139, 21, 14]]);
+        assert_eq!(outlines.fd_select, vec![0, 0]);
+        assert_eq!(outlines.private, vec![vec![(6, vec![-8.0, 0.0])]]);
+        assert_eq!(outlines.font_matrix, None);
+
+        let outlines = cff2_convert(&data, &[]).unwrap();
+        assert_eq!(outlines.charstrings[1], vec![239, 139, 21, 14]);
+    }
+
+    #[test]
+    fn hintmasks() {
+        let mut converter = cs_converter {
+            global_subrs: &[],
+            local_subrs: &[],
+            scalars: &|_| None,
+            scalar_cache: Vec::new(),
+            vsindex: 0,
+            stack: Vec::new(),
+            num_stems: 0,
+            ended: false,
+            out: Vec::new(),
+        };
+        /* nine stems need a two-byte mask */
+        let mut cs = vec![];
+        for _ in 0..9 {
+            cs.extend_from_slice(&[139, 139]);
+        }
+        cs.extend_from_slice(&[18, 19, 0xff, 0x80, 14]);
+        let out = converter.convert(&cs, 0).unwrap();
+        assert_eq!(out, cs);
+    }
+}
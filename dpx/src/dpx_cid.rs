@@ -42,6 +42,7 @@ use super::dpx_cidtype2::{
     CIDFont_type2_dofont, CIDFont_type2_open, CIDFont_type2_set_flags, CIDFont_type2_set_verbose,
 };
 use super::dpx_mem::new;
+use super::dpx_pdfa::pdf_get_pdfa;
 use crate::dpx_pdfobj::{
     pdf_get_version, pdf_link_obj, pdf_name, pdf_obj, pdf_ref_obj, pdf_release_obj,
     pdf_remove_dict, pdf_string_value,
@@ -78,6 +79,7 @@ pub(crate) struct cid_opt {
     pub(crate) embed: i32,
     pub(crate) stemv: i32,
     pub(crate) cff_charsets: *mut libc::c_void,
+    pub(crate) variations: Vec<(u32, f64)>,
 }
 use super::dpx_fontmap::fontmap_opt;
 /*
@@ -594,6 +596,12 @@ unsafe fn CIDFont_base_open(
     let fontdict = start.parse_pdf_dict(ptr::null_mut()).unwrap();
     let mut start = basefont.descriptor.as_bytes();
     let descriptor = start.parse_pdf_dict(ptr::null_mut()).unwrap();
+    if let Some(level) = pdf_get_pdfa() {
+        warn!(
+            "Built-in CID font \"{}\" cannot be embedded; the output will not conform to {}.",
+            fontname, level
+        );
+    }
     (*font).fontname = fontname.clone();
     (*font).flags |= 1i32 << 0i32;
     let tmp = (*fontdict)
@@ -689,18 +697,29 @@ pub(crate) unsafe fn CIDFont_cache_find(
     cmap_csi: *mut CIDSysInfo,
     mut fmap_opt: *mut fontmap_opt,
 ) -> i32 {
+    let mut embed = if (*fmap_opt).flags & 1i32 << 1i32 != 0 {
+        0i32
+    } else {
+        1i32
+    };
+    if embed == 0 {
+        if let Some(level) = pdf_get_pdfa() {
+            warn!(
+                "Font \"{}\" is marked as not embedded, but {} requires embedding.",
+                map_name, level
+            );
+            embed = 1i32
+        }
+    }
     let opt = Box::into_raw(Box::new(cid_opt {
         style: (*fmap_opt).style,
         index: (*fmap_opt).index,
-        embed: if (*fmap_opt).flags & 1i32 << 1i32 != 0 {
-            0i32
-        } else {
-            1i32
-        },
+        embed,
         name: String::new(),
         csi: get_cidsysinfo(map_name, fmap_opt),
         stemv: (*fmap_opt).stemv,
         cff_charsets: ptr::null_mut(),
+        variations: (*fmap_opt).variations.clone(),
     }));
 
     if (*opt).csi.is_null() && !cmap_csi.is_null() {
@@ -720,6 +739,7 @@ pub(crate) unsafe fn CIDFont_cache_find(
         if font.name == map_name
             && (*font.options).style == (*opt).style
             && (*font.options).index == (*opt).index
+            && (*font.options).variations == (*opt).variations
         {
             if (*font.options).embed == (*opt).embed {
                 /*
@@ -821,7 +841,8 @@ unsafe fn release_opt(opt: *mut cid_opt) {
 unsafe fn get_cidsysinfo(map_name: &str, fmap_opt: *mut fontmap_opt) -> *mut CIDSysInfo {
     let mut csi: *mut CIDSysInfo = ptr::null_mut();
     let mut csi_idx: i32 = -1i32;
-    let pdf_ver = pdf_get_version() as i32;
+    /* PDF 2.0 adds nothing here, so it is treated like PDF 1.7. */
+    let pdf_ver = pdf_get_version().min(7) as i32;
     /* Use heighest supported value for current output PDF version. */
 
     if fmap_opt.is_null() || (*fmap_opt).charcoll.is_empty() {
@@ -40,15 +40,17 @@ use super::dpx_agl::{
     agl_sput_UTF16BE,
 };
 use super::dpx_cff::{
-    cff_add_string, cff_charsets_lookup_inverse, cff_close, cff_get_index_header, cff_get_name,
-    cff_get_sid, cff_get_string, cff_glyph_lookup, cff_index_size, cff_new_index, cff_open,
-    cff_pack_charsets, cff_pack_fdselect, cff_pack_index, cff_put_header, cff_read_charsets,
-    cff_read_fdselect, cff_read_subrs, cff_release_index, cff_set_name, cff_update_string,
+    cff_add_string, cff_charsets_lookup_inverse, cff_close, cff_get_charstrings_header,
+    cff_get_index_header, cff_get_name, cff_get_sid, cff_get_string, cff_glyph_lookup,
+    cff_index_size, cff_new_index, cff_open, cff_pack_charsets, cff_pack_fdselect, cff_pack_index,
+    cff_put_header, cff_read_charsets, cff_read_charstring, cff_read_fdselect, cff_read_subrs,
+    cff_release_index, cff_set_name, cff_update_string,
 };
 use super::dpx_cff::{
     cff_charsets_lookup, cff_fdselect_lookup, cff_read_fdarray, cff_read_private,
     cff_release_charsets, cff_release_fdselect, CffIndex, Pack,
 };
+use super::dpx_cff2::cff2_open;
 use super::dpx_cff_dict::{
     cff_dict_add, cff_dict_get, cff_dict_known, cff_dict_pack, cff_dict_remove, cff_dict_set,
     cff_dict_update, cff_new_dict,
@@ -75,6 +77,7 @@ use super::dpx_tt_table::{
     tt_read_VORG_table, tt_read_head_table, tt_read_hhea_table, tt_read_longMetrics,
     tt_read_maxp_table, tt_read_os2__table, tt_read_vhea_table,
 };
+use super::dpx_tt_var::tt_var_instance_load;
 use super::dpx_type0::{Type0Font_cache_get, Type0Font_get_usedchars, Type0Font_set_ToUnicode};
 use crate::bridge::ttstub_input_read;
 use crate::dpx_pdfobj::{
@@ -392,6 +395,12 @@ unsafe fn add_CIDMetrics(
         (*hhea).numOfLongHorMetrics,
         (*hhea).numOfExSideBearings,
     );
+    if !(*sfont).instance.is_null() {
+        for gid in 0..(*maxp).numGlyphs {
+            let metrics = &mut *hmtx.offset(gid as isize);
+            metrics.advance = (*(*sfont).instance).instance_advance(gid, metrics.advance);
+        }
+    }
     add_CIDHMetrics(fontdict, CIDToGIDMap, last_cid, maxp, head, hmtx);
     if need_vmetrics != 0 {
         add_CIDVMetrics(sfont, fontdict, CIDToGIDMap, last_cid, maxp, head, hmtx);
@@ -603,6 +612,7 @@ unsafe fn CIDFontInfo_close(info: *mut CIDType0Info) {
 unsafe fn CIDFont_type0_try_open(
     name: &str,
     index: i32,
+    variations: &[(u32, f64)],
     required_cid: i32,
     mut info: *mut CIDType0Info,
 ) -> Result<(), CidOpenError> {
@@ -623,13 +633,23 @@ unsafe fn CIDFont_type0_try_open(
         || sfnt_read_table_directory((*info).sfont, offset) < 0i32
         || {
             offset = sfnt_find_table_pos((*info).sfont, b"CFF ");
-            offset == 0_u32
+            offset == 0_u32 && sfnt_find_table_pos((*info).sfont, b"CFF2") == 0_u32
         }
     {
         CIDFontInfo_close(info);
         return Err(CidOpenError::NO_CFF_TABLE);
     }
-    (*info).cffont = cff_open(&mut (*(*info).sfont).handle, offset as i32, 0i32); // TODO: use link
+    (*info).cffont = if offset != 0_u32 {
+        cff_open(&mut (*(*info).sfont).handle, offset as i32, 0i32) // TODO: use link
+    } else {
+        /* CFF2 outlines are converted at the selected instance */
+        if !variations.is_empty() {
+            if let Some(instance) = tt_var_instance_load((*info).sfont, variations) {
+                (*(*info).sfont).instance = Box::into_raw(instance);
+            }
+        }
+        cff2_open((*info).sfont)
+    };
     if (*info).cffont.is_null() {
         return Err(CidOpenError::CANNOT_OPEN_CFF_FONT);
     }
@@ -686,9 +706,13 @@ pub(crate) unsafe fn CIDFont_type0_dofont(font: *mut CIDFont) {
         }
     }
     let used_chars = CIDFont_type0_get_used_chars(font);
-    if let Err(error) =
-        CIDFont_type0_try_open(&(*font).ident, CIDFont_get_opt_index(font), 1i32, &mut info)
-    {
+    if let Err(error) = CIDFont_type0_try_open(
+        &(*font).ident,
+        CIDFont_get_opt_index(font),
+        &(*(*font).options).variations,
+        1i32,
+        &mut info,
+    ) {
         CIDType0Error_Show(error, &(*font).ident);
         return;
     }
@@ -763,25 +787,7 @@ pub(crate) unsafe fn CIDFont_type0_dofont(font: *mut CIDFont) {
     cff_read_fdarray(cffont);
     cff_read_private(cffont);
     cff_read_subrs(cffont);
-    let offset = cff_dict_get(
-        (*cffont).topdict,
-        b"CharStrings\x00" as *const u8 as *const i8,
-        0i32,
-    ) as u64;
-    cffont
-        .handle
-        .as_mut()
-        .unwrap()
-        .seek(SeekFrom::Start((*cffont).offset as u64 + offset))
-        .unwrap();
-    let idx = cff_get_index_header(cffont);
-    /* offset is now absolute offset ... bad */
-    let offset = cffont
-        .handle
-        .as_mut()
-        .unwrap()
-        .seek(SeekFrom::Current(0))
-        .unwrap();
+    let (idx, offset) = cff_get_charstrings_header(cffont);
     let cs_count = (*idx).count;
     if (cs_count as i32) < 2i32 {
         panic!("No valid charstring data found.");
@@ -834,13 +840,7 @@ pub(crate) unsafe fn CIDFont_type0_dofont(font: *mut CIDFont) {
                 ) as *mut u8
             }
             *(*charstrings).offset.offset(gid as isize) = (charstring_len + 1i32) as l_offset;
-            let handle = cffont.handle.as_mut().unwrap();
-            handle
-                .seek(SeekFrom::Start(
-                    offset as u64 + *(*idx).offset.offset(gid_org as isize) as u64 - 1,
-                ))
-                .unwrap();
-            ttstub_input_read(handle.as_ptr(), data as *mut i8, size as size_t);
+            cff_read_charstring(cffont, idx, offset, gid_org, data, size as usize);
             let fd = cff_fdselect_lookup(cffont, gid_org) as i32;
             charstring_len += cs_copy_charstring(
                 (*charstrings).data.offset(charstring_len as isize),
@@ -960,13 +960,18 @@ pub(crate) unsafe fn CIDFont_type0_open(
             || sfnt_read_table_directory(sfont, offset) < 0i32
             || {
                 offset = sfnt_find_table_pos(sfont, b"CFF ");
-                offset == 0_u32
+                offset == 0_u32 && sfnt_find_table_pos(sfont, b"CFF2") == 0_u32
             }
         {
             sfnt_close(sfont);
             return -1i32;
         }
-        cffont = cff_open(&mut (*sfont).handle, offset as i32, 0i32); // TODO: use link
+        cffont = if offset != 0_u32 {
+            cff_open(&mut (*sfont).handle, offset as i32, 0i32) // TODO: use link
+        } else {
+            /* Only the names are needed here, so the default instance will do. */
+            cff2_open(sfont)
+        };
         if cffont.is_null() {
             panic!("Cannot read CFF font data");
         }
@@ -1141,9 +1146,13 @@ pub(crate) unsafe fn CIDFont_type0_t1cdofont(font: *mut CIDFont) {
         .as_dict_mut()
         .set("FontDescriptor", pdf_ref_obj((*font).descriptor));
     let used_chars = CIDFont_type0_get_used_chars(font);
-    if let Err(error) =
-        CIDFont_type0_try_open(&(*font).ident, CIDFont_get_opt_index(font), 0i32, &mut info)
-    {
+    if let Err(error) = CIDFont_type0_try_open(
+        &(*font).ident,
+        CIDFont_get_opt_index(font),
+        &(*(*font).options).variations,
+        0i32,
+        &mut info,
+    ) {
         CIDType0Error_Show(error, &(*font).ident);
         return;
     }
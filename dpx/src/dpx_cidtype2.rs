@@ -51,6 +51,7 @@ use super::dpx_tt_gsub::{
     otl_gsub_add_feat, otl_gsub_apply, otl_gsub_new, otl_gsub_release, otl_gsub_select,
 };
 use super::dpx_tt_table::tt_get_ps_fontname;
+use super::dpx_tt_var::tt_var_instance_load;
 use super::dpx_type0::{Type0Font_cache_get, Type0Font_get_usedchars};
 use crate::dpx_pdfobj::{
     pdf_dict, pdf_name, pdf_obj, pdf_ref_obj, pdf_release_obj, pdf_stream, pdf_string, IntoObj,
@@ -654,6 +655,11 @@ pub(crate) unsafe fn CIDFont_type2_dofont(font: &mut CIDFont) {
     if sfnt_read_table_directory(sfont, offset) < 0i32 {
         panic!("Could not read TrueType table directory ({}).", font.ident);
     }
+    if !(*font.options).variations.is_empty() {
+        if let Some(instance) = tt_var_instance_load(sfont, &(*font.options).variations) {
+            (*sfont).instance = Box::into_raw(instance);
+        }
+    }
     /*
      * Adobe-Identity means font's internal glyph ordering here.
      */
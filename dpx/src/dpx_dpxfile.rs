@@ -31,6 +31,7 @@ use std::io::{Read, Seek, SeekFrom};
 
 use super::dpx_mem::new;
 use super::dpx_numbers::{tt_get_unsigned_pair, tt_get_unsigned_quad};
+use super::dpx_woff::woff_flavor;
 use crate::bridge::{ttstub_input_close, ttstub_input_open};
 use libc::{free, remove, strcat, strcpy, strlen, strrchr};
 
@@ -51,29 +52,36 @@ static mut _SBUF: [u8; 128] = [0; 128];
  *  0x00010000: TrueType (Win)/OpenType
  *  `OTTO': PostScript CFF font with OpenType wrapper
  *  `ttcf': TrueType Collection
+ *
+ * WOFF and WOFF2 files are classified by the sfnt they contain, since
+ * sfnt_open() unpacks them.
  */
-unsafe fn check_stream_is_truetype(handle: &mut InputHandleWrapper) -> bool {
+unsafe fn sfnt_signature(handle: &mut InputHandleWrapper) -> Option<[u8; 4]> {
     handle.seek(SeekFrom::Start(0)).unwrap();
-    let n = handle.read(&mut _SBUF[..4]).unwrap();
+    let n = handle.read(&mut _SBUF[..8]).unwrap();
     handle.seek(SeekFrom::Start(0)).unwrap();
-    if n != 4 {
-        return false;
+    if n < 4 {
+        return None;
+    }
+    match woff_flavor(&_SBUF[..8]) {
+        Some(flavor) => Some(flavor.to_be_bytes()),
+        None => Some([_SBUF[0], _SBUF[1], _SBUF[2], _SBUF[3]]),
     }
-    if &_SBUF[..4] == b"true" || _SBUF[..4] == [0, 1, 0, 0] {
+}
+unsafe fn check_stream_is_truetype(handle: &mut InputHandleWrapper) -> bool {
+    let sig = match sfnt_signature(handle) {
+        Some(sig) => sig,
+        None => return false,
+    };
+    if &sig == b"true" || sig == [0, 1, 0, 0] {
         /* This doesn't help... */
         return true;
     }
-    &_SBUF[..4] == b"ttcf"
+    &sig == b"ttcf"
 }
 /* "OpenType" is only for ".otf" here */
 unsafe fn check_stream_is_opentype(handle: &mut InputHandleWrapper) -> bool {
-    handle.seek(SeekFrom::Start(0)).unwrap();
-    let n = handle.read(&mut _SBUF[..4]).unwrap();
-    handle.seek(SeekFrom::Start(0)).unwrap();
-    if n != 4 {
-        return false;
-    }
-    &_SBUF[..4] == b"OTTO"
+    matches!(sfnt_signature(handle), Some(sig) if &sig == b"OTTO")
 }
 unsafe fn check_stream_is_type1(handle: &mut InputHandleWrapper) -> bool {
     let p = &_SBUF;
@@ -49,7 +49,9 @@ use super::dpx_dpxfile::{
 };
 use super::dpx_dpxutil::{ParseCIdent, ParseFloatDecimal};
 use super::dpx_dvipdfmx::{is_xdv, landscape_mode, paper_height, paper_width};
-use super::dpx_fontmap::{pdf_insert_native_fontmap_record, pdf_lookup_fontmap_record};
+use super::dpx_fontmap::{
+    native_fontmap_key, pdf_insert_native_fontmap_record, pdf_lookup_fontmap_record,
+};
 use super::dpx_mem::new;
 use super::dpx_numbers::{
     sqxfw, tt_get_positive_quad, tt_get_signed_quad, tt_get_unsigned_byte, tt_get_unsigned_num,
@@ -57,8 +59,9 @@ use super::dpx_numbers::{
 };
 use super::dpx_pdfcolor::{pdf_color_pop, pdf_color_push, PdfColor};
 use super::dpx_pdfdev::{
-    graphics_mode, pdf_dev_begin_actualtext, pdf_dev_end_actualtext, pdf_dev_locate_font,
-    pdf_dev_set_dirmode, pdf_dev_set_rect, pdf_dev_set_rule, pdf_dev_set_string,
+    graphics_mode, pdf_dev_begin_actualtext, pdf_dev_end_actualtext, pdf_dev_get_dirmode,
+    pdf_dev_get_param, pdf_dev_locate_font, pdf_dev_set_dirmode, pdf_dev_set_rect,
+    pdf_dev_set_rule, pdf_dev_set_string,
 };
 use super::dpx_pdfdoc::{
     pdf_doc_begin_page, pdf_doc_break_annot, pdf_doc_end_page, pdf_doc_expand_box,
@@ -71,6 +74,9 @@ use super::dpx_tfm::{
     tfm_close_all, tfm_get_fw_depth, tfm_get_fw_height, tfm_get_fw_width, tfm_open, tfm_set_verbose,
 };
 use super::dpx_tt_aux::ttc_read_offset;
+use super::dpx_tt_color::{
+    tt_color_font, tt_color_font_close, tt_color_font_load, tt_color_glyph_draw,
+};
 use super::dpx_tt_table::{
     tt_read_head_table, tt_read_hhea_table, tt_read_longMetrics, tt_read_maxp_table,
     tt_read_vhea_table,
@@ -129,6 +135,7 @@ pub(crate) struct font_def {
     pub(crate) extend: i32,
     pub(crate) slant: i32,
     pub(crate) embolden: i32,
+    pub(crate) variations: Vec<(u32, i32)>,
 }
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -145,6 +152,7 @@ pub(crate) struct loaded_font<'a> {
     pub(crate) descent: i32,
     pub(crate) unitsPerEm: u32,
     pub(crate) cffont: *mut cff_font<'a>,
+    pub(crate) color: *mut tt_color_font,
     pub(crate) numGlyphs: u32,
     pub(crate) layout_dir: i32,
     pub(crate) extend: f32,
@@ -543,6 +551,7 @@ unsafe fn read_font_record(tex_id: u32) {
         extend: 0x10000i32,
         slant: 0i32,
         embolden: 0i32,
+        variations: Vec::new(),
     });
     free(font_name as *mut _);
 }
@@ -574,6 +583,7 @@ unsafe fn read_native_font_record(tex_id: u32) {
         extend: 0x10000i32,
         slant: 0i32,
         embolden: 0i32,
+        variations: Vec::new(),
     };
     if flags & 0x100_u32 != 0 {
         font.layout_dir = 1i32
@@ -581,6 +591,14 @@ unsafe fn read_native_font_record(tex_id: u32) {
     if flags & 0x200_u32 != 0 {
         font.rgba_color = tt_get_unsigned_quad(handle)
     }
+    if flags & 0x800_u32 != 0 {
+        let n = tt_get_unsigned_pair(handle) as usize;
+        let tags: Vec<u32> = (0..n).map(|_| tt_get_unsigned_quad(handle)).collect();
+        font.variations = tags
+            .into_iter()
+            .map(|tag| (tag, tt_get_signed_quad(handle)))
+            .collect();
+    }
     if flags & 0x1000_u32 != 0 {
         font.extend = tt_get_signed_quad(handle)
     }
@@ -789,6 +807,7 @@ pub(crate) unsafe fn dvi_locate_font(tfm_name: &str, ptsize: spt_t) -> u32 {
         descent: 0,
         unitsPerEm: 0,
         cffont: ptr::null_mut(),
+        color: ptr::null_mut(),
         numGlyphs: 0,
         layout_dir: 0,
         extend: 0.,
@@ -925,6 +944,7 @@ pub(crate) unsafe fn dvi_locate_font(tfm_name: &str, ptsize: spt_t) -> u32 {
     loaded_fonts.push(new_font);
     loaded_fonts.len() as u32 - 1
 }
+#[allow(clippy::too_many_arguments)]
 unsafe fn dvi_locate_native_font(
     filename: &str,
     index: u32,
@@ -933,6 +953,7 @@ unsafe fn dvi_locate_native_font(
     extend: i32,
     slant: i32,
     embolden: i32,
+    variations: &[(u32, i32)],
 ) -> i32 {
     let mut offset: u32 = 0_u32;
     let mut is_dfont: i32 = 0i32;
@@ -959,25 +980,20 @@ unsafe fn dvi_locate_native_font(
     }
     let handle = handle.unwrap();
 
-    let fontmap_key = format!(
-        "{}/{}/{}/{}/{}/{}",
-        filename,
-        index,
-        if layout_dir == 0i32 { 'H' } else { 'V' },
-        extend,
-        slant,
-        embolden,
+    let fontmap_key = native_fontmap_key(
+        filename, index, layout_dir, extend, slant, embolden, variations,
     );
     let mut mrec = pdf_lookup_fontmap_record(fontmap_key.as_bytes());
     if mrec.is_null() {
-        mrec =
-            pdf_insert_native_fontmap_record(filename, index, layout_dir, extend, slant, embolden);
+        mrec = pdf_insert_native_fontmap_record(
+            filename, index, layout_dir, extend, slant, embolden, variations,
+        );
         if mrec.is_null() {
             panic!("Failed to insert font record for font: {}", filename);
         }
     }
     let mut font = loaded_font {
-        font_id: pdf_dev_locate_font(&CString::new(fontmap_key).unwrap(), ptsize),
+        font_id: pdf_dev_locate_font(&CString::new(fontmap_key.as_str()).unwrap(), ptsize),
         size: ptsize,
         type_0: 4i32,
 
@@ -988,6 +1004,7 @@ unsafe fn dvi_locate_native_font(
         descent: 0,
         unitsPerEm: 0,
         cffont: ptr::null_mut(),
+        color: ptr::null_mut(),
         numGlyphs: 0,
         layout_dir: 0,
         extend: 0.,
@@ -1050,6 +1067,11 @@ unsafe fn dvi_locate_native_font(
         font.descent = (*hhea).descent as i32;
         font.unitsPerEm = (*head).unitsPerEm as u32;
         font.numGlyphs = (*maxp).numGlyphs as u32;
+        if let Some(color) =
+            tt_color_font_load(sfont, &fontmap_key, (*head).unitsPerEm, (*maxp).numGlyphs)
+        {
+            font.color = Box::into_raw(color);
+        }
         if layout_dir == 1i32 && sfnt_find_table_pos(sfont, b"vmtx") > 0_u32 {
             let vhea: *mut tt_vhea_table = tt_read_vhea_table(sfont);
             sfnt_locate_table(sfont, b"vmtx");
@@ -1463,6 +1485,7 @@ unsafe fn do_fnt(tex_id: u32) {
                 font.extend,
                 font.slant,
                 font.embolden,
+                &font.variations,
             ) as usize
         } else {
             dvi_locate_font(&font.font_name, font.point_size) as usize
@@ -1566,6 +1589,10 @@ unsafe fn skip_native_font_def() {
     if flags & 0x200_u32 != 0 {
         tt_skip_bytes(4, handle);
     }
+    if flags & 0x800_u32 != 0 {
+        let n = tt_get_unsigned_pair(handle) as u32;
+        tt_skip_bytes(8 * n, handle);
+    }
     if flags & 0x1000_u32 != 0 {
         tt_skip_bytes(4, handle);
     }
@@ -1698,10 +1725,39 @@ unsafe fn do_glyphs(do_actual_text: i32) {
                 pdf_doc_expand_box(&mut rect);
             }
         }
+        let xpos = dvi_state.h + *xloc.offset(i as isize);
+        let ypos = -dvi_state.v - *yloc.offset(i as isize);
+        if !(*font).color.is_null()
+            && (glyph_id as u32) < (*font).numGlyphs
+            && (*font).layout_dir == 0i32
+            && pdf_dev_get_dirmode() == 0i32
+            && pdf_dev_get_param(2i32) != 0
+        {
+            let scale = (*font).size as f64 * dvi2pts / (*font).unitsPerEm as f64;
+            let units = [
+                scale * (*font).extend as f64,
+                0.,
+                scale * (*font).slant as f64,
+                scale,
+                0.,
+                0.,
+            ];
+            if tt_color_glyph_draw(
+                &mut *(*font).color,
+                glyph_id,
+                xpos,
+                ypos,
+                glyph_width,
+                (*font).font_id,
+                &units,
+            ) {
+                continue;
+            }
+        }
         let wbuf = glyph_id.to_be_bytes();
         pdf_dev_set_string(
-            dvi_state.h + *xloc.offset(i as isize),
-            -dvi_state.v - *yloc.offset(i as isize),
+            xpos,
+            ypos,
             wbuf.as_ptr() as *const libc::c_void,
             2i32 as size_t,
             glyph_width,
@@ -1984,6 +2040,10 @@ pub(crate) unsafe fn dvi_close() {
             cff_close(font.cffont);
         }
         font.cffont = ptr::null_mut();
+        if !font.color.is_null() {
+            tt_color_font_close(Box::from_raw(font.color));
+        }
+        font.color = ptr::null_mut();
     }
     loaded_fonts.clear();
     vf_close_all_fonts();
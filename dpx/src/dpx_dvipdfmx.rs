@@ -32,6 +32,7 @@ use super::dpx_dvi::{
     dvi_close, dvi_comment, dvi_do_page, dvi_init, dvi_npages, dvi_reset_global_state,
     dvi_scan_specials, dvi_set_verbose, ReadLength,
 };
+use super::dpx_pdfa::{pdf_set_pdfa, PdfAConformance};
 use super::dpx_pdfdev::{
     pdf_close_device, pdf_dev_reset_global_state, pdf_dev_set_verbose, pdf_init_device, Point, Rect,
 };
@@ -43,9 +44,9 @@ use super::dpx_pdffont::{
     pdf_font_reset_unique_tag_state, pdf_font_set_deterministic_unique_tags, pdf_font_set_dpi,
 };
 use super::dpx_tt_aux::tt_aux_set_verbose;
-use crate::bridge::DisplayExt;
+use crate::bridge::{ttstub_check_limits, ttstub_note_page_output, DisplayExt};
 use crate::dpx_pdfparse::parse_unsigned;
-use crate::info;
+use crate::{info, warn};
 use std::ffi::CStr;
 use std::ptr;
 
@@ -59,7 +60,8 @@ use super::dpx_fontmap::{
 use super::dpx_pdfencrypt::{pdf_enc_compute_id_string, pdf_enc_set_passwd, pdf_enc_set_verbose};
 use super::dpx_pdfobj::{
     pdf_files_close, pdf_files_init, pdf_get_version, pdf_obj_reset_global_state,
-    pdf_obj_set_verbose, pdf_set_compression, pdf_set_use_predictor, pdf_set_version,
+    pdf_obj_set_verbose, pdf_set_compression, pdf_set_linearize, pdf_set_use_predictor,
+    pdf_set_version, pdf_version_name, PDF_VERSION_2_0,
 };
 use super::dpx_tfm::tfm_reset_global_state;
 use super::dpx_vf::vf_reset_global_state;
@@ -225,6 +227,7 @@ unsafe fn do_dvi_pages(mut page_ranges: Vec<PageRange>) {
         let mut page_no = page_ranges[i].first;
         while dvi_npages() != 0 {
             if (page_no as u32) < dvi_npages() {
+                ttstub_check_limits();
                 info!("[{}", page_no + 1);
                 /* Users want to change page size even after page is started! */
                 page_width = paper_width;
@@ -269,6 +272,7 @@ unsafe fn do_dvi_pages(mut page_ranges: Vec<PageRange>) {
                 }
                 dvi_do_page(page_height, x_offset, y_offset);
                 page_count = page_count + 1;
+                ttstub_note_page_output(page_no + 1);
                 info!("]");
             }
             if step > 0i32 && page_no >= page_ranges[i].last {
@@ -297,6 +301,8 @@ pub unsafe fn dvipdfmx_main(
     deterministic_tags: bool,
     quiet: bool,
     verbose: u32,
+    pdfa: Option<PdfAConformance>,
+    linearize: bool,
 ) -> i32 {
     let mut enable_object_stream: bool = true; /* This must come before parsing options... */
     let mut page_ranges = Vec::new();
@@ -332,6 +338,15 @@ pub unsafe fn dvipdfmx_main(
      * arguments, so we emulate the default TeXLive config file by copying those
      * code bits. */
     pdf_set_version(5_u32); /* last page */
+    /* PDF/A forces full font embedding regardless of the fonts' licensing
+     * flags; see tt_get_fontdesc(). */
+    pdf_set_pdfa(pdfa);
+    always_embed = pdfa.is_some() as i32;
+    if let Some(level) = pdfa {
+        if pdf_get_version() > level.max_pdf_version() {
+            pdf_set_version(level.max_pdf_version());
+        }
+    }
     select_paper(b"letter");
     annot_grow = 0i32 as f64;
     bookmark_open = 0i32;
@@ -405,8 +420,41 @@ pub unsafe fn dvipdfmx_main(
         owner_pw.as_mut_ptr(),
         user_pw.as_mut_ptr(),
     );
-    if ver_minor >= 3i32 && ver_minor <= 7i32 {
-        pdf_set_version(ver_minor as u32);
+    /* "pdf:majorversion 2" asks for PDF 2.0, the only 2.x version. */
+    let requested_version = if ver_major == 2 {
+        Some(PDF_VERSION_2_0)
+    } else if ver_minor >= 3i32 && ver_minor <= 7i32 {
+        Some(ver_minor as u32)
+    } else {
+        None
+    };
+    if let Some(version) = requested_version {
+        match pdfa {
+            Some(level) if version > level.max_pdf_version() => {
+                warn!(
+                    "PDF version {} is not permitted in {}; using {}.",
+                    pdf_version_name(version),
+                    level,
+                    pdf_version_name(level.max_pdf_version())
+                );
+            }
+            _ => pdf_set_version(version),
+        }
+    }
+    if let Some(level) = pdfa {
+        if do_encryption != 0 {
+            warn!(
+                "Encryption is not permitted in {}; the output will not be encrypted.",
+                level
+            );
+            do_encryption = 0i32;
+        }
+    }
+    if linearize && do_encryption != 0 {
+        warn!("Encrypted output cannot be linearized; the output will not be linearized.");
+        pdf_set_linearize(false);
+    } else {
+        pdf_set_linearize(linearize);
     }
     if do_encryption != 0 {
         if !(key_bits >= 40i32 && key_bits <= 128i32 && key_bits % 8i32 == 0i32)
@@ -418,6 +466,10 @@ pub unsafe fn dvipdfmx_main(
                 panic!("Chosen key length requires at least PDF 1.4. Use \"-V 4\" to change.");
             }
         }
+        if pdf_get_version() == PDF_VERSION_2_0 && key_bits != 256i32 {
+            warn!("PDF 2.0 only allows 256-bit AES encryption; using a 256-bit key.");
+            key_bits = 256i32;
+        }
         do_encryption = 1i32;
         pdf_enc_set_passwd(
             key_bits as u32,
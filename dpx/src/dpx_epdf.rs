@@ -34,8 +34,8 @@ use super::dpx_pdfdoc::pdf_doc_get_page;
 use super::dpx_pdfximage::{pdf_ximage_init_form_info, pdf_ximage_set_form};
 use crate::dpx_pdfobj::{
     pdf_close, pdf_concat_stream, pdf_deref_obj, pdf_file_get_catalog, pdf_file_get_version,
-    pdf_get_version, pdf_import_object, pdf_obj, pdf_open, pdf_release_obj, pdf_stream, IntoObj,
-    PushObj, STREAM_COMPRESS,
+    pdf_get_version, pdf_import_object, pdf_obj, pdf_open, pdf_release_obj, pdf_stream,
+    pdf_version_name, IntoObj, PushObj, STREAM_COMPRESS,
 };
 pub(crate) type __off_t = i64;
 pub(crate) type __off64_t = i64;
@@ -86,8 +86,8 @@ pub(crate) unsafe fn pdf_include_page(
     }
     if pdf_file_get_version(pf) > pdf_get_version() {
         warn!(
-            "Trying to include PDF file which has newer version number than output PDF: 1.{}.",
-            pdf_get_version()
+            "Trying to include PDF file which has newer version number than output PDF: {}.",
+            pdf_version_name(pdf_get_version())
         );
     }
     if options.page_no == 0i32 {
@@ -39,6 +39,24 @@ pub(crate) static mut _dpx_quietness: i32 = 0i32;
 pub(crate) unsafe fn shut_up(quietness: i32) {
     _dpx_quietness = quietness;
 }
+
+/// Changes the quietness level until the returned value is dropped, for
+/// code that runs outside of a dvipdfmx session.
+pub(crate) struct QuietScope(i32);
+
+impl QuietScope {
+    pub(crate) unsafe fn new(quietness: i32) -> Self {
+        let previous = QuietScope(_dpx_quietness);
+        shut_up(quietness);
+        previous
+    }
+}
+
+impl Drop for QuietScope {
+    fn drop(&mut self) {
+        unsafe { shut_up(self.0) }
+    }
+}
 pub(crate) static mut _dpx_message_handle: Option<OutputHandleWrapper> = None;
 
 static mut _dpx_message_buf: [u8; 1024] = [0; 1024];
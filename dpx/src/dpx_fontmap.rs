@@ -63,6 +63,8 @@ pub(crate) struct fontmap_opt {
     pub(crate) index: i32,
     pub(crate) style: i32,
     pub(crate) stemv: i32,
+    /// Design coordinates of a variable font instance, as (axis tag, value)
+    pub(crate) variations: Vec<(u32, f64)>,
 }
 #[derive(Clone)]
 pub(crate) struct fontmap_rec {
@@ -119,6 +121,7 @@ pub(crate) unsafe fn pdf_init_fontmap_record() -> fontmap_rec {
             style: 0i32,
             stemv: -1i32,
             cff_charsets: ptr::null_mut(),
+            variations: Vec::new(),
         },
     }
 }
@@ -147,6 +150,7 @@ unsafe fn pdf_copy_fontmap_record(src: *const fontmap_rec) -> fontmap_rec {
             style: (*src).opt.style,
             stemv: (*src).opt.stemv,
             cff_charsets: (*src).opt.cff_charsets,
+            variations: (*src).opt.variations.clone(),
         },
     }
 }
@@ -1073,15 +1077,18 @@ pub(crate) unsafe fn pdf_load_fontmap_file(filename: &CStr, mode: i32) -> i32 {
     error
 }
 
-pub(crate) unsafe fn pdf_insert_native_fontmap_record(
+/// The fontmap key for a native (XeTeX) font definition. Variation settings
+/// are only appended when present so that keys for static fonts are unchanged.
+pub(crate) fn native_fontmap_key(
     path: &str,
     index: u32,
     layout_dir: i32,
     extend: i32,
     slant: i32,
     embolden: i32,
-) -> *mut fontmap_rec {
-    let fontmap_key = format!(
+    variations: &[(u32, i32)],
+) -> String {
+    let mut key = format!(
         "{}/{}/{}/{}/{}/{}",
         path,
         index,
@@ -1090,6 +1097,24 @@ pub(crate) unsafe fn pdf_insert_native_fontmap_record(
         slant,
         embolden,
     );
+    for (i, &(tag, value)) in variations.iter().enumerate() {
+        key.push(if i == 0 { '/' } else { ',' });
+        key.push_str(&format!("{:08x}={}", tag, value));
+    }
+    key
+}
+
+pub(crate) unsafe fn pdf_insert_native_fontmap_record(
+    path: &str,
+    index: u32,
+    layout_dir: i32,
+    extend: i32,
+    slant: i32,
+    embolden: i32,
+    variations: &[(u32, i32)],
+) -> *mut fontmap_rec {
+    let fontmap_key =
+        native_fontmap_key(path, index, layout_dir, extend, slant, embolden, variations);
     if verbose != 0 {
         info!("<NATIVE-FONTMAP:{}", fontmap_key);
     }
@@ -1109,6 +1134,10 @@ pub(crate) unsafe fn pdf_insert_native_fontmap_record(
     mrec.opt.extend = extend as f64 / 65536.0f64;
     mrec.opt.slant = slant as f64 / 65536.0f64;
     mrec.opt.bold = embolden as f64 / 65536.0f64;
+    mrec.opt.variations = variations
+        .iter()
+        .map(|&(tag, value)| (tag, value as f64 / 65536.0f64))
+        .collect();
     let ret = pdf_insert_fontmap_record(&mrec.map_name, &mrec);
     if verbose != 0 {
         info!(">");
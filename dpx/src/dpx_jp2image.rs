@@ -31,7 +31,7 @@ use crate::warn;
 use super::dpx_numbers::{get_unsigned_byte, get_unsigned_pair, get_unsigned_quad};
 use super::dpx_pdfximage::pdf_ximage_set_image;
 use crate::bridge::{ttstub_input_get_size, InputHandleWrapper};
-use crate::dpx_pdfobj::{pdf_get_version, pdf_stream, IntoObj};
+use crate::dpx_pdfobj::{pdf_get_version, pdf_stream, pdf_version_name, IntoObj};
 use std::io::{Read, Seek, SeekFrom};
 
 pub(crate) type __off_t = i64;
@@ -331,8 +331,8 @@ pub(crate) unsafe fn jp2_include_image(
     let pdf_version = pdf_get_version();
     if pdf_version < 5_u32 {
         warn!(
-            "JPEG 2000 support requires PDF version >= 1.5 (Current setting 1.{})\n",
-            pdf_version
+            "JPEG 2000 support requires PDF version >= 1.5 (Current setting {})\n",
+            pdf_version_name(pdf_version)
         );
         return -1i32;
     }
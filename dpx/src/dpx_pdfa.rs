@@ -0,0 +1,530 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2020 the Tectonic Project
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_upper_case_globals)]
+
+//! PDF/A output.
+//!
+//! PDF/A (ISO 19005) is the archival subset of PDF. Most of what it asks
+//! for is handled where the corresponding objects are written: fonts are
+//! always embedded and given ToUnicode maps, encryption is refused and, for
+//! PDF/A-1, transparency is dropped. This module keeps track of the
+//! requested conformance level and builds the two document-level objects
+//! the standard requires: the XMP metadata stream mirroring the DocInfo
+//! dictionary and the sRGB OutputIntent. The metadata stream is also used
+//! by PDF 2.0 output, which deprecates DocInfo in favour of XMP.
+
+use std::ffi::CString;
+use std::fmt;
+use std::ptr;
+use std::str::FromStr;
+
+use crate::warn;
+
+use super::dpx_pdfcolor::{iccp_load_profile, pdf_get_iccbased_profile};
+use crate::dpx_pdfobj::{pdf_dict, pdf_obj, pdf_stream, pdf_string, IntoObj, PushObj};
+
+/// A PDF/A conformance level. Only the "b" (basic, visual appearance)
+/// levels are offered since the "a" levels need a tagged document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PdfAConformance {
+    /// PDF/A-1b (ISO 19005-1), based on PDF 1.4.
+    A1b,
+    /// PDF/A-2b (ISO 19005-2), based on PDF 1.7.
+    A2b,
+    /// PDF/A-3b (ISO 19005-3), like PDF/A-2b but allowing embedded files.
+    A3b,
+}
+
+impl PdfAConformance {
+    /// The part of ISO 19005 this level belongs to.
+    pub fn part(self) -> u32 {
+        match self {
+            PdfAConformance::A1b => 1,
+            PdfAConformance::A2b => 2,
+            PdfAConformance::A3b => 3,
+        }
+    }
+
+    /// The conformance letter, as written to `pdfaid:conformance`.
+    pub fn conformance(self) -> &'static str {
+        "B"
+    }
+
+    /// The highest PDF minor version this level may be written as.
+    pub(crate) fn max_pdf_version(self) -> u32 {
+        match self {
+            PdfAConformance::A1b => 4,
+            PdfAConformance::A2b | PdfAConformance::A3b => 7,
+        }
+    }
+
+    /// Whether transparency (soft masks, constant alpha) is permitted.
+    pub(crate) fn allows_transparency(self) -> bool {
+        self != PdfAConformance::A1b
+    }
+}
+
+impl fmt::Display for PdfAConformance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PDF/A-{}{}",
+            self.part(),
+            self.conformance().to_lowercase()
+        )
+    }
+}
+
+impl FromStr for PdfAConformance {
+    type Err = String;
+
+    /// Accepts `1b`, `a-1b`, `pdf/a-1b` and so on, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let level = lower.trim_start_matches("pdf/").trim_start_matches("a-");
+        match level {
+            "1b" => Ok(PdfAConformance::A1b),
+            "2b" => Ok(PdfAConformance::A2b),
+            "3b" => Ok(PdfAConformance::A3b),
+            _ => Err(format!(
+                "unsupported PDF/A conformance level \"{}\" (expected 1b, 2b or 3b)",
+                s
+            )),
+        }
+    }
+}
+
+static mut pdfa_level: Option<PdfAConformance> = None;
+static mut transparency_warned: bool = false;
+
+pub(crate) unsafe fn pdf_set_pdfa(level: Option<PdfAConformance>) {
+    pdfa_level = level;
+    transparency_warned = false;
+}
+
+pub(crate) unsafe fn pdf_get_pdfa() -> Option<PdfAConformance> {
+    pdfa_level
+}
+
+/// Checks whether a transparency feature may be written. When it may not,
+/// a warning naming the feature is issued (once per document) and the
+/// caller is expected to fall back to an opaque rendering.
+pub(crate) unsafe fn pdfa_transparency_allowed(what: &str) -> bool {
+    match pdfa_level {
+        Some(level) if !level.allows_transparency() => {
+            if !transparency_warned {
+                warn!(
+                    "{} does not permit transparency; {} will be rendered opaque.",
+                    level, what
+                );
+                transparency_warned = true;
+            }
+            false
+        }
+        _ => true,
+    }
+}
+
+const SRGB_IDENT: &str = "sRGB IEC61966-2.1";
+
+/// Creates the `/OutputIntents` array of the catalog, embedding the sRGB
+/// profile as the destination output profile. Returns null if the profile
+/// could not be loaded.
+pub(crate) unsafe fn pdfa_create_output_intents() -> *mut pdf_obj {
+    let profile = srgb_icc_profile();
+    let ident = CString::new(SRGB_IDENT).unwrap();
+    let cspc_id = iccp_load_profile(ident.as_ptr(), &profile);
+    if cspc_id < 0 {
+        warn!("Could not load the sRGB profile for the PDF/A OutputIntent.");
+        return ptr::null_mut();
+    }
+    let dest = pdf_get_iccbased_profile(cspc_id);
+    if dest.is_null() {
+        warn!("Could not load the sRGB profile for the PDF/A OutputIntent.");
+        return ptr::null_mut();
+    }
+    let mut intent = pdf_dict::new();
+    intent.set("Type", "OutputIntent");
+    intent.set("S", "GTS_PDFA1");
+    intent.set("OutputCondition", pdf_string::new(SRGB_IDENT));
+    intent.set("OutputConditionIdentifier", pdf_string::new(SRGB_IDENT));
+    intent.set("RegistryName", pdf_string::new("http://www.color.org"));
+    intent.set("Info", pdf_string::new(SRGB_IDENT));
+    intent.set("DestOutputProfile", dest);
+    let mut intents = vec![];
+    intents.push_obj(intent);
+    intents.into_obj()
+}
+
+/// Creates the XMP metadata stream for the catalog. Its contents mirror
+/// the DocInfo dictionary, which must already be complete. The PDF/A
+/// identification is included when a conformance level is set.
+pub(crate) unsafe fn pdf_create_xmp_metadata(docinfo: &pdf_obj) -> *mut pdf_obj {
+    let level = pdfa_level;
+    let info = docinfo.as_dict();
+    let get = |key: &str| {
+        info.get(key)
+            .filter(|value| value.is_string())
+            .map(|value| pdf_text_string(value.as_string().to_bytes()))
+    };
+    let xmp = xmp_packet(level, &get);
+    /* The metadata must stay readable by tools that do not understand PDF
+     * filters, so it is never compressed. */
+    let mut stream = pdf_stream::new(0);
+    stream.get_dict_mut().set("Type", "Metadata");
+    stream.get_dict_mut().set("Subtype", "XML");
+    stream.add_slice(xmp.as_bytes());
+    stream.into_obj()
+}
+
+fn xmp_packet(level: Option<PdfAConformance>, get: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut props = String::new();
+    if let Some(level) = level {
+        props.push_str(&format!(
+            "   <pdfaid:part>{}</pdfaid:part>\n   <pdfaid:conformance>{}</pdfaid:conformance>\n",
+            level.part(),
+            level.conformance()
+        ));
+    }
+    props.push_str("   <dc:format>application/pdf</dc:format>\n");
+    if let Some(title) = get("Title") {
+        props.push_str(&format!(
+            "   <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n",
+            xml_escape(&title)
+        ));
+    }
+    if let Some(author) = get("Author") {
+        props.push_str(&format!(
+            "   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+            xml_escape(&author)
+        ));
+    }
+    if let Some(subject) = get("Subject") {
+        props.push_str(&format!(
+            "   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+            xml_escape(&subject)
+        ));
+    }
+    if let Some(keywords) = get("Keywords") {
+        props.push_str(&format!(
+            "   <pdf:Keywords>{}</pdf:Keywords>\n",
+            xml_escape(&keywords)
+        ));
+    }
+    if let Some(producer) = get("Producer") {
+        props.push_str(&format!(
+            "   <pdf:Producer>{}</pdf:Producer>\n",
+            xml_escape(&producer)
+        ));
+    }
+    if let Some(creator) = get("Creator") {
+        props.push_str(&format!(
+            "   <xmp:CreatorTool>{}</xmp:CreatorTool>\n",
+            xml_escape(&creator)
+        ));
+    }
+    if let Some(date) = get("CreationDate").and_then(|d| xmp_date(&d)) {
+        props.push_str(&format!("   <xmp:CreateDate>{}</xmp:CreateDate>\n", date));
+    }
+    if let Some(date) = get("ModDate").and_then(|d| xmp_date(&d)) {
+        props.push_str(&format!(
+            "   <xmp:ModifyDate>{}</xmp:ModifyDate>\n   <xmp:MetadataDate>{}</xmp:MetadataDate>\n",
+            date, date
+        ));
+    }
+
+    let mut xmp = String::new();
+    xmp.push_str("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    xmp.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+    xmp.push_str(" <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+    xmp.push_str("  <rdf:Description rdf:about=\"\"\n");
+    xmp.push_str("    xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\"\n");
+    xmp.push_str("    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n");
+    xmp.push_str("    xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"\n");
+    xmp.push_str("    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\n");
+    xmp.push_str(&props);
+    xmp.push_str("  </rdf:Description>\n");
+    xmp.push_str(" </rdf:RDF>\n");
+    xmp.push_str("</x:xmpmeta>\n");
+    /* Padding lets the packet be edited in place. */
+    for _ in 0..20 {
+        xmp.push_str(&" ".repeat(99));
+        xmp.push('\n');
+    }
+    xmp.push_str("<?xpacket end=\"w\"?>");
+    xmp
+}
+
+/// Decodes a PDF text string: UTF-16BE with a byte order mark, or
+/// otherwise PDFDocEncoding, which we approximate by Latin-1.
+fn pdf_text_string(s: &[u8]) -> String {
+    if s.starts_with(&[0xfe, 0xff]) {
+        let units: Vec<u16> = s[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        s.iter().map(|&b| char::from(b)).collect()
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            /* Control characters are not allowed in XML 1.0. */
+            c if (c as u32) < 0x20 && c != '\t' && c != '\n' && c != '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Converts a PDF date `D:YYYYMMDDHHmmSSOHH'mm'` into the ISO 8601 form
+/// used by XMP. Missing trailing fields take their default values.
+fn xmp_date(date: &str) -> Option<String> {
+    let s = date.strip_prefix("D:").unwrap_or(date);
+    let digits = s.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 4 {
+        return None;
+    }
+    let field = |start: usize, default: &'static str| -> &str {
+        if digits >= start + 2 {
+            &s[start..start + 2]
+        } else {
+            default
+        }
+    };
+    let mut out = format!(
+        "{}-{}-{}T{}:{}:{}",
+        &s[..4],
+        field(4, "01"),
+        field(6, "01"),
+        field(8, "00"),
+        field(10, "00"),
+        field(12, "00")
+    );
+    let tz = &s[digits..];
+    match tz.as_bytes().first() {
+        Some(b'Z') => out.push('Z'),
+        Some(&sign) if sign == b'+' || sign == b'-' => {
+            let tz_digits: String = tz[1..].chars().filter(char::is_ascii_digit).collect();
+            if tz_digits.len() >= 2 {
+                let minutes = tz_digits.get(2..4).unwrap_or("00");
+                out.push_str(&format!(
+                    "{}{}:{}",
+                    char::from(sign),
+                    &tz_digits[..2],
+                    minutes
+                ));
+            }
+        }
+        _ => {}
+    }
+    Some(out)
+}
+
+/* The sRGB profile is generated rather than shipped: a version 2 display
+ * profile with the IEC 61966-2.1 primaries adapted to D50 and the sRGB
+ * tone curve sampled into `curv` tables. */
+
+fn s15fixed16(v: f64) -> [u8; 4] {
+    ((v * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn icc_xyz(x: f64, y: f64, z: f64) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    tag.extend_from_slice(&s15fixed16(x));
+    tag.extend_from_slice(&s15fixed16(y));
+    tag.extend_from_slice(&s15fixed16(z));
+    tag
+}
+
+fn icc_text_description(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    /* No Unicode or ScriptCode descriptions. */
+    tag.extend_from_slice(&[0; 4 + 4 + 2 + 1 + 67]);
+    tag
+}
+
+fn icc_text(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+fn icc_srgb_curve() -> Vec<u8> {
+    const N: usize = 1024;
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(N as u32).to_be_bytes());
+    for i in 0..N {
+        let v = i as f64 / (N - 1) as f64;
+        let linear = if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+        tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    tag
+}
+
+pub(crate) fn srgb_icc_profile() -> Vec<u8> {
+    let curve = icc_srgb_curve();
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", icc_text_description(SRGB_IDENT)),
+        (b"cprt", icc_text("No copyright, use freely")),
+        (b"wtpt", icc_xyz(0.9642, 1.0, 0.8249)),
+        (b"rXYZ", icc_xyz(0.4361, 0.2225, 0.0139)),
+        (b"gXYZ", icc_xyz(0.3851, 0.7169, 0.0971)),
+        (b"bXYZ", icc_xyz(0.1431, 0.0606, 0.7141)),
+        (b"rTRC", curve),
+    ];
+    /* gTRC and bTRC share the data of rTRC. */
+    let num_entries = tags.len() + 2;
+    let mut offset = 128 + 4 + 12 * num_entries;
+    let mut table = vec![];
+    let mut data = vec![];
+    let mut trc = (0, 0);
+    for (sig, tag) in &tags {
+        table.extend_from_slice(*sig);
+        table.extend_from_slice(&(offset as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        if *sig == b"rTRC" {
+            trc = (offset, tag.len());
+        }
+        data.extend_from_slice(tag);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        offset = 128 + 4 + 12 * num_entries + data.len();
+    }
+    for sig in &[b"gTRC", b"bTRC"] {
+        table.extend_from_slice(*sig);
+        table.extend_from_slice(&(trc.0 as u32).to_be_bytes());
+        table.extend_from_slice(&(trc.1 as u32).to_be_bytes());
+    }
+    let size = offset;
+
+    let mut profile = Vec::with_capacity(size);
+    profile.extend_from_slice(&(size as u32).to_be_bytes());
+    profile.extend_from_slice(&[0; 4]); /* preferred CMM */
+    profile.extend_from_slice(&0x0210_0000u32.to_be_bytes());
+    profile.extend_from_slice(b"mntr");
+    profile.extend_from_slice(b"RGB ");
+    profile.extend_from_slice(b"XYZ ");
+    for v in &[2020u16, 1, 1, 0, 0, 0] {
+        profile.extend_from_slice(&v.to_be_bytes());
+    }
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 4 + 4 + 4 + 4 + 8]); /* platform .. attributes */
+    profile.extend_from_slice(&0u32.to_be_bytes()); /* perceptual intent */
+    profile.extend_from_slice(&s15fixed16(0.9642));
+    profile.extend_from_slice(&s15fixed16(1.0));
+    profile.extend_from_slice(&s15fixed16(0.8249));
+    profile.extend_from_slice(&[0; 4 + 16 + 28]); /* creator, ID, reserved */
+    assert_eq!(profile.len(), 128);
+    profile.extend_from_slice(&(num_entries as u32).to_be_bytes());
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    assert_eq!(profile.len(), size);
+    profile
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_levels() {
+        assert_eq!("1b".parse(), Ok(PdfAConformance::A1b));
+        assert_eq!("PDF/A-2b".parse(), Ok(PdfAConformance::A2b));
+        assert_eq!("a-3B".parse(), Ok(PdfAConformance::A3b));
+        assert!("1a".parse::<PdfAConformance>().is_err());
+        assert_eq!(PdfAConformance::A2b.to_string(), "PDF/A-2b");
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(
+            xmp_date("D:20200102030405+01'30'").as_deref(),
+            Some("2020-01-02T03:04:05+01:30")
+        );
+        assert_eq!(
+            xmp_date("D:20200102030405Z").as_deref(),
+            Some("2020-01-02T03:04:05Z")
+        );
+        assert_eq!(xmp_date("D:2020").as_deref(), Some("2020-01-01T00:00:00"));
+        assert_eq!(xmp_date("yesterday"), None);
+    }
+
+    #[test]
+    fn text_strings() {
+        assert_eq!(pdf_text_string(b"\xfe\xff\x00A\x00\xe9"), "A\u{e9}");
+        assert_eq!(pdf_text_string(b"caf\xe9"), "caf\u{e9}");
+        assert_eq!(xml_escape("a<b & \"c\""), "a&lt;b &amp; &quot;c&quot;");
+    }
+
+    #[test]
+    fn srgb_profile_is_well_formed() {
+        let profile = srgb_icc_profile();
+        assert_eq!(profile.len() % 4, 0);
+        assert_eq!(
+            u32::from_be_bytes([profile[0], profile[1], profile[2], profile[3]]) as usize,
+            profile.len()
+        );
+        assert_eq!(&profile[36..40], b"acsp");
+        let count = u32::from_be_bytes([profile[128], profile[129], profile[130], profile[131]]);
+        for i in 0..count as usize {
+            let entry = &profile[132 + 12 * i..144 + 12 * i];
+            let offset = u32::from_be_bytes([entry[4], entry[5], entry[6], entry[7]]) as usize;
+            let size = u32::from_be_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+            assert!(offset + size <= profile.len());
+        }
+    }
+
+    #[test]
+    fn xmp_mirrors_docinfo() {
+        let get = |key: &str| match key {
+            "Title" => Some("A & B".to_owned()),
+            "CreationDate" => Some("D:20200102030405Z".to_owned()),
+            _ => None,
+        };
+        let xmp = xmp_packet(Some(PdfAConformance::A1b), &get);
+        assert!(xmp.contains("<pdfaid:part>1</pdfaid:part>"));
+        assert!(xmp.contains("<pdfaid:conformance>B</pdfaid:conformance>"));
+        assert!(xmp.contains(">A &amp; B</rdf:li>"));
+        assert!(xmp.contains("<xmp:CreateDate>2020-01-02T03:04:05Z</xmp:CreateDate>"));
+        assert!(!xmp.contains("pdf:Producer"));
+
+        let xmp = xmp_packet(None, &get);
+        assert!(!xmp.contains("<pdfaid:part>"));
+        assert!(xmp.contains(">A &amp; B</rdf:li>"));
+    }
+}
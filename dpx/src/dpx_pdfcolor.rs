@@ -380,7 +380,8 @@ const ICC_VERSIONS: [IccVersion; 8] = [
 ];
 
 unsafe fn iccp_version_supported(major: i32, minor: i32) -> i32 {
-    let pdf_ver = pdf_get_version() as i32;
+    /* PDF 2.0 supports the same ICC versions as PDF 1.7. */
+    let pdf_ver = pdf_get_version().min(7) as i32;
     if pdf_ver < 8i32 {
         if ICC_VERSIONS[pdf_ver as usize].major < major {
             return 0i32;
@@ -1122,6 +1123,19 @@ pub(crate) unsafe fn pdf_get_colorspace_reference(cspc_id: i32) -> *mut pdf_obj
     pdf_link_obj((*colorspace).reference)
 }
 
+/* Returns a reference to the profile stream of an ICCBased color space,
+ * e.g. for use as the DestOutputProfile of an OutputIntent. This only works
+ * before pdf_get_colorspace_reference() has been called for the color space,
+ * since the resource array is released then.
+ */
+pub(crate) unsafe fn pdf_get_iccbased_profile(cspc_id: i32) -> *mut pdf_obj {
+    let colorspace = &*CSPC_CACHE.colorspaces.offset(cspc_id as isize);
+    if colorspace.subtype != 4 || colorspace.resource.is_null() {
+        return ptr::null_mut();
+    }
+    pdf_link_obj((*colorspace.resource).as_array()[1])
+}
+
 pub(crate) unsafe fn pdf_init_colors() {
     CSPC_CACHE.count = 0_u32;
     CSPC_CACHE.capacity = 0_u32;
@@ -1274,6 +1274,39 @@ pub(crate) unsafe fn pdf_dev_set_string(
     pdf_doc_add_page_content(&format_buffer[..len as usize]); /* op: */
     text_state.offset += width;
 }
+/*
+ * Same as pdf_dev_set_string() but with the text rendering mode `mode`,
+ * e.g. 3 (invisible) or 7 (add to clipping path), instead of the font's.
+ * Clipping takes effect at the end of the text object, so callers wanting
+ * mode 7 should switch to graphics mode afterwards, inside a gsave.
+ */
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn pdf_dev_set_string_rendermode(
+    xpos: spt_t,
+    ypos: spt_t,
+    instr_ptr: *const libc::c_void,
+    instr_len: size_t,
+    width: spt_t,
+    font_id: i32,
+    ctype: i32,
+    mode: i32,
+) {
+    if font_id < 0i32 || font_id >= num_dev_fonts {
+        panic!("Invalid font: {} ({})", font_id, num_dev_fonts);
+    }
+    if font_id != text_state.font_id {
+        dev_set_font(font_id);
+    }
+    text_mode();
+    pdf_doc_add_page_content(format!(" {} Tr", mode).as_bytes()); /* op: Tr */
+    pdf_dev_set_string(xpos, ypos, instr_ptr, instr_len, width, font_id, ctype);
+    text_mode();
+    pdf_doc_add_page_content(if text_state.bold_param > 0.0f64 {
+        b" 2 Tr"
+    } else {
+        b" 0 Tr"
+    });
+}
 
 pub(crate) unsafe fn pdf_init_device(dvi2pts: f64, precision: i32, black_and_white: i32) {
     if precision < 0i32 || precision > 8i32 {
@@ -42,6 +42,7 @@ use super::dpx_dpxutil::{
 use super::dpx_dvipdfmx::is_xdv;
 use super::dpx_jpegimage::check_for_jpeg;
 use super::dpx_mem::{new, renew};
+use super::dpx_pdfa::{pdf_create_xmp_metadata, pdf_get_pdfa, pdfa_create_output_intents};
 use super::dpx_pdfcolor::{pdf_close_colors, pdf_color_set_verbose, pdf_init_colors, WHITE};
 use super::dpx_pdfdev::{
     pdf_dev_bop, pdf_dev_eop, pdf_dev_get_coord, pdf_dev_get_param, pdf_dev_reset_color,
@@ -59,6 +60,9 @@ use super::dpx_pdfnames::{
     pdf_delete_name_tree, pdf_names_add_object, pdf_names_create_tree, pdf_new_name_tree,
 };
 use super::dpx_pdfresource::{pdf_close_resources, pdf_init_resources};
+use super::dpx_pdfstruct::{
+    pdf_struct_begin_page, pdf_struct_close, pdf_struct_end_page, pdf_struct_init,
+};
 use super::dpx_pdfximage::{
     pdf_close_images, pdf_init_images, pdf_ximage_defineresource, pdf_ximage_findresource,
     pdf_ximage_get_reference, pdf_ximage_init_form_info, pdf_ximage_set_verbose, XInfo,
@@ -66,10 +70,11 @@ use super::dpx_pdfximage::{
 use super::dpx_pngimage::check_for_png;
 use crate::bridge::{ttstub_input_close, ttstub_input_open};
 use crate::dpx_pdfobj::{
-    pdf_deref_obj, pdf_dict, pdf_file, pdf_file_get_catalog, pdf_link_obj, pdf_obj, pdf_out_flush,
-    pdf_out_init, pdf_ref_obj, pdf_release_obj, pdf_remove_dict, pdf_set_encrypt, pdf_set_id,
-    pdf_set_info, pdf_set_root, pdf_stream, pdf_string, pdf_string_length, pdf_string_value,
-    IntoObj, PdfObjType, PushObj, STREAM_COMPRESS,
+    pdf_deref_obj, pdf_dict, pdf_file, pdf_file_get_catalog, pdf_get_version, pdf_link_obj,
+    pdf_obj, pdf_out_flush, pdf_out_init, pdf_ref_obj, pdf_release_obj, pdf_remove_dict,
+    pdf_set_encrypt, pdf_set_id, pdf_set_info, pdf_set_root, pdf_stream, pdf_string,
+    pdf_string_length, pdf_string_value, IntoObj, PdfObjType, PushObj, PDF_VERSION_2_0,
+    STREAM_COMPRESS,
 };
 use libc::{free, memcpy, strcmp, strcpy, strlen, strncmp, strncpy};
 
@@ -515,6 +520,29 @@ unsafe fn pdf_doc_close_docinfo(mut p: *mut pdf_doc) {
             .as_dict_mut()
             .set("CreationDate", pdf_string::new(now));
     }
+    let pdf_2_0 = pdf_get_version() >= PDF_VERSION_2_0;
+    if pdf_get_pdfa().is_some() || pdf_2_0 {
+        /* The XMP metadata must agree with DocInfo, and its ModifyDate is
+         * expected, so make sure both carry one. */
+        if !(*docinfo).as_dict().has("ModDate") {
+            let created = pdf_link_obj((*docinfo).as_dict_mut().get_mut("CreationDate").unwrap());
+            (*docinfo).as_dict_mut().set("ModDate", created);
+        }
+        let metadata = pdf_create_xmp_metadata(&*docinfo);
+        (*(*p).root.dict)
+            .as_dict_mut()
+            .set("Metadata", pdf_ref_obj(metadata));
+        pdf_release_obj(metadata);
+    }
+    if pdf_2_0 {
+        /* PDF 2.0 deprecates everything in DocInfo but the dates; the rest
+         * now lives in the XMP metadata only. */
+        for key in KEYS.iter() {
+            if *key != "CreationDate" && *key != "ModDate" {
+                pdf_remove_dict(&mut *docinfo, key);
+            }
+        }
+    }
     pdf_release_obj(docinfo);
     (*p).info = ptr::null_mut();
 }
@@ -2261,16 +2289,19 @@ unsafe fn pdf_doc_finish_page(mut p: *mut pdf_doc) {
     if !(*currentpage).resources.is_null() {
         /*
          * ProcSet is obsolete in PDF-1.4 but recommended for compatibility.
+         * PDF 2.0 drops it altogether.
          */
-        let mut procset = vec![];
-        procset.push_obj("PDF");
-        procset.push_obj("Text");
-        procset.push_obj("ImageC");
-        procset.push_obj("ImageB");
-        procset.push_obj("ImageI");
-        (*(*currentpage).resources)
-            .as_dict_mut()
-            .set("ProcSet", procset);
+        if pdf_get_version() < PDF_VERSION_2_0 {
+            let mut procset = vec![];
+            procset.push_obj("PDF");
+            procset.push_obj("Text");
+            procset.push_obj("ImageC");
+            procset.push_obj("ImageB");
+            procset.push_obj("ImageI");
+            (*(*currentpage).resources)
+                .as_dict_mut()
+                .set("ProcSet", procset);
+        }
         (*(*currentpage).page_obj)
             .as_dict_mut()
             .set("Resources", pdf_ref_obj((*currentpage).resources));
@@ -2332,10 +2363,12 @@ pub(crate) unsafe fn pdf_doc_begin_page(scale: f64, x_origin: f64, y_origin: f64
     /* pdf_doc_new_page() allocates page content stream. */
     pdf_doc_new_page(p);
     pdf_dev_bop(&mut M);
+    pdf_struct_begin_page();
 }
 
 pub(crate) unsafe fn pdf_doc_end_page() {
     let p = &mut pdoc;
+    pdf_struct_end_page();
     pdf_dev_eop();
     doc_fill_page_background(p);
     pdf_doc_finish_page(p);
@@ -2356,6 +2389,11 @@ pub(crate) unsafe fn pdf_doc_add_page_content(buffer: &[u8]) {
     };
 }
 
+/// Whether page content is currently being captured into a form XObject.
+pub(crate) unsafe fn pdf_doc_grabbing_form() -> bool {
+    !pdoc.pending_forms.is_null()
+}
+
 static mut doccreator: *mut i8 = ptr::null_mut();
 /* Ugh */
 
@@ -2376,7 +2414,14 @@ pub(crate) unsafe fn pdf_open_document(
     p.opt.outline_open_depth = bookmark_open_depth;
     pdf_init_resources();
     pdf_init_colors();
+    if pdf_get_pdfa().is_some() {
+        let intents = pdfa_create_output_intents();
+        if !intents.is_null() {
+            (*p.root.dict).as_dict_mut().set("OutputIntents", intents);
+        }
+    }
     pdf_init_fonts();
+    pdf_struct_init();
     /* Thumbnail want this to be initialized... */
     pdf_init_images();
     pdf_doc_init_docinfo(p);
@@ -2444,6 +2489,7 @@ pub(crate) unsafe fn pdf_close_document() {
     pdf_doc_close_bookmarks(p);
     pdf_doc_close_page_tree(p);
     pdf_doc_close_docinfo(p);
+    pdf_struct_close((*p).root.dict);
     pdf_doc_close_catalog(p);
     pdf_close_images();
     pdf_close_fonts();
@@ -2546,14 +2592,17 @@ pub(crate) unsafe fn pdf_doc_end_grabbing(attrib: *mut pdf_obj) {
     pdf_dev_grestore_to((*fnode).q_depth as usize);
     /*
      * ProcSet is obsolete in PDF-1.4 but recommended for compatibility.
+     * PDF 2.0 drops it altogether.
      */
-    let mut procset = vec![];
-    procset.push_obj("PDF");
-    procset.push_obj("Text");
-    procset.push_obj("ImageC");
-    procset.push_obj("ImageB");
-    procset.push_obj("ImageI");
-    (*(*form).resources).as_dict_mut().set("ProcSet", procset);
+    if pdf_get_version() < PDF_VERSION_2_0 {
+        let mut procset = vec![];
+        procset.push_obj("PDF");
+        procset.push_obj("Text");
+        procset.push_obj("ImageC");
+        procset.push_obj("ImageB");
+        procset.push_obj("ImageI");
+        (*(*form).resources).as_dict_mut().set("ProcSet", procset);
+    }
     let matrix = (*form).matrix.clone();
     pdf_doc_make_xform(
         (*form).contents,
@@ -42,6 +42,7 @@ use super::dpx_cmap_read::{CMap_parse, CMap_parse_check_sig};
 use super::dpx_cmap_write::CMap_create_stream;
 use super::dpx_dpxfile::dpx_tt_open;
 use super::dpx_mem::new;
+use super::dpx_pdfa::pdf_get_pdfa;
 use crate::bridge::{ttstub_input_close, ttstub_input_get_size, ttstub_input_open};
 use crate::dpx_pdfobj::{
     pdf_copy_name, pdf_dict, pdf_get_version, pdf_link_obj, pdf_name, pdf_obj, pdf_release_obj,
@@ -398,7 +399,17 @@ pub(crate) unsafe fn pdf_encoding_complete() {
         const FLAG_IS_PREDEFINED: i32 = 1;
         const FLAG_USED_BY_TYPE3: i32 = 2;
         let encoding_is_predefined = (encoding.flags & FLAG_IS_PREDEFINED) != 0;
-        if !encoding_is_predefined {
+        if encoding_is_predefined && pdf_get_pdfa().is_some() {
+            /* PDF/A wants a ToUnicode CMap even for the standard encodings. */
+            assert!(encoding.tounicode.is_null());
+            encoding.tounicode = pdf_create_ToUnicode_CMap(
+                &encoding.enc_name,
+                encoding.glyphs.as_mut_ptr(),
+                encoding.is_used.as_mut_ptr(),
+            )
+            .map(IntoObj::into_obj)
+            .unwrap_or(ptr::null_mut());
+        } else if !encoding_is_predefined {
             /* Section 5.5.4 of the PDF 1.5 reference says that the encoding
              * of a Type 3 font must be completely described by a Differences
              * array, but implementation note 56 explains that this is rather
@@ -541,7 +552,9 @@ pub(crate) unsafe fn pdf_create_ToUnicode_CMap(
     CMap_set_wmode(&mut cmap, 0i32);
     CMap_set_CIDSysInfo(&mut cmap, &mut CSI_UNICODE);
     CMap_add_codespacerange(&mut cmap, range_min.as_mut_ptr(), range_max.as_mut_ptr(), 1);
-    let mut all_predef = 1;
+    /* In PDF/A mode every code gets an explicit mapping. */
+    let complete = pdf_get_version() < 5_u32 || pdf_get_pdfa().is_some();
+    let mut all_predef = if pdf_get_pdfa().is_some() { 0 } else { 1 };
     for code in 0..=0xff {
         if !(!is_used.is_null() && *is_used.offset(code as isize) == 0) {
             if !(*enc_vec.offset(code as isize)).is_null() {
@@ -550,7 +563,7 @@ pub(crate) unsafe fn pdf_create_ToUnicode_CMap(
                 /* Adobe glyph naming conventions are not used by viewers,
                  * hence even ligatures (e.g, "f_i") must be explicitly defined
                  */
-                if pdf_get_version() < 5_u32 || agln.is_null() || (*agln).is_predef == 0 {
+                if complete || agln.is_null() || (*agln).is_predef == 0 {
                     wbuf[0] = (code & 0xffi32) as u8;
                     let mut p = wbuf.as_mut_ptr().offset(1);
                     let endptr = wbuf.as_mut_ptr().offset(1024);
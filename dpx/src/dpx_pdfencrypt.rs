@@ -35,7 +35,7 @@ use super::dpx_mem::new;
 use super::dpx_pdfdoc::pdf_doc_get_dictionary;
 use super::dpx_pdffont::get_unique_time_if_given;
 use super::dpx_unicode::{UC_UTF8_decode_char, UC_is_valid};
-use crate::dpx_pdfobj::{pdf_dict, pdf_get_version, pdf_obj, pdf_string, PushObj};
+use crate::dpx_pdfobj::{pdf_dict, pdf_get_version, pdf_obj, pdf_string, PushObj, PDF_VERSION_2_0};
 use crate::warn;
 use chrono::prelude::*;
 use libc::{free, memcpy, memset, srand, strcpy, strlen};
@@ -822,7 +822,9 @@ pub(crate) unsafe fn pdf_encrypt_obj() -> pdf_dict {
         );
         free(cipher as *mut libc::c_void);
     }
-    if p.R > 5i32 {
+    /* PDF 2.0 includes AES-256 itself, so the extension is only declared
+     * for PDF 1.7. */
+    if p.R > 5i32 && pdf_get_version() < PDF_VERSION_2_0 {
         let catalog: *mut pdf_obj = pdf_doc_get_dictionary("Catalog");
         let mut ext = pdf_dict::new();
         let mut adbe = pdf_dict::new();
@@ -39,6 +39,7 @@ use super::dpx_cmap::{
 };
 use super::dpx_fontmap::pdf_lookup_fontmap_record;
 use super::dpx_mem::new;
+use super::dpx_pdfa::pdf_get_pdfa;
 use super::dpx_pdfencoding::{
     pdf_close_encodings, pdf_encoding_add_usedchars, pdf_encoding_complete,
     pdf_encoding_findresource, pdf_encoding_get_name, pdf_encoding_get_tounicode,
@@ -437,6 +438,13 @@ pub(crate) unsafe fn pdf_close_fonts() {
         }
         if font.encoding_id >= 0i32 && font.subtype != 4i32 {
             pdf_encoding_add_usedchars(font.encoding_id, font.usedchars);
+        } else if font.subtype == 3i32 && pdf_get_pdfa().is_some() {
+            /* Mark the MacRoman codes in use so that PDF/A gets a ToUnicode
+             * CMap for them below. */
+            let macroman = pdf_encoding_findresource("MacRomanEncoding");
+            if macroman >= 0i32 {
+                pdf_encoding_add_usedchars(macroman, font.usedchars);
+            }
         }
         if __verbose != 0 {
             if font.subtype != 4i32 {
@@ -478,6 +486,31 @@ pub(crate) unsafe fn pdf_close_fonts() {
             (*(*font_0).resource)
                 .as_dict_mut()
                 .set("Encoding", "MacRomanEncoding"); /* After encoding */
+            if pdf_get_pdfa().is_some() && !(*(*font_0).resource).as_dict().has("ToUnicode") {
+                let macroman = pdf_encoding_findresource("MacRomanEncoding");
+                let tounicode = if macroman >= 0i32 {
+                    pdf_encoding_get_tounicode(macroman)
+                } else {
+                    ptr::null_mut()
+                };
+                if !tounicode.is_null() {
+                    (*(*font_0).resource)
+                        .as_dict_mut()
+                        .set("ToUnicode", pdf_ref_obj(tounicode));
+                }
+            }
+        }
+        if let Some(level) = pdf_get_pdfa() {
+            if (*font_0).subtype != 4i32
+                && !(*font_0).resource.is_null()
+                && !(*(*font_0).resource).as_dict().has("ToUnicode")
+            {
+                warn!(
+                    "Font \"{}\" has no ToUnicode mapping; the output will not conform to {}.",
+                    (*font_0).ident,
+                    level
+                );
+            }
         }
         pdf_flush_font(&mut *font_0);
         pdf_clean_font_struct(font_0);
@@ -0,0 +1,840 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2020 the Tectonic Project
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! Linearized ("fast web view") output.
+//!
+//! Objects are written out as soon as they are released, long before we
+//! know which page needs them, so a linearized file cannot be produced as
+//! we go. Instead, when linearization is requested, the whole PDF is kept
+//! in memory and rearranged here once it is complete, following Annex F of
+//! the PDF specification:
+//!
+//! 1. header
+//! 2. linearization dictionary
+//! 3. first-page cross-reference section and trailer
+//! 4. document catalog
+//! 5. primary hint stream
+//! 6. first page: its page object and everything it uses
+//! 7. the remaining pages, each with the objects only it uses
+//! 8. objects shared by several pages
+//! 9. everything else (outlines, names, the page tree, ...)
+//! 10. main cross-reference section and trailer
+//!
+//! Objects are renumbered so that each cross-reference section is a single
+//! contiguous range. Since the encryption key of an object depends on its
+//! number, encrypted documents cannot be linearized this way. Object
+//! streams are not used either.
+
+use std::collections::{HashMap, HashSet};
+
+type Span = (usize, usize, u32);
+
+enum Value {
+    Ref(u32),
+    Name(Vec<u8>),
+    Array(Vec<Value>),
+    Dict(Vec<(Vec<u8>, Value)>),
+    Other,
+}
+
+impl Value {
+    fn get(&self, key: &[u8]) -> Option<&Value> {
+        match self {
+            Value::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn is_name(&self, name: &[u8]) -> bool {
+        match self {
+            Value::Name(n) => n == name,
+            _ => false,
+        }
+    }
+
+    fn as_ref(&self) -> Option<u32> {
+        match self {
+            Value::Ref(label) => Some(*label),
+            _ => None,
+        }
+    }
+}
+
+fn is_white(c: u8) -> bool {
+    b"\x00\t\n\x0c\r ".contains(&c)
+}
+
+fn is_delim(c: u8) -> bool {
+    b"()<>[]{}/%".contains(&c)
+}
+
+fn parse_uint(token: &[u8]) -> Option<u32> {
+    if token.is_empty() || !token.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(token).ok()?.parse().ok()
+}
+
+/// Just enough of a PDF parser to follow the references between the
+/// objects we have written ourselves.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Byte ranges of the `n g R` references found, with their targets.
+    refs: Vec<Span>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Parser {
+            data,
+            pos: 0,
+            refs: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn skip_white(&mut self) {
+        loop {
+            while matches!(self.peek(), Some(c) if is_white(c)) {
+                self.pos += 1;
+            }
+            if self.peek() != Some(b'%') {
+                break;
+            }
+            while matches!(self.peek(), Some(c) if c != b'\n' && c != b'\r') {
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn token(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if !is_white(c) && !is_delim(c)) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn name(&mut self) -> Vec<u8> {
+        let raw = self.token();
+        let mut name = Vec::with_capacity(raw.len());
+        let mut i = 0;
+        while i < raw.len() {
+            let decoded = if raw[i] == b'#' && i + 2 < raw.len() {
+                std::str::from_utf8(&raw[i + 1..i + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            } else {
+                None
+            };
+            match decoded {
+                Some(c) => {
+                    name.push(c);
+                    i += 3;
+                }
+                None => {
+                    name.push(raw[i]);
+                    i += 1;
+                }
+            }
+        }
+        name
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_white();
+        let start = self.pos;
+        match self.peek() {
+            None => Err("unexpected end of object".to_string()),
+            Some(b'<') if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                let mut entries = Vec::new();
+                loop {
+                    self.skip_white();
+                    if self.data[self.pos..].starts_with(b">>") {
+                        self.pos += 2;
+                        return Ok(Value::Dict(entries));
+                    }
+                    if self.peek() != Some(b'/') {
+                        return Err(format!("dictionary key expected at byte {}", self.pos));
+                    }
+                    self.pos += 1;
+                    let key = self.name();
+                    let value = self.value()?;
+                    entries.push((key, value));
+                }
+            }
+            Some(b'<') => match self.data[self.pos..].iter().position(|&c| c == b'>') {
+                Some(len) => {
+                    self.pos += len + 1;
+                    Ok(Value::Other)
+                }
+                None => Err("unterminated hex string".to_string()),
+            },
+            Some(b'(') => {
+                let mut depth = 0;
+                loop {
+                    match self.peek() {
+                        None => return Err("unterminated string".to_string()),
+                        Some(b'\\') => self.pos += 1,
+                        Some(b'(') => depth += 1,
+                        Some(b')') => depth -= 1,
+                        Some(_) => {}
+                    }
+                    self.pos += 1;
+                    if depth == 0 {
+                        return Ok(Value::Other);
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_white();
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                }
+            }
+            Some(b'/') => {
+                self.pos += 1;
+                Ok(Value::Name(self.name()))
+            }
+            Some(c) => {
+                let token = self.token();
+                if token.is_empty() {
+                    return Err(format!("unexpected {:?} at byte {}", c as char, start));
+                }
+                if let Some(label) = parse_uint(token) {
+                    let save = self.pos;
+                    self.skip_white();
+                    if parse_uint(self.token()).is_some() {
+                        self.skip_white();
+                        if self.token() == b"R" {
+                            self.refs.push((start, self.pos, label));
+                            return Ok(Value::Ref(label));
+                        }
+                    }
+                    self.pos = save;
+                }
+                Ok(Value::Other)
+            }
+        }
+    }
+}
+
+struct Object<'a> {
+    data: &'a [u8],
+    /// Where the object's value starts, just after `n g obj`.
+    body: usize,
+    value: Value,
+    refs: Vec<Span>,
+}
+
+impl<'a> Object<'a> {
+    fn parse(label: u32, data: &'a [u8]) -> Result<Self, String> {
+        let mut p = Parser::new(data);
+        p.skip_white();
+        let number = parse_uint(p.token());
+        p.skip_white();
+        let generation = parse_uint(p.token());
+        p.skip_white();
+        if number != Some(label) || generation.is_none() || p.token() != b"obj" {
+            return Err(format!("object {} is malformed", label));
+        }
+        let body = p.pos;
+        let value = p.value()?;
+        Ok(Object {
+            data,
+            body,
+            value,
+            refs: p.refs,
+        })
+    }
+
+    /// Collects the objects this one refers to, leaving out `/Parent`
+    /// links, which lead back up the page tree.
+    fn references(&self) -> Vec<u32> {
+        fn collect(value: &Value, out: &mut Vec<u32>) {
+            match value {
+                Value::Ref(label) => out.push(*label),
+                Value::Array(items) => items.iter().for_each(|v| collect(v, out)),
+                Value::Dict(entries) => entries
+                    .iter()
+                    .filter(|(k, _)| k != b"Parent")
+                    .for_each(|(_, v)| collect(v, out)),
+                _ => {}
+            }
+        }
+        let mut out = Vec::new();
+        collect(&self.value, &mut out);
+        out
+    }
+}
+
+/// Copies `data[from..]`, rewriting the references at `refs` to the new
+/// object numbers.
+fn renumber(
+    out: &mut Vec<u8>,
+    data: &[u8],
+    from: usize,
+    refs: &[Span],
+    labels: &HashMap<u32, u32>,
+) -> Result<(), String> {
+    let mut pos = from;
+    for &(start, end, target) in refs {
+        let label = labels
+            .get(&target)
+            .ok_or_else(|| format!("reference to missing object {}", target))?;
+        out.extend_from_slice(&data[pos..start]);
+        out.extend_from_slice(format!("{} 0 R", label).as_bytes());
+        pos = end;
+    }
+    out.extend_from_slice(&data[pos..]);
+    Ok(())
+}
+
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    byte: u8,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u64, nbits: u32) {
+        for i in (0..nbits).rev() {
+            self.byte = self.byte << 1 | ((value >> i) & 1) as u8;
+            self.nbits += 1;
+            if self.nbits == 8 {
+                self.data.push(self.byte);
+                self.byte = 0;
+                self.nbits = 0;
+            }
+        }
+    }
+
+    /// Hint table items start on a byte boundary.
+    fn pad(&mut self) {
+        if self.nbits > 0 {
+            self.bits(0, 8 - self.nbits);
+        }
+    }
+}
+
+fn nbits(value: u64) -> u32 {
+    64 - value.leading_zeros()
+}
+
+/// Where the pieces that go into the hint tables ended up.
+struct Layout {
+    /// Offset and length of each page: its page object and the objects
+    /// only it uses (for the first page, all of part 6).
+    pages: Vec<(u64, u64, u64)>,
+    /// For each page, the indices into the shared object table of the
+    /// shared objects it uses.
+    shared_refs: Vec<Vec<u64>>,
+    /// Lengths of the shared objects: first those in part 6, then those in
+    /// part 8.
+    shared_lengths: Vec<u64>,
+    first_page_shared: u64,
+    /// Number and offset of the first object in part 8, if any.
+    shared_section: (u64, u64),
+}
+
+/// Builds the page offset and shared object hint tables (F.4.1 and
+/// F.4.2), returning the data and the offset of the shared object table.
+fn hint_tables(layout: &Layout) -> (Vec<u8>, usize) {
+    let mut w = BitWriter::default();
+    let pages = &layout.pages;
+    let least_objects = pages.iter().map(|p| p.2).min().unwrap_or(0);
+    let most_objects = pages.iter().map(|p| p.2).max().unwrap_or(0);
+    let least_length = pages.iter().map(|p| p.1).min().unwrap_or(0);
+    let most_length = pages.iter().map(|p| p.1).max().unwrap_or(0);
+    let most_shared = layout.shared_refs.iter().map(Vec::len).max().unwrap_or(0);
+    let length_bits = nbits(most_length - least_length);
+    let shared_id_bits = nbits((layout.shared_lengths.len() as u64).saturating_sub(1));
+
+    w.bits(least_objects, 32);
+    w.bits(pages.first().map_or(0, |p| p.0), 32);
+    w.bits(u64::from(nbits(most_objects - least_objects)), 16);
+    w.bits(least_length, 32);
+    w.bits(u64::from(length_bits), 16);
+    /* Like Acrobat, we describe the content stream of each page as
+     * spanning the whole page, so the offsets are all zero. */
+    w.bits(0, 32);
+    w.bits(0, 16);
+    w.bits(least_length, 32);
+    w.bits(u64::from(length_bits), 16);
+    w.bits(u64::from(nbits(most_shared as u64)), 16);
+    w.bits(u64::from(shared_id_bits), 16);
+    w.bits(0, 16);
+    w.bits(1, 16);
+
+    for page in pages {
+        w.bits(page.2 - least_objects, nbits(most_objects - least_objects));
+    }
+    w.pad();
+    for page in pages {
+        w.bits(page.1 - least_length, length_bits);
+    }
+    w.pad();
+    for refs in &layout.shared_refs {
+        w.bits(refs.len() as u64, nbits(most_shared as u64));
+    }
+    w.pad();
+    for refs in &layout.shared_refs {
+        for &id in refs {
+            w.bits(id, shared_id_bits);
+        }
+    }
+    w.pad();
+    /* No numerators, since they take no bits, and content stream offsets,
+     * which are all zero. */
+    for page in pages {
+        w.bits(page.1 - least_length, length_bits);
+    }
+    w.pad();
+
+    let shared_offset = w.data.len();
+    let lengths = &layout.shared_lengths;
+    let least_length = lengths.iter().copied().min().unwrap_or(0);
+    let most_length = lengths.iter().copied().max().unwrap_or(0);
+    let length_bits = nbits(most_length - least_length);
+    w.bits(layout.shared_section.0, 32);
+    w.bits(layout.shared_section.1, 32);
+    w.bits(layout.first_page_shared, 32);
+    w.bits(lengths.len() as u64, 32);
+    /* Every group holds a single object. */
+    w.bits(0, 16);
+    w.bits(least_length, 32);
+    w.bits(u64::from(length_bits), 16);
+    for &length in lengths {
+        w.bits(length - least_length, length_bits);
+    }
+    w.pad();
+    for _ in lengths {
+        w.bits(0, 1);
+    }
+    w.pad();
+    (w.data, shared_offset)
+}
+
+fn linearization_dict(label: u32, values: [u64; 7]) -> Vec<u8> {
+    let [length, hint_offset, hint_length, first_page, first_page_end, npages, main_xref] = values;
+    /* The values are padded so the object doesn't change size once the
+     * real ones are known. */
+    format!(
+        "{} 0 obj\n<</Linearized 1 /L {:<10} /H [{:<10} {:<10}] /O {:<10} /E {:<10} /N {:<10} /T {:<10}>>\nendobj\n",
+        label, length, hint_offset, hint_length, first_page, first_page_end, npages, main_xref
+    )
+    .into_bytes()
+}
+
+/// Returns the offsets of `objects` when written one after the other from
+/// `offset`, which is advanced past them.
+fn place(objects: &[Vec<u8>], offset: &mut u64) -> Vec<u64> {
+    objects
+        .iter()
+        .map(|data| {
+            let at = *offset;
+            *offset += data.len() as u64;
+            at
+        })
+        .collect()
+}
+
+fn xref_entries(out: &mut Vec<u8>, offsets: &[u64]) {
+    for offset in offsets {
+        out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+}
+
+/// Rearranges a complete PDF file into a linearized one. `objects` holds
+/// the label and full text (`n g obj ... endobj`) of each object in the
+/// file, and `trailer` the trailer dictionary, without `/Size`.
+pub(crate) fn linearize(
+    header: &[u8],
+    objects: &[(u32, &[u8])],
+    trailer: &[u8],
+) -> Result<Vec<u8>, String> {
+    let objects = objects
+        .iter()
+        .map(|&(label, data)| Object::parse(label, data).map(|obj| (label, obj)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let index: HashMap<u32, usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, (label, _))| (*label, i))
+        .collect();
+    let lookup = |value: Option<&Value>| {
+        value
+            .and_then(Value::as_ref)
+            .and_then(|l| index.get(&l).copied())
+    };
+
+    let mut tp = Parser::new(trailer);
+    let trailer_value = tp.value()?;
+    let trailer_refs = tp.refs;
+    let root = lookup(trailer_value.get(b"Root")).ok_or("no document catalog")?;
+
+    /* Find the pages, in order. Neither they nor the page tree nodes are
+     * followed when collecting the objects each page uses. */
+    let mut pages = Vec::new();
+    let mut barrier = HashSet::new();
+    barrier.insert(root);
+    let mut stack = vec![lookup(objects[root].1.value.get(b"Pages")).ok_or("no page tree")?];
+    while let Some(node) = stack.pop() {
+        if !barrier.insert(node) {
+            return Err("loop in the page tree".to_string());
+        }
+        let value = &objects[node].1.value;
+        match value.get(b"Kids") {
+            Some(Value::Array(kids)) if !matches!(value.get(b"Type"), Some(t) if t.is_name(b"Page")) => {
+                for kid in kids.iter().rev() {
+                    stack.push(lookup(Some(kid)).ok_or("broken page tree")?);
+                }
+            }
+            _ => pages.push(node),
+        }
+    }
+    if pages.is_empty() {
+        return Err("no pages".to_string());
+    }
+
+    let uses: Vec<Vec<usize>> = pages
+        .iter()
+        .map(|&page| {
+            let mut seen = HashSet::new();
+            let mut order = Vec::new();
+            let mut stack = vec![page];
+            while let Some(i) = stack.pop() {
+                if i != page {
+                    order.push(i);
+                }
+                for label in objects[i].1.references().iter().rev() {
+                    if let Some(&j) = index.get(label) {
+                        if !barrier.contains(&j) && seen.insert(j) {
+                            stack.push(j);
+                        }
+                    }
+                }
+            }
+            order
+        })
+        .collect();
+    let mut users = vec![0; objects.len()];
+    for &i in uses.iter().flatten() {
+        users[i] += 1;
+    }
+
+    let mut first_page = vec![pages[0]];
+    first_page.extend(&uses[0]);
+    let in_first_page: HashSet<usize> = first_page.iter().copied().collect();
+    let other_pages: Vec<Vec<usize>> = pages[1..]
+        .iter()
+        .zip(&uses[1..])
+        .map(|(&page, used)| {
+            let mut group = vec![page];
+            group.extend(used.iter().filter(|&&i| users[i] == 1));
+            group
+        })
+        .collect();
+    let mut shared = Vec::new();
+    for &i in uses[1..].iter().flatten() {
+        if users[i] > 1 && !in_first_page.contains(&i) && !shared.contains(&i) {
+            shared.push(i);
+        }
+    }
+    let mut placed: HashSet<usize> = first_page.iter().chain(&shared).copied().collect();
+    placed.extend(other_pages.iter().flatten());
+    placed.insert(root);
+    let rest: Vec<usize> = (0..objects.len()).filter(|i| !placed.contains(i)).collect();
+
+    /* Number the objects: the main section first, then the first-page
+     * section, which holds the linearization dictionary, the catalog, the
+     * hint stream and the first page. */
+    let main: Vec<usize> = other_pages
+        .iter()
+        .flatten()
+        .chain(&shared)
+        .chain(&rest)
+        .copied()
+        .collect();
+    let main_count = main.len() as u32;
+    let mut labels = HashMap::new();
+    for (n, &i) in main.iter().enumerate() {
+        labels.insert(objects[i].0, n as u32 + 1);
+    }
+    let lin_label = main_count + 1;
+    labels.insert(objects[root].0, main_count + 2);
+    let hint_label = main_count + 3;
+    for (n, &i) in first_page.iter().enumerate() {
+        labels.insert(objects[i].0, main_count + 4 + n as u32);
+    }
+    let size = main_count + 4 + first_page.len() as u32;
+
+    let render = |i: usize| -> Result<Vec<u8>, String> {
+        let (label, obj) = &objects[i];
+        let mut out = format!("{} 0 obj", labels[label]).into_bytes();
+        renumber(&mut out, obj.data, obj.body, &obj.refs, &labels)?;
+        Ok(out)
+    };
+    let catalog = render(root)?;
+    let first_page_data = first_page
+        .iter()
+        .map(|&i| render(i))
+        .collect::<Result<Vec<_>, _>>()?;
+    let main_data = main
+        .iter()
+        .map(|&i| render(i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut trailer_data = Vec::new();
+    renumber(&mut trailer_data, trailer, 0, &trailer_refs, &labels)?;
+    let trailer_entries = trailer_data
+        .get(2..trailer_data.len().saturating_sub(2))
+        .ok_or("malformed trailer")?;
+    let first_trailer = |prev: u64| {
+        let mut out = format!("trailer\n<</Size {} /Prev {:<10} ", size, prev).into_bytes();
+        out.extend_from_slice(trailer_entries);
+        out.extend_from_slice(b">>\nstartxref\n0\n%%EOF\n");
+        out
+    };
+
+    /* Lay out the file as if there were no hint stream, which is how the
+     * hint tables describe it. */
+    let lin_offset = header.len() as u64;
+    let lin_length = linearization_dict(lin_label, [0; 7]).len() as u64;
+    let first_xref_offset = lin_offset + lin_length;
+    let first_xref_head = format!("xref\n{} {}\n", lin_label, size - lin_label);
+    let first_xref_length =
+        (first_xref_head.len() + 20 * (size - lin_label) as usize + first_trailer(0).len()) as u64;
+    let catalog_offset = first_xref_offset + first_xref_length;
+    let hint_offset = catalog_offset + catalog.len() as u64;
+
+    let mut offset = hint_offset;
+    let first_page_offsets = place(&first_page_data, &mut offset);
+    let first_page_end = offset;
+    let main_offsets = place(&main_data, &mut offset);
+    let main_xref_offset = offset;
+
+    let position: HashMap<usize, usize> = main.iter().enumerate().map(|(n, &i)| (i, n)).collect();
+    let mut page_layout = vec![(
+        first_page_offsets[0],
+        first_page_end - first_page_offsets[0],
+        first_page.len() as u64,
+    )];
+    let mut n = 0;
+    for group in &other_pages {
+        let start = main_offsets[n];
+        let end = main_offsets
+            .get(n + group.len())
+            .copied()
+            .unwrap_or(main_xref_offset);
+        page_layout.push((start, end - start, group.len() as u64));
+        n += group.len();
+    }
+    let shared_ids: HashMap<usize, u64> = first_page
+        .iter()
+        .chain(&shared)
+        .enumerate()
+        .map(|(id, &i)| (i, id as u64))
+        .collect();
+    let mut shared_refs = vec![Vec::new()];
+    for used in &uses[1..] {
+        shared_refs.push(
+            used.iter()
+                .filter(|&&i| users[i] > 1)
+                .map(|i| shared_ids[i])
+                .collect(),
+        );
+    }
+    let mut shared_lengths: Vec<u64> = first_page_data.iter().map(|d| d.len() as u64).collect();
+    shared_lengths.extend(shared.iter().map(|i| main_data[position[i]].len() as u64));
+    let shared_section = match shared.first() {
+        Some(i) => (u64::from(labels[&objects[*i].0]), main_offsets[position[i]]),
+        None => (0, 0),
+    };
+    let (hints, shared_offset) = hint_tables(&Layout {
+        pages: page_layout,
+        shared_refs,
+        shared_lengths,
+        first_page_shared: first_page.len() as u64,
+        shared_section,
+    });
+    let mut hint = format!(
+        "{} 0 obj\n<</Length {} /S {}>>\nstream\n",
+        hint_label,
+        hints.len(),
+        shared_offset
+    )
+    .into_bytes();
+    hint.extend_from_slice(&hints);
+    hint.extend_from_slice(b"\nendstream\nendobj\n");
+
+    /* Now account for the hint stream and put it all together. */
+    let shift = hint.len() as u64;
+    let main_xref_offset = main_xref_offset + shift;
+    let main_xref_head = format!("xref\n0 {}", main_count + 1);
+    let mut main_xref = format!("{}\n0000000000 65535 f \n", main_xref_head).into_bytes();
+    xref_entries(
+        &mut main_xref,
+        &main_offsets.iter().map(|o| o + shift).collect::<Vec<_>>(),
+    );
+    main_xref.extend_from_slice(format!("trailer\n<</Size {}>>\n", size).as_bytes());
+    main_xref.extend_from_slice(format!("startxref\n{}\n%%EOF\n", first_xref_offset).as_bytes());
+    let file_length = main_xref_offset + main_xref.len() as u64;
+
+    let mut out = Vec::with_capacity(file_length as usize);
+    out.extend_from_slice(header);
+    out.extend_from_slice(&linearization_dict(
+        lin_label,
+        [
+            file_length,
+            hint_offset,
+            shift,
+            u64::from(labels[&objects[pages[0]].0]),
+            first_page_end + shift,
+            pages.len() as u64,
+            main_xref_offset + main_xref_head.len() as u64,
+        ],
+    ));
+    out.extend_from_slice(first_xref_head.as_bytes());
+    let mut first_offsets = vec![lin_offset, catalog_offset, hint_offset];
+    first_offsets.extend(first_page_offsets.iter().map(|o| o + shift));
+    xref_entries(&mut out, &first_offsets);
+    out.extend_from_slice(&first_trailer(main_xref_offset));
+    out.extend_from_slice(&catalog);
+    out.extend_from_slice(&hint);
+    for data in first_page_data.iter().chain(&main_data) {
+        out.extend_from_slice(data);
+    }
+    out.extend_from_slice(&main_xref);
+    debug_assert_eq!(out.len() as u64, file_length);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample() -> Vec<(u32, Vec<u8>)> {
+        let objects: &[(u32, &str)] = &[
+            (1, "<</Type/Catalog/Pages 2 0 R/Outlines 9 0 R>>"),
+            (2, "<</Type/Pages/Count 2/Kids[3 0 R 4 0 R]>>"),
+            (3, "<</Type/Page/Parent 2 0 R/Contents 5 0 R/Resources<</Font<</F1 7 0 R>>>>>>"),
+            (4, "<</Type/Page/Parent 2 0 R/Contents 6 0 R/Resources<</Font<</F1 7 0 R/F2 8 0 R>>>>>>"),
+            (5, "<</Length 8>>\nstream\n(1 0 R)\n\nendstream"),
+            (6, "<</Length 3>>\nstream\nBT \nendstream"),
+            (7, "<</Type/Font/Name (a\\) 3 0 R)>>"),
+            (8, "<</Type/Font/Dest[3 0 R/Fit]>>"),
+            (9, "<</Type/Outlines/First 3 0 R>>"),
+        ];
+        objects
+            .iter()
+            .map(|(l, body)| (*l, format!("{} 0 obj\n{}\nendobj\n", l, body).into_bytes()))
+            .collect()
+    }
+
+    fn find(pdf: &[u8], needle: &str) -> usize {
+        pdf.windows(needle.len())
+            .position(|w| w == needle.as_bytes())
+            .unwrap()
+    }
+
+    fn rfind(pdf: &[u8], needle: &str) -> usize {
+        pdf.windows(needle.len())
+            .rposition(|w| w == needle.as_bytes())
+            .unwrap()
+    }
+
+    fn xref_offsets(pdf: &[u8], at: usize) -> Vec<(u32, usize)> {
+        let text = String::from_utf8_lossy(&pdf[at..]);
+        let mut lines = text.lines().skip(1);
+        let head: Vec<u32> = lines
+            .next()
+            .unwrap()
+            .split(' ')
+            .map(|n| n.parse().unwrap())
+            .collect();
+        (head[0]..head[0] + head[1])
+            .zip(lines)
+            .filter(|(_, line)| line.ends_with("n "))
+            .map(|(label, line)| (label, line[..10].parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn linearized_layout() {
+        let sample = sample();
+        let objects: Vec<(u32, &[u8])> = sample.iter().map(|(l, d)| (*l, &d[..])).collect();
+        let header = b"%PDF-1.5\n";
+        let pdf = linearize(header, &objects, b"<</Root 1 0 R/Info 9 0 R>>").unwrap();
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.5\n6 0 obj\n<</Linearized 1 /L "));
+        assert!(text.contains(&format!("/L {:<10}", pdf.len())));
+        assert!(text.contains("/N 2 "));
+
+        /* Every cross-reference entry must point at its object. */
+        let first_xref = find(&pdf, "xref");
+        let main_xref = rfind(&pdf, "\nxref\n") + 1;
+        assert!(text.ends_with(&format!("startxref\n{}\n%%EOF\n", first_xref)));
+        let mut entries = xref_offsets(&pdf, first_xref);
+        entries.extend(xref_offsets(&pdf, main_xref));
+        assert_eq!(entries.len(), 11);
+        for (label, offset) in entries {
+            assert!(pdf[offset..].starts_with(format!("{} 0 obj\n", label).as_bytes()));
+        }
+
+        /* The first page comes right after the hint stream, and the
+         * page is followed by what it uses, shared or not. */
+        let first_page = find(&pdf, "<</Type/Page/");
+        assert!(find(&pdf, "endstream") < first_page);
+        assert!(pdf[first_page..].starts_with(b"<</Type/Page/Parent 4 0 R/Contents 10 0 R"));
+        assert!(text.contains("/Font<</F1 11 0 R>>"));
+        assert!(text.contains("/Font<</F1 11 0 R/F2 3 0 R>>"));
+        /* References in strings and stream data are left alone. */
+        assert!(text.contains("(a\\) 3 0 R)"));
+        assert!(text.contains("stream\n(1 0 R)\n"));
+    }
+
+    #[test]
+    fn dangling_reference_is_an_error() {
+        let data = b"1 0 obj\n<</Type/Catalog/Pages 5 0 R>>\nendobj\n".to_vec();
+        assert!(linearize(b"%PDF-1.5\n", &[(1, &data[..])], b"<</Root 1 0 R>>").is_err());
+    }
+
+    #[test]
+    fn bits_are_packed_big_endian() {
+        let mut w = BitWriter::default();
+        w.bits(1, 1);
+        w.bits(5, 3);
+        w.pad();
+        w.bits(0x1234, 16);
+        assert_eq!(w.data, vec![0xd0, 0x12, 0x34]);
+    }
+}
@@ -41,6 +41,7 @@ use super::dpx_mem::{new, renew};
 use super::dpx_mfileio::{tt_mfgets, work_buffer, work_buffer_u8 as WORK_BUFFER};
 use super::dpx_pdfdev::pdf_sprint_number;
 use super::dpx_pdfencrypt::{pdf_enc_set_generation, pdf_enc_set_label, pdf_encrypt_data};
+use super::dpx_pdflinear::linearize;
 use super::dpx_pdfparse::skip_white;
 use crate::bridge::{
     ttstub_input_get_size, ttstub_input_getc, ttstub_input_read, ttstub_input_ungetc,
@@ -472,6 +473,9 @@ static mut xref_stream: *mut pdf_obj = ptr::null_mut();
 static mut verbose: i32 = 0i32;
 static mut compression_level: i8 = 9_i8;
 static mut compression_use_predictor: i8 = 1_i8;
+static mut linearize_output: bool = false;
+/* When linearizing, the file is assembled here and rearranged at the end. */
+static mut linear_buffer: Option<Vec<u8>> = None;
 
 pub(crate) unsafe fn pdf_set_compression(level: i32) {
     if cfg!(not(feature = "libz-sys")) {
@@ -489,14 +493,32 @@ pub(crate) unsafe fn pdf_set_compression(level: i32) {
     };
 }
 
+/// Write linearized ("fast web view") PDF. This keeps the whole file in
+/// memory and rules out object streams.
+pub(crate) unsafe fn pdf_set_linearize(enable: bool) {
+    linearize_output = enable;
+}
 pub(crate) unsafe fn pdf_set_use_predictor(bval: i32) {
     compression_use_predictor = (if bval != 0 { 1i32 } else { 0i32 }) as i8;
 }
 static mut pdf_version: u32 = 5_u32;
 
+/// PDF versions 1.x are identified by their minor number. PDF 2.0 gets a
+/// number above all of them, so that checks like `pdf_get_version() >= 5`
+/// keep working.
+pub(crate) const PDF_VERSION_2_0: u32 = 20;
+
+pub(crate) fn pdf_version_name(version: u32) -> String {
+    if version == PDF_VERSION_2_0 {
+        "2.0".to_string()
+    } else {
+        format!("1.{}", version)
+    }
+}
+
 pub(crate) unsafe fn pdf_set_version(version: u32) {
     /* Don't forget to update CIDFont_stdcc_def[] in cid.c too! */
-    if version >= 3_u32 && version <= 7_u32 {
+    if version >= 3_u32 && version <= 7_u32 || version == PDF_VERSION_2_0 {
         pdf_version = version
     };
 }
@@ -536,7 +558,7 @@ pub(crate) unsafe fn pdf_out_init(
     pdf_max_ind_objects = 0;
     add_xref_entry(0, 0_u8, (0_u32, 0xffff_u16));
     next_label = 1;
-    if pdf_version >= 5_u32 {
+    if pdf_version >= 5_u32 && !linearize_output {
         if enable_object_stream {
             xref_stream = pdf_stream::new(STREAM_COMPRESS).into_obj();
             (*xref_stream).flags |= OBJ_NO_ENCRYPT;
@@ -564,10 +586,14 @@ pub(crate) unsafe fn pdf_out_init(
             panic!("Unable to open file.");
         }
     }
+    linear_buffer = if linearize_output {
+        Some(Vec::new())
+    } else {
+        None
+    };
     let handle = pdf_output_handle.as_mut().unwrap();
-    pdf_out(handle, b"%PDF-1.");
-    let v = [b'0' + (pdf_version as u8)];
-    pdf_out(handle, &v[..]);
+    pdf_out(handle, b"%PDF-");
+    pdf_out(handle, pdf_version_name(pdf_version).as_bytes());
     pdf_out(handle, b"\n");
     pdf_out(handle, b"%\xe4\xf0\xed\xf8\n");
     enc_mode = false;
@@ -651,6 +677,50 @@ unsafe fn dump_xref_stream() {
     pdf_release_obj(xref_stream);
 }
 
+/* Rearranges the buffered file into a linearized one and writes it out.
+ * If that fails, the file is written as it is and false returned, so that
+ * the caller can finish it in the usual way. */
+unsafe fn flush_linearized(handle: &mut OutputHandleWrapper) -> bool {
+    let data = linear_buffer.take().unwrap();
+    let position = (pdf_output_file_position, pdf_output_line_position);
+    linear_buffer = Some(Vec::new());
+    enc_mode = false;
+    write_dict((*trailer_dict).as_dict(), handle);
+    let trailer = linear_buffer.take().unwrap();
+    pdf_output_file_position = position.0;
+    pdf_output_line_position = position.1;
+
+    let mut offsets: Vec<(u32, usize)> = (1..next_label)
+        .filter(|&label| output_xref[label].typ == 1)
+        .map(|label| (label as u32, output_xref[label].id.0 as usize))
+        .collect();
+    offsets.sort_by_key(|&(_, offset)| offset);
+    let header_end = offsets.first().map_or(data.len(), |&(_, offset)| offset);
+    let objects: Vec<(u32, &[u8])> = offsets
+        .iter()
+        .enumerate()
+        .map(|(i, &(label, start))| {
+            let end = offsets.get(i + 1).map_or(data.len(), |&(_, offset)| offset);
+            (label, &data[start..end])
+        })
+        .collect();
+    match linearize(&data[..header_end], &objects, &trailer) {
+        Ok(linearized) => {
+            handle.write_all(&linearized).unwrap();
+            pdf_release_obj(trailer_dict);
+            true
+        }
+        Err(msg) => {
+            warn!(
+                "Could not linearize the output ({}); writing it as is.",
+                msg
+            );
+            handle.write_all(&data).unwrap();
+            false
+        }
+    }
+}
+
 pub(crate) unsafe fn pdf_out_flush() {
     if let Some(handle) = pdf_output_handle.as_mut() {
         /* Flush current object stream */
@@ -658,29 +728,31 @@ pub(crate) unsafe fn pdf_out_flush() {
             release_objstm(current_objstm);
             current_objstm = ptr::null_mut()
         }
-        /*
-         * Label xref stream - we need the number of correct objects
-         * for the xref stream dictionary (= trailer).
-         * Labelling it in pdf_out_init (with 1)  does not work (why?).
-         */
-        if !xref_stream.is_null() {
-            pdf_label_obj(xref_stream);
-        }
-        /* Record where this xref is for trailer */
-        startxref = pdf_output_file_position as u32;
-        (*trailer_dict).as_dict_mut().set("Size", next_label as f64);
-        if !xref_stream.is_null() {
-            dump_xref_stream();
-        } else {
-            dump_xref_table();
-            dump_trailer_dict();
+        if linear_buffer.is_none() || !flush_linearized(handle) {
+            /*
+             * Label xref stream - we need the number of correct objects
+             * for the xref stream dictionary (= trailer).
+             * Labelling it in pdf_out_init (with 1)  does not work (why?).
+             */
+            if !xref_stream.is_null() {
+                pdf_label_obj(xref_stream);
+            }
+            /* Record where this xref is for trailer */
+            startxref = pdf_output_file_position as u32;
+            (*trailer_dict).as_dict_mut().set("Size", next_label as f64);
+            if !xref_stream.is_null() {
+                dump_xref_stream();
+            } else {
+                dump_xref_table();
+                dump_trailer_dict();
+            }
+            pdf_out(handle, b"startxref\n");
+            let out = format!("{}\n", startxref);
+            pdf_out(handle, out.as_bytes());
+            pdf_out(handle, b"%%EOF\n");
         }
         /* Done with xref table */
         output_xref = vec![];
-        pdf_out(handle, b"startxref\n");
-        let out = format!("{}\n", startxref);
-        pdf_out(handle, out.as_bytes());
-        pdf_out(handle, b"%%EOF\n");
         if verbose != 0 {
             if compression_level as i32 > 0i32 {
                 info!(
@@ -703,6 +775,7 @@ pub(crate) unsafe fn pdf_error_cleanup() {
      * This routine is the cleanup required for an abnormal exit.
      * For now, simply close the file.
      */
+    linear_buffer = None;
     if pdf_output_handle.is_some() {
         ttstub_output_close(pdf_output_handle.take().unwrap());
     };
@@ -753,7 +826,12 @@ unsafe fn pdf_out_char(handle: &mut OutputHandleWrapper, c: u8) {
     if !output_stream.is_null() && handle == pdf_output_handle.as_mut().unwrap() {
         (*output_stream).as_stream_mut().add_slice([c].as_ref());
     } else {
-        ttstub_output_putc(handle, c as i32);
+        match linear_buffer.as_mut() {
+            Some(buf) if handle == pdf_output_handle.as_mut().unwrap() => buf.push(c),
+            _ => {
+                ttstub_output_putc(handle, c as i32);
+            }
+        }
         /* Keep tallys for xref table *only* if writing a pdf file. */
         if pdf_output_handle.is_some() {
             pdf_output_file_position += 1;
@@ -772,7 +850,14 @@ unsafe fn pdf_out(handle: &mut OutputHandleWrapper, buffer: &[u8]) {
     if !output_stream.is_null() && handle == pdf_output_handle.as_mut().unwrap() {
         (*output_stream).as_stream_mut().add_slice(buffer);
     } else {
-        handle.write(buffer).unwrap();
+        match linear_buffer.as_mut() {
+            Some(buf) if handle == pdf_output_handle.as_mut().unwrap() => {
+                buf.extend_from_slice(buffer)
+            }
+            _ => {
+                handle.write(buffer).unwrap();
+            }
+        }
         /* Keep tallys for xref table *only* if writing a pdf file */
         if pdf_output_handle.is_some() {
             pdf_output_file_position += length;
@@ -3779,6 +3864,9 @@ fn parse_pdf_version(handle: &mut InputHandleWrapper) -> Result<u32, ()> {
         .trim_end()
         .to_string();
 
+    if buffer == "%PDF-2.0" {
+        return Ok(PDF_VERSION_2_0);
+    }
     if !buffer.starts_with("%PDF-1.") {
         return Err(());
     }
@@ -3794,8 +3882,8 @@ pub(crate) unsafe extern "C" fn check_for_pdf(handle: &mut InputHandleWrapper) -
                 true
             } else {
                 warn!(
-                    "Version of PDF file (1.{}) is newer than version limit specification.",
-                    version
+                    "Version of PDF file ({}) is newer than version limit specification.",
+                    pdf_version_name(version)
                 );
                 true
             }
@@ -3924,4 +4012,6 @@ pub(crate) unsafe fn pdf_obj_reset_global_state() {
     pdf_output_file_position = 0;
     pdf_output_line_position = 0;
     compression_saved = 0i32;
+    linearize_output = false;
+    linear_buffer = None;
 }
@@ -0,0 +1,397 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2020 the Tectonic Project
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_upper_case_globals)]
+
+//! The logical structure tree of tagged PDF.
+//!
+//! Structure elements are opened and closed by the `pdf:bstruct` and
+//! `pdf:estruct` specials and nest like the TeX groups that produce them.
+//! Page content is tied to the innermost open element by marked-content
+//! sequences (`pdf:bmc` ... `pdf:emc`), each of which gets the next MCID of
+//! its page and an entry in the parent tree. A marked-content sequence that
+//! is still open at the end of a page is closed there and reopened, with a
+//! fresh MCID, at the top of the next one, since PDF does not allow them to
+//! span content streams.
+//!
+//! Nothing is written unless a structure element is created, so untagged
+//! documents are unaffected.
+
+use std::ptr;
+
+use crate::warn;
+
+use super::dpx_pdfdev::graphics_mode;
+use super::dpx_pdfdoc::{
+    pdf_doc_add_page_content, pdf_doc_current_page_number, pdf_doc_get_dictionary,
+    pdf_doc_get_reference, pdf_doc_grabbing_form,
+};
+use crate::dpx_pdfobj::{pdf_dict, pdf_obj, pdf_ref_obj, pdf_release_obj, IntoObj, PushObj};
+
+struct StructElem {
+    obj: *mut pdf_obj,
+    kids: Vec<*mut pdf_obj>,
+}
+
+enum MarkedContent {
+    /// Content belonging to the structure element at the given depth of
+    /// the stack of open elements.
+    Tagged {
+        tag: Vec<u8>,
+        props: Vec<u8>,
+        depth: usize,
+    },
+    Artifact {
+        props: Vec<u8>,
+    },
+}
+
+struct StructTree {
+    root: *mut pdf_obj,
+    root_kids: Vec<*mut pdf_obj>,
+    open: Vec<StructElem>,
+    marked: Vec<MarkedContent>,
+    role_map: *mut pdf_obj,
+    /* One entry per page with marked content: the page number and, indexed
+     * by MCID, references to the elements owning the content. */
+    parent_tree: Vec<(i32, Vec<*mut pdf_obj>)>,
+}
+
+static mut struct_tree: StructTree = StructTree {
+    root: ptr::null_mut(),
+    root_kids: Vec::new(),
+    open: Vec::new(),
+    marked: Vec::new(),
+    role_map: ptr::null_mut(),
+    parent_tree: Vec::new(),
+};
+
+pub(crate) unsafe fn pdf_struct_init() {
+    let t = &mut struct_tree;
+    t.root = ptr::null_mut();
+    t.root_kids.clear();
+    t.open.clear();
+    t.marked.clear();
+    t.role_map = ptr::null_mut();
+    t.parent_tree.clear();
+}
+
+unsafe fn struct_root() -> *mut pdf_obj {
+    let t = &mut struct_tree;
+    if t.root.is_null() {
+        t.root = pdf_dict::new().into_obj();
+    }
+    t.root
+}
+
+/// Opens a structure element, which becomes a kid of the innermost open
+/// element (or of the structure tree root). `elem` must be a dictionary
+/// with a `/S` entry; ownership passes to the structure tree.
+pub(crate) unsafe fn pdf_struct_begin_elem(elem: *mut pdf_obj) -> i32 {
+    if !matches!((*elem).as_dict().get("S"), Some(s) if s.is_name()) {
+        warn!("Structure element without a /S entry ignored.");
+        pdf_release_obj(elem);
+        return -1;
+    }
+    let root = struct_root();
+    let t = &mut struct_tree;
+    (*elem).as_dict_mut().set("Type", "StructElem");
+    let parent = t.open.last().map(|e| e.obj).unwrap_or(root);
+    (*elem).as_dict_mut().set("P", pdf_ref_obj(parent));
+    match t.open.last_mut() {
+        Some(parent) => parent.kids.push(pdf_ref_obj(elem)),
+        None => t.root_kids.push(pdf_ref_obj(elem)),
+    }
+    t.open.push(StructElem {
+        obj: elem,
+        kids: Vec::new(),
+    });
+    0
+}
+
+/// Closes the innermost open structure element.
+pub(crate) unsafe fn pdf_struct_end_elem() -> i32 {
+    let t = &mut struct_tree;
+    let depth = match t.open.len() {
+        0 => {
+            warn!("No structure element to end.");
+            return -1;
+        }
+        n => n - 1,
+    };
+    let has_open_content = t.marked.iter().any(|mc| match mc {
+        MarkedContent::Tagged { depth: d, .. } => *d >= depth,
+        MarkedContent::Artifact { .. } => false,
+    });
+    if has_open_content {
+        warn!("Cannot end a structure element while its marked content is open.");
+        return -1;
+    }
+    let elem = t.open.pop().unwrap();
+    finish_elem(elem);
+    0
+}
+
+unsafe fn finish_elem(elem: StructElem) {
+    if !elem.kids.is_empty() {
+        (*elem.obj).as_dict_mut().set("K", elem.kids);
+    }
+    pdf_release_obj(elem.obj);
+}
+
+/// Writes a name token, escaping as PDF requires.
+fn push_name(buf: &mut Vec<u8>, name: &[u8]) {
+    buf.push(b'/');
+    for &c in name {
+        if c < b'!' || c > b'~' || b"#()<>[]{}/%".contains(&c) {
+            buf.extend_from_slice(format!("#{:02X}", c).as_bytes());
+        } else {
+            buf.push(c);
+        }
+    }
+}
+
+/// Returns the property list `props` (a dictionary in PDF syntax, possibly
+/// empty) with the entry `/MCID mcid` added.
+fn props_with_mcid(props: &[u8], mcid: usize) -> Vec<u8> {
+    let mut buf = format!("<</MCID {}", mcid).into_bytes();
+    if props.len() >= 4 {
+        buf.push(b' ');
+        buf.extend_from_slice(&props[2..]);
+    } else {
+        buf.extend_from_slice(b">>");
+    }
+    buf
+}
+
+/* Marked-content operators cannot appear inside a text object that they do
+ * not enclose entirely, so any open BT ... ET block is closed first. */
+unsafe fn emit_begin(mc: &MarkedContent) {
+    let t = &mut struct_tree;
+    graphics_mode();
+    let mut buf = vec![b' '];
+    match mc {
+        MarkedContent::Tagged { tag, props, depth } => {
+            let page_no = pdf_doc_current_page_number();
+            if t.parent_tree.last().map(|e| e.0) != Some(page_no) {
+                let page = pdf_doc_get_dictionary("@THISPAGE");
+                (*page)
+                    .as_dict_mut()
+                    .set("StructParents", t.parent_tree.len() as f64);
+                t.parent_tree.push((page_no, Vec::new()));
+            }
+            let elem = &mut t.open[*depth];
+            let owners = &mut t.parent_tree.last_mut().unwrap().1;
+            let mcid = owners.len();
+            owners.push(pdf_ref_obj(elem.obj));
+            let mut mcr = pdf_dict::new();
+            mcr.set("Type", "MCR");
+            mcr.set("Pg", pdf_doc_get_reference("@THISPAGE"));
+            mcr.set("MCID", mcid as f64);
+            elem.kids.push(mcr.into_obj());
+            push_name(&mut buf, tag);
+            buf.push(b' ');
+            buf.extend_from_slice(&props_with_mcid(props, mcid));
+            buf.extend_from_slice(b" BDC");
+        }
+        MarkedContent::Artifact { props } => {
+            buf.extend_from_slice(b"/Artifact");
+            if props.is_empty() {
+                buf.extend_from_slice(b" BMC");
+            } else {
+                buf.push(b' ');
+                buf.extend_from_slice(props);
+                buf.extend_from_slice(b" BDC");
+            }
+        }
+    }
+    pdf_doc_add_page_content(&buf);
+}
+
+/// Begins a marked-content sequence owned by the innermost open structure
+/// element. `tag` defaults to the element's structure type; `props` is an
+/// optional property list in PDF syntax.
+pub(crate) unsafe fn pdf_struct_begin_marked_content(tag: Option<&[u8]>, props: &[u8]) -> i32 {
+    if pdf_doc_grabbing_form() {
+        warn!("Marked content inside a form XObject cannot be tagged.");
+        return -1;
+    }
+    let t = &mut struct_tree;
+    let elem = match t.open.last() {
+        Some(elem) => elem,
+        None => {
+            warn!("Marked content must belong to a structure element; use pdf:bstruct first.");
+            return -1;
+        }
+    };
+    let tag = match tag {
+        Some(tag) => tag.to_vec(),
+        None => (*elem.obj)
+            .as_dict()
+            .get("S")
+            .unwrap()
+            .as_name()
+            .to_bytes()
+            .to_vec(),
+    };
+    let mc = MarkedContent::Tagged {
+        tag,
+        props: props.to_vec(),
+        depth: t.open.len() - 1,
+    };
+    emit_begin(&mc);
+    t.marked.push(mc);
+    0
+}
+
+/// Begins an artifact, i.e. content that is not part of the structure tree
+/// such as running heads or page numbers.
+pub(crate) unsafe fn pdf_struct_begin_artifact(props: &[u8]) -> i32 {
+    let mc = MarkedContent::Artifact {
+        props: props.to_vec(),
+    };
+    emit_begin(&mc);
+    struct_tree.marked.push(mc);
+    0
+}
+
+/// Ends the innermost marked-content sequence or artifact. `artifact`
+/// says which of the two the caller expects to end.
+pub(crate) unsafe fn pdf_struct_end_marked_content(artifact: bool) -> i32 {
+    let t = &mut struct_tree;
+    match t.marked.last() {
+        Some(MarkedContent::Artifact { .. }) if artifact => {}
+        Some(MarkedContent::Tagged { .. }) if !artifact => {}
+        Some(_) => {
+            warn!("Marked content and artifacts are not properly nested.");
+            return -1;
+        }
+        None => {
+            warn!("No marked content to end.");
+            return -1;
+        }
+    }
+    t.marked.pop();
+    graphics_mode();
+    pdf_doc_add_page_content(b" EMC");
+    0
+}
+
+/// Merges entries into the `/RoleMap` of the structure tree root.
+pub(crate) unsafe fn pdf_struct_add_role_map(dict: *mut pdf_obj) {
+    let t = &mut struct_tree;
+    if t.role_map.is_null() {
+        t.role_map = dict;
+    } else {
+        (*t.role_map).as_dict_mut().merge((*dict).as_dict());
+        pdf_release_obj(dict);
+    }
+}
+
+/// Suspends open marked content at the end of a page.
+pub(crate) unsafe fn pdf_struct_end_page() {
+    if !struct_tree.marked.is_empty() {
+        graphics_mode();
+    }
+    for _ in 0..struct_tree.marked.len() {
+        pdf_doc_add_page_content(b" EMC");
+    }
+}
+
+/// Resumes suspended marked content at the start of a page.
+pub(crate) unsafe fn pdf_struct_begin_page() {
+    let t = &mut struct_tree;
+    for mc in t.marked.iter() {
+        emit_begin(mc);
+    }
+}
+
+/// Writes out the structure tree, if there is one, and hooks it up to the
+/// catalog.
+pub(crate) unsafe fn pdf_struct_close(catalog: *mut pdf_obj) {
+    let t = &mut struct_tree;
+    if t.root.is_null() {
+        if !t.role_map.is_null() {
+            pdf_release_obj(t.role_map);
+        }
+        pdf_struct_init();
+        return;
+    }
+    if !t.marked.is_empty() {
+        warn!("{} marked-content sequence(s) not ended.", t.marked.len());
+        t.marked.clear();
+    }
+    if !t.open.is_empty() {
+        warn!("{} structure element(s) not ended.", t.open.len());
+        while let Some(elem) = t.open.pop() {
+            finish_elem(elem);
+        }
+    }
+    let root = t.root;
+    (*root).as_dict_mut().set("Type", "StructTreeRoot");
+    let kids: Vec<*mut pdf_obj> = t.root_kids.drain(..).collect();
+    (*root).as_dict_mut().set("K", kids);
+    let mut nums = vec![];
+    for (key, (_, owners)) in t.parent_tree.drain(..).enumerate() {
+        nums.push_obj(key as f64);
+        nums.push_obj(owners);
+    }
+    let next_key = nums.len() / 2;
+    let mut parent_tree = pdf_dict::new();
+    parent_tree.set("Nums", nums);
+    let parent_tree = parent_tree.into_obj();
+    (*root)
+        .as_dict_mut()
+        .set("ParentTree", pdf_ref_obj(parent_tree));
+    pdf_release_obj(parent_tree);
+    (*root)
+        .as_dict_mut()
+        .set("ParentTreeNextKey", next_key as f64);
+    if !t.role_map.is_null() {
+        (*root).as_dict_mut().set("RoleMap", t.role_map);
+    }
+    (*catalog)
+        .as_dict_mut()
+        .set("StructTreeRoot", pdf_ref_obj(root));
+    let mut mark_info = pdf_dict::new();
+    mark_info.set("Marked", true);
+    (*catalog).as_dict_mut().set("MarkInfo", mark_info);
+    pdf_release_obj(root);
+    pdf_struct_init();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mcid_is_merged_into_props() {
+        assert_eq!(props_with_mcid(b"", 3), b"<</MCID 3>>".to_vec());
+        assert_eq!(
+            props_with_mcid(b"<</Lang (en)>>", 0),
+            b"<</MCID 0 /Lang (en)>>".to_vec()
+        );
+    }
+
+    #[test]
+    fn names_are_escaped() {
+        let mut buf = vec![];
+        push_name(&mut buf, b"My Tag#1");
+        assert_eq!(buf, b"/My#20Tag#231".to_vec());
+    }
+}
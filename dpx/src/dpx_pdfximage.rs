@@ -41,7 +41,7 @@ use super::dpx_jpegimage::{check_for_jpeg, jpeg_include_image};
 use super::dpx_mem::{new, renew};
 use super::dpx_mfileio::{tt_mfgets, work_buffer};
 use super::dpx_pdfdraw::pdf_dev_transform;
-use super::dpx_pngimage::{check_for_png, png_include_image};
+use super::dpx_pngimage::{check_for_png, png_include_image, png_include_image_data};
 use crate::bridge::{ttstub_input_close, ttstub_input_open};
 use crate::dpx_epdf::pdf_include_page;
 use crate::dpx_pdfobj::{check_for_pdf, pdf_link_obj, pdf_obj, pdf_ref_obj, pdf_release_obj};
@@ -434,6 +434,43 @@ pub(crate) unsafe fn pdf_ximage_findresource(ident: *const i8, options: load_opt
     }
     id
 }
+/* Like pdf_ximage_findresource() for PNG data that does not come from a
+ * file, such as the bitmaps of color glyphs. The ident only serves for
+ * finding the image again; it must be unique to the data.
+ */
+pub(crate) unsafe fn pdf_ximage_load_png_data(ident: &CStr, data: &[u8]) -> i32 {
+    let mut ic: *mut ic_ = &mut _ic;
+    for id in 0..(*ic).count {
+        let I = &mut *(*ic).ximages.offset(id as isize) as *mut pdf_ximage;
+        if !(*I).ident.is_null() && streq_ptr(ident.as_ptr(), (*I).ident) {
+            return id;
+        }
+    }
+    let id = (*ic).count;
+    if (*ic).count >= (*ic).capacity {
+        (*ic).capacity += 16i32;
+        (*ic).ximages = renew(
+            (*ic).ximages as *mut libc::c_void,
+            ((*ic).capacity as u32 as u64).wrapping_mul(::std::mem::size_of::<pdf_ximage>() as u64)
+                as u32,
+        ) as *mut pdf_ximage
+    }
+    let I = &mut *(*ic).ximages.offset(id as isize) as *mut pdf_ximage;
+    pdf_init_ximage_struct(I);
+    (*I).ident = new((ident.to_bytes().len() + 1) as _) as *mut i8;
+    strcpy((*I).ident, ident.as_ptr());
+    if png_include_image_data(I, data) < 0 {
+        pdf_clean_ximage_struct(I);
+        return -1;
+    }
+    sprintf(
+        (*I).res_name.as_mut_ptr(),
+        b"Im%d\x00" as *const u8 as *const i8,
+        id,
+    );
+    (*ic).count += 1;
+    id
+}
 /* Reference: PDF Reference 1.5 v6, pp.321--322
  *
  * TABLE 4.42 Additional entries specific to a type 1 form dictionary
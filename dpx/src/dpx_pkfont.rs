@@ -44,8 +44,8 @@ use super::dpx_pdffont::{
 };
 use super::dpx_tfm::{tfm_get_design_size, tfm_open};
 use crate::dpx_pdfobj::{
-    pdf_copy_name, pdf_dict, pdf_ref_obj, pdf_release_obj, pdf_stream, IntoObj, PushObj,
-    STREAM_COMPRESS,
+    pdf_copy_name, pdf_dict, pdf_get_version, pdf_ref_obj, pdf_release_obj, pdf_stream, IntoObj,
+    PushObj, PDF_VERSION_2_0, STREAM_COMPRESS,
 };
 use crate::shims::sprintf;
 use libc::{free, memset};
@@ -727,13 +727,15 @@ pub(crate) unsafe fn pdf_font_load_pkfont(font: &mut pdf_font) -> i32 {
      *  PDF Reference 4th ed. describes it as "Optional but strongly recommended".
      *  There are no reason to put it in our case, but we will put this.
      *  We do not care about compatibility with Acrobat 2.x. (See implementation
-     *  note 47, Appendix H of PDF Ref., 4th ed.).
+     *  note 47, Appendix H of PDF Ref., 4th ed.). PDF 2.0 has no ProcSet.
      */
     let mut procset = pdf_dict::new();
-    let mut tmp_array = vec![];
-    tmp_array.push_obj("PDF");
-    tmp_array.push_obj("ImageB");
-    procset.set("ProcSet", tmp_array);
+    if pdf_get_version() < PDF_VERSION_2_0 {
+        let mut tmp_array = vec![];
+        tmp_array.push_obj("PDF");
+        tmp_array.push_obj("ImageB");
+        procset.set("ProcSet", tmp_array);
+    }
     fontdict.as_dict_mut().set("Resources", procset);
     /* Encoding */
     let mut tmp_array = vec![];
@@ -33,6 +33,7 @@ use std::ptr;
 use crate::warn;
 
 use super::dpx_mem::new;
+use super::dpx_pdfa::pdfa_transparency_allowed;
 use super::dpx_pdfcolor::{iccp_check_colorspace, iccp_load_profile, pdf_get_colorspace_reference};
 use super::dpx_pdfximage::pdf_ximage_set_image;
 use crate::bridge::ttstub_input_read;
@@ -90,17 +91,48 @@ unsafe extern "C" fn _png_read(png_ptr: *mut png_struct, outbytes: *mut u8, n: u
         panic!("error reading PNG");
     };
 }
+/* PNG data already in memory, e.g. a color glyph bitmap from sbix or CBDT */
+struct png_mem_source<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+unsafe extern "C" fn _png_read_mem(png_ptr: *mut png_struct, outbytes: *mut u8, n: usize) {
+    let png = png_ptr.as_ref().unwrap();
+    let source = &mut *(png_get_io_ptr(png) as *mut png_mem_source);
+    if source.data.len() - source.pos < n {
+        panic!("error reading PNG");
+    }
+    ptr::copy_nonoverlapping(source.data[source.pos..].as_ptr(), outbytes, n);
+    source.pos += n;
+}
 
 pub(crate) unsafe fn png_include_image(
     ximage: *mut pdf_ximage,
     handle: &mut InputHandleWrapper,
+) -> i32 {
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    png_include(ximage, handle.as_ptr(), _png_read)
+}
+
+pub(crate) unsafe fn png_include_image_data(ximage: *mut pdf_ximage, data: &[u8]) -> i32 {
+    let mut source = png_mem_source { data, pos: 0 };
+    png_include(
+        ximage,
+        &mut source as *mut png_mem_source as *mut libc::c_void,
+        _png_read_mem,
+    )
+}
+
+unsafe fn png_include(
+    ximage: *mut pdf_ximage,
+    io_ptr: *mut libc::c_void,
+    read_fn: unsafe extern "C" fn(*mut png_struct, *mut u8, usize),
 ) -> i32 {
     /* Libpng stuff */
     let mut info = ximage_info::init();
     let mut intent = ptr::null_mut();
     let mut mask = intent;
     let mut colorspace = mask;
-    handle.seek(SeekFrom::Start(0)).unwrap();
 
     let png = if let Some(png) = png_create_read_struct(
         b"1.6.37\x00" as *const u8 as *const i8,
@@ -131,7 +163,7 @@ pub(crate) unsafe fn png_include_image(
     /* ignore possibly incorrect CMF bytes */
     png_set_option(png, 2i32, 3i32);
     /* Rust-backed IO */
-    png_set_read_fn(png, handle.as_ptr(), Some(_png_read));
+    png_set_read_fn(png, io_ptr, Some(read_fn));
     /* NOTE: could use png_set_sig_bytes() to tell libpng if we started at non-zero file offset */
     /* Read PNG info-header and get some info. */
     png_read_info(png, png_info);
@@ -286,6 +318,8 @@ pub(crate) unsafe fn png_include_image(
     if !mask.is_null() {
         if trans_type == 1i32 {
             stream_dict.set("Mask", mask);
+        } else if trans_type == 2i32 && !pdfa_transparency_allowed("PNG alpha channel") {
+            pdf_release_obj(mask);
         } else if trans_type == 2i32 {
             if info.bits_per_component >= 8i32 && info.width > 64i32 {
                 pdf_stream_set_predictor(mask, 2i32, info.width, info.bits_per_component, 1i32);
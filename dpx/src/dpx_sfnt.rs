@@ -33,6 +33,8 @@ use super::dpx_numbers::{tt_get_unsigned_pair, tt_get_unsigned_quad};
 use crate::bridge::ttstub_input_read;
 use crate::dpx_pdfobj::{pdf_stream, STREAM_COMPRESS};
 use crate::dpx_truetype::SfntTableInfo;
+use crate::dpx_tt_var::tt_var_instance;
+use crate::dpx_woff::woff_open;
 use crate::mfree;
 use libc::{free, memcpy};
 
@@ -69,9 +71,13 @@ pub(crate) struct sfnt {
     pub(crate) directory: *mut sfnt_table_directory,
     pub(crate) handle: InputHandleWrapper,
     pub(crate) offset: u32,
+    /* variable font instance to apply to glyphs, or NULL */
+    pub(crate) instance: *mut tt_var_instance,
 }
 
-pub(crate) unsafe fn sfnt_open(mut handle: InputHandleWrapper) -> *mut sfnt {
+pub(crate) unsafe fn sfnt_open(handle: InputHandleWrapper) -> *mut sfnt {
+    /* WOFF and WOFF2 fonts are read from an unpacked copy */
+    let mut handle = woff_open(handle);
     handle.seek(SeekFrom::Start(0)).unwrap(); /* mbz */
     let sfont =
         new((1_u32 as u64).wrapping_mul(::std::mem::size_of::<sfnt>() as u64) as u32) as *mut sfnt; /* typefaces position */
@@ -89,6 +95,7 @@ pub(crate) unsafe fn sfnt_open(mut handle: InputHandleWrapper) -> *mut sfnt {
     (*sfont).handle = handle;
     (*sfont).directory = ptr::null_mut();
     (*sfont).offset = 0u64 as u32;
+    (*sfont).instance = ptr::null_mut();
     sfont
 }
 
@@ -139,6 +146,7 @@ pub(crate) unsafe fn dfont_open(mut handle: InputHandleWrapper, index: i32) -> *
     (*sfont).handle = handle;
     (*sfont).type_0 = 1i32 << 8i32;
     (*sfont).directory = ptr::null_mut();
+    (*sfont).instance = ptr::null_mut();
     (*sfont).offset = (res_pos as u64 & 0xffffff)
         .wrapping_add(rdata_pos as u64)
         .wrapping_add(4i32 as u64) as u32;
@@ -163,6 +171,9 @@ pub(crate) unsafe fn sfnt_close(sfont: *mut sfnt) {
         if !(*sfont).directory.is_null() {
             release_directory((*sfont).directory);
         }
+        if !(*sfont).instance.is_null() {
+            let _ = Box::from_raw((*sfont).instance);
+        }
         free(sfont as *mut libc::c_void);
     };
 }
@@ -244,6 +255,12 @@ pub(crate) unsafe fn sfnt_set_table(
                 .wrapping_mul(::std::mem::size_of::<sfnt_table>() as u64) as u32,
         ) as *mut sfnt_table;
         (*(*td).tables.offset(idx as isize)).tag = tag.clone();
+        /* a table the font did not have, e.g. glyf for bitmap-only fonts */
+        (*td).flags = renew(
+            (*td).flags as *mut libc::c_void,
+            (*td).num_tables as u32 * ::std::mem::size_of::<i8>() as u32,
+        ) as *mut i8;
+        *(*td).flags.offset(idx as isize) = 0_i8;
     }
     (*(*td).tables.offset(idx as isize)).check_sum = sfnt_calc_checksum(data, length);
     (*(*td).tables.offset(idx as isize)).offset = 0i64 as u32;
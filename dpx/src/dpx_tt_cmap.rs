@@ -32,10 +32,11 @@ use super::dpx_sfnt::{
 };
 use crate::bridge::DisplayExt;
 use crate::{info, warn};
+use std::collections::HashMap;
 use std::ffi::CStr;
 use std::ptr;
 
-use super::dpx_agl::agl_get_unicodes;
+use super::dpx_agl::{agl_close_map, agl_get_unicodes, agl_init_map};
 use super::dpx_cff::{
     cff_charsets_lookup_inverse, cff_close, cff_get_glyphname, cff_get_string, cff_open,
     cff_read_charsets,
@@ -49,6 +50,7 @@ use super::dpx_cmap::{
 };
 use super::dpx_cmap_write::CMap_create_stream;
 use super::dpx_dpxfile::{dpx_open_dfont_file, dpx_open_opentype_file, dpx_open_truetype_file};
+use super::dpx_error::QuietScope;
 use super::dpx_mem::new;
 use super::dpx_numbers::{
     tt_get_signed_pair, tt_get_unsigned_byte, tt_get_unsigned_pair, tt_get_unsigned_quad,
@@ -60,8 +62,8 @@ use super::dpx_tt_gsub::{
     otl_gsub_new, otl_gsub_release, otl_gsub_select, otl_gsub_set_chain, otl_gsub_set_verbose,
 };
 use super::dpx_tt_post::{tt_get_glyphname, tt_read_post_table, tt_release_post_table};
-use super::dpx_tt_table::tt_read_maxp_table;
-use super::dpx_unicode::UC_UTF16BE_encode_char;
+use super::dpx_tt_table::{tt_read_head_table, tt_read_hhea_table, tt_read_maxp_table};
+use super::dpx_unicode::{UC_UTF16BE_encode_char, UC_UTF16BE_to_string};
 use crate::dpx_pdfobj::{pdf_obj, pdf_stream, IntoObj};
 use crate::dpx_truetype::sfnt_table_info;
 use crate::mfree;
@@ -1094,13 +1096,29 @@ unsafe fn create_ToUnicode_cmap(
     sfont: *mut sfnt,
     code_to_cid_cmap: *mut CMap,
 ) -> Option<pdf_stream> {
-    let mut count: u16 = 0_u16;
     let cffont = prepare_CIDFont_from_sfnt(&mut *sfont);
-    let is_cidfont = if let Some(cffont) = &cffont {
-        cffont.flag & 1i32 << 0i32 != 0
+    let mut cmap = new_ToUnicode_cmap(cmap_name);
+    let count = add_ToUnicode_mappings(
+        &mut cmap,
+        ttcmap,
+        cmap_add,
+        used_chars,
+        sfont,
+        cffont.as_deref(),
+        code_to_cid_cmap,
+    );
+    let stream = if (count as i32) < 1i32 {
+        None
     } else {
-        false
+        CMap_create_stream(&mut cmap)
     };
+    CMap_release(&mut cmap);
+    if let Some(cffont) = cffont {
+        cff_close(cffont);
+    }
+    stream
+}
+unsafe fn new_ToUnicode_cmap(cmap_name: &str) -> CMap {
     let mut cmap = CMap_new();
     CMap_set_name(&mut cmap, cmap_name);
     CMap_set_wmode(&mut cmap, 0i32);
@@ -1112,6 +1130,26 @@ unsafe fn create_ToUnicode_cmap(
         srange_max.as_mut_ptr(),
         2i32 as size_t,
     );
+    cmap
+}
+/* Fills in the ToUnicode mappings of the glyphs in used_chars. Returns the
+ * number of mappings added.
+ */
+unsafe fn add_ToUnicode_mappings(
+    cmap: *mut CMap,
+    ttcmap: *mut tt_cmap,
+    cmap_add: *mut CMap,
+    used_chars: *const i8,
+    sfont: *mut sfnt,
+    cffont: Option<&cff_font>,
+    code_to_cid_cmap: *mut CMap,
+) -> u16 {
+    let mut count: u16 = 0_u16;
+    let is_cidfont = if let Some(cffont) = cffont {
+        cffont.flag & 1i32 << 0i32 != 0
+    } else {
+        false
+    };
     /* cmap_add here stores information about all unencoded glyphs which can be
      * accessed only through OT Layout GSUB table.
      */
@@ -1133,7 +1171,7 @@ unsafe fn create_ToUnicode_cmap(
                                 UC_UTF16BE_encode_char(ch, &mut p, wbuf.as_mut_ptr().offset(1024))
                                     as i32;
                             CMap_add_bfchar(
-                                &mut cmap,
+                                cmap,
                                 wbuf.as_mut_ptr(),
                                 2i32 as size_t,
                                 wbuf.as_mut_ptr().offset(2),
@@ -1157,34 +1195,18 @@ unsafe fn create_ToUnicode_cmap(
         match (*ttcmap).format as i32 {
             4 => {
                 count = create_ToUnicode_cmap4(
-                    &mut cmap,
+                    cmap,
                     (*ttcmap).map as *mut cmap4,
                     used_chars_copy.as_mut_ptr(),
-                    if is_cidfont {
-                        if let Some(cffont) = &cffont {
-                            Some(cffont)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    },
+                    if is_cidfont { cffont } else { None },
                 )
             }
             12 => {
                 count = create_ToUnicode_cmap12(
-                    &mut cmap,
+                    cmap,
                     (*ttcmap).map as *mut cmap12,
                     used_chars_copy.as_mut_ptr(),
-                    if is_cidfont {
-                        if let Some(cffont) = &cffont {
-                            Some(cffont)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    },
+                    if is_cidfont { cffont } else { None },
                 )
             }
             _ => {}
@@ -1193,31 +1215,14 @@ unsafe fn create_ToUnicode_cmap(
          * it is only needed for non-CID fonts. */
         count = (count as i32
             + handle_subst_glyphs(
-                &mut cmap,
+                cmap,
                 cmap_add,
                 used_chars_copy.as_mut_ptr(),
                 sfont,
-                if is_cidfont {
-                    None
-                } else {
-                    if let Some(cffont) = &cffont {
-                        Some(cffont)
-                    } else {
-                        None
-                    }
-                },
+                if is_cidfont { None } else { cffont },
             ) as i32) as u16
     }
-    let stream = if (count as i32) < 1i32 {
-        None
-    } else {
-        CMap_create_stream(&mut cmap)
-    };
-    CMap_release(&mut cmap);
-    if let Some(cffont) = cffont {
-        cff_close(cffont);
-    }
-    stream
+    count
 }
 static mut cmap_plat_encs: [cmap_plat_enc_rec; 5] = [
     cmap_plat_enc_rec {
@@ -1249,8 +1254,6 @@ pub(crate) unsafe fn otf_create_ToUnicode_stream(
     cmap_id: i32,
 ) -> *mut pdf_obj {
     let mut cmap_obj = None;
-    let mut ttcmap: *mut tt_cmap = ptr::null_mut();
-    let offset;
     /* replace slash in map name with dash to make the output cmap name valid,
      * happens when XeTeX embeds full font path
      * https://sourceforge.net/p/xetex/bugs/52/
@@ -1269,6 +1272,52 @@ pub(crate) unsafe fn otf_create_ToUnicode_stream(
             font_name
         );
     }
+    let sfont = otf_open_sfnt(font_name, ttc_index);
+    if sfont.is_null() {
+        return ptr::null_mut();
+    }
+    let mut code_to_cid_cmap = CMap_cache_get(cmap_id);
+    let cmap_type = CMap_get_type(code_to_cid_cmap);
+    if cmap_type != 1i32 {
+        code_to_cid_cmap = ptr::null_mut()
+    }
+    let cmap_add_id = CMap_cache_find(&format!("{},{:03}-UCS32-Add", font_name, ttc_index,));
+    let cmap_add = if cmap_add_id < 0i32 {
+        ptr::null_mut()
+    } else {
+        CMap_cache_get(cmap_add_id)
+    };
+    CMap_set_silent(1i32); /* many warnings without this... */
+    let ttcmap = read_unicode_cmap(sfont);
+    if !ttcmap.is_null() {
+        cmap_obj = create_ToUnicode_cmap(
+            ttcmap,
+            &cmap_name,
+            cmap_add,
+            used_chars,
+            sfont,
+            code_to_cid_cmap,
+        );
+    }
+    if cmap_obj.is_none() {
+        warn!("Unable to read OpenType/TrueType Unicode cmap table.");
+    }
+    tt_cmap_release(ttcmap);
+    CMap_set_silent(0i32);
+    let cmap_ref = if let Some(cmap_obj) = cmap_obj {
+        let res_id = pdf_defineresource("CMap", &cmap_name, cmap_obj.into_obj(), 1i32);
+        pdf_get_resource_reference(res_id)
+    } else {
+        ptr::null_mut()
+    };
+    sfnt_close(sfont);
+    cmap_ref
+}
+/* Opens the font and reads its table directory. Returns NULL if the font
+ * file can't be found.
+ */
+unsafe fn otf_open_sfnt(font_name: &str, ttc_index: i32) -> *mut sfnt {
+    let offset;
     let sfont = if let Some(handle) =
         dpx_open_truetype_file(font_name).or_else(|| dpx_open_opentype_file(font_name))
     {
@@ -1297,53 +1346,123 @@ pub(crate) unsafe fn otf_create_ToUnicode_stream(
     if sfnt_read_table_directory(sfont, offset) < 0i32 {
         panic!("Could not read OpenType/TrueType table directory.");
     }
-    let mut code_to_cid_cmap = CMap_cache_get(cmap_id);
-    let cmap_type = CMap_get_type(code_to_cid_cmap);
-    if cmap_type != 1i32 {
-        code_to_cid_cmap = ptr::null_mut()
+    sfont
+}
+/* Reads the first Unicode cmap subtable in a format that
+ * create_ToUnicode_cmap() understands, or returns NULL.
+ */
+unsafe fn read_unicode_cmap(sfont: *mut sfnt) -> *mut tt_cmap {
+    for enc in cmap_plat_encs.iter() {
+        let ttcmap = tt_cmap_read(sfont, enc.platform as u16, enc.encoding as u16);
+        if !ttcmap.is_null() {
+            if (*ttcmap).format as i32 == 4i32 || (*ttcmap).format as i32 == 12i32 {
+                return ttcmap;
+            }
+            tt_cmap_release(ttcmap);
+        }
     }
-    let cmap_add_id = CMap_cache_find(&format!("{},{:03}-UCS32-Add", font_name, ttc_index,));
-    let cmap_add = if cmap_add_id < 0i32 {
-        ptr::null_mut()
+    ptr::null_mut()
+}
+
+/// The Unicode text of the glyphs of an OpenType/TrueType font, as its
+/// ToUnicode CMap would give them, along with the font's vertical extent.
+pub struct OtfGlyphText {
+    /// Ascender and descender as fractions of an em.
+    pub ascent: f64,
+    pub descent: f64,
+    /// The text of each glyph that has a mapping, keyed by glyph ID.
+    pub unicodes: HashMap<u16, String>,
+}
+
+/// Work out the text of every glyph of a font the same way that
+/// otf_create_ToUnicode_stream() does, for extracting text without
+/// producing a PDF.
+pub unsafe fn otf_get_glyph_text(font_name: &str, ttc_index: i32) -> Option<OtfGlyphText> {
+    let _quiet = QuietScope::new(2i32);
+    let sfont = otf_open_sfnt(font_name, ttc_index);
+    if sfont.is_null() {
+        return None;
+    }
+    let ttcmap = read_unicode_cmap(sfont);
+    if ttcmap.is_null() {
+        sfnt_close(sfont);
+        return None;
+    }
+    let head = tt_read_head_table(sfont);
+    let hhea = tt_read_hhea_table(sfont);
+    let maxp = tt_read_maxp_table(sfont);
+    let upem = f64::from((*head).unitsPerEm.max(1));
+    let ascent = f64::from((*hhea).ascent) / upem;
+    let descent = -f64::from((*hhea).descent) / upem;
+    let num_glyphs = (*maxp).numGlyphs;
+    free(head as *mut libc::c_void);
+    free(hhea as *mut libc::c_void);
+    free(maxp as *mut libc::c_void);
+    let cffont = prepare_CIDFont_from_sfnt(&mut *sfont);
+    let is_cidfont = if let Some(cffont) = &cffont {
+        cffont.flag & 1i32 << 0i32 != 0
     } else {
-        CMap_cache_get(cmap_add_id)
+        false
     };
-    CMap_set_silent(1i32); /* many warnings without this... */
-    for i in 0..(::std::mem::size_of::<[cmap_plat_enc_rec; 5]>() as u64)
-        .wrapping_div(::std::mem::size_of::<cmap_plat_enc_rec>() as u64) as usize
-    {
-        ttcmap = tt_cmap_read(
-            sfont,
-            cmap_plat_encs[i].platform as u16,
-            cmap_plat_encs[i].encoding as u16,
+    /* Treat every glyph (or, for CID fonts, every CID) as used. */
+    let mut used_chars: [i8; 8192] = [0; 8192];
+    for code in 0..=0xffff_u32 {
+        if is_cidfont || code < num_glyphs as u32 {
+            used_chars[(code / 8) as usize] |= (1i32 << 7i32 - (code % 8) as i32) as i8;
+        }
+    }
+    agl_init_map();
+    CMap_set_silent(1i32);
+    let mut cmap = new_ToUnicode_cmap("glyph-text");
+    add_ToUnicode_mappings(
+        &mut cmap,
+        ttcmap,
+        ptr::null_mut(),
+        used_chars.as_ptr(),
+        sfont,
+        cffont.as_deref(),
+        ptr::null_mut(),
+    );
+    let mut unicodes = HashMap::new();
+    for gid in 0..num_glyphs {
+        /* For CID fonts the CMap is keyed by CID, as in add_to_cmap_if_used(). */
+        let code = match &cffont {
+            Some(cffont) if is_cidfont => cff_charsets_lookup_inverse(cffont, gid),
+            _ => gid,
+        };
+        let code = code.to_be_bytes();
+        let mut inbuf = code.as_ptr();
+        let mut inbytesleft = 2i32 as size_t;
+        let mut outbuf = wbuf.as_mut_ptr();
+        let mut outbytesleft = 1024i32 as size_t;
+        CMap_decode(
+            &mut cmap,
+            &mut inbuf,
+            &mut inbytesleft,
+            &mut outbuf,
+            &mut outbytesleft,
         );
-        if !ttcmap.is_null() {
-            if (*ttcmap).format as i32 == 4i32 || (*ttcmap).format as i32 == 12i32 {
-                cmap_obj = create_ToUnicode_cmap(
-                    ttcmap,
-                    &cmap_name,
-                    cmap_add,
-                    used_chars,
-                    sfont,
-                    code_to_cid_cmap,
-                );
-                break;
+        let len = 1024 - outbytesleft as usize;
+        /* Unmapped codes decode to U+FFFD. */
+        if inbytesleft == 0 && &wbuf[..len] != b"\xff\xfd" {
+            if let Some(text) = UC_UTF16BE_to_string(&wbuf[..len]) {
+                unicodes.insert(gid, text);
             }
         }
     }
-    if cmap_obj.is_none() {
-        warn!("Unable to read OpenType/TrueType Unicode cmap table.");
+    CMap_release(&mut cmap);
+    CMap_set_silent(0i32);
+    agl_close_map();
+    if let Some(cffont) = cffont {
+        cff_close(cffont);
     }
     tt_cmap_release(ttcmap);
-    CMap_set_silent(0i32);
-    let cmap_ref = if let Some(cmap_obj) = cmap_obj {
-        let res_id = pdf_defineresource("CMap", &cmap_name, cmap_obj.into_obj(), 1i32);
-        pdf_get_resource_reference(res_id)
-    } else {
-        ptr::null_mut()
-    };
     sfnt_close(sfont);
-    cmap_ref
+    Some(OtfGlyphText {
+        ascent,
+        descent,
+        unicodes,
+    })
 }
 unsafe fn load_base_CMap(
     cmap_name: &str,
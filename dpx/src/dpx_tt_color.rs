@@ -0,0 +1,1187 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2020 the Tectonic Project
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_camel_case_types, non_upper_case_globals)]
+
+//! Color glyphs: `COLR`/`CPAL` layers and the PNG bitmaps of `sbix` and
+//! `CBDT`/`CBLC`.
+//!
+//! The CIDFont we embed for a native font only has room for outlines, so a
+//! color glyph is drawn by `tt_color_glyph_draw()` instead of being shown
+//! like any other. The glyph itself still goes into the content stream, as
+//! invisible text, which keeps the font's ToUnicode mapping and thus copy
+//! and paste working. On top of it go either its color layers, each shown
+//! as a glyph of the same font with its own fill (gradients become shadings
+//! clipped to the layer glyph), or its bitmap, as an image XObject.
+//!
+//! COLR version 1 paint graphs are flattened into such layers. Sweep
+//! gradients are approximated by a solid fill, composite modes other than
+//! source-over are ignored, gradient stops share a single opacity and any
+//! variation deltas are left out.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+
+use crate::bridge::DisplayExt;
+
+use super::dpx_pdfa::pdfa_transparency_allowed;
+use super::dpx_pdfcolor::PdfColor;
+use super::dpx_pdfdev::{
+    dev_unit_dviunit, graphics_mode, pdf_dev_get_coord, pdf_dev_set_string,
+    pdf_dev_set_string_rendermode, spt_t, TMatrix,
+};
+use super::dpx_pdfdoc::{pdf_doc_add_page_content, pdf_doc_add_page_resource};
+use super::dpx_pdfdraw::{pdf_dev_concat, pdf_dev_grestore, pdf_dev_gsave, pdf_dev_set_color};
+use super::dpx_pdfximage::{
+    pdf_ximage_get_reference, pdf_ximage_get_resname, pdf_ximage_load_png_data,
+};
+use super::dpx_sfnt::sfnt;
+use super::dpx_tt_var::read_table;
+use crate::dpx_pdfobj::{
+    pdf_dict, pdf_link_obj, pdf_obj, pdf_ref_obj, pdf_release_obj, IntoObj, PushObj,
+};
+
+fn get_u16(data: &[u8], pos: usize) -> Option<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| (b[0] as u16) << 8 | b[1] as u16)
+}
+
+fn get_i16(data: &[u8], pos: usize) -> Option<i16> {
+    get_u16(data, pos).map(|v| v as i16)
+}
+
+fn get_u24(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 3)
+        .map(|b| (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32)
+}
+
+fn get_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+}
+
+fn get_f2dot14(data: &[u8], pos: usize) -> Option<f64> {
+    get_i16(data, pos).map(|v| v as f64 / 16384.0)
+}
+
+fn get_fixed(data: &[u8], pos: usize) -> Option<f64> {
+    get_u32(data, pos).map(|v| v as i32 as f64 / 65536.0)
+}
+
+/// Find the record for `gid` in an array of `count` records of `size` bytes
+/// each, sorted by the glyph ID they start with.
+fn find_record(data: &[u8], start: usize, count: usize, size: usize, gid: u16) -> Option<usize> {
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        let pos = start + mid * size;
+        let g = get_u16(data, pos)?;
+        if g == gid {
+            return Some(pos);
+        } else if g < gid {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    None
+}
+
+/// An affine transformation `[a b c d e f]`, as for the PDF `cm` operator.
+pub(crate) type tt_matrix = [f64; 6];
+
+const IDENTITY: tt_matrix = [1., 0., 0., 1., 0., 0.];
+
+/// The transformation applying `inner` first and `outer` second.
+fn concat(outer: &tt_matrix, inner: &tt_matrix) -> tt_matrix {
+    [
+        outer[0] * inner[0] + outer[2] * inner[1],
+        outer[1] * inner[0] + outer[3] * inner[1],
+        outer[0] * inner[2] + outer[2] * inner[3],
+        outer[1] * inner[2] + outer[3] * inner[3],
+        outer[0] * inner[4] + outer[2] * inner[5] + outer[4],
+        outer[1] * inner[4] + outer[3] * inner[5] + outer[5],
+    ]
+}
+
+fn invert(m: &tt_matrix) -> Option<tt_matrix> {
+    let det = m[0] * m[3] - m[1] * m[2];
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    Some([
+        m[3] / det,
+        -m[1] / det,
+        -m[2] / det,
+        m[0] / det,
+        (m[2] * m[5] - m[3] * m[4]) / det,
+        (m[1] * m[4] - m[0] * m[5]) / det,
+    ])
+}
+
+fn translate(dx: f64, dy: f64) -> tt_matrix {
+    [1., 0., 0., 1., dx, dy]
+}
+
+fn around(cx: f64, cy: f64, m: tt_matrix) -> tt_matrix {
+    concat(&translate(cx, cy), &concat(&m, &translate(-cx, -cy)))
+}
+
+/// Angles in COLR are multiples of 180 degrees.
+fn rotate(angle: f64) -> tt_matrix {
+    let (sin, cos) = (angle * std::f64::consts::PI).sin_cos();
+    [cos, sin, -sin, cos, 0., 0.]
+}
+
+fn skew(x_angle: f64, y_angle: f64) -> tt_matrix {
+    [
+        1.,
+        (y_angle * std::f64::consts::PI).tan(),
+        -(x_angle * std::f64::consts::PI).tan(),
+        1.,
+        0.,
+        0.,
+    ]
+}
+
+/// A non-premultiplied RGBA color with components between 0 and 1.
+pub(crate) type tt_rgba = [f64; 4];
+
+/// A gradient's color stops, sorted and spread out over [0, 1].
+pub(crate) type tt_color_stops = Vec<(f64, tt_rgba)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum tt_color_fill {
+    Solid(tt_rgba),
+    /// The text color, with the given opacity.
+    Foreground(f64),
+    Linear {
+        p0: (f64, f64),
+        p1: (f64, f64),
+        stops: tt_color_stops,
+    },
+    Radial {
+        c0: (f64, f64),
+        r0: f64,
+        c1: (f64, f64),
+        r1: f64,
+        stops: tt_color_stops,
+    },
+}
+
+/// One glyph of a color glyph's stack of layers, bottom first.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct tt_color_layer {
+    pub(crate) gid: u16,
+    /// Maps the layer glyph's design space into the color glyph's.
+    pub(crate) matrix: tt_matrix,
+    pub(crate) fill: tt_color_fill,
+    /// Maps the geometry of a gradient fill into the layer glyph's design
+    /// space.
+    pub(crate) fill_matrix: tt_matrix,
+}
+
+/// A color glyph's PNG image and where it goes, in design units.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct tt_color_bitmap<'a> {
+    pub(crate) png: &'a [u8],
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+}
+
+/* Paint graphs are acyclic, but nothing stops a broken font from nesting
+ * them forever. */
+const MAX_PAINT_DEPTH: u32 = 64;
+
+/// Restrict the stops to those of a gradient running from 0 to 1, returning
+/// where the original ones started and ended, or `None` if they don't span
+/// a range at all.
+fn normalize_stops(stops: &mut tt_color_stops) -> Option<(f64, f64)> {
+    stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    let t0 = stops.first()?.0;
+    let t1 = stops.last()?.0;
+    if t1 - t0 < 1e-6 {
+        return None;
+    }
+    for stop in stops.iter_mut() {
+        stop.0 = (stop.0 - t0) / (t1 - t0);
+    }
+    Some((t0, t1))
+}
+
+fn lerp(a: (f64, f64), b: (f64, f64), t: f64) -> (f64, f64) {
+    (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1))
+}
+
+fn linear_gradient(
+    p0: (f64, f64),
+    p1: (f64, f64),
+    p2: (f64, f64),
+    mut stops: tt_color_stops,
+) -> Option<tt_color_fill> {
+    /* The color varies along p0p1, but is constant along lines parallel to
+     * p0p2; PDF wants the two perpendicular. */
+    let (nx, ny) = (p2.1 - p0.1, p0.0 - p2.0);
+    let len = nx * nx + ny * ny;
+    let p1 = if len > 0. {
+        let t = ((p1.0 - p0.0) * nx + (p1.1 - p0.1) * ny) / len;
+        (p0.0 + t * nx, p0.1 + t * ny)
+    } else {
+        p1
+    };
+    let last = stops.last()?.1;
+    match normalize_stops(&mut stops) {
+        Some((t0, t1)) if p0 != p1 => Some(tt_color_fill::Linear {
+            p0: lerp(p0, p1, t0),
+            p1: lerp(p0, p1, t1),
+            stops,
+        }),
+        _ => Some(tt_color_fill::Solid(last)),
+    }
+}
+
+fn radial_gradient(
+    c0: (f64, f64),
+    r0: f64,
+    c1: (f64, f64),
+    r1: f64,
+    mut stops: tt_color_stops,
+) -> Option<tt_color_fill> {
+    let last = stops.last()?.1;
+    match normalize_stops(&mut stops) {
+        Some((t0, t1)) => Some(tt_color_fill::Radial {
+            c0: lerp(c0, c1, t0),
+            r0: (r0 + t0 * (r1 - r0)).max(0.),
+            c1: lerp(c0, c1, t1),
+            r1: (r0 + t1 * (r1 - r0)).max(0.),
+            stops,
+        }),
+        None => Some(tt_color_fill::Solid(last)),
+    }
+}
+
+/// Read the first palette of a `CPAL` table.
+fn read_cpal(cpal: &[u8]) -> Option<Vec<tt_rgba>> {
+    let num_entries = get_u16(cpal, 2)? as usize;
+    if get_u16(cpal, 4)? == 0 {
+        return None;
+    }
+    let records = get_u32(cpal, 8)? as usize;
+    let first = get_u16(cpal, 12)? as usize;
+    (0..num_entries)
+        .map(|i| {
+            let pos = records + 4 * (first + i);
+            let bgra = cpal.get(pos..pos + 4)?;
+            Some([
+                bgra[2] as f64 / 255.,
+                bgra[1] as f64 / 255.,
+                bgra[0] as f64 / 255.,
+                bgra[3] as f64 / 255.,
+            ])
+        })
+        .collect()
+}
+
+/// The pixel size of a PNG image, from its IHDR chunk.
+fn png_size(png: &[u8]) -> Option<(u32, u32)> {
+    if png.get(..8)? != b"\x89PNG\r\n\x1a\n" || png.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    Some((get_u32(png, 16)?, get_u32(png, 20)?))
+}
+
+/// The color glyph tables of a native font, and what we have written to
+/// the PDF file for them so far.
+pub(crate) struct tt_color_font {
+    units_per_em: f64,
+    num_glyphs: u16,
+    palette: Vec<tt_rgba>,
+    colr: Option<Vec<u8>>,
+    sbix: Option<Vec<u8>>,
+    cbdt: Option<(Vec<u8>, Vec<u8>)>,
+    /* The fontmap key, for naming bitmaps, and a serial number, for naming
+     * resources. */
+    ident: String,
+    serial: u32,
+    gstates: HashMap<u32, *mut pdf_obj>,
+    shadings: HashMap<(u16, usize), *mut pdf_obj>,
+    images: HashMap<u16, Option<(i32, tt_matrix)>>,
+}
+
+impl tt_color_font {
+    fn new(units_per_em: u16, num_glyphs: u16) -> Self {
+        tt_color_font {
+            units_per_em: units_per_em as f64,
+            num_glyphs,
+            palette: Vec::new(),
+            colr: None,
+            sbix: None,
+            cbdt: None,
+            ident: String::new(),
+            serial: 0,
+            gstates: HashMap::new(),
+            shadings: HashMap::new(),
+            images: HashMap::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.colr.is_none() && self.sbix.is_none() && self.cbdt.is_none()
+    }
+
+    fn rgba(&self, palette_index: u16, alpha: f64) -> Option<tt_rgba> {
+        self.palette
+            .get(palette_index as usize)
+            .map(|c| [c[0], c[1], c[2], c[3] * alpha])
+    }
+
+    fn color_fill(&self, palette_index: u16, alpha: f64) -> tt_color_fill {
+        match self.rgba(palette_index, alpha) {
+            Some(rgba) => tt_color_fill::Solid(rgba),
+            /* 0xFFFF, or out of range */
+            None => tt_color_fill::Foreground(alpha),
+        }
+    }
+
+    /// The layers making up glyph `gid`, or `None` if it isn't a `COLR`
+    /// color glyph.
+    pub(crate) fn layers(&self, gid: u16) -> Option<Vec<tt_color_layer>> {
+        let colr = self.colr.as_deref()?;
+        let mut layers = Vec::new();
+        if let Some(paint) = self.base_paint(gid) {
+            self.paint(paint, &IDENTITY, &mut layers, 0)?;
+        } else {
+            let num_base = get_u16(colr, 2)? as usize;
+            let base_records = get_u32(colr, 4)? as usize;
+            let layer_records = get_u32(colr, 8)? as usize;
+            let num_layers = get_u16(colr, 12)? as usize;
+            let record = find_record(colr, base_records, num_base, 6, gid)?;
+            let first = get_u16(colr, record + 2)? as usize;
+            let count = get_u16(colr, record + 4)? as usize;
+            if first + count > num_layers {
+                return None;
+            }
+            for i in first..first + count {
+                let pos = layer_records + 4 * i;
+                layers.push(tt_color_layer {
+                    gid: get_u16(colr, pos)?,
+                    matrix: IDENTITY,
+                    fill: self.color_fill(get_u16(colr, pos + 2)?, 1.),
+                    fill_matrix: IDENTITY,
+                });
+            }
+        }
+        if layers.is_empty() {
+            None
+        } else {
+            Some(layers)
+        }
+    }
+
+    /// The offset of the root of glyph `gid`'s COLRv1 paint graph.
+    fn base_paint(&self, gid: u16) -> Option<usize> {
+        let colr = self.colr.as_deref()?;
+        if get_u16(colr, 0)? < 1 {
+            return None;
+        }
+        let list = get_u32(colr, 14)? as usize;
+        if list == 0 {
+            return None;
+        }
+        let count = get_u32(colr, list)? as usize;
+        let record = find_record(colr, list + 4, count, 6, gid)?;
+        Some(list + get_u32(colr, record + 2)? as usize)
+    }
+
+    /// Flatten the paint table at `pos`, whose design space `matrix` maps
+    /// into the color glyph's, into layers.
+    fn paint(
+        &self,
+        pos: usize,
+        matrix: &tt_matrix,
+        out: &mut Vec<tt_color_layer>,
+        depth: u32,
+    ) -> Option<()> {
+        let colr = self.colr.as_deref()?;
+        if depth > MAX_PAINT_DEPTH {
+            return None;
+        }
+        match *colr.get(pos)? {
+            1 => {
+                /* PaintColrLayers */
+                let count = *colr.get(pos + 1)? as usize;
+                let first = get_u32(colr, pos + 2)? as usize;
+                let list = get_u32(colr, 18)? as usize;
+                if list == 0 || first + count > get_u32(colr, list)? as usize {
+                    return None;
+                }
+                for i in first..first + count {
+                    let paint = list + get_u32(colr, list + 4 + 4 * i)? as usize;
+                    self.paint(paint, matrix, out, depth + 1)?;
+                }
+            }
+            10 => {
+                /* PaintGlyph */
+                let child = pos + get_u24(colr, pos + 1)? as usize;
+                let gid = get_u16(colr, pos + 4)?;
+                if let Some((fill, fill_matrix)) = self.fill(child, &IDENTITY, depth + 1) {
+                    out.push(tt_color_layer {
+                        gid,
+                        matrix: *matrix,
+                        fill,
+                        fill_matrix,
+                    });
+                }
+            }
+            11 => {
+                /* PaintColrGlyph */
+                let paint = self.base_paint(get_u16(colr, pos + 1)?)?;
+                self.paint(paint, matrix, out, depth + 1)?;
+            }
+            format @ 12..=31 => {
+                let (child, m) = self.transform(pos, format)?;
+                self.paint(child, &concat(matrix, &m), out, depth + 1)?;
+            }
+            32 => {
+                /* PaintComposite, always as source-over */
+                let source = pos + get_u24(colr, pos + 1)? as usize;
+                let backdrop = pos + get_u24(colr, pos + 5)? as usize;
+                self.paint(backdrop, matrix, out, depth + 1)?;
+                self.paint(source, matrix, out, depth + 1)?;
+            }
+            /* Fills that aren't clipped by a glyph would cover the whole
+             * clip box; we have no use for those. */
+            _ => {}
+        }
+        Some(())
+    }
+
+    /// The fill of a `PaintGlyph`, with the transformation from the fill's
+    /// space into the glyph's.
+    fn fill(
+        &self,
+        pos: usize,
+        matrix: &tt_matrix,
+        depth: u32,
+    ) -> Option<(tt_color_fill, tt_matrix)> {
+        let colr = self.colr.as_deref()?;
+        if depth > MAX_PAINT_DEPTH {
+            return None;
+        }
+        let coord = |i: usize| get_i16(colr, pos + 4 + 2 * i).map(f64::from);
+        let fill = match *colr.get(pos)? {
+            2 | 3 => self.color_fill(get_u16(colr, pos + 1)?, get_f2dot14(colr, pos + 3)?),
+            format @ 4 | format @ 5 => {
+                let stops = self.color_line(pos + get_u24(colr, pos + 1)? as usize, format == 5)?;
+                linear_gradient(
+                    (coord(0)?, coord(1)?),
+                    (coord(2)?, coord(3)?),
+                    (coord(4)?, coord(5)?),
+                    stops,
+                )?
+            }
+            format @ 6 | format @ 7 => {
+                let stops = self.color_line(pos + get_u24(colr, pos + 1)? as usize, format == 7)?;
+                radial_gradient(
+                    (coord(0)?, coord(1)?),
+                    get_u16(colr, pos + 8)? as f64,
+                    (coord(3)?, coord(4)?),
+                    get_u16(colr, pos + 14)? as f64,
+                    stops,
+                )?
+            }
+            format @ 8 | format @ 9 => {
+                let stops = self.color_line(pos + get_u24(colr, pos + 1)? as usize, format == 9)?;
+                let n = stops.len() as f64;
+                let mut mean = [0.; 4];
+                for (_, rgba) in &stops {
+                    for (m, c) in mean.iter_mut().zip(rgba) {
+                        *m += c / n;
+                    }
+                }
+                tt_color_fill::Solid(mean)
+            }
+            10 => {
+                /* a glyph clipped by another: just use the inner fill */
+                let child = pos + get_u24(colr, pos + 1)? as usize;
+                return self.fill(child, matrix, depth + 1);
+            }
+            format @ 12..=31 => {
+                let (child, m) = self.transform(pos, format)?;
+                return self.fill(child, &concat(matrix, &m), depth + 1);
+            }
+            _ => return None,
+        };
+        Some((fill, *matrix))
+    }
+
+    fn color_line(&self, pos: usize, var: bool) -> Option<tt_color_stops> {
+        let colr = self.colr.as_deref()?;
+        let count = get_u16(colr, pos + 1)? as usize;
+        let size = if var { 10 } else { 6 };
+        let stops: Option<tt_color_stops> = (0..count)
+            .map(|i| {
+                let stop = pos + 3 + size * i;
+                let alpha = get_f2dot14(colr, stop + 4)?;
+                /* we don't know the text color here */
+                let rgba = self
+                    .rgba(get_u16(colr, stop + 2)?, alpha)
+                    .unwrap_or([0., 0., 0., alpha]);
+                Some((get_f2dot14(colr, stop)?, rgba))
+            })
+            .collect();
+        stops.filter(|stops| !stops.is_empty())
+    }
+
+    /// The child paint of a transformation paint table, and its matrix.
+    fn transform(&self, pos: usize, format: u8) -> Option<(usize, tt_matrix)> {
+        let colr = self.colr.as_deref()?;
+        let child = pos + get_u24(colr, pos + 1)? as usize;
+        let f2dot14 = |i: usize| get_f2dot14(colr, pos + 4 + 2 * i);
+        let fword = |i: usize| get_i16(colr, pos + 4 + 2 * i).map(f64::from);
+        let m = match format {
+            12 | 13 => {
+                let affine = pos + get_u24(colr, pos + 4)? as usize;
+                let v = |i: usize| get_fixed(colr, affine + 4 * i);
+                [v(0)?, v(1)?, v(2)?, v(3)?, v(4)?, v(5)?]
+            }
+            14 | 15 => translate(fword(0)?, fword(1)?),
+            16 | 17 => [f2dot14(0)?, 0., 0., f2dot14(1)?, 0., 0.],
+            18 | 19 => around(
+                fword(2)?,
+                fword(3)?,
+                [f2dot14(0)?, 0., 0., f2dot14(1)?, 0., 0.],
+            ),
+            20 | 21 => [f2dot14(0)?, 0., 0., f2dot14(0)?, 0., 0.],
+            22 | 23 => around(
+                fword(1)?,
+                fword(2)?,
+                [f2dot14(0)?, 0., 0., f2dot14(0)?, 0., 0.],
+            ),
+            24 | 25 => rotate(f2dot14(0)?),
+            26 | 27 => around(fword(1)?, fword(2)?, rotate(f2dot14(0)?)),
+            28 | 29 => skew(f2dot14(0)?, f2dot14(1)?),
+            30 | 31 => around(fword(2)?, fword(3)?, skew(f2dot14(0)?, f2dot14(1)?)),
+            _ => return None,
+        };
+        Some((child, m))
+    }
+
+    /// The bitmap of glyph `gid`, from the largest strike that has one.
+    pub(crate) fn bitmap(&self, gid: u16) -> Option<tt_color_bitmap<'_>> {
+        if gid >= self.num_glyphs {
+            return None;
+        }
+        self.sbix_bitmap(gid).or_else(|| self.cbdt_bitmap(gid))
+    }
+
+    fn sbix_bitmap(&self, gid: u16) -> Option<tt_color_bitmap<'_>> {
+        let sbix = self.sbix.as_deref()?;
+        let num_strikes = get_u32(sbix, 4)? as usize;
+        let mut strikes: Vec<(u16, usize)> = (0..num_strikes)
+            .filter_map(|i| {
+                let strike = get_u32(sbix, 8 + 4 * i)? as usize;
+                Some((get_u16(sbix, strike)?, strike))
+            })
+            .filter(|&(ppem, _)| ppem > 0)
+            .collect();
+        strikes.sort_by_key(|&(ppem, _)| std::cmp::Reverse(ppem));
+        strikes.into_iter().find_map(|(ppem, strike)| {
+            let mut gid = gid;
+            /* follow at most one 'dupe' */
+            for _ in 0..2 {
+                if gid >= self.num_glyphs {
+                    return None;
+                }
+                let start = get_u32(sbix, strike + 4 + 4 * gid as usize)? as usize;
+                let end = get_u32(sbix, strike + 8 + 4 * gid as usize)? as usize;
+                if end < start + 8 {
+                    return None;
+                }
+                let data = sbix.get(strike + start..strike + end)?;
+                match &data[4..8] {
+                    b"png " => {
+                        let png = &data[8..];
+                        let (width, height) = png_size(png)?;
+                        let scale = self.units_per_em / ppem as f64;
+                        return Some(tt_color_bitmap {
+                            png,
+                            x: get_i16(data, 0)? as f64 * scale,
+                            y: get_i16(data, 2)? as f64 * scale,
+                            width: width as f64 * scale,
+                            height: height as f64 * scale,
+                        });
+                    }
+                    b"dupe" => gid = get_u16(data, 8)?,
+                    _ => return None,
+                }
+            }
+            None
+        })
+    }
+
+    fn cbdt_bitmap(&self, gid: u16) -> Option<tt_color_bitmap<'_>> {
+        let (cblc, _) = self.cbdt.as_ref()?;
+        let num_sizes = get_u32(cblc, 4)? as usize;
+        let mut sizes: Vec<(u8, usize)> = (0..num_sizes)
+            .filter_map(|i| {
+                let size = 8 + 48 * i;
+                let first = get_u16(cblc, size + 40)?;
+                let last = get_u16(cblc, size + 42)?;
+                if gid < first || gid > last {
+                    return None;
+                }
+                Some((*cblc.get(size + 45)?, size))
+            })
+            .filter(|&(ppem, _)| ppem > 0)
+            .collect();
+        sizes.sort_by_key(|&(ppem, _)| std::cmp::Reverse(ppem));
+        sizes
+            .into_iter()
+            .find_map(|(ppem, size)| self.cbdt_strike_bitmap(size, ppem, gid))
+    }
+
+    fn cbdt_strike_bitmap(&self, size: usize, ppem: u8, gid: u16) -> Option<tt_color_bitmap<'_>> {
+        let (cblc, cbdt) = self.cbdt.as_ref()?;
+        let array = get_u32(cblc, size)? as usize;
+        let num_subtables = get_u32(cblc, size + 8)? as usize;
+        for i in 0..num_subtables {
+            let entry = array + 8 * i;
+            let first = get_u16(cblc, entry)?;
+            let last = get_u16(cblc, entry + 2)?;
+            if gid < first || gid > last {
+                continue;
+            }
+            let sub = array + get_u32(cblc, entry + 4)? as usize;
+            let index_format = get_u16(cblc, sub)?;
+            let image_format = get_u16(cblc, sub + 2)?;
+            let image_data = get_u32(cblc, sub + 4)? as usize;
+            let k = (gid - first) as usize;
+            /* big glyph metrics shared by all glyphs of the subtable */
+            let mut metrics = None;
+            let (start, end) = match index_format {
+                1 => (
+                    get_u32(cblc, sub + 8 + 4 * k)? as usize,
+                    get_u32(cblc, sub + 12 + 4 * k)? as usize,
+                ),
+                2 => {
+                    let image_size = get_u32(cblc, sub + 8)? as usize;
+                    metrics = Some(cblc.get(sub + 12..sub + 20)?);
+                    (image_size * k, image_size * (k + 1))
+                }
+                3 => (
+                    get_u16(cblc, sub + 8 + 2 * k)? as usize,
+                    get_u16(cblc, sub + 10 + 2 * k)? as usize,
+                ),
+                4 => {
+                    let count = get_u32(cblc, sub + 8)? as usize;
+                    let pairs = sub + 12;
+                    let j = (0..count).find(|&j| get_u16(cblc, pairs + 4 * j) == Some(gid))?;
+                    (
+                        get_u16(cblc, pairs + 4 * j + 2)? as usize,
+                        get_u16(cblc, pairs + 4 * j + 6)? as usize,
+                    )
+                }
+                5 => {
+                    let image_size = get_u32(cblc, sub + 8)? as usize;
+                    metrics = Some(cblc.get(sub + 12..sub + 20)?);
+                    let count = get_u32(cblc, sub + 20)? as usize;
+                    let j = (0..count).find(|&j| get_u16(cblc, sub + 24 + 2 * j) == Some(gid))?;
+                    (image_size * j, image_size * (j + 1))
+                }
+                _ => return None,
+            };
+            let glyph = cbdt.get(image_data + start..image_data + end)?;
+            /* height, width, bearing x, bearing y, then the PNG data */
+            let (metrics, png) = match image_format {
+                17 => (glyph.get(..4)?, 5),
+                18 => (glyph.get(..4)?, 8),
+                19 => (metrics?.get(..4)?, 0),
+                _ => return None,
+            };
+            let length = get_u32(glyph, png)? as usize;
+            let png = glyph.get(png + 4..png + 4 + length)?;
+            let scale = self.units_per_em / ppem as f64;
+            let height = metrics[0] as f64;
+            return Some(tt_color_bitmap {
+                png,
+                x: metrics[2] as i8 as f64 * scale,
+                y: (metrics[3] as i8 as f64 - height) * scale,
+                width: metrics[1] as f64 * scale,
+                height: height * scale,
+            });
+        }
+        None
+    }
+}
+
+static mut color_font_serial: u32 = 0;
+
+/// Read the color glyph tables of a native font, if it has any. `ident`
+/// must identify the font file and face, e.g. the fontmap key.
+pub(crate) unsafe fn tt_color_font_load(
+    sfont: *mut sfnt,
+    ident: &str,
+    units_per_em: u16,
+    num_glyphs: u16,
+) -> Option<Box<tt_color_font>> {
+    let mut font = tt_color_font::new(units_per_em, num_glyphs);
+    if let Some(cpal) = read_table(sfont, b"CPAL") {
+        font.palette = read_cpal(&cpal).unwrap_or_default();
+    }
+    font.colr = read_table(sfont, b"COLR");
+    font.sbix = read_table(sfont, b"sbix");
+    if let (Some(cblc), Some(cbdt)) = (read_table(sfont, b"CBLC"), read_table(sfont, b"CBDT")) {
+        font.cbdt = Some((cblc, cbdt));
+    }
+    if font.is_empty() {
+        return None;
+    }
+    font.ident = ident.to_owned();
+    color_font_serial += 1;
+    font.serial = color_font_serial;
+    Some(Box::new(font))
+}
+
+pub(crate) unsafe fn tt_color_font_close(font: Box<tt_color_font>) {
+    for &obj in font.gstates.values().chain(font.shadings.values()) {
+        pdf_release_obj(obj);
+    }
+}
+
+fn to_tmatrix(m: &tt_matrix) -> TMatrix {
+    TMatrix::row_major(m[0], m[1], m[2], m[3], m[4], m[5])
+}
+
+unsafe fn set_fill_alpha(font: &mut tt_color_font, alpha: f64) {
+    if alpha >= 1. || !pdfa_transparency_allowed("Color glyph transparency") {
+        return;
+    }
+    let key = (alpha * 1000.).round() as u32;
+    let name = format!("_Cg{}a{:04}_", font.serial, key);
+    let gstate = *font.gstates.entry(key).or_insert_with(|| {
+        let mut dict = pdf_dict::new();
+        dict.set("Type", "ExtGState");
+        dict.set("ca", key as f64 / 1000.);
+        let dict = dict.into_obj();
+        let gstate = pdf_ref_obj(dict);
+        pdf_release_obj(dict);
+        gstate
+    });
+    let resname = CString::new(name.as_bytes()).unwrap();
+    pdf_doc_add_page_resource("ExtGState", resname.as_ptr(), pdf_link_obj(gstate));
+    pdf_doc_add_page_content(format!(" /{} gs", name).as_bytes()); /* op: gs */
+}
+
+/// An axial or radial shading for a gradient fill.
+unsafe fn gradient_shading(fill: &tt_color_fill) -> Option<pdf_dict> {
+    let (shading_type, coords, stops) = match fill {
+        tt_color_fill::Linear { p0, p1, stops } => (2., vec![p0.0, p0.1, p1.0, p1.1], stops),
+        tt_color_fill::Radial {
+            c0,
+            r0,
+            c1,
+            r1,
+            stops,
+        } => (3., vec![c0.0, c0.1, *r0, c1.0, c1.1, *r1], stops),
+        _ => return None,
+    };
+    let mut functions = vec![];
+    let mut bounds = vec![];
+    let mut encode = vec![];
+    for (i, pair) in stops.windows(2).enumerate() {
+        let mut function = pdf_dict::new();
+        function.set("FunctionType", 2_f64);
+        function.set("Domain", vec![0_f64.into_obj(), 1_f64.into_obj()]);
+        let c0: Vec<*mut pdf_obj> = pair[0].1[..3].iter().map(|&c| c.into_obj()).collect();
+        let c1: Vec<*mut pdf_obj> = pair[1].1[..3].iter().map(|&c| c.into_obj()).collect();
+        function.set("C0", c0);
+        function.set("C1", c1);
+        function.set("N", 1_f64);
+        functions.push_obj(function);
+        if i + 2 < stops.len() {
+            bounds.push_obj(pair[1].0);
+        }
+        encode.push_obj(0_f64);
+        encode.push_obj(1_f64);
+    }
+    let function = if functions.len() == 1 {
+        functions.pop().unwrap()
+    } else {
+        let mut stitching = pdf_dict::new();
+        stitching.set("FunctionType", 3_f64);
+        stitching.set("Domain", vec![0_f64.into_obj(), 1_f64.into_obj()]);
+        stitching.set("Functions", functions);
+        stitching.set("Bounds", bounds);
+        stitching.set("Encode", encode);
+        stitching.into_obj()
+    };
+    let mut shading = pdf_dict::new();
+    shading.set("ShadingType", shading_type);
+    shading.set("ColorSpace", "DeviceRGB");
+    shading.set(
+        "Coords",
+        coords
+            .into_iter()
+            .map(IntoObj::into_obj)
+            .collect::<Vec<_>>(),
+    );
+    shading.set("Function", function);
+    shading.set("Extend", vec![true.into_obj(), true.into_obj()]);
+    Some(shading)
+}
+
+/// Paint one layer of a color glyph. `units` maps design units to PDF
+/// units in the glyph's position.
+unsafe fn draw_layer(
+    font: &mut tt_color_font,
+    base_gid: u16,
+    index: usize,
+    layer: &tt_color_layer,
+    (xpos, ypos): (spt_t, spt_t),
+    font_id: i32,
+    units: &tt_matrix,
+) {
+    let code = layer.gid.to_be_bytes();
+    pdf_dev_gsave();
+    if layer.matrix != IDENTITY {
+        if let Some(inverse) = invert(units) {
+            pdf_dev_concat(&to_tmatrix(&concat(
+                units,
+                &concat(&layer.matrix, &inverse),
+            )));
+        }
+    }
+    match &layer.fill {
+        tt_color_fill::Solid(rgba) => {
+            set_fill_alpha(font, rgba[3]);
+            if let Ok(color) = PdfColor::from_rgb(rgba[0], rgba[1], rgba[2]) {
+                pdf_dev_set_color(&color, 0x20, 0);
+            }
+            pdf_dev_set_string(
+                xpos,
+                ypos,
+                code.as_ptr() as *const libc::c_void,
+                2,
+                0,
+                font_id,
+                -1,
+            );
+        }
+        tt_color_fill::Foreground(alpha) => {
+            set_fill_alpha(font, *alpha);
+            pdf_dev_set_string(
+                xpos,
+                ypos,
+                code.as_ptr() as *const libc::c_void,
+                2,
+                0,
+                font_id,
+                -1,
+            );
+        }
+        fill => {
+            let shading = match font.shadings.get(&(base_gid, index)) {
+                Some(&shading) => shading,
+                None => match gradient_shading(fill) {
+                    Some(dict) => {
+                        let dict = dict.into_obj();
+                        let shading = pdf_ref_obj(dict);
+                        pdf_release_obj(dict);
+                        font.shadings.insert((base_gid, index), shading);
+                        shading
+                    }
+                    None => {
+                        pdf_dev_grestore();
+                        return;
+                    }
+                },
+            };
+            if let tt_color_fill::Linear { stops, .. } | tt_color_fill::Radial { stops, .. } = fill
+            {
+                let alpha = stops.iter().map(|s| s.1[3]).sum::<f64>() / stops.len() as f64;
+                set_fill_alpha(font, alpha);
+            }
+            /* op: 7 Tr */
+            pdf_dev_set_string_rendermode(
+                xpos,
+                ypos,
+                code.as_ptr() as *const libc::c_void,
+                2,
+                0,
+                font_id,
+                -1,
+                7,
+            );
+            graphics_mode();
+            pdf_dev_concat(&to_tmatrix(&concat(units, &layer.fill_matrix)));
+            let name = format!("_Cg{}s{}_{}_", font.serial, base_gid, index);
+            let resname = CString::new(name.as_bytes()).unwrap();
+            pdf_doc_add_page_resource("Shading", resname.as_ptr(), pdf_link_obj(shading));
+            pdf_doc_add_page_content(format!(" /{} sh", name).as_bytes()); /* op: sh */
+        }
+    }
+    graphics_mode();
+    pdf_dev_grestore();
+}
+
+/// The image XObject for the bitmap of glyph `gid`, and the transformation
+/// from the unit square to its place in design space.
+unsafe fn bitmap_image(font: &mut tt_color_font, gid: u16) -> Option<(i32, tt_matrix)> {
+    if let Some(&image) = font.images.get(&gid) {
+        return image;
+    }
+    let image = font.bitmap(gid).and_then(|bitmap| {
+        let ident = CString::new(format!("{}:{}", font.ident, gid)).ok()?;
+        let id = pdf_ximage_load_png_data(&ident, bitmap.png);
+        if id < 0 {
+            return None;
+        }
+        Some((
+            id,
+            [bitmap.width, 0., 0., bitmap.height, bitmap.x, bitmap.y],
+        ))
+    });
+    font.images.insert(gid, image);
+    image
+}
+
+/// Draw glyph `gid` of native font `font_id` at (xpos, ypos) in color, if
+/// it is a color glyph, and return whether it was. `units` maps the font's
+/// design units to PDF units, leaving out the glyph's position; `width` is
+/// its advance in DVI units.
+pub(crate) unsafe fn tt_color_glyph_draw(
+    font: &mut tt_color_font,
+    gid: u16,
+    xpos: spt_t,
+    ypos: spt_t,
+    width: spt_t,
+    font_id: i32,
+    units: &tt_matrix,
+) -> bool {
+    let layers = font.layers(gid);
+    let image = if layers.is_none() {
+        match bitmap_image(font, gid) {
+            Some(image) => Some(image),
+            None => return false,
+        }
+    } else {
+        None
+    };
+    /* The glyph itself, for the text; the rest is marked as not being any. */
+    let code = gid.to_be_bytes();
+    pdf_dev_set_string_rendermode(
+        xpos,
+        ypos,
+        code.as_ptr() as *const libc::c_void,
+        2,
+        width,
+        font_id,
+        -1,
+        3,
+    );
+    graphics_mode();
+    pdf_doc_add_page_content(b" /Span<</ActualText()>>BDC"); /* op: BDC */
+    let origin = pdf_dev_get_coord();
+    let dvi2pts = 1. / dev_unit_dviunit();
+    let mut units = *units;
+    units[4] = xpos as f64 * dvi2pts - origin.x;
+    units[5] = ypos as f64 * dvi2pts - origin.y;
+    if let Some(layers) = layers {
+        for (index, layer) in layers.iter().enumerate() {
+            draw_layer(font, gid, index, layer, (xpos, ypos), font_id, &units);
+        }
+    } else if let Some((id, placement)) = image {
+        pdf_dev_gsave();
+        pdf_dev_concat(&to_tmatrix(&concat(&units, &placement)));
+        let res_name = pdf_ximage_get_resname(id);
+        let content = format!(" /{} Do", CStr::from_ptr(res_name).display());
+        pdf_doc_add_page_content(content.as_bytes()); /* op: Do */
+        pdf_dev_grestore();
+        pdf_doc_add_page_resource("XObject", res_name, pdf_ximage_get_reference(id));
+    }
+    pdf_doc_add_page_content(b" EMC"); /* op: EMC */
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cpal(colors: &[[u8; 4]]) -> Vec<u8> {
+        let mut data = vec![0, 0, 0, colors.len() as u8, 0, 1, 0, colors.len() as u8];
+        data.extend_from_slice(&[0, 0, 0, 14, 0, 0]);
+        for c in colors {
+            data.extend_from_slice(c);
+        }
+        data
+    }
+
+    #[test]
+    fn palette() {
+        let palette = read_cpal(&cpal(&[[0, 0, 255, 255], [255, 0, 0, 51]])).unwrap();
+        assert_eq!(palette[0], [1., 0., 0., 1.]);
+        assert_eq!(palette[1], [0., 0., 1., 0.2]);
+    }
+
+    #[test]
+    fn colr_v0_layers() {
+        /* base glyph 5 = glyph 6 in palette entry 1 over glyph 7 in the text color */
+        let colr = vec![
+            0, 0, 0, 1, 0, 0, 0, 14, 0, 0, 0, 20, 0, 2, /* base */ 0, 5, 0, 0, 0, 2,
+            /* layers */ 0, 7, 0xff, 0xff, 0, 6, 0, 1,
+        ];
+        let mut font = tt_color_font::new(1000, 10);
+        font.palette = read_cpal(&cpal(&[[0, 0, 0, 255], [0, 255, 0, 255]])).unwrap();
+        font.colr = Some(colr);
+        let layers = font.layers(5).unwrap();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].gid, 7);
+        assert_eq!(layers[0].fill, tt_color_fill::Foreground(1.));
+        assert_eq!(layers[1].gid, 6);
+        assert_eq!(layers[1].fill, tt_color_fill::Solid([0., 1., 0., 1.]));
+        assert!(font.layers(6).is_none());
+    }
+
+    #[test]
+    fn colr_v1_paint_graph() {
+        let mut colr = vec![0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        /* BaseGlyphList at 34, LayerList at 44, no clips or variations */
+        colr.extend_from_slice(&[0, 0, 0, 34, 0, 0, 0, 44, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        /* glyph 3 -> PaintColrLayers at 56 */
+        colr.extend_from_slice(&[0, 0, 0, 1, 0, 3, 0, 0, 0, 22]);
+        /* two layers, at 62 and 74 */
+        colr.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 18, 0, 0, 0, 30]);
+        /* PaintColrLayers: 2 layers from 0 */
+        colr.extend_from_slice(&[1, 2, 0, 0, 0, 0]);
+        /* PaintGlyph 8 -> PaintSolid at +6 */
+        colr.extend_from_slice(&[10, 0, 0, 6, 0, 8]);
+        colr.extend_from_slice(&[2, 0, 0, 0x20, 0]);
+        colr.push(0);
+        /* PaintTranslate(100, -50) -> PaintGlyph 9 at +8 */
+        colr.extend_from_slice(&[14, 0, 0, 8, 0, 100, 0xff, 0xce]);
+        /* PaintGlyph 9 -> PaintLinearGradient at +6 */
+        colr.extend_from_slice(&[10, 0, 0, 6, 0, 9]);
+        /* color line at +16; p0 (0,0), p1 (100,0), p2 (0,100) */
+        colr.extend_from_slice(&[4, 0, 0, 16, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 100]);
+        colr.extend_from_slice(&[0, 0, 2, 0, 0, 0, 0, 0x40, 0, 0x40, 0, 0, 1, 0x40, 0]);
+        let mut font = tt_color_font::new(1000, 10);
+        font.palette = vec![[1., 0., 0., 1.], [0., 0., 1., 1.]];
+        font.colr = Some(colr);
+        let layers = font.layers(3).unwrap();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].gid, 8);
+        assert_eq!(layers[0].fill, tt_color_fill::Solid([1., 0., 0., 0.5]));
+        assert_eq!(layers[1].gid, 9);
+        assert_eq!(layers[1].matrix, translate(100., -50.));
+        assert_eq!(
+            layers[1].fill,
+            tt_color_fill::Linear {
+                p0: (0., 0.),
+                p1: (100., 0.),
+                stops: vec![(0., [1., 0., 0., 1.]), (1., [0., 0., 1., 1.])],
+            }
+        );
+    }
+
+    #[test]
+    fn gradient_geometry() {
+        /* p2 at 45 degrees: the gradient runs along (1, -1) */
+        let stops = vec![(0.5, [0., 0., 0., 1.]), (1., [1., 1., 1., 1.])];
+        match linear_gradient((0., 0.), (100., 0.), (100., 100.), stops).unwrap() {
+            tt_color_fill::Linear { p0, p1, stops } => {
+                assert_eq!(p0, (25., -25.));
+                assert_eq!(p1, (50., -50.));
+                assert_eq!(stops[0].0, 0.);
+            }
+            fill => panic!("unexpected fill {:?}", fill),
+        }
+        let stops = vec![(0.5, [0., 0., 0., 1.])];
+        assert_eq!(
+            radial_gradient((0., 0.), 0., (0., 0.), 10., stops),
+            Some(tt_color_fill::Solid([0., 0., 0., 1.]))
+        );
+    }
+
+    #[test]
+    fn matrices() {
+        let m = around(10., 0., rotate(0.5));
+        let p = concat(&m, &translate(20., 0.));
+        assert!((p[4] - 10.).abs() < 1e-9 && (p[5] - 10.).abs() < 1e-9);
+        let inv = invert(&m).unwrap();
+        let id = concat(&m, &inv);
+        for (a, b) in id.iter().zip(&IDENTITY) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    fn png(width: u8, height: u8) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0, width, 0, 0, 0, height, 8, 6, 0, 0, 0]);
+        png
+    }
+
+    #[test]
+    fn sbix_bitmap() {
+        let image = png(16, 32);
+        /* one strike at 64 ppem with glyph 1 only */
+        let mut sbix = vec![0, 1, 0, 1, 0, 0, 0, 1, 0, 0, 0, 12];
+        sbix.extend_from_slice(&[0, 64, 0, 72, 0, 0, 0, 16, 0, 0, 0, 16]);
+        sbix.extend_from_slice(&(24 + image.len() as u32).to_be_bytes());
+        sbix.extend_from_slice(&[0, 4, 0xff, 0xfc]);
+        sbix.extend_from_slice(b"png ");
+        sbix.extend_from_slice(&image);
+        let mut font = tt_color_font::new(2048, 2);
+        font.sbix = Some(sbix);
+        let bitmap = font.bitmap(1).unwrap();
+        assert_eq!(bitmap.png, &image[..]);
+        assert_eq!((bitmap.x, bitmap.y), (128., -128.));
+        assert_eq!((bitmap.width, bitmap.height), (512., 1024.));
+        assert!(font.bitmap(0).is_none());
+    }
+
+    #[test]
+    fn cbdt_bitmap() {
+        let image = png(136, 128);
+        let mut cblc = vec![0, 3, 0, 0, 0, 0, 0, 1];
+        /* BitmapSize: subtable array at 56, one subtable, glyphs 4-4, 109 ppem */
+        cblc.extend_from_slice(&[0, 0, 0, 56, 0, 0, 0, 16, 0, 0, 0, 1, 0, 0, 0, 0]);
+        cblc.extend_from_slice(&[0; 24]);
+        cblc.extend_from_slice(&[0, 4, 0, 4, 109, 109, 32, 1]);
+        /* array entry and an index format 1 subtable, data at 4 in CBDT */
+        cblc.extend_from_slice(&[0, 4, 0, 4, 0, 0, 0, 8]);
+        cblc.extend_from_slice(&[0, 1, 0, 17, 0, 0, 0, 4]);
+        cblc.extend_from_slice(&[0, 0, 0, 0]);
+        cblc.extend_from_slice(&(9 + image.len() as u32).to_be_bytes());
+        let mut cbdt = vec![0, 3, 0, 0];
+        cbdt.extend_from_slice(&[128, 136, 0, 101, 136]);
+        cbdt.extend_from_slice(&(image.len() as u32).to_be_bytes());
+        cbdt.extend_from_slice(&image);
+        let mut font = tt_color_font::new(2048, 10);
+        font.cbdt = Some((cblc, cbdt));
+        let bitmap = font.bitmap(4).unwrap();
+        assert_eq!(bitmap.png, &image[..]);
+        let scale = 2048. / 109.;
+        assert_eq!(bitmap.x, 0.);
+        assert_eq!(bitmap.y, (101. - 128.) * scale);
+        assert_eq!(bitmap.width, 136. * scale);
+        assert!(font.bitmap(5).is_none());
+    }
+}
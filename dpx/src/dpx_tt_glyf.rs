@@ -192,6 +192,73 @@ pub(crate) unsafe fn tt_build_finish(g: *mut tt_glyphs) {
     };
 }
 
+/* Replace the original glyph data and metrics with those of the variable font
+ * instance selected for this font, if any. */
+unsafe fn tt_apply_instance(sfont: *mut sfnt, gd: &mut tt_glyph_desc) {
+    if (*sfont).instance.is_null() {
+        return;
+    }
+    let orig = if gd.data.is_null() {
+        &[][..]
+    } else {
+        slice::from_raw_parts(gd.data, gd.length as usize)
+    };
+    let (data, advw, lsb) = (*(*sfont).instance).instance_glyph(gd.ogid, orig, gd.advw, gd.lsb);
+    free(gd.data as *mut libc::c_void);
+    gd.data = ptr::null_mut();
+    gd.length = data.len() as u32;
+    gd.advw = advw;
+    gd.lsb = lsb;
+    if !data.is_empty() {
+        gd.data = new(data.len() as u32) as *mut u8;
+        memcpy(
+            gd.data as *mut libc::c_void,
+            data.as_ptr() as *const libc::c_void,
+            data.len(),
+        );
+        let bbox = |i: usize| i16::from_be_bytes([data[i], data[i + 1]]);
+        gd.llx = bbox(2);
+        gd.lly = bbox(4);
+        gd.urx = bbox(6);
+        gd.ury = bbox(8);
+    }
+}
+/* Bitmap-only color fonts (sbix, CBDT) may have no outlines at all. Their
+ * glyphs are drawn separately, so treat every glyph as empty: the embedded
+ * font then only serves for the text.
+ */
+unsafe fn location_has_glyf(sfont: *mut sfnt) -> bool {
+    sfnt_find_table_pos(sfont, sfnt_table_info::LOCA) > 0
+        && sfnt_find_table_pos(sfont, sfnt_table_info::GLYF) > 0
+}
+unsafe fn tt_read_locations(
+    sfont: *mut sfnt,
+    index_to_loc_format: i16,
+    num_glyphs: u16,
+) -> *mut u32 {
+    let location = new(((num_glyphs as i32 + 1i32) as u32 as u64)
+        .wrapping_mul(::std::mem::size_of::<u32>() as u64) as u32) as *mut u32;
+    if !location_has_glyf(sfont) {
+        for i in 0..=num_glyphs as u32 {
+            *location.offset(i as isize) = 0;
+        }
+        return location;
+    }
+    sfnt_locate_table(sfont, sfnt_table_info::LOCA);
+    if index_to_loc_format as i32 == 0i32 {
+        for i in 0..=num_glyphs as u32 {
+            *location.offset(i as isize) =
+                (2_u32).wrapping_mul(tt_get_unsigned_pair(&mut (*sfont).handle) as u32);
+        }
+    } else if index_to_loc_format as i32 == 1i32 {
+        for i in 0..=num_glyphs as u32 {
+            *location.offset(i as isize) = tt_get_unsigned_quad(&mut (*sfont).handle);
+        }
+    } else {
+        panic!("Unknown IndexToLocFormat.");
+    }
+    location
+}
 pub(crate) unsafe fn tt_build_tables(sfont: *mut sfnt, mut g: *mut tt_glyphs) -> i32 {
     /* some information available from other TrueType table */
     let vmtx;
@@ -249,21 +316,7 @@ pub(crate) unsafe fn tt_build_tables(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
     } else {
         vmtx = ptr::null_mut()
     }
-    sfnt_locate_table(sfont, sfnt_table_info::LOCA);
-    let location = new((((*maxp).numGlyphs as i32 + 1i32) as u32 as u64)
-        .wrapping_mul(::std::mem::size_of::<u32>() as u64) as u32) as *mut u32; /* Estimate most frequently appeared width */
-    if (*head).indexToLocFormat as i32 == 0i32 {
-        for i in 0..=(*maxp).numGlyphs as i32 {
-            *location.offset(i as isize) =
-                (2_u32).wrapping_mul(tt_get_unsigned_pair(&mut (*sfont).handle) as u32);
-        }
-    } else if (*head).indexToLocFormat as i32 == 1i32 {
-        for i in 0..=(*maxp).numGlyphs as i32 {
-            *location.offset(i as isize) = tt_get_unsigned_quad(&mut (*sfont).handle);
-        }
-    } else {
-        panic!("Unknown IndexToLocFormat.");
-    }
+    let location = tt_read_locations(sfont, (*head).indexToLocFormat, (*maxp).numGlyphs);
     let w_stat =
         new(((*g).emsize + 2).wrapping_mul(::std::mem::size_of::<u16>() as _) as _) as *mut u16;
     memset(
@@ -274,7 +327,11 @@ pub(crate) unsafe fn tt_build_tables(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
     /*
      * Read glyf table.
      */
-    let offset = sfnt_locate_table(sfont, sfnt_table_info::GLYF);
+    let offset = if location_has_glyf(sfont) {
+        sfnt_locate_table(sfont, sfnt_table_info::GLYF)
+    } else {
+        0
+    };
     /*
      * The num_glyphs may grow when composite glyph is found.
      * A component of glyph refered by a composite glyph is appended
@@ -305,14 +362,6 @@ pub(crate) unsafe fn tt_build_tables(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
         (*(*g).gd.offset(i as isize)).length = len;
         let ref mut fresh2 = (*(*g).gd.offset(i as isize)).data;
         *fresh2 = ptr::null_mut();
-        if (*(*g).gd.offset(i as isize)).advw as i32 <= (*g).emsize as i32 {
-            let ref mut fresh3 = *w_stat.offset((*(*g).gd.offset(i as isize)).advw as isize);
-            *fresh3 = (*fresh3 as i32 + 1i32) as u16
-        } else {
-            let ref mut fresh4 = *w_stat.offset(((*g).emsize as i32 + 1i32) as isize);
-            *fresh4 = (*fresh4 as i32 + 1i32) as u16
-            /* larger than em */
-        }
         if !(len == 0_u32) {
             if len < 10_u32 {
                 panic!("Invalid TrueType glyph data (gid {}).", gid);
@@ -321,7 +370,7 @@ pub(crate) unsafe fn tt_build_tables(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
                 as *mut u8;
             let ref mut fresh5 = (*(*g).gd.offset(i as isize)).data;
             *fresh5 = p;
-            let endptr = p.offset(len as isize);
+            let mut endptr = p.offset(len as isize);
             let handle = &mut (*sfont).handle;
             handle
                 .seek(SeekFrom::Start(offset as u64 + loc as u64))
@@ -369,6 +418,16 @@ pub(crate) unsafe fn tt_build_tables(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
                 p as *mut i8,
                 len.wrapping_sub(10_u32) as size_t,
             );
+            if !(*sfont).instance.is_null() {
+                let gd = &mut *(*g).gd.offset(i as isize);
+                tt_apply_instance(sfont, gd);
+                if vmtx.is_null() {
+                    gd.tsb =
+                        ((*g).default_advh as i32 - (*g).default_tsb as i32 - gd.ury as i32) as i16
+                }
+                p = gd.data.offset(10);
+                endptr = gd.data.offset(gd.length as isize);
+            }
             /*
              * Fix GIDs of composite glyphs.
              */
@@ -423,8 +482,18 @@ pub(crate) unsafe fn tt_build_tables(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
                     }
                 }
             }
+        } else {
+            /* Does not contains any data, but the advance may still vary. */
+            tt_apply_instance(sfont, &mut *(*g).gd.offset(i as isize));
+        }
+        if (*(*g).gd.offset(i as isize)).advw as i32 <= (*g).emsize as i32 {
+            let ref mut fresh3 = *w_stat.offset((*(*g).gd.offset(i as isize)).advw as isize);
+            *fresh3 = (*fresh3 as i32 + 1i32) as u16
+        } else {
+            let ref mut fresh4 = *w_stat.offset(((*g).emsize as i32 + 1i32) as isize);
+            *fresh4 = (*fresh4 as i32 + 1i32) as u16
+            /* larger than em */
         }
-        /* Does not contains any data. */
     }
     free(location as *mut libc::c_void);
     free(hmtx as *mut libc::c_void);
@@ -671,21 +740,7 @@ pub(crate) unsafe fn tt_get_metrics(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
     } else {
         vmtx = ptr::null_mut()
     }
-    sfnt_locate_table(sfont, sfnt_table_info::LOCA);
-    let location = new((((*maxp).numGlyphs as i32 + 1i32) as u32 as u64)
-        .wrapping_mul(::std::mem::size_of::<u32>() as u64) as u32) as *mut u32;
-    if (*head).indexToLocFormat as i32 == 0i32 {
-        for i in 0..=(*maxp).numGlyphs as u32 {
-            *location.offset(i as isize) =
-                (2_u32).wrapping_mul(tt_get_unsigned_pair(&mut (*sfont).handle) as u32);
-        }
-    } else if (*head).indexToLocFormat as i32 == 1i32 {
-        for i in 0..=(*maxp).numGlyphs as u32 {
-            *location.offset(i as isize) = tt_get_unsigned_quad(&mut (*sfont).handle);
-        }
-    } else {
-        panic!("Unknown IndexToLocFormat.");
-    }
+    let location = tt_read_locations(sfont, (*head).indexToLocFormat, (*maxp).numGlyphs);
     let w_stat = new((((*g).emsize as i32 + 2i32) as u32 as u64)
         .wrapping_mul(::std::mem::size_of::<u16>() as u64) as u32) as *mut u16;
     memset(
@@ -696,7 +751,11 @@ pub(crate) unsafe fn tt_get_metrics(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
     /*
      * Read glyf table.
      */
-    let offset = sfnt_locate_table(sfont, sfnt_table_info::GLYF); /* old gid */
+    let offset = if location_has_glyf(sfont) {
+        sfnt_locate_table(sfont, sfnt_table_info::GLYF)
+    } else {
+        0
+    };
     for i in 0..(*g).num_glyphs as u32 {
         let gid = (*(*g).gd.offset(i as isize)).ogid;
         if gid as i32 >= (*maxp).numGlyphs as i32 {
@@ -716,14 +775,6 @@ pub(crate) unsafe fn tt_get_metrics(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
         (*(*g).gd.offset(i as isize)).length = len;
         let ref mut fresh7 = (*(*g).gd.offset(i as isize)).data;
         *fresh7 = ptr::null_mut();
-        if (*(*g).gd.offset(i as isize)).advw as i32 <= (*g).emsize as i32 {
-            let ref mut fresh8 = *w_stat.offset((*(*g).gd.offset(i as isize)).advw as isize);
-            *fresh8 = (*fresh8 as i32 + 1i32) as u16
-        } else {
-            let ref mut fresh9 = *w_stat.offset(((*g).emsize as i32 + 1i32) as isize);
-            *fresh9 = (*fresh9 as i32 + 1i32) as u16
-            /* larger than em */
-        }
         if !(len == 0_u32) {
             if len < 10_u32 {
                 panic!("Invalid TrueType glyph data (gid {}).", gid);
@@ -739,6 +790,19 @@ pub(crate) unsafe fn tt_get_metrics(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
             (*(*g).gd.offset(i as isize)).urx = tt_get_signed_pair(handle);
             (*(*g).gd.offset(i as isize)).ury = tt_get_signed_pair(handle);
             /* _FIXME_ */
+            if !(*sfont).instance.is_null() {
+                /* we need the whole outline to find the instance's metrics */
+                let gd = &mut *(*g).gd.offset(i as isize);
+                gd.data = new(len) as *mut u8;
+                handle
+                    .seek(SeekFrom::Start(offset as u64 + loc as u64))
+                    .unwrap();
+                ttstub_input_read(handle.as_ptr(), gd.data as *mut i8, len as size_t);
+                tt_apply_instance(sfont, gd);
+                free(gd.data as *mut libc::c_void);
+                gd.data = ptr::null_mut();
+                gd.length = len;
+            }
             if vmtx.is_null() {
                 /* vertOriginY == sTypeAscender */
                 (*(*g).gd.offset(i as isize)).tsb = ((*g).default_advh as i32
@@ -746,8 +810,18 @@ pub(crate) unsafe fn tt_get_metrics(sfont: *mut sfnt, mut g: *mut tt_glyphs) ->
                     - (*(*g).gd.offset(i as isize)).ury as i32)
                     as i16
             }
+        } else {
+            /* Does not contains any data, but the advance may still vary. */
+            tt_apply_instance(sfont, &mut *(*g).gd.offset(i as isize));
+        }
+        if (*(*g).gd.offset(i as isize)).advw as i32 <= (*g).emsize as i32 {
+            let ref mut fresh8 = *w_stat.offset((*(*g).gd.offset(i as isize)).advw as isize);
+            *fresh8 = (*fresh8 as i32 + 1i32) as u16
+        } else {
+            let ref mut fresh9 = *w_stat.offset(((*g).emsize as i32 + 1i32) as isize);
+            *fresh9 = (*fresh9 as i32 + 1i32) as u16
+            /* larger than em */
         }
-        /* Does not contains any data. */
     }
     free(location as *mut libc::c_void);
     free(hmtx as *mut libc::c_void);
@@ -0,0 +1,952 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2020 the Tectonic Project
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_camel_case_types)]
+
+//! Instancing of OpenType variable fonts.
+//!
+//! PDF has no notion of font variations, so when XeTeX has selected a named
+//! or custom instance of a variable font we have to bake the design
+//! coordinates into the glyph outlines and metrics before embedding. This
+//! module reads `fvar`, `avar`, `gvar` and `HVAR` and applies the deltas to
+//! individual `glyf` entries as `tt_build_tables` copies them. CFF2 outlines
+//! are instanced by `dpx_cff2` using the coordinates and item variation
+//! store from here. The variation tables themselves are never copied into
+//! the embedded font.
+
+use crate::warn;
+
+use super::dpx_sfnt::{sfnt, sfnt_find_table_len, sfnt_find_table_pos};
+
+use std::io::{Read, Seek, SeekFrom};
+
+fn get_u16(data: &[u8], pos: usize) -> Option<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| (b[0] as u16) << 8 | b[1] as u16)
+}
+
+fn get_i16(data: &[u8], pos: usize) -> Option<i16> {
+    get_u16(data, pos).map(|v| v as i16)
+}
+
+fn get_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+}
+
+fn get_f2dot14(data: &[u8], pos: usize) -> Option<f64> {
+    get_i16(data, pos).map(|v| v as f64 / 16384.0)
+}
+
+fn get_fixed(data: &[u8], pos: usize) -> Option<f64> {
+    get_u32(data, pos).map(|v| v as i32 as f64 / 65536.0)
+}
+
+/// One `fvar` axis record.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct tt_var_axis {
+    pub(crate) tag: u32,
+    pub(crate) minimum: f64,
+    pub(crate) default: f64,
+    pub(crate) maximum: f64,
+}
+
+fn read_fvar(fvar: &[u8]) -> Option<Vec<tt_var_axis>> {
+    let axes_offset = get_u16(fvar, 4)? as usize;
+    let axis_count = get_u16(fvar, 8)? as usize;
+    let axis_size = get_u16(fvar, 10)? as usize;
+    (0..axis_count)
+        .map(|i| {
+            let pos = axes_offset + i * axis_size;
+            Some(tt_var_axis {
+                tag: get_u32(fvar, pos)?,
+                minimum: get_fixed(fvar, pos + 4)?,
+                default: get_fixed(fvar, pos + 8)?,
+                maximum: get_fixed(fvar, pos + 12)?,
+            })
+        })
+        .collect()
+}
+
+/// Map a user-space coordinate to the normalized [-1, 1] range, without
+/// `avar` adjustment.
+pub(crate) fn normalize_coord(axis: &tt_var_axis, value: f64) -> f64 {
+    let value = value.max(axis.minimum).min(axis.maximum);
+    let n = if value < axis.default {
+        -(axis.default - value) / (axis.default - axis.minimum)
+    } else if value > axis.default {
+        (value - axis.default) / (axis.maximum - axis.default)
+    } else {
+        0.0
+    };
+    /* coordinates are F2Dot14 values from here on */
+    (n * 16384.0).round() / 16384.0
+}
+
+/// Apply the `avar` segment maps to normalized coordinates in place.
+fn apply_avar(avar: &[u8], coords: &mut [f64]) -> Option<()> {
+    let axis_count = get_u16(avar, 6)? as usize;
+    let mut pos = 8;
+    for coord in coords.iter_mut().take(axis_count) {
+        let count = get_u16(avar, pos)? as usize;
+        pos += 2;
+        let map = (0..count)
+            .map(|i| {
+                Some((
+                    get_f2dot14(avar, pos + 4 * i)?,
+                    get_f2dot14(avar, pos + 4 * i + 2)?,
+                ))
+            })
+            .collect::<Option<Vec<(f64, f64)>>>()?;
+        pos += 4 * count;
+        if let Some(j) = map.iter().position(|&(from, _)| *coord <= from) {
+            let (from, to) = map[j];
+            if j == 0 || *coord == from {
+                *coord = to;
+            } else {
+                let (pfrom, pto) = map[j - 1];
+                *coord = pto + (to - pto) * (*coord - pfrom) / (from - pfrom);
+            }
+            *coord = (*coord * 16384.0).round() / 16384.0;
+        }
+    }
+    Some(())
+}
+
+/// The scalar for a tuple variation (or variation region) with the given
+/// peak and optional intermediate start/end coordinates.
+pub(crate) fn tuple_scalar(
+    coords: &[f64],
+    peak: &[f64],
+    intermediate: Option<(&[f64], &[f64])>,
+) -> f64 {
+    let mut scalar = 1.0;
+    for (i, &p) in peak.iter().enumerate() {
+        let v = coords.get(i).cloned().unwrap_or(0.0);
+        if p == 0.0 {
+            continue;
+        }
+        if v == 0.0 {
+            return 0.0;
+        }
+        let (start, end) = match intermediate {
+            Some((start, end)) => (start[i], end[i]),
+            None => (p.min(0.0), p.max(0.0)),
+        };
+        if start > p || p > end || (start < 0.0 && end > 0.0) {
+            continue;
+        }
+        if v < start || v > end {
+            return 0.0;
+        }
+        if v < p {
+            scalar *= (v - start) / (p - start);
+        } else if v > p {
+            scalar *= (end - v) / (end - p);
+        }
+    }
+    scalar
+}
+
+/// An `ItemVariationStore`, as used by `HVAR` and `CFF2`.
+pub(crate) struct tt_item_variation_store<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> tt_item_variation_store<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        tt_item_variation_store { data }
+    }
+
+    fn region_scalar(&self, coords: &[f64], region: usize) -> Option<f64> {
+        let list = get_u32(self.data, 2)? as usize;
+        let axis_count = get_u16(self.data, list)? as usize;
+        let pos = list + 4 + region * axis_count * 6;
+        let mut start = Vec::with_capacity(axis_count);
+        let mut peak = Vec::with_capacity(axis_count);
+        let mut end = Vec::with_capacity(axis_count);
+        for i in 0..axis_count {
+            start.push(get_f2dot14(self.data, pos + 6 * i)?);
+            peak.push(get_f2dot14(self.data, pos + 6 * i + 2)?);
+            end.push(get_f2dot14(self.data, pos + 6 * i + 4)?);
+        }
+        Some(tuple_scalar(coords, &peak, Some((&start, &end))))
+    }
+
+    /// The scalars of the regions used by one `ItemVariationData`, in the
+    /// order its deltas are stored. CFF2 `blend` operators need these.
+    pub(crate) fn region_scalars(&self, coords: &[f64], outer: u16) -> Option<Vec<f64>> {
+        let count = get_u16(self.data, 6)?;
+        if outer >= count {
+            return None;
+        }
+        let pos = get_u32(self.data, 8 + 4 * outer as usize)? as usize;
+        let region_count = get_u16(self.data, pos + 4)? as usize;
+        (0..region_count)
+            .map(|r| {
+                let region = get_u16(self.data, pos + 6 + 2 * r)? as usize;
+                self.region_scalar(coords, region)
+            })
+            .collect()
+    }
+
+    /// The interpolated delta for an (outer, inner) index pair.
+    pub(crate) fn delta(&self, coords: &[f64], outer: u16, inner: u16) -> Option<f64> {
+        let count = get_u16(self.data, 6)?;
+        if outer >= count {
+            return None;
+        }
+        let pos = get_u32(self.data, 8 + 4 * outer as usize)? as usize;
+        let item_count = get_u16(self.data, pos)?;
+        let word_delta_count = get_u16(self.data, pos + 2)?;
+        let region_count = get_u16(self.data, pos + 4)? as usize;
+        if inner >= item_count {
+            return None;
+        }
+        let long_words = word_delta_count & 0x8000 != 0;
+        let word_count = (word_delta_count & 0x7fff) as usize;
+        let (word_size, short_size) = if long_words { (4, 2) } else { (2, 1) };
+        let row_size =
+            word_count * word_size + region_count.saturating_sub(word_count) * short_size;
+        let mut p = pos + 6 + 2 * region_count + inner as usize * row_size;
+        let mut delta = 0.0;
+        for r in 0..region_count {
+            let region = get_u16(self.data, pos + 6 + 2 * r)? as usize;
+            let d = match (r < word_count, long_words) {
+                (true, true) => get_u32(self.data, p)? as i32 as f64,
+                (true, false) | (false, true) => get_i16(self.data, p)? as f64,
+                (false, false) => *self.data.get(p)? as i8 as f64,
+            };
+            p += if r < word_count {
+                word_size
+            } else {
+                short_size
+            };
+            if d != 0.0 {
+                delta += d * self.region_scalar(coords, region)?;
+            }
+        }
+        Some(delta)
+    }
+}
+
+/// Look up a glyph in a `DeltaSetIndexMap`, returning its (outer, inner)
+/// indices.
+fn delta_set_index(map: &[u8], gid: u16) -> Option<(u16, u16)> {
+    let format = *map.get(0)?;
+    let entry_format = *map.get(1)?;
+    let (count, mut pos) = if format == 0 {
+        (get_u16(map, 2)? as usize, 4)
+    } else {
+        (get_u32(map, 2)? as usize, 6)
+    };
+    if count == 0 {
+        return None;
+    }
+    let size = ((entry_format >> 4) & 3) as usize + 1;
+    let inner_bits = (entry_format & 0xf) as u32 + 1;
+    pos += size * (gid as usize).min(count - 1);
+    let mut entry = 0u32;
+    for b in map.get(pos..pos + size)? {
+        entry = entry << 8 | *b as u32;
+    }
+    Some((
+        (entry >> inner_bits) as u16,
+        (entry & ((1 << inner_bits) - 1)) as u16,
+    ))
+}
+
+/// Decode packed point numbers, returning `None` for "all points" and the
+/// number of bytes consumed.
+fn read_packed_points(data: &[u8], mut pos: usize) -> Option<(Option<Vec<u16>>, usize)> {
+    let start = pos;
+    let mut count = *data.get(pos)? as usize;
+    pos += 1;
+    if count == 0 {
+        return Some((None, 1));
+    }
+    if count & 0x80 != 0 {
+        count = (count & 0x7f) << 8 | *data.get(pos)? as usize;
+        pos += 1;
+    }
+    let mut points = Vec::with_capacity(count);
+    let mut last = 0u16;
+    while points.len() < count {
+        let control = *data.get(pos)?;
+        pos += 1;
+        let run = (control & 0x7f) as usize + 1;
+        for _ in 0..run {
+            let d = if control & 0x80 != 0 {
+                pos += 2;
+                get_u16(data, pos - 2)?
+            } else {
+                pos += 1;
+                *data.get(pos - 1)? as u16
+            };
+            last = last.wrapping_add(d);
+            points.push(last);
+        }
+    }
+    points.truncate(count);
+    Some((Some(points), pos - start))
+}
+
+/// Decode `count` packed deltas, returning them and the bytes consumed.
+fn read_packed_deltas(data: &[u8], mut pos: usize, count: usize) -> Option<(Vec<i32>, usize)> {
+    let start = pos;
+    let mut deltas = Vec::with_capacity(count);
+    while deltas.len() < count {
+        let control = *data.get(pos)?;
+        pos += 1;
+        let run = (control & 0x3f) as usize + 1;
+        for _ in 0..run {
+            if control & 0x80 != 0 {
+                deltas.push(0);
+            } else if control & 0x40 != 0 {
+                deltas.push(get_i16(data, pos)? as i32);
+                pos += 2;
+            } else {
+                deltas.push(*data.get(pos)? as i8 as i32);
+                pos += 1;
+            }
+        }
+    }
+    deltas.truncate(count);
+    Some((deltas, pos - start))
+}
+
+/// Infer deltas for the points of one contour that weren't given explicit
+/// ones ("interpolate untouched points").
+fn iup_contour(orig: &[(f64, f64)], deltas: &mut [(f64, f64)], touched: &[bool]) {
+    let n = orig.len();
+    let refs: Vec<usize> = (0..n).filter(|&i| touched[i]).collect();
+    if refs.is_empty() || refs.len() == n {
+        return;
+    }
+    if refs.len() == 1 {
+        let d = deltas[refs[0]];
+        for (i, delta) in deltas.iter_mut().enumerate() {
+            if !touched[i] {
+                *delta = d;
+            }
+        }
+        return;
+    }
+    for (k, &r1) in refs.iter().enumerate() {
+        let r2 = refs[(k + 1) % refs.len()];
+        let mut i = (r1 + 1) % n;
+        while i != r2 {
+            let (x, y) = iup_point(orig[i], orig[r1], orig[r2], deltas[r1], deltas[r2]);
+            deltas[i] = (x, y);
+            i = (i + 1) % n;
+        }
+    }
+}
+
+fn iup_point(
+    p: (f64, f64),
+    p1: (f64, f64),
+    p2: (f64, f64),
+    d1: (f64, f64),
+    d2: (f64, f64),
+) -> (f64, f64) {
+    fn axis(v: f64, a: f64, b: f64, da: f64, db: f64) -> f64 {
+        let (lo, hi, dlo, dhi) = if a <= b {
+            (a, b, da, db)
+        } else {
+            (b, a, db, da)
+        };
+        if lo == hi {
+            if da == db {
+                da
+            } else {
+                0.0
+            }
+        } else if v <= lo {
+            dlo
+        } else if v >= hi {
+            dhi
+        } else {
+            dlo + (v - lo) * (dhi - dlo) / (hi - lo)
+        }
+    }
+    (
+        axis(p.0, p1.0, p2.0, d1.0, d2.0),
+        axis(p.1, p1.1, p2.1, d1.1, d2.1),
+    )
+}
+
+/// A decoded simple glyph.
+struct tt_simple_glyph {
+    end_points: Vec<u16>,
+    flags: Vec<u8>,
+    points: Vec<(f64, f64)>,
+}
+
+fn parse_simple_glyph(data: &[u8], num_contours: usize) -> Option<tt_simple_glyph> {
+    let mut pos = 10;
+    let mut end_points = Vec::with_capacity(num_contours);
+    for _ in 0..num_contours {
+        end_points.push(get_u16(data, pos)?);
+        pos += 2;
+    }
+    let num_points = end_points.last().map_or(0, |&e| e as usize + 1);
+    let instruction_length = get_u16(data, pos)? as usize;
+    pos += 2 + instruction_length;
+    let mut flags = Vec::with_capacity(num_points);
+    while flags.len() < num_points {
+        let flag = *data.get(pos)?;
+        pos += 1;
+        flags.push(flag);
+        if flag & 0x08 != 0 {
+            let repeat = *data.get(pos)?;
+            pos += 1;
+            for _ in 0..repeat {
+                flags.push(flag);
+            }
+        }
+    }
+    flags.truncate(num_points);
+    let mut points = vec![(0.0, 0.0); num_points];
+    let mut v = 0i32;
+    for (i, &flag) in flags.iter().enumerate() {
+        if flag & 0x02 != 0 {
+            let d = *data.get(pos)? as i32;
+            pos += 1;
+            v += if flag & 0x10 != 0 { d } else { -d };
+        } else if flag & 0x10 == 0 {
+            v += get_i16(data, pos)? as i32;
+            pos += 2;
+        }
+        points[i].0 = v as f64;
+    }
+    v = 0;
+    for (i, &flag) in flags.iter().enumerate() {
+        if flag & 0x04 != 0 {
+            let d = *data.get(pos)? as i32;
+            pos += 1;
+            v += if flag & 0x20 != 0 { d } else { -d };
+        } else if flag & 0x20 == 0 {
+            v += get_i16(data, pos)? as i32;
+            pos += 2;
+        }
+        points[i].1 = v as f64;
+    }
+    Some(tt_simple_glyph {
+        end_points,
+        flags,
+        points,
+    })
+}
+
+/// Encode a simple glyph, without instructions. Hinting is meaningless for
+/// an instance the instructions weren't written for.
+fn encode_simple_glyph(glyph: &tt_simple_glyph, points: &[(i32, i32)]) -> Vec<u8> {
+    let (mut xmin, mut ymin, mut xmax, mut ymax) = (0, 0, 0, 0);
+    if let Some(&(x, y)) = points.first() {
+        xmin = x;
+        xmax = x;
+        ymin = y;
+        ymax = y;
+    }
+    for &(x, y) in points {
+        xmin = xmin.min(x);
+        xmax = xmax.max(x);
+        ymin = ymin.min(y);
+        ymax = ymax.max(y);
+    }
+    let mut out = Vec::new();
+    for v in &[glyph.end_points.len() as i32, xmin, ymin, xmax, ymax] {
+        out.extend_from_slice(&(*v as i16).to_be_bytes());
+    }
+    for e in &glyph.end_points {
+        out.extend_from_slice(&e.to_be_bytes());
+    }
+    out.extend_from_slice(&[0, 0]);
+    encode_glyph_points(&glyph.flags, points, &mut out);
+    out
+}
+
+/// Append the flags and coordinates of a simple glyph's points, with only
+/// the on-curve and overlap bits taken from `glyph_flags`.
+pub(crate) fn encode_glyph_points(glyph_flags: &[u8], points: &[(i32, i32)], out: &mut Vec<u8>) {
+    let mut flags = Vec::with_capacity(points.len());
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    let (mut px, mut py) = (0, 0);
+    for (i, &(x, y)) in points.iter().enumerate() {
+        /* keep on-curve and overlap bits */
+        let mut flag = glyph_flags[i] & 0x41;
+        let (dx, dy) = (x - px, y - py);
+        if dx == 0 {
+            flag |= 0x10;
+        } else if dx.abs() < 256 {
+            flag |= 0x02 | if dx > 0 { 0x10 } else { 0 };
+            xs.push(dx.abs() as u8);
+        } else {
+            xs.extend_from_slice(&(dx as i16).to_be_bytes());
+        }
+        if dy == 0 {
+            flag |= 0x20;
+        } else if dy.abs() < 256 {
+            flag |= 0x04 | if dy > 0 { 0x20 } else { 0 };
+            ys.push(dy.abs() as u8);
+        } else {
+            ys.extend_from_slice(&(dy as i16).to_be_bytes());
+        }
+        flags.push(flag);
+        px = x;
+        py = y;
+    }
+    let mut i = 0;
+    while i < flags.len() {
+        let mut run = 0;
+        while i + run + 1 < flags.len() && flags[i + run + 1] == flags[i] && run < 255 {
+            run += 1;
+        }
+        if run > 0 {
+            out.push(flags[i] | 0x08);
+            out.push(run as u8);
+        } else {
+            out.push(flags[i]);
+        }
+        i += run + 1;
+    }
+    out.extend_from_slice(&xs);
+    out.extend_from_slice(&ys);
+}
+
+/// The design coordinates of a variable font instance, and the variation
+/// tables needed to apply them.
+pub(crate) struct tt_var_instance {
+    coords: Vec<f64>,
+    gvar: Option<Vec<u8>>,
+    hvar: Option<Vec<u8>>,
+}
+
+pub(crate) unsafe fn read_table(sfont: *mut sfnt, tag: &[u8; 4]) -> Option<Vec<u8>> {
+    let pos = sfnt_find_table_pos(sfont, tag);
+    let len = sfnt_find_table_len(sfont, tag);
+    if pos == 0 || len == 0 {
+        return None;
+    }
+    let handle = &mut (*sfont).handle;
+    handle.seek(SeekFrom::Start(pos as u64)).ok()?;
+    let mut data = vec![0u8; len as usize];
+    handle.read_exact(&mut data).ok()?;
+    Some(data)
+}
+
+/// Prepare to instance the font at the given design coordinates. Returns
+/// `None` if the font isn't a variable font, in which case the settings are
+/// ignored, as HarfBuzz does when shaping.
+pub(crate) unsafe fn tt_var_instance_load(
+    sfont: *mut sfnt,
+    variations: &[(u32, f64)],
+) -> Option<Box<tt_var_instance>> {
+    let axes = read_table(sfont, b"fvar").and_then(|fvar| read_fvar(&fvar))?;
+    let mut coords: Vec<f64> = axes
+        .iter()
+        .map(|axis| {
+            variations
+                .iter()
+                .rev()
+                .find(|v| v.0 == axis.tag)
+                .map_or(0.0, |v| normalize_coord(axis, v.1))
+        })
+        .collect();
+    if let Some(avar) = read_table(sfont, b"avar") {
+        if apply_avar(&avar, &mut coords).is_none() {
+            warn!("Invalid avar table in variable font; ignoring it.");
+        }
+    }
+    Some(Box::new(tt_var_instance {
+        coords,
+        gvar: read_table(sfont, b"gvar"),
+        hvar: read_table(sfont, b"HVAR"),
+    }))
+}
+
+impl tt_var_instance {
+    /// The normalized design coordinates, one per `fvar` axis.
+    pub(crate) fn coords(&self) -> &[f64] {
+        &self.coords
+    }
+
+    /// The advance width of a glyph at this instance, for fonts whose
+    /// outlines are instanced elsewhere (CFF2). Only `HVAR` is consulted.
+    pub(crate) fn instance_advance(&self, gid: u16, advance: u16) -> u16 {
+        match self.hvar_advance_delta(gid) {
+            Some(delta) => (advance as f64 + delta).round().max(0.0) as u16,
+            None => advance,
+        }
+    }
+
+    /// Advance width delta from `HVAR`, if the font has one.
+    fn hvar_advance_delta(&self, gid: u16) -> Option<f64> {
+        let hvar = self.hvar.as_ref()?;
+        let store = get_u32(hvar, 4)? as usize;
+        let map = get_u32(hvar, 8)? as usize;
+        let (outer, inner) = if map == 0 {
+            (0, gid)
+        } else {
+            delta_set_index(hvar.get(map..)?, gid)?
+        };
+        tt_item_variation_store::new(hvar.get(store..)?).delta(&self.coords, outer, inner)
+    }
+
+    /// Accumulated `gvar` deltas for a glyph with `orig` points (including
+    /// the four phantom points at the end). `contours` gives the end points
+    /// of the outline's contours, for inferring untouched deltas; it is
+    /// empty for composite glyphs, where untouched points don't move.
+    fn gvar_deltas(
+        &self,
+        gid: u16,
+        orig: &[(f64, f64)],
+        contours: &[u16],
+    ) -> Option<Vec<(f64, f64)>> {
+        let gvar = self.gvar.as_ref()?;
+        let axis_count = get_u16(gvar, 4)? as usize;
+        let shared_count = get_u16(gvar, 6)? as usize;
+        let shared_offset = get_u32(gvar, 8)? as usize;
+        let glyph_count = get_u16(gvar, 12)?;
+        let flags = get_u16(gvar, 14)?;
+        let array_offset = get_u32(gvar, 16)? as usize;
+        if gid >= glyph_count {
+            return None;
+        }
+        let (start, end) = if flags & 1 != 0 {
+            (
+                get_u32(gvar, 20 + 4 * gid as usize)? as usize,
+                get_u32(gvar, 24 + 4 * gid as usize)? as usize,
+            )
+        } else {
+            (
+                2 * get_u16(gvar, 20 + 2 * gid as usize)? as usize,
+                2 * get_u16(gvar, 22 + 2 * gid as usize)? as usize,
+            )
+        };
+        let mut total = vec![(0.0, 0.0); orig.len()];
+        if end <= start {
+            return Some(total);
+        }
+        let data = gvar.get(array_offset + start..array_offset + end)?;
+        let read_tuple = |pos: usize| -> Option<Vec<f64>> {
+            (0..axis_count)
+                .map(|i| get_f2dot14(data, pos + 2 * i))
+                .collect()
+        };
+        let tuple_count = get_u16(data, 0)?;
+        let mut serialized = get_u16(data, 2)? as usize;
+        let mut shared_points = None;
+        if tuple_count & 0x8000 != 0 {
+            let (points, size) = read_packed_points(data, serialized)?;
+            shared_points = Some(points);
+            serialized += size;
+        }
+        let mut header = 4;
+        for _ in 0..(tuple_count & 0x0fff) {
+            let size = get_u16(data, header)? as usize;
+            let index = get_u16(data, header + 2)?;
+            header += 4;
+            let peak = if index & 0x8000 != 0 {
+                header += 2 * axis_count;
+                read_tuple(header - 2 * axis_count)?
+            } else {
+                let i = (index & 0x0fff) as usize;
+                if i >= shared_count {
+                    return None;
+                }
+                (0..axis_count)
+                    .map(|a| get_f2dot14(gvar, shared_offset + 2 * (i * axis_count + a)))
+                    .collect::<Option<Vec<f64>>>()?
+            };
+            let intermediate = if index & 0x4000 != 0 {
+                header += 4 * axis_count;
+                Some((
+                    read_tuple(header - 4 * axis_count)?,
+                    read_tuple(header - 2 * axis_count)?,
+                ))
+            } else {
+                None
+            };
+            let this = serialized;
+            serialized += size;
+            let scalar = tuple_scalar(
+                &self.coords,
+                &peak,
+                intermediate.as_ref().map(|(s, e)| (&s[..], &e[..])),
+            );
+            if scalar == 0.0 {
+                continue;
+            }
+            let mut pos = this;
+            let points = if index & 0x2000 != 0 {
+                let (points, size) = read_packed_points(data, pos)?;
+                pos += size;
+                points
+            } else {
+                shared_points.clone()?
+            };
+            let count = points.as_ref().map_or(orig.len(), |p| p.len());
+            let (xs, size) = read_packed_deltas(data, pos, count)?;
+            let (ys, _) = read_packed_deltas(data, pos + size, count)?;
+            let mut deltas = vec![(0.0, 0.0); orig.len()];
+            let mut touched = vec![false; orig.len()];
+            for k in 0..count {
+                let p = points.as_ref().map_or(k, |p| p[k] as usize);
+                if p < orig.len() {
+                    deltas[p] = (xs[k] as f64, ys[k] as f64);
+                    touched[p] = true;
+                }
+            }
+            if points.is_some() {
+                let mut first = 0;
+                for &e in contours {
+                    let last = e as usize + 1;
+                    if last > orig.len() || last <= first {
+                        break;
+                    }
+                    iup_contour(
+                        &orig[first..last],
+                        &mut deltas[first..last],
+                        &touched[first..last],
+                    );
+                    first = last;
+                }
+            }
+            for (t, d) in total.iter_mut().zip(deltas) {
+                t.0 += d.0 * scalar;
+                t.1 += d.1 * scalar;
+            }
+        }
+        Some(total)
+    }
+
+    /// Instance one glyph. `data` is its original `glyf` entry (possibly
+    /// empty), `advance` and `lsb` its `hmtx` metrics. Returns the new
+    /// `glyf` entry with the advance width and left side bearing to go with
+    /// it. Composite glyphs keep their original bounding box.
+    pub(crate) fn instance_glyph(
+        &self,
+        gid: u16,
+        data: &[u8],
+        advance: u16,
+        lsb: i16,
+    ) -> (Vec<u8>, u16, i16) {
+        let num_contours = get_i16(data, 0).unwrap_or(0);
+        let xmin = get_i16(data, 2).unwrap_or(0) as f64;
+        let simple = if num_contours > 0 {
+            parse_simple_glyph(data, num_contours as usize)
+        } else {
+            None
+        };
+        let mut orig: Vec<(f64, f64)> = match (&simple, num_contours < 0) {
+            (Some(glyph), _) => glyph.points.clone(),
+            (None, true) => composite_offsets(data)
+                .into_iter()
+                .map(|(_, x, y)| (x, y))
+                .collect(),
+            (None, false) => Vec::new(),
+        };
+        let n = orig.len();
+        let pp1 = xmin - lsb as f64;
+        orig.push((pp1, 0.0));
+        orig.push((pp1 + advance as f64, 0.0));
+        orig.push((0.0, 0.0));
+        orig.push((0.0, 0.0));
+        let contours = simple.as_ref().map_or(&[][..], |g| &g.end_points[..]);
+        let deltas = match self.gvar_deltas(gid, &orig, contours) {
+            Some(deltas) => deltas,
+            None => vec![(0.0, 0.0); orig.len()],
+        };
+        /* the instance's origin is wherever the first phantom point moved to */
+        let shift = deltas[n].0;
+        let advance_delta = self
+            .hvar_advance_delta(gid)
+            .unwrap_or(deltas[n + 1].0 - deltas[n].0);
+        let new_advance = (advance as f64 + advance_delta).round().max(0.0) as u16;
+        let moved: Vec<(i32, i32)> = orig[..n]
+            .iter()
+            .zip(&deltas)
+            .map(|(p, d)| {
+                (
+                    (p.0 + d.0 - shift).round() as i32,
+                    (p.1 + d.1).round() as i32,
+                )
+            })
+            .collect();
+        match simple {
+            Some(glyph) => {
+                let out = encode_simple_glyph(&glyph, &moved);
+                let new_lsb = get_i16(&out, 2).unwrap_or(0);
+                (out, new_advance, new_lsb)
+            }
+            None if num_contours < 0 => {
+                let out = offset_composite(data, &moved);
+                (out, new_advance, lsb)
+            }
+            None => (data.to_vec(), new_advance, lsb),
+        }
+    }
+}
+
+/// The position of each component record of a composite glyph, with its
+/// x/y offset if it is positioned by offset rather than by point matching.
+fn composite_offsets(data: &[u8]) -> Vec<(usize, f64, f64)> {
+    let mut out = Vec::new();
+    let mut pos = 10;
+    loop {
+        let flags = match get_u16(data, pos) {
+            Some(f) => f,
+            None => break,
+        };
+        let (x, y) = if flags & 0x0002 == 0 {
+            (0.0, 0.0)
+        } else if flags & 0x0001 != 0 {
+            (
+                get_i16(data, pos + 4).unwrap_or(0) as f64,
+                get_i16(data, pos + 6).unwrap_or(0) as f64,
+            )
+        } else {
+            (
+                *data.get(pos + 4).unwrap_or(&0) as i8 as f64,
+                *data.get(pos + 5).unwrap_or(&0) as i8 as f64,
+            )
+        };
+        out.push((pos, x, y));
+        pos += 4 + if flags & 0x0001 != 0 { 4 } else { 2 };
+        if flags & 0x0008 != 0 {
+            pos += 2;
+        } else if flags & 0x0040 != 0 {
+            pos += 4;
+        } else if flags & 0x0080 != 0 {
+            pos += 8;
+        }
+        if flags & 0x0020 == 0 {
+            break;
+        }
+    }
+    out
+}
+
+/// Rewrite the component offsets of a composite glyph, always using word
+/// arguments so that the new offsets are guaranteed to fit.
+fn offset_composite(data: &[u8], offsets: &[(i32, i32)]) -> Vec<u8> {
+    let records = composite_offsets(data);
+    let mut out = data[..10].to_vec();
+    for (k, &(pos, _, _)) in records.iter().enumerate() {
+        let flags = get_u16(data, pos).unwrap_or(0);
+        let args = if flags & 0x0001 != 0 { 4 } else { 2 };
+        let next = records.get(k + 1).map_or_else(
+            || {
+                let mut end = pos + 4 + args;
+                if flags & 0x0008 != 0 {
+                    end += 2;
+                } else if flags & 0x0040 != 0 {
+                    end += 4;
+                } else if flags & 0x0080 != 0 {
+                    end += 8;
+                }
+                end
+            },
+            |r| r.0,
+        );
+        if flags & 0x0002 != 0 {
+            let (x, y) = offsets.get(k).cloned().unwrap_or((0, 0));
+            out.extend_from_slice(&(flags | 0x0001).to_be_bytes());
+            out.extend_from_slice(&data[pos + 2..pos + 4]);
+            out.extend_from_slice(&(x as i16).to_be_bytes());
+            out.extend_from_slice(&(y as i16).to_be_bytes());
+            out.extend_from_slice(&data[pos + 4 + args..next.min(data.len())]);
+        } else {
+            out.extend_from_slice(&data[pos..next.min(data.len())]);
+        }
+        if k + 1 == records.len() {
+            /* trailing instructions, if any */
+            out.extend_from_slice(&data[next.min(data.len())..]);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalization() {
+        let wght = tt_var_axis {
+            tag: u32::from_be_bytes(*b"wght"),
+            minimum: 100.0,
+            default: 400.0,
+            maximum: 900.0,
+        };
+        assert_eq!(normalize_coord(&wght, 400.0), 0.0);
+        assert_eq!(normalize_coord(&wght, 100.0), -1.0);
+        assert_eq!(normalize_coord(&wght, 650.0), 0.5);
+        assert_eq!(normalize_coord(&wght, 2000.0), 1.0);
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(tuple_scalar(&[0.5], &[1.0], None), 0.5);
+        assert_eq!(tuple_scalar(&[-0.5], &[1.0], None), 0.0);
+        assert_eq!(tuple_scalar(&[0.5, 0.0], &[1.0, 1.0], None), 0.0);
+        let (start, end) = ([0.0], [1.0]);
+        assert_eq!(tuple_scalar(&[0.75], &[0.5], Some((&start, &end))), 0.5);
+    }
+
+    #[test]
+    fn packed_data() {
+        /* three points: 1, 3, 4 */
+        let (points, size) = read_packed_points(&[3, 2, 1, 2, 1], 0).unwrap();
+        assert_eq!(points, Some(vec![1, 3, 4]));
+        assert_eq!(size, 5);
+        assert_eq!(read_packed_points(&[0], 0).unwrap(), (None, 1));
+        /* two byte deltas, then three zeroes, then one word */
+        let (deltas, size) =
+            read_packed_deltas(&[0x01, 0xff, 0x05, 0x82, 0x40, 0x01, 0x00], 0, 6).unwrap();
+        assert_eq!(deltas, vec![-1, 5, 0, 0, 0, 256]);
+        assert_eq!(size, 7);
+    }
+
+    #[test]
+    fn untouched_points() {
+        let orig = [(0.0, 0.0), (50.0, 50.0), (100.0, 100.0), (150.0, 150.0)];
+        let mut deltas = [(0.0, 0.0), (0.0, 0.0), (10.0, 4.0), (0.0, 0.0)];
+        iup_contour(&orig, &mut deltas, &[true, false, true, false]);
+        assert_eq!(deltas[1], (5.0, 2.0));
+        /* beyond the range of the reference points: take the nearer delta */
+        assert_eq!(deltas[3], (10.0, 4.0));
+    }
+
+    #[test]
+    fn simple_glyph_roundtrip() {
+        let glyph = tt_simple_glyph {
+            end_points: vec![2],
+            flags: vec![1, 1, 1],
+            points: vec![(0.0, 0.0), (300.0, 0.0), (150.0, 700.0)],
+        };
+        let points = [(0, 0), (300, 0), (150, 700)];
+        let data = encode_simple_glyph(&glyph, &points);
+        let back = parse_simple_glyph(&data, 1).unwrap();
+        assert_eq!(back.points, glyph.points);
+        assert_eq!(get_i16(&data, 8), Some(700));
+    }
+}
@@ -37,6 +37,7 @@ use super::dpx_cid::{
 };
 use super::dpx_cmap::{CMap_cache_get, CMap_get_CIDSysInfo, CMap_get_wmode, CMap_is_Identity};
 use super::dpx_mem::new;
+use super::dpx_pdfa::pdf_get_pdfa;
 use super::dpx_pdfencoding::pdf_load_ToUnicode_stream;
 use super::dpx_pdfresource::{pdf_defineresource, pdf_findresource, pdf_get_resource_reference};
 use super::dpx_tt_cmap::otf_create_ToUnicode_stream;
@@ -152,8 +153,11 @@ unsafe fn add_ToUnicode(font: *mut Type0Font) {
         panic!("{}: No descendant CID-keyed font.", "Type0",);
     }
     if CIDFont_is_ACCFont(&mut *cidfont) {
-        /* No need to embed ToUnicode */
-        return;
+        /* No need to embed ToUnicode, except that PDF/A wants one for every
+         * font: fall through to the Registry-Ordering-UCS2 lookup below. */
+        if pdf_get_pdfa().is_none() {
+            return;
+        }
     } else {
         if CIDFont_is_UCSFont(cidfont) {
             /*
@@ -33,6 +33,7 @@ use crate::{info, warn};
 use std::ffi::{CStr, CString};
 use std::ptr;
 
+use super::dpx_agl::{agl_close_map, agl_init_map, agl_name_to_unicode};
 use super::dpx_cff::{
     cff_add_string, cff_close, cff_get_seac_sid, cff_glyph_lookup, cff_index_size, cff_new_index,
     cff_pack_charsets, cff_pack_encoding, cff_pack_index, cff_put_header, cff_release_charsets,
@@ -41,7 +42,9 @@ use super::dpx_cff::{
 use super::dpx_cff_dict::{
     cff_dict_add, cff_dict_get, cff_dict_known, cff_dict_pack, cff_dict_set, cff_dict_update,
 };
+use super::dpx_error::QuietScope;
 use super::dpx_mem::{new, renew};
+use super::dpx_pdfa::pdf_get_pdfa;
 use super::dpx_pdfencoding::{pdf_create_ToUnicode_CMap, pdf_encoding_get_encoding};
 use super::dpx_pdffont::{
     pdf_font, pdf_font_get_descriptor, pdf_font_get_encoding, pdf_font_get_resource,
@@ -128,6 +131,12 @@ pub(crate) unsafe fn pdf_font_open_type1(font: &mut pdf_font) -> i32 {
     let ident = &*font.ident;
     let ident_ = CString::new(ident).unwrap();
     if is_basefont(ident_.as_ptr()) {
+        if let Some(level) = pdf_get_pdfa() {
+            warn!(
+                "Standard font \"{}\" is not embedded; the output will not conform to {}.",
+                ident, level
+            );
+        }
         font.fontname = ident.to_owned();
         pdf_font_set_subtype(font, 0i32);
         pdf_font_set_flags(font, 1i32 << 0i32 | 1i32 << 2i32);
@@ -994,3 +1003,43 @@ pub(crate) unsafe fn pdf_font_load_type1(font: &mut pdf_font) -> i32 {
     /* Maybe writing Charset is recommended for subsetted font. */
     0i32
 }
+
+/// The glyph name of a character of a Type 1 font, and the Unicode text
+/// that the font's ToUnicode CMap would map it to, if any.
+pub type T1GlyphText = (String, Option<String>);
+
+/// Get the glyph name and text of each code in the built-in encoding of a
+/// Type 1 font. This is for extracting text without producing a PDF.
+pub unsafe fn t1_get_encoding_text(ident: &str) -> Option<Vec<Option<T1GlyphText>>> {
+    let _quiet = QuietScope::new(2i32);
+    let ident_ = CString::new(ident).ok()?;
+    let mut handle = ttstub_input_open(ident_.as_ptr(), TTInputFormat::TYPE1, 0i32)?;
+    if !is_pfb(&mut handle) {
+        ttstub_input_close(handle);
+        return None;
+    }
+    let mut enc_vec: [*mut i8; 256] = [ptr::null_mut(); 256];
+    let cffont = t1_load_font(enc_vec.as_mut_ptr(), 0i32, handle);
+    if cffont.is_null() {
+        return None;
+    }
+    cff_close(cffont);
+    agl_init_map();
+    let glyphs = enc_vec
+        .iter()
+        .map(|&glyph| {
+            if glyph.is_null() {
+                return None;
+            }
+            let name = CStr::from_ptr(glyph).to_string_lossy().into_owned();
+            free(glyph as *mut libc::c_void);
+            if name == ".notdef" {
+                return None;
+            }
+            let text = agl_name_to_unicode(&name);
+            Some((name, text))
+        })
+        .collect();
+    agl_close_map();
+    Some(glyphs)
+}
@@ -121,6 +121,16 @@ pub(crate) unsafe fn UC_UTF16BE_encode_char(
     count as size_t
 }
 
+/// Decode a complete UTF-16BE string, such as the value of a ToUnicode
+/// CMap entry.
+pub(crate) fn UC_UTF16BE_to_string(bytes: &[u8]) -> Option<String> {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .collect();
+    String::from_utf16(&units).ok()
+}
+
 pub(crate) unsafe fn UC_UTF8_decode_char(pp: *mut *const u8, endptr: *const u8) -> i32 {
     let mut p: *const u8 = *pp;
     let fresh0 = p;
@@ -0,0 +1,828 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2020 the Tectonic Project
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_camel_case_types)]
+
+//! Unpacking of WOFF and WOFF2 web fonts.
+//!
+//! Both formats are containers for an ordinary sfnt, so rather than teaching
+//! every table reader about them we rebuild the plain TrueType or OpenType
+//! file in memory as soon as the font is opened. WOFF tables are each
+//! zlib-compressed. WOFF2 compresses all tables in a single Brotli stream and
+//! may also transform `glyf`, `loca` and `hmtx` into a more compressible
+//! form, which is undone here. WOFF2 font collections are not supported.
+//!
+//! XeTeX uses `woff_to_sfnt` on the font data it hands to FreeType, and
+//! `sfnt_open` uses `woff_open` so that xdvipdfmx sees the same tables.
+
+use crate::warn;
+
+use super::dpx_tt_var::encode_glyph_points;
+use bridge::{
+    ttstub_input_close, ttstub_input_get_size, ttstub_input_open_memory, InputHandleWrapper,
+};
+
+use std::io::{Read, Seek, SeekFrom};
+
+const WOFF_SIGNATURE: u32 = 0x774f_4646; /* wOFF */
+const WOFF2_SIGNATURE: u32 = 0x774f_4632; /* wOF2 */
+const TTCF_FLAVOR: u32 = 0x7474_6366;
+
+/// Refuse to unpack fonts claiming to be larger than this, so that a corrupt
+/// header can't make us allocate without bound.
+const MAX_SFNT_SIZE: usize = 1 << 30;
+
+/* Table tags by index in the WOFF2 table directory. */
+const WOFF2_KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+const TAG_GLYF: u32 = 0x676c_7966;
+const TAG_LOCA: u32 = 0x6c6f_6361;
+const TAG_HMTX: u32 = 0x686d_7478;
+const TAG_HHEA: u32 = 0x6868_6561;
+const TAG_HEAD: u32 = 0x6865_6164;
+
+fn get_u16(data: &[u8], pos: usize) -> Option<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| (b[0] as u16) << 8 | b[1] as u16)
+}
+
+fn get_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+}
+
+/// Is this the start of a WOFF or WOFF2 file?
+pub fn check_for_woff(data: &[u8]) -> bool {
+    matches!(
+        get_u32(data, 0),
+        Some(WOFF_SIGNATURE) | Some(WOFF2_SIGNATURE)
+    )
+}
+
+/// The sfnt version (`0x00010000`, `true` or `OTTO`) of the font packed in a
+/// WOFF or WOFF2 file.
+pub(crate) fn woff_flavor(data: &[u8]) -> Option<u32> {
+    if check_for_woff(data) {
+        get_u32(data, 4)
+    } else {
+        None
+    }
+}
+
+/// Unpack a WOFF or WOFF2 file into the sfnt it contains.
+pub fn woff_to_sfnt(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    match get_u32(data, 0) {
+        Some(WOFF_SIGNATURE) => woff1_decode(data),
+        Some(WOFF2_SIGNATURE) => woff2_decode(data),
+        _ => Err("not a WOFF or WOFF2 file"),
+    }
+}
+
+/// If `handle` holds a WOFF or WOFF2 font, unpack it and return a handle to
+/// the plain sfnt data instead. Anything else is handed back untouched, as
+/// is a font that fails to unpack, after a warning.
+pub(crate) unsafe fn woff_open(mut handle: InputHandleWrapper) -> InputHandleWrapper {
+    let mut signature = [0u8; 4];
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    let is_woff = handle.read_exact(&mut signature).is_ok() && check_for_woff(&signature);
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if !is_woff {
+        return handle;
+    }
+    let mut data = vec![0u8; ttstub_input_get_size(&mut handle)];
+    let unpacked = handle
+        .read_exact(&mut data)
+        .map_err(|_| "failed to read font file")
+        .and_then(|_| woff_to_sfnt(&data));
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    match unpacked {
+        Ok(sfnt) => match ttstub_input_open_memory(&sfnt) {
+            Some(unpacked) => {
+                ttstub_input_close(handle);
+                unpacked
+            }
+            None => handle,
+        },
+        Err(msg) => {
+            warn!("Could not unpack WOFF font: {}", msg);
+            handle
+        }
+    }
+}
+
+/// One table of the sfnt being rebuilt.
+struct woff_table {
+    tag: u32,
+    data: Vec<u8>,
+}
+
+fn table_checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// Lay out the tables as an sfnt, with the directory sorted by tag and the
+/// checksums (including `head.checkSumAdjustment`) recomputed.
+fn build_sfnt(flavor: u32, mut tables: Vec<woff_table>) -> Vec<u8> {
+    tables.sort_by_key(|t| t.tag);
+    let num_tables = tables.len() as u16;
+    let mut entry_selector = 0u16;
+    while 2u32 << entry_selector <= num_tables as u32 {
+        entry_selector += 1;
+    }
+    let search_range = 16u16 << entry_selector;
+    let range_shift = num_tables.wrapping_mul(16).wrapping_sub(search_range);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&flavor.to_be_bytes());
+    for v in &[num_tables, search_range, entry_selector, range_shift] {
+        out.extend_from_slice(&v.to_be_bytes());
+    }
+    let mut offset = 12 + 16 * tables.len();
+    let mut head_offset = None;
+    for table in &mut tables {
+        if table.tag == TAG_HEAD && table.data.len() >= 12 {
+            table.data[8..12].copy_from_slice(&[0; 4]);
+            head_offset = Some(offset);
+        }
+        out.extend_from_slice(&table.tag.to_be_bytes());
+        out.extend_from_slice(&table_checksum(&table.data).to_be_bytes());
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&(table.data.len() as u32).to_be_bytes());
+        offset += (table.data.len() + 3) & !3;
+    }
+    for table in &tables {
+        out.extend_from_slice(&table.data);
+        out.resize((out.len() + 3) & !3, 0);
+    }
+    if let Some(pos) = head_offset {
+        let adjustment = 0xb1b0_afba_u32.wrapping_sub(table_checksum(&out));
+        out[pos + 8..pos + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    out
+}
+
+fn woff1_decode(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    use flate2::read::ZlibDecoder;
+
+    const ERR_HEADER: &str = "truncated WOFF header";
+    let flavor = get_u32(data, 4).ok_or(ERR_HEADER)?;
+    let num_tables = get_u16(data, 12).ok_or(ERR_HEADER)? as usize;
+    let total_size = get_u32(data, 16).ok_or(ERR_HEADER)? as usize;
+    if total_size > MAX_SFNT_SIZE {
+        return Err("font is too large");
+    }
+    let mut tables = Vec::with_capacity(num_tables);
+    for i in 0..num_tables {
+        let pos = 44 + 20 * i;
+        let entry = data
+            .get(pos..pos + 20)
+            .ok_or("truncated WOFF table directory")?;
+        let tag = get_u32(entry, 0).unwrap();
+        let offset = get_u32(entry, 4).unwrap() as usize;
+        let comp_length = get_u32(entry, 8).unwrap() as usize;
+        let orig_length = get_u32(entry, 12).unwrap() as usize;
+        if orig_length > total_size {
+            return Err("bad WOFF table length");
+        }
+        let src = data
+            .get(offset..offset + comp_length)
+            .ok_or("WOFF table data out of bounds")?;
+        let table = if comp_length < orig_length {
+            let mut table = Vec::with_capacity(orig_length);
+            ZlibDecoder::new(src)
+                .take(orig_length as u64)
+                .read_to_end(&mut table)
+                .map_err(|_| "corrupt compressed WOFF table")?;
+            table
+        } else if comp_length == orig_length {
+            src.to_vec()
+        } else {
+            return Err("bad WOFF table length");
+        };
+        if table.len() != orig_length {
+            return Err("bad WOFF table length");
+        }
+        tables.push(woff_table { tag, data: table });
+    }
+    Ok(build_sfnt(flavor, tables))
+}
+
+/// A cursor over one of the WOFF2 data streams.
+struct woff2_stream<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> woff2_stream<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| (b[0] as u16) << 8 | b[1] as u16)
+    }
+
+    fn i16(&mut self) -> Option<i16> {
+        self.u16().map(|v| v as i16)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|b| get_u32(b, 0).unwrap())
+    }
+
+    /// A `UIntBase128`: big-endian groups of seven bits, at most five bytes.
+    fn base128(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+        for i in 0..5 {
+            let byte = self.u8()?;
+            /* no leading zeroes, and no overflow */
+            if (i == 0 && byte == 0x80) || value & 0xfe00_0000 != 0 {
+                return None;
+            }
+            value = value << 7 | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// A `255UInt16`: one byte, or an escape code and one or two more.
+    fn ushort255(&mut self) -> Option<u16> {
+        match self.u8()? {
+            253 => self.u16(),
+            254 => self.u8().map(|b| b as u16 + 506),
+            255 => self.u8().map(|b| b as u16 + 253),
+            code => Some(code as u16),
+        }
+    }
+
+    /// Carve the next `len` bytes off as a stream of their own.
+    fn substream(&mut self, len: u32) -> Option<woff2_stream<'a>> {
+        self.bytes(len as usize).map(woff2_stream::new)
+    }
+}
+
+/// One entry of the WOFF2 table directory.
+struct woff2_entry {
+    tag: u32,
+    transform: u8,
+    /// Length of the table as stored in the compressed stream.
+    length: usize,
+}
+
+impl woff2_entry {
+    /// Whether `transform` names anything but the null transform, which is
+    /// version 3 for `glyf` and `loca` and version 0 for everything else.
+    fn is_transformed(&self) -> bool {
+        if self.tag == TAG_GLYF || self.tag == TAG_LOCA {
+            self.transform != 3
+        } else {
+            self.transform != 0
+        }
+    }
+}
+
+fn woff2_decode(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    const ERR_DIRECTORY: &str = "truncated WOFF2 table directory";
+    let mut header = woff2_stream::new(data);
+    header.bytes(4).ok_or(ERR_DIRECTORY)?;
+    let flavor = header.u32().ok_or(ERR_DIRECTORY)?;
+    if flavor == TTCF_FLAVOR {
+        return Err("WOFF2 font collections are not supported");
+    }
+    header.u32().ok_or(ERR_DIRECTORY)?;
+    let num_tables = header.u16().ok_or(ERR_DIRECTORY)? as usize;
+    header.u16().ok_or(ERR_DIRECTORY)?;
+    let total_size = header.u32().ok_or(ERR_DIRECTORY)? as usize;
+    let compressed_size = header.u32().ok_or(ERR_DIRECTORY)? as usize;
+    if total_size > MAX_SFNT_SIZE {
+        return Err("font is too large");
+    }
+    header.pos = 48;
+
+    let mut entries = Vec::with_capacity(num_tables);
+    let mut stream_size = 0usize;
+    for _ in 0..num_tables {
+        let flags = header.u8().ok_or(ERR_DIRECTORY)?;
+        let tag = match flags & 0x3f {
+            63 => header.u32().ok_or(ERR_DIRECTORY)?,
+            known => u32::from_be_bytes(*WOFF2_KNOWN_TAGS[known as usize]),
+        };
+        let mut entry = woff2_entry {
+            tag,
+            transform: flags >> 6,
+            length: header.base128().ok_or(ERR_DIRECTORY)? as usize,
+        };
+        if entry.is_transformed() {
+            entry.length = header.base128().ok_or(ERR_DIRECTORY)? as usize;
+        }
+        stream_size = stream_size
+            .checked_add(entry.length)
+            .filter(|&size| size <= MAX_SFNT_SIZE)
+            .ok_or("font is too large")?;
+        entries.push(entry);
+    }
+
+    let compressed = header
+        .bytes(compressed_size)
+        .ok_or("truncated WOFF2 table data")?;
+    let mut stream = Vec::with_capacity(stream_size);
+    brotli_decompressor::Decompressor::new(compressed, 4096)
+        .take(stream_size as u64)
+        .read_to_end(&mut stream)
+        .map_err(|_| "corrupt WOFF2 table data")?;
+    if stream.len() != stream_size {
+        return Err("truncated WOFF2 table data");
+    }
+
+    let mut srcs = Vec::with_capacity(num_tables);
+    let mut offset = 0;
+    for entry in &entries {
+        srcs.push(&stream[offset..offset + entry.length]);
+        offset += entry.length;
+    }
+    /* loca and hmtx are rebuilt from glyf, wherever it is in the directory */
+    let mut glyf = match entries
+        .iter()
+        .position(|e| e.tag == TAG_GLYF && e.is_transformed())
+    {
+        Some(i) => Some(woff2_glyf(srcs[i])?),
+        None => None,
+    };
+    let num_hmetrics = entries
+        .iter()
+        .zip(&srcs)
+        .find(|(e, _)| e.tag == TAG_HHEA && !e.is_transformed())
+        .and_then(|(_, src)| get_u16(src, 34));
+
+    let mut tables = Vec::with_capacity(num_tables);
+    for (entry, src) in entries.iter().zip(srcs) {
+        let table = if !entry.is_transformed() {
+            src.to_vec()
+        } else {
+            if !matches!(entry.tag, TAG_GLYF | TAG_LOCA | TAG_HMTX) {
+                return Err("unknown WOFF2 table transform");
+            }
+            let (glyf_data, loca, x_mins) = glyf
+                .as_mut()
+                .ok_or("WOFF2 transformed loca or hmtx without transformed glyf")?;
+            match entry.tag {
+                TAG_GLYF => std::mem::take(glyf_data),
+                TAG_LOCA => std::mem::take(loca),
+                _ => {
+                    let num_hmetrics = num_hmetrics.ok_or("WOFF2 hmtx without hhea")?;
+                    woff2_hmtx(src, num_hmetrics as usize, x_mins)?
+                }
+            }
+        };
+        tables.push(woff_table {
+            tag: entry.tag,
+            data: table,
+        });
+    }
+    Ok(build_sfnt(flavor, tables))
+}
+
+/// Decode one point of a simple glyph from its flag byte and the triplet
+/// data in the glyph stream, returning the coordinate deltas.
+fn woff2_triplet(flag: u8, glyphs: &mut woff2_stream) -> Option<(i32, i32)> {
+    fn with_sign(flag: u8, value: i32) -> i32 {
+        if flag & 1 != 0 {
+            value
+        } else {
+            -value
+        }
+    }
+    let flag = flag & 0x7f;
+    let f = flag as i32;
+    Some(if flag < 10 {
+        let b0 = glyphs.u8()? as i32;
+        (0, with_sign(flag, ((f & 14) << 7) + b0))
+    } else if flag < 20 {
+        let b0 = glyphs.u8()? as i32;
+        (with_sign(flag, (((f - 10) & 14) << 7) + b0), 0)
+    } else if flag < 84 {
+        let (b0, b1) = (f - 20, glyphs.u8()? as i32);
+        (
+            with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+            with_sign(flag >> 1, 1 + ((b0 & 0x0c) << 2) + (b1 & 0x0f)),
+        )
+    } else if flag < 120 {
+        let b0 = f - 84;
+        let b = glyphs.bytes(2)?;
+        (
+            with_sign(flag, 1 + ((b0 / 12) << 8) + b[0] as i32),
+            with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + b[1] as i32),
+        )
+    } else if flag < 124 {
+        let b = glyphs.bytes(3)?;
+        (
+            with_sign(flag, ((b[0] as i32) << 4) + ((b[1] as i32) >> 4)),
+            with_sign(flag >> 1, (((b[1] & 0x0f) as i32) << 8) + b[2] as i32),
+        )
+    } else {
+        let b = glyphs.bytes(4)?;
+        (
+            with_sign(flag, (b[0] as i32) << 8 | b[1] as i32),
+            with_sign(flag >> 1, (b[2] as i32) << 8 | b[3] as i32),
+        )
+    })
+}
+
+/// Undo the WOFF2 `glyf` transform, returning the `glyf` and `loca` tables
+/// and each glyph's `xMin` for rebuilding `hmtx`.
+fn woff2_glyf(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<i16>), &'static str> {
+    const ERR_GLYF: &str = "corrupt WOFF2 glyf table";
+    let mut header = woff2_stream::new(data);
+    header.u16().ok_or(ERR_GLYF)?;
+    let option_flags = header.u16().ok_or(ERR_GLYF)?;
+    let num_glyphs = header.u16().ok_or(ERR_GLYF)? as usize;
+    let index_format = header.u16().ok_or(ERR_GLYF)?;
+    let mut sizes = [0u32; 7];
+    for size in &mut sizes {
+        *size = header.u32().ok_or(ERR_GLYF)?;
+    }
+    let mut streams = Vec::with_capacity(7);
+    for &size in &sizes {
+        streams.push(header.substream(size).ok_or(ERR_GLYF)?);
+    }
+    let mut instructions = streams.pop().unwrap();
+    let mut bboxes = streams.pop().unwrap();
+    let mut composites = streams.pop().unwrap();
+    let mut glyphs = streams.pop().unwrap();
+    let mut flags = streams.pop().unwrap();
+    let mut n_points = streams.pop().unwrap();
+    let mut n_contours = streams.pop().unwrap();
+    let overlap_bitmap = if option_flags & 1 != 0 {
+        Some(header.bytes((num_glyphs + 7) / 8).ok_or(ERR_GLYF)?)
+    } else {
+        None
+    };
+    let bbox_bitmap = bboxes.bytes((num_glyphs + 31) / 32 * 4).ok_or(ERR_GLYF)?;
+    let bit = |bitmap: &[u8], gid: usize| bitmap[gid >> 3] & (0x80 >> (gid & 7)) != 0;
+
+    let mut glyf = Vec::new();
+    let mut offsets = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins = Vec::with_capacity(num_glyphs);
+    for gid in 0..num_glyphs {
+        offsets.push(glyf.len());
+        let num_contours = n_contours.i16().ok_or(ERR_GLYF)?;
+        let has_bbox = bit(bbox_bitmap, gid);
+        let mut read_bbox = || -> Option<[i16; 4]> {
+            Some([bboxes.i16()?, bboxes.i16()?, bboxes.i16()?, bboxes.i16()?])
+        };
+        let bbox;
+        match num_contours {
+            0 => {
+                if has_bbox {
+                    return Err(ERR_GLYF);
+                }
+                x_mins.push(0);
+                continue;
+            }
+            -1 => {
+                /* composite glyph: copy the component records */
+                if !has_bbox {
+                    return Err(ERR_GLYF);
+                }
+                let start = composites.pos;
+                let mut have_instructions = false;
+                loop {
+                    let flags = composites.u16().ok_or(ERR_GLYF)?;
+                    let mut len = 2 + if flags & 0x0001 != 0 { 4 } else { 2 };
+                    if flags & 0x0008 != 0 {
+                        len += 2;
+                    } else if flags & 0x0040 != 0 {
+                        len += 4;
+                    } else if flags & 0x0080 != 0 {
+                        len += 8;
+                    }
+                    composites.bytes(len).ok_or(ERR_GLYF)?;
+                    have_instructions |= flags & 0x0100 != 0;
+                    if flags & 0x0020 == 0 {
+                        break;
+                    }
+                }
+                bbox = read_bbox().ok_or(ERR_GLYF)?;
+                glyf.extend_from_slice(&(-1i16).to_be_bytes());
+                for v in &bbox {
+                    glyf.extend_from_slice(&v.to_be_bytes());
+                }
+                glyf.extend_from_slice(&composites.data[start..composites.pos]);
+                if have_instructions {
+                    let len = glyphs.ushort255().ok_or(ERR_GLYF)?;
+                    glyf.extend_from_slice(&len.to_be_bytes());
+                    glyf.extend_from_slice(instructions.bytes(len as usize).ok_or(ERR_GLYF)?);
+                }
+            }
+            n if n > 0 => {
+                let mut end_points = Vec::with_capacity(n as usize);
+                let mut total = 0u32;
+                for _ in 0..n {
+                    total += n_points.ushort255().ok_or(ERR_GLYF)? as u32;
+                    if total == 0 || total > 0xffff {
+                        return Err(ERR_GLYF);
+                    }
+                    end_points.push((total - 1) as u16);
+                }
+                let mut point_flags = Vec::with_capacity(total as usize);
+                let mut points = Vec::with_capacity(total as usize);
+                let (mut x, mut y) = (0i32, 0i32);
+                for i in 0..total as usize {
+                    let flag = flags.u8().ok_or(ERR_GLYF)?;
+                    let (dx, dy) = woff2_triplet(flag, &mut glyphs).ok_or(ERR_GLYF)?;
+                    x = x.wrapping_add(dx);
+                    y = y.wrapping_add(dy);
+                    points.push((x, y));
+                    let mut point_flag = if flag & 0x80 == 0 { 0x01 } else { 0 };
+                    if i == 0 && matches!(overlap_bitmap, Some(bitmap) if bit(bitmap, gid)) {
+                        point_flag |= 0x40;
+                    }
+                    point_flags.push(point_flag);
+                }
+                let instruction_len = glyphs.ushort255().ok_or(ERR_GLYF)?;
+                bbox = if has_bbox {
+                    read_bbox().ok_or(ERR_GLYF)?
+                } else {
+                    let xs = points.iter().map(|p| p.0);
+                    let ys = points.iter().map(|p| p.1);
+                    [
+                        xs.clone().min().unwrap() as i16,
+                        ys.clone().min().unwrap() as i16,
+                        xs.max().unwrap() as i16,
+                        ys.max().unwrap() as i16,
+                    ]
+                };
+                glyf.extend_from_slice(&n.to_be_bytes());
+                for v in &bbox {
+                    glyf.extend_from_slice(&v.to_be_bytes());
+                }
+                for e in &end_points {
+                    glyf.extend_from_slice(&e.to_be_bytes());
+                }
+                glyf.extend_from_slice(&instruction_len.to_be_bytes());
+                glyf.extend_from_slice(
+                    instructions
+                        .bytes(instruction_len as usize)
+                        .ok_or(ERR_GLYF)?,
+                );
+                encode_glyph_points(&point_flags, &points, &mut glyf);
+            }
+            _ => return Err(ERR_GLYF),
+        }
+        x_mins.push(bbox[0]);
+        glyf.resize((glyf.len() + 3) & !3, 0);
+    }
+    offsets.push(glyf.len());
+
+    let mut loca = Vec::with_capacity(offsets.len() * 4);
+    for offset in offsets {
+        if index_format == 0 {
+            if offset > 0x1fffe {
+                return Err(ERR_GLYF);
+            }
+            loca.extend_from_slice(&((offset / 2) as u16).to_be_bytes());
+        } else {
+            loca.extend_from_slice(&(offset as u32).to_be_bytes());
+        }
+    }
+    Ok((glyf, loca, x_mins))
+}
+
+/// Undo the WOFF2 `hmtx` transform, which may drop left side bearings that
+/// equal the glyph's `xMin`.
+fn woff2_hmtx(data: &[u8], num_hmetrics: usize, x_mins: &[i16]) -> Result<Vec<u8>, &'static str> {
+    const ERR_HMTX: &str = "corrupt WOFF2 hmtx table";
+    let num_glyphs = x_mins.len();
+    if num_hmetrics == 0 || num_hmetrics > num_glyphs {
+        return Err(ERR_HMTX);
+    }
+    let mut src = woff2_stream::new(data);
+    let flags = src.u8().ok_or(ERR_HMTX)?;
+    if flags & 0x03 == 0 || flags & 0xfc != 0 {
+        return Err(ERR_HMTX);
+    }
+    let mut advances = Vec::with_capacity(num_hmetrics);
+    for _ in 0..num_hmetrics {
+        advances.push(src.u16().ok_or(ERR_HMTX)?);
+    }
+    let mut lsbs = Vec::with_capacity(num_glyphs);
+    for (gid, &x_min) in x_mins.iter().enumerate() {
+        let explicit = if gid < num_hmetrics {
+            flags & 0x01 == 0
+        } else {
+            flags & 0x02 == 0
+        };
+        lsbs.push(if explicit {
+            src.i16().ok_or(ERR_HMTX)?
+        } else {
+            x_min
+        });
+    }
+    let mut hmtx = Vec::with_capacity(num_hmetrics * 2 + num_glyphs * 2);
+    for (gid, lsb) in lsbs.iter().enumerate() {
+        if let Some(advance) = advances.get(gid) {
+            hmtx.extend_from_slice(&advance.to_be_bytes());
+        }
+        hmtx.extend_from_slice(&lsb.to_be_bytes());
+    }
+    Ok(hmtx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table_entry(sfnt: &[u8], tag: &[u8; 4]) -> Option<Vec<u8>> {
+        let num_tables = get_u16(sfnt, 4)? as usize;
+        (0..num_tables).find_map(|i| {
+            let pos = 12 + 16 * i;
+            if &sfnt[pos..pos + 4] != tag {
+                return None;
+            }
+            let offset = get_u32(sfnt, pos + 8)? as usize;
+            let length = get_u32(sfnt, pos + 12)? as usize;
+            Some(sfnt[offset..offset + length].to_vec())
+        })
+    }
+
+    #[test]
+    fn number_encodings() {
+        let mut s = woff2_stream::new(&[0x3f, 0x81, 0x00, 0x80, 0x01]);
+        assert_eq!(s.base128(), Some(63));
+        assert_eq!(s.base128(), Some(128));
+        /* leading zero bits are not allowed */
+        assert_eq!(s.base128(), None);
+        let mut s = woff2_stream::new(&[252, 255, 0, 254, 0, 253, 0x12, 0x34]);
+        assert_eq!(s.ushort255(), Some(252));
+        assert_eq!(s.ushort255(), Some(253));
+        assert_eq!(s.ushort255(), Some(506));
+        assert_eq!(s.ushort255(), Some(0x1234));
+    }
+
+    #[test]
+    fn sfnt_layout() {
+        let tables = vec![
+            woff_table {
+                tag: u32::from_be_bytes(*b"post"),
+                data: vec![1, 2, 3],
+            },
+            woff_table {
+                tag: TAG_HEAD,
+                data: vec![0xff; 54],
+            },
+        ];
+        let sfnt = build_sfnt(0x0001_0000, tables);
+        assert_eq!(get_u16(&sfnt, 4), Some(2));
+        assert_eq!(get_u16(&sfnt, 6), Some(32));
+        assert_eq!(&sfnt[12..16], b"head");
+        assert_eq!(table_entry(&sfnt, b"post"), Some(vec![1, 2, 3]));
+        /* the whole font sums to the magic number */
+        assert_eq!(table_checksum(&sfnt), 0xb1b0_afba);
+    }
+
+    #[test]
+    fn woff1_tables() {
+        use flate2::write::ZlibEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let cmap = vec![7u8; 100];
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&cmap).unwrap();
+        let packed = encoder.finish().unwrap();
+        let name = vec![1u8, 2, 3, 4, 5];
+
+        let mut woff = Vec::new();
+        woff.extend_from_slice(b"wOFF");
+        woff.extend_from_slice(&0x0001_0000_u32.to_be_bytes());
+        woff.extend_from_slice(&[0; 4]);
+        woff.extend_from_slice(&2u16.to_be_bytes());
+        woff.extend_from_slice(&[0; 2]);
+        woff.extend_from_slice(&1000u32.to_be_bytes());
+        woff.resize(44, 0);
+        let data_start = 44 + 2 * 20;
+        for (tag, offset, comp, orig) in &[
+            (b"cmap", data_start, packed.len(), cmap.len()),
+            (b"name", data_start + packed.len(), name.len(), name.len()),
+        ] {
+            woff.extend_from_slice(*tag);
+            for v in &[*offset, *comp, *orig, 0] {
+                woff.extend_from_slice(&(*v as u32).to_be_bytes());
+            }
+        }
+        woff.extend_from_slice(&packed);
+        woff.extend_from_slice(&name);
+
+        assert!(check_for_woff(&woff));
+        assert_eq!(woff_flavor(&woff), Some(0x0001_0000));
+        let sfnt = woff_to_sfnt(&woff).unwrap();
+        assert_eq!(get_u32(&sfnt, 0), Some(0x0001_0000));
+        assert_eq!(table_entry(&sfnt, b"cmap"), Some(cmap));
+        assert_eq!(table_entry(&sfnt, b"name"), Some(name));
+        woff.truncate(woff.len() - 1);
+        assert!(woff_to_sfnt(&woff).is_err());
+    }
+
+    #[test]
+    fn triplets() {
+        let mut s = woff2_stream::new(&[0x05]);
+        /* flag 1: dy = +(0 << 8 | 5) */
+        assert_eq!(woff2_triplet(1, &mut s), Some((0, 5)));
+        let mut s = woff2_stream::new(&[0x05]);
+        /* flag 10: dx = -5 */
+        assert_eq!(woff2_triplet(10, &mut s), Some((-5, 0)));
+        let mut s = woff2_stream::new(&[0x12, 0x34, 0x56, 0x78]);
+        /* flag 127: both words, both positive */
+        assert_eq!(woff2_triplet(0x80 | 127, &mut s), Some((0x1234, 0x5678)));
+        let mut s = woff2_stream::new(&[0x12, 0x34, 0x56]);
+        /* flag 120: three bytes, both negative */
+        assert_eq!(woff2_triplet(120, &mut s), Some((-0x123, -0x456)));
+    }
+
+    #[test]
+    fn transformed_glyf_and_hmtx() {
+        /* glyph 0 is empty; glyph 1 is a triangle with one instruction byte */
+        let n_contours = [0u8, 0, 0, 1];
+        let n_points = [3u8];
+        let flags = [1u8, 10, 1 | 0x80];
+        let glyphs = [100u8, 50, 200, 1];
+        let instructions = [0xb0u8];
+        let bboxes = [0u8; 4];
+        let mut glyf_t = Vec::new();
+        glyf_t.extend_from_slice(&[0, 0, 0, 0, 0, 2, 0, 0]);
+        for stream in &[
+            &n_contours[..],
+            &n_points,
+            &flags,
+            &glyphs,
+            &[],
+            &bboxes,
+            &instructions,
+        ] {
+            glyf_t.extend_from_slice(&(stream.len() as u32).to_be_bytes());
+        }
+        for stream in &[
+            &n_contours[..],
+            &n_points,
+            &flags,
+            &glyphs,
+            &[],
+            &bboxes,
+            &instructions,
+        ] {
+            glyf_t.extend_from_slice(stream);
+        }
+        let (glyf, loca, x_mins) = woff2_glyf(&glyf_t).unwrap();
+        assert_eq!(loca.len(), 6);
+        assert_eq!(get_u16(&loca, 2), Some(0));
+        assert_eq!(get_u16(&loca, 4), Some(glyf.len() as u16 / 2));
+        /* points at (0, 100), (-50, 100), (-50, 300) */
+        assert_eq!(x_mins, vec![0, -50]);
+        assert_eq!(&glyf[..10], &[0, 1, 0xff, 0xce, 0, 100, 0, 0, 1, 0x2c]);
+        assert_eq!(get_u16(&glyf, 10), Some(2));
+        assert_eq!(&glyf[12..15], &[0, 1, 0xb0]);
+
+        let hmtx = woff2_hmtx(&[0x01, 0x01, 0xf4, 0x02, 0x58], 2, &x_mins).unwrap();
+        assert_eq!(hmtx, vec![0x01, 0xf4, 0, 0, 0x02, 0x58, 0xff, 0xce]);
+        assert!(woff2_hmtx(&[0x00, 0x01, 0xf4], 1, &x_mins).is_err());
+    }
+}
@@ -158,6 +158,7 @@ impl FromBEByteSlice for u16 {
 pub(crate) mod dpx_agl;
 pub(crate) mod dpx_bmpimage;
 pub(crate) mod dpx_cff;
+pub(crate) mod dpx_cff2;
 pub(crate) mod dpx_cff_dict;
 pub(crate) mod dpx_cid;
 pub(crate) mod dpx_cidtype0;
@@ -183,6 +184,7 @@ pub(crate) mod dpx_mfileio;
 pub(crate) mod dpx_mpost;
 pub(crate) mod dpx_numbers;
 pub(crate) mod dpx_otl_opt;
+pub(crate) mod dpx_pdfa;
 pub(crate) mod dpx_pdfcolor;
 pub(crate) mod dpx_pdfdev;
 pub(crate) mod dpx_pdfdoc;
@@ -190,10 +192,12 @@ pub(crate) mod dpx_pdfdraw;
 pub(crate) mod dpx_pdfencoding;
 pub(crate) mod dpx_pdfencrypt;
 pub(crate) mod dpx_pdffont;
+pub(crate) mod dpx_pdflinear;
 pub(crate) mod dpx_pdfnames;
 pub(crate) mod dpx_pdfobj;
 pub(crate) mod dpx_pdfparse;
 pub(crate) mod dpx_pdfresource;
+pub(crate) mod dpx_pdfstruct;
 pub(crate) mod dpx_pdfximage;
 pub(crate) mod dpx_pkfont;
 pub(crate) mod dpx_pngimage;
@@ -207,24 +211,31 @@ pub(crate) mod dpx_tfm;
 pub(crate) mod dpx_truetype;
 pub(crate) mod dpx_tt_aux;
 pub(crate) mod dpx_tt_cmap;
+pub(crate) mod dpx_tt_color;
 pub(crate) mod dpx_tt_glyf;
 pub(crate) mod dpx_tt_gsub;
 pub(crate) mod dpx_tt_post;
 pub(crate) mod dpx_tt_table;
+pub(crate) mod dpx_tt_var;
 pub(crate) mod dpx_type0;
 pub(crate) mod dpx_type1;
 pub(crate) mod dpx_type1c;
 pub(crate) mod dpx_unicode;
 pub(crate) mod dpx_vf;
+pub(crate) mod dpx_woff;
 mod shims;
 pub(crate) mod specials;
 
 pub use crate::dpx_bmpimage::{bmp_get_bbox, check_for_bmp};
 pub use crate::dpx_dvipdfmx::dvipdfmx_main;
 pub use crate::dpx_jpegimage::{check_for_jpeg, jpeg_get_bbox};
+pub use crate::dpx_pdfa::PdfAConformance;
 pub use crate::dpx_pdfdev::Corner;
 pub use crate::dpx_pdfdoc::{pdf_doc_get_page, pdf_doc_get_page_count};
 pub use crate::dpx_pdfdraw::pdf_dev_transform;
 pub use crate::dpx_pdfobj::{pdf_close, pdf_file, pdf_obj, pdf_open, pdf_release_obj};
 pub use crate::dpx_pdfobj::{pdf_files_close, pdf_files_init};
 pub use crate::dpx_pngimage::{check_for_png, png_get_bbox};
+pub use crate::dpx_tt_cmap::{otf_get_glyph_text, OtfGlyphText};
+pub use crate::dpx_type1::{t1_get_encoding_text, T1GlyphText};
+pub use crate::dpx_woff::{check_for_woff, woff_to_sfnt};
@@ -37,6 +37,7 @@ use crate::dpx_pdfximage::{
 use super::{spc_begin_annot, spc_end_annot};
 use crate::dpx_dpxutil::{ParseCIdent, ParseFloatDecimal};
 use crate::dpx_mem::new;
+use crate::dpx_pdfa::pdfa_transparency_allowed;
 use crate::dpx_pdfdev::{graphics_mode, transform_info, transform_info_clear, Rect, TMatrix};
 use crate::dpx_pdfdoc::{
     pdf_doc_add_names, pdf_doc_add_page_content, pdf_doc_add_page_resource,
@@ -566,7 +567,7 @@ unsafe fn spc_html__img_empty(spe: &mut spc_env, attr: &pdf_obj) -> i32 {
         graphics_mode();
         pdf_dev_gsave();
         let a: i32 = (100.0f64 * alpha).round() as i32;
-        if a != 0i32 {
+        if a != 0i32 && (a == 100 || pdfa_transparency_allowed("image opacity")) {
             let res_name = format!("_Tps_a{:03}_", a);
             let res_name_c = CString::new(res_name.as_str()).unwrap();
             if check_resourcestatus("ExtGState", &res_name) == 0 {
@@ -67,6 +67,10 @@ use crate::dpx_pdfobj::{
     pdf_string, pdf_string_value, IntoObj, PdfObjVariant, STREAM_COMPRESS,
 };
 use crate::dpx_pdfparse::{ParseIdent, ParsePdfObj, SkipWhite};
+use crate::dpx_pdfstruct::{
+    pdf_struct_add_role_map, pdf_struct_begin_artifact, pdf_struct_begin_elem,
+    pdf_struct_begin_marked_content, pdf_struct_end_elem, pdf_struct_end_marked_content,
+};
 use crate::dpx_pdfximage::{pdf_ximage_findresource, pdf_ximage_get_reference};
 use crate::dpx_unicode::{
     UC_UTF16BE_encode_char, UC_UTF16BE_is_valid_string, UC_UTF8_decode_char,
@@ -170,9 +174,21 @@ unsafe fn spc_handler_pdfm__init(sd: &mut spc_pdf_) -> i32 {
     /* The folllowing dictionary entry keys are considered as keys for
      * text strings. Be sure that string object is NOT always a text string.
      */
-    const DEFAULT_TAINTKEYS: [&str; 11] = [
-        "Title", "Author", "Subject", "Keywords", "Creator", "Producer", "Contents", "Subj", "TU",
-        "T", "TM",
+    const DEFAULT_TAINTKEYS: [&str; 14] = [
+        "Title",
+        "Author",
+        "Subject",
+        "Keywords",
+        "Creator",
+        "Producer",
+        "Contents",
+        "Subj",
+        "TU",
+        "T",
+        "TM",
+        "Alt",
+        "ActualText",
+        "E",
     ];
     sd.annot_dict = ptr::null_mut();
     sd.lowest_level = 255i32;
@@ -1574,7 +1590,122 @@ unsafe fn spc_handler_pdfm_tounicode(spe: &mut spc_env, args: &mut spc_arg) -> i
         -1
     }
 }
-const PDFM_HANDLERS: [SpcHandler; 80] = [
+/* Tagged PDF: structure elements and marked content.
+ *
+ *  pdf:bstruct [@name] /Type [<< attributes >>]
+ *  pdf:bstruct [@name] << /S /Type ... >>
+ *  pdf:estruct
+ *  pdf:bmc [/Tag] [<< properties >>]
+ *  pdf:emc
+ *  pdf:bartifact [<< properties >>]
+ *  pdf:eartifact
+ *  pdf:rolemap << /Custom /Standard ... >>
+ */
+unsafe fn spc_handler_pdfm_bstruct(spe: &mut spc_env, args: &mut spc_arg) -> i32 {
+    let sd = &mut _PDF_STAT;
+    let mut ident = None;
+    args.cur.skip_white();
+    if !args.cur.is_empty() && args.cur[0] == b'@' {
+        ident = args.cur.parse_opt_ident();
+        args.cur.skip_white();
+    }
+    let mut stype = None;
+    if !args.cur.is_empty() && args.cur[0] == b'/' {
+        stype = args.cur.parse_pdf_name();
+        if stype.is_none() {
+            spc_warn!(spe, "Invalid structure type for pdf:bstruct.");
+            return -1;
+        }
+        args.cur.skip_white();
+    }
+    let elem = if !args.cur.is_empty() {
+        match args.cur.parse_pdf_dict_with_tounicode(&mut sd.cd) {
+            Some(dict) => dict,
+            None => {
+                spc_warn!(spe, "Could not find dictionary object for pdf:bstruct.");
+                if let Some(stype) = stype {
+                    pdf_release_obj(stype);
+                }
+                return -1;
+            }
+        }
+    } else {
+        pdf_dict::new().into_obj()
+    };
+    if let Some(stype) = stype {
+        (*elem).as_dict_mut().set("S", stype);
+    }
+    if let Some(i) = ident.as_ref() {
+        spc_push_object(i.as_ptr(), pdf_link_obj(elem));
+    }
+    pdf_struct_begin_elem(elem)
+}
+unsafe fn spc_handler_pdfm_estruct(_spe: &mut spc_env, _args: &mut spc_arg) -> i32 {
+    pdf_struct_end_elem()
+}
+/* Reads an optional property list, returning it as written. */
+unsafe fn spc_read_mc_properties(spe: &mut spc_env, args: &mut spc_arg) -> Option<Vec<u8>> {
+    args.cur.skip_white();
+    if args.cur.is_empty() {
+        return Some(Vec::new());
+    }
+    let start = args.cur;
+    match args.cur.parse_pdf_dict(ptr::null_mut()) {
+        Some(dict) => {
+            pdf_release_obj(dict);
+            Some(start[..start.len() - args.cur.len()].to_vec())
+        }
+        None => {
+            spc_warn!(spe, "Invalid property list for marked content.");
+            None
+        }
+    }
+}
+unsafe fn spc_handler_pdfm_bmc(spe: &mut spc_env, args: &mut spc_arg) -> i32 {
+    args.cur.skip_white();
+    let mut tag = None;
+    if !args.cur.is_empty() && args.cur[0] == b'/' {
+        if let Some(name) = args.cur.parse_pdf_name() {
+            tag = Some((*name).as_name().to_bytes().to_vec());
+            pdf_release_obj(name);
+        } else {
+            spc_warn!(spe, "Invalid tag for pdf:bmc.");
+            return -1;
+        }
+    }
+    if let Some(props) = spc_read_mc_properties(spe, args) {
+        pdf_struct_begin_marked_content(tag.as_ref().map(|t| t.as_slice()), &props)
+    } else {
+        -1
+    }
+}
+unsafe fn spc_handler_pdfm_emc(_spe: &mut spc_env, _args: &mut spc_arg) -> i32 {
+    pdf_struct_end_marked_content(false)
+}
+unsafe fn spc_handler_pdfm_bartifact(spe: &mut spc_env, args: &mut spc_arg) -> i32 {
+    if let Some(props) = spc_read_mc_properties(spe, args) {
+        pdf_struct_begin_artifact(&props)
+    } else {
+        -1
+    }
+}
+unsafe fn spc_handler_pdfm_eartifact(_spe: &mut spc_env, _args: &mut spc_arg) -> i32 {
+    pdf_struct_end_marked_content(true)
+}
+unsafe fn spc_handler_pdfm_rolemap(spe: &mut spc_env, args: &mut spc_arg) -> i32 {
+    args.cur.skip_white();
+    match args.cur.parse_pdf_dict(ptr::null_mut()) {
+        Some(dict) => {
+            pdf_struct_add_role_map(dict);
+            0
+        }
+        None => {
+            spc_warn!(spe, "Could not find dictionary object for pdf:rolemap.");
+            -1
+        }
+    }
+}
+const PDFM_HANDLERS: [SpcHandler; 88] = [
     SpcHandler {
         key: b"annotation",
         exec: Some(spc_handler_pdfm_annot),
@@ -1887,6 +2018,10 @@ const PDFM_HANDLERS: [SpcHandler; 80] = [
         key: b"code",
         exec: Some(spc_handler_pdfm_code),
     },
+    SpcHandler {
+        key: b"majorversion",
+        exec: Some(spc_handler_pdfm_do_nothing),
+    },
     SpcHandler {
         key: b"minorversion",
         exec: Some(spc_handler_pdfm_do_nothing),
@@ -1895,6 +2030,34 @@ const PDFM_HANDLERS: [SpcHandler; 80] = [
         key: b"encrypt",
         exec: Some(spc_handler_pdfm_do_nothing),
     },
+    SpcHandler {
+        key: b"bstruct",
+        exec: Some(spc_handler_pdfm_bstruct),
+    },
+    SpcHandler {
+        key: b"estruct",
+        exec: Some(spc_handler_pdfm_estruct),
+    },
+    SpcHandler {
+        key: b"bmc",
+        exec: Some(spc_handler_pdfm_bmc),
+    },
+    SpcHandler {
+        key: b"emc",
+        exec: Some(spc_handler_pdfm_emc),
+    },
+    SpcHandler {
+        key: b"bartifact",
+        exec: Some(spc_handler_pdfm_bartifact),
+    },
+    SpcHandler {
+        key: b"eartifact",
+        exec: Some(spc_handler_pdfm_eartifact),
+    },
+    SpcHandler {
+        key: b"rolemap",
+        exec: Some(spc_handler_pdfm_rolemap),
+    },
 ];
 pub(crate) fn spc_pdfm_check_special(mut buf: &[u8]) -> bool {
     buf.skip_white();
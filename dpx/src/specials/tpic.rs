@@ -31,6 +31,7 @@ use super::{spc_arg, spc_env};
 use crate::spc_warn;
 
 use crate::dpx_dpxutil::{ParseCIdent, ParseCString, ParseFloatDecimal};
+use crate::dpx_pdfa::pdfa_transparency_allowed;
 use crate::dpx_pdfcolor::pdf_color_get_current;
 use crate::dpx_pdfdev::pdf_dev_scale;
 use crate::dpx_pdfdoc::{
@@ -153,7 +154,10 @@ unsafe fn set_styles(tp: &mut spc_tpic_, c: &Point, f_fs: bool, f_vp: bool, pn:
         set_linestyle(pn, da);
     }
     if f_fs {
-        let (g, a) = if tp.mode.fill == 0i32 || tp.fill_color == 0. {
+        let (g, a) = if tp.mode.fill == 0i32
+            || tp.fill_color == 0.
+            || !pdfa_transparency_allowed("tpic transparent fill")
+        {
             (1. - tp.fill_color, 0.)
         } else {
             (0., tp.fill_color)
@@ -15,12 +15,28 @@ use std::env;
 use std::path::{Path, PathBuf};
 
 #[cfg(not(target_os = "macos"))]
-const PKGCONFIG_LIBS: &'static str =
-    "fontconfig harfbuzz >= 1.4 harfbuzz-icu icu-uc freetype2 graphite2";
+const PKGCONFIG_LIBS: &'static str = "fontconfig harfbuzz >= 1.4 freetype2 graphite2";
 
 // No fontconfig on MacOS:
 #[cfg(target_os = "macos")]
-const PKGCONFIG_LIBS: &'static str = "harfbuzz >= 1.4 harfbuzz-icu icu-uc freetype2 graphite2";
+const PKGCONFIG_LIBS: &'static str = "harfbuzz >= 1.4 freetype2 graphite2";
+
+/// Only needed when the "icu" feature is enabled; otherwise line breaking
+/// and friends are handled in Rust.
+const PKGCONFIG_ICU_LIBS: &'static str = "harfbuzz-icu icu-uc";
+
+/// Whether the "icu" cargo feature is enabled for this build.
+fn use_icu() -> bool {
+    env::var_os("CARGO_FEATURE_ICU").is_some()
+}
+
+fn pkgconfig_libs() -> String {
+    if use_icu() {
+        format!("{} {}", PKGCONFIG_LIBS, PKGCONFIG_ICU_LIBS)
+    } else {
+        PKGCONFIG_LIBS.to_owned()
+    }
+}
 
 /// Build-script state when using pkg-config as the backend.
 #[derive(Debug)]
@@ -63,7 +79,7 @@ impl DepState {
     fn new_pkg_config() -> Self {
         let libs = pkg_config::Config::new()
             .cargo_metadata(false)
-            .probe(PKGCONFIG_LIBS)
+            .probe(&pkgconfig_libs())
             .unwrap();
         DepState::PkgConfig(PkgConfigState { libs })
     }
@@ -111,12 +127,12 @@ impl DepState {
             &DepState::PkgConfig(_) => {
                 pkg_config::Config::new()
                     .cargo_metadata(true)
-                    .probe(PKGCONFIG_LIBS)
+                    .probe(&pkgconfig_libs())
                     .unwrap();
             }
 
             &DepState::VcPkg(_) => {
-                if target.contains("-linux-") {
+                if target.contains("-linux-") && use_icu() {
                     // add icudata to the end of the list of libs as vcpkg-rs
                     // does not order individual libraries as a single pass
                     // linker requires.
@@ -153,11 +169,7 @@ fn main() {
         DepState::default()
     };
 
-    // Actually I'm not 100% sure that I can't compile the C and C++ code
-    // into one library, but who cares?
-
     let mut ccfg = cc::Build::new();
-    let mut cppcfg = cc::Build::new();
     let cflags = [
         "-Wall",
         "-Wcast-qual",
@@ -206,62 +218,22 @@ fn main() {
         .define("ZLIB_CONST", "1")
         .include(".");
 
-    let cppflags = [
-        "-std=c++14",
-        "-Wall",
-        "-Wdate-time",
-        "-Wendif-labels",
-        "-Wextra",
-        "-Wformat=2",
-        "-Wlogical-op",
-        "-Wmissing-declarations",
-        "-Wmissing-include-dirs",
-        "-Wpointer-arith",
-        "-Wredundant-decls",
-        "-Wsuggest-attribute=noreturn",
-        "-Wsuggest-attribute=format",
-        "-Wshadow",
-        "-Wswitch-bool",
-        "-Wundef",
-        // TODO: Fix existing warnings before enabling these:
-        // "-Wdouble-promotion",
-        // "-Wcast-align",
-        // "-Wconversion",
-        // "-Wmissing-variable-declarations",
-        "-Wextra-semi",
-        // "-Wsuggest-attribute=const",
-        // "-Wsuggest-attribute=pure",
-        // "-Wunreachable-code-aggresive",
-        "-Wno-unused-parameter",
-        "-Wno-implicit-fallthrough",
-        "-fno-exceptions",
-        "-fno-rtti",
-    ];
-
-    for flag in &cppflags {
-        cppcfg.flag_if_supported(flag);
-    }
     ccfg.flag("-Wall")
-        .file("tectonic/stub_icu.c")
         .file("tectonic/stub_stdio.c")
         .include(".");
 
-    cppcfg
-        .cpp(true)
-        .flag("-Wall")
-        .file("tectonic/teckit-Engine.cpp")
-        .include(".");
+    if use_icu() {
+        ccfg.file("tectonic/stub_icu.c");
+    }
 
     dep_state.foreach_include_path(|p| {
         ccfg.include(p);
-        cppcfg.include(p);
     });
 
     // Platform-specific adjustments:
 
     if cfg!(target_os = "macos") {
         ccfg.define("XETEX_MAC", Some("1"));
-        cppcfg.define("XETEX_MAC", Some("1"));
 
         println!("cargo:rustc-link-lib=framework=Foundation");
         println!("cargo:rustc-link-lib=framework=CoreFoundation");
@@ -272,21 +244,17 @@ fn main() {
 
     if cfg!(target_endian = "big") {
         ccfg.define("WORDS_BIGENDIAN", "1");
-        cppcfg.define("WORDS_BIGENDIAN", "1");
     }
 
     if target.contains("-msvc") {
         ccfg.flag("/EHsc");
-        cppcfg.flag("/EHsc");
         if rustflags.contains("+crt-static") {
             ccfg.define("GRAPHITE2_STATIC", None);
-            cppcfg.define("GRAPHITE2_STATIC", None);
         }
     }
 
     // OK, back to generic build rules.
     ccfg.compile("libtectonic_c.a");
-    cppcfg.compile("libtectonic_cpp.a");
 
     dep_state.emit_late_extras(&target);
 
@@ -10,14 +10,17 @@
 
 use std::io::Write;
 
+use crate::bibtex_unicode::{self, CaseConversion, SortCollator};
 use crate::core_memory::{xmalloc, xmalloc_array, xrealloc};
 use bridge::{
-    ttstub_input_close, ttstub_input_getc, ttstub_input_open, ttstub_output_close,
-    ttstub_output_open, ttstub_output_open_stdout, ttstub_output_putc,
+    ttstub_input_close, ttstub_input_getc, ttstub_input_open, ttstub_issue_warning_slice,
+    ttstub_output_close, ttstub_output_open, ttstub_output_open_stdout, ttstub_output_putc,
 };
 use libc::{free, strcpy, strlen};
+use std::cmp::Ordering;
+use std::ffi::CStr;
 use std::panic;
-use std::ptr;
+use std::{ptr, slice};
 
 use bridge::{TTHistory, TTInputFormat};
 
@@ -74,6 +77,76 @@ const MAX_GLOB_STRS: i32 = 10;
 const MAX_FIELDS: i32 = 17250;
 const LIT_STK_SIZE: i32 = 100;
 
+/// Upper bounds on how far BibTeX's tables may grow. Every table starts out
+/// at its classic BibTeX size and is enlarged on demand; `None` leaves it
+/// unbounded, except for `aux_depth`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct bibtex_limits_t {
+    /// Bytes in the string pool.
+    pub pool_size: Option<usize>,
+    /// Number of strings; this also bounds the hash table.
+    pub max_strings: Option<usize>,
+    /// Bytes in each of the line buffers.
+    pub buf_size: Option<usize>,
+    /// Number of cited entries.
+    pub max_cites: Option<usize>,
+    /// Number of entry fields, summed over all entries.
+    pub max_fields: Option<usize>,
+    /// Space for the compiled functions of the style file.
+    pub wiz_fn_space: Option<usize>,
+    /// Characters in a global string variable. Longer values are truncated
+    /// with a warning rather than stopping the run.
+    pub glob_str_size: Option<usize>,
+    /// Nesting level of `\@input` auxiliary files. `None` means the classic
+    /// limit of 20 levels, since nesting is never bounded by memory alone.
+    pub aux_depth: Option<usize>,
+}
+
+impl bibtex_limits_t {
+    /// The deepest level of `\@input` nesting that BibTeX will read.
+    pub fn max_aux_depth(&self) -> usize {
+        self.aux_depth.unwrap_or(aux_stack_size as usize)
+    }
+}
+
+/// What a BibTeX warning or error is about.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum bibtex_diagnostic_kind_t {
+    /// A cited key has no entry in any database file.
+    MISSING_ENTRY,
+    /// An entry's `crossref` field names an entry that doesn't exist.
+    BAD_CROSS_REFERENCE,
+    /// An entry cross-references an entry that has a `crossref` of its own.
+    NESTED_CROSS_REFERENCE,
+    /// A problem in a database (`.bib`) file.
+    DATABASE,
+    /// A problem in the style (`.bst`) file, or while executing it.
+    STYLE,
+    /// A message from the style's `warning$` function, such as a missing
+    /// field.
+    STYLE_WARNING,
+    /// A problem in an auxiliary (`.aux`) file.
+    AUX,
+}
+
+/// A warning or error issued by BibTeX. The same text also goes to the
+/// `.blg` log.
+#[derive(Clone, Debug)]
+pub struct bibtex_diagnostic_t {
+    pub kind: bibtex_diagnostic_kind_t,
+    pub is_error: bool,
+    /// The message, without the location BibTeX prints alongside it.
+    pub message: String,
+    /// The cite key of the entry concerned.
+    pub entry: Option<String>,
+    /// The name of the field concerned.
+    pub field: Option<String>,
+    /// The `.bib`, `.bst` or `.aux` file in which the problem was found.
+    pub file: Option<String>,
+    /// The line of `file` on which the problem was found.
+    pub line: Option<usize>,
+}
+
 unsafe fn peekable_open(
     mut path: *const i8,
     mut format: TTInputFormat,
@@ -136,6 +209,24 @@ unsafe fn eoln(peekable: &mut peekable_input_t) -> bool {
     c == '\n' as i32 || c == '\r' as i32 || c == -1i32
 }
 static mut standard_output: Option<OutputHandleWrapper> = None;
+static mut limits: bibtex_limits_t = bibtex_limits_t {
+    pool_size: None,
+    max_strings: None,
+    buf_size: None,
+    max_cites: None,
+    max_fields: None,
+    wiz_fn_space: None,
+    glob_str_size: None,
+    aux_depth: None,
+};
+/// Set when strings are to be treated as UTF-8; see `bibtex_unicode`.
+static mut unicode_mode: bool = false;
+static mut sort_collator: Option<SortCollator> = None;
+static mut diagnostics: Vec<bibtex_diagnostic_t> = Vec::new();
+/* the diagnostic being issued, filled in as its parts are printed */
+static mut diag_info: Option<bibtex_diagnostic_t> = None;
+/* the log line being written; see `note_headline` */
+static mut log_line: Vec<u8> = Vec::new();
 static mut pool_size: i32 = 0;
 static mut MAX_BIB_FILES: usize = 0;
 static mut max_cites: i32 = 0;
@@ -198,13 +289,10 @@ static mut buf_ptr2: buf_pointer = 0;
 static mut scan_result: u8 = 0;
 static mut token_value: i32 = 0;
 static mut aux_name_length: i32 = 0;
-static mut aux_file: [Option<peekable_input_t>; 21] = [
-    None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-    None, None, None, None, None,
-];
-static mut aux_list: [str_number; 21] = [0; 21];
+static mut aux_file: Vec<Option<peekable_input_t>> = Vec::new();
+static mut aux_list: Vec<str_number> = Vec::new();
 static mut aux_ptr: aux_number = 0;
-static mut aux_ln_stack: [i32; 21] = [0; 21];
+static mut aux_ln_stack: Vec<i32> = Vec::new();
 static mut top_lev_str: str_number = 0;
 static mut log_file: Option<OutputHandleWrapper> = None;
 static mut bbl_file: Option<OutputHandleWrapper> = None;
@@ -394,16 +482,72 @@ static mut min_crossrefs: i32 = 0;
 unsafe fn putc_log(c: i32) {
     ttstub_output_putc(log_file.as_mut().unwrap(), c); /* note: global! */
     ttstub_output_putc(standard_output.as_mut().unwrap(), c);
+    capture_log(&[c as u8]);
 }
 
 macro_rules! log {
     ($($arg:tt)*) => {{
         use std::io::Write;
-        log_file.as_mut().unwrap().write_fmt(format_args!($($arg)*)).unwrap();
-        standard_output.as_mut().unwrap().write_fmt(format_args!($($arg)*)).unwrap();
+        let text = format!($($arg)*);
+        log_file.as_mut().unwrap().write_all(text.as_bytes()).unwrap();
+        standard_output.as_mut().unwrap().write_all(text.as_bytes()).unwrap();
+        capture_log(text.as_bytes());
     }}
 }
 
+unsafe fn capture_log(bytes: &[u8]) {
+    for &b in bytes {
+        if b == b'\n' {
+            log_line.clear();
+        } else {
+            log_line.push(b);
+        }
+    }
+}
+fn new_diagnostic(kind: bibtex_diagnostic_kind_t) -> bibtex_diagnostic_t {
+    bibtex_diagnostic_t {
+        kind,
+        is_error: false,
+        message: String::new(),
+        entry: None,
+        field: None,
+        file: None,
+        line: None,
+    }
+}
+/// The diagnostic being issued, which is created with the given kind if
+/// nothing about it has been noted yet.
+unsafe fn diag(kind: bibtex_diagnostic_kind_t) -> &'static mut bibtex_diagnostic_t {
+    diag_info.get_or_insert_with(|| new_diagnostic(kind))
+}
+/// Called where BibTeX starts printing the location of a problem, which it
+/// does on the line holding the message. That line becomes the message of
+/// the diagnostic being issued, unless its call site has set one.
+unsafe fn note_headline(kind: bibtex_diagnostic_kind_t) -> &'static mut bibtex_diagnostic_t {
+    let d = diag(kind);
+    if d.message.is_empty() {
+        let line = String::from_utf8_lossy(&log_line);
+        let line = line.strip_prefix("Warning--").unwrap_or(&line);
+        d.message = line
+            .trim_end_matches(|c| c == '-' || c == ',' || c == ' ')
+            .trim_start()
+            .to_owned();
+    }
+    d
+}
+unsafe fn record_diagnostic(is_error: bool) {
+    let mut d = diag_info
+        .take()
+        .unwrap_or_else(|| new_diagnostic(bibtex_diagnostic_kind_t::STYLE));
+    d.is_error = is_error;
+    diagnostics.push(d);
+}
+/// Returns the warnings and errors issued by the most recent BibTeX run, and
+/// forgets them.
+pub unsafe fn bibtex_take_diagnostics() -> Vec<bibtex_diagnostic_t> {
+    std::mem::take(&mut diagnostics)
+}
+
 unsafe fn mark_warning() {
     if history == TTHistory::WARNING_ISSUED {
         err_count += 1
@@ -411,6 +555,7 @@ unsafe fn mark_warning() {
         history = TTHistory::WARNING_ISSUED;
         err_count = 1i32
     };
+    record_diagnostic(false);
 }
 unsafe fn mark_error() {
     if (history as i32) < (TTHistory::ERROR_ISSUED as i32) {
@@ -419,6 +564,7 @@ unsafe fn mark_error() {
     } else {
         err_count += 1
     };
+    record_diagnostic(true);
 }
 unsafe fn mark_fatal() {
     history = TTHistory::FATAL_ERROR;
@@ -432,33 +578,56 @@ unsafe fn print_confusion() {
     log!("*Please notify the BibTeX maintainer*\n");
     mark_fatal();
 }
+/// The largest size that a table bounded by `cap` may reach.
+fn limit_of(cap: Option<usize>) -> i32 {
+    cap.map_or(i32::MAX - 1, |c| c.min((i32::MAX - 1) as usize) as i32)
+}
+/// Works out the new size of a table that currently holds `size` entries
+/// and has to hold at least `needed`, growing it by `step` at a time. If
+/// that would take it past `cap`, reports the overflow the way BibTeX always
+/// has and aborts the run.
+unsafe fn grow_capacity(size: i32, needed: i32, step: i32, cap: Option<usize>, what: &str) -> i32 {
+    let cap = limit_of(cap);
+    if needed > cap {
+        print_overflow();
+        log!("{} {}\n", what, cap);
+        panic!();
+    }
+    size.saturating_add(step).max(needed).min(cap)
+}
 unsafe fn buffer_overflow() {
+    let new_size = grow_capacity(
+        buf_size,
+        buf_size + 1,
+        BUF_SIZE,
+        limits.buf_size,
+        "buffer size",
+    );
     buffer = xrealloc(
         buffer as *mut libc::c_void,
-        ((buf_size + 20000i32 + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
+        ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
     ) as *mut u8;
     sv_buffer = xrealloc(
         sv_buffer as *mut libc::c_void,
-        ((buf_size + 20000i32 + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
+        ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
     ) as *mut u8;
     ex_buf = xrealloc(
         ex_buf as *mut libc::c_void,
-        ((buf_size + 20000i32 + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
+        ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
     ) as *mut u8;
     out_buf = xrealloc(
         out_buf as *mut libc::c_void,
-        ((buf_size + 20000i32 + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
+        ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
     ) as *mut u8;
     name_tok = xrealloc(
         name_tok as *mut libc::c_void,
-        ((buf_size + 20000i32 + 1i32) as u64)
-            .wrapping_mul(::std::mem::size_of::<buf_pointer>() as u64) as _,
+        ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<buf_pointer>() as u64) as _,
     ) as *mut buf_pointer;
     name_sep_char = xrealloc(
         name_sep_char as *mut libc::c_void,
-        ((buf_size + 20000i32 + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
+        ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
     ) as *mut u8;
-    buf_size = buf_size + 20000i32;
+    buf_size = new_size;
 }
 unsafe fn input_ln(peekable: &mut Option<peekable_input_t>) -> bool {
     last = 0i32;
@@ -499,23 +668,62 @@ unsafe fn out_pool_str(handle: &mut OutputHandleWrapper, mut s: str_number) {
 unsafe fn print_a_pool_str(mut s: str_number) {
     out_pool_str(standard_output.as_mut().unwrap(), s);
     out_pool_str(log_file.as_mut().unwrap(), s);
+    capture_log(pool_str_text(s).as_bytes());
 }
 unsafe fn pool_overflow() {
+    let new_size = grow_capacity(
+        pool_size,
+        pool_size + 1,
+        POOL_SIZE,
+        limits.pool_size,
+        "pool size",
+    );
     str_pool = xrealloc(
         str_pool as *mut libc::c_void,
-        ((pool_size as i64 + 65000 + 1i32 as i64) as u64)
-            .wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
+        ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as _,
     ) as *mut u8;
-    pool_size = (pool_size as i64 + 65000) as i32;
+    pool_size = new_size;
+}
+/// The text of pool string `s`, decoded as UTF-8 for the Unicode mode.
+unsafe fn pool_str_text(s: str_number) -> String {
+    let start = *str_start.offset(s as isize);
+    let end = *str_start.offset((s + 1i32) as isize);
+    let bytes = slice::from_raw_parts(str_pool.offset(start as isize), (end - start) as usize);
+    String::from_utf8_lossy(bytes).into_owned()
+}
+/// Copies `text` into the pool as a new string and pushes it onto the
+/// literal stack.
+unsafe fn push_pool_text(text: &str) {
+    let len = text.len() as i32;
+    while pool_ptr + len > pool_size {
+        pool_overflow();
+    }
+    ptr::copy_nonoverlapping(
+        text.as_ptr(),
+        str_pool.offset(pool_ptr as isize),
+        text.len(),
+    );
+    pool_ptr = pool_ptr + len;
+    push_lit_stk(make_string(), 1i32 as stk_type);
 }
 unsafe fn out_token(handle: &mut OutputHandleWrapper) {
     for i in buf_ptr1..buf_ptr2 {
         ttstub_output_putc(handle, *buffer.offset(i as isize) as i32);
     }
 }
+unsafe fn token_slice() -> &'static [u8] {
+    slice::from_raw_parts(
+        buffer.offset(buf_ptr1 as isize),
+        (buf_ptr2 - buf_ptr1) as usize,
+    )
+}
+unsafe fn token_text() -> String {
+    String::from_utf8_lossy(token_slice()).into_owned()
+}
 unsafe fn print_a_token() {
     out_token(standard_output.as_mut().unwrap());
     out_token(log_file.as_mut().unwrap());
+    capture_log(token_slice());
 }
 unsafe fn print_bad_input_line() {
     let mut bf_ptr: buf_pointer = 0;
@@ -561,7 +769,6 @@ unsafe fn print_bad_input_line() {
         log!("(Error may have been on previous line)\n");
         /*any_value */
     }
-    mark_error();
 }
 unsafe fn print_skipping_whatever_remains() {
     log!("I\'m skipping whatever remains of this ");
@@ -588,11 +795,15 @@ unsafe fn log_pr_aux_name() {
     ttstub_output_putc(lg, '\n' as i32);
 }
 unsafe fn aux_err_print() {
+    let d = note_headline(bibtex_diagnostic_kind_t::AUX);
+    d.file = Some(pool_str_text(aux_list[aux_ptr as usize]));
+    d.line = Some(aux_ln_stack[aux_ptr as usize] as usize);
     log!("---line {} of file ", aux_ln_stack[aux_ptr as usize]);
     print_aux_name();
     print_bad_input_line();
     print_skipping_whatever_remains();
     log!("command\n");
+    mark_error();
 }
 unsafe fn aux_err_illegal_another_print(cmd_num: i32) {
     log!("Illegal, another \\bib");
@@ -645,27 +856,32 @@ unsafe fn hash_cite_confusion() {
 }
 unsafe fn check_cite_overflow(mut last_cite: cite_number) {
     if last_cite == max_cites {
+        let new_size = grow_capacity(
+            max_cites,
+            max_cites + 1,
+            MAX_CITES,
+            limits.max_cites,
+            "number of cites",
+        );
         cite_list = xrealloc(
             cite_list as *mut libc::c_void,
-            ((max_cites + 750i32 + 1i32) as u64)
-                .wrapping_mul(::std::mem::size_of::<str_number>() as u64) as _,
+            ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<str_number>() as u64)
+                as _,
         ) as *mut str_number;
         type_list = xrealloc(
             type_list as *mut libc::c_void,
-            ((max_cites + 750i32 + 1i32) as u64)
-                .wrapping_mul(::std::mem::size_of::<hash_ptr2>() as u64) as _,
+            ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<hash_ptr2>() as u64) as _,
         ) as *mut hash_ptr2;
         entry_exists = xrealloc(
             entry_exists as *mut libc::c_void,
-            ((max_cites + 750i32 + 1i32) as u64).wrapping_mul(::std::mem::size_of::<bool>() as u64)
-                as _,
+            ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<bool>() as u64) as _,
         ) as *mut bool;
         cite_info = xrealloc(
             cite_info as *mut libc::c_void,
-            ((max_cites + 750i32 + 1i32) as u64)
-                .wrapping_mul(::std::mem::size_of::<str_number>() as u64) as _,
+            ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<str_number>() as u64)
+                as _,
         ) as *mut str_number;
-        max_cites = max_cites + 750i32;
+        max_cites = new_size;
         while last_cite < max_cites {
             *type_list.offset(last_cite as isize) = 0i32;
             *cite_info.offset(last_cite as isize) = 0i32;
@@ -677,11 +893,16 @@ unsafe fn aux_end1_err_print() {
     log!("I found no ");
 }
 unsafe fn aux_end2_err_print() {
+    note_headline(bibtex_diagnostic_kind_t::AUX).file =
+        Some(pool_str_text(aux_list[aux_ptr as usize]));
     log!("---while reading file ");
     print_aux_name();
     mark_error();
 }
 unsafe fn bst_ln_num_print() {
+    let d = note_headline(bibtex_diagnostic_kind_t::STYLE);
+    d.file = Some(pool_str_text(bst_str) + &pool_str_text(s_bst_extension));
+    d.line = Some(bst_line_num as usize);
     log!("--line {} of file ", bst_line_num);
     print_bst_name();
 }
@@ -689,6 +910,7 @@ unsafe fn bst_err_print_and_look_for_blank_line() {
     putc_log('-' as i32);
     bst_ln_num_print();
     print_bad_input_line();
+    mark_error();
     while last != 0i32 {
         if !input_ln(&mut bst_file) {
             panic!();
@@ -710,19 +932,25 @@ unsafe fn unknwn_function_class_confusion() {
     print_confusion();
     panic!();
 }
-unsafe fn print_fn_class(fn_loc_0: hash_loc) {
+unsafe fn fn_class_name(fn_loc_0: hash_loc) -> &'static str {
     match *fn_type.offset(fn_loc_0 as isize) as i32 {
-        0 => log!("built-in"),
-        1 => log!("wizard-defined"),
-        2 => log!("integer-literal"),
-        3 => log!("string-literal"),
-        4 => log!("field"),
-        5 => log!("integer-entry-variable"),
-        6 => log!("string-entry-variable"),
-        7 => log!("integer-global-variable"),
-        8 => log!("string-global-variable"),
-        _ => unknwn_function_class_confusion(),
-    };
+        0 => "built-in",
+        1 => "wizard-defined",
+        2 => "integer-literal",
+        3 => "string-literal",
+        4 => "field",
+        5 => "integer-entry-variable",
+        6 => "string-entry-variable",
+        7 => "integer-global-variable",
+        8 => "string-global-variable",
+        _ => {
+            unknwn_function_class_confusion();
+            unreachable!()
+        }
+    }
+}
+unsafe fn print_fn_class(fn_loc_0: hash_loc) {
+    log!("{}", fn_class_name(fn_loc_0));
 }
 /*:159*/
 /*160: */
@@ -755,6 +983,11 @@ unsafe fn bst_right_brace_print() {
     log!("\"}}\" is missing in command: ");
 }
 unsafe fn already_seen_function_print(mut seen_fn_loc: hash_loc) {
+    diag(bibtex_diagnostic_kind_t::STYLE).message = format!(
+        "{} is already a type \"{}\" function name",
+        pool_str_text(*hash_text.offset(seen_fn_loc as isize)),
+        fn_class_name(seen_fn_loc),
+    );
     print_a_pool_str(*hash_text.offset(seen_fn_loc as isize));
     log!(" is already a type \"");
     print_fn_class(seen_fn_loc);
@@ -762,6 +995,10 @@ unsafe fn already_seen_function_print(mut seen_fn_loc: hash_loc) {
     bst_err_print_and_look_for_blank_line();
 }
 unsafe fn bib_ln_num_print() {
+    let d = note_headline(bibtex_diagnostic_kind_t::DATABASE);
+    d.file =
+        Some(pool_str_text(*bib_list.offset(bib_ptr as isize)) + &pool_str_text(s_bib_extension));
+    d.line = Some(bib_line_num as usize);
     log!("--line {} of file ", bib_line_num);
     print_bib_name();
 }
@@ -775,6 +1012,7 @@ unsafe fn bib_err_print() {
     } else {
         log!("entry\n");
     };
+    mark_error();
 }
 unsafe fn bib_warn_print() {
     bib_ln_num_print();
@@ -785,12 +1023,19 @@ unsafe fn check_field_overflow(mut total_fields: i32) {
     let mut start_fields: field_loc = 0;
     if total_fields > max_fields {
         start_fields = max_fields;
+        let new_size = grow_capacity(
+            total_fields,
+            total_fields,
+            MAX_FIELDS,
+            limits.max_fields,
+            "number of fields",
+        );
         field_info = xrealloc(
             field_info as *mut libc::c_void,
-            ((total_fields + 17250i32 + 1i32) as u64)
-                .wrapping_mul(::std::mem::size_of::<str_number>() as u64) as _,
+            ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<str_number>() as u64)
+                as _,
         ) as *mut str_number;
-        max_fields = total_fields + 17250i32;
+        max_fields = new_size;
         let mut for_end: i32 = 0;
         f_ptr = start_fields;
         for_end = max_fields - 1i32;
@@ -831,10 +1076,12 @@ unsafe fn bib_field_too_long_print() {
     log!("Your field is more than {} characters", buf_size);
     bib_err_print();
 }
-unsafe fn macro_warn_print() {
+unsafe fn macro_warn_print(problem: &str) {
+    diag(bibtex_diagnostic_kind_t::DATABASE).message =
+        format!("string name \"{}\" is {}", token_text(), problem);
     log!("Warning--string name \"");
     print_a_token();
-    log!("\" is ");
+    log!("\" is {}\n", problem);
 }
 unsafe fn bib_id_print() {
     if scan_result as i32 == 0i32 {
@@ -869,12 +1116,25 @@ unsafe fn bad_cross_reference_print(mut s: str_number) {
     print_a_pool_str(s);
 }
 unsafe fn nonexistent_cross_reference_error() {
+    let entry = pool_str_text(*cite_list.offset(cite_ptr as isize));
+    let d = diag(bibtex_diagnostic_kind_t::BAD_CROSS_REFERENCE);
+    d.message = format!(
+        "A bad cross reference: entry \"{}\" refers to entry \"{}\", which doesn't exist",
+        entry,
+        pool_str_text(*field_info.offset(field_ptr as isize)),
+    );
+    d.entry = Some(entry);
+    d.field = Some("crossref".to_owned());
     log!("A bad cross reference-");
     bad_cross_reference_print(*field_info.offset(field_ptr as isize));
     log!("\", which doesn\'t exist\n");
     mark_error();
 }
 unsafe fn print_missing_entry(mut s: str_number) {
+    let key = pool_str_text(s);
+    let d = diag(bibtex_diagnostic_kind_t::MISSING_ENTRY);
+    d.message = format!("I didn't find a database entry for \"{}\"", key);
+    d.entry = Some(key);
     log!("Warning--I didn\'t find a database entry for \"");
     print_a_pool_str(s);
     putc_log('\"' as i32);
@@ -882,7 +1142,9 @@ unsafe fn print_missing_entry(mut s: str_number) {
     mark_warning();
 }
 unsafe fn bst_ex_warn_print() {
+    let d = note_headline(bibtex_diagnostic_kind_t::STYLE);
     if mess_with_entries {
+        d.entry = Some(pool_str_text(*cite_list.offset(cite_ptr as isize)));
         log!(" for entry ");
         print_a_pool_str(*cite_list.offset(cite_ptr as isize));
     }
@@ -892,7 +1154,9 @@ unsafe fn bst_ex_warn_print() {
     mark_error();
 }
 unsafe fn bst_mild_ex_warn_print() {
+    let d = note_headline(bibtex_diagnostic_kind_t::STYLE);
     if mess_with_entries {
+        d.entry = Some(pool_str_text(*cite_list.offset(cite_ptr as isize)));
         log!(" for entry ");
         print_a_pool_str(*cite_list.offset(cite_ptr as isize));
     }
@@ -980,6 +1244,29 @@ unsafe fn output_bbl_line() {
     bbl_line_num += 1;
     out_buf_length = 0i32;
 }
+/// Widens every global string variable so that it can hold `needed`
+/// characters, or as many as the configured limit allows. The caller still
+/// truncates anything longer, as BibTeX always has.
+unsafe fn grow_glob_str_size(needed: i32) {
+    let new_size = glob_str_size
+        .saturating_add(GLOB_STR_SIZE)
+        .max(needed)
+        .min(limit_of(limits.glob_str_size));
+    if new_size <= glob_str_size {
+        return;
+    }
+    let new_strs = xmalloc((max_glob_strs as usize * (new_size as usize + 1)) as _) as *mut u8;
+    for i in 0..max_glob_strs {
+        ptr::copy_nonoverlapping(
+            global_strs.offset((i * (glob_str_size + 1i32)) as isize),
+            new_strs.offset((i * (new_size + 1i32)) as isize),
+            *glb_str_end.offset(i as isize) as usize,
+        );
+    }
+    free(global_strs as *mut libc::c_void);
+    global_strs = new_strs;
+    glob_str_size = new_size;
+}
 unsafe fn bst_1print_string_size_exceeded() {
     log!("Warning--you\'ve exceeded ");
 }
@@ -1031,9 +1318,19 @@ unsafe fn add_extension(mut ext: str_number) {
 }
 unsafe fn make_string() -> str_number {
     if str_ptr == max_strings {
-        print_overflow();
-        log!("number of strings {}\n", max_strings);
-        panic!();
+        let new_size = grow_capacity(
+            max_strings,
+            max_strings + 1,
+            MAX_STRINGS,
+            limits.max_strings,
+            "number of strings",
+        );
+        str_start = xrealloc(
+            str_start as *mut libc::c_void,
+            ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<pool_pointer>() as u64)
+                as _,
+        ) as *mut pool_pointer;
+        max_strings = new_size;
     }
     str_ptr = str_ptr + 1i32;
     *str_start.offset(str_ptr as isize) = pool_ptr;
@@ -1122,6 +1419,51 @@ unsafe fn upper_case(mut buf: buf_type, mut bf_ptr: buf_pointer, mut len: buf_po
         }
     };
 }
+/// Adds fresh slots to the top of the hash table once the free ones have
+/// run out. Hash locations are stored all over BibTeX's tables, so nothing
+/// can be rehashed: the primary slots stay where they are and the new space
+/// only serves collision chains, which `str_lookup` allocates downwards from
+/// `hash_used`.
+unsafe fn grow_hash_table() {
+    let new_max = grow_capacity(
+        hash_max,
+        hash_max + 1,
+        MAX_STRINGS,
+        limits.max_strings,
+        "hash size",
+    );
+    let n = (new_max + 1i32) as usize;
+    hash_next = xrealloc(
+        hash_next as *mut libc::c_void,
+        (n * ::std::mem::size_of::<hash_pointer>()) as _,
+    ) as *mut hash_pointer;
+    hash_text = xrealloc(
+        hash_text as *mut libc::c_void,
+        (n * ::std::mem::size_of::<str_number>()) as _,
+    ) as *mut str_number;
+    hash_ilk = xrealloc(
+        hash_ilk as *mut libc::c_void,
+        (n * ::std::mem::size_of::<str_ilk>()) as _,
+    ) as *mut str_ilk;
+    ilk_info = xrealloc(
+        ilk_info as *mut libc::c_void,
+        (n * ::std::mem::size_of::<i32>()) as _,
+    ) as *mut i32;
+    fn_type = xrealloc(
+        fn_type as *mut libc::c_void,
+        (n * ::std::mem::size_of::<fn_class>()) as _,
+    ) as *mut fn_class;
+    for k in hash_max + 1..=new_max {
+        *hash_next.offset(k as isize) = 0i32;
+        *hash_text.offset(k as isize) = 0i32;
+        *hash_ilk.offset(k as isize) = 0;
+        *ilk_info.offset(k as isize) = 0i32;
+        *fn_type.offset(k as isize) = 0;
+    }
+    hash_max = new_max;
+    hash_size = new_max;
+    hash_used = hash_max + 1i32;
+}
 unsafe fn str_lookup(
     mut buf: buf_type,
     mut j: buf_pointer,
@@ -1165,9 +1507,7 @@ unsafe fn str_lookup(
             if *hash_text.offset(p as isize) > 0i32 {
                 loop {
                     if hash_used == 1i32 {
-                        print_overflow();
-                        log!("hash size {}\n", hash_size);
-                        panic!();
+                        grow_hash_table();
                     }
                     hash_used = hash_used - 1i32;
                     if *hash_text.offset(hash_used as isize) == 0i32 {
@@ -1298,6 +1638,16 @@ unsafe fn swap(mut swap1: cite_number, mut swap2: cite_number) {
     *cite_info.offset(swap2 as isize) = *cite_info.offset(swap1 as isize);
     *cite_info.offset(swap1 as isize) = innocent_bystander;
 }
+/// The bytes of the entry string at `ptr`, which ends at an `end_of_string`
+/// marker or after `ent_str_size` characters.
+unsafe fn sort_key<'a>(ptr: str_ent_loc) -> &'a [u8] {
+    let key = slice::from_raw_parts(
+        entry_strs.offset((ptr * (ent_str_size + 1i32)) as isize),
+        (ent_str_size + 1i32) as usize,
+    );
+    let len = key.iter().position(|&c| c == 127).unwrap_or(key.len());
+    &key[..len]
+}
 unsafe fn less_than(mut arg1: cite_number, mut arg2: cite_number) -> bool {
     let mut char_ptr: i32 = 0;
     let mut ptr1: str_ent_loc = 0;
@@ -1306,6 +1656,15 @@ unsafe fn less_than(mut arg1: cite_number, mut arg2: cite_number) -> bool {
     let mut char2: u8 = 0;
     ptr1 = arg1 * num_ent_strs + sort_key_num;
     ptr2 = arg2 * num_ent_strs + sort_key_num;
+    if let Some(collator) = sort_collator.as_ref() {
+        let key1 = sort_key(ptr1);
+        let key2 = sort_key(ptr2);
+        return match collator.compare(key1, key2) {
+            Ordering::Less => true,
+            Ordering::Greater => false,
+            Ordering::Equal => arg1 < arg2,
+        };
+    }
     char_ptr = 0i32;
     loop {
         char1 = *entry_strs.offset((ptr1 * (ent_str_size + 1i32) + char_ptr) as isize);
@@ -2238,13 +2597,19 @@ unsafe fn scan_fn_def(mut fn_hash_loc: hash_loc) {
         single_fn_space = single_fn_space + 100i32
     }
     single_ptr = single_ptr + 1i32;
-    while single_ptr + wiz_def_ptr > wiz_fn_space {
+    if single_ptr + wiz_def_ptr > wiz_fn_space {
+        let new_size = grow_capacity(
+            wiz_fn_space,
+            single_ptr + wiz_def_ptr,
+            WIZ_FN_SPACE,
+            limits.wiz_fn_space,
+            "wizard-defined function space",
+        );
         wiz_functions = xrealloc(
             wiz_functions as *mut libc::c_void,
-            ((wiz_fn_space + 3000i32 + 1i32) as u64)
-                .wrapping_mul(::std::mem::size_of::<hash_ptr2>() as u64) as _,
+            ((new_size + 1i32) as u64).wrapping_mul(::std::mem::size_of::<hash_ptr2>() as u64) as _,
         ) as *mut hash_ptr2;
-        wiz_fn_space = wiz_fn_space + 3000i32
+        wiz_fn_space = new_size
     }
     *ilk_info.offset(fn_hash_loc as isize) = wiz_def_ptr;
     copy_ptr = 0i32;
@@ -2528,16 +2893,14 @@ unsafe fn scan_a_field_token_and_eat_white() -> bool {
                         /*n_bib_string */
                         if macro_name_loc == cur_macro_loc {
                             store_token = false;
-                            macro_warn_print();
-                            log!("used in its own definition\n");
+                            macro_warn_print("used in its own definition");
                             bib_warn_print();
                         }
                     }
                 }
                 if !hash_found {
                     store_token = false;
-                    macro_warn_print();
-                    log!("undefined\n");
+                    macro_warn_print("undefined");
                     bib_warn_print();
                 }
                 if store_token {
@@ -2660,6 +3023,12 @@ unsafe fn scan_and_store_the_field_value_and_eat_white() -> bool {
             }
             if *field_info.offset(field_ptr as isize) != 0i32 {
                 /*missing */
+                let entry = pool_str_text(*cite_list.offset(entry_cite_ptr as isize));
+                let field = pool_str_text(*hash_text.offset(field_name_loc as isize));
+                let d = diag(bibtex_diagnostic_kind_t::DATABASE);
+                d.message = format!("I'm ignoring {}'s extra \"{}\" field", entry, field);
+                d.entry = Some(entry);
+                d.field = Some(field);
                 log!("Warning--I\'m ignoring ");
                 print_a_pool_str(*cite_list.offset(entry_cite_ptr as isize));
                 log!("\'s extra \"");
@@ -3086,12 +3455,24 @@ unsafe fn figure_out_the_formatted_name() {
                                     == 2i32
                                 {
                                     /*alpha */
-                                    if ex_buf_ptr == buf_size {
+                                    let token = slice::from_raw_parts(
+                                        sv_buffer.offset(name_bf_ptr as isize),
+                                        (name_bf_xptr - name_bf_ptr) as usize,
+                                    );
+                                    let letter_len = if unicode_mode {
+                                        bibtex_unicode::first_grapheme_len(token) as i32
+                                    } else {
+                                        1i32
+                                    };
+                                    while ex_buf_ptr + letter_len > buf_size {
                                         buffer_overflow();
                                     }
-                                    *ex_buf.offset(ex_buf_ptr as isize) =
-                                        *sv_buffer.offset(name_bf_ptr as isize);
-                                    ex_buf_ptr = ex_buf_ptr + 1i32;
+                                    ptr::copy_nonoverlapping(
+                                        sv_buffer.offset(name_bf_ptr as isize),
+                                        ex_buf.offset(ex_buf_ptr as isize),
+                                        letter_len as usize,
+                                    );
+                                    ex_buf_ptr = ex_buf_ptr + letter_len;
                                     break;
                                 } else {
                                     if name_bf_ptr + 1i32 < name_bf_xptr
@@ -3301,6 +3682,10 @@ unsafe fn pop_lit_stk(mut pop_lit: *mut i32, mut pop_type: *mut stk_type) {
 unsafe fn print_wrong_stk_lit(mut stk_lt: i32, mut stk_tp1: stk_type, mut stk_tp2: stk_type) {
     if stk_tp1 as i32 != 4i32 {
         /*stk_empty */
+        if stk_tp1 as i32 == 3i32 {
+            /*stk_field_missing */
+            diag(bibtex_diagnostic_kind_t::STYLE).field = Some(pool_str_text(stk_lt));
+        }
         print_stk_lit(stk_lt, stk_tp1);
         match stk_tp2 as i32 {
             0 => log!(", not an integer,"),
@@ -3728,6 +4113,9 @@ unsafe fn x_gets() {
                         glob_chr_ptr = 0i32;
                         sp_ptr = *str_start.offset(pop_lit2 as isize);
                         sp_end = *str_start.offset((pop_lit2 + 1i32) as isize);
+                        if sp_end - sp_ptr > glob_str_size {
+                            grow_glob_str_size(sp_end - sp_ptr);
+                        }
                         if sp_end - sp_ptr > glob_str_size {
                             bst_1print_string_size_exceeded();
                             log!("{}, the global", glob_str_size);
@@ -3844,6 +4232,20 @@ unsafe fn x_change_case() {
             log!(" is an illegal case-conversion string");
             bst_ex_warn_print();
         }
+        if unicode_mode && conversion_type != 3 {
+            let conversion = match conversion_type {
+                0 => CaseConversion::TitleLowers,
+                1 => CaseConversion::AllLowers,
+                _ => CaseConversion::AllUppers,
+            };
+            let (text, unbalanced) =
+                bibtex_unicode::change_case(&pool_str_text(pop_lit2), conversion);
+            if unbalanced {
+                braces_unbalanced_complaint(pop_lit2);
+            }
+            push_pool_text(&text);
+            return;
+        }
         ex_buf_length = 0i32;
         add_buf_pool(pop_lit2);
         brace_level = 0i32;
@@ -4436,6 +4838,8 @@ unsafe fn x_purify() {
         /*stk_str */
         print_wrong_stk_lit(pop_lit1, pop_typ1, 1i32 as stk_type); /*space */
         push_lit_stk(s_null, 1i32 as stk_type);
+    } else if unicode_mode {
+        push_pool_text(&bibtex_unicode::purify(&pool_str_text(pop_lit1)));
     } else {
         ex_buf_length = 0i32;
         add_buf_pool(pop_lit1);
@@ -4563,6 +4967,9 @@ unsafe fn x_substring() {
         /*stk_str */
         print_wrong_stk_lit(pop_lit3, pop_typ3, 1i32 as stk_type); /*439: */
         push_lit_stk(s_null, 1i32 as stk_type); /*441: */
+    } else if unicode_mode {
+        let text = bibtex_unicode::substring(&pool_str_text(pop_lit3), pop_lit2, pop_lit1);
+        push_pool_text(&text);
     } else {
         sp_length =
             *str_start.offset((pop_lit3 + 1i32) as isize) - *str_start.offset(pop_lit3 as isize);
@@ -4650,6 +5057,9 @@ unsafe fn x_text_length() {
         /*stk_str */
         print_wrong_stk_lit(pop_lit1, pop_typ1, 1i32 as stk_type);
         push_lit_stk(s_null, 1i32 as stk_type);
+    } else if unicode_mode {
+        let n = bibtex_unicode::text_length(&pool_str_text(pop_lit1));
+        push_lit_stk(n as i32, 0i32 as stk_type);
     } else {
         num_text_chars = 0i32;
         sp_ptr = *str_start.offset(pop_lit1 as isize);
@@ -4703,6 +5113,9 @@ unsafe fn x_text_prefix() {
     } else if pop_lit1 <= 0i32 {
         push_lit_stk(s_null, 1i32 as stk_type);
         return;
+    } else if unicode_mode {
+        let text = bibtex_unicode::text_prefix(&pool_str_text(pop_lit2), pop_lit1 as usize);
+        push_pool_text(&text);
     } else {
         sp_ptr = *str_start.offset(pop_lit2 as isize);
         sp_end = *str_start.offset((pop_lit2 + 1i32) as isize);
@@ -4781,6 +5194,11 @@ unsafe fn x_warning() {
         /*stk_str */
         print_wrong_stk_lit(pop_lit1, pop_typ1, 1i32 as stk_type);
     } else {
+        let d = diag(bibtex_diagnostic_kind_t::STYLE_WARNING);
+        d.message = pool_str_text(pop_lit1);
+        if mess_with_entries {
+            d.entry = Some(pool_str_text(*cite_list.offset(cite_ptr as isize)));
+        }
         log!("Warning--");
         print_lit(pop_lit1, pop_typ1);
         mark_warning();
@@ -5345,13 +5763,19 @@ unsafe fn aux_input_command() {
         return;
     }
     aux_ptr = aux_ptr + 1i32;
-    if aux_ptr == 20i32 {
+    let depth = limits.max_aux_depth();
+    if aux_ptr as usize > depth {
         print_a_token();
         log!(": ");
         print_overflow();
-        log!("auxiliary file depth {}\n", 20,);
+        log!("auxiliary file depth {}\n", depth);
         panic!();
     }
+    if aux_ptr as usize == aux_file.len() {
+        aux_file.push(None);
+        aux_list.push(0);
+        aux_ln_stack.push(0);
+    }
     aux_extension_ok = true;
     if buf_ptr2 - buf_ptr1
         < *str_start.offset((s_aux_extension + 1i32) as isize)
@@ -5380,18 +5804,35 @@ unsafe fn aux_input_command() {
         *hash_text.offset(
             str_lookup(buffer, buf_ptr1, buf_ptr2 - buf_ptr1, 3i32 as str_ilk, true) as isize,
         );
+    let name = pool_str_text(aux_list[aux_ptr as usize]);
     if hash_found {
+        diag(bibtex_diagnostic_kind_t::AUX).message = format!("Already encountered file {}", name);
         log!("Already encountered file ");
         print_aux_name();
         aux_ptr = aux_ptr - 1i32;
         aux_err_print();
         return;
     }
+    /* a different spelling of a file being read would include it forever */
+    if (0..aux_ptr).any(|p| {
+        aux_path_components(&pool_str_text(aux_list[p as usize])) == aux_path_components(&name)
+    }) {
+        diag(bibtex_diagnostic_kind_t::AUX).message =
+            format!("Auxiliary file {} is already being read", name);
+        log!("Auxiliary file ");
+        print_a_pool_str(aux_list[aux_ptr as usize]);
+        log!(" is already being read\n");
+        aux_ptr -= 1;
+        aux_err_print();
+        return;
+    }
     start_name(aux_list[aux_ptr as usize]);
     name_ptr = name_length;
     *name_of_file.offset(name_ptr as isize) = 0i32 as u8;
     aux_file[aux_ptr as usize] = peekable_open(name_of_file as *mut i8, TTInputFormat::TEX);
     if aux_file[aux_ptr as usize].is_none() {
+        diag(bibtex_diagnostic_kind_t::AUX).message =
+            format!("I couldn't open auxiliary file {}", name);
         log!("I couldn\'t open auxiliary file ");
         print_aux_name();
         aux_ptr -= 1;
@@ -5402,6 +5843,25 @@ unsafe fn aux_input_command() {
     print_aux_name();
     aux_ln_stack[aux_ptr as usize] = 0i32;
 }
+/// The components of the file name `name`, with `.` components and repeated
+/// slashes dropped and `..` components applied, so that two spellings of the
+/// same auxiliary file compare equal.
+fn aux_path_components(name: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    if name.starts_with('/') {
+        parts.push("");
+    }
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().map_or(false, |p| *p != ".." && !p.is_empty()) => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts
+}
 unsafe fn pop_the_aux_stack() -> i32 {
     peekable_close(aux_file[aux_ptr as usize].take());
     if aux_ptr == 0i32 {
@@ -6362,6 +6822,12 @@ unsafe fn get_bib_command_or_entry_and_process() {
             *type_list.offset(entry_cite_ptr as isize) = entry_type_loc
         } else {
             *type_list.offset(entry_cite_ptr as isize) = undefined;
+            let d = diag(bibtex_diagnostic_kind_t::DATABASE);
+            d.message = format!(
+                "entry type for \"{}\" isn't style-file defined",
+                token_text(),
+            );
+            d.entry = Some(pool_str_text(*cite_list.offset(entry_cite_ptr as isize)));
             log!("Warning--entry type for \"");
             print_a_token();
             log!("\" isn\'t style-file defined\n");
@@ -6565,6 +7031,15 @@ unsafe fn bst_read_command() {
                         /*missing */
                         /*missing */
                         /*283: */
+                        let entry = pool_str_text(*cite_list.offset(cite_ptr as isize));
+                        let d = diag(bibtex_diagnostic_kind_t::NESTED_CROSS_REFERENCE);
+                        d.message = format!(
+                            "you've nested cross references: entry \"{}\" refers to entry \"{}\", which also refers to something",
+                            entry,
+                            pool_str_text(*cite_list.offset(cite_parent_ptr as isize)),
+                        );
+                        d.entry = Some(entry);
+                        d.field = Some("crossref".to_owned());
                         log!("Warning--you\'ve nested cross references");
                         bad_cross_reference_print(*cite_list.offset(cite_parent_ptr as isize));
                         log!("\", which also refers to something\n");
@@ -6868,8 +7343,10 @@ unsafe fn setup_params() {
         hash_size = 5000i32
     } /*other_lex */
     hash_max = hash_size + 1i32 - 1i32; /*alpha */
-    end_of_def = hash_max + 1i32; /*illegal */
-    undefined = hash_max + 1i32; /*illegal */
+    /* These markers used to sit just past the end of the hash table, but the
+     * table can now grow, so they're put beyond its largest possible size. */
+    end_of_def = i32::MAX; /*illegal */
+    undefined = i32::MAX; /*illegal */
 }
 unsafe fn compute_hash_prime() {
     let mut hash_want: i32 = 0; /*white_space */
@@ -7151,7 +7628,28 @@ unsafe fn initialize(mut aux_file_name: *const i8) -> i32 {
    Copyright 2017 the Tectonic Project
    Licensed under the MIT License.
 */
-pub(crate) unsafe fn bibtex_main(mut aux_file_name: *const i8) -> TTHistory {
+pub(crate) unsafe fn bibtex_main(
+    mut aux_file_name: *const i8,
+    mut run_limits: bibtex_limits_t,
+    mut unicode_locale: *const i8,
+) -> TTHistory {
+    limits = run_limits;
+    diagnostics.clear();
+    diag_info = None;
+    log_line.clear();
+    unicode_mode = !unicode_locale.is_null();
+    sort_collator = None;
+    if unicode_mode {
+        let locale = CStr::from_ptr(unicode_locale).to_string_lossy();
+        sort_collator = Some(match SortCollator::new(&locale) {
+            Ok(c) => c,
+            Err(e) => {
+                let msg = format!("BibTeX: {}; sorting with the root collation\0", e);
+                ttstub_issue_warning_slice(msg.as_bytes());
+                SortCollator::new("").unwrap()
+            }
+        });
+    }
     pool_size = POOL_SIZE;
     buf_size = BUF_SIZE;
     MAX_BIB_FILES = MAX_BIBFILES;
@@ -7160,6 +7658,9 @@ pub(crate) unsafe fn bibtex_main(mut aux_file_name: *const i8) -> TTHistory {
     max_cites = MAX_CITES;
     wiz_fn_space = WIZ_FN_SPACE;
     lit_stk_size = LIT_STK_SIZE;
+    aux_file = (0..=aux_stack_size).map(|_| None).collect();
+    aux_list = vec![0; aux_stack_size as usize + 1];
+    aux_ln_stack = vec![0; aux_stack_size as usize + 1];
     standard_output = ttstub_output_open_stdout();
     if standard_output.is_none() {
         return TTHistory::FATAL_ERROR;
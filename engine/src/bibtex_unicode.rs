@@ -0,0 +1,483 @@
+/* engine/src/bibtex_unicode.rs: the Unicode mode of the BibTeX engine
+   Copyright 2020 the Tectonic Project
+   Licensed under the MIT License.
+*/
+
+//! Unicode-aware versions of the BibTeX built-ins that look inside strings.
+//!
+//! Classic BibTeX treats its input as bytes, which goes wrong as soon as a
+//! name or title contains UTF-8: `change.case$` only knows about ASCII
+//! letters, `text.length$` and `substring$` count bytes and may split a
+//! character in two, and sorting compares byte values. In Unicode mode, in
+//! the spirit of bibtexu, the engine hands strings to the functions in this
+//! module instead. They follow the classic algorithms (including the
+//! handling of "special characters" such as `{\"o}`) but work on grapheme
+//! clusters and use full Unicode case mappings, and sort keys are compared
+//! with a locale-specific collation.
+
+use icu_collator::{Collator, CollatorOptions};
+use icu_locid::Locale;
+use std::cmp::Ordering;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The case conversions performed by `change.case$`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum CaseConversion {
+    /// "t": lowercase everything but the first character and anything
+    /// following a colon and white space.
+    TitleLowers,
+    /// "l"
+    AllLowers,
+    /// "u"
+    AllUppers,
+}
+
+/// The control sequences that BibTeX treats as letters inside special
+/// characters, in the order of their `ilk_info` codes in the engine.
+const CONTROL_SEQS: [&str; 13] = [
+    "i", "j", "oe", "OE", "ae", "AE", "aa", "AA", "o", "O", "l", "L", "ss",
+];
+
+fn control_seq(name: &str) -> Option<usize> {
+    CONTROL_SEQS.iter().position(|&s| s == name)
+}
+
+/// BibTeX's `white_space` lexical class, extended to Unicode.
+fn is_white(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\r' || (!c.is_ascii() && c.is_whitespace())
+}
+
+/// BibTeX's `sep_char` lexical class.
+fn is_sep(c: char) -> bool {
+    c == '-' || c == '~'
+}
+
+/// Whether a grapheme cluster counts as a letter or digit.
+fn is_alphanumeric(g: &str) -> bool {
+    matches!(g.chars().next(), Some(c) if c.is_alphanumeric())
+}
+
+fn convert(out: &mut String, text: &str, conversion: CaseConversion) {
+    match conversion {
+        CaseConversion::TitleLowers | CaseConversion::AllLowers => {
+            out.extend(text.chars().flat_map(char::to_lowercase))
+        }
+        CaseConversion::AllUppers => out.extend(text.chars().flat_map(char::to_uppercase)),
+    }
+}
+
+/// The Unicode version of `change.case$`. The second element of the result
+/// is true if the braces in `text` are unbalanced, which the engine reports
+/// as a warning.
+pub(crate) fn change_case(text: &str, conversion: CaseConversion) -> (String, bool) {
+    let chars: Vec<char> = text.chars().collect();
+    let n = chars.len();
+    let mut out = String::with_capacity(text.len());
+    let mut unbalanced = false;
+    let mut brace_level = 0;
+    let mut prev_colon = false;
+    let mut i = 0;
+
+    while i < n {
+        let c = chars[i];
+
+        if c == '{' {
+            out.push(c);
+            brace_level += 1;
+
+            let special = brace_level == 1 && i + 4 <= n && chars[i + 1] == '\\';
+            let left_alone = conversion == CaseConversion::TitleLowers
+                && (i == 0 || (prev_colon && is_white(chars[i - 1])));
+
+            if special && !left_alone {
+                // A special character: convert the letters that it
+                // contains, but not the names of control sequences, except
+                // for the ones that stand for letters themselves.
+                i += 1;
+
+                while i < n && brace_level > 0 {
+                    i += 1;
+                    let start = i;
+                    while i < n && chars[i].is_alphabetic() {
+                        i += 1;
+                    }
+                    let name: String = chars[start..i].iter().collect();
+
+                    match control_seq(&name) {
+                        Some(code) => match conversion {
+                            CaseConversion::TitleLowers | CaseConversion::AllLowers => {
+                                out.push('\\');
+                                if let 3 | 5 | 7 | 9 | 11 = code {
+                                    out.push_str(&name.to_lowercase());
+                                } else {
+                                    out.push_str(&name);
+                                }
+                            }
+                            CaseConversion::AllUppers => match code {
+                                2 | 4 | 6 | 8 | 10 => {
+                                    out.push('\\');
+                                    out.push_str(&name.to_uppercase());
+                                }
+                                0 | 1 | 12 => {
+                                    // \i, \j and \ss become plain letters,
+                                    // losing the white space after them.
+                                    out.push_str(&name.to_uppercase());
+                                    while i < n && is_white(chars[i]) {
+                                        i += 1;
+                                    }
+                                }
+                                _ => {
+                                    out.push('\\');
+                                    out.push_str(&name);
+                                }
+                            },
+                        },
+                        None => {
+                            out.push('\\');
+                            out.push_str(&name);
+                        }
+                    }
+
+                    let start = i;
+                    while i < n && brace_level > 0 && chars[i] != '\\' {
+                        if chars[i] == '}' {
+                            brace_level -= 1;
+                        } else if chars[i] == '{' {
+                            brace_level += 1;
+                        }
+                        i += 1;
+                    }
+                    let rest: String = chars[start..i].iter().collect();
+                    convert(&mut out, &rest, conversion);
+                }
+
+                prev_colon = false;
+                continue;
+            }
+
+            prev_colon = false;
+        } else if c == '}' {
+            out.push(c);
+            if brace_level == 0 {
+                unbalanced = true;
+            } else {
+                brace_level -= 1;
+            }
+            prev_colon = false;
+        } else if brace_level == 0 {
+            match conversion {
+                CaseConversion::TitleLowers => {
+                    if i == 0 || (prev_colon && is_white(chars[i - 1])) {
+                        out.push(c);
+                    } else {
+                        out.extend(c.to_lowercase());
+                    }
+
+                    if c == ':' {
+                        prev_colon = true;
+                    } else if !is_white(c) {
+                        prev_colon = false;
+                    }
+                }
+                CaseConversion::AllLowers => out.extend(c.to_lowercase()),
+                CaseConversion::AllUppers => out.extend(c.to_uppercase()),
+            }
+        } else {
+            out.push(c);
+        }
+
+        i += 1;
+    }
+
+    (out, unbalanced || brace_level > 0)
+}
+
+/// Scans over the special character that starts at byte `start` of `text`,
+/// which must be a left brace followed by a backslash, returning the byte
+/// offset just past it.
+fn skip_special(text: &str, start: usize) -> usize {
+    let mut level = 0;
+
+    for (i, c) in text[start..].char_indices() {
+        if c == '{' {
+            level += 1;
+        } else if c == '}' {
+            level -= 1;
+            if level == 0 {
+                return start + i + 1;
+            }
+        }
+    }
+
+    text.len()
+}
+
+/// Splits `text` into the units that `text.length$` and `text.prefix$`
+/// count: grapheme clusters outside braces and whole special characters.
+/// Each item gives the byte range of a unit and whether it is a text
+/// character; braces are returned as non-text units so that callers can
+/// copy them.
+fn text_units(text: &str) -> Vec<(usize, usize, bool)> {
+    let mut units = Vec::new();
+    let mut level = 0;
+    let mut graphemes = text.grapheme_indices(true).peekable();
+
+    while let Some((i, g)) = graphemes.next() {
+        if g == "{" {
+            level += 1;
+            if level == 1 && text[i + 1..].starts_with('\\') {
+                let end = skip_special(text, i);
+                units.push((i, end, true));
+                level = 0;
+                while let Some(&(j, _)) = graphemes.peek() {
+                    if j >= end {
+                        break;
+                    }
+                    graphemes.next();
+                }
+            } else {
+                units.push((i, i + 1, false));
+            }
+        } else if g == "}" {
+            if level > 0 {
+                level -= 1;
+            }
+            units.push((i, i + 1, false));
+        } else {
+            units.push((i, i + g.len(), true));
+        }
+    }
+
+    units
+}
+
+/// The Unicode version of `text.length$`.
+pub(crate) fn text_length(text: &str) -> usize {
+    text_units(text).iter().filter(|u| u.2).count()
+}
+
+/// The Unicode version of `text.prefix$`: the first `n` text characters of
+/// `text`, with any braces left open at that point closed again.
+pub(crate) fn text_prefix(text: &str, n: usize) -> String {
+    let mut count = 0;
+    let mut end = 0;
+    let mut level = 0;
+
+    for (start, stop, is_text) in text_units(text) {
+        if count >= n {
+            break;
+        }
+
+        if is_text {
+            count += 1;
+        } else if &text[start..stop] == "{" {
+            level += 1;
+        } else if level > 0 {
+            level -= 1;
+        }
+
+        end = stop;
+    }
+
+    let mut result = text[..end].to_owned();
+    for _ in 0..level {
+        result.push('}');
+    }
+    result
+}
+
+/// The Unicode version of `substring$`, counting grapheme clusters. As in
+/// classic BibTeX, a negative `start` counts from the end of the string.
+pub(crate) fn substring(text: &str, start: i32, len: i32) -> String {
+    let graphemes: Vec<&str> = text.graphemes(true).collect();
+    let total = graphemes.len() as i64;
+    let (start, len) = (start as i64, len as i64);
+
+    if len <= 0 || start == 0 || start > total || start < -total {
+        return String::new();
+    }
+
+    let (from, to) = if start > 0 {
+        let from = start - 1;
+        (from, (from + len).min(total))
+    } else {
+        let to = total + start + 1;
+        ((to - len).max(0), to)
+    };
+
+    graphemes[from as usize..to as usize].concat()
+}
+
+/// The Unicode version of `purify$`: keep letters and digits, turn white
+/// space and hyphens into spaces, and drop everything else, except that the
+/// letter-like control sequences in special characters are spelled out.
+pub(crate) fn purify(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut level = 0;
+    let mut graphemes = text.grapheme_indices(true).peekable();
+
+    while let Some((i, g)) = graphemes.next() {
+        let first = g.chars().next().unwrap_or(' ');
+
+        if is_white(first) || is_sep(first) {
+            out.push(' ');
+        } else if is_alphanumeric(g) {
+            out.push_str(g);
+        } else if g == "{" {
+            level += 1;
+            if level == 1 && text[i + 1..].starts_with('\\') {
+                let end = skip_special(text, i);
+                purify_special(&text[i + 1..end], &mut out);
+                level = 0;
+                while let Some(&(j, _)) = graphemes.peek() {
+                    if j >= end {
+                        break;
+                    }
+                    graphemes.next();
+                }
+            }
+        } else if g == "}" && level > 0 {
+            level -= 1;
+        }
+    }
+
+    out
+}
+
+/// Purifies the inside of a special character, starting at its backslash.
+fn purify_special(inner: &str, out: &mut String) {
+    for (k, piece) in inner.split('\\').enumerate() {
+        if k == 0 {
+            continue;
+        }
+
+        let name_len = piece
+            .char_indices()
+            .find(|&(_, c)| !c.is_alphabetic())
+            .map_or(piece.len(), |(j, _)| j);
+        let (name, rest) = piece.split_at(name_len);
+
+        if let Some(code) = control_seq(name) {
+            let keep = if let 2 | 3 | 4 | 5 | 12 = code { 2 } else { 1 };
+            out.extend(name.chars().take(keep));
+        }
+
+        for g in rest.graphemes(true) {
+            if is_alphanumeric(g) {
+                out.push_str(g);
+            }
+        }
+    }
+}
+
+/// The number of bytes taken up by the first grapheme cluster of `text`.
+/// Name formatting uses this to abbreviate a name to its first letter.
+pub(crate) fn first_grapheme_len(text: &[u8]) -> usize {
+    let s = String::from_utf8_lossy(text);
+    match s.graphemes(true).next() {
+        Some(g) if !g.contains('\u{fffd}') => g.len(),
+        _ => 1,
+    }
+}
+
+/// Compares sort keys using the collation rules of a locale.
+pub(crate) struct SortCollator(Collator);
+
+impl SortCollator {
+    /// Creates a collator for `locale`, a BCP 47 language tag such as "de"
+    /// or "sv". The empty string selects the CLDR root collation.
+    pub(crate) fn new(locale: &str) -> Result<SortCollator, String> {
+        let loc = if locale.is_empty() {
+            Locale::UND
+        } else {
+            locale
+                .parse::<Locale>()
+                .map_err(|e| format!("invalid locale \"{}\": {}", locale, e))?
+        };
+
+        Collator::try_new(&(&loc).into(), CollatorOptions::new())
+            .map(SortCollator)
+            .map_err(|e| format!("no collation for locale \"{}\": {}", locale, e))
+    }
+
+    /// Compares two sort keys. Keys that the collation considers equal are
+    /// ordered by their bytes, so that the order is total.
+    pub(crate) fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.0
+            .compare(&String::from_utf8_lossy(a), &String::from_utf8_lossy(b))
+            .then_with(|| a.cmp(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_conversion() {
+        use CaseConversion::*;
+
+        assert_eq!(
+            change_case("Émile ZOLA: Œuvres Complètes", TitleLowers).0,
+            "Émile zola: Œuvres complètes"
+        );
+        assert_eq!(change_case("Ärger über ÖL", AllLowers).0, "ärger über öl");
+        assert_eq!(change_case("straße", AllUppers).0, "STRASSE");
+        assert_eq!(change_case("Der {DNA} Test", AllLowers).0, "der {DNA} test");
+        assert_eq!(change_case("Ein {\\\"U}bel", AllLowers).0, "ein {\\\"u}bel");
+        assert_eq!(change_case("{\\ss}x", AllUppers).0, "{SS}X");
+        assert_eq!(change_case("A {\\OE}uvre", AllLowers).0, "a {\\oe}uvre");
+        assert_eq!(change_case("{\\OE}uvre", TitleLowers).0, "{\\OE}uvre");
+        assert!(change_case("a}b", AllLowers).1);
+        assert!(!change_case("a{b}", AllLowers).1);
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(text_length("Müller"), 6);
+        assert_eq!(text_length("Mu\u{308}ller"), 6);
+        assert_eq!(text_length("M{\\\"u}ller"), 6);
+        assert_eq!(text_length("{Zoë}"), 3);
+        assert_eq!(text_length("東京大学"), 4);
+        assert_eq!(text_prefix("Ångström", 3), "Ång");
+        assert_eq!(text_prefix("{Ångström}", 2), "{Ån}");
+        assert_eq!(text_prefix("{\\AA}ngström", 2), "{\\AA}n");
+    }
+
+    #[test]
+    fn substrings() {
+        assert_eq!(substring("Ødegård", 1, 1), "Ø");
+        assert_eq!(substring("Ødegård", 5, 10), "ård");
+        assert_eq!(substring("Ødegård", -1, 3), "ård");
+        assert_eq!(substring("東京大学", -2, 1), "大");
+        assert_eq!(substring("abc", 4, 1), "");
+        assert_eq!(substring("abc", 1, 0), "");
+    }
+
+    #[test]
+    fn purification() {
+        assert_eq!(purify("Jean-Pierre L’Écuyer"), "Jean Pierre LÉcuyer");
+        assert_eq!(purify("{\\\"O}zt{\\\"u}rk"), "Ozturk");
+        assert_eq!(purify("{\\ss}{\\AE}"), "ssAE");
+        assert_eq!(purify("«北京»"), "北京");
+    }
+
+    #[test]
+    fn first_graphemes() {
+        assert_eq!(first_grapheme_len("Élodie".as_bytes()), 2);
+        assert_eq!(first_grapheme_len("E\u{301}lodie".as_bytes()), 3);
+        assert_eq!(first_grapheme_len(b"\xc3"), 1);
+    }
+
+    #[test]
+    fn collation() {
+        let root = SortCollator::new("").unwrap();
+        assert_eq!(root.compare("Ärger".as_bytes(), b"Zorn"), Ordering::Less);
+        assert_eq!(root.compare(b"zorn", "Öl".as_bytes()), Ordering::Greater);
+
+        let sv = SortCollator::new("sv").unwrap();
+        assert_eq!(sv.compare("Ö".as_bytes(), b"Z"), Ordering::Greater);
+
+        assert!(SortCollator::new("not a locale!").is_err());
+    }
+}
@@ -34,9 +34,17 @@ use bridge::TTHistory;
 use dpx::dvipdfmx_main;
 use xetex_ini::tt_run_engine;
 
+pub use bibtex::{
+    bibtex_diagnostic_kind_t, bibtex_diagnostic_t, bibtex_limits_t, bibtex_take_diagnostics,
+};
 pub use bridge::tt_bridge_api_t;
 pub use bridge::tt_get_error_message;
-pub use xetex_engine_interface::tt_xetex_set_int_variable;
+pub use bridge::{
+    tt_engine_limits_t, tt_set_engine_limits, tt_take_limit_violation, TTLimitViolation,
+};
+pub use dpx::{check_for_woff, woff_to_sfnt, OtfGlyphText, PdfAConformance, T1GlyphText};
+pub use xetex_engine_interface::{tt_xetex_set_font_index, tt_xetex_set_int_variable};
+pub use xetex_font_manager::{font_axis_t, font_description_t, font_index_entry_t};
 
 pub unsafe fn tex_simple_main(
     mut api: *const tt_bridge_api_t,
@@ -47,12 +55,30 @@ pub unsafe fn tex_simple_main(
         .unwrap_or(TTHistory::FATAL_ERROR as i32)
 }
 
+/// Lists the fonts that native font lookup can resolve names to, opening
+/// font files through `api`. Returns `None` if the font manager aborted.
+pub unsafe fn tex_list_fonts(mut api: *const tt_bridge_api_t) -> Option<Vec<font_description_t>> {
+    use xetex_font_manager::{
+        XeTeXFontMgr_Destroy, XeTeXFontMgr_GetFontManager, XeTeXFontMgr_Terminate,
+        XeTeXFontMgr_listFonts,
+    };
+    let fonts = bridge::tt_with_bridge(api, || {
+        let mgr = XeTeXFontMgr_GetFontManager();
+        XeTeXFontMgr_listFonts(mgr)
+    });
+    XeTeXFontMgr_Terminate();
+    XeTeXFontMgr_Destroy();
+    fonts
+}
+
 pub unsafe fn dvipdfmx_simple_main(
     mut api: *const tt_bridge_api_t,
     mut dviname: *const i8,
     mut pdfname: *const i8,
     mut compress: bool,
     mut deterministic_tags: bool,
+    mut pdfa: Option<PdfAConformance>,
+    mut linearize: bool,
 ) -> i32 {
     bridge::tt_with_bridge(api, || {
         dvipdfmx_main(
@@ -65,16 +91,44 @@ pub unsafe fn dvipdfmx_simple_main(
             deterministic_tags,
             false,
             0_u32,
+            pdfa,
+            linearize,
         ) as i32
     })
     .unwrap_or(99)
 }
 
+/// Gets the Unicode text of the glyphs of an OpenType/TrueType font the way
+/// that xdvipdfmx's ToUnicode CMaps map them, opening the font through
+/// `api`. Returns `None` if the font can't be read.
+pub unsafe fn dvipdfmx_otf_glyph_text(
+    mut api: *const tt_bridge_api_t,
+    font_name: &str,
+    index: u32,
+) -> Option<OtfGlyphText> {
+    bridge::tt_with_bridge(api, || dpx::otf_get_glyph_text(font_name, index as i32)).unwrap_or(None)
+}
+
+/// Gets the glyph name and Unicode text of each code in the built-in
+/// encoding of a Type 1 font, opening the font through `api`. Returns
+/// `None` if the font can't be read.
+pub unsafe fn dvipdfmx_type1_encoding_text(
+    mut api: *const tt_bridge_api_t,
+    font_name: &str,
+) -> Option<Vec<Option<T1GlyphText>>> {
+    bridge::tt_with_bridge(api, || dpx::t1_get_encoding_text(font_name)).unwrap_or(None)
+}
+
 pub unsafe fn bibtex_simple_main(
     mut api: *const tt_bridge_api_t,
     mut aux_file_name: *const i8,
+    mut limits: bibtex_limits_t,
+    mut unicode_locale: *const i8,
 ) -> i32 {
-    bridge::tt_with_bridge(api, || bibtex_main(aux_file_name) as i32).unwrap_or(99)
+    bridge::tt_with_bridge(api, || {
+        bibtex_main(aux_file_name, limits, unicode_locale) as i32
+    })
+    .unwrap_or(99)
 }
 
 mod core_memory {
@@ -162,6 +216,7 @@ mod core_memory {
 }
 
 mod bibtex;
+mod bibtex_unicode;
 mod xetex_aatfont;
 mod xetex_consts;
 mod xetex_engine_interface;
@@ -182,9 +237,19 @@ mod xetex_texmfmp;
 mod xetex_xetex0;
 mod xetex_xetexd;
 
+#[cfg(feature = "icu")]
 mod stub_icu;
 mod stub_stdio;
-mod stub_teckit;
+mod teckit;
+#[cfg(not(feature = "icu"))]
+mod unicode;
+
+// Line breaking, bidi and encoding conversion come either from ICU or from
+// our pure-Rust replacements, which expose the same API.
+#[cfg(feature = "icu")]
+use stub_icu as icu;
+#[cfg(not(feature = "icu"))]
+use unicode as icu;
 
 #[inline]
 pub(crate) unsafe fn strstartswith(s: *const i8, prefix: *const i8) -> *const i8 {
@@ -209,8 +274,8 @@ mod xetex_layout_interface;
 
 pub(crate) mod freetype_sys_patch {
     use freetype::freetype_sys::{
-        FT_Byte, FT_Error, FT_Face, FT_Fixed, FT_Int32, FT_Long, FT_Sfnt_Tag, FT_Short, FT_UInt,
-        FT_ULong, FT_UShort,
+        FT_Byte, FT_Error, FT_Face, FT_Fixed, FT_Int32, FT_Library, FT_Long, FT_Sfnt_Tag, FT_Short,
+        FT_String, FT_UInt, FT_ULong, FT_UShort,
     };
 
     extern "C" {
@@ -247,6 +312,19 @@ pub(crate) mod freetype_sys_patch {
             idx: FT_UInt,
             aname: *mut FT_SfntName,
         ) -> FT_Error;
+
+        #[no_mangle]
+        pub(crate) fn FT_Get_MM_Var(face: FT_Face, amaster: *mut *mut FT_MM_Var) -> FT_Error;
+
+        #[no_mangle]
+        pub(crate) fn FT_Done_MM_Var(library: FT_Library, amaster: *mut FT_MM_Var) -> FT_Error;
+
+        #[no_mangle]
+        pub(crate) fn FT_Set_Var_Design_Coordinates(
+            face: FT_Face,
+            num_coords: FT_UInt,
+            coords: *mut FT_Fixed,
+        ) -> FT_Error;
     }
 
     pub(crate) const FT_SFNT_MAX: FT_Sfnt_Tag = 7;
@@ -292,6 +370,35 @@ pub(crate) mod freetype_sys_patch {
         pub(crate) string_len: FT_UInt,
     }
     pub(crate) type FT_SfntName = FT_SfntName_;
+
+    #[derive(Copy, Clone)]
+    #[repr(C)]
+    pub(crate) struct FT_Var_Axis {
+        pub(crate) name: *mut FT_String,
+        pub(crate) minimum: FT_Fixed,
+        pub(crate) def: FT_Fixed,
+        pub(crate) maximum: FT_Fixed,
+        pub(crate) tag: FT_ULong,
+        pub(crate) strid: FT_UInt,
+    }
+
+    #[derive(Copy, Clone)]
+    #[repr(C)]
+    pub(crate) struct FT_Var_Named_Style {
+        pub(crate) coords: *mut FT_Fixed,
+        pub(crate) strid: FT_UInt,
+        pub(crate) psid: FT_UInt,
+    }
+
+    #[derive(Copy, Clone)]
+    #[repr(C)]
+    pub(crate) struct FT_MM_Var {
+        pub(crate) num_axis: FT_UInt,
+        pub(crate) num_designs: FT_UInt,
+        pub(crate) num_namedstyles: FT_UInt,
+        pub(crate) axis: *mut FT_Var_Axis,
+        pub(crate) namedstyle: *mut FT_Var_Named_Style,
+    }
 }
 
 #[cfg(target_os = "macos")]
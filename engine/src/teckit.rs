@@ -0,0 +1,1635 @@
+/*------------------------------------------------------------------------
+Copyright (C) 2002-2016 SIL International. All rights reserved.
+
+Distributable under the terms of either the Common Public License or the
+GNU Lesser General Public License, as specified in the LICENSING.txt file.
+
+File: TECkit_Engine.cpp
+Responsibility: Jonathan Kew
+Last reviewed: Not yet.
+
+Description:
+    Implements the TECkit conversion engine: an interpreter for compiled
+    `.tec` mapping tables plus the Unicode normalization passes that go
+    along with them.
+-------------------------------------------------------------------------*/
+
+//! A pure-Rust port of the TECkit conversion engine.
+//!
+//! Only the parts that XeTeX needs are provided: creating a converter from a
+//! compiled (and possibly zlib-compressed) mapping table, or a bare
+//! normalizer when no table is given, and running buffers through it. The
+//! mapping bytes are loaded by the caller through the regular I/O stack, so
+//! `.tec` files come out of the bundle like everything else.
+
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+use std::{mem, ptr, slice};
+use unicode_normalization::char::{canonical_combining_class, compose, decompose_canonical};
+
+pub(crate) type TECkit_Status = i64;
+
+pub(crate) type TECkit_Converter = *mut Converter;
+
+const kStatus_NoError: TECkit_Status = 0;
+const kStatusMask_Basic: TECkit_Status = 0xff;
+const kStatus_OutputBufferFull: TECkit_Status = 1;
+const kStatus_NeedMoreInput: TECkit_Status = 2;
+const kStatus_UnmappedChar: TECkit_Status = 3;
+const kStatus_UsedReplacement: TECkit_Status = 0x100;
+const kStatus_InvalidForm: TECkit_Status = -1;
+const kStatus_InvalidMapping: TECkit_Status = -4;
+const kStatus_BadMappingVersion: TECkit_Status = -5;
+const kStatus_IncompleteChar: TECkit_Status = -8;
+
+const kForm_EncodingFormMask: u16 = 0x000f;
+const kForm_Bytes: u16 = 1;
+const kForm_UTF8: u16 = 2;
+const kForm_UTF16BE: u16 = 3;
+const kForm_UTF16LE: u16 = 4;
+const kForm_UTF32BE: u16 = 5;
+const kForm_UTF32LE: u16 = 6;
+const kForm_NormalizationMask: u16 = 0x0f00;
+const kForm_NFC: u16 = 0x0100;
+const kForm_NFD: u16 = 0x0200;
+
+const kFlags_ExpectsNFC: u32 = 0x0000_0001;
+const kFlags_ExpectsNFD: u32 = 0x0000_0002;
+const kFlags_Unicode: u32 = 0x0001_0000;
+
+const kOptionsUnmapped_UseReplacementCharSilently: u32 = 0x00;
+const kOptionsUnmapped_UseReplacementCharWithWarning: u32 = 0x01;
+const kOptionsUnmapped_DontUseReplacementChar: u32 = 0x02;
+
+/* pseudo-characters passed between the stages of the pipeline */
+const kEndOfText: u32 = 0xffff_ffff;
+const kNeedMoreInput: u32 = 0xffff_fffe;
+const kInvalidChar: u32 = 0xffff_fffd;
+const kUnmappedChar: u32 = 0xffff_fffc;
+
+const kMagicNumber: u32 = 0x714d_6170; /* 'qMap' */
+const kMagicNumberCmp: u32 = 0x7a51_6d70; /* 'zQmp' */
+const kCurrentFileVersion: u32 = 0x0003_0000;
+
+const kTableType_BB: u32 = 0x422d_3e42;
+const kTableType_BU: u32 = 0x422d_3e55;
+const kTableType_UB: u32 = 0x552d_3e42;
+const kTableType_UU: u32 = 0x552d_3e55;
+const kTableType_NFC: u32 = 0x4e46_4320;
+const kTableType_NFD: u32 = 0x4e46_4420;
+
+const kTableFlags_Supplementary: u32 = 0x0001;
+
+const kLookupType_StringRules: u8 = 0xff;
+const kLookupType_IllegalDBCS: u8 = 0xfe;
+const kLookupType_Unmapped: u8 = 0xfd;
+const kLookupType_RuleTypeMask: u8 = 0xc0;
+const kLookupType_ExtStringRules: u8 = 0x80;
+const kLookupType_ExtRuleCountMask: u8 = 0x3f;
+
+const kMatchElem_Negate: u8 = 0x80;
+const kMatchElem_NonLit: u8 = 0x40;
+const kMatchElem_TypeMask: u8 = 0x3f;
+const kMatchElem_Type_Class: u8 = 0x01;
+const kMatchElem_Type_BGroup: u8 = 0x02;
+const kMatchElem_Type_EGroup: u8 = 0x03;
+const kMatchElem_Type_OR: u8 = 0x04;
+const kMatchElem_Type_ANY: u8 = 0x05;
+const kMatchElem_Type_EOS: u8 = 0x06;
+
+const kUSVMask: u32 = 0x001f_ffff;
+
+const kRepElem_Literal: u8 = 0x00;
+const kRepElem_Class: u8 = kMatchElem_Type_Class;
+const kRepElem_Copy: u8 = 0x07;
+const kRepElem_Unmapped: u8 = 0x0f;
+
+const matchNo: u32 = 0;
+const matchYes: u32 = 1;
+
+/* constants for algorithmic Hangul composition */
+const SBase: u32 = 0xac00;
+const LBase: u32 = 0x1100;
+const VBase: u32 = 0x1161;
+const TBase: u32 = 0x11a7;
+const LCount: u32 = 19;
+const VCount: u32 = 21;
+const TCount: u32 = 28;
+const NCount: u32 = VCount * TCount;
+const SCount: u32 = LCount * NCount;
+
+#[inline]
+fn is_exceptional(c: u32) -> bool {
+    c == kNeedMoreInput || c == kInvalidChar || c == kUnmappedChar
+}
+
+/* Compiled tables are big-endian. Out-of-range reads yield zero rather than
+ * panicking, so that a corrupt mapping produces junk output instead of
+ * taking the whole engine down. */
+
+#[inline]
+fn read8(data: &[u8], offset: usize) -> u32 {
+    data.get(offset).cloned().unwrap_or(0) as u32
+}
+
+#[inline]
+fn read16(data: &[u8], offset: usize) -> u32 {
+    match data.get(offset..offset + 2) {
+        Some(b) => u16::from_be_bytes([b[0], b[1]]) as u32,
+        None => 0,
+    }
+}
+
+#[inline]
+fn read32(data: &[u8], offset: usize) -> u32 {
+    match data.get(offset..offset + 4) {
+        Some(b) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        None => 0,
+    }
+}
+
+/// The first stage of every pipeline: decodes the caller's input buffer
+/// into characters and carries the per-call conversion options.
+struct Source {
+    input_form: u16,
+    data: Vec<u8>,
+    data_ptr: usize,
+    saved: Vec<u8>,
+    input_complete: bool,
+    unmapped_behavior: u32,
+    warning_status: TECkit_Status,
+}
+
+static bytesFromUTF8: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5,
+];
+
+static offsetsFromUTF8: [u32; 6] = [
+    0x0000_0000,
+    0x0000_3080,
+    0x000e_2080,
+    0x03c8_2080,
+    0xfa08_2080,
+    0x8208_2080,
+];
+
+impl Source {
+    fn new(input_form: u16) -> Source {
+        Source {
+            input_form,
+            data: Vec::new(),
+            data_ptr: 0,
+            saved: Vec::new(),
+            input_complete: false,
+            unmapped_behavior: kOptionsUnmapped_UseReplacementCharSilently,
+            warning_status: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.data.clear();
+        self.data_ptr = 0;
+        self.saved.clear();
+        self.warning_status = 0;
+    }
+
+    /// Make sure that `n` more bytes are available. If they aren't, either
+    /// the input is truncated (returns `kInvalidChar`) or we stash the partial
+    /// character for the next call (returns `kNeedMoreInput`).
+    fn check_avail(&mut self, n: usize) -> Option<u32> {
+        if self.data_ptr + n <= self.data.len() {
+            return None;
+        }
+        if self.input_complete {
+            Some(kInvalidChar)
+        } else {
+            self.saved = self.data[self.data_ptr..].to_vec();
+            self.data_ptr = self.data.len();
+            Some(kNeedMoreInput)
+        }
+    }
+
+    fn next_byte(&mut self) -> u32 {
+        let b = self.data[self.data_ptr] as u32;
+        self.data_ptr += 1;
+        b
+    }
+
+    fn get_char(&mut self) -> u32 {
+        if self.data_ptr >= self.data.len() {
+            return if self.input_complete {
+                kEndOfText
+            } else {
+                kNeedMoreInput
+            };
+        }
+
+        match self.input_form {
+            kForm_Bytes => self.next_byte(),
+
+            kForm_UTF8 => {
+                let extra_bytes = bytesFromUTF8[self.data[self.data_ptr] as usize] as usize;
+                if let Some(c) = self.check_avail(extra_bytes + 1) {
+                    return c;
+                }
+                let mut rval = 0u32;
+                for _ in 0..extra_bytes {
+                    rval = rval.wrapping_add(self.next_byte()) << 6;
+                }
+                rval = rval.wrapping_add(self.next_byte());
+                rval.wrapping_sub(offsetsFromUTF8[extra_bytes])
+            }
+
+            kForm_UTF16BE | kForm_UTF16LE => {
+                let big_endian = self.input_form == kForm_UTF16BE;
+                if let Some(c) = self.check_avail(2) {
+                    return c;
+                }
+                let read_unit = |s: &mut Source| {
+                    let (a, b) = (s.next_byte(), s.next_byte());
+                    if big_endian {
+                        (a << 8) + b
+                    } else {
+                        a + (b << 8)
+                    }
+                };
+                let rval = read_unit(self);
+                if !(0xd800..=0xdbff).contains(&rval) {
+                    return rval;
+                }
+                // High surrogate: if the low half hasn't arrived yet we'll
+                // retry from the start of the pair next time.
+                self.data_ptr -= 2;
+                if let Some(c) = self.check_avail(4) {
+                    return c;
+                }
+                self.data_ptr += 2;
+                let low = read_unit(self);
+                ((rval - 0xd800) << 10)
+                    .wrapping_add(low.wrapping_sub(0xdc00))
+                    .wrapping_add(0x10000)
+            }
+
+            kForm_UTF32BE | kForm_UTF32LE => {
+                if let Some(c) = self.check_avail(4) {
+                    return c;
+                }
+                let b = [
+                    self.next_byte() as u8,
+                    self.next_byte() as u8,
+                    self.next_byte() as u8,
+                    self.next_byte() as u8,
+                ];
+                if self.input_form == kForm_UTF32BE {
+                    u32::from_be_bytes(b)
+                } else {
+                    u32::from_le_bytes(b)
+                }
+            }
+
+            _ => kInvalidChar,
+        }
+    }
+}
+
+/// A processing stage of the conversion pipeline.
+enum Stage {
+    Pass(Box<Pass>),
+    Normalizer(Normalizer),
+}
+
+impl Stage {
+    fn reset(&mut self) {
+        match self {
+            Stage::Pass(p) => p.reset(),
+            Stage::Normalizer(n) => n.reset(),
+        }
+    }
+}
+
+/// Fetch the next character from the last stage in `stages`, which pulls
+/// from the stages before it as needed.
+fn pull(stages: &mut [Stage], src: &mut Source) -> u32 {
+    match stages.split_last_mut() {
+        None => src.get_char(),
+        Some((Stage::Pass(p), prev)) => p.get_char(prev, src),
+        Some((Stage::Normalizer(n), prev)) => n.get_char(prev, src),
+    }
+}
+
+fn combining_class(c: u32) -> u8 {
+    std::char::from_u32(c)
+        .map(canonical_combining_class)
+        .unwrap_or(0)
+}
+
+fn compose_pair(a: u32, b: u32) -> Option<u32> {
+    match (std::char::from_u32(a), std::char::from_u32(b)) {
+        (Some(a), Some(b)) => compose(a, b).map(|c| c as u32),
+        _ => None,
+    }
+}
+
+/// Streaming NFC/NFD normalization. Characters are decomposed and reordered
+/// into `buffer`; everything before `buffer_safe` can no longer be affected
+/// by later input and may be handed on.
+struct Normalizer {
+    compose: bool,
+    buffer: Vec<u32>,
+    buffer_ptr: usize,
+    buffer_safe: usize,
+    prev_comb_class: u8,
+}
+
+impl Normalizer {
+    fn new(compose: bool) -> Normalizer {
+        Normalizer {
+            compose,
+            buffer: Vec::with_capacity(256),
+            buffer_ptr: 0,
+            buffer_safe: 0,
+            prev_comb_class: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.buffer_ptr = 0;
+        self.buffer_safe = 0;
+        self.prev_comb_class = 0;
+    }
+
+    fn get_char(&mut self, prev: &mut [Stage], src: &mut Source) -> u32 {
+        while self.buffer_safe == 0 {
+            let c = self.process(prev, src);
+            if is_exceptional(c) {
+                return c;
+            }
+        }
+        let c = self.buffer[self.buffer_ptr];
+        self.buffer_ptr += 1;
+        if self.buffer_ptr == self.buffer_safe {
+            self.buffer.drain(..self.buffer_ptr);
+            self.buffer_ptr = 0;
+            self.buffer_safe = 0;
+        }
+        c
+    }
+
+    fn process(&mut self, prev: &mut [Stage], src: &mut Source) -> u32 {
+        let in_char = pull(prev, src);
+        if is_exceptional(in_char) {
+            return in_char;
+        }
+        if in_char == kEndOfText {
+            self.generate_char(kEndOfText);
+            return in_char;
+        }
+        match std::char::from_u32(in_char) {
+            Some(c) => decompose_canonical(c, |d| self.generate_char(d as u32)),
+            None => self.generate_char(in_char),
+        }
+        0
+    }
+
+    fn generate_char(&mut self, c: u32) {
+        let comb_class = if c == kEndOfText {
+            0
+        } else {
+            combining_class(c)
+        };
+
+        if comb_class != 0 {
+            // combiners are always buffered for sorting and possible composition
+            if self.prev_comb_class <= comb_class {
+                self.buffer.push(c);
+                self.prev_comb_class = comb_class;
+            } else {
+                self.insert_char(c, comb_class);
+            }
+            return;
+        }
+
+        if self.compose {
+            if let Some(last) = self.buffer.last_mut() {
+                // an L + V pair forms an LV syllable; don't update
+                // buffer_safe, as a following T could still compose
+                let l_index = last.wrapping_sub(LBase);
+                let v_index = c.wrapping_sub(VBase);
+                if l_index < LCount && v_index < VCount {
+                    *last = SBase + (l_index * VCount + v_index) * TCount;
+                    return;
+                }
+
+                // an LV syllable + T forms an LVT syllable
+                let s_index = last.wrapping_sub(SBase);
+                let t_index = c.wrapping_sub(TBase);
+                if s_index < SCount && s_index % TCount == 0 && t_index > 0 && t_index < TCount {
+                    *last += t_index;
+                    self.buffer_safe = self.buffer.len();
+                    return;
+                }
+            }
+
+            self.compose_buffer();
+        } else {
+            self.buffer_safe = self.buffer.len();
+        }
+
+        self.buffer.push(c);
+        if c == kEndOfText {
+            self.buffer_safe = self.buffer.len();
+        }
+        self.prev_comb_class = 0;
+    }
+
+    fn insert_char(&mut self, c: u32, comb_class: u8) {
+        let mut pos = self.buffer.len();
+        while pos > 1 && comb_class < combining_class(self.buffer[pos - 1]) {
+            pos -= 1;
+        }
+        self.buffer.insert(pos, c);
+    }
+
+    /// Apply canonical compositions to the buffered text and update
+    /// `buffer_safe` to cover whatever can no longer compose.
+    fn compose_buffer(&mut self) {
+        if self.buffer.is_empty() {
+            self.buffer_safe = 0;
+            return;
+        }
+
+        let mut starter_pos = 0;
+        let mut last_class = combining_class(self.buffer[0]) as i32;
+        if last_class != 0 {
+            last_class = 256;
+        }
+
+        if self.buffer.len() > 1 {
+            let mut comp_pos = 1;
+
+            for decomp_pos in 1..self.buffer.len() {
+                let c = self.buffer[decomp_pos];
+                let ch_class = combining_class(c) as i32;
+
+                if last_class < ch_class || last_class == 0 {
+                    if let Some(composite) = compose_pair(self.buffer[starter_pos], c) {
+                        self.buffer[starter_pos] = composite;
+                        continue;
+                    }
+                }
+
+                if ch_class == 0 {
+                    starter_pos = comp_pos;
+                }
+                last_class = ch_class;
+                self.buffer[comp_pos] = c;
+                comp_pos += 1;
+            }
+
+            self.buffer.truncate(comp_pos);
+        }
+
+        self.buffer_safe = if last_class != 0 {
+            self.buffer.len()
+        } else {
+            starter_pos
+        };
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct MatchInfo {
+    class_index: u32,
+    group_repeats: i32,
+    start: i32,
+    limit: i32,
+}
+
+/// One mapping pass (B->B, B->U, U->B or U->U) from a compiled table.
+/// All offsets are relative to the start of the table header in `table`.
+struct Pass {
+    table: Vec<u8>,
+    input_is_unicode: bool,
+    output_is_unicode: bool,
+    supplementary_chars: bool,
+
+    page_base: usize,
+    plane_map: usize,
+    num_page_maps: usize,
+    lookup_base: usize,
+    match_class_base: usize,
+    rep_class_base: usize,
+    string_list_base: usize,
+    string_rule_data: usize,
+    replacement_char: u32,
+
+    i_buffer: Vec<u32>,
+    i_buf_start: usize,
+    i_buf_end: usize,
+    i_buf_ptr: usize,
+
+    o_buffer: Vec<u32>,
+    o_buf_size: usize,
+    o_buf_ptr: usize,
+
+    pattern: usize,
+    pattern_length: i32,
+    direction: i32,
+    info: Vec<MatchInfo>,
+    info_limit: i32,
+    match_elems: i32,
+    matched_length: i32,
+}
+
+impl Pass {
+    fn new(table: Vec<u8>) -> Pass {
+        let ty = read32(&table, 0);
+        let flags = read32(&table, 12);
+        let input_is_unicode = (ty >> 24) == 'U' as u32;
+        let output_is_unicode = (ty & 0xff) == 'U' as u32;
+        let supplementary_chars = flags & kTableFlags_Supplementary != 0;
+
+        let mut page_base = read32(&table, 16) as usize;
+        let mut plane_map = 0;
+        let mut num_page_maps = 1;
+
+        if input_is_unicode && supplementary_chars {
+            // support supplementary plane chars
+            plane_map = page_base;
+            page_base += 20;
+            num_page_maps = read8(&table, plane_map + 17) as usize;
+        }
+
+        let i_buf_size =
+            ((read8(&table, 40) + read8(&table, 41) + read8(&table, 42) + 7) & !3) as usize;
+        let o_buf_size = ((read8(&table, 43) + 7) & !3) as usize;
+
+        Pass {
+            input_is_unicode,
+            output_is_unicode,
+            supplementary_chars,
+            page_base,
+            plane_map,
+            num_page_maps,
+            lookup_base: read32(&table, 20) as usize,
+            match_class_base: read32(&table, 24) as usize,
+            rep_class_base: read32(&table, 28) as usize,
+            string_list_base: read32(&table, 32) as usize,
+            string_rule_data: read32(&table, 36) as usize,
+            replacement_char: read32(&table, 44),
+            i_buffer: vec![0; i_buf_size],
+            i_buf_start: 0,
+            i_buf_end: 0,
+            i_buf_ptr: 0,
+            o_buffer: Vec::with_capacity(o_buf_size),
+            o_buf_size,
+            o_buf_ptr: 0,
+            pattern: 0,
+            pattern_length: 0,
+            direction: 1,
+            // match, post-context and pre-context lengths are each a byte
+            info: vec![MatchInfo::default(); 3 * 256],
+            info_limit: 0,
+            match_elems: 0,
+            matched_length: 0,
+            table,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.i_buf_start = 0;
+        self.i_buf_end = 0;
+        self.i_buf_ptr = 0;
+        self.o_buffer.clear();
+        self.o_buf_ptr = 0;
+    }
+
+    fn get_char(&mut self, prev: &mut [Stage], src: &mut Source) -> u32 {
+        while self.o_buf_ptr == self.o_buffer.len() {
+            self.o_buffer.clear();
+            self.o_buf_ptr = 0;
+            let c = self.do_mapping(prev, src);
+            if is_exceptional(c) {
+                return c;
+            }
+        }
+        let c = self.o_buffer[self.o_buf_ptr];
+        self.o_buf_ptr += 1;
+        c
+    }
+
+    fn output_char(&mut self, c: u32) {
+        // Cannot overflow provided the table correctly declares maxOutput.
+        if self.o_buffer.len() < self.o_buf_size {
+            self.o_buffer.push(c);
+        }
+    }
+
+    /// Emit the output for a character that has no mapping. Returns false
+    /// if the caller asked us to stop at unmapped characters instead.
+    fn output_unmapped(&mut self, src: &mut Source, in_char: u32) -> bool {
+        if self.output_is_unicode == self.input_is_unicode {
+            // B->B or U->U simply copies the input to the output
+            self.output_char(in_char);
+            return true;
+        }
+
+        match src.unmapped_behavior {
+            kOptionsUnmapped_DontUseReplacementChar => return false,
+            kOptionsUnmapped_UseReplacementCharWithWarning => {
+                src.warning_status |= kStatus_UsedReplacement;
+            }
+            _ => {}
+        }
+        self.output_char(self.replacement_char);
+        true
+    }
+
+    /// Append a character from the previous stage to the circular input
+    /// buffer, dropping the oldest pre-context if it's full.
+    fn push_input(&mut self, c: u32) {
+        self.i_buffer[self.i_buf_end] = c;
+        self.i_buf_end += 1;
+        if self.i_buf_end == self.i_buffer.len() {
+            self.i_buf_end = 0;
+        }
+        if self.i_buf_end == self.i_buf_start {
+            self.i_buf_start += 1;
+            if self.i_buf_start == self.i_buffer.len() {
+                self.i_buf_start = 0;
+            }
+        }
+    }
+
+    /// Read the character at `index` relative to the current input
+    /// position, pulling more input from the previous stage if needed.
+    fn input_char(&mut self, prev: &mut [Stage], src: &mut Source, index: i32) -> u32 {
+        let size = self.i_buffer.len() as i64;
+        let ptr = self.i_buf_ptr as i64;
+        let start = self.i_buf_start as i64;
+        let mut target = ptr + index as i64;
+
+        if index < 0 {
+            // look back
+            if target < 0 {
+                target += size;
+            }
+            let valid = if ptr < start {
+                // the pointer has wrapped, so the pre-context runs from
+                // start to the end of the buffer and then from 0 to ptr
+                target >= start || target < ptr
+            } else {
+                target >= start && target < ptr
+            };
+            return if valid {
+                self.i_buffer[target as usize]
+            } else {
+                kEndOfText
+            };
+        }
+
+        // look ahead
+        if target >= size {
+            target -= size;
+        }
+        if self.i_buf_ptr == self.i_buf_end {
+            // ensure that the current character is actually available
+            let ch = pull(prev, src);
+            if is_exceptional(ch) {
+                return ch;
+            }
+            self.push_input(ch);
+        }
+        let mut i = self.i_buf_ptr as i64;
+        while i != target {
+            i = if i == size - 1 { 0 } else { i + 1 };
+            if i as usize == self.i_buf_end {
+                let ch = pull(prev, src);
+                if is_exceptional(ch) {
+                    return ch;
+                }
+                self.push_input(ch);
+            }
+        }
+        self.i_buffer[i as usize]
+    }
+
+    /// Move forward over characters that a rule has already examined.
+    fn advance_input(&mut self, prev: &mut [Stage], src: &mut Source, num_chars: i32) {
+        for _ in 0..num_chars {
+            if self.i_buf_ptr == self.i_buf_end {
+                let ch = pull(prev, src);
+                self.push_input(ch);
+            }
+            self.i_buf_ptr += 1;
+            if self.i_buf_ptr == self.i_buffer.len() {
+                self.i_buf_ptr = 0;
+            }
+        }
+    }
+
+    /// Read element `index` of a class whose members are `width` bytes wide.
+    fn class_member(&self, members: usize, width: usize, index: usize) -> u32 {
+        match width {
+            4 => read32(&self.table, members + 4 * index),
+            2 => read16(&self.table, members + 2 * index),
+            _ => read8(&self.table, members + index),
+        }
+    }
+
+    fn class_match(&self, class_number: u32, in_char: u32) -> i32 {
+        let base = self.match_class_base;
+        let class_ptr = base + read32(&self.table, base + 4 * class_number as usize) as usize;
+        let member_count = read32(&self.table, class_ptr) as usize;
+        let members = class_ptr + 4;
+        let width = match (self.input_is_unicode, self.supplementary_chars) {
+            (true, true) => 4,
+            (true, false) => 2,
+            (false, _) => 1,
+        };
+
+        // classes are sorted, so binary-search for the lower bound
+        let (mut lo, mut count) = (0, member_count);
+        while count > 0 {
+            let half = count / 2;
+            if self.class_member(members, width, lo + half) < in_char {
+                lo += half + 1;
+                count -= half + 1;
+            } else {
+                count = half;
+            }
+        }
+
+        if lo < member_count && self.class_member(members, width, lo) == in_char {
+            lo as i32
+        } else {
+            -1
+        }
+    }
+
+    fn rep_class_member(&self, class_number: u32, index: u32) -> u32 {
+        let base = self.rep_class_base;
+        let class_ptr = base + read32(&self.table, base + 4 * class_number as usize) as usize;
+        let member_count = read32(&self.table, class_ptr);
+        if index >= member_count {
+            return 0; // this can't happen if the compiler is right!
+        }
+        let width = match (self.output_is_unicode, self.supplementary_chars) {
+            (true, true) => 4,
+            (true, false) => 2,
+            (false, _) => 1,
+        };
+        self.class_member(class_ptr + 4, width, index as usize)
+    }
+
+    /// Test a single non-group match element against `in_char`.
+    fn element_matches(
+        &mut self,
+        elem: usize,
+        ty: u8,
+        negate: bool,
+        in_char: u32,
+        index: i32,
+        repeats: i32,
+    ) -> bool {
+        let matches = match ty {
+            0 => read32(&self.table, elem) & kUSVMask == in_char,
+            kMatchElem_Type_Class => {
+                let class_index = self.class_match(read16(&self.table, elem + 2), in_char);
+                if class_index != -1 && repeats == 0 && index < self.info_limit {
+                    self.info[index as usize].class_index = class_index as u32;
+                }
+                class_index != -1
+            }
+            kMatchElem_Type_ANY => in_char != kEndOfText,
+            kMatchElem_Type_EOS => in_char == kEndOfText,
+            _ => false,
+        };
+        matches != negate
+    }
+
+    /// Attempt to match the current pattern starting at element `index`,
+    /// with initial repeat count `repeats`, at text offset `text_loc`.
+    /// Recurses whenever we might need to backtrack.
+    ///
+    /// Returns `matchYes`, `matchNo`, or one of the exceptional
+    /// pseudo-characters if no definite decision could be made.
+    fn match_at(
+        &mut self,
+        prev: &mut [Stage],
+        src: &mut Source,
+        mut index: i32,
+        mut repeats: i32,
+        mut text_loc: i32,
+    ) -> u32 {
+        let rval = 'restart: loop {
+            // if this is the first attempt to match at this index, record where we are
+            if repeats == 0 {
+                if index == self.match_elems {
+                    self.matched_length = text_loc;
+                }
+                if index < self.info_limit {
+                    self.info[index as usize].start = text_loc;
+                }
+            }
+
+            // if we're at the end of the pattern, we have a match
+            if index >= self.pattern_length {
+                break matchYes;
+            }
+
+            let elem = self.pattern + 4 * index as usize;
+            let repeat = read8(&self.table, elem) as i32;
+            let repeat_min = repeat >> 4;
+            let repeat_max = repeat & 0x0f;
+            let raw_type = read8(&self.table, elem + 1) as u8;
+            let negate = raw_type & kMatchElem_Negate != 0;
+            let ty = if raw_type & kMatchElem_NonLit != 0 {
+                raw_type & kMatchElem_TypeMask
+            } else {
+                0
+            };
+
+            match ty {
+                kMatchElem_Type_BGroup => {
+                    // try matching one of the alternatives in the group (again)
+                    self.info[index as usize].group_repeats = repeats;
+                    if repeats < repeat_max {
+                        let mut alt_index = index;
+                        loop {
+                            let mr = self.match_at(prev, src, alt_index + 1, 0, text_loc);
+                            if mr != matchNo {
+                                break 'restart mr;
+                            }
+                            // failed, so step ahead to the next alternative or the end of the group
+                            let alt = self.pattern + 4 * alt_index as usize;
+                            alt_index += read8(&self.table, alt + 2) as i32;
+                            let alt = self.pattern + 4 * alt_index as usize;
+                            if read8(&self.table, alt + 1) as u8 & kMatchElem_TypeMask
+                                != kMatchElem_Type_OR
+                            {
+                                break;
+                            }
+                        }
+                    }
+
+                    // if the group has matched enough times, try to match the following stuff
+                    if repeats < repeat_min {
+                        break matchNo;
+                    }
+                    let d_after = read8(&self.table, elem + 3) as i32;
+                    let mr = self.match_at(prev, src, index + d_after, 0, text_loc);
+                    if mr == matchYes && index < self.info_limit {
+                        self.info[index as usize].limit = text_loc;
+                        // don't allow elements within the group to indicate
+                        // matches beyond the span of the group itself
+                        for i in (index + 1..index + d_after).rev() {
+                            if i < self.info_limit {
+                                let info = &mut self.info[i as usize];
+                                info.start = info.start.min(text_loc);
+                                info.limit = info.limit.min(text_loc);
+                            }
+                        }
+                    }
+                    break mr;
+                }
+
+                kMatchElem_Type_OR | kMatchElem_Type_EGroup => {
+                    // reached the end of an alternative
+                    let start_index = index - read8(&self.table, elem + 3) as i32;
+                    let group_repeats = self.info[start_index as usize].group_repeats;
+                    break self.match_at(prev, src, start_index, group_repeats + 1, text_loc);
+                }
+
+                _ => {
+                    // not a group, so we loop rather than recurse until optionality strikes
+
+                    // ensure that the item matches at least repeat_min times
+                    while repeats < repeat_min {
+                        let in_char = self.input_char(prev, src, text_loc);
+                        if is_exceptional(in_char) {
+                            break 'restart in_char;
+                        }
+                        if !self.element_matches(elem, ty, negate, in_char, index, repeats) {
+                            break 'restart matchNo;
+                        }
+                        repeats += 1;
+                        text_loc += self.direction;
+                    }
+
+                    if index < self.info_limit {
+                        self.info[index as usize].limit = text_loc;
+                    }
+
+                    if repeat_min == repeat_max {
+                        // no need to recurse, as there's no optionality
+                        index += 1;
+                        repeats = 0;
+                        continue;
+                    }
+
+                    // try for another repeat if allowed
+                    if repeats < repeat_max {
+                        let in_char = self.input_char(prev, src, text_loc);
+                        if is_exceptional(in_char) {
+                            break in_char;
+                        }
+                        if self.element_matches(elem, ty, negate, in_char, index, repeats) {
+                            let mr = self.match_at(
+                                prev,
+                                src,
+                                index,
+                                repeats + 1,
+                                text_loc + self.direction,
+                            );
+                            if mr != matchNo {
+                                break mr;
+                            }
+                        }
+                    }
+
+                    // otherwise try to match the remainder of the pattern
+                    break self.match_at(prev, src, index + 1, 0, text_loc);
+                }
+            }
+        };
+
+        if rval == matchNo && index < self.info_limit {
+            self.info[index as usize].limit = text_loc;
+        }
+        rval
+    }
+
+    /// Find the lookup entry for `in_char`, possibly consuming a DBCS
+    /// trail byte. Returns the offset of the entry, or an exceptional
+    /// pseudo-character as the error.
+    fn find_lookup(
+        &mut self,
+        prev: &mut [Stage],
+        src: &mut Source,
+        in_char: u32,
+    ) -> Result<usize, u32> {
+        if self.input_is_unicode {
+            let mut char_index = 0;
+
+            // if the lookup table immediately follows the page maps, this
+            // is a pass with no rules and everything uses entry 0
+            if self.lookup_base != self.page_base {
+                let plane = (in_char >> 16) as usize;
+                let page_map = if self.supplementary_chars {
+                    let map = read8(&self.table, self.plane_map + plane);
+                    if plane < 17 && map != 0xff {
+                        Some(self.page_base + 256 * map as usize)
+                    } else {
+                        None
+                    }
+                } else if plane == 0 {
+                    Some(self.page_base)
+                } else {
+                    None
+                };
+
+                if let Some(page_map) = page_map {
+                    let page = ((in_char >> 8) & 0xff) as usize;
+                    let map = read8(&self.table, page_map + page);
+                    if map != 0xff {
+                        let char_map_base = self.page_base + 256 * self.num_page_maps;
+                        let entry = 256 * map as usize + (in_char & 0xff) as usize;
+                        char_index = read16(&self.table, char_map_base + 2 * entry);
+                    }
+                }
+            }
+
+            return Ok(self.lookup_base + 4 * char_index as usize);
+        }
+
+        // byte-oriented lookup; a nonzero page base means that there's a DBCS page table
+        let single = self.lookup_base + 4 * in_char as usize;
+        if self.page_base == 0 {
+            return Ok(single);
+        }
+
+        let page_number = read8(&self.table, self.page_base + in_char as usize) as usize;
+        if page_number == 0 {
+            // not a valid DBCS lead byte
+            return Ok(single);
+        }
+
+        let next_char = self.input_char(prev, src, 1);
+        if is_exceptional(next_char) {
+            return Err(next_char);
+        }
+        if next_char == kEndOfText {
+            return Ok(single);
+        }
+
+        let lookup = self.lookup_base + 4 * (page_number * 256 + next_char as usize);
+        if read8(&self.table, lookup) as u8 == kLookupType_IllegalDBCS {
+            // illegal DBCS sequence; map the lead byte alone
+            Ok(single)
+        } else {
+            self.matched_length = 2;
+            Ok(lookup)
+        }
+    }
+
+    fn do_mapping(&mut self, prev: &mut [Stage], src: &mut Source) -> u32 {
+        let in_char = self.input_char(prev, src, 0);
+        if is_exceptional(in_char) {
+            return in_char;
+        }
+        if in_char == kEndOfText {
+            self.output_char(kEndOfText);
+            return in_char;
+        }
+        self.matched_length = 1;
+
+        let lookup = match self.find_lookup(prev, src, in_char) {
+            Ok(l) => l,
+            Err(c) => return c,
+        };
+
+        let rule_type = read8(&self.table, lookup) as u8;
+
+        if rule_type == kLookupType_StringRules
+            || rule_type & kLookupType_RuleTypeMask == kLookupType_ExtStringRules
+        {
+            let mut rule_list =
+                self.string_list_base + 4 * read16(&self.table, lookup + 2) as usize;
+            let mut rule_count = read8(&self.table, lookup + 1) as usize;
+            if rule_type & kLookupType_RuleTypeMask == kLookupType_ExtStringRules {
+                rule_count += 256 * (rule_type & kLookupType_ExtRuleCountMask) as usize;
+            }
+            let mut matched = false;
+            let mut allow_insertion = true;
+
+            for _ in 0..rule_count {
+                let rule = self.string_rule_data + read32(&self.table, rule_list) as usize;
+                rule_list += 4;
+
+                let match_length = read8(&self.table, rule) as i32;
+                let post_length = read8(&self.table, rule + 1) as i32;
+                let pre_length = read8(&self.table, rule + 2) as i32;
+                let rep_length = read8(&self.table, rule + 3) as usize;
+
+                self.match_elems = match_length;
+                if self.match_elems == 0 && !allow_insertion {
+                    continue;
+                }
+                self.pattern_length = self.match_elems + post_length;
+                self.pattern = rule + 4; // the match elements follow the rule header
+                self.direction = 1;
+                self.info_limit = self.match_elems;
+
+                for info in &mut self.info[..self.info_limit as usize] {
+                    info.start = 0;
+                    info.limit = 0;
+                }
+
+                let mut mr = self.match_at(prev, src, 0, 0, 0);
+                if mr != matchYes {
+                    if mr != matchNo {
+                        return mr;
+                    }
+                    continue;
+                }
+                if self.matched_length == 0 && !allow_insertion {
+                    continue;
+                }
+
+                self.pattern += 4 * self.pattern_length as usize;
+                self.pattern_length = pre_length;
+                if self.pattern_length > 0 {
+                    self.direction = -1;
+                    self.info_limit = 0;
+                    self.match_elems = -1;
+                    mr = self.match_at(prev, src, 0, 0, -1);
+                }
+                if mr != matchYes {
+                    if mr != matchNo {
+                        return mr;
+                    }
+                    continue;
+                }
+
+                // RULE MATCHED! execute it
+                let mut rep = self.pattern + 4 * self.pattern_length as usize;
+                for _ in 0..rep_length {
+                    match read8(&self.table, rep) as u8 {
+                        kRepElem_Literal => self.output_char(read32(&self.table, rep)),
+
+                        kRepElem_Class => {
+                            let info = self.info[read8(&self.table, rep + 1) as usize];
+                            if info.start < info.limit {
+                                let class = read16(&self.table, rep + 2);
+                                let c = self.rep_class_member(class, info.class_index);
+                                self.output_char(c);
+                            }
+                        }
+
+                        kRepElem_Copy => {
+                            let info = self.info[read8(&self.table, rep + 1) as usize];
+                            for j in info.start..info.limit {
+                                let c = self.input_char(prev, src, j);
+                                self.output_char(c);
+                            }
+                        }
+
+                        kRepElem_Unmapped if !self.output_unmapped(src, in_char) => {
+                            return kUnmappedChar;
+                        }
+
+                        _ => {}
+                    }
+                    rep += 4;
+                }
+
+                if self.matched_length > 0 {
+                    // we've matched the current input character, so stop here
+                    matched = true;
+                    break;
+                }
+
+                // must have been an insertion (or null!) rule, so skip any further insertion rules
+                allow_insertion = false;
+            }
+
+            if !matched {
+                // no rule matched the current input char, so we simulate a default "Unmapped" lookup
+                if !self.output_unmapped(src, in_char) {
+                    return kUnmappedChar;
+                }
+                self.matched_length = 1;
+            }
+        } else if rule_type == kLookupType_Unmapped {
+            if !self.output_unmapped(src, in_char) {
+                return kUnmappedChar;
+            }
+        } else if self.output_is_unicode {
+            // direct character output
+            let usv = read32(&self.table, lookup);
+            if usv <= 0x0010_ffff {
+                self.output_char(usv);
+            }
+        } else {
+            for i in 0..read8(&self.table, lookup) as usize {
+                let b = read8(&self.table, lookup + 1 + i);
+                self.output_char(b);
+            }
+        }
+
+        let matched_length = self.matched_length;
+        self.advance_input(prev, src, matched_length);
+        0
+    }
+}
+
+/// A complete conversion pipeline: the input decoder, followed by the
+/// mapping passes and normalizers, followed by the output encoder.
+pub(crate) struct Converter {
+    source: Source,
+    stages: Vec<Stage>,
+    output_form: u16,
+    pending_output_char: Option<u32>,
+}
+
+fn is_unicode_form(form: u16) -> bool {
+    (kForm_UTF8..=kForm_UTF32LE).contains(&form)
+}
+
+impl Converter {
+    fn new(
+        mapping: Option<&[u8]>,
+        forward: bool,
+        in_form: u16,
+        out_form: u16,
+    ) -> Result<Converter, TECkit_Status> {
+        let input_form = in_form & kForm_EncodingFormMask;
+        let output_form = out_form & kForm_EncodingFormMask;
+        let mut stages = Vec::new();
+        let mut norm_form = 0;
+
+        if let Some(mapping) = mapping {
+            let table = if read32(mapping, 0) == kMagicNumberCmp {
+                // the table is compressed; the "version" field holds the expanded size
+                let expanded_len = read32(mapping, 4) as usize;
+                let mut table = Vec::with_capacity(expanded_len);
+                let mut decoder = ZlibDecoder::new(mapping.get(8..).unwrap_or(&[]));
+                if decoder.read_to_end(&mut table).is_err() || table.len() != expanded_len {
+                    return Err(kStatus_InvalidMapping);
+                }
+                table
+            } else {
+                mapping.to_vec()
+            };
+
+            if read32(&table, 0) != kMagicNumber {
+                return Err(kStatus_InvalidMapping);
+            }
+            if (read32(&table, 4) & 0xffff_0000) > (kCurrentFileVersion & 0xffff_0000) {
+                return Err(kStatus_BadMappingVersion);
+            }
+
+            let (lhs_flags, rhs_flags) = (read32(&table, 12), read32(&table, 16));
+            let num_names = read32(&table, 20) as usize;
+            let num_fwd_tables = read32(&table, 24) as usize;
+            let num_rev_tables = read32(&table, 28) as usize;
+
+            let mut table_base = 32 + 4 * num_names;
+            let num_tables = if forward {
+                num_fwd_tables
+            } else {
+                table_base += 4 * num_fwd_tables;
+                num_rev_tables
+            };
+
+            // check that the output form matches the output of the mapping
+            let (source_flags, target_flags) = if forward {
+                (lhs_flags, rhs_flags)
+            } else {
+                (rhs_flags, lhs_flags)
+            };
+            if target_flags & kFlags_Unicode != 0 {
+                if !is_unicode_form(output_form) {
+                    return Err(kStatus_InvalidForm);
+                }
+            } else if output_form != kForm_Bytes {
+                return Err(kStatus_InvalidForm);
+            }
+
+            // if converting from Unicode, prefix a Normalizer if the mapping wants it
+            if source_flags & kFlags_Unicode != 0 {
+                if !is_unicode_form(input_form) {
+                    return Err(kStatus_InvalidForm);
+                }
+                if source_flags & kFlags_ExpectsNFD != 0 {
+                    stages.push(Stage::Normalizer(Normalizer::new(false)));
+                    norm_form = kForm_NFD;
+                } else if source_flags & kFlags_ExpectsNFC != 0 {
+                    stages.push(Stage::Normalizer(Normalizer::new(true)));
+                    norm_form = kForm_NFC;
+                }
+            } else if input_form != kForm_Bytes {
+                return Err(kStatus_InvalidForm);
+            }
+
+            // create the processing pipeline
+            for i in 0..num_tables {
+                let offset = read32(&table, table_base + 4 * i) as usize;
+                match read32(&table, offset) {
+                    kTableType_BB | kTableType_BU | kTableType_UU | kTableType_UB => {
+                        let length = read32(&table, offset + 8) as usize;
+                        let data = match table.get(offset..offset + length) {
+                            Some(d) => d.to_vec(),
+                            None => return Err(kStatus_InvalidMapping),
+                        };
+                        stages.push(Stage::Pass(Box::new(Pass::new(data))));
+                        norm_form = 0;
+                    }
+                    kTableType_NFC => {
+                        stages.push(Stage::Normalizer(Normalizer::new(true)));
+                        norm_form = kForm_NFC;
+                    }
+                    kTableType_NFD => {
+                        stages.push(Stage::Normalizer(Normalizer::new(false)));
+                        norm_form = kForm_NFD;
+                    }
+                    _ => return Err(kStatus_InvalidMapping),
+                }
+            }
+        } else if !is_unicode_form(input_form) || !is_unicode_form(output_form) {
+            // No mapping table provided, so we're mapping Unicode->Unicode,
+            // possibly doing normalization and/or encoding form change.
+            return Err(kStatus_InvalidForm);
+        }
+
+        // if converting to Unicode, add a Normalizer pass at the end if requested
+        if is_unicode_form(output_form) {
+            let requested = out_form & kForm_NormalizationMask;
+            if requested == kForm_NFD && norm_form != kForm_NFD {
+                stages.push(Stage::Normalizer(Normalizer::new(false)));
+            } else if requested == kForm_NFC && norm_form != kForm_NFC {
+                stages.push(Stage::Normalizer(Normalizer::new(true)));
+            }
+        }
+
+        Ok(Converter {
+            source: Source::new(input_form),
+            stages,
+            output_form,
+            pending_output_char: None,
+        })
+    }
+
+    fn reset(&mut self) {
+        self.pending_output_char = None;
+        self.source.reset();
+        for stage in &mut self.stages {
+            stage.reset();
+        }
+    }
+
+    /// Encode `c` into `out` at `*out_ptr`; returns false if it doesn't fit.
+    fn put_char(&self, mut c: u32, out: &mut [u8], out_ptr: &mut usize) -> bool {
+        let mut buf = [0u8; 4];
+        let encoded: &[u8] = match self.output_form {
+            kForm_Bytes => {
+                buf[0] = c as u8;
+                &buf[..1]
+            }
+
+            kForm_UTF8 => {
+                if c >= 0x20_0000 {
+                    c = 0xfffd;
+                }
+                let n = if c < 0x80 {
+                    1
+                } else if c < 0x800 {
+                    2
+                } else if c < 0x10000 {
+                    3
+                } else {
+                    4
+                };
+                const FIRST_BYTE_MARK: [u8; 5] = [0x00, 0x00, 0xc0, 0xe0, 0xf0];
+                for i in (1..n).rev() {
+                    buf[i] = ((c & 0x3f) | 0x80) as u8;
+                    c >>= 6;
+                }
+                buf[0] = c as u8 | FIRST_BYTE_MARK[n];
+                &buf[..n]
+            }
+
+            kForm_UTF16BE | kForm_UTF16LE => {
+                let units = if c < 0x10000 {
+                    [c as u16, 0]
+                } else {
+                    let c = c - 0x10000;
+                    [((c >> 10) + 0xd800) as u16, ((c & 0x3ff) + 0xdc00) as u16]
+                };
+                let n = if c < 0x10000 { 1 } else { 2 };
+                for (i, u) in units[..n].iter().enumerate() {
+                    let b = if self.output_form == kForm_UTF16BE {
+                        u.to_be_bytes()
+                    } else {
+                        u.to_le_bytes()
+                    };
+                    buf[2 * i..2 * i + 2].copy_from_slice(&b);
+                }
+                &buf[..2 * n]
+            }
+
+            kForm_UTF32BE => {
+                buf = c.to_be_bytes();
+                &buf
+            }
+
+            _ => {
+                buf = c.to_le_bytes();
+                &buf
+            }
+        };
+
+        if *out_ptr + encoded.len() > out.len() {
+            return false;
+        }
+        out[*out_ptr..*out_ptr + encoded.len()].copy_from_slice(encoded);
+        *out_ptr += encoded.len();
+        true
+    }
+
+    fn convert_buffer(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        input_complete: bool,
+    ) -> (TECkit_Status, usize, usize) {
+        let saved = mem::take(&mut self.source.saved);
+        let num_saved = saved.len();
+        self.source.data = saved;
+        self.source.data.extend_from_slice(input);
+        self.source.data_ptr = 0;
+        self.source.input_complete = input_complete;
+        self.source.unmapped_behavior = kOptionsUnmapped_UseReplacementCharSilently;
+
+        let mut out_ptr = 0;
+
+        let mut rval = loop {
+            let c = match self.pending_output_char.take() {
+                Some(c) => c,
+                None => pull(&mut self.stages, &mut self.source),
+            };
+
+            match c {
+                kEndOfText => break kStatus_NoError,
+                kNeedMoreInput => break kStatus_NeedMoreInput,
+                kInvalidChar => break kStatus_IncompleteChar,
+                kUnmappedChar => break kStatus_UnmappedChar,
+                _ => {
+                    if !self.put_char(c, output, &mut out_ptr) {
+                        self.pending_output_char = Some(c);
+                        break kStatus_OutputBufferFull;
+                    }
+                }
+            }
+        };
+
+        let in_used = self.source.data_ptr.saturating_sub(num_saved);
+
+        rval |= self.source.warning_status;
+        if rval & kStatusMask_Basic == kStatus_NoError {
+            self.reset();
+        }
+
+        (rval, in_used, out_ptr)
+    }
+}
+
+pub(crate) unsafe fn TECkit_CreateConverter(
+    mapping: *mut u8,
+    mappingSize: u32,
+    mapForward: u8,
+    sourceForm: u16,
+    targetForm: u16,
+    converter: *mut TECkit_Converter,
+) -> TECkit_Status {
+    *converter = ptr::null_mut();
+    let mapping = if mapping.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts(
+            mapping as *const u8,
+            mappingSize as usize,
+        ))
+    };
+    match Converter::new(mapping, mapForward != 0, sourceForm, targetForm) {
+        Ok(cnv) => {
+            *converter = Box::into_raw(Box::new(cnv));
+            kStatus_NoError
+        }
+        Err(status) => status,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn TECkit_ConvertBuffer(
+    converter: TECkit_Converter,
+    inBuffer: *const u8,
+    inLength: u32,
+    inUsed: *mut u32,
+    outBuffer: *mut u8,
+    outLength: u32,
+    outUsed: *mut u32,
+    inputIsComplete: u8,
+) -> TECkit_Status {
+    let input = if inBuffer.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(inBuffer, inLength as usize)
+    };
+    let output = if outBuffer.is_null() {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(outBuffer, outLength as usize)
+    };
+    let (status, in_used, out_used) =
+        (*converter).convert_buffer(input, output, inputIsComplete != 0);
+    if !inUsed.is_null() {
+        *inUsed = in_used as u32;
+    }
+    if !outUsed.is_null() {
+        *outUsed = out_used as u32;
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn convert(mapping: Option<&[u8]>, in_form: u16, out_form: u16, input: &[u8]) -> Vec<u8> {
+        let mut cnv = Converter::new(mapping, true, in_form, out_form).unwrap();
+        let mut output = vec![0; 256];
+        let (status, in_used, out_used) = cnv.convert_buffer(input, &mut output, true);
+        assert_eq!(status, kStatus_NoError);
+        assert_eq!(in_used, input.len());
+        output.truncate(out_used);
+        output
+    }
+
+    fn normalize(input: &str, form: u16) -> String {
+        let output = convert(None, kForm_UTF8, kForm_UTF8 | form, input.as_bytes());
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(normalize("e\u{301}A", kForm_NFC), "\u{e9}A");
+        assert_eq!(normalize("\u{e9}A", kForm_NFD), "e\u{301}A");
+        assert_eq!(normalize("\u{1100}\u{1161}\u{11a8}", kForm_NFC), "\u{ac01}");
+        assert_eq!(
+            normalize("a\u{307}\u{323}", kForm_NFC),
+            normalize("a\u{323}\u{307}", kForm_NFC)
+        );
+    }
+
+    /// A minimal U->U mapping: "--" becomes an en dash, "`" a left quote.
+    fn dash_mapping() -> Vec<u8> {
+        fn be32(v: &mut Vec<u8>, x: u32) {
+            v.extend_from_slice(&x.to_be_bytes());
+        }
+
+        let mut page_map = vec![0xff; 256];
+        page_map[0] = 0;
+        let mut char_map = vec![0; 512];
+        char_map[2 * b'-' as usize + 1] = 1;
+        char_map[2 * b'`' as usize + 1] = 2;
+        let mut lookups = vec![
+            kLookupType_Unmapped,
+            0,
+            0,
+            0,
+            kLookupType_StringRules,
+            1,
+            0,
+            0,
+        ];
+        be32(&mut lookups, 0x2018);
+        let string_list = vec![0; 4];
+        let mut rule = vec![2, 0, 0, 1, 0x11, 0, 0, b'-', 0x11, 0, 0, b'-'];
+        be32(&mut rule, 0x2013);
+
+        let page_base = 48;
+        let lookup_base = page_base + page_map.len() + char_map.len();
+        let string_list_base = lookup_base + lookups.len();
+        let string_rule_data = string_list_base + string_list.len();
+        let length = string_rule_data + rule.len();
+
+        let mut table = Vec::new();
+        for &x in &[kTableType_UU, kCurrentFileVersion, length as u32, 0] {
+            be32(&mut table, x);
+        }
+        for &x in &[page_base, lookup_base, string_list_base, string_list_base] {
+            be32(&mut table, x as u32);
+        }
+        be32(&mut table, string_list_base as u32);
+        be32(&mut table, string_rule_data as u32);
+        table.extend_from_slice(&[2, 0, 0, 1]);
+        be32(&mut table, 0xfffd);
+        for part in &[page_map, char_map, lookups, string_list, rule] {
+            table.extend_from_slice(part);
+        }
+
+        let mut file = Vec::new();
+        for &x in &[
+            kMagicNumber,
+            kCurrentFileVersion,
+            36,
+            kFlags_Unicode,
+            kFlags_Unicode,
+            0,
+            1,
+            0,
+            36,
+        ] {
+            be32(&mut file, x);
+        }
+        file.extend_from_slice(&table);
+        file
+    }
+
+    #[test]
+    fn string_rules() {
+        let mapping = dash_mapping();
+        let output = convert(Some(&mapping), kForm_UTF8, kForm_UTF8, b"a--b-`x");
+        assert_eq!(String::from_utf8(output).unwrap(), "a\u{2013}b-\u{2018}x");
+    }
+
+    #[test]
+    fn compressed_mapping() {
+        let mapping = dash_mapping();
+        let mut compressed = Vec::new();
+        compressed.extend_from_slice(&kMagicNumberCmp.to_be_bytes());
+        compressed.extend_from_slice(&(mapping.len() as u32).to_be_bytes());
+        let mut encoder = ZlibEncoder::new(compressed, Compression::default());
+        encoder.write_all(&mapping).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let input: Vec<u8> = "---"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect();
+        let output = convert(Some(&compressed), kForm_UTF16LE, kForm_UTF16LE, &input);
+        assert_eq!(output, vec![0x13, 0x20, b'-', 0]);
+    }
+}
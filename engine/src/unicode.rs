@@ -0,0 +1,666 @@
+/* engine/src/unicode.rs: pure-Rust replacements for the ICU services we use
+   Copyright 2020 the Tectonic Project
+   Licensed under the MIT License.
+*/
+
+//! Line breaking, bidi analysis and legacy encoding conversion without ICU.
+//!
+//! This module mirrors the small slice of the ICU C API that XeTeX calls
+//! (see `stub_icu.rs`), so that the engine can use either one depending on
+//! whether the `icu` cargo feature is enabled. Line breaking follows the
+//! UAX #14 rules, with Thai, Lao, Khmer and Burmese text broken into words
+//! using dictionaries, as ICU does.
+//! Encoding conversions cover the Unicode forms, ISO-8859-1 and everything
+//! known to the WHATWG Encoding Standard.
+
+use encoding_rs::Encoding;
+use icu_segmenter::LineSegmenter;
+use std::ffi::CStr;
+use std::{ptr, slice};
+use unicode_bidi::{bidi_class, BidiClass, BidiInfo, Level};
+
+pub(crate) type UChar = u16;
+pub(crate) type UErrorCode = i32;
+pub(crate) type UConverterType = i32;
+pub(crate) type UBiDiLevel = u8;
+
+pub(crate) const UCNV_UTF32_LittleEndian: UConverterType = 8;
+pub(crate) const UCNV_UTF32_BigEndian: UConverterType = 7;
+
+pub(crate) const U_ZERO_ERROR: UErrorCode = 0;
+pub(crate) const U_ILLEGAL_ARGUMENT_ERROR: UErrorCode = 1;
+pub(crate) const U_FILE_ACCESS_ERROR: UErrorCode = 4;
+pub(crate) const U_BUFFER_OVERFLOW_ERROR: UErrorCode = 15;
+
+pub(crate) type UBiDiDirection = u32;
+pub(crate) const UBIDI_RTL: UBiDiDirection = 1;
+pub(crate) const UBIDI_MIXED: UBiDiDirection = 2;
+pub(crate) const UBIDI_LTR: UBiDiDirection = 0;
+
+const UBIDI_DEFAULT_LTR: UBiDiLevel = 0xfe;
+const UBIDI_DEFAULT_RTL: UBiDiLevel = 0xff;
+
+pub(crate) type UBreakIteratorType = u32;
+pub(crate) const UBRK_LINE: UBreakIteratorType = 2;
+
+const UBRK_DONE: i32 = -1;
+
+#[inline]
+fn failed(err: *mut UErrorCode) -> bool {
+    unsafe { !err.is_null() && *err > U_ZERO_ERROR }
+}
+
+#[inline]
+fn set_error(err: *mut UErrorCode, code: UErrorCode) {
+    if !err.is_null() {
+        unsafe { *err = code };
+    }
+}
+
+/// Get a slice from an ICU-style (pointer, length) pair, where a negative
+/// length means that the data are zero-terminated.
+unsafe fn icu_slice<'a, T: Copy + Default + PartialEq>(p: *const T, len: i32) -> &'a [T] {
+    if p.is_null() {
+        return &[];
+    }
+    let len = if len >= 0 {
+        len as usize
+    } else {
+        let mut n = 0;
+        while *p.add(n) != T::default() {
+            n += 1;
+        }
+        n
+    };
+    slice::from_raw_parts(p, len)
+}
+
+/// Decode UTF-16 into a `String`, along with a table giving the UTF-16
+/// offset of every character boundary in the string, keyed by byte offset.
+fn decode_utf16_with_offsets(text: &[UChar]) -> (String, Vec<(usize, i32)>) {
+    let mut s = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    let mut unit_offset = 0;
+
+    for c in std::char::decode_utf16(text.iter().cloned()) {
+        offsets.push((s.len(), unit_offset));
+        let c = match c {
+            Ok(c) => {
+                unit_offset += c.len_utf16() as i32;
+                c
+            }
+            Err(_) => {
+                unit_offset += 1;
+                std::char::REPLACEMENT_CHARACTER
+            }
+        };
+        s.push(c);
+    }
+
+    offsets.push((s.len(), unit_offset));
+    (s, offsets)
+}
+
+fn utf16_offset(offsets: &[(usize, i32)], byte_offset: usize) -> i32 {
+    match offsets.binary_search_by_key(&byte_offset, |&(b, _)| b) {
+        Ok(i) => offsets[i].1,
+        Err(i) => offsets[i.saturating_sub(1)].1,
+    }
+}
+
+/* Line breaking */
+
+pub(crate) struct UBreakIterator {
+    segmenter: LineSegmenter,
+    breaks: Vec<i32>,
+    next: usize,
+}
+
+pub(crate) unsafe fn ubrk_open(
+    type_0: UBreakIteratorType,
+    _locale: *const i8,
+    text: *const UChar,
+    textLength: i32,
+    status: *mut UErrorCode,
+) -> *mut UBreakIterator {
+    if failed(status) {
+        return ptr::null_mut();
+    }
+    if type_0 != UBRK_LINE {
+        set_error(status, U_ILLEGAL_ARGUMENT_ERROR);
+        return ptr::null_mut();
+    }
+    let bi = Box::into_raw(Box::new(UBreakIterator {
+        segmenter: LineSegmenter::new_dictionary(),
+        breaks: Vec::new(),
+        next: 0,
+    }));
+    if !text.is_null() {
+        ubrk_setText(bi, text, textLength, status);
+    }
+    bi
+}
+
+pub(crate) unsafe fn ubrk_setText(
+    bi: *mut UBreakIterator,
+    text: *const UChar,
+    textLength: i32,
+    status: *mut UErrorCode,
+) {
+    if failed(status) || bi.is_null() {
+        return;
+    }
+    (*bi).breaks = (*bi)
+        .segmenter
+        .segment_utf16(icu_slice(text, textLength))
+        .filter(|&b| b > 0)
+        .map(|b| b as i32)
+        .collect();
+    (*bi).next = 0;
+}
+
+pub(crate) unsafe fn ubrk_next(bi: *mut UBreakIterator) -> i32 {
+    let bi = &mut *bi;
+    match bi.breaks.get(bi.next) {
+        Some(&b) => {
+            bi.next += 1;
+            b
+        }
+        None => UBRK_DONE,
+    }
+}
+
+pub(crate) unsafe fn ubrk_close(bi: *mut UBreakIterator) {
+    if !bi.is_null() {
+        drop(Box::from_raw(bi));
+    }
+}
+
+/* Bidi analysis */
+
+struct VisualRun {
+    logical_start: i32,
+    length: i32,
+    rtl: bool,
+}
+
+pub(crate) struct UBiDi {
+    direction: UBiDiDirection,
+    runs: Vec<VisualRun>,
+}
+
+pub(crate) unsafe fn ubidi_open() -> *mut UBiDi {
+    Box::into_raw(Box::new(UBiDi {
+        direction: UBIDI_LTR,
+        runs: Vec::new(),
+    }))
+}
+
+pub(crate) unsafe fn ubidi_close(pBiDi: *mut UBiDi) {
+    if !pBiDi.is_null() {
+        drop(Box::from_raw(pBiDi));
+    }
+}
+
+/// The paragraph level to hand to `unicode_bidi`: `None` asks it to use the
+/// first strong character, falling back to LTR, so the "default RTL" mode
+/// has to be emulated by checking for a strong character ourselves.
+fn paragraph_level(text: &str, paraLevel: UBiDiLevel) -> Option<Level> {
+    match paraLevel {
+        UBIDI_DEFAULT_LTR => None,
+        UBIDI_DEFAULT_RTL => {
+            let has_strong = text
+                .chars()
+                .any(|c| matches!(bidi_class(c), BidiClass::L | BidiClass::R | BidiClass::AL));
+            if has_strong {
+                None
+            } else {
+                Some(Level::rtl())
+            }
+        }
+        n => Some(Level::new(n).unwrap_or_else(|_| Level::ltr())),
+    }
+}
+
+pub(crate) unsafe fn ubidi_setPara(
+    pBiDi: *mut UBiDi,
+    text: *const UChar,
+    length: i32,
+    paraLevel: UBiDiLevel,
+    _embeddingLevels: *mut UBiDiLevel,
+    pErrorCode: *mut UErrorCode,
+) {
+    if failed(pErrorCode) {
+        return;
+    }
+    let bidi = &mut *pBiDi;
+    let (s, offsets) = decode_utf16_with_offsets(icu_slice(text, length));
+    let info = BidiInfo::new(&s, paragraph_level(&s, paraLevel));
+
+    bidi.runs.clear();
+    for para in &info.paragraphs {
+        let (levels, runs) = info.visual_runs(para, para.range.clone());
+        for run in runs {
+            let start = utf16_offset(&offsets, run.start);
+            bidi.runs.push(VisualRun {
+                logical_start: start,
+                length: utf16_offset(&offsets, run.end) - start,
+                rtl: levels[run.start].is_rtl(),
+            });
+        }
+    }
+
+    let any_rtl = info.levels.iter().any(|l| l.is_rtl());
+    let any_ltr = info.levels.iter().any(|l| l.is_ltr());
+    bidi.direction = match (any_ltr, any_rtl) {
+        (true, true) => UBIDI_MIXED,
+        (false, true) => UBIDI_RTL,
+        (true, false) => UBIDI_LTR,
+        (false, false) => {
+            if matches!(paragraph_level(&s, paraLevel), Some(l) if l.is_rtl()) {
+                UBIDI_RTL
+            } else {
+                UBIDI_LTR
+            }
+        }
+    };
+}
+
+pub(crate) unsafe fn ubidi_getDirection(pBiDi: *const UBiDi) -> UBiDiDirection {
+    (*pBiDi).direction
+}
+
+pub(crate) unsafe fn ubidi_countRuns(pBiDi: *mut UBiDi, pErrorCode: *mut UErrorCode) -> i32 {
+    if failed(pErrorCode) {
+        return -1;
+    }
+    (*pBiDi).runs.len() as i32
+}
+
+pub(crate) unsafe fn ubidi_getVisualRun(
+    pBiDi: *mut UBiDi,
+    runIndex: i32,
+    pLogicalStart: *mut i32,
+    pLength: *mut i32,
+) -> UBiDiDirection {
+    let bidi = &*pBiDi;
+    let run = match bidi.runs.get(runIndex as usize) {
+        Some(r) => r,
+        None => return UBIDI_LTR,
+    };
+    if !pLogicalStart.is_null() {
+        *pLogicalStart = run.logical_start;
+    }
+    if !pLength.is_null() {
+        *pLength = run.length;
+    }
+    if run.rtl {
+        UBIDI_RTL
+    } else {
+        UBIDI_LTR
+    }
+}
+
+/* Encoding conversion */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Charset {
+    Utf8,
+    Utf16BE,
+    Utf16LE,
+    Utf32BE,
+    Utf32LE,
+    Latin1,
+    Legacy(&'static Encoding),
+}
+
+pub(crate) struct UConverter {
+    charset: Charset,
+}
+
+impl Charset {
+    /// Look up a converter name. Like ICU, we ignore case and the
+    /// punctuation that people sprinkle into encoding names; anything that
+    /// isn't a Unicode form or Latin-1 is handed to the WHATWG label table.
+    fn from_name(name: &str) -> Option<Charset> {
+        let key: String = name
+            .chars()
+            .filter(|c| !matches!(c, '-' | '_' | ' '))
+            .flat_map(char::to_lowercase)
+            .collect();
+
+        let cs = match key.as_str() {
+            "utf8" => Charset::Utf8,
+            "utf16" | "utf16be" => Charset::Utf16BE,
+            "utf16le" => Charset::Utf16LE,
+            "utf32" | "utf32be" => Charset::Utf32BE,
+            "utf32le" => Charset::Utf32LE,
+            "latin1" | "l1" | "iso88591" | "88591" | "cp819" | "ibm819" => Charset::Latin1,
+            "macroman" => Charset::Legacy(encoding_rs::MACINTOSH),
+            _ => {
+                let enc = Encoding::for_label(name.as_bytes())
+                    .or_else(|| Encoding::for_label(key.as_bytes()))?;
+                if enc == encoding_rs::UTF_8 {
+                    Charset::Utf8
+                } else if enc == encoding_rs::UTF_16BE {
+                    Charset::Utf16BE
+                } else if enc == encoding_rs::UTF_16LE {
+                    Charset::Utf16LE
+                } else if enc == encoding_rs::REPLACEMENT {
+                    return None;
+                } else {
+                    Charset::Legacy(enc)
+                }
+            }
+        };
+
+        Some(cs)
+    }
+
+    fn decode(self, bytes: &[u8]) -> String {
+        fn units<F: Fn(&[u8]) -> u32>(bytes: &[u8], size: usize, f: F) -> Vec<u32> {
+            bytes.chunks(size).map(f).collect()
+        }
+
+        fn chars_from_u32(units: Vec<u32>) -> String {
+            units
+                .into_iter()
+                .map(|u| std::char::from_u32(u).unwrap_or(std::char::REPLACEMENT_CHARACTER))
+                .collect()
+        }
+
+        fn utf16(units: Vec<u32>) -> String {
+            std::char::decode_utf16(units.into_iter().map(|u| u as u16))
+                .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+                .collect()
+        }
+
+        // an incomplete trailing code unit becomes U+FFFD
+        let pad = |b: &[u8], size: usize| -> u32 {
+            if b.len() < size {
+                0xfffd
+            } else {
+                0
+            }
+        };
+
+        match self {
+            Charset::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Charset::Utf16BE => utf16(units(bytes, 2, |b| match b {
+                [hi, lo] => u32::from(*hi) << 8 | u32::from(*lo),
+                _ => pad(b, 2),
+            })),
+            Charset::Utf16LE => utf16(units(bytes, 2, |b| match b {
+                [lo, hi] => u32::from(*hi) << 8 | u32::from(*lo),
+                _ => pad(b, 2),
+            })),
+            Charset::Utf32BE => chars_from_u32(units(bytes, 4, |b| match b {
+                [a, b, c, d] => u32::from_be_bytes([*a, *b, *c, *d]),
+                _ => pad(b, 4),
+            })),
+            Charset::Utf32LE => chars_from_u32(units(bytes, 4, |b| match b {
+                [a, b, c, d] => u32::from_le_bytes([*a, *b, *c, *d]),
+                _ => pad(b, 4),
+            })),
+            Charset::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Charset::Legacy(enc) => enc.decode_without_bom_handling(bytes).0.into_owned(),
+        }
+    }
+
+    fn encode(self, s: &str) -> Vec<u8> {
+        match self {
+            Charset::Utf8 => s.as_bytes().to_vec(),
+            Charset::Utf16BE => s
+                .encode_utf16()
+                .flat_map(|u| u.to_be_bytes().to_vec())
+                .collect(),
+            Charset::Utf16LE => s
+                .encode_utf16()
+                .flat_map(|u| u.to_le_bytes().to_vec())
+                .collect(),
+            Charset::Utf32BE => s
+                .chars()
+                .flat_map(|c| (c as u32).to_be_bytes().to_vec())
+                .collect(),
+            Charset::Utf32LE => s
+                .chars()
+                .flat_map(|c| (c as u32).to_le_bytes().to_vec())
+                .collect(),
+            Charset::Latin1 => s
+                .chars()
+                .map(|c| if (c as u32) < 0x100 { c as u8 } else { 0x1a })
+                .collect(),
+            Charset::Legacy(enc) => enc.encode(s).0.into_owned(),
+        }
+    }
+}
+
+/// Copy `data` to an ICU-style output buffer, returning the full length
+/// and flagging an overflow if it doesn't fit. As in ICU, the output is
+/// zero-terminated when there's room for it.
+unsafe fn fill_buffer<T: Copy + Default>(
+    data: &[T],
+    dest: *mut T,
+    capacity: i32,
+    err: *mut UErrorCode,
+) -> i32 {
+    let capacity = capacity.max(0) as usize;
+    if data.len() > capacity || (dest.is_null() && !data.is_empty()) {
+        set_error(err, U_BUFFER_OVERFLOW_ERROR);
+    } else if !dest.is_null() {
+        ptr::copy_nonoverlapping(data.as_ptr(), dest, data.len());
+        if data.len() < capacity {
+            *dest.add(data.len()) = T::default();
+        }
+    }
+    data.len() as i32
+}
+
+pub(crate) unsafe fn ucnv_open(converterName: *const i8, err: *mut UErrorCode) -> *mut UConverter {
+    if failed(err) {
+        return ptr::null_mut();
+    }
+    let charset = if converterName.is_null() {
+        Some(Charset::Utf8)
+    } else {
+        Charset::from_name(&CStr::from_ptr(converterName).to_string_lossy())
+    };
+    match charset {
+        Some(charset) => Box::into_raw(Box::new(UConverter { charset })),
+        None => {
+            set_error(err, U_FILE_ACCESS_ERROR);
+            ptr::null_mut()
+        }
+    }
+}
+
+pub(crate) unsafe fn ucnv_close(converter: *mut UConverter) {
+    if !converter.is_null() {
+        drop(Box::from_raw(converter));
+    }
+}
+
+pub(crate) unsafe fn ucnv_toAlgorithmic(
+    algorithmicType: UConverterType,
+    cnv: *mut UConverter,
+    target: *mut i8,
+    targetCapacity: i32,
+    source: *const i8,
+    sourceLength: i32,
+    pErrorCode: *mut UErrorCode,
+) -> i32 {
+    if failed(pErrorCode) {
+        return 0;
+    }
+    let output = match algorithmicType {
+        UCNV_UTF32_LittleEndian => Charset::Utf32LE,
+        UCNV_UTF32_BigEndian => Charset::Utf32BE,
+        _ => {
+            set_error(pErrorCode, U_ILLEGAL_ARGUMENT_ERROR);
+            return 0;
+        }
+    };
+    let text = (*cnv)
+        .charset
+        .decode(icu_slice(source as *const u8, sourceLength));
+    fill_buffer(
+        &output.encode(&text),
+        target as *mut u8,
+        targetCapacity,
+        pErrorCode,
+    )
+}
+
+pub(crate) unsafe fn ucnv_fromUChars(
+    cnv: *mut UConverter,
+    dest: *mut i8,
+    destCapacity: i32,
+    src: *const UChar,
+    srcLength: i32,
+    pErrorCode: *mut UErrorCode,
+) -> i32 {
+    if failed(pErrorCode) {
+        return 0;
+    }
+    let text = String::from_utf16_lossy(icu_slice(src, srcLength));
+    fill_buffer(
+        &(*cnv).charset.encode(&text),
+        dest as *mut u8,
+        destCapacity,
+        pErrorCode,
+    )
+}
+
+pub(crate) unsafe fn ucnv_toUChars(
+    cnv: *mut UConverter,
+    dest: *mut UChar,
+    destCapacity: i32,
+    src: *const i8,
+    srcLength: i32,
+    pErrorCode: *mut UErrorCode,
+) -> i32 {
+    if failed(pErrorCode) {
+        return 0;
+    }
+    let text = (*cnv)
+        .charset
+        .decode(icu_slice(src as *const u8, srcLength));
+    let units: Vec<UChar> = text.encode_utf16().collect();
+    fill_buffer(&units, dest, destCapacity, pErrorCode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaks(s: &str) -> Vec<i32> {
+        let text: Vec<UChar> = s.encode_utf16().collect();
+        let mut status = U_ZERO_ERROR;
+        unsafe {
+            let bi = ubrk_open(
+                UBRK_LINE,
+                ptr::null(),
+                text.as_ptr(),
+                text.len() as i32,
+                &mut status,
+            );
+            assert_eq!(status, U_ZERO_ERROR);
+            let mut result = Vec::new();
+            loop {
+                let b = ubrk_next(bi);
+                if b == UBRK_DONE {
+                    break;
+                }
+                result.push(b);
+            }
+            ubrk_close(bi);
+            result
+        }
+    }
+
+    #[test]
+    fn line_breaks() {
+        assert_eq!(breaks("hello world"), vec![6, 11]);
+        assert_eq!(breaks("foo-bar"), vec![4, 7]);
+        // ideographs may break between every character, but not before
+        // closing punctuation
+        assert_eq!(breaks("\u{4e00}\u{4e8c}\u{4e09}\u{3002}"), vec![1, 2, 4]);
+        // offsets are in UTF-16 code units
+        assert_eq!(breaks("\u{1f600} x"), vec![3, 4]);
+        assert_eq!(breaks(""), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn dictionary_line_breaks() {
+        // Thai and Lao don't separate words with spaces
+        assert_eq!(
+            breaks("\u{e20}\u{e32}\u{e29}\u{e32}\u{e44}\u{e17}\u{e22}"),
+            vec![4, 7]
+        );
+        assert_eq!(
+            breaks("\u{ea5}\u{eb2}\u{ea7}\u{e9e}\u{eb2}\u{eaa}\u{eb2}"),
+            vec![3, 7]
+        );
+    }
+
+    #[test]
+    fn bidi_runs() {
+        let text: Vec<UChar> = "abc \u{5d0}\u{5d1}\u{5d2} def".encode_utf16().collect();
+        let mut err = U_ZERO_ERROR;
+        unsafe {
+            let bidi = ubidi_open();
+            ubidi_setPara(
+                bidi,
+                text.as_ptr(),
+                text.len() as i32,
+                UBIDI_DEFAULT_LTR,
+                ptr::null_mut(),
+                &mut err,
+            );
+            assert_eq!(ubidi_getDirection(bidi), UBIDI_MIXED);
+            assert_eq!(ubidi_countRuns(bidi, &mut err), 3);
+            let (mut start, mut len) = (0, 0);
+            assert_eq!(ubidi_getVisualRun(bidi, 1, &mut start, &mut len), UBIDI_RTL);
+            assert_eq!((start, len), (4, 3));
+            ubidi_close(bidi);
+        }
+    }
+
+    #[test]
+    fn converters() {
+        let mut err = U_ZERO_ERROR;
+        let mut out = [0u16; 16];
+        unsafe {
+            let cnv = ucnv_open(b"MacRoman\0".as_ptr() as *const i8, &mut err);
+            assert!(!cnv.is_null());
+            let n = ucnv_toUChars(
+                cnv,
+                out.as_mut_ptr(),
+                16,
+                b"\x8e\xa5".as_ptr() as *const i8,
+                2,
+                &mut err,
+            );
+            assert_eq!(&out[..n as usize], &[0xe9, 0x2022]);
+            ucnv_close(cnv);
+
+            let cnv = ucnv_open(b"latin1\0".as_ptr() as *const i8, &mut err);
+            let mut utf32 = [0u8; 8];
+            let n = ucnv_toAlgorithmic(
+                UCNV_UTF32_LittleEndian,
+                cnv,
+                utf32.as_mut_ptr() as *mut i8,
+                8,
+                b"\x80\xe9".as_ptr() as *const i8,
+                2,
+                &mut err,
+            );
+            assert_eq!(&utf32[..n as usize], &[0x80, 0, 0, 0, 0xe9, 0, 0, 0]);
+            ucnv_close(cnv);
+
+            assert_eq!(err, U_ZERO_ERROR);
+            assert!(ucnv_open(b"no-such-encoding\0".as_ptr() as *const i8, &mut err).is_null());
+            assert_eq!(err, U_FILE_ACCESS_ERROR);
+        }
+    }
+}
@@ -9,6 +9,7 @@
 )]
 
 use crate::streq_ptr;
+use crate::xetex_font_manager::{font_index_entry_t, XeTeXFontMgr_sFontIndex};
 use crate::xetex_ini::{
     halt_on_error_p, in_initex_mode, semantic_pagination_enabled, synctex_enabled,
 };
@@ -45,6 +46,12 @@ pub unsafe extern "C" fn tt_xetex_set_int_variable(mut var_name: *mut i8, mut va
     0i32
     /* success */
 }
+/// Restricts native font lookup to the faces in `index`. With `None`, names
+/// are looked up among the fonts installed on the host, through fontconfig or
+/// CoreText.
+pub unsafe fn tt_xetex_set_font_index(index: Option<Vec<font_index_entry_t>>) {
+    XeTeXFontMgr_sFontIndex = index;
+}
 pub(crate) unsafe fn tt_xetex_set_string_variable(
     mut _var_name: *mut i8,
     mut _value: *mut i8,
@@ -11,12 +11,13 @@
 use bridge::DisplayExt;
 use std::ffi::CStr;
 
-use crate::stub_icu as icu;
-use crate::stub_teckit as teckit;
+use crate::icu;
+use crate::teckit;
 use crate::xetex_xetexd::print_c_string;
 use crate::{streq_ptr, strstartswith};
 use bridge::{ttstub_input_close, ttstub_input_get_size, ttstub_input_open, ttstub_input_read};
 use libc::free;
+use std::ops::Range;
 use std::ptr;
 
 #[cfg(target_os = "macos")]
@@ -31,10 +32,10 @@ use crate::cf_prelude::{
 use crate::core_memory::{mfree, xcalloc, xmalloc, xrealloc, xstrdup};
 use crate::xetex_ini::memory_word;
 use crate::xetex_ini::{
-    loaded_font_design_size, loaded_font_flags, loaded_font_letter_space, loaded_font_mapping,
-    mapped_text, name_length, name_of_file, native_font_type_flag, xdv_buffer, DEPTH_BASE,
-    FONT_AREA, FONT_FLAGS, FONT_INFO, FONT_LAYOUT_ENGINE, FONT_LETTER_SPACE, HEIGHT_BASE,
-    PARAM_BASE,
+    internal_font_number, loaded_font_design_size, loaded_font_fallback, loaded_font_flags,
+    loaded_font_letter_space, loaded_font_mapping, mapped_text, name_length, name_of_file,
+    native_font_type_flag, xdv_buffer, DEPTH_BASE, FONT_AREA, FONT_FALLBACK, FONT_FLAGS, FONT_INFO,
+    FONT_LAYOUT_ENGINE, FONT_LETTER_SPACE, HEIGHT_BASE, PARAM_BASE,
 };
 use crate::xetex_output::{print_char, print_int, print_nl, print_raw_char};
 use crate::xetex_scaledmath::xn_over_d;
@@ -45,9 +46,11 @@ use crate::xetex_xetex0::{
 };
 
 use crate::stub_stdio::strcasecmp;
+use crate::xetex_font_info::hb_variation_t;
 use crate::xetex_layout_interface::*;
 use harfbuzz_sys::{hb_feature_t, hb_tag_from_string, hb_tag_t};
 use libc::{memcpy, strcat, strcpy, strdup, strlen, strncpy, strstr};
+use unicode_normalization::char::is_combining_mark;
 
 pub(crate) type size_t = usize;
 pub(crate) type ssize_t = isize;
@@ -341,6 +344,8 @@ unsafe extern "C" fn load_mapping_file(
                 &mut cnv,
             );
         }
+        /* the converter keeps its own copy of the table */
+        free(mapping as *mut libc::c_void);
         if cnv.is_null() {
             /* tracing */
             font_mapping_warning(buffer as *const libc::c_void, strlen(buffer) as i32, 2i32);
@@ -582,6 +587,17 @@ pub(crate) unsafe fn readCommonFeatures(
         *letterspace = read_double(&mut sep) as f32;
         return 1i32;
     }
+    sep = strstartswith(feat, b"fallback\x00" as *const u8 as *const i8);
+    if !sep.is_null() {
+        if *sep as i32 != '=' as i32 {
+            return -1i32;
+        }
+        sep = sep.offset(1);
+        let names =
+            std::slice::from_raw_parts(sep as *const u8, end.wrapping_offset_from(sep) as usize);
+        loaded_font_fallback.extend(fallback_font_names(names));
+        return 1i32;
+    }
     sep = strstartswith(feat, b"color\x00" as *const u8 as *const i8);
     if !sep.is_null() {
         let mut s: *const i8 = ptr::null();
@@ -645,6 +661,34 @@ unsafe extern "C" fn readFeatureNumber(
     }
     true
 }
+unsafe extern "C" fn readVariation(
+    mut font: XeTeXFont,
+    mut s: *const i8,
+    mut e: *const i8,
+    mut tag: *mut hb_tag_t,
+    mut value: *mut f32,
+) -> bool
+/* s...e is a "tag=value" string naming an axis of a variable font */ {
+    let mut cp: *const i8 = s;
+    while cp < e && (*cp as u8).is_ascii_alphanumeric() {
+        cp = cp.offset(1)
+    }
+    let len = cp.wrapping_offset_from(s) as i32;
+    if len < 1i32 || len > 4i32 || cp >= e || *cp as i32 != '=' as i32 {
+        return false;
+    }
+    *tag = hb_tag_from_string(s, len);
+    if getVariationAxisRange(font, *tag).is_none() {
+        return false;
+    }
+    cp = cp.offset(1);
+    let start = cp;
+    *value = read_double(&mut cp) as f32;
+    while *cp as i32 == ' ' as i32 || *cp as i32 == '\t' as i32 {
+        cp = cp.offset(1)
+    }
+    cp != start && cp == e
+}
 unsafe extern "C" fn loadOTfont(
     mut fontRef: PlatformFontRef,
     mut font: XeTeXFont,
@@ -659,8 +703,10 @@ unsafe extern "C" fn loadOTfont(
         | 0_u32 & 0xff_u32;
     let mut language: *mut i8 = 0 as *mut i8;
     let mut features: *mut hb_feature_t = 0 as *mut hb_feature_t;
+    let mut variations: *mut hb_variation_t = 0 as *mut hb_variation_t;
     let mut shapers: *mut *mut i8 = 0 as *mut *mut i8;
     let mut nFeatures: i32 = 0i32;
+    let mut nVariations: i32 = 0i32;
     let mut nShapers: i32 = 0i32;
     let mut cp2: *mut i8 = 0 as *mut i8;
     let mut cp3: *const i8 = ptr::null();
@@ -700,6 +746,8 @@ unsafe extern "C" fn loadOTfont(
             features,
             nFeatures,
             tmpShapers.as_mut_ptr(),
+            0 as *mut hb_variation_t,
+            0i32,
             rgbValue,
             extend,
             slant,
@@ -824,7 +872,20 @@ unsafe extern "C" fn loadOTfont(
                             match current_block {
                                 13857423536159756434 => {}
                                 _ => {
-                                    if *cp1 as i32 == '+' as i32 {
+                                    let mut varValue: f32 = 0.0f32;
+                                    if readVariation(font, cp1, cp2, &mut tag, &mut varValue) {
+                                        variations = xrealloc(
+                                            variations as *mut libc::c_void,
+                                            ((nVariations + 1i32) as u64).wrapping_mul(
+                                                ::std::mem::size_of::<hb_variation_t>() as u64,
+                                            ) as _,
+                                        )
+                                            as *mut hb_variation_t;
+                                        (*variations.offset(nVariations as isize)).tag = tag;
+                                        (*variations.offset(nVariations as isize)).value = varValue;
+                                        nVariations += 1;
+                                        current_block = 13857423536159756434;
+                                    } else if *cp1 as i32 == '+' as i32 {
                                         let mut param: i32 = 0i32;
                                         tag = read_tag_with_param(cp1.offset(1), &mut param);
                                         features = xrealloc(
@@ -938,12 +999,24 @@ unsafe extern "C" fn loadOTfont(
         setFontLayoutDir(font, 1i32);
     }
     engine = createLayoutEngine(
-        fontRef, font, script, language, features, nFeatures, shapers, rgbValue, extend, slant,
+        fontRef,
+        font,
+        script,
+        language,
+        features,
+        nFeatures,
+        shapers,
+        variations,
+        nVariations,
+        rgbValue,
+        extend,
+        slant,
         embolden,
     );
     if engine.is_null() {
         // only free these if creation failed, otherwise the engine now owns them
         free(features as *mut libc::c_void);
+        free(variations as *mut libc::c_void);
         free(shapers as *mut libc::c_void);
     } else {
         native_font_type_flag = 0xfffeu32 as i32
@@ -1024,6 +1097,7 @@ pub(crate) unsafe fn find_native_font(
     loaded_font_mapping = 0 as *mut libc::c_void;
     loaded_font_flags = 0_i8;
     loaded_font_letter_space = 0i32;
+    loaded_font_fallback = Vec::new();
     splitFontName(name, &mut var, &mut feat, &mut end, &mut index);
     nameString =
         xmalloc((var.wrapping_offset_from(name) as i64 + 1i32 as i64) as size_t) as *mut i8;
@@ -1047,6 +1121,33 @@ pub(crate) unsafe fn find_native_font(
         );
         *featString.offset((end.wrapping_offset_from(feat) as i64 - 1i32 as i64) as isize) = 0_i8
     }
+    // CoreText can only describe fonts that it reaches itself, and fonts from
+    // the bundle are not among them, so with a font index we resolve the name
+    // here and load the face as a file, as if it had been given as "[file]"
+    #[cfg(target_os = "macos")]
+    {
+        use crate::xetex_font_manager::{XeTeXFontMgr_findIndexedFont, XeTeXFontMgr_sFontIndex};
+        if *nameString.offset(0) as i32 != '[' as i32 {
+            if let Some(ref faces) = XeTeXFontMgr_sFontIndex {
+                let indexed = XeTeXFontMgr_findIndexedFont(
+                    faces,
+                    &CStr::from_ptr(nameString).to_string_lossy(),
+                );
+                if let Some(face) = indexed {
+                    free(nameString as *mut libc::c_void);
+                    nameString = xmalloc((face.file.len() + 2) as size_t) as *mut i8;
+                    *nameString.offset(0) = '[' as i32 as i8;
+                    memcpy(
+                        nameString.offset(1) as *mut libc::c_void,
+                        face.file.as_ptr() as *const libc::c_void,
+                        face.file.len(),
+                    );
+                    *nameString.offset(face.file.len() as isize + 1) = 0_i8;
+                    index = face.index as i32;
+                }
+            }
+        }
+    }
     // check for "[filename]" form, don't search maps in this case
     if *nameString.offset(0) as i32 == '[' as i32 {
         if scaled_size < 0i32 {
@@ -1242,6 +1343,7 @@ pub(crate) unsafe fn ot_font_get(mut what: i32, mut pEngine: *mut libc::c_void)
     let mut fontInst: XeTeXFont = getFont(engine);
     match what {
         1 => return countGlyphs(fontInst) as i32,
+        2 => return countVariationAxes(fontInst) as i32,
         8 => {
             /* ie Graphite features */
             return countGraphiteFeatures(engine) as i32;
@@ -1259,6 +1361,16 @@ pub(crate) unsafe fn ot_font_get_1(
     let mut engine: XeTeXLayoutEngine = pEngine as XeTeXLayoutEngine;
     let mut fontInst: XeTeXFont = getFont(engine);
     match what {
+        3 => return getIndVariationAxis(fontInst, param as u32) as i32,
+        5 | 6 | 7 => {
+            /* axis minimum, maximum and default, in design units */
+            return match getVariationAxisRange(fontInst, param as hb_tag_t) {
+                Some((min, _, _)) if what == 5 => min.round() as i32,
+                Some((_, max, _)) if what == 6 => max.round() as i32,
+                Some((_, _, def)) => def.round() as i32,
+                None => -1i32,
+            };
+        }
         17 => return countLanguages(fontInst, param as hb_tag_t) as i32,
         19 => return getIndScript(fontInst, param as u32) as i32,
         9 => {
@@ -1334,6 +1446,35 @@ pub(crate) unsafe fn gr_print_font_name(
         gr_label_destroy(name as *mut libc::c_void);
     };
 }
+pub(crate) unsafe fn ot_print_font_name(
+    mut what: i32,
+    mut pEngine: *mut libc::c_void,
+    mut param: i32,
+) {
+    let mut engine: XeTeXLayoutEngine = pEngine as XeTeXLayoutEngine;
+    match what {
+        7 => {
+            if let Some(name) = getVariationAxisName(getFont(engine), param as hb_tag_t) {
+                print_c_string(name.as_ptr());
+            }
+        }
+        _ => {}
+    }
+}
+pub(crate) unsafe fn ot_font_get_named(mut what: i32, mut pEngine: *mut libc::c_void) -> i32 {
+    let mut rval: i32 = -1i32;
+    let mut engine: XeTeXLayoutEngine = pEngine as XeTeXLayoutEngine;
+    match what {
+        4 => {
+            let tag = findVariationAxisByName(getFont(engine), name_of_file, name_length);
+            if tag != 0 {
+                rval = tag as i32
+            }
+        }
+        _ => {}
+    }
+    rval
+}
 pub(crate) unsafe fn gr_font_get_named(mut what: i32, mut pEngine: *mut libc::c_void) -> i32 {
     let mut rval: i64 = -1i32 as i64;
     let mut engine: XeTeXLayoutEngine = pEngine as XeTeXLayoutEngine;
@@ -1472,6 +1613,8 @@ pub(crate) unsafe fn make_font_def(mut f: i32) -> i32 {
     let mut extend: f32 = 1.0f64 as f32;
     let mut slant: f32 = 0.0f64 as f32;
     let mut embolden: f32 = 0.0f64 as f32;
+    let mut variations: *const hb_variation_t = ptr::null();
+    let mut nVariations: i32 = 0i32;
     match FONT_AREA[f as usize] as u32 {
         #[cfg(target_os = "macos")]
         0xffffu32 => {
@@ -1539,6 +1682,7 @@ pub(crate) unsafe fn make_font_def(mut f: i32) -> i32 {
             extend = getExtendFactor(engine);
             slant = getSlantFactor(engine);
             embolden = getEmboldenFactor(engine);
+            nVariations = getVariations(engine, &mut variations);
             size = D2Fix(getPointSize(engine) as f64)
         }
         _ => panic!("bad native font flag in `make_font_def`"),
@@ -1550,12 +1694,18 @@ pub(crate) unsafe fn make_font_def(mut f: i32) -> i32 {
     //  l[1] n[l]
     //  if flags & COLORED:
     //      c[4]
+    //  if flags & VARIATIONS:
+    //      nv[2] axes[4 * nv] values[4 * nv]
      */
     fontDefLength = 4i32 + 2i32 + 1i32 + filenameLen as i32 + 4i32; /* face index */
     if FONT_FLAGS[f as usize] as i32 & 0x1i32 != 0i32 {
         fontDefLength += 4i32; /* 32-bit RGBA value */
         flags = (flags as i32 | 0x200i32) as u16
     }
+    if nVariations > 0i32 {
+        fontDefLength += 2i32 + 8i32 * nVariations;
+        flags = (flags as i32 | 0x800i32) as u16
+    }
     if extend as f64 != 1.0f64 {
         fontDefLength += 4i32;
         flags = (flags as i32 | 0x1000i32) as u16
@@ -1592,6 +1742,19 @@ pub(crate) unsafe fn make_font_def(mut f: i32) -> i32 {
         *(cp as *mut u32) = SWAP32(rgba);
         cp = cp.offset(4)
     }
+    if flags as i32 & 0x800i32 != 0 {
+        let variations = std::slice::from_raw_parts(variations, nVariations as usize);
+        *(cp as *mut u16) = SWAP16(nVariations as u16);
+        cp = cp.offset(2);
+        for v in variations {
+            *(cp as *mut u32) = SWAP32(v.tag);
+            cp = cp.offset(4)
+        }
+        for v in variations {
+            *(cp as *mut u32) = SWAP32(D2Fix(v.value as f64) as u32);
+            cp = cp.offset(4)
+        }
+    }
     if flags as i32 & 0x1000i32 != 0 {
         let mut f_0: Fixed = D2Fix(extend as f64);
         *(cp as *mut u32) = SWAP32(f_0 as u32);
@@ -2240,6 +2403,74 @@ pub(crate) unsafe fn map_char_to_glyph(mut font: i32, mut ch: i32) -> i32 {
         _ => panic!("bad native font flag in `map_char_to_glyph`"),
     }
 }
+/// Returns the font that should render `ch` when set in `font`: `font` itself
+/// if it has a glyph for it, otherwise the first font of its fallback chain
+/// that does. Returns `None` if no font in the chain covers the character.
+pub(crate) unsafe fn fallback_font_for_char(
+    font: internal_font_number,
+    ch: i32,
+) -> Option<internal_font_number> {
+    if map_char_to_glyph(font, ch) != 0 {
+        return Some(font);
+    }
+    FONT_FALLBACK[font as usize]
+        .iter()
+        .cloned()
+        .find(|&f| map_char_to_glyph(f, ch) != 0)
+}
+/// Splits the UTF-16 `text` of a native word set in `font` into runs that
+/// each use a single font of `font`'s fallback chain. Combining marks,
+/// joiners and variation selectors stay with the run they follow, so that
+/// a cluster is never shaped across two fonts.
+pub(crate) unsafe fn fallback_runs(
+    font: internal_font_number,
+    text: &[u16],
+) -> Vec<(internal_font_number, Range<usize>)> {
+    split_fallback_runs(font, text, |ch| fallback_font_for_char(font, ch))
+}
+/// Does the work of `fallback_runs`, with `font_for_char` choosing the font
+/// of each character.
+fn split_fallback_runs<F>(
+    font: internal_font_number,
+    text: &[u16],
+    mut font_for_char: F,
+) -> Vec<(internal_font_number, Range<usize>)>
+where
+    F: FnMut(i32) -> Option<internal_font_number>,
+{
+    let mut runs: Vec<(internal_font_number, Range<usize>)> = Vec::new();
+    let mut offset = 0;
+    for c in std::char::decode_utf16(text.iter().cloned()) {
+        let (ch, len) = match c {
+            Ok(c) => (c as i32, c.len_utf16()),
+            Err(_) => (0xfffd, 1),
+        };
+        let f = match runs.last() {
+            Some(&(last, _)) if extends_cluster(ch) => last,
+            _ => font_for_char(ch).unwrap_or(font),
+        };
+        match runs.last_mut() {
+            Some((last, range)) if *last == f => range.end += len,
+            _ => runs.push((f, offset..offset + len)),
+        }
+        offset += len;
+    }
+    runs
+}
+fn extends_cluster(ch: i32) -> bool {
+    match ch {
+        0x200c | 0x200d | 0xfe00..=0xfe0f | 0xe0100..=0xe01ef => true,
+        _ => matches!(std::char::from_u32(ch as u32), Some(c) if is_combining_mark(c)),
+    }
+}
+/// Splits the value of a `fallback=` font feature into font names, which are
+/// separated by '|' since ',' already ends the feature.
+fn fallback_font_names(names: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    names
+        .split(|&c| c == b'|')
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_vec())
+}
 pub(crate) unsafe fn map_glyph_to_index(mut font: i32) -> i32
 /* glyph name is at name_of_file */ {
     match FONT_AREA[font as usize] as u32 {
@@ -2332,3 +2563,96 @@ pub(crate) unsafe fn real_get_native_word_cp(mut pNode: *mut libc::c_void, mut s
     }
     get_cp_code(f, actual_glyph as u32, side)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIMARY: internal_font_number = 1;
+    const FALLBACK: internal_font_number = 2;
+
+    /// Splits `s` as if the primary font only had ASCII and the fallback font
+    /// had everything else except U+2603, which no font has.
+    fn runs(s: &str) -> Vec<(internal_font_number, Range<usize>)> {
+        let text: Vec<u16> = s.encode_utf16().collect();
+        split_fallback_runs(PRIMARY, &text, |ch| match ch {
+            0..=0x7f => Some(PRIMARY),
+            0x2603 => None,
+            _ => Some(FALLBACK),
+        })
+    }
+
+    #[test]
+    fn runs_follow_coverage() {
+        assert_eq!(runs(""), vec![]);
+        assert_eq!(runs("abc"), vec![(PRIMARY, 0..3)]);
+        assert_eq!(
+            runs("a\u{3b1}\u{3b2}b"),
+            vec![(PRIMARY, 0..1), (FALLBACK, 1..3), (PRIMARY, 3..4)]
+        );
+        // characters that no font has stay in the primary font
+        assert_eq!(runs("a\u{2603}b"), vec![(PRIMARY, 0..3)]);
+    }
+
+    #[test]
+    fn runs_keep_clusters_together() {
+        // combining marks follow their base, whichever font covers them
+        assert_eq!(
+            runs("\u{3b1}\u{301}a"),
+            vec![(FALLBACK, 0..2), (PRIMARY, 2..3)]
+        );
+        assert_eq!(
+            runs("e\u{301}\u{3b1}"),
+            vec![(PRIMARY, 0..2), (FALLBACK, 2..3)]
+        );
+        // joiners and variation selectors too
+        assert_eq!(
+            runs("a\u{200d}\u{2665}\u{fe0f}"),
+            vec![(PRIMARY, 0..2), (FALLBACK, 2..4)]
+        );
+        assert_eq!(runs("a\u{fe0e}"), vec![(PRIMARY, 0..2)]);
+        assert_eq!(runs("a\u{e0100}"), vec![(PRIMARY, 0..3)]);
+        // a mark with nothing before it starts its own run
+        assert_eq!(runs("\u{301}a"), vec![(FALLBACK, 0..1), (PRIMARY, 1..2)]);
+    }
+
+    #[test]
+    fn runs_count_utf16_units() {
+        // surrogate pairs are one character, two units long
+        assert_eq!(
+            runs("a\u{1f600}\u{1f3fb}b"),
+            vec![(PRIMARY, 0..1), (FALLBACK, 1..5), (PRIMARY, 5..6)]
+        );
+        // a lone surrogate counts as U+FFFD
+        let text = [0x61, 0xd800, 0x62];
+        assert_eq!(
+            split_fallback_runs(PRIMARY, &text, |ch| {
+                Some(if ch == 0xfffd { FALLBACK } else { PRIMARY })
+            }),
+            vec![(PRIMARY, 0..1), (FALLBACK, 1..2), (PRIMARY, 2..3)]
+        );
+    }
+
+    #[test]
+    fn cluster_extenders() {
+        for &ch in &[
+            0x301, 0x200c, 0x200d, 0xfe00, 0xfe0f, 0xe0100, 0xe01ef, 0x94d,
+        ] {
+            assert!(extends_cluster(ch), "U+{:04X}", ch);
+        }
+        for &ch in &[0x61, 0x3b1, 0x200b, 0xfe10, 0x1f600, 0xfffd] {
+            assert!(!extends_cluster(ch), "U+{:04X}", ch);
+        }
+    }
+
+    #[test]
+    fn fallback_names() {
+        let names = |s: &[u8]| fallback_font_names(s).collect::<Vec<_>>();
+        assert_eq!(names(b"Foo"), vec![b"Foo".to_vec()]);
+        assert_eq!(
+            names(b"[foo.otf]|Bar Sans:+smcp||"),
+            vec![b"[foo.otf]".to_vec(), b"Bar Sans:+smcp".to_vec()]
+        );
+        assert!(names(b"").is_empty());
+    }
+}
@@ -19,7 +19,11 @@ use harfbuzz_sys::{
     HB_MEMORY_MODE_WRITABLE,
 };
 
-use crate::freetype_sys_patch::{FT_Face_GetCharVariantIndex, FT_Get_Advance, FT_Load_Sfnt_Table};
+use crate::freetype_sys_patch::{
+    FT_Done_MM_Var, FT_Face_GetCharVariantIndex, FT_Get_Advance, FT_Get_MM_Var, FT_Get_Sfnt_Name,
+    FT_Get_Sfnt_Name_Count, FT_Load_Sfnt_Table, FT_MM_Var, FT_Set_Var_Design_Coordinates,
+    FT_SfntName,
+};
 use freetype::freetype_sys::{
     FT_Attach_Stream, FT_Done_Face, FT_Done_Glyph, FT_Get_Char_Index, FT_Get_First_Char,
     FT_Get_Glyph, FT_Get_Glyph_Name, FT_Get_Kerning, FT_Get_Name_Index, FT_Get_Next_Char,
@@ -30,9 +34,14 @@ use freetype::freetype_sys::{
     FT_Parameter, FT_Pointer, FT_Sfnt_Tag, FT_String, FT_UInt, FT_ULong, FT_Vector,
 };
 
-use bridge::{ttstub_input_close, ttstub_input_get_size, ttstub_input_open, ttstub_input_read};
+use bridge::{
+    ttstub_input_close, ttstub_input_get_size, ttstub_input_open, ttstub_input_read,
+    ttstub_issue_warning_slice,
+};
+use dpx::{check_for_woff, woff_to_sfnt};
 
-use std::ptr;
+use std::ffi::{CStr, CString};
+use std::{ptr, slice};
 
 use bridge::TTInputFormat;
 
@@ -56,6 +65,12 @@ extern "C" {
         destroy: hb_destroy_func_t,
     );
     #[no_mangle]
+    fn hb_font_set_variations(
+        font: *mut hb_font_t,
+        variations: *const hb_variation_t,
+        variations_length: libc::c_uint,
+    );
+    #[no_mangle]
     fn hb_font_funcs_set_glyph_func(
         ffuncs: *mut hb_font_funcs_t,
         func: hb_font_get_glyph_func_t,
@@ -116,6 +131,25 @@ pub(crate) struct GlyphBBox {
     pub(crate) yMax: f32,
 }
 
+/// An axis setting for a variable font, as taken by `hb_font_set_variations`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub(crate) struct hb_variation_t {
+    pub(crate) tag: hb_tag_t,
+    pub(crate) value: libc::c_float,
+}
+
+/// One axis of an OpenType variable font, with values in design units.
+#[derive(Copy, Clone)]
+pub(crate) struct VariationAxis {
+    pub(crate) tag: OTTag,
+    pub(crate) minimum: f64,
+    pub(crate) default: f64,
+    pub(crate) maximum: f64,
+    /// `name` table ID of the axis label
+    pub(crate) strid: u32,
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub(crate) struct XeTeXFontInst {
@@ -559,6 +593,28 @@ pub(crate) unsafe fn XeTeXFontInst_initialize(
         abort!("failed to read font file");
     }
     ttstub_input_close(handle);
+    // FreeType can only read WOFF2 if it was built with Brotli, so web fonts
+    // are unpacked before it sees them.
+    if check_for_woff(slice::from_raw_parts((*self_0).m_backingData, sz)) {
+        match woff_to_sfnt(slice::from_raw_parts((*self_0).m_backingData, sz)) {
+            Ok(sfnt) => {
+                free((*self_0).m_backingData as *mut libc::c_void);
+                sz = sfnt.len();
+                (*self_0).m_backingData = xmalloc(sz as _) as *mut FT_Byte;
+                slice::from_raw_parts_mut((*self_0).m_backingData, sz).copy_from_slice(&sfnt);
+            }
+            Err(msg) => {
+                let msg = format!(
+                    "could not unpack WOFF font {}: {}\0",
+                    CStr::from_ptr(pathname).to_string_lossy(),
+                    msg
+                );
+                ttstub_issue_warning_slice(msg.as_bytes());
+                *status = 1i32;
+                return;
+            }
+        }
+    }
     error = FT_New_Memory_Face(
         gFreeTypeLibrary,
         (*self_0).m_backingData,
@@ -677,6 +733,98 @@ pub(crate) unsafe fn XeTeXFontInst_setLayoutDirVertical(
 ) {
     (*self_0).m_vertical = vertical;
 }
+pub(crate) unsafe fn XeTeXFontInst_getVariationAxes(
+    self_0: *const XeTeXFontInst,
+) -> Vec<VariationAxis> {
+    let face = (*self_0).m_ftFace;
+    let mut axes = Vec::new();
+    /* FT_FACE_FLAG_MULTIPLE_MASTERS */
+    if face.is_null() || (*face).face_flags & 1 << 8i32 == 0 {
+        return axes;
+    }
+    let mut mm: *mut FT_MM_Var = ptr::null_mut();
+    if FT_Get_MM_Var(face, &mut mm) != 0 {
+        return axes;
+    }
+    for axis in slice::from_raw_parts((*mm).axis, (*mm).num_axis as usize) {
+        axes.push(VariationAxis {
+            tag: axis.tag as OTTag,
+            minimum: axis.minimum as f64 / 65536.0f64,
+            default: axis.def as f64 / 65536.0f64,
+            maximum: axis.maximum as f64 / 65536.0f64,
+            strid: axis.strid as u32,
+        });
+    }
+    FT_Done_MM_Var(gFreeTypeLibrary, mm);
+    axes
+}
+/// Select an instance of a variable font. Axes that aren't mentioned keep
+/// their default values and out-of-range values are clamped, as HarfBuzz
+/// does, so that the metrics we get from FreeType agree with the shaping.
+pub(crate) unsafe fn XeTeXFontInst_setVariations(
+    self_0: *mut XeTeXFontInst,
+    variations: *const hb_variation_t,
+    count: libc::c_uint,
+) {
+    let axes = XeTeXFontInst_getVariationAxes(self_0);
+    if axes.is_empty() || count == 0 {
+        return;
+    }
+    let variations = slice::from_raw_parts(variations, count as usize);
+    let mut coords: Vec<FT_Fixed> = axes
+        .iter()
+        .map(|axis| {
+            let value = variations
+                .iter()
+                .rev()
+                .find(|v| v.tag == axis.tag)
+                .map_or(axis.default, |v| v.value as f64);
+            let value = value.max(axis.minimum).min(axis.maximum);
+            (value * 65536.0f64).round() as FT_Fixed
+        })
+        .collect();
+    let face = (*self_0).m_ftFace;
+    FT_Set_Var_Design_Coordinates(face, coords.len() as FT_UInt, coords.as_mut_ptr());
+    hb_font_set_variations((*self_0).m_hbFont, variations.as_ptr(), count);
+    /* an MVAR table may have adjusted the global metrics */
+    (*self_0).m_ascent = XeTeXFontInst_unitsToPoints(self_0, (*face).ascender as libc::c_float);
+    (*self_0).m_descent = XeTeXFontInst_unitsToPoints(self_0, (*face).descender as libc::c_float);
+}
+/// Look up a string in the font's `name` table, preferring the Unicode
+/// platforms and US English.
+pub(crate) unsafe fn XeTeXFontInst_getNameString(
+    self_0: *const XeTeXFontInst,
+    name_id: u32,
+) -> Option<CString> {
+    let face = (*self_0).m_ftFace;
+    let mut best: Option<(i32, String)> = None;
+    for i in 0..FT_Get_Sfnt_Name_Count(face) {
+        let mut rec: FT_SfntName = std::mem::zeroed();
+        if FT_Get_Sfnt_Name(face, i, &mut rec) != 0 || rec.name_id as u32 != name_id {
+            continue;
+        }
+        let bytes = slice::from_raw_parts(rec.string, rec.string_len as usize);
+        let (score, text) = match rec.platform_id {
+            0 | 3 => {
+                let units: Vec<u16> = bytes
+                    .chunks(2)
+                    .map(|c| (c[0] as u16) << 8 | *c.get(1).unwrap_or(&0) as u16)
+                    .collect();
+                let english = rec.platform_id == 0 || rec.language_id == 0x409;
+                (
+                    if english { 3 } else { 2 },
+                    String::from_utf16_lossy(&units),
+                )
+            }
+            1 if rec.encoding_id == 0 => (1, bytes.iter().map(|&b| b as char).collect()),
+            _ => continue,
+        };
+        if best.as_ref().map_or(true, |(s, _)| score > *s) {
+            best = Some((score, text));
+        }
+    }
+    best.and_then(|(_, text)| CString::new(text).ok())
+}
 pub(crate) unsafe fn XeTeXFontInst_getFontTable(
     mut self_0: *const XeTeXFontInst,
     mut tag: OTTag,
@@ -14,7 +14,7 @@ pub(crate) mod imp;
 #[path = "xetex_font_manager_coretext.rs"]
 pub(crate) mod imp;
 
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::ptr;
 use std::ptr::NonNull;
 
@@ -24,7 +24,7 @@ use crate::xetex_ext::Fix2D;
 use crate::xetex_font_info::{XeTeXFontInst_getFontTableFT, XeTeXFontInst_getHbFont};
 use crate::xetex_ini::loaded_font_design_size;
 use crate::xetex_layout_interface::collection_types::*;
-use crate::xetex_layout_interface::{createFont, deleteFont};
+use crate::xetex_layout_interface::{createFont, createFontFromFile, deleteFont};
 use crate::xetex_output::{print_char, print_nl};
 use crate::xetex_xetex0::{begin_diagnostic, end_diagnostic, get_tracing_fonts_state};
 
@@ -168,6 +168,7 @@ pub(crate) struct XeTeXFontMgr {
             _: PlatformFontRef,
         ) -> *mut XeTeXFontMgrNameCollection,
     >,
+    pub(crate) m_memfnCacheAllFonts: Option<unsafe extern "C" fn(_: *mut XeTeXFontMgr) -> ()>,
     pub(crate) m_nameToFont: *mut CppStdMap<CString, NonNull<XeTeXFontMgrFont>>,
     pub(crate) m_nameToFamily: *mut CppStdMap<CString, NonNull<XeTeXFontMgrFamily>>,
     pub(crate) m_platformRefToFont: *mut CppStdMap<PlatformFontRef, NonNull<XeTeXFontMgrFont>>,
@@ -280,6 +281,12 @@ unsafe extern "C" fn XeTeXFontMgr_searchForHostPlatformFonts(
         .expect("non-null function pointer")(self_0, name);
 }
 #[inline]
+unsafe extern "C" fn XeTeXFontMgr_cacheAllFonts(mut self_0: *mut XeTeXFontMgr) {
+    (*self_0)
+        .m_memfnCacheAllFonts
+        .expect("non-null function pointer")(self_0);
+}
+#[inline]
 unsafe extern "C" fn XeTeXFontMgr_getOpSizeRecAndStyleFlags(
     mut self_0: *mut XeTeXFontMgr,
     mut theFont: *mut XeTeXFontMgrFont,
@@ -324,6 +331,50 @@ authorization from the copyright holders.
 pub(crate) static mut XeTeXFontMgr_sFontManager: *mut XeTeXFontMgr = ptr::null_mut();
 #[no_mangle]
 pub(crate) static mut XeTeXFontMgr_sReqEngine: libc::c_char = 0i32 as libc::c_char;
+/// A font face that native font names may resolve to when lookup is
+/// restricted to an index rather than the fonts installed on the host.
+#[derive(Clone, Debug, Default)]
+pub struct font_index_entry_t {
+    /// The name under which the I/O layer opens the font file: a bundle file
+    /// name or an absolute path.
+    pub file: String,
+    /// The index of the face within a font collection.
+    pub index: u32,
+    pub family_names: Vec<String>,
+    pub style_names: Vec<String>,
+    pub full_names: Vec<String>,
+    pub ps_name: String,
+}
+/// When set, the font manager resolves names to these faces only, and never
+/// asks the host platform; see `tt_xetex_set_font_index`.
+pub(crate) static mut XeTeXFontMgr_sFontIndex: Option<Vec<font_index_entry_t>> = None;
+/// A variation axis of a font, with its range of values.
+#[derive(Clone, Debug, Default)]
+pub struct font_axis_t {
+    pub tag: String,
+    /// The label of the axis in the font's `name` table, or the tag.
+    pub name: String,
+    pub minimum: f64,
+    pub default: f64,
+    pub maximum: f64,
+}
+/// A font that native font names can resolve to; see
+/// `XeTeXFontMgr_listFonts`.
+#[derive(Clone, Debug, Default)]
+pub struct font_description_t {
+    pub family_name: String,
+    pub style_name: String,
+    pub full_name: String,
+    pub ps_name: String,
+    /// The file the font is loaded from, and the index of the face within it.
+    pub file: String,
+    pub index: u32,
+    /// The OpenType script tags covered by the GSUB and GPOS tables.
+    pub scripts: Vec<String>,
+    /// The OpenType feature tags available for any script and language.
+    pub features: Vec<String>,
+    pub axes: Vec<font_axis_t>,
+}
 /* use our own fmax function because it seems to be missing on certain platforms
 (solaris2.9, at least) */
 #[inline]
@@ -824,6 +875,165 @@ pub(crate) unsafe fn XeTeXFontMgr_getFullName(
     }
     return CppStdString_cstr((*font_ptr).m_psName);
 }
+/// Resolves a native font name against the faces of a font index, trying it
+/// in the same order as `XeTeXFontMgr_findFont`: as a full name, as
+/// "Family-Style", as a PostScript name, and as a family name, whose regular
+/// member is preferred.
+pub(crate) fn XeTeXFontMgr_findIndexedFont<'a>(
+    faces: &'a [font_index_entry_t],
+    name: &str,
+) -> Option<&'a font_index_entry_t> {
+    if let Some(face) = faces
+        .iter()
+        .find(|f| f.full_names.iter().any(|n| n == name))
+    {
+        return Some(face);
+    }
+    if let Some(hyph) = name.find('-') {
+        let (family, style) = (&name[..hyph], &name[hyph + 1..]);
+        if !family.is_empty() && !style.is_empty() {
+            if let Some(face) = faces.iter().find(|f| {
+                f.family_names.iter().any(|n| n == family)
+                    && f.style_names.iter().any(|n| n == style)
+            }) {
+                return Some(face);
+            }
+        }
+    }
+    if let Some(face) = faces.iter().find(|f| f.ps_name == name) {
+        return Some(face);
+    }
+    let family: Vec<&font_index_entry_t> = faces
+        .iter()
+        .filter(|f| f.family_names.iter().any(|n| n == name))
+        .collect();
+    for style in &["Regular", "Plain", "Normal", "Roman"] {
+        if let Some(face) = family
+            .iter()
+            .find(|f| f.style_names.iter().any(|n| n == style))
+        {
+            return Some(face);
+        }
+    }
+    family.first().cloned()
+}
+/// Lists every font that `XeTeXFontMgr_findFont` can resolve a name to,
+/// ordered by PostScript name. This reads the names of all fonts the platform
+/// offers, and opens each of them, so it's slow.
+pub(crate) unsafe fn XeTeXFontMgr_listFonts(
+    mut self_0: *mut XeTeXFontMgr,
+) -> Vec<font_description_t> {
+    if let Some(ref faces) = XeTeXFontMgr_sFontIndex {
+        // the platform caches can't always hold indexed fonts (CoreText can't
+        // reach them at all), so describe the index entries themselves
+        let mut fonts: Vec<font_description_t> = faces
+            .iter()
+            .map(|face| XeTeXFontMgr_describeIndexedFont(face))
+            .collect();
+        fonts.sort_by(|a, b| a.ps_name.cmp(&b.ps_name));
+        return fonts;
+    }
+    XeTeXFontMgr_cacheAllFonts(self_0);
+    let name_of = |s: *const CppStdString| {
+        if s.is_null() {
+            String::new()
+        } else {
+            (*s).to_string_lossy().into_owned()
+        }
+    };
+    let mut fonts = Vec::new();
+    for font_ptr in (*(*self_0).m_psNameToFont).values() {
+        let font_ptr = font_ptr.as_ptr();
+        let mut desc = font_description_t {
+            family_name: name_of((*font_ptr).m_familyName),
+            style_name: name_of((*font_ptr).m_styleName),
+            full_name: name_of((*font_ptr).m_fullName),
+            ps_name: name_of((*font_ptr).m_psName),
+            ..font_description_t::default()
+        };
+        let mut font: XeTeXFont = createFont((*font_ptr).fontRef, 655360i32);
+        if !font.is_null() {
+            XeTeXFontMgr_describeFont(font, &mut desc);
+            deleteFont(font);
+        }
+        if desc.file.is_empty() {
+            let mut path = XeTeXFontMgr_getPlatformFontDesc(self_0, (*font_ptr).fontRef);
+            desc.file = CStr::from_ptr(path).to_string_lossy().into_owned();
+            free(path as *mut libc::c_void);
+        }
+        fonts.push(desc);
+    }
+    fonts
+}
+/// Describes a font index entry, opening the font through the I/O layer.
+unsafe fn XeTeXFontMgr_describeIndexedFont(face: &font_index_entry_t) -> font_description_t {
+    let first = |names: &[String]| names.first().cloned().unwrap_or_default();
+    let mut desc = font_description_t {
+        family_name: first(&face.family_names),
+        style_name: first(&face.style_names),
+        full_name: first(&face.full_names),
+        ps_name: face.ps_name.clone(),
+        file: face.file.clone(),
+        index: face.index,
+        ..font_description_t::default()
+    };
+    if let Ok(file) = CString::new(face.file.as_str()) {
+        let mut font: XeTeXFont =
+            createFontFromFile(file.as_ptr(), face.index as libc::c_int, 655360i32);
+        if !font.is_null() {
+            XeTeXFontMgr_describeFont(font, &mut desc);
+            deleteFont(font);
+        }
+    }
+    desc
+}
+/// Fills in the file, layout tables and variation axes of an opened font.
+unsafe fn XeTeXFontMgr_describeFont(mut font: XeTeXFont, desc: &mut font_description_t) {
+    use crate::xetex_font_info::{XeTeXFontInst_getNameString, XeTeXFontInst_getVariationAxes};
+    use crate::xetex_layout_interface::{
+        countFeatures, countLanguages, countScripts, getIndFeature, getIndLanguage, getIndScript,
+    };
+    use std::collections::BTreeSet;
+    fn tag_name(tag: u32) -> String {
+        String::from_utf8_lossy(&tag.to_be_bytes())
+            .trim_end()
+            .to_owned()
+    }
+    let mut fontInst: *mut XeTeXFontInst = font as *mut XeTeXFontInst;
+    if !(*fontInst).m_filename.is_null() {
+        desc.file = CStr::from_ptr((*fontInst).m_filename)
+            .to_string_lossy()
+            .into_owned();
+        desc.index = (*fontInst).m_index;
+    }
+    let mut features = BTreeSet::new();
+    for i in 0..countScripts(font) {
+        let script = getIndScript(font, i);
+        desc.scripts.push(tag_name(script));
+        // language 0 is the default language system of the script
+        let mut languages = vec![0];
+        for j in 0..countLanguages(font, script) {
+            languages.push(getIndLanguage(font, script, j));
+        }
+        for language in languages {
+            for k in 0..countFeatures(font, script, language) {
+                features.insert(tag_name(getIndFeature(font, script, language, k)));
+            }
+        }
+    }
+    desc.features = features.into_iter().collect();
+    for axis in XeTeXFontInst_getVariationAxes(fontInst) {
+        desc.axes.push(font_axis_t {
+            tag: tag_name(axis.tag),
+            name: XeTeXFontInst_getNameString(fontInst, axis.strid)
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| tag_name(axis.tag)),
+            minimum: axis.minimum,
+            default: axis.default,
+            maximum: axis.maximum,
+        });
+    }
+}
 pub(crate) unsafe fn XeTeXFontMgr_weightAndWidthDiff(
     mut _self_0: *const XeTeXFontMgr,
     mut a: *const XeTeXFontMgrFont,
@@ -1141,8 +1351,42 @@ pub(crate) unsafe fn XeTeXFontMgr_base_ctor(mut self_0: *mut XeTeXFontMgr) {
     );
     (*self_0).m_memfnSearchForHostPlatformFonts = None;
     (*self_0).m_memfnReadNames = None;
+    (*self_0).m_memfnCacheAllFonts = None;
     (*self_0).m_nameToFont = CppStdMap_create();
     (*self_0).m_nameToFamily = CppStdMap_create();
     (*self_0).m_platformRefToFont = CppStdMap_create();
     (*self_0).m_psNameToFont = CppStdMap_create();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face(file: &str, family: &str, style: &str, ps_name: &str) -> font_index_entry_t {
+        font_index_entry_t {
+            file: file.to_owned(),
+            index: 0,
+            family_names: vec![family.to_owned()],
+            style_names: vec![style.to_owned()],
+            full_names: vec![format!("{} {}", family, style)],
+            ps_name: ps_name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn indexed_font_lookup() {
+        let faces = vec![
+            face("sans-b.otf", "Sans", "Bold", "Sans-Bold"),
+            face("sans-r.otf", "Sans", "Regular", "Sans-Regular"),
+            face("serif-i.otf", "Serif", "Italic", "SerifItalic"),
+        ];
+        let find = |name| XeTeXFontMgr_findIndexedFont(&faces, name).map(|f| f.file.as_str());
+        assert_eq!(find("Sans Bold"), Some("sans-b.otf"));
+        assert_eq!(find("Sans-Regular"), Some("sans-r.otf"));
+        assert_eq!(find("SerifItalic"), Some("serif-i.otf"));
+        assert_eq!(find("Sans"), Some("sans-r.otf"));
+        assert_eq!(find("Serif"), Some("serif-i.otf"));
+        assert_eq!(find("Mono"), None);
+        assert_eq!(find("-"), None);
+    }
+}
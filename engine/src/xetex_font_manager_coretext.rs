@@ -26,7 +26,7 @@ type id = *mut Object;
 use super::{
     XeTeXFontMgr, XeTeXFontMgrFamily, XeTeXFontMgrFont, XeTeXFontMgrNameCollection,
     XeTeXFontMgrOpSizeRec, XeTeXFontMgr_addToMaps, XeTeXFontMgr_appendToList,
-    XeTeXFontMgr_base_ctor,
+    XeTeXFontMgr_base_ctor, XeTeXFontMgr_sFontIndex,
 };
 
 use libc::{free, malloc, strchr, strdup, strlen};
@@ -258,6 +258,11 @@ pub(crate) unsafe extern "C" fn XeTeXFontMgr_Mac_searchForHostPlatformFonts(
     mut self_0: *mut XeTeXFontMgr,
     mut name: *const libc::c_char,
 ) {
+    if XeTeXFontMgr_sFontIndex.is_some() {
+        // CoreText can only describe fonts it can reach itself, so indexed
+        // names are resolved by find_native_font instead
+        return;
+    }
     // the name might be:
     //  FullName
     //  Family-Style (if there's a hyphen)
@@ -331,6 +336,23 @@ pub(crate) unsafe extern "C" fn XeTeXFontMgr_Mac_searchForHostPlatformFonts(
     };
 }
 #[no_mangle]
+pub(crate) unsafe extern "C" fn XeTeXFontMgr_Mac_cacheAllFonts(mut self_0: *mut XeTeXFontMgr) {
+    if XeTeXFontMgr_sFontIndex.is_some() {
+        return;
+    }
+    let shared_font_manager: *const NSFontManager =
+        msg_send![class!(NSFontManager), sharedFontManager];
+    let mut families: *mut NSArray<NSString, Shared> =
+        msg_send![shared_font_manager, availableFontFamilies];
+    let mut enumerator: id = msg_send![families, objectEnumerator];
+    for mut family in NSEnumerator::<NSString>::from_ptr(enumerator) {
+        let family: *const NSString = family;
+        let mut members: *mut NSArray<NSFont, Shared> =
+            msg_send![shared_font_manager, availableMembersOfFontFamily: family];
+        XeTeXFontMgr_Mac_addFontsToCaches(self_0, members as CFArrayRef);
+    }
+}
+#[no_mangle]
 pub(crate) static mut pool: *mut NSAutoreleasePool = ptr::null_mut();
 
 #[no_mangle]
@@ -403,6 +425,8 @@ pub(crate) unsafe fn XeTeXFontMgr_Mac_ctor(mut self_0: *mut XeTeXFontMgr_Mac) {
                 _: CTFontDescriptorRef,
             ) -> *mut XeTeXFontMgrNameCollection,
     );
+    (*self_0).super_.m_memfnCacheAllFonts =
+        Some(XeTeXFontMgr_Mac_cacheAllFonts as unsafe extern "C" fn(_: *mut XeTeXFontMgr) -> ());
 }
 pub(crate) unsafe fn XeTeXFontMgr_Mac_create() -> *mut XeTeXFontMgr_Mac {
     let mut self_0: *mut XeTeXFontMgr_Mac =
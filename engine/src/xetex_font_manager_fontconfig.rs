@@ -10,10 +10,11 @@
 )]
 
 use super::{
-    XeTeXFontMgr_addToMaps, XeTeXFontMgr_appendToList, XeTeXFontMgr_base_ctor,
+    font_index_entry_t, XeTeXFontMgr_addToMaps, XeTeXFontMgr_appendToList, XeTeXFontMgr_base_ctor,
     XeTeXFontMgr_base_getOpSizeRecAndStyleFlags, XeTeXFontMgr_prependToList,
+    XeTeXFontMgr_sFontIndex,
 };
-use crate::stub_icu as icu;
+use crate::icu;
 use crate::xetex_font_info::gFreeTypeLibrary;
 use crate::xetex_layout_interface::collection_types::*;
 
@@ -29,6 +30,10 @@ extern "C" {
     #[no_mangle]
     fn FcConfigGetCurrent() -> *mut FcConfig;
     #[no_mangle]
+    fn FcFontSetCreate() -> *mut FcFontSet;
+    #[no_mangle]
+    fn FcFontSetAdd(s: *mut FcFontSet, font: *mut FcPattern) -> FcBool;
+    #[no_mangle]
     fn FcFontSetDestroy(s: *mut FcFontSet);
     #[no_mangle]
     fn FcInit() -> FcBool;
@@ -42,8 +47,22 @@ extern "C" {
     #[no_mangle]
     fn FcNameParse(name: *const FcChar8) -> *mut FcPattern;
     #[no_mangle]
+    fn FcPatternCreate() -> *mut FcPattern;
+    #[no_mangle]
     fn FcPatternDestroy(p: *mut FcPattern);
     #[no_mangle]
+    fn FcPatternAddInteger(
+        p: *mut FcPattern,
+        object: *const libc::c_char,
+        i: libc::c_int,
+    ) -> FcBool;
+    #[no_mangle]
+    fn FcPatternAddString(
+        p: *mut FcPattern,
+        object: *const libc::c_char,
+        s: *const FcChar8,
+    ) -> FcBool;
+    #[no_mangle]
     fn FcPatternGetInteger(
         p: *const FcPattern,
         object: *const libc::c_char,
@@ -266,6 +285,24 @@ pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_readNames(
     {
         return names;
     }
+    if XeTeXFontMgr_sFontIndex.is_some() {
+        // indexed fonts may live inside the bundle rather than on disk, so we
+        // take the names that the index recorded instead of asking FreeType
+        let mut psName: *mut libc::c_char = 0 as *mut libc::c_char;
+        if FcPatternGetString(
+            pat,
+            b"postscriptname\x00" as *const u8 as *const libc::c_char,
+            0i32,
+            &mut psName as *mut *mut libc::c_char as *mut *mut FcChar8,
+        ) == FcResultMatch
+        {
+            CppStdString_assign_from_const_char_ptr((*names).m_psName, psName);
+        }
+        readPatternNames(self_0, pat, b"fullname\x00", (*names).m_fullNames);
+        readPatternNames(self_0, pat, b"family\x00", (*names).m_familyNames);
+        readPatternNames(self_0, pat, b"style\x00", (*names).m_styleNames);
+        return names;
+    }
     let mut index: libc::c_int = 0;
     if FcPatternGetInteger(
         pat,
@@ -360,54 +397,9 @@ pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_readNames(
         CppStdListOfString_delete(subFamilyNames);
         CppStdListOfString_delete(familyNames);
     } else {
-        index = 0i32;
-        loop {
-            let fresh0 = index;
-            index = index + 1;
-            if !(FcPatternGetString(
-                pat,
-                b"fullname\x00" as *const u8 as *const libc::c_char,
-                fresh0,
-                &mut name as *mut *const libc::c_char as *mut *mut FcChar8,
-            ) as libc::c_uint
-                == FcResultMatch as libc::c_int as libc::c_uint)
-            {
-                break;
-            }
-            XeTeXFontMgr_appendToList(self_0, (*names).m_fullNames, name);
-        }
-        index = 0i32;
-        loop {
-            let fresh1 = index;
-            index = index + 1;
-            if !(FcPatternGetString(
-                pat,
-                b"family\x00" as *const u8 as *const libc::c_char,
-                fresh1,
-                &mut name as *mut *const libc::c_char as *mut *mut FcChar8,
-            ) as libc::c_uint
-                == FcResultMatch as libc::c_int as libc::c_uint)
-            {
-                break;
-            }
-            XeTeXFontMgr_appendToList(self_0, (*names).m_familyNames, name);
-        }
-        index = 0i32;
-        loop {
-            let fresh2 = index;
-            index = index + 1;
-            if !(FcPatternGetString(
-                pat,
-                b"style\x00" as *const u8 as *const libc::c_char,
-                fresh2,
-                &mut name as *mut *const libc::c_char as *mut *mut FcChar8,
-            ) as libc::c_uint
-                == FcResultMatch as libc::c_int as libc::c_uint)
-            {
-                break;
-            }
-            XeTeXFontMgr_appendToList(self_0, (*names).m_styleNames, name);
-        }
+        readPatternNames(self_0, pat, b"fullname\x00", (*names).m_fullNames);
+        readPatternNames(self_0, pat, b"family\x00", (*names).m_familyNames);
+        readPatternNames(self_0, pat, b"style\x00", (*names).m_styleNames);
         if (*(*names).m_fullNames).is_empty() {
             let mut fullName: *mut CppStdString = CppStdString_create();
             CppStdString_append_const_char_ptr(fullName, (*(*names).m_familyNames)[0].as_ptr());
@@ -425,6 +417,62 @@ pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_readNames(
     FT_Done_Face(face);
     return names;
 }
+/// Appends every value of the string property `object` of `pat` to `list`.
+unsafe fn readPatternNames(
+    self_0: *mut XeTeXFontMgr,
+    pat: *mut FcPattern,
+    object: &[u8],
+    list: *mut CppStdListOfString,
+) {
+    let mut name: *mut libc::c_char = 0 as *mut libc::c_char;
+    let mut i = 0;
+    while FcPatternGetString(
+        pat,
+        object.as_ptr() as *const libc::c_char,
+        i,
+        &mut name as *mut *mut libc::c_char as *mut *mut FcChar8,
+    ) == FcResultMatch
+    {
+        XeTeXFontMgr_appendToList(self_0, list, name);
+        i += 1;
+    }
+}
+/// Builds the font set that lookup searches from the faces of a font index,
+/// in place of the fonts that fontconfig knows about.
+unsafe fn indexFontSet(faces: &[font_index_entry_t]) -> *mut FcFontSet {
+    use std::ffi::CString;
+    unsafe fn add_string(pat: *mut FcPattern, object: &[u8], value: &str) {
+        if let Ok(value) = CString::new(value) {
+            FcPatternAddString(
+                pat,
+                object.as_ptr() as *const libc::c_char,
+                value.as_ptr() as *const FcChar8,
+            );
+        }
+    }
+    let set = FcFontSetCreate();
+    for face in faces {
+        let pat = FcPatternCreate();
+        add_string(pat, b"file\x00", &face.file);
+        FcPatternAddInteger(
+            pat,
+            b"index\x00" as *const u8 as *const libc::c_char,
+            face.index as libc::c_int,
+        );
+        for name in &face.family_names {
+            add_string(pat, b"family\x00", name);
+        }
+        for name in &face.style_names {
+            add_string(pat, b"style\x00", name);
+        }
+        for name in &face.full_names {
+            add_string(pat, b"fullname\x00", name);
+        }
+        add_string(pat, b"postscriptname\x00", &face.ps_name);
+        FcFontSetAdd(set, pat);
+    }
+    set
+}
 #[no_mangle]
 pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_getOpSizeRecAndStyleFlags(
     mut self_0: *mut XeTeXFontMgr,
@@ -530,7 +578,8 @@ pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_searchForHostPlatformFonts(
     loop {
         'traverse_fonts: for f in 0..(*(*real_self).allFonts).nfont {
             let mut pat: *mut FcPattern = *(*(*real_self).allFonts).fonts.offset(f as isize);
-            if !(*(*self_0).m_platformRefToFont).contains_key(&pat) {
+            // fonts already in our maps can't be the one we're missing
+            if (*(*self_0).m_platformRefToFont).contains_key(&pat) {
                 continue;
             }
 
@@ -625,9 +674,26 @@ pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_searchForHostPlatformFonts(
     CppStdString_delete(famName);
 }
 #[no_mangle]
+pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_cacheAllFonts(mut self_0: *mut XeTeXFontMgr) {
+    let mut real_self: *mut XeTeXFontMgr_FC = self_0 as *mut XeTeXFontMgr_FC;
+    if (*real_self).cachedAll {
+        return;
+    }
+    for f in 0..(*(*real_self).allFonts).nfont {
+        let mut pat: *mut FcPattern = *(*(*real_self).allFonts).fonts.offset(f as isize);
+        if (*(*self_0).m_platformRefToFont).contains_key(&pat) {
+            continue;
+        }
+        let mut names: *mut XeTeXFontMgrNameCollection = XeTeXFontMgr_readNames(self_0, pat);
+        XeTeXFontMgr_addToMaps(self_0, pat, names);
+        XeTeXFontMgrNameCollection_delete(names);
+    }
+    (*real_self).cachedAll = true;
+}
+#[no_mangle]
 pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_initialize(mut self_0: *mut XeTeXFontMgr) {
     let mut real_self: *mut XeTeXFontMgr_FC = self_0 as *mut XeTeXFontMgr_FC;
-    if FcInit() == 0i32 {
+    if XeTeXFontMgr_sFontIndex.is_none() && FcInit() == 0i32 {
         abort!("fontconfig initialization failed");
     }
     if gFreeTypeLibrary.is_null() && FT_Init_FreeType(&mut gFreeTypeLibrary) != 0i32 {
@@ -643,6 +709,11 @@ pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_initialize(mut self_0: *mut XeTe
     if err as u64 != 0 {
         abort!("cannot read font names");
     }
+    (*real_self).cachedAll = 0i32 != 0;
+    if let Some(ref faces) = XeTeXFontMgr_sFontIndex {
+        (*real_self).allFonts = indexFontSet(faces);
+        return;
+    }
     let mut pat: *mut FcPattern =
         FcNameParse(b":outline=true\x00" as *const u8 as *const libc::c_char as *const FcChar8);
     let mut os: *mut FcObjectSet = FcObjectSetBuild(
@@ -730,6 +801,8 @@ pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_ctor(mut self_0: *mut XeTeXFontM
                 _: *mut FcPattern,
             ) -> *mut XeTeXFontMgrNameCollection,
     );
+    (*self_0).super_.m_memfnCacheAllFonts =
+        Some(XeTeXFontMgr_FC_cacheAllFonts as unsafe extern "C" fn(_: *mut XeTeXFontMgr) -> ());
 }
 #[no_mangle]
 pub(crate) unsafe extern "C" fn XeTeXFontMgr_FC_create() -> *mut XeTeXFontMgr_FC {
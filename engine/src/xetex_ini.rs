@@ -817,12 +817,16 @@ pub(crate) static mut FONT_FLAGS: Vec<i8> = Vec::new();
 #[no_mangle]
 pub(crate) static mut FONT_LETTER_SPACE: Vec<scaled_t> = Vec::new();
 #[no_mangle]
+pub(crate) static mut FONT_FALLBACK: Vec<Vec<internal_font_number>> = Vec::new();
+#[no_mangle]
 pub(crate) static mut loaded_font_mapping: *mut libc::c_void = ptr::null_mut();
 #[no_mangle]
 pub(crate) static mut loaded_font_flags: i8 = 0;
 #[no_mangle]
 pub(crate) static mut loaded_font_letter_space: scaled_t = 0;
 #[no_mangle]
+pub(crate) static mut loaded_font_fallback: Vec<Vec<u8>> = Vec::new();
+#[no_mangle]
 pub(crate) static mut loaded_font_design_size: scaled_t = 0;
 #[no_mangle]
 pub(crate) static mut mapped_text: *mut UTF16_code = ptr::null_mut();
@@ -4296,6 +4300,7 @@ unsafe extern "C" fn load_fmt_file() -> bool {
     FONT_LAYOUT_ENGINE = vec![0 as *mut libc::c_void; FONT_MAX + 1];
     FONT_FLAGS = vec![0; FONT_MAX + 1];
     FONT_LETTER_SPACE = vec![0; FONT_MAX + 1];
+    FONT_FALLBACK = vec![Vec::new(); FONT_MAX + 1];
     FONT_CHECK = vec![b16x4_le_t::default(); FONT_MAX + 1];
     FONT_SIZE = vec![0; FONT_MAX + 1];
     FONT_DSIZE = vec![0; FONT_MAX + 1];
@@ -6279,6 +6284,7 @@ pub(crate) unsafe fn tt_run_engine(
         FONT_LAYOUT_ENGINE = vec![0 as *mut libc::c_void; FONT_MAX + 1];
         FONT_FLAGS = vec![0; FONT_MAX + 1];
         FONT_LETTER_SPACE = vec![0; FONT_MAX + 1];
+        FONT_FALLBACK = vec![Vec::new(); FONT_MAX + 1];
         FONT_CHECK = vec![b16x4_le_t::default(); FONT_MAX + 1];
         FONT_SIZE = vec![0; FONT_MAX + 1];
         FONT_DSIZE = vec![0; FONT_MAX + 1];
@@ -6399,6 +6405,7 @@ pub(crate) unsafe fn tt_run_engine(
     FONT_LAYOUT_ENGINE = Vec::new();
     FONT_FLAGS = Vec::new();
     FONT_LETTER_SPACE = Vec::new();
+    FONT_FALLBACK = Vec::new();
     FONT_CHECK = Vec::new();
     FONT_SIZE = Vec::new();
     FONT_DSIZE = Vec::new();
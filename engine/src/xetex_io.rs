@@ -13,8 +13,8 @@ use crate::bridge::{
     ttstub_input_ungetc,
 };
 use crate::core_memory::{xcalloc, xmalloc, xstrdup};
-use crate::stub_icu as icu;
-use crate::stub_teckit as teckit;
+use crate::icu;
+use crate::teckit;
 use crate::xetex_ini::{
     buf_size, buffer, cur_area, cur_chr, cur_ext, cur_name, cur_val, first, last, max_buf_stack,
     name_in_progress, name_length, name_length16, name_of_file, name_of_file16, read_file,
@@ -56,6 +56,31 @@ use libc::{free, malloc, strcmp, strdup, strlen, strncmp};
 
 extern "C" {
     pub(crate) type gr_face;
+    #[no_mangle]
+    fn hb_font_get_var_coords_normalized(
+        font: *mut hb_font_t,
+        length: *mut libc::c_uint,
+    ) -> *const libc::c_int;
+    #[no_mangle]
+    fn hb_shape_plan_create_cached2(
+        face: *mut hb_face_t,
+        props: *const hb_segment_properties_t,
+        user_features: *const hb_feature_t,
+        num_user_features: libc::c_uint,
+        coords: *const libc::c_int,
+        num_coords: libc::c_uint,
+        shaper_list: *const *const libc::c_char,
+    ) -> *mut hb_shape_plan_t;
+    #[no_mangle]
+    fn hb_shape_plan_create2(
+        face: *mut hb_face_t,
+        props: *const hb_segment_properties_t,
+        user_features: *const hb_feature_t,
+        num_user_features: libc::c_uint,
+        coords: *const libc::c_int,
+        num_coords: libc::c_uint,
+        shaper_list: *const *const libc::c_char,
+    ) -> *mut hb_shape_plan_t;
     pub(crate) type gr_font;
     pub(crate) type gr_feature_ref;
     pub(crate) type gr_feature_val;
@@ -170,8 +195,12 @@ extern "C" {
     fn hb_graphite2_face_get_gr_face(face: *mut hb_face_t) -> *mut gr_face;
     #[no_mangle]
     fn hb_graphite2_font_get_gr_font(font: *mut hb_font_t) -> *mut gr_font;
+    #[cfg(feature = "icu")]
     #[no_mangle]
     fn hb_icu_get_unicode_funcs() -> *mut hb_unicode_funcs_t;
+    #[cfg(not(feature = "icu"))]
+    #[no_mangle]
+    fn hb_unicode_funcs_get_default() -> *mut hb_unicode_funcs_t;
 }
 
 pub(crate) use crate::xetex_font_manager::XeTeXFont;
@@ -182,14 +211,15 @@ use crate::xetex_font_manager::{
     XeTeXFontMgr_getReqEngine, XeTeXFontMgr_setReqEngine,
 };
 
-use crate::xetex_font_info::XeTeXFontInst;
+use crate::xetex_font_info::{hb_variation_t, XeTeXFontInst};
 
 use crate::xetex_font_info::{
     XeTeXFontInst_getFirstCharCode, XeTeXFontInst_getFontTable, XeTeXFontInst_getGlyphBounds,
     XeTeXFontInst_getGlyphHeightDepth, XeTeXFontInst_getGlyphItalCorr, XeTeXFontInst_getGlyphName,
     XeTeXFontInst_getGlyphSidebearings, XeTeXFontInst_getGlyphWidth, XeTeXFontInst_getHbFont,
-    XeTeXFontInst_getLastCharCode, XeTeXFontInst_getNumGlyphs, XeTeXFontInst_mapCharToGlyph,
-    XeTeXFontInst_mapGlyphToIndex, XeTeXFontInst_pointsToUnits, XeTeXFontInst_setLayoutDirVertical,
+    XeTeXFontInst_getLastCharCode, XeTeXFontInst_getNameString, XeTeXFontInst_getNumGlyphs,
+    XeTeXFontInst_getVariationAxes, XeTeXFontInst_mapCharToGlyph, XeTeXFontInst_mapGlyphToIndex,
+    XeTeXFontInst_pointsToUnits, XeTeXFontInst_setLayoutDirVertical, XeTeXFontInst_setVariations,
     XeTeXFontInst_unitsToPoints,
 };
 
@@ -334,6 +364,8 @@ pub(crate) struct XeTeXLayoutEngine_rec {
     pub(crate) ShaperList: *mut *mut libc::c_char,
     pub(crate) shaper: *mut libc::c_char,
     pub(crate) nFeatures: libc::c_int,
+    pub(crate) variations: *mut hb_variation_t,
+    pub(crate) nVariations: libc::c_int,
     pub(crate) rgbValue: uint32_t,
     pub(crate) extend: libc::c_float,
     pub(crate) slant: libc::c_float,
@@ -1206,6 +1238,62 @@ pub(crate) unsafe fn getSlantFactor(mut engine: XeTeXLayoutEngine) -> libc::c_fl
 pub(crate) unsafe fn getEmboldenFactor(mut engine: XeTeXLayoutEngine) -> libc::c_float {
     return (*engine).embolden;
 }
+pub(crate) unsafe fn getVariations(
+    mut engine: XeTeXLayoutEngine,
+    mut variations: *mut *const hb_variation_t,
+) -> libc::c_int {
+    *variations = (*engine).variations;
+    return (*engine).nVariations;
+}
+pub(crate) unsafe fn countVariationAxes(mut font: XeTeXFont) -> libc::c_uint {
+    return XeTeXFontInst_getVariationAxes(font as *const XeTeXFontInst).len() as libc::c_uint;
+}
+pub(crate) unsafe fn getIndVariationAxis(mut font: XeTeXFont, mut index: libc::c_uint) -> hb_tag_t {
+    XeTeXFontInst_getVariationAxes(font as *const XeTeXFontInst)
+        .get(index as usize)
+        .map_or(0, |axis| axis.tag)
+}
+/// Returns the minimum, maximum and default values of the given axis, or
+/// `None` if the font has no such axis.
+pub(crate) unsafe fn getVariationAxisRange(
+    mut font: XeTeXFont,
+    mut tag: hb_tag_t,
+) -> Option<(f64, f64, f64)> {
+    XeTeXFontInst_getVariationAxes(font as *const XeTeXFontInst)
+        .iter()
+        .find(|axis| axis.tag == tag)
+        .map(|axis| (axis.minimum, axis.maximum, axis.default))
+}
+/// Finds an axis by its tag or by the label in the font's `name` table.
+pub(crate) unsafe fn findVariationAxisByName(
+    mut font: XeTeXFont,
+    mut name: *const libc::c_char,
+    mut len: libc::c_int,
+) -> hb_tag_t {
+    let name = std::slice::from_raw_parts(name as *const u8, len as usize);
+    for axis in XeTeXFontInst_getVariationAxes(font as *const XeTeXFontInst) {
+        if name == &axis.tag.to_be_bytes()[..] {
+            return axis.tag;
+        }
+        if let Some(label) = XeTeXFontInst_getNameString(font as *const XeTeXFontInst, axis.strid) {
+            if label.as_bytes() == name {
+                return axis.tag;
+            }
+        }
+    }
+    0
+}
+/// Returns the human-readable name of an axis, falling back to its tag.
+pub(crate) unsafe fn getVariationAxisName(
+    mut font: XeTeXFont,
+    mut tag: hb_tag_t,
+) -> Option<std::ffi::CString> {
+    let axis = XeTeXFontInst_getVariationAxes(font as *const XeTeXFontInst)
+        .into_iter()
+        .find(|axis| axis.tag == tag)?;
+    XeTeXFontInst_getNameString(font as *const XeTeXFontInst, axis.strid)
+        .or_else(|| std::ffi::CString::new(&tag.to_be_bytes()[..]).ok())
+}
 pub(crate) unsafe fn XeTeXLayoutEngine_create() -> *mut XeTeXLayoutEngine_rec {
     return malloc(::std::mem::size_of::<XeTeXLayoutEngine_rec>()) as *mut XeTeXLayoutEngine_rec;
 }
@@ -1220,6 +1308,8 @@ pub(crate) unsafe fn createLayoutEngine(
     mut features: *mut hb_feature_t,
     mut nFeatures: libc::c_int,
     mut shapers: *mut *mut libc::c_char,
+    mut variations: *mut hb_variation_t,
+    mut nVariations: libc::c_int,
     mut rgbValue: uint32_t,
     mut extend: libc::c_float,
     mut slant: libc::c_float,
@@ -1233,6 +1323,15 @@ pub(crate) unsafe fn createLayoutEngine(
     (*result).ShaperList = shapers;
     (*result).shaper = 0 as *mut libc::c_char;
     (*result).nFeatures = nFeatures;
+    (*result).variations = variations;
+    (*result).nVariations = nVariations;
+    if nVariations > 0 {
+        XeTeXFontInst_setVariations(
+            (*result).font,
+            variations as *const hb_variation_t,
+            nVariations as libc::c_uint,
+        );
+    }
     (*result).rgbValue = rgbValue;
     (*result).extend = extend;
     (*result).slant = slant;
@@ -1254,6 +1353,7 @@ pub(crate) unsafe fn deleteLayoutEngine(mut engine: XeTeXLayoutEngine) {
     hb_buffer_destroy((*engine).hbBuffer);
     XeTeXFontInst_delete((*engine).font);
     free((*engine).shaper as *mut libc::c_void);
+    free((*engine).variations as *mut libc::c_void);
     XeTeXLayoutEngine_delete(engine);
 }
 unsafe extern "C" fn _decompose_compat(
@@ -1267,7 +1367,12 @@ unsafe extern "C" fn _decompose_compat(
 unsafe extern "C" fn _get_unicode_funcs() -> *mut hb_unicode_funcs_t {
     static mut ufuncs: *mut hb_unicode_funcs_t = ptr::null_mut();
     if ufuncs.is_null() {
-        ufuncs = hb_unicode_funcs_create(hb_icu_get_unicode_funcs())
+        #[cfg(feature = "icu")]
+        let parent = hb_icu_get_unicode_funcs();
+        /* HarfBuzz's built-in UCD tables, when we're not linking with ICU */
+        #[cfg(not(feature = "icu"))]
+        let parent = hb_unicode_funcs_get_default();
+        ufuncs = hb_unicode_funcs_create(parent)
     }
     hb_unicode_funcs_set_decompose_compatibility_func(
         ufuncs,
@@ -1346,11 +1451,17 @@ pub(crate) unsafe fn layoutChars(
         let ref mut fresh1 = *(*engine).ShaperList.offset(1);
         *fresh1 = 0 as *mut libc::c_char
     }
-    shape_plan = hb_shape_plan_create_cached(
+    // The shape plan depends on the variation coordinates (feature
+    // variations via GSUB/GPOS FeatureVariations), so key the cache on them.
+    let mut nCoords: libc::c_uint = 0;
+    let coords = hb_font_get_var_coords_normalized(hbFont, &mut nCoords);
+    shape_plan = hb_shape_plan_create_cached2(
         hbFace,
         &mut segment_props,
         (*engine).features,
         (*engine).nFeatures as libc::c_uint,
+        coords,
+        nCoords,
         (*engine).ShaperList as *const *const libc::c_char,
     );
     res = hb_shape_plan_execute(
@@ -1371,11 +1482,13 @@ pub(crate) unsafe fn layoutChars(
         // all selected shapers failed, retrying with default
         // we don't use _cached here as the cached plain will always fail.
         hb_shape_plan_destroy(shape_plan); /* negative is forwards */
-        shape_plan = hb_shape_plan_create(
+        shape_plan = hb_shape_plan_create2(
             hbFace,
             &mut segment_props,
             (*engine).features,
             (*engine).nFeatures as libc::c_uint,
+            coords,
+            nCoords,
             0 as *const *const libc::c_char,
         ); /* negative is upwards */
         res = hb_shape_plan_execute(
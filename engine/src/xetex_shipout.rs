@@ -44,7 +44,7 @@ use crate::xetex_xetex0::{
     NODE_type, UTF16_code,
 };
 use crate::xetex_xetexd::{is_char_node, print_c_string};
-use bridge::{ttstub_output_close, ttstub_output_open};
+use bridge::{ttstub_note_page_output, ttstub_output_close, ttstub_output_open};
 use libc::{free, strerror, strlen};
 
 use bridge::OutputHandleWrapper;
@@ -303,6 +303,7 @@ pub(crate) unsafe fn ship_out(mut p: i32) {
         dvi_out(EOP as _);
         total_pages += 1;
         cur_s = -1;
+        ttstub_note_page_output(COUNT_REG(0));
     }
 
     /*1518: "Check for LR anomalies at the end of ship_out" */
@@ -24,15 +24,15 @@ use crate::xetex_aatfont as aat;
 use crate::xetex_consts::*;
 use crate::xetex_errors::{confusion, error, fatal_error, overflow, pdf_error};
 use crate::xetex_ext::{
-    apply_mapping, apply_tfm_font_mapping, check_for_tfm_font_mapping, find_native_font,
-    get_encoding_mode_and_info, get_font_char_range, get_glyph_bounds,
-    get_native_char_height_depth, get_native_char_sidebearings, getnativechardp, getnativecharht,
-    getnativecharic, getnativecharwd, gr_font_get_named, gr_font_get_named_1, gr_print_font_name,
-    linebreak_next, linebreak_start, load_tfm_font_mapping, map_char_to_glyph, map_glyph_to_index,
-    measure_native_glyph, measure_native_node, ot_font_get, ot_font_get_1, ot_font_get_2,
-    ot_font_get_3, ot_get_font_metrics, print_glyph_name, print_utf8_str,
-    real_get_native_glyph_italic_correction, real_get_native_italic_correction,
-    real_get_native_word_cp, release_font_engine,
+    apply_mapping, apply_tfm_font_mapping, check_for_tfm_font_mapping, fallback_font_for_char,
+    fallback_runs, find_native_font, get_encoding_mode_and_info, get_font_char_range,
+    get_glyph_bounds, get_native_char_height_depth, get_native_char_sidebearings, getnativechardp,
+    getnativecharht, getnativecharic, getnativecharwd, gr_font_get_named, gr_font_get_named_1,
+    gr_print_font_name, linebreak_next, linebreak_start, load_tfm_font_mapping, map_char_to_glyph,
+    map_glyph_to_index, measure_native_glyph, measure_native_node, ot_font_get, ot_font_get_1,
+    ot_font_get_2, ot_font_get_3, ot_font_get_named, ot_get_font_metrics, ot_print_font_name,
+    print_glyph_name, print_utf8_str, real_get_native_glyph_italic_correction,
+    real_get_native_italic_correction, real_get_native_word_cp, release_font_engine,
 };
 use crate::xetex_ini::{
     _xeq_level_array, active_width, adjust_tail, after_token, align_ptr, align_state,
@@ -50,29 +50,29 @@ use crate::xetex_ini::{
     insert_src_special_every_par, insert_src_special_every_vbox, interaction, is_hyph,
     is_in_csname, job_name, last, last_badness, last_glue, last_kern, last_leftmost_char,
     last_node_type, last_penalty, last_rightmost_char, lft_hit, lig_stack, ligature_present, line,
-    lo_mem_max, loaded_font_design_size, loaded_font_flags, loaded_font_letter_space,
-    loaded_font_mapping, log_file, log_opened, long_help_seen, long_state, mag_set, main_f, main_h,
-    main_i, main_j, main_k, main_p, main_pp, main_ppp, main_s, mapped_text, max_buf_stack,
-    max_nest_stack, max_print_line, max_reg_help_line, max_reg_num, max_strings, mem_end,
-    name_in_progress, name_length, name_length16, name_of_file, name_of_file16,
-    native_font_type_flag, native_len, native_text, native_text_size, nest, nest_ptr, nest_size,
-    no_new_control_sequence, old_setting, open_parens, output_active, pack_begin_line,
-    page_contents, page_so_far, page_tail, par_loc, par_token, pdf_last_x_pos, pdf_last_y_pos,
-    pool_ptr, pool_size, pre_adjust_tail, prev_class, prim, prim_eqtb, prim_used, pseudo_files,
-    pstack, quoted_filename, radix, read_file, read_open, rover, rt_hit, rust_stdout, sa_chain,
-    sa_level, sa_null, sa_root, save_native_len, scanner_status, selector, set_box_allowed,
-    shown_mode, skip_line, space_class, stop_at_space, str_pool, str_ptr, str_start, tally,
-    temp_ptr, term_offset, tex_remainder, texmf_log_name, total_shrink, total_stretch, trick_buf,
-    trick_count, use_err_help, used_tectonic_coda_tokens, warning_index, write_file, write_open,
-    xtx_ligature_present, LR_problems, LR_ptr, BASE_PTR, BCHAR_LABEL, CHAR_BASE, DEPTH_BASE,
-    EOF_SEEN, EQTB, EQTB_TOP, EXTEN_BASE, FONT_AREA, FONT_BC, FONT_BCHAR, FONT_CHECK, FONT_DSIZE,
-    FONT_EC, FONT_FALSE_BCHAR, FONT_FLAGS, FONT_GLUE, FONT_INFO, FONT_LAYOUT_ENGINE,
-    FONT_LETTER_SPACE, FONT_MAPPING, FONT_MAX, FONT_MEM_SIZE, FONT_NAME, FONT_PARAMS, FONT_SIZE,
-    FULL_SOURCE_FILENAME_STACK, GRP_STACK, HEIGHT_BASE, HYPHEN_CHAR, IF_STACK, INPUT_FILE,
-    INPUT_PTR, INPUT_STACK, IN_OPEN, ITALIC_BASE, KERN_BASE, LIG_KERN_BASE, LINE_STACK,
-    MAX_IN_OPEN, MAX_IN_STACK, MAX_PARAM_STACK, MAX_SAVE_STACK, MEM, PARAM_BASE, PARAM_PTR,
-    PARAM_SIZE, PARAM_STACK, SAVE_PTR, SAVE_SIZE, SAVE_STACK, SKEW_CHAR, SOURCE_FILENAME_STACK,
-    STACK_SIZE, WIDTH_BASE,
+    lo_mem_max, loaded_font_design_size, loaded_font_fallback, loaded_font_flags,
+    loaded_font_letter_space, loaded_font_mapping, log_file, log_opened, long_help_seen,
+    long_state, mag_set, main_f, main_h, main_i, main_j, main_k, main_p, main_pp, main_ppp, main_s,
+    mapped_text, max_buf_stack, max_nest_stack, max_print_line, max_reg_help_line, max_reg_num,
+    max_strings, mem_end, name_in_progress, name_length, name_length16, name_of_file,
+    name_of_file16, native_font_type_flag, native_len, native_text, native_text_size, nest,
+    nest_ptr, nest_size, no_new_control_sequence, old_setting, open_parens, output_active,
+    pack_begin_line, page_contents, page_so_far, page_tail, par_loc, par_token, pdf_last_x_pos,
+    pdf_last_y_pos, pool_ptr, pool_size, pre_adjust_tail, prev_class, prim, prim_eqtb, prim_used,
+    pseudo_files, pstack, quoted_filename, radix, read_file, read_open, rover, rt_hit, rust_stdout,
+    sa_chain, sa_level, sa_null, sa_root, save_native_len, scanner_status, selector,
+    set_box_allowed, shown_mode, skip_line, space_class, stop_at_space, str_pool, str_ptr,
+    str_start, tally, temp_ptr, term_offset, tex_remainder, texmf_log_name, total_shrink,
+    total_stretch, trick_buf, trick_count, use_err_help, used_tectonic_coda_tokens, warning_index,
+    write_file, write_open, xtx_ligature_present, LR_problems, LR_ptr, BASE_PTR, BCHAR_LABEL,
+    CHAR_BASE, DEPTH_BASE, EOF_SEEN, EQTB, EQTB_TOP, EXTEN_BASE, FONT_AREA, FONT_BC, FONT_BCHAR,
+    FONT_CHECK, FONT_DSIZE, FONT_EC, FONT_FALLBACK, FONT_FALSE_BCHAR, FONT_FLAGS, FONT_GLUE,
+    FONT_INFO, FONT_LAYOUT_ENGINE, FONT_LETTER_SPACE, FONT_MAPPING, FONT_MAX, FONT_MEM_SIZE,
+    FONT_NAME, FONT_PARAMS, FONT_SIZE, FULL_SOURCE_FILENAME_STACK, GRP_STACK, HEIGHT_BASE,
+    HYPHEN_CHAR, IF_STACK, INPUT_FILE, INPUT_PTR, INPUT_STACK, IN_OPEN, ITALIC_BASE, KERN_BASE,
+    LIG_KERN_BASE, LINE_STACK, MAX_IN_OPEN, MAX_IN_STACK, MAX_PARAM_STACK, MAX_SAVE_STACK, MEM,
+    PARAM_BASE, PARAM_PTR, PARAM_SIZE, PARAM_STACK, SAVE_PTR, SAVE_SIZE, SAVE_STACK, SKEW_CHAR,
+    SOURCE_FILENAME_STACK, STACK_SIZE, WIDTH_BASE,
 };
 use crate::xetex_ini::{b16x4, b32x2, memory_word, prefixed_command};
 use crate::xetex_io::{input_line, open_or_close_in, set_input_file_encoding, u_close};
@@ -102,8 +102,8 @@ use crate::xetex_texmfmp::{
 };
 use crate::xetex_xetexd::{is_char_node, is_non_discardable_node, print_c_string};
 use bridge::{
-    ttstub_input_close, ttstub_input_getc, ttstub_issue_warning, ttstub_output_close,
-    ttstub_output_open, ttstub_output_putc,
+    ttstub_check_limits, ttstub_input_close, ttstub_input_getc, ttstub_issue_warning,
+    ttstub_output_close, ttstub_output_open, ttstub_output_putc,
 };
 use bridge::{TTHistory, TTInputFormat};
 
@@ -5754,6 +5754,8 @@ pub(crate) unsafe fn expand() {
 }
 pub(crate) unsafe fn get_x_token() {
     loop {
+        /* Runaway expansions like `\def\a{\a}\a` never get back to main_control. */
+        ttstub_check_limits();
         get_next();
         if cur_cmd as i32 <= 102i32 {
             break;
@@ -7011,10 +7013,28 @@ pub(crate) unsafe fn scan_something_internal(mut level: small_number, mut negati
                                 _ => cur_val = 0,
                             }
                         }
-                        17 | 19 | 20 | 21 | 16 => {
+                        16 => {
                             scan_font_ident();
                             n = cur_val;
-                            cur_val = 0i32
+                            match FONT_AREA[n as usize] as u32 {
+                                0xfffeu32 => {
+                                    cur_val = ot_font_get(m - 14i32, FONT_LAYOUT_ENGINE[n as usize])
+                                }
+                                _ => cur_val = 0i32,
+                            }
+                        }
+                        17 | 19 | 20 | 21 => {
+                            scan_font_ident();
+                            n = cur_val;
+                            scan_int();
+                            k = cur_val;
+                            match FONT_AREA[n as usize] as u32 {
+                                0xfffeu32 => {
+                                    cur_val =
+                                        ot_font_get_1(m - 14i32, FONT_LAYOUT_ENGINE[n as usize], k)
+                                }
+                                _ => cur_val = -1i32,
+                            }
                         }
                         23 | 25 | 26 => {
                             scan_font_ident();
@@ -7126,6 +7146,13 @@ pub(crate) unsafe fn scan_something_internal(mut level: small_number, mut negati
                                     scan_and_pack_name();
                                     cur_val = -1;
                                 }
+                                0xfffeu32 => {
+                                    scan_and_pack_name();
+                                    cur_val = ot_font_get_named(
+                                        m - 14i32,
+                                        FONT_LAYOUT_ENGINE[n as usize],
+                                    );
+                                }
                                 _ => {
                                     not_aat_font_error(71i32, m, n);
                                     cur_val = -1i32
@@ -9017,12 +9044,14 @@ pub(crate) unsafe fn conv_toks() {
         7 => {
             scan_font_ident();
             fnt = cur_val;
-            if FONT_AREA[fnt as usize] as u32 == 0xffffu32 {
+            if FONT_AREA[fnt as usize] as u32 == 0xffffu32
+                || FONT_AREA[fnt as usize] as u32 == 0xfffeu32
+            {
                 scan_int();
                 arg1 = cur_val;
                 arg2 = 0i32
             } else {
-                not_aat_font_error(110i32, c as i32, fnt);
+                not_native_font_error(110i32, c as i32, fnt);
             }
         }
         8 => {
@@ -9153,6 +9182,9 @@ pub(crate) unsafe fn conv_toks() {
                 0xffffu32 => {
                     // do nothing
                 }
+                0xfffeu32 => {
+                    ot_print_font_name(c as i32, FONT_LAYOUT_ENGINE[fnt as usize], arg1);
+                }
                 _ => {
                     // do nothing
                 }
@@ -10895,7 +10927,94 @@ pub(crate) unsafe fn load_native_font(
     }
     FONT_MAPPING[font_ptr as usize] = loaded_font_mapping;
     FONT_FLAGS[font_ptr as usize] = loaded_font_flags;
-    font_ptr
+    f = font_ptr;
+    let fallback_names = std::mem::take(&mut loaded_font_fallback);
+    if !fallback_names.is_empty() {
+        load_fallback_fonts(u, nom, aire, f, fallback_names);
+    }
+    f
+}
+/// Loads the fonts named by the `fallback=` feature of native font `f`, at
+/// the same size, and records them as its fallback chain.
+unsafe fn load_fallback_fonts(
+    u: i32,
+    nom: str_number,
+    aire: str_number,
+    f: internal_font_number,
+    names: Vec<Vec<u8>>,
+) {
+    let saved_name_of_file = name_of_file;
+    let saved_name_length = name_length;
+    for name in names {
+        name_length = name.len() as i32;
+        name_of_file = xmalloc_array(name.len() + 1);
+        ptr::copy_nonoverlapping(name.as_ptr() as *const i8, name_of_file, name.len());
+        *name_of_file.add(name.len()) = 0;
+        let g = load_native_font(u, nom, aire, FONT_SIZE[f as usize]);
+        if g == FONT_BASE {
+            fallback_font_warning(f, &name);
+        } else if g != f {
+            FONT_FALLBACK[f as usize].push(g);
+        }
+        free(name_of_file as *mut libc::c_void);
+    }
+    name_of_file = saved_name_of_file;
+    name_length = saved_name_length;
+}
+pub(crate) unsafe fn fallback_font_warning(f: internal_font_number, name: &[u8]) {
+    begin_diagnostic();
+    print_nl_cstr(b"Fallback font `");
+    print_utf8_str(name.as_ptr(), name.len() as i32);
+    print_cstr(b"\' for font `");
+    print(FONT_NAME[f as usize]);
+    print_cstr(b"\' not loadable.");
+    end_diagnostic(false);
+}
+/// Splits the native word nodes after `prev` in the current list into runs
+/// that each use one font of their font's fallback chain, so that characters
+/// missing from the primary font are shaped with a font that has them.
+pub(crate) unsafe fn apply_font_fallback(mut prev: i32) {
+    let mut p = MEM[prev as usize].b32.s1;
+    while p != TEX_NULL {
+        if !is_char_node(p)
+            && MEM[p as usize].b16.s1 as i32 == 8
+            && (MEM[p as usize].b16.s0 as i32 == 40 || MEM[p as usize].b16.s0 as i32 == 41)
+            && !FONT_FALLBACK[MEM[(p + 4) as usize].b16.s2 as usize].is_empty()
+        {
+            let f = MEM[(p + 4) as usize].b16.s2 as internal_font_number;
+            let text = std::slice::from_raw_parts(
+                &MEM[(p + 6) as usize] as *const memory_word as *const u16,
+                MEM[(p + 4) as usize].b16.s1 as usize,
+            )
+            .to_vec();
+            let runs = fallback_runs(f, &text);
+            if runs.len() > 1 || runs.first().map(|run| run.0) != Some(f) {
+                let next = MEM[p as usize].b32.s1;
+                let mut q = prev;
+                for (g, range) in runs {
+                    let r = new_native_word_node(g, range.len() as i32);
+                    for (i, &c) in text[range].iter().enumerate() {
+                        *(&mut MEM[(r + 6) as usize] as *mut memory_word as *mut u16).add(i) = c;
+                    }
+                    measure_native_node(
+                        &mut MEM[r as usize] as *mut memory_word as *mut libc::c_void,
+                        (EQTB[(INT_BASE + 74i32) as usize].b32.s1 > 0i32) as i32,
+                    );
+                    MEM[q as usize].b32.s1 = r;
+                    q = r;
+                }
+                MEM[q as usize].b32.s1 = next;
+                if cur_list.tail == p {
+                    cur_list.tail = q;
+                }
+                MEM[p as usize].b32.s1 = TEX_NULL;
+                flush_node_list(p);
+                p = q;
+            }
+        }
+        prev = p;
+        p = MEM[p as usize].b32.s1;
+    }
 }
 pub(crate) unsafe fn do_locale_linebreaks(mut s: i32, mut len: i32) {
     let mut offs: i32 = 0;
@@ -16706,6 +16825,7 @@ pub(crate) unsafe fn main_control() {
     }
     'c_125208: loop {
         /* big_switch */
+        ttstub_check_limits();
         get_x_token();
         loop {
             /*1066: */
@@ -17419,11 +17539,14 @@ pub(crate) unsafe fn main_control() {
                                 main_k + *native_text.offset(temp_ptr as isize) as i32 - 0xdc00i32;
                             temp_ptr += 1
                         }
-                        if map_char_to_glyph(main_f, main_k) == 0i32 {
+                        if fallback_font_for_char(main_f, main_k).is_none() {
                             char_warning(main_f, main_k);
                         }
                     }
                 }
+                /* the node before the old tail, which the new text may be
+                 * merged into; it marks where the fallback runs start */
+                let mut fallback_prev = TEX_NULL;
                 main_k = native_len;
                 main_pp = cur_list.tail;
                 if cur_list.mode as i32 == 104i32 {
@@ -17452,6 +17575,9 @@ pub(crate) unsafe fn main_control() {
                             }
                         }
                     }
+                    if !FONT_FALLBACK[main_f as usize].is_empty() {
+                        fallback_prev = main_ppp
+                    }
                     temp_ptr = 0i32;
                     loop {
                         if main_h == 0i32 {
@@ -17593,6 +17719,9 @@ pub(crate) unsafe fn main_control() {
                             }
                         }
                     }
+                    if !FONT_FALLBACK[main_f as usize].is_empty() {
+                        fallback_prev = main_ppp
+                    }
                     if main_pp != TEX_NULL
                         && !is_char_node(main_pp)
                         && MEM[main_pp as usize].b16.s1 as i32 == 8
@@ -17683,6 +17812,9 @@ pub(crate) unsafe fn main_control() {
                         );
                     }
                 }
+                if fallback_prev != TEX_NULL {
+                    apply_font_fallback(fallback_prev);
+                }
                 if EQTB[(INT_BASE + 80i32) as usize].b32.s1 > 0i32 {
                     main_p = cur_list.head;
                     main_pp = TEX_NULL;
@@ -0,0 +1,923 @@
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Read and write BibTeX database (`.bib`) files.
+//!
+//! This is a standalone reader that follows the same rules as the `.bib`
+//! scanner in Tectonic's BibTeX engine, so that tools working on
+//! bibliographies see exactly what BibTeX would. In particular:
+//!
+//! - Text outside of `@` commands is ignored, as is everything after
+//!   `@comment` up to the next `@`.
+//! - Entry types, field names and `@string` names are case-insensitive and
+//!   are stored in lower case. Cite keys keep their case, but two keys that
+//!   differ only in case are the same entry.
+//! - Field values may be `{braced}`, `"quoted"`, numbers or `@string` names,
+//!   joined with `#`. Runs of white space, including line breaks, become a
+//!   single space, and the white space at either end of an entry's field
+//!   value is dropped.
+//! - Errors abandon the rest of the command or entry in which they occur,
+//!   keeping any fields that were read before the error, and reading resumes
+//!   at the next `@`.
+//!
+//! Besides its expanded text, every value keeps the pieces it was written
+//! as, so a [`Database`] can be modified and written back out as `.bib`
+//! text with its `@string` references intact.
+//!
+//! ```
+//! use tectonic::bib::Database;
+//!
+//! let mut db = Database::new();
+//! db.parse_str(
+//!     "refs.bib",
+//!     r#"@string{ tug = "TeX Users Group" }
+//!        @book{ Knuth84, author = "Donald E. Knuth",
+//!               title = {The {\TeX}book}, publisher = tug # { (reprint)},
+//!               year = 1984 }"#,
+//! );
+//!
+//! let entry = db.entry("knuth84").unwrap();
+//! assert_eq!(entry.get("publisher"), Some("TeX Users Group (reprint)"));
+//! assert!(db.errors().is_empty());
+//! ```
+
+use std::fmt;
+
+use crate::status::MessageKind;
+
+/// One piece of a field value, as it was written in the `.bib` file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValuePart {
+    /// Text delimited by braces, without the outer braces.
+    Braced(String),
+    /// Text delimited by double quotes, without the quotes.
+    Quoted(String),
+    /// A bare number.
+    Number(String),
+    /// The name of an `@string` macro, in lower case.
+    Macro(String),
+}
+
+impl fmt::Display for ValuePart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValuePart::Braced(s) => write!(f, "{{{}}}", s),
+            ValuePart::Quoted(s) => write!(f, "\"{}\"", s),
+            ValuePart::Number(s) | ValuePart::Macro(s) => f.write_str(s),
+        }
+    }
+}
+
+/// A value made of one or more parts joined with `#`, together with the text
+/// that BibTeX makes of it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Value {
+    /// The parts of the value, as written.
+    pub parts: Vec<ValuePart>,
+
+    /// The value with its macros expanded and its white space compressed, as
+    /// BibTeX stores it. Macros are expanded with their definitions at the
+    /// point where the value was read.
+    pub text: String,
+}
+
+impl Value {
+    /// Create a value that is written as a single braced string.
+    pub fn braced(text: &str) -> Value {
+        Value {
+            parts: vec![ValuePart::Braced(text.to_owned())],
+            text: text.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, part) in self.parts.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" # ")?;
+            }
+            write!(f, "{}", part)?;
+        }
+        Ok(())
+    }
+}
+
+/// A field of an entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Field {
+    /// The field name, in lower case.
+    pub name: String,
+    /// The field value.
+    pub value: Value,
+    /// The line on which the field name appears.
+    pub line: usize,
+}
+
+/// A database entry, such as `@article{key, ...}`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// The entry type, such as `article`, in lower case.
+    pub entry_type: String,
+    /// The cite key, as written.
+    pub key: String,
+    /// The fields of the entry, in the order they were written.
+    pub fields: Vec<Field>,
+    /// The name of the file that the entry was read from.
+    pub file: String,
+    /// The line on which the entry starts.
+    pub line: usize,
+}
+
+impl Entry {
+    /// Create an entry with no fields.
+    pub fn new(entry_type: &str, key: &str) -> Entry {
+        Entry {
+            entry_type: entry_type.to_ascii_lowercase(),
+            key: key.to_owned(),
+            fields: Vec::new(),
+            file: String::new(),
+            line: 0,
+        }
+    }
+
+    /// The field with the given name, which is matched case-insensitively.
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|f| f.name.eq_ignore_ascii_case(name))
+    }
+
+    /// A mutable reference to the field with the given name.
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut Field> {
+        self.fields
+            .iter_mut()
+            .find(|f| f.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The text of the field with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.field(name).map(|f| f.value.text.as_str())
+    }
+
+    /// Set a field, replacing its value if the entry already has it and
+    /// adding it at the end otherwise.
+    pub fn set(&mut self, name: &str, value: Value) {
+        if let Some(f) = self.field_mut(name) {
+            f.value = value;
+            return;
+        }
+
+        self.fields.push(Field {
+            name: name.to_ascii_lowercase(),
+            value,
+            line: 0,
+        });
+    }
+
+    /// Remove a field, returning it if the entry had it.
+    pub fn remove(&mut self, name: &str) -> Option<Field> {
+        let i = self
+            .fields
+            .iter()
+            .position(|f| f.name.eq_ignore_ascii_case(name))?;
+        Some(self.fields.remove(i))
+    }
+
+    /// The key of the entry named by this entry's `crossref` field, if any.
+    pub fn crossref(&self) -> Option<&str> {
+        self.get("crossref")
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@{}{{{},", self.entry_type, self.key)?;
+        for field in &self.fields {
+            write!(f, "\n  {} = {},", field.name, field.value)?;
+        }
+        f.write_str("\n}\n")
+    }
+}
+
+/// An `@string` definition.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Macro {
+    /// The macro name, in lower case.
+    pub name: String,
+    /// The macro's definition.
+    pub value: Value,
+    /// The line on which the definition starts.
+    pub line: usize,
+}
+
+/// One of the commands or entries of a database, in the order they were
+/// read.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Item {
+    /// An `@string` definition.
+    Macro(Macro),
+    /// An `@preamble` command.
+    Preamble(Value),
+    /// A database entry.
+    Entry(Entry),
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Item::Macro(m) => writeln!(f, "@string{{{} = {}}}", m.name, m.value),
+            Item::Preamble(v) => writeln!(f, "@preamble{{{}}}", v),
+            Item::Entry(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A problem found while reading a `.bib` file. The messages are those that
+/// BibTeX logs for the same problems.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BibError {
+    /// Either `MessageKind::Warning` or `MessageKind::Error`. After an error,
+    /// the rest of the command or entry was skipped.
+    pub severity: MessageKind,
+    /// The name of the file in which the problem was found.
+    pub file: String,
+    /// The line on which the problem was found.
+    pub line: usize,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for BibError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+/// A BibTeX database, which may be read from any number of `.bib` files.
+///
+/// As with BibTeX's `\bibdata{a,b}`, `@string` macros defined in one file are
+/// available in the files read after it, and an entry whose key repeats an
+/// earlier one is an error.
+#[derive(Clone, Debug, Default)]
+pub struct Database {
+    items: Vec<Item>,
+    errors: Vec<BibError>,
+}
+
+impl Database {
+    /// Create an empty database.
+    pub fn new() -> Database {
+        Default::default()
+    }
+
+    /// Define a macro that the files read afterwards can use. Styles usually
+    /// predefine the month names `jan` to `dec` in this way.
+    pub fn define_macro(&mut self, name: &str, text: &str) {
+        self.items.push(Item::Macro(Macro {
+            name: name.to_ascii_lowercase(),
+            value: Value::braced(text),
+            line: 0,
+        }));
+    }
+
+    /// Read the contents of a `.bib` file into the database. `file` is only
+    /// used to label errors and entries. Problems are recorded in
+    /// [`Database::errors`] rather than stopping the read.
+    pub fn parse_str(&mut self, file: &str, text: &str) {
+        let mut p = Parser {
+            db: self,
+            file,
+            text: text.as_bytes(),
+            pos: 0,
+            line: 1,
+        };
+        p.parse();
+    }
+
+    /// All of the database's commands and entries, in order.
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    /// A mutable reference to the database's commands and entries.
+    pub fn items_mut(&mut self) -> &mut Vec<Item> {
+        &mut self.items
+    }
+
+    /// The database's entries, in order.
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.items.iter().filter_map(|i| match i {
+            Item::Entry(e) => Some(e),
+            _ => None,
+        })
+    }
+
+    /// The database's entries, mutably.
+    pub fn entries_mut(&mut self) -> impl Iterator<Item = &mut Entry> {
+        self.items.iter_mut().filter_map(|i| match i {
+            Item::Entry(e) => Some(e),
+            _ => None,
+        })
+    }
+
+    /// The entry with the given key, which is matched case-insensitively.
+    pub fn entry(&self, key: &str) -> Option<&Entry> {
+        self.entries().find(|e| e.key.eq_ignore_ascii_case(key))
+    }
+
+    /// A mutable reference to the entry with the given key.
+    pub fn entry_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        self.entries_mut().find(|e| e.key.eq_ignore_ascii_case(key))
+    }
+
+    /// Add an entry at the end of the database. Returns `false`, and leaves
+    /// the database unchanged, if it already has an entry with the same key.
+    pub fn add_entry(&mut self, entry: Entry) -> bool {
+        if self.entry(&entry.key).is_some() {
+            return false;
+        }
+
+        self.items.push(Item::Entry(entry));
+        true
+    }
+
+    /// Remove the entry with the given key, returning it if there was one.
+    pub fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        let i = self.items.iter().position(|i| match i {
+            Item::Entry(e) => e.key.eq_ignore_ascii_case(key),
+            _ => false,
+        })?;
+
+        match self.items.remove(i) {
+            Item::Entry(e) => Some(e),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The text of a field of an entry, taking cross-references into
+    /// account: if the entry doesn't have the field but its `crossref` field
+    /// names an entry that does, that entry's value is used, as BibTeX does.
+    pub fn resolved_field(&self, key: &str, field: &str) -> Option<&str> {
+        let entry = self.entry(key)?;
+
+        if let Some(text) = entry.get(field) {
+            return Some(text);
+        }
+
+        self.entry(entry.crossref()?)?.get(field)
+    }
+
+    /// The current definition of a macro.
+    pub fn macro_text(&self, name: &str) -> Option<&str> {
+        self.items.iter().rev().find_map(|i| match i {
+            Item::Macro(m) if m.name.eq_ignore_ascii_case(name) => Some(m.value.text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The text of the `@preamble` commands, concatenated as BibTeX does.
+    pub fn preamble(&self) -> String {
+        self.items
+            .iter()
+            .filter_map(|i| match i {
+                Item::Preamble(v) => Some(v.text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The errors and warnings issued while reading the database.
+    pub fn errors(&self) -> &[BibError] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for Database {
+    /// Write the database out as `.bib` text.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{}", item)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_white(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\r' | b'\n')
+}
+
+/// Whether `c` may appear in an identifier, following BibTeX's `id_class`.
+fn is_id_char(c: u8) -> bool {
+    !(c < 32
+        || matches!(
+            c,
+            b' ' | b'"' | b'#' | b'%' | b'\'' | b'(' | b')' | b',' | b'=' | b'{' | b'}'
+        ))
+}
+
+/// Raised when a command or entry has to be abandoned. The error has already
+/// been recorded.
+struct Abandon;
+
+type PResult<T> = Result<T, Abandon>;
+
+struct Parser<'a> {
+    db: &'a mut Database,
+    file: &'a str,
+    text: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn report(&mut self, severity: MessageKind, message: String) {
+        self.db.errors.push(BibError {
+            severity,
+            file: self.file.to_owned(),
+            line: self.line,
+            message,
+        });
+    }
+
+    fn error<T>(&mut self, message: String) -> PResult<T> {
+        self.report(MessageKind::Error, message);
+        Err(Abandon)
+    }
+
+    fn warn(&mut self, message: String) {
+        self.report(MessageKind::Warning, message);
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).cloned()
+    }
+
+    fn bump(&mut self) {
+        if self.text[self.pos] == b'\n' {
+            self.line += 1;
+        }
+        self.pos += 1;
+    }
+
+    /// Skip white space, failing at the end of the file.
+    fn eat_white(&mut self) -> PResult<()> {
+        while let Some(c) = self.peek() {
+            if !is_white(c) {
+                return Ok(());
+            }
+            self.bump();
+        }
+        self.error("Illegal end of database file".to_owned())
+    }
+
+    fn expect_open(&mut self) -> PResult<u8> {
+        match self.peek() {
+            Some(b'{') => {
+                self.bump();
+                Ok(b'}')
+            }
+            Some(b'(') => {
+                self.bump();
+                Ok(b')')
+            }
+            _ => self.error("I was expecting a `{' or a `('".to_owned()),
+        }
+    }
+
+    /// Scan an identifier, which must be followed by white space or one of
+    /// `followers`. Identifiers can't start with a digit.
+    fn identifier(&mut self, followers: &[u8], what: &str) -> PResult<String> {
+        let start = self.pos;
+        if !matches!(self.peek(), Some(c) if c.is_ascii_digit()) {
+            while matches!(self.peek(), Some(c) if is_id_char(c)) {
+                self.pos += 1;
+            }
+        }
+
+        match self.peek() {
+            _ if self.pos == start => self.error(format!("You're missing {}", what)),
+            Some(c) if !is_white(c) && !followers.contains(&c) => {
+                self.error(format!("\"{}\" immediately follows {}", c as char, what))
+            }
+            _ => Ok(String::from_utf8_lossy(&self.text[start..self.pos]).to_ascii_lowercase()),
+        }
+    }
+
+    fn parse(&mut self) {
+        loop {
+            while let Some(c) = self.peek() {
+                if c == b'@' {
+                    break;
+                }
+                self.bump();
+            }
+
+            if self.peek().is_none() {
+                return;
+            }
+
+            self.bump();
+            let _ = self.command_or_entry();
+        }
+    }
+
+    fn command_or_entry(&mut self) -> PResult<()> {
+        let start_line = self.line;
+        self.eat_white()?;
+        let kind = self.identifier(b"{(", "an entry type")?;
+
+        match kind.as_str() {
+            "comment" => Ok(()),
+
+            "preamble" => {
+                self.eat_white()?;
+                let close = self.expect_open()?;
+                self.eat_white()?;
+                let value = self.value(close, None, false)?;
+                if self.peek() != Some(close) {
+                    return self
+                        .error(format!("Missing \"{}\" in preamble command", close as char));
+                }
+                self.bump();
+                self.db.items.push(Item::Preamble(value));
+                Ok(())
+            }
+
+            "string" => {
+                self.eat_white()?;
+                let close = self.expect_open()?;
+                self.eat_white()?;
+                let name = self.identifier(b"=", "a string name")?;
+
+                // Until its definition has been read, BibTeX takes a macro
+                // to stand for its own name.
+                self.db.items.push(Item::Macro(Macro {
+                    name: name.clone(),
+                    value: Value::braced(&name),
+                    line: start_line,
+                }));
+                let index = self.db.items.len() - 1;
+
+                self.eat_white()?;
+                if self.peek() != Some(b'=') {
+                    return self.error("I was expecting an \"=\"".to_owned());
+                }
+                self.bump();
+                self.eat_white()?;
+                let value = self.value(close, Some(&name), false)?;
+                if self.peek() != Some(close) {
+                    return self.error(format!("Missing \"{}\" in string command", close as char));
+                }
+                self.bump();
+                self.db.items[index] = Item::Macro(Macro {
+                    name,
+                    value,
+                    line: start_line,
+                });
+                Ok(())
+            }
+
+            _ => self.entry(kind, start_line),
+        }
+    }
+
+    fn entry(&mut self, entry_type: String, start_line: usize) -> PResult<()> {
+        self.eat_white()?;
+        let close = self.expect_open()?;
+        self.eat_white()?;
+
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if !is_white(c) && c != b',' && !(close == b'}' && c == b'}'))
+        {
+            self.pos += 1;
+        }
+        let key = String::from_utf8_lossy(&self.text[start..self.pos]).into_owned();
+
+        if self.db.entry(&key).is_some() {
+            return self.error("Repeated entry".to_owned());
+        }
+
+        self.db.items.push(Item::Entry(Entry {
+            entry_type,
+            key,
+            fields: Vec::new(),
+            file: self.file.to_owned(),
+            line: start_line,
+        }));
+        let index = self.db.items.len() - 1;
+
+        self.eat_white()?;
+
+        while self.peek() != Some(close) {
+            if self.peek() != Some(b',') {
+                return self.error(format!("I was expecting a `,' or a `{}'", close as char));
+            }
+            self.bump();
+            self.eat_white()?;
+            if self.peek() == Some(close) {
+                break;
+            }
+
+            let field_line = self.line;
+            let name = self.identifier(b"=", "a field name")?;
+            self.eat_white()?;
+            if self.peek() != Some(b'=') {
+                return self.error("I was expecting an \"=\"".to_owned());
+            }
+            self.bump();
+            self.eat_white()?;
+            let value = self.value(close, None, true)?;
+
+            let entry = match self.db.items[index] {
+                Item::Entry(ref mut e) => e,
+                _ => unreachable!(),
+            };
+
+            if entry.field(&name).is_some() {
+                let message = format!("I'm ignoring {}'s extra \"{}\" field", entry.key, name);
+                self.warn(message);
+            } else {
+                entry.fields.push(Field {
+                    name,
+                    value,
+                    line: field_line,
+                });
+            }
+        }
+
+        self.bump();
+        Ok(())
+    }
+
+    /// Scan a field value: tokens joined with `#`. `defining` is the name of
+    /// the macro whose definition this is, if any. The white space at the
+    /// ends of an entry's field values is trimmed.
+    fn value(&mut self, close: u8, defining: Option<&str>, trim: bool) -> PResult<Value> {
+        let mut parts = Vec::new();
+        let mut text = Vec::new();
+
+        loop {
+            self.token(close, defining, &mut parts, &mut text)?;
+            self.eat_white()?;
+
+            if self.peek() != Some(b'#') {
+                break;
+            }
+            self.bump();
+            self.eat_white()?;
+        }
+
+        let mut text = &text[..];
+        if trim {
+            if text.last() == Some(&b' ') {
+                text = &text[..text.len() - 1];
+            }
+            if text.first() == Some(&b' ') {
+                text = &text[1..];
+            }
+        }
+
+        Ok(Value {
+            parts,
+            text: String::from_utf8_lossy(text).into_owned(),
+        })
+    }
+
+    fn token(
+        &mut self,
+        close: u8,
+        defining: Option<&str>,
+        parts: &mut Vec<ValuePart>,
+        text: &mut Vec<u8>,
+    ) -> PResult<()> {
+        match self.peek() {
+            Some(b'{') | Some(b'"') => {
+                let quoted = self.peek() == Some(b'"');
+                let raw = self.delimited(quoted)?;
+                compress_into(text, raw.as_bytes());
+                parts.push(if quoted {
+                    ValuePart::Quoted(raw)
+                } else {
+                    ValuePart::Braced(raw)
+                });
+            }
+
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                let digits = &self.text[start..self.pos];
+                text.extend_from_slice(digits);
+                parts.push(ValuePart::Number(
+                    String::from_utf8_lossy(digits).into_owned(),
+                ));
+            }
+
+            _ => {
+                let name = self.identifier(&[b',', close, b'#'], "a field part")?;
+
+                if defining == Some(name.as_str()) {
+                    self.warn(format!(
+                        "string name \"{}\" is used in its own definition",
+                        name
+                    ));
+                } else {
+                    match self.db.macro_text(&name) {
+                        Some(expansion) => {
+                            let expansion = expansion.as_bytes().to_owned();
+                            expand_macro_into(text, &expansion);
+                        }
+                        None => self.warn(format!("string name \"{}\" is undefined", name)),
+                    }
+                }
+
+                parts.push(ValuePart::Macro(name));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Scan a braced or quoted string, returning its contents. Braces must
+    /// balance within a quoted string.
+    fn delimited(&mut self, quoted: bool) -> PResult<String> {
+        self.bump();
+        let start = self.pos;
+        let mut depth = 0usize;
+
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return self.error("Illegal end of database file".to_owned()),
+            };
+
+            match c {
+                b'{' => depth += 1,
+                b'}' if depth > 0 => depth -= 1,
+                b'}' if !quoted => break,
+                b'}' => return self.error("Unbalanced braces".to_owned()),
+                b'"' if quoted && depth == 0 => break,
+                _ => {}
+            }
+
+            self.bump();
+        }
+
+        let raw = String::from_utf8_lossy(&self.text[start..self.pos]).into_owned();
+        self.bump();
+        Ok(raw)
+    }
+}
+
+/// Append a braced or quoted string to a value's text, turning each run of
+/// white space into a single space.
+fn compress_into(text: &mut Vec<u8>, raw: &[u8]) {
+    let start = text.len();
+    let mut in_white = false;
+
+    for &c in raw {
+        if is_white(c) {
+            if !in_white {
+                text.push(b' ');
+            }
+            in_white = true;
+        } else {
+            text.push(c);
+            in_white = false;
+        }
+    }
+
+    // A token that starts with white space doesn't add a second space after
+    // one that ends with it.
+    if start > 0 && text.len() > start && text[start] == b' ' && text[start - 1] == b' ' {
+        text.remove(start);
+    }
+}
+
+/// Append a macro's text to a value's text, without doubling up spaces.
+fn expand_macro_into(text: &mut Vec<u8>, expansion: &[u8]) {
+    let mut rest = expansion;
+
+    if text.is_empty() && matches!(rest.first(), Some(&c) if is_white(c)) {
+        text.push(b' ');
+        while matches!(rest.first(), Some(&c) if is_white(c)) {
+            rest = &rest[1..];
+        }
+    }
+
+    for &c in rest {
+        if !is_white(c) {
+            text.push(c);
+        } else if text.last() != Some(&b' ') {
+            text.push(b' ');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Database {
+        let mut db = Database::new();
+        db.parse_str("test.bib", text);
+        db
+    }
+
+    #[test]
+    fn values_and_macros() {
+        let db = parse(
+            "junk @STRING(pub = \"Some  Press\")\n\
+             @Book{ K1,\n  Title = { A \n   {B  C} }, Publisher = pub # \" Inc\",\n  year = 1984,\n}",
+        );
+        assert!(db.errors().is_empty(), "{:?}", db.errors());
+
+        let e = db.entry("k1").unwrap();
+        assert_eq!(e.entry_type, "book");
+        assert_eq!(e.key, "K1");
+        assert_eq!(e.get("title"), Some("A {B C}"));
+        assert_eq!(e.get("publisher"), Some("Some Press Inc"));
+        assert_eq!(e.get("YEAR"), Some("1984"));
+        assert_eq!(e.field("title").unwrap().line, 3);
+        assert_eq!(
+            e.field("publisher").unwrap().value.parts,
+            vec![
+                ValuePart::Macro("pub".to_owned()),
+                ValuePart::Quoted(" Inc".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn preamble_comment_and_crossref() {
+        let db = parse(
+            "@comment{ @ignored\n@preamble{ \"\\newcommand{\\x}{}\" }\n\
+             @inproceedings{child, crossref = {parent}, title = {T}}\n\
+             @proceedings{parent, booktitle = {Proc}, title = {P}}",
+        );
+        assert_eq!(db.preamble(), "\\newcommand{\\x}{}");
+        assert_eq!(db.resolved_field("child", "booktitle"), Some("Proc"));
+        assert_eq!(db.resolved_field("child", "title"), Some("T"));
+        // "@ignored" looks like the start of an entry.
+        assert_eq!(db.errors().len(), 1);
+        assert_eq!(db.errors()[0].line, 2);
+        assert_eq!(db.errors()[0].message, "I was expecting a `{' or a `('");
+    }
+
+    #[test]
+    fn errors_keep_earlier_fields() {
+        let db = parse("@misc{a, title = {T},\n note = \"x}\" }\n@misc{A, title = {U}}\n@misc{b,\n year = 19 99}");
+        let messages: Vec<_> = db
+            .errors()
+            .iter()
+            .map(|e| (e.line, e.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (2, "Unbalanced braces"),
+                (3, "Repeated entry"),
+                (5, "I was expecting a `,' or a `}'"),
+            ]
+        );
+        assert_eq!(db.entry("a").unwrap().get("title"), Some("T"));
+        assert_eq!(db.entry("b").unwrap().get("year"), Some("19"));
+    }
+
+    #[test]
+    fn undefined_and_self_referencing_macros() {
+        let db = parse("@string{s = s # {x}}\n@misc{k, note = nope # s}");
+        let messages: Vec<_> = db.errors().iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "string name \"s\" is used in its own definition",
+                "string name \"nope\" is undefined",
+            ]
+        );
+        assert_eq!(db.entry("k").unwrap().get("note"), Some("x"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut db = parse("@string{j = {J. Foo}}\n@article{k, journal = j, title = \"Old\"}");
+        let e = db.entry_mut("k").unwrap();
+        e.set("title", Value::braced("New"));
+        e.set("year", Value::braced("2020"));
+
+        let written = db.to_string();
+        assert_eq!(
+            written,
+            "@string{j = {J. Foo}}\n\n@article{k,\n  journal = j,\n  title = {New},\n  year = {2020},\n}\n"
+        );
+
+        let again = parse(&written);
+        assert!(again.errors().is_empty());
+        assert_eq!(again.entry("k").unwrap().get("journal"), Some("J. Foo"));
+    }
+}
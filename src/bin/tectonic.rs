@@ -4,8 +4,10 @@
 
 use tectonic;
 
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
+use std::collections::HashSet;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::process;
@@ -14,21 +16,17 @@ use std::str::FromStr;
 use tectonic::config::PersistentConfig;
 use tectonic::driver::{OutputFormat, PassSetting, ProcessingSessionBuilder};
 use tectonic::errors::{ErrorKind, Result};
+use tectonic::font_index::{FontIndex, FontSources};
 use tectonic::io::zipbundle::ZipBundle;
+use tectonic::io::{Bundle, FilesystemIo, IoStack};
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::status::{ChatterLevel, StatusBackend};
+use tectonic::{FontDescription, PdfAConformance, TexEngine};
 
 use tectonic::{ctry, errmsg, tt_error, tt_error_styled, tt_note};
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "Tectonic", about = "Process a (La)TeX document")]
-struct CliOptions {
-    /// The file to process, or "-" to process the standard input stream"
-    #[structopt(name = "input")]
-    input: String,
-    /// The name of the "format" file used to initialize the TeX engine
-    #[structopt(long, short, name = "path", default_value = "latex")]
-    format: String,
+struct BundleOptions {
     /// Use this Zip-format bundle file to find resource files instead of the default
     #[structopt(
         takes_value(true),
@@ -42,14 +40,57 @@ struct CliOptions {
     #[structopt(takes_value(true), long, short, name = "url")]
     // TODO add URL validation
     web_bundle: Option<String>,
-    /// How much chatter to print when running
-    #[structopt(long = "chatter", short, name = "level", default_value = "default", possible_values(&["default", "minimal"]))]
-    chatter_level: String,
     /// Use only resource files cached locally
     #[structopt(short = "C")]
     only_cached: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct FontOptions {
+    /// Where to find fonts requested by name: the host system, or only the bundle and any --font-dir
+    #[structopt(long, name = "source", default_value = "system", possible_values(&["system", "bundle"]))]
+    fonts: String,
+    /// With "--fonts bundle", also use the fonts in <font_dir> and its subdirectories
+    #[structopt(long, name = "font_dir", parse(from_os_str))]
+    font_dir: Option<Vec<PathBuf>>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// List the fonts that documents can request by name, with their files, scripts, features and variation axes
+    #[structopt(name = "list-fonts")]
+    ListFonts {
+        /// Only list the fonts with a name containing <pattern>, ignoring case
+        #[structopt(name = "pattern")]
+        pattern: Option<String>,
+        #[structopt(flatten)]
+        bundle: BundleOptions,
+        /// Also list the fonts in <font_dir> and its subdirectories
+        #[structopt(long, name = "font_dir", parse(from_os_str))]
+        font_dir: Option<Vec<PathBuf>>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "Tectonic",
+    about = "Process a (La)TeX document",
+    setting = AppSettings::SubcommandsNegateReqs
+)]
+struct CliOptions {
+    /// The file to process, or "-" to process the standard input stream"
+    #[structopt(name = "input", required = true)]
+    input: Option<String>,
+    /// The name of the "format" file used to initialize the TeX engine
+    #[structopt(long, short, name = "path", default_value = "latex")]
+    format: String,
+    #[structopt(flatten)]
+    bundle: BundleOptions,
+    /// How much chatter to print when running
+    #[structopt(long = "chatter", short, name = "level", default_value = "default", possible_values(&["default", "minimal"]))]
+    chatter_level: String,
     /// The kind of output to generate
-    #[structopt(long, name = "format", default_value = "pdf", possible_values(&["pdf", "html", "xdv", "aux", "format"]))]
+    #[structopt(long, name = "format", default_value = "pdf", possible_values(&["pdf", "html", "svg", "txt", "png", "xdv", "aux", "format"]))]
     outfmt: String,
     /// Write Makefile-format rules expressing the dependencies of this run to <dest_path>
     #[structopt(long, name = "dest_path")]
@@ -69,6 +110,26 @@ struct CliOptions {
     /// Generate SyncTeX data
     #[structopt(long)]
     synctex: bool,
+    /// With the "txt" format, also write the bounding box of every word as JSON
+    #[structopt(long)]
+    word_boxes: bool,
+    /// With the "png" format, the resolution of the images in pixels per inch [default: 96]
+    #[structopt(long)]
+    dpi: Option<f64>,
+    /// Run BibTeX in Unicode mode, with UTF-8 text handling and Unicode-aware sorting
+    #[structopt(long)]
+    bibtex_unicode: bool,
+    /// Sort the bibliography with the collation rules of <locale>; implies --bibtex-unicode
+    #[structopt(long, name = "locale")]
+    bibtex_locale: Option<String>,
+    /// Produce PDF/A output at this conformance level (1b, 2b or 3b)
+    #[structopt(long, name = "pdfa_level")]
+    pdfa: Option<PdfAConformance>,
+    /// Produce linearized ("fast web view") PDF
+    #[structopt(long)]
+    linearize: bool,
+    #[structopt(flatten)]
+    fonts: FontOptions,
     /// Tell the engine that no file at <hide_path> exists, if it tries to read it
     #[structopt(long, name = "hide_path")]
     hide: Option<Vec<PathBuf>>,
@@ -78,12 +139,182 @@ struct CliOptions {
     /// The directory in which to place output files [default: the directory containing <input>]
     #[structopt(name = "outdir", short, long, parse(from_os_str))]
     outdir: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+impl BundleOptions {
+    fn make_bundle(
+        self,
+        config: &PersistentConfig,
+        status: &mut TermcolorStatusBackend,
+    ) -> Result<Box<dyn Bundle>> {
+        if self.only_cached {
+            tt_note!(status, "using only cached resource files");
+        }
+
+        if let Some(p) = self.bundle {
+            let zb = ctry!(ZipBundle::<File>::open(&p); "error opening bundle");
+            Ok(Box::new(zb))
+        } else if let Some(u) = self.web_bundle {
+            Ok(Box::new(config.make_cached_url_provider(
+                &u,
+                self.only_cached,
+                None,
+                status,
+            )?))
+        } else {
+            config.default_bundle(self.only_cached, status)
+        }
+    }
+}
+
+impl FontOptions {
+    fn font_sources(self) -> Result<FontSources> {
+        let font_dirs = self.font_dir.unwrap_or_default();
+
+        match self.fonts.as_str() {
+            "bundle" if font_dirs.is_empty() => Ok(FontSources::BundleOnly),
+            "bundle" => Ok(FontSources::BundleAndDirectories(font_dirs)),
+            _ if !font_dirs.is_empty() => Err(errmsg!(
+                "--font-dir can only be used with \"--fonts bundle\""
+            )),
+            _ => Ok(FontSources::HostSystem),
+        }
+    }
+}
+
+/// Print every font that the engine can find by name, with any `--fonts`
+/// setting, for debugging "font not found" errors.
+fn list_fonts(
+    pattern: Option<String>,
+    bundle: BundleOptions,
+    font_dirs: Vec<PathBuf>,
+    config: &PersistentConfig,
+    status: &mut TermcolorStatusBackend,
+) -> Result<()> {
+    let mut bundle = bundle.make_bundle(config, status)?;
+    let cache_path = config.format_cache_path()?;
+    let index = FontIndex::for_sources(
+        &FontSources::BundleAndDirectories(font_dirs),
+        Some(&mut *bundle),
+        Some(cache_path.as_path()),
+        status,
+    )?;
+
+    // Like a processing session, look on the filesystem before the bundle.
+    let mut filesystem = FilesystemIo::new(Path::new(""), false, true, HashSet::new());
+    let mut stack = IoStack::new(vec![&mut filesystem, &mut bundle]);
+    let mut engine = TexEngine::new();
+
+    // Indexed fonts are named by absolute path if they came from a font
+    // directory, and by file name if they came from the bundle.
+    let mut fonts: Vec<_> = engine
+        .font_index(index)
+        .list_fonts(&mut stack, status)?
+        .into_iter()
+        .map(|f| {
+            let source = if Path::new(&f.file).is_absolute() {
+                "font directory"
+            } else {
+                "bundle"
+            };
+            (source, f)
+        })
+        .collect();
+
+    fonts.extend(
+        engine
+            .font_index(None)
+            .list_fonts(&mut stack, status)?
+            .into_iter()
+            .map(|f| ("system", f)),
+    );
+
+    if let Some(pattern) = pattern {
+        let pattern = pattern.to_lowercase();
+        fonts.retain(|(_, f)| {
+            [
+                &f.family_name,
+                &f.style_name,
+                &f.full_name,
+                &f.postscript_name,
+            ]
+            .iter()
+            .any(|name| name.to_lowercase().contains(&pattern))
+        });
+    }
+
+    fonts.sort_by(|(_, a), (_, b)| {
+        (&a.family_name, &a.style_name, &a.postscript_name).cmp(&(
+            &b.family_name,
+            &b.style_name,
+            &b.postscript_name,
+        ))
+    });
+
+    for (source, font) in &fonts {
+        print_font(source, font);
+    }
+
+    tt_note!(status, "{} fonts found", fonts.len());
+    Ok(())
 }
+
+fn print_font(source: &str, font: &FontDescription) {
+    fn list(items: &[String]) -> String {
+        if items.is_empty() {
+            "(none)".to_owned()
+        } else {
+            items.join(" ")
+        }
+    }
+
+    println!("{}", font.full_name);
+    println!("    family:     {}", font.family_name);
+    println!("    style:      {}", font.style_name);
+    println!("    PostScript: {}", font.postscript_name);
+    println!("    source:     {}", source);
+
+    if font.index > 0 {
+        println!("    file:       {} (face {})", font.file, font.index);
+    } else {
+        println!("    file:       {}", font.file);
+    }
+
+    println!("    scripts:    {}", list(&font.scripts));
+    println!("    features:   {}", list(&font.features));
+
+    for axis in &font.axes {
+        println!(
+            "    axis:       {} ({}) {} to {}, default {}",
+            axis.tag, axis.name, axis.minimum, axis.maximum, axis.default
+        );
+    }
+
+    println!();
+}
+
 fn inner(
     args: CliOptions,
     config: PersistentConfig,
     status: &mut TermcolorStatusBackend,
 ) -> Result<()> {
+    if let Some(Command::ListFonts {
+        pattern,
+        bundle,
+        font_dir,
+    }) = args.command
+    {
+        return list_fonts(
+            pattern,
+            bundle,
+            font_dir.unwrap_or_default(),
+            &config,
+            status,
+        );
+    }
+
     let mut sess_builder = ProcessingSessionBuilder::default();
     let format_path = args.format;
     sess_builder
@@ -91,10 +322,27 @@ fn inner(
         .keep_logs(args.keep_logs)
         .keep_intermediates(args.keep_intermediates)
         .format_cache_path(config.format_cache_path()?)
-        .synctex(args.synctex);
+        .synctex(args.synctex)
+        .word_boxes(args.word_boxes)
+        .bibtex_unicode(args.bibtex_unicode)
+        .linearize(args.linearize);
 
     sess_builder.output_format(OutputFormat::from_str(&args.outfmt).unwrap());
 
+    if let Some(dpi) = args.dpi {
+        sess_builder.png_dpi(dpi);
+    }
+
+    if let Some(ref locale) = args.bibtex_locale {
+        sess_builder.bibtex_locale(locale);
+    }
+
+    if let Some(level) = args.pdfa {
+        sess_builder.pdfa(level);
+    }
+
+    sess_builder.font_sources(args.fonts.font_sources()?);
+
     let pass = PassSetting::from_str(&args.pass).unwrap();
     sess_builder.pass(pass);
 
@@ -108,7 +356,8 @@ fn inner(
 
     // Input and path setup
 
-    let input_path = args.input;
+    // clap requires the input unless a subcommand was given
+    let input_path = args.input.unwrap();
     if input_path == "-" {
         // Don't provide an input path to the ProcessingSession, so it will default to stdin.
         sess_builder.tex_input_name("texput.tex");
@@ -160,23 +409,7 @@ fn inner(
         }
     }
 
-    let only_cached = args.only_cached;
-    if only_cached {
-        tt_note!(status, "using only cached resource files");
-    }
-    if let Some(p) = args.bundle {
-        let zb = ctry!(ZipBundle::<File>::open(&p); "error opening bundle");
-        sess_builder.bundle(Box::new(zb));
-    } else if let Some(u) = args.web_bundle {
-        sess_builder.bundle(Box::new(config.make_cached_url_provider(
-            &u,
-            only_cached,
-            None,
-            status,
-        )?));
-    } else {
-        sess_builder.bundle(config.default_bundle(only_cached, status)?);
-    }
+    sess_builder.bundle(args.bundle.make_bundle(&config, status)?);
 
     let mut sess = sess_builder.create(status)?;
     let result = sess.run(status);
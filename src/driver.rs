@@ -14,18 +14,25 @@
 use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::str::FromStr;
 
-use crate::digest::DigestData;
-use crate::engines::IoEventBackend;
+use crate::digest::{self, Digest, DigestComputer, DigestData};
+use crate::engines::{
+    BibtexLimits, EngineKind, EngineLimits, IoEventBackend, NoopProgressEventBackend,
+    ProgressEventBackend,
+};
 use crate::errors::{ErrorKind, Result, ResultExt};
-use crate::io::{Bundle, InputOrigin, IoProvider, IoSetup, IoSetupBuilder, OpenResult};
+use crate::font_index::{FontIndex, FontSources};
+use crate::io::{Bundle, InputOrigin, IoProvider, IoSetup, IoSetupBuilder, IoStack, OpenResult};
 use crate::status::StatusBackend;
 use crate::{ctry, errmsg, tt_error, tt_note, tt_warning};
-use crate::{BibtexEngine, Spx2HtmlEngine, TexEngine, TexResult, XdvipdfmxEngine};
+use crate::{
+    BibtexEngine, PdfAConformance, Spx2HtmlEngine, TexEngine, TexResult, Xdv2PngEngine,
+    Xdv2SvgEngine, Xdv2TextEngine, XdvipdfmxEngine,
+};
 use std::result::Result as StdResult;
 
 /// Different patterns with which files may have been accessed by the
@@ -208,6 +215,12 @@ pub enum OutputFormat {
     Xdv,
     /// A '.pdf' file.
     Pdf,
+    /// One '.svg' file per page.
+    Svg,
+    /// A '.txt' file with the text of the document.
+    Text,
+    /// One '.png' image per page.
+    Png,
     /// A '.fmt' file, for initializing the TeX engine.
     Format,
 }
@@ -221,6 +234,9 @@ impl FromStr for OutputFormat {
             "html" => Ok(OutputFormat::Html),
             "xdv" => Ok(OutputFormat::Xdv),
             "pdf" => Ok(OutputFormat::Pdf),
+            "svg" => Ok(OutputFormat::Svg),
+            "txt" => Ok(OutputFormat::Text),
+            "png" => Ok(OutputFormat::Png),
             "fmt" => Ok(OutputFormat::Format),
             _ => Err("unsupported or unknown format"),
         }
@@ -323,6 +339,16 @@ pub struct ProcessingSessionBuilder {
     keep_intermediates: bool,
     keep_logs: bool,
     synctex: bool,
+    word_boxes: bool,
+    png_dpi: Option<f64>,
+    limits: EngineLimits,
+    bibtex_limits: BibtexLimits,
+    bibtex_unicode: bool,
+    bibtex_locale: Option<String>,
+    pdfa: Option<PdfAConformance>,
+    linearize: bool,
+    font_sources: FontSources,
+    progress: Option<Box<dyn ProgressEventBackend>>,
 }
 
 impl ProcessingSessionBuilder {
@@ -461,11 +487,97 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// If set to `true`, the "txt" output format also writes the bounding
+    /// box of every word as JSON.
+    pub fn word_boxes(&mut self, w: bool) -> &mut Self {
+        self.word_boxes = w;
+        self
+    }
+
+    /// Sets the resolution of the images produced by the "png" output
+    /// format, in pixels per inch.
+    pub fn png_dpi(&mut self, dpi: f64) -> &mut Self {
+        self.png_dpi = Some(dpi);
+        self
+    }
+
+    /// Sets cancellation and resource limits for the TeX and xdvipdfmx
+    /// passes. If a limit is hit, [`ProcessingSession::run`] returns an error
+    /// of kind `ErrorKind::Cancelled` or `ErrorKind::LimitExceeded`.
+    pub fn limits(&mut self, l: EngineLimits) -> &mut Self {
+        self.limits = l;
+        self
+    }
+
+    /// Bounds the sizes that BibTeX's internal tables may grow to. By default
+    /// they grow as far as the bibliography requires.
+    pub fn bibtex_limits(&mut self, l: BibtexLimits) -> &mut Self {
+        self.bibtex_limits = l;
+        self
+    }
+
+    /// Runs BibTeX in Unicode mode, in which it works with UTF-8 text and
+    /// sorts entries with a Unicode collation. See
+    /// [`BibtexEngine::with_unicode`].
+    pub fn bibtex_unicode(&mut self, unicode: bool) -> &mut Self {
+        self.bibtex_unicode = unicode;
+        self
+    }
+
+    /// Sets the locale whose collation rules BibTeX uses to sort entries.
+    /// This implies Unicode mode.
+    pub fn bibtex_locale(&mut self, locale: &str) -> &mut Self {
+        self.bibtex_locale = Some(locale.to_owned());
+        self
+    }
+
+    /// Makes xdvipdfmx produce PDF/A output at the given conformance level.
+    /// See [`XdvipdfmxEngine::with_pdfa`].
+    pub fn pdfa(&mut self, level: PdfAConformance) -> &mut Self {
+        self.pdfa = Some(level);
+        self
+    }
+
+    /// Makes xdvipdfmx produce linearized ("fast web view") PDF. See
+    /// [`XdvipdfmxEngine::with_linearize`].
+    pub fn linearize(&mut self, linearize: bool) -> &mut Self {
+        self.linearize = linearize;
+        self
+    }
+
+    /// Sets where the TeX engine looks for fonts that documents request by
+    /// name. By default it uses the fonts installed on the host system,
+    /// which makes the output depend on the machine; the other settings
+    /// resolve names from an index of the bundle fonts and, optionally, of
+    /// some local directories.
+    pub fn font_sources(&mut self, s: FontSources) -> &mut Self {
+        self.font_sources = s;
+        self
+    }
+
+    /// Sets a backend that will be told about the progress of the session:
+    /// each engine pass as it starts and ends, each page produced, and each
+    /// file opened. By default, progress events are discarded.
+    pub fn progress_events(&mut self, p: Box<dyn ProgressEventBackend>) -> &mut Self {
+        self.progress = Some(p);
+        self
+    }
+
     /// Creates a `ProcessingSession`.
     pub fn create(self, status: &mut dyn StatusBackend) -> Result<ProcessingSession> {
+        let mut bundle = self.bundle.expect("a bundle must be specified");
+
+        // The bundle part of the font index is cached alongside the formats,
+        // since both depend on nothing but the bundle contents.
+        let font_index = FontIndex::for_sources(
+            &self.font_sources,
+            Some(&mut *bundle),
+            self.format_cache_path.as_deref(),
+            status,
+        )?;
+
         let mut io = IoSetupBuilder::default();
-        io.bundle(self.bundle.expect("a bundle must be specified"))
-            .use_genuine_stdout(self.print_stdout);
+        io.bundle(bundle).use_genuine_stdout(self.print_stdout);
         for p in &self.hidden_input_paths {
             io.hide_path(p);
         }
@@ -548,6 +660,19 @@ impl ProcessingSessionBuilder {
             keep_logs: self.keep_logs,
             noted_tex_warnings: false,
             synctex_enabled: self.synctex,
+            word_boxes: self.word_boxes,
+            png_dpi: self.png_dpi,
+            limits: self.limits,
+            bibtex_limits: self.bibtex_limits,
+            bibtex_unicode: self.bibtex_unicode,
+            bibtex_locale: self.bibtex_locale,
+            bibtex_aux_digests: HashMap::new(),
+            pdfa: self.pdfa,
+            linearize: self.linearize,
+            font_index,
+            progress: self
+                .progress
+                .unwrap_or_else(|| Box::new(NoopProgressEventBackend::new())),
         })
     }
 }
@@ -604,6 +729,25 @@ pub struct ProcessingSession {
     keep_logs: bool,
     noted_tex_warnings: bool,
     synctex_enabled: bool,
+    word_boxes: bool,
+    png_dpi: Option<f64>,
+    limits: EngineLimits,
+    bibtex_limits: BibtexLimits,
+    bibtex_unicode: bool,
+    bibtex_locale: Option<String>,
+    pdfa: Option<PdfAConformance>,
+    linearize: bool,
+
+    /// If set, the fonts that the TeX engine may use; otherwise it uses the
+    /// host fonts.
+    font_index: Option<FontIndex>,
+
+    /// For each `.aux` file that BibTeX has processed, the digest of its
+    /// contents at the time, so that we know when BibTeX needs to run on it
+    /// again.
+    bibtex_aux_digests: HashMap<OsString, DigestData>,
+
+    progress: Box<dyn ProgressEventBackend>,
 }
 
 const DEFAULT_MAX_TEX_PASSES: usize = 6;
@@ -637,7 +781,7 @@ impl ProcessingSession {
                 };
 
                 if file_changed {
-                    return Some(name.to_string_lossy().into_owned());
+                    return Some(format!("\"{}\" changed", name.to_string_lossy()));
                 }
             }
         }
@@ -676,7 +820,7 @@ impl ProcessingSession {
     ///
     /// - if a `.fmt` file does not yet exist, generate one and cache it
     /// - run the TeX engine once
-    /// - run BibTeX, if it seems to be required
+    /// - run BibTeX on each `.aux` file that seems to require it
     /// - repeat the last two steps as often as needed
     /// - write the output files to disk, including a Makefile if it was requested.
     pub fn run<S: StatusBackend>(&mut self, status: &mut S) -> Result<()> {
@@ -890,16 +1034,17 @@ impl ProcessingSession {
         // then go ahead.
 
         let mut rerun_result = if bibtex_first {
-            self.bibtex_pass(status)?;
-            Some(String::new())
+            let aux = self.tex_aux_path.clone();
+            let digest = self.bibtex_aux_digest(&aux, status);
+            self.bibtex_aux_digests.insert(aux.clone(), digest);
+            self.bibtex_pass(&aux, status)?;
+            Some("bibtex was run".to_owned())
         } else {
             self.tex_pass(None, status)?;
 
-            if self.use_bibtex() {
-                self.bibtex_pass(status)?;
-                Some(String::new())
-            } else {
-                self.rerun_needed(status)
+            match self.bibtex_passes(status)? {
+                Some(explanation) => Some(explanation),
+                None => self.rerun_needed(status),
             }
         };
 
@@ -915,13 +1060,7 @@ impl ProcessingSession {
                 "I was told to".to_owned()
             } else {
                 match rerun_result {
-                    Some(ref s) => {
-                        if s == "" {
-                            "bibtex was run".to_owned()
-                        } else {
-                            format!("\"{}\" changed", s)
-                        }
-                    }
+                    Some(ref s) => s.clone(),
                     None => {
                         break;
                     }
@@ -940,8 +1079,16 @@ impl ProcessingSession {
 
             self.tex_pass(Some(&rerun_explanation), status)?;
 
+            // Packages like `bibunits` and `chapterbib` may only write out
+            // some of their `.aux` files, or change their citations, on a
+            // later pass, so BibTeX may need to run again.
+            let bibtex_result = self.bibtex_passes(status)?;
+
             if !reruns_fixed {
-                rerun_result = self.rerun_needed(status);
+                rerun_result = match bibtex_result {
+                    Some(explanation) => Some(explanation),
+                    None => self.rerun_needed(status),
+                };
 
                 if rerun_result.is_some() && i == DEFAULT_MAX_TEX_PASSES - 1 {
                     tt_warning!(
@@ -954,25 +1101,31 @@ impl ProcessingSession {
             }
         }
 
-        // And finally, xdvipdfmx or spx2html. Maybe.
+        // And finally, xdvipdfmx or one of the pure-Rust converters. Maybe.
 
         if let OutputFormat::Pdf = self.output_format {
             self.xdvipdfmx_pass(status)?;
         } else if let OutputFormat::Html = self.output_format {
             self.spx2html_pass(status)?;
+        } else if let OutputFormat::Svg = self.output_format {
+            self.xdv2svg_pass(status)?;
+        } else if let OutputFormat::Text = self.output_format {
+            self.xdv2text_pass(status)?;
+        } else if let OutputFormat::Png = self.output_format {
+            self.xdv2png_pass(status)?;
         }
 
         Ok(0)
     }
 
-    fn use_bibtex(&self) -> bool {
+    fn use_bibtex(&self, aux: &OsStr) -> bool {
         const BIBDATA: &[u8] = b"\\bibdata";
 
         self.io
             .mem
             .files
             .borrow()
-            .get(&self.tex_aux_path)
+            .get(aux)
             .map(|data| {
                 // We used to use aho-corasick crate here, but it was removed to reduce the code
                 // size.
@@ -981,6 +1134,84 @@ impl ProcessingSession {
             .unwrap_or(false)
     }
 
+    /// Find the `.aux` files that BibTeX needs to process, along with the
+    /// digests of what BibTeX will read from them: those that the TeX engine
+    /// has written, that contain a `\bibdata` command, and whose BibTeX
+    /// commands have changed since BibTeX last ran on them. Besides the
+    /// primary `.aux` file, packages like `chapterbib`, `multibib` and
+    /// `bibunits` write additional ones. The primary file comes first; the
+    /// rest are sorted by name so that runs are reproducible.
+    fn aux_files_needing_bibtex(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Vec<(OsString, DigestData)> {
+        let mut candidates: Vec<OsString> = self
+            .events
+            .0
+            .iter()
+            .filter(|(name, info)| {
+                Path::new(name).extension() == Some(OsStr::new("aux"))
+                    && info.access_pattern != AccessPattern::Read
+                    && info.write_digest.is_some()
+                    && self.use_bibtex(name)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let primary = &self.tex_aux_path;
+        candidates.sort_by(|a, b| (a != primary, a).cmp(&(b != primary, b)));
+
+        let mut auxes = Vec::new();
+
+        for aux in candidates {
+            let digest = self.bibtex_aux_digest(&aux, status);
+
+            if self.bibtex_aux_digests.get(&aux) != Some(&digest) {
+                auxes.push((aux, digest));
+            }
+        }
+
+        auxes
+    }
+
+    /// Compute a digest of what BibTeX reads from the `.aux` file `aux`: its
+    /// `\citation`, `\bibdata` and `\bibstyle` lines, and those of the
+    /// files that it pulls in with `\@input`, as `\include` does. The rest
+    /// of an `.aux` file tends to change on every TeX pass, and doesn't
+    /// matter to BibTeX.
+    fn bibtex_aux_digest(&mut self, aux: &OsStr, status: &mut dyn StatusBackend) -> DigestData {
+        let mut dc = digest::create();
+        let mut reader = BibtexAuxReader {
+            max_depth: self.bibtex_limits.aux_depth(),
+            seen: HashSet::new(),
+        };
+        reader.digest(&mut self.io.as_stack(), aux, &mut dc, 0, status);
+        DigestData::from(dc)
+    }
+
+    /// Run BibTeX on every `.aux` file that needs it. Returns the reason to
+    /// rerun TeX if BibTeX was run at all.
+    fn bibtex_passes<S: StatusBackend>(&mut self, status: &mut S) -> Result<Option<String>> {
+        let auxes = self.aux_files_needing_bibtex(status);
+
+        for (aux, digest) in &auxes {
+            self.bibtex_aux_digests.insert(aux.clone(), *digest);
+            self.bibtex_pass(aux, status)?;
+        }
+
+        Ok(match auxes.len() {
+            0 => None,
+            1 if auxes[0].0 == self.tex_aux_path => Some("bibtex was run".to_owned()),
+            _ => {
+                let names: Vec<_> = auxes
+                    .iter()
+                    .map(|(aux, _)| format!("\"{}\"", aux.to_string_lossy()))
+                    .collect();
+                Some(format!("bibtex was run on {}", names.join(", ")))
+            }
+        })
+    }
+
     /// Use the TeX engine to generate a format file.
     fn make_format_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
         if self.io.bundle.is_none() {
@@ -1015,6 +1246,7 @@ impl ProcessingSession {
             TexEngine::new()
                 .halt_on_error_mode(true)
                 .initex_mode(true)
+                .font_index(self.font_index.clone())
                 .process(&mut stack, &mut self.events, status, "UNUSED.fmt", "texput")
         };
 
@@ -1072,20 +1304,26 @@ impl ProcessingSession {
             } else {
                 status.note_highlighted("Running ", "TeX", " ...");
             }
+            self.progress
+                .pass_started(EngineKind::Tex, rerun_explanation);
 
             TexEngine::new()
                 .halt_on_error_mode(true)
                 .initex_mode(self.output_format == OutputFormat::Format)
                 .synctex(self.synctex_enabled)
                 .semantic_pagination(self.output_format == OutputFormat::Html)
-                .process(
+                .limits(self.limits.clone())
+                .font_index(self.font_index.clone())
+                .process_with_progress(
                     &mut stack,
                     &mut self.events,
+                    &mut *self.progress,
                     status,
                     &self.format_name,
                     &self.primary_input_tex_path,
                 )
         };
+        self.progress.pass_finished(EngineKind::Tex, result.is_ok());
 
         match result {
             Ok(TexResult::Spotless) => {}
@@ -1108,6 +1346,9 @@ impl ProcessingSession {
                 }
             }
             Err(e) => {
+                if let ErrorKind::Cancelled | ErrorKind::LimitExceeded(_) = *e.kind() {
+                    return Err(e);
+                }
                 return Err(e.chain_err(|| ErrorKind::EngineError("TeX")));
             }
         }
@@ -1115,18 +1356,40 @@ impl ProcessingSession {
         Ok(0)
     }
 
-    fn bibtex_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
-        let result = {
+    fn bibtex_pass<S: StatusBackend>(&mut self, aux: &OsStr, status: &mut S) -> Result<i32> {
+        let (result, diagnostics) = {
             let mut stack = self.io.as_stack();
-            let mut engine = BibtexEngine::new();
-            status.note_highlighted("Running ", "BibTeX", " ...");
-            engine.process(
+            let mut engine = BibtexEngine::new()
+                .with_limits(self.bibtex_limits)
+                .with_unicode(self.bibtex_unicode);
+            if let Some(ref locale) = self.bibtex_locale {
+                engine = engine.with_locale(locale);
+            }
+            if aux == self.tex_aux_path {
+                status.note_highlighted("Running ", "BibTeX", " ...");
+            } else {
+                status.note_highlighted(
+                    "Running ",
+                    "BibTeX",
+                    &format!(" on {} ...", aux.to_string_lossy()),
+                );
+            }
+            self.progress.pass_started(EngineKind::Bibtex, None);
+            let result = engine.process_with_progress(
                 &mut stack,
                 &mut self.events,
+                &mut *self.progress,
                 status,
-                &self.tex_aux_path.to_str().unwrap(),
-            )
+                &aux.to_string_lossy(),
+            );
+            (result, engine.diagnostics().to_vec())
         };
+        self.progress
+            .pass_finished(EngineKind::Bibtex, result.is_ok());
+
+        for diag in &diagnostics {
+            status.report_bibtex_diagnostic(diag);
+        }
 
         match result {
             Ok(TexResult::Spotless) => {}
@@ -1152,35 +1415,115 @@ impl ProcessingSession {
     }
 
     fn xdvipdfmx_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
-        {
+        let result = {
             let mut stack = self.io.as_stack();
-            let mut engine = XdvipdfmxEngine::new();
+            let mut engine = XdvipdfmxEngine::new()
+                .with_pdfa(self.pdfa)
+                .with_linearize(self.linearize)
+                .with_limits(self.limits.clone());
             status.note_highlighted("Running ", "xdvipdfmx", " ...");
-            engine.process(
+            self.progress.pass_started(EngineKind::Xdvipdfmx, None);
+            engine.process_with_progress(
                 &mut stack,
                 &mut self.events,
+                &mut *self.progress,
                 status,
                 &self.tex_xdv_path.to_str().unwrap(),
                 &self.tex_pdf_path.to_str().unwrap(),
-            )?;
-        }
+            )
+        };
+        self.progress
+            .pass_finished(EngineKind::Xdvipdfmx, result.is_ok());
+        result?;
 
         self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
         Ok(0)
     }
 
     fn spx2html_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
-        {
+        let result = {
             let mut stack = self.io.as_stack();
             let mut engine = Spx2HtmlEngine::new();
             status.note_highlighted("Running ", "spx2html", " ...");
+            self.progress.pass_started(EngineKind::Spx2Html, None);
             engine.process(
                 &mut stack,
                 &mut self.events,
                 status,
                 &self.tex_xdv_path.to_str().unwrap(),
-            )?;
-        }
+            )
+        };
+        self.progress
+            .pass_finished(EngineKind::Spx2Html, result.is_ok());
+        result?;
+
+        self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
+        Ok(0)
+    }
+
+    fn xdv2svg_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
+        let result = {
+            let mut stack = self.io.as_stack();
+            let mut engine = Xdv2SvgEngine::new();
+            status.note_highlighted("Running ", "xdv2svg", " ...");
+            self.progress.pass_started(EngineKind::Xdv2Svg, None);
+            engine.process(
+                &mut stack,
+                &mut self.events,
+                status,
+                &self.tex_xdv_path.to_str().unwrap(),
+            )
+        };
+        self.progress
+            .pass_finished(EngineKind::Xdv2Svg, result.is_ok());
+        result?;
+
+        self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
+        Ok(0)
+    }
+
+    fn xdv2text_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
+        let result = {
+            let mut stack = self.io.as_stack();
+            let mut engine = Xdv2TextEngine::new().with_word_boxes(self.word_boxes);
+            status.note_highlighted("Running ", "xdv2text", " ...");
+            self.progress.pass_started(EngineKind::Xdv2Text, None);
+            engine.process(
+                &mut stack,
+                &mut self.events,
+                status,
+                &self.tex_xdv_path.to_str().unwrap(),
+            )
+        };
+        self.progress
+            .pass_finished(EngineKind::Xdv2Text, result.is_ok());
+        result?;
+
+        self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
+        Ok(0)
+    }
+
+    fn xdv2png_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
+        let result = {
+            let mut stack = self.io.as_stack();
+            let mut engine = Xdv2PngEngine::new();
+
+            if let Some(dpi) = self.png_dpi {
+                engine = engine.with_dpi(dpi);
+            }
+
+            status.note_highlighted("Running ", "xdv2png", " ...");
+            self.progress.pass_started(EngineKind::Xdv2Png, None);
+            engine.process(
+                &mut stack,
+                &mut self.events,
+                status,
+                &self.tex_xdv_path.to_str().unwrap(),
+            )
+        };
+        self.progress
+            .pass_finished(EngineKind::Xdv2Png, result.is_ok());
+        result?;
 
         self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
         Ok(0)
@@ -1202,3 +1545,60 @@ impl ProcessingSession {
             .into_inner()
     }
 }
+
+/// Follows the `\@input` commands of `.aux` files the way BibTeX does, so
+/// that a digest covers the same files that BibTeX reads.
+struct BibtexAuxReader {
+    /// BibTeX gives up on `.aux` files nested more deeply than this; it's
+    /// set by the session's `BibtexLimits`.
+    max_depth: usize,
+    /// BibTeX reads each `.aux` file name at most once.
+    seen: HashSet<OsString>,
+}
+
+impl BibtexAuxReader {
+    /// Feed the lines of the `.aux` file `name` that BibTeX acts on into
+    /// `dc`, descending into the files it names with `\@input`, in the order
+    /// in which BibTeX reads them. Files that can't be opened contribute
+    /// nothing.
+    fn digest(
+        &mut self,
+        io: &mut IoStack,
+        name: &OsStr,
+        dc: &mut DigestComputer,
+        depth: usize,
+        status: &mut dyn StatusBackend,
+    ) {
+        const COMMANDS: &[&[u8]] = &[b"\\citation{", b"\\bibdata{", b"\\bibstyle{"];
+        const INPUT: &[u8] = b"\\@input{";
+
+        if depth > self.max_depth || !self.seen.insert(name.to_owned()) {
+            return;
+        }
+
+        let mut data = Vec::new();
+
+        match io.input_open_name(name, status) {
+            OpenResult::Ok(mut ih) => {
+                if ih.read_to_end(&mut data).is_err() {
+                    return;
+                }
+            }
+            _ => return,
+        }
+
+        for line in data.split(|b| *b == b'\n') {
+            if COMMANDS.iter().any(|c| line.starts_with(c)) {
+                dc.input(line);
+                dc.input(b"\n");
+            } else if line.starts_with(INPUT) {
+                let rest = &line[INPUT.len()..];
+
+                if let Some(end) = rest.iter().position(|b| *b == b'}') {
+                    let nested = String::from_utf8_lossy(&rest[..end]).into_owned();
+                    self.digest(io, OsStr::new(&nested), dc, depth + 1, status);
+                }
+            }
+        }
+    }
+}
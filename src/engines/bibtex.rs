@@ -3,37 +3,279 @@
 // Licensed under the MIT License.
 
 use std::ffi::{CStr, CString};
+use std::fmt;
+use std::ptr;
+
+use tectonic_engine::{bibtex_diagnostic_kind_t, bibtex_diagnostic_t};
 
 use super::tex::TexResult;
-use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
+use super::{
+    EngineKind, ExecutionState, IoEventBackend, NoopProgressEventBackend, ProgressEventBackend,
+    TectonicBridgeApi,
+};
 use crate::errors::{ErrorKind, Result};
 use crate::io::IoStack;
-use crate::status::StatusBackend;
+use crate::status::{MessageKind, StatusBackend};
+
+/// Upper bounds on the sizes of BibTeX's internal tables.
+///
+/// BibTeX starts each of its tables at the classic size and enlarges it
+/// whenever a database or style file needs more room, so by default a run is
+/// only limited by available memory. Setting a bound makes the run stop with
+/// BibTeX's usual "Sorry---you've exceeded BibTeX's ..." message instead,
+/// which `process` reports as a fatal error. A bound smaller than the classic
+/// size of a table just keeps that table from growing. The one bound that is
+/// always set is the nesting depth of auxiliary files, which defaults to the
+/// classic 20 levels.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BibtexLimits(tectonic_engine::bibtex_limits_t);
+
+impl BibtexLimits {
+    pub fn new() -> BibtexLimits {
+        Default::default()
+    }
+
+    /// Limit the string pool, which holds the text of every string, to `n`
+    /// bytes.
+    pub fn max_pool_size(&mut self, n: usize) -> &mut Self {
+        self.0.pool_size = Some(n);
+        self
+    }
+
+    /// Limit the number of distinct strings, and with it the size of the
+    /// hash table, to `n`.
+    pub fn max_strings(&mut self, n: usize) -> &mut Self {
+        self.0.max_strings = Some(n);
+        self
+    }
+
+    /// Limit the line buffers to `n` bytes each. A `.bib` entry has to fit
+    /// in one buffer.
+    pub fn max_buffer_size(&mut self, n: usize) -> &mut Self {
+        self.0.buf_size = Some(n);
+        self
+    }
+
+    /// Limit the number of cited entries to `n`.
+    pub fn max_cites(&mut self, n: usize) -> &mut Self {
+        self.0.max_cites = Some(n);
+        self
+    }
+
+    /// Limit the number of entry fields, summed over all entries, to `n`.
+    pub fn max_fields(&mut self, n: usize) -> &mut Self {
+        self.0.max_fields = Some(n);
+        self
+    }
+
+    /// Limit the space for the style file's compiled functions to `n`
+    /// entries.
+    pub fn max_wiz_fn_space(&mut self, n: usize) -> &mut Self {
+        self.0.wiz_fn_space = Some(n);
+        self
+    }
+
+    /// Limit global string variables to `n` characters. Longer values are
+    /// truncated with a warning, as in classic BibTeX, rather than stopping
+    /// the run.
+    pub fn max_global_string_size(&mut self, n: usize) -> &mut Self {
+        self.0.glob_str_size = Some(n);
+        self
+    }
+
+    /// Limit the nesting of auxiliary files read with `\@input` to `n`
+    /// levels below the top-level file, instead of the classic 20.
+    pub fn max_aux_depth(&mut self, n: usize) -> &mut Self {
+        self.0.aux_depth = Some(n);
+        self
+    }
+
+    /// The deepest level of `\@input` nesting that BibTeX will read with
+    /// these limits.
+    pub(crate) fn aux_depth(&self) -> usize {
+        self.0.max_aux_depth()
+    }
+}
+
+/// What a [`BibtexDiagnostic`] is about.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BibtexDiagnosticKind {
+    /// A cited key has no entry in any database file: "I didn't find a
+    /// database entry for ...".
+    MissingEntry,
+    /// An entry's `crossref` field names an entry that doesn't exist.
+    BadCrossReference,
+    /// An entry cross-references an entry that has a `crossref` of its own.
+    NestedCrossReference,
+    /// A problem in a database (`.bib`) file, such as a syntax error or a
+    /// repeated field.
+    Database,
+    /// A problem in the style (`.bst`) file, or one found while executing
+    /// it.
+    Style,
+    /// A message from the style's `warning$` function. The standard styles
+    /// use these to report missing or empty fields.
+    StyleWarning,
+    /// A problem in an auxiliary (`.aux`) file.
+    Aux,
+}
+
+/// A warning or error issued by BibTeX. These report the same problems as
+/// the `.blg` log, but with the entry, field and location broken out.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BibtexDiagnostic {
+    /// What the diagnostic is about.
+    pub kind: BibtexDiagnosticKind,
+    /// Either `MessageKind::Warning` or `MessageKind::Error`.
+    pub severity: MessageKind,
+    /// The message, without the location that BibTeX logs alongside it.
+    pub message: String,
+    /// The cite key of the entry concerned, if any.
+    pub entry: Option<String>,
+    /// The name of the field concerned, if any.
+    pub field: Option<String>,
+    /// The `.bib`, `.bst` or `.aux` file in which the problem was found.
+    pub file: Option<String>,
+    /// The line of `file` on which the problem was found.
+    pub line: Option<usize>,
+}
+
+impl From<bibtex_diagnostic_t> for BibtexDiagnostic {
+    fn from(d: bibtex_diagnostic_t) -> Self {
+        let kind = match d.kind {
+            bibtex_diagnostic_kind_t::MISSING_ENTRY => BibtexDiagnosticKind::MissingEntry,
+            bibtex_diagnostic_kind_t::BAD_CROSS_REFERENCE => {
+                BibtexDiagnosticKind::BadCrossReference
+            }
+            bibtex_diagnostic_kind_t::NESTED_CROSS_REFERENCE => {
+                BibtexDiagnosticKind::NestedCrossReference
+            }
+            bibtex_diagnostic_kind_t::DATABASE => BibtexDiagnosticKind::Database,
+            bibtex_diagnostic_kind_t::STYLE => BibtexDiagnosticKind::Style,
+            bibtex_diagnostic_kind_t::STYLE_WARNING => BibtexDiagnosticKind::StyleWarning,
+            bibtex_diagnostic_kind_t::AUX => BibtexDiagnosticKind::Aux,
+        };
+
+        BibtexDiagnostic {
+            kind,
+            severity: if d.is_error {
+                MessageKind::Error
+            } else {
+                MessageKind::Warning
+            },
+            message: d.message,
+            entry: d.entry,
+            field: d.field,
+            file: d.file,
+            line: d.line,
+        }
+    }
+}
+
+impl fmt::Display for BibtexDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: ", file, line)?,
+            (Some(file), None) => write!(f, "{}: ", file)?,
+            _ => {}
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
 
 #[derive(Default)]
-pub struct BibtexEngine {}
+pub struct BibtexEngine {
+    limits: BibtexLimits,
+    unicode: bool,
+    locale: String,
+    diagnostics: Vec<BibtexDiagnostic>,
+}
 
 impl BibtexEngine {
     pub fn new() -> BibtexEngine {
         Default::default()
     }
 
+    /// Bound the sizes that BibTeX's tables may grow to during a run.
+    pub fn with_limits(mut self, limits: BibtexLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Run BibTeX in Unicode mode, like `bibtexu`. Input is decoded as UTF-8;
+    /// `text.length$`, `substring$` and `text.prefix$` count grapheme clusters
+    /// rather than bytes; `change.case$` and `purify$` handle non-ASCII
+    /// letters; and entries are sorted with a Unicode collation. Classic
+    /// byte-oriented behavior is the default.
+    pub fn with_unicode(mut self, unicode: bool) -> Self {
+        self.unicode = unicode;
+        self
+    }
+
+    /// Sort entries with the collation rules of the given BCP 47 locale,
+    /// such as `"sv"` or `"de-u-co-phonebk"`. This implies Unicode mode. An
+    /// unknown locale falls back to the root collation with a warning.
+    pub fn with_locale(mut self, locale: &str) -> Self {
+        self.unicode = true;
+        self.locale = locale.to_owned();
+        self
+    }
+
+    /// The warnings and errors issued during the most recent call to
+    /// `process`, in the order that BibTeX issued them.
+    pub fn diagnostics(&self) -> &[BibtexDiagnostic] {
+        &self.diagnostics
+    }
+
     pub fn process(
         &mut self,
         io: &mut IoStack,
         events: &mut dyn IoEventBackend,
         status: &mut dyn StatusBackend,
         aux: &str,
+    ) -> Result<TexResult> {
+        self.process_with_progress(
+            io,
+            events,
+            &mut NoopProgressEventBackend::new(),
+            status,
+            aux,
+        )
+    }
+
+    /// Like `process`, but also reports the files opened to a
+    /// ProgressEventBackend.
+    pub fn process_with_progress(
+        &mut self,
+        io: &mut IoStack,
+        events: &mut dyn IoEventBackend,
+        progress: &mut dyn ProgressEventBackend,
+        status: &mut dyn StatusBackend,
+        aux: &str,
     ) -> Result<TexResult> {
         let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
 
         let caux = CString::new(aux)?;
+        let clocale = CString::new(self.locale.as_str())?;
+        let locale_ptr = if self.unicode {
+            clocale.as_ptr()
+        } else {
+            ptr::null()
+        };
 
-        let /*mut*/ state = ExecutionState::new(io, events, status);
+        let /*mut*/ state = ExecutionState::new(io, EngineKind::Bibtex, events, progress, status);
         let bridge = TectonicBridgeApi::new(&state);
 
         unsafe {
-            match super::bibtex_simple_main(&*bridge, caux.as_ptr()) {
+            let history =
+                super::bibtex_simple_main(&*bridge, caux.as_ptr(), self.limits.0, locale_ptr);
+            self.diagnostics = tectonic_engine::bibtex_take_diagnostics()
+                .into_iter()
+                .map(BibtexDiagnostic::from)
+                .collect();
+
+            match history {
                 0 => Ok(TexResult::Spotless),
                 1 => Ok(TexResult::Warnings),
                 2 => Ok(TexResult::Errors),
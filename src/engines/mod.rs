@@ -19,10 +19,12 @@ use libc;
 use md5::{Digest, Md5};
 use std::borrow::Cow;
 use std::ffi::{CStr, OsStr, OsString};
-use std::io::{Read, SeekFrom, Write};
+use std::io::{Cursor, Read, SeekFrom, Write};
 use std::path::Path;
-use std::sync::Mutex;
-use std::{io, ptr, slice};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, io, ptr, slice};
 
 use crate::digest::DigestData;
 use crate::errors::{Error, ErrorKind, Result};
@@ -35,12 +37,18 @@ use crate::{tt_error, tt_warning};
 pub mod bibtex;
 pub mod spx2html;
 pub mod tex;
+pub mod xdv2png;
+pub mod xdv2svg;
+pub mod xdv2text;
 pub mod xdvipdfmx;
 
-pub use self::bibtex::BibtexEngine;
+pub use self::bibtex::{BibtexDiagnostic, BibtexDiagnosticKind, BibtexEngine, BibtexLimits};
 pub use self::spx2html::Spx2HtmlEngine;
 pub use self::tex::TexEngine;
-pub use self::xdvipdfmx::XdvipdfmxEngine;
+pub use self::xdv2png::Xdv2PngEngine;
+pub use self::xdv2svg::Xdv2SvgEngine;
+pub use self::xdv2text::Xdv2TextEngine;
+pub use self::xdvipdfmx::{PdfAConformance, XdvipdfmxEngine};
 
 #[cfg(unix)]
 fn osstr_from_cstr(s: &CStr) -> Cow<OsStr> {
@@ -106,6 +114,200 @@ impl NoopIoEventBackend {
 
 impl IoEventBackend for NoopIoEventBackend {}
 
+/// The different engines that make up a processing session.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EngineKind {
+    Tex,
+    Bibtex,
+    Xdvipdfmx,
+    Spx2Html,
+    Xdv2Svg,
+    Xdv2Text,
+    Xdv2Png,
+}
+
+/// The ProgressEventBackend trait allows the program driving the engines to
+/// follow the progress of a long build: which engine is running and why,
+/// which pages have been produced so far, and which files are being opened.
+/// Unlike IoEventBackend, it is purely informational and does not affect
+/// the rerun logic.
+///
+/// All of the trait methods have default implementations that do nothing.
+pub trait ProgressEventBackend {
+    /// This function is called when a pass of an engine begins. If the pass
+    /// is a rerun, `rerun_reason` explains why it is being rerun.
+    fn pass_started(&mut self, _engine: EngineKind, _rerun_reason: Option<&str>) {}
+
+    /// This function is called when a pass of an engine ends, whether or not
+    /// it succeeded.
+    fn pass_finished(&mut self, _engine: EngineKind, _succeeded: bool) {}
+
+    /// This function is called when the TeX engine ships out a page. The
+    /// argument is the value of `\count0` at the time, which is usually the
+    /// page number as printed on the page.
+    fn page_shipped_out(&mut self, _count0: i32) {}
+
+    /// This function is called when xdvipdfmx has finished writing a page.
+    /// The argument is the 1-based index of the page in the XDV file.
+    fn page_written(&mut self, _page_number: usize) {}
+
+    /// This function is called when an engine opens a file, for either input
+    /// or output.
+    fn file_opened(&mut self, _name: &OsStr, _is_output: bool) {}
+}
+
+/// This struct implements the ProgressEventBackend trait but does nothing.
+#[derive(Default)]
+pub struct NoopProgressEventBackend {}
+
+impl NoopProgressEventBackend {
+    pub fn new() -> NoopProgressEventBackend {
+        Default::default()
+    }
+}
+
+impl ProgressEventBackend for NoopProgressEventBackend {}
+
+/// A handle that can be used to stop a running engine from another thread.
+///
+/// Clones of a token share the same underlying flag, so a caller can hand
+/// one copy to an engine via [`EngineLimits`] and keep another to call
+/// [`CancellationToken::cancel`] on. The engines poll the flag from their
+/// main loops; once it is set, the engine unwinds and its `process` method
+/// returns an error of kind `ErrorKind::Cancelled`.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        Default::default()
+    }
+
+    /// Ask any engine using this token to stop as soon as possible.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true if `cancel` has been called on this token or any of its
+    /// clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Which resource limit caused an engine to be stopped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ResourceLimit {
+    /// The wall-clock deadline passed.
+    WallTime,
+
+    /// The engine tried to emit more pages than allowed.
+    Pages,
+
+    /// The engine tried to write more bytes of output than allowed.
+    OutputBytes,
+}
+
+impl fmt::Display for ResourceLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResourceLimit::WallTime => write!(f, "wall time"),
+            ResourceLimit::Pages => write!(f, "output pages"),
+            ResourceLimit::OutputBytes => write!(f, "output bytes"),
+        }
+    }
+}
+
+/// Cancellation and resource limits for an engine run.
+///
+/// The page and output-byte limits apply separately to each invocation of an
+/// engine's `process` method. The deadline is an absolute point in time, so
+/// a single `EngineLimits` value can be shared by all of the passes of a
+/// processing session to impose one overall time budget. If a limit is
+/// exceeded, `process` returns an error of kind `ErrorKind::LimitExceeded`.
+#[derive(Clone, Debug, Default)]
+pub struct EngineLimits {
+    cancellation: Option<CancellationToken>,
+    deadline: Option<Instant>,
+    max_pages: Option<usize>,
+    max_output_bytes: Option<usize>,
+}
+
+impl EngineLimits {
+    pub fn new() -> EngineLimits {
+        Default::default()
+    }
+
+    /// Stop the engine when `token` is cancelled.
+    pub fn cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Stop the engine if it is still running at `deadline`.
+    pub fn deadline(&mut self, deadline: Instant) -> &mut Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Stop the engine if it is still running `budget` from now.
+    pub fn wall_time(&mut self, budget: Duration) -> &mut Self {
+        self.deadline(Instant::now() + budget)
+    }
+
+    /// Stop the engine if it tries to emit more than `n` pages.
+    pub fn max_pages(&mut self, n: usize) -> &mut Self {
+        self.max_pages = Some(n);
+        self
+    }
+
+    /// Stop the engine if it tries to write more than `n` bytes to its
+    /// output files, summed over all of them.
+    pub fn max_output_bytes(&mut self, n: usize) -> &mut Self {
+        self.max_output_bytes = Some(n);
+        self
+    }
+
+    fn to_bridge(&self) -> tectonic_engine::tt_engine_limits_t {
+        tectonic_engine::tt_engine_limits_t {
+            cancel: self.cancellation.as_ref().map(|t| t.0.clone()),
+            deadline: self.deadline,
+            max_pages: self.max_pages,
+            max_output_bytes: self.max_output_bytes,
+        }
+    }
+
+    /// Run an engine entry point with these limits installed. If the engine
+    /// was stopped because of them, the corresponding error is returned
+    /// instead of `f`'s result.
+    fn enforce<T, F: FnOnce() -> Result<T>>(&self, f: F) -> Result<T> {
+        use tectonic_engine::TTLimitViolation;
+
+        unsafe {
+            tectonic_engine::tt_set_engine_limits(Some(self.to_bridge()));
+        }
+        let result = f();
+        let violation = unsafe {
+            tectonic_engine::tt_set_engine_limits(None);
+            tectonic_engine::tt_take_limit_violation()
+        };
+
+        match violation {
+            None => result,
+            Some(TTLimitViolation::Cancelled) => Err(ErrorKind::Cancelled.into()),
+            Some(TTLimitViolation::WallTime) => {
+                Err(ErrorKind::LimitExceeded(ResourceLimit::WallTime).into())
+            }
+            Some(TTLimitViolation::Pages) => {
+                Err(ErrorKind::LimitExceeded(ResourceLimit::Pages).into())
+            }
+            Some(TTLimitViolation::OutputBytes) => {
+                Err(ErrorKind::LimitExceeded(ResourceLimit::OutputBytes).into())
+            }
+        }
+    }
+}
+
 // Now, the private interfaces for executing various engines implemented in C/C++.
 
 // The C/C++ engines currently maintain global state, which means that we can
@@ -125,7 +327,9 @@ lazy_static! {
 
 struct ExecutionState<'a, I: 'a + IoProvider> {
     io: &'a mut I,
+    engine: EngineKind,
     events: &'a mut dyn IoEventBackend,
+    progress: &'a mut dyn ProgressEventBackend,
     status: &'a mut dyn StatusBackend,
     #[allow(clippy::vec_box)]
     input_handles: Vec<Box<InputHandle>>,
@@ -136,12 +340,16 @@ struct ExecutionState<'a, I: 'a + IoProvider> {
 impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
     pub fn new(
         io: &'a mut I,
+        engine: EngineKind,
         events: &'a mut dyn IoEventBackend,
+        progress: &'a mut dyn ProgressEventBackend,
         status: &'a mut dyn StatusBackend,
     ) -> ExecutionState<'a, I> {
         ExecutionState {
             io,
+            engine,
             events,
+            progress,
             status,
             output_handles: Vec::new(),
             input_handles: Vec::new(),
@@ -301,6 +509,7 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
         }
 
         self.events.output_opened(oh.name());
+        self.progress.file_opened(oh.name(), true);
         self.output_handles.push(Box::new(oh));
         &*self.output_handles[self.output_handles.len() - 1]
     }
@@ -383,6 +592,7 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
 
         // the file name may have had an extension added, so we use ih.name() here:
         self.events.input_opened(ih.name(), ih.origin());
+        self.progress.file_opened(ih.name(), false);
         self.input_handles.push(Box::new(ih));
         &*self.input_handles[self.input_handles.len() - 1]
     }
@@ -401,6 +611,16 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
         };
 
         self.events.primary_input_opened(ih.origin());
+        self.progress.file_opened(ih.name(), false);
+        self.input_handles.push(Box::new(ih));
+        &*self.input_handles[self.input_handles.len() - 1]
+    }
+
+    fn input_open_memory(&mut self, data: Vec<u8>) -> *const InputHandle {
+        // This is not a file that anyone could depend on, so it is kept out
+        // of the event tracking: see `input_close`.
+        let ih =
+            InputHandle::new_read_only(OsStr::new(""), Cursor::new(data), InputOrigin::NotInput);
         self.input_handles.push(Box::new(ih));
         &*self.input_handles[self.input_handles.len() - 1]
     }
@@ -444,6 +664,9 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
 
             if p == handle {
                 let ih = self.input_handles.swap_remove(i);
+                if ih.origin() == InputOrigin::NotInput {
+                    return false;
+                }
                 let (name, digest_opt) = ih.into_name_digest();
                 self.events.input_closed(name, digest_opt);
                 return false;
@@ -458,6 +681,13 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
 
         true
     }
+
+    fn page_output(&mut self, number: i32) {
+        match self.engine {
+            EngineKind::Tex => self.progress.page_shipped_out(number),
+            _ => self.progress.page_written(number as usize),
+        }
+    }
 }
 
 // Now, here' the actual C API. There are two parts to this: the functions in
@@ -481,12 +711,14 @@ struct TectonicBridgeApi {
     output_close: *const libc::c_void,
     input_open: *const libc::c_void,
     input_open_primary: *const libc::c_void,
+    input_open_memory: *const libc::c_void,
     input_get_size: *const libc::c_void,
     input_seek: *const libc::c_void,
     input_read: *const libc::c_void,
     input_getc: *const libc::c_void,
     input_ungetc: *const libc::c_void,
     input_close: *const libc::c_void,
+    page_output: *const libc::c_void,
 }
 */
 #[repr(transparent)]
@@ -500,8 +732,9 @@ impl std::ops::Deref for TectonicBridgeApi {
 }
 
 use tectonic_engine::{
-    bibtex_simple_main, dvipdfmx_simple_main, tex_simple_main, tt_get_error_message,
-    tt_xetex_set_int_variable,
+    bibtex_simple_main, dvipdfmx_otf_glyph_text, dvipdfmx_simple_main,
+    dvipdfmx_type1_encoding_text, tex_list_fonts, tex_simple_main, tt_get_error_message,
+    tt_xetex_set_font_index, tt_xetex_set_int_variable,
 };
 
 // Entry points for the C/C++ API functions.
@@ -673,6 +906,17 @@ extern "C" fn input_open_primary<'a, I: 'a + IoProvider>(
     es.input_open_primary() as *const _
 }
 
+extern "C" fn input_open_memory<'a, I: 'a + IoProvider>(
+    es: *mut ExecutionState<'a, I>,
+    data: *const u8,
+    len: libc::size_t,
+) -> *const libc::c_void {
+    let es = unsafe { &mut *es };
+    let rdata = unsafe { slice::from_raw_parts(data, len) };
+
+    es.input_open_memory(rdata.to_vec()) as *const _
+}
+
 extern "C" fn input_get_size<'a, I: 'a + IoProvider>(
     es: *mut ExecutionState<'a, I>,
     handle: *mut libc::c_void,
@@ -797,6 +1041,15 @@ extern "C" fn input_close<'a, I: 'a + IoProvider>(
     }
 }
 
+extern "C" fn page_output<'a, I: 'a + IoProvider>(
+    es: *mut ExecutionState<'a, I>,
+    number: libc::c_int,
+) {
+    let es = unsafe { &mut *es };
+
+    es.page_output(number);
+}
+
 // All of these entry points are used to populate the bridge API struct:
 
 impl TectonicBridgeApi {
@@ -820,12 +1073,14 @@ impl TectonicBridgeApi {
                 output_close: transmute(output_close::<'a, I> as *const libc::c_void),
                 input_open: transmute(input_open::<'a, I> as *const libc::c_void),
                 input_open_primary: transmute(input_open_primary::<'a, I> as *const libc::c_void),
+                input_open_memory: transmute(input_open_memory::<'a, I> as *const libc::c_void),
                 input_get_size: transmute(input_get_size::<'a, I> as *const libc::c_void),
                 input_seek: transmute(input_seek::<'a, I> as *const libc::c_void),
                 input_read: transmute(input_read::<'a, I> as *const libc::c_void),
                 input_getc: transmute(input_getc::<'a, I> as *const libc::c_void),
                 input_ungetc: transmute(input_ungetc::<'a, I> as *const libc::c_void),
                 input_close: transmute(input_close::<'a, I> as *const libc::c_void),
+                page_output: transmute(page_output::<'a, I> as *const libc::c_void),
             })
         }
     }
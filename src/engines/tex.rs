@@ -4,8 +4,14 @@
 
 use std::ffi::{CStr, CString};
 
-use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
+use tectonic_engine::{font_axis_t, font_description_t};
+
+use super::{
+    EngineKind, EngineLimits, ExecutionState, IoEventBackend, NoopIoEventBackend,
+    NoopProgressEventBackend, ProgressEventBackend, TectonicBridgeApi,
+};
 use crate::errors::{DefinitelySame, ErrorKind, Result};
+use crate::font_index::FontIndex;
 use crate::io::IoStack;
 use crate::status::StatusBackend;
 
@@ -27,6 +33,74 @@ impl DefinitelySame for TexResult {
     }
 }
 
+/// A variation axis of a font, with the range of values it accepts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontAxis {
+    /// The four-letter axis tag, such as `wght`.
+    pub tag: String,
+
+    /// The human-readable name of the axis, or the tag if the font has none.
+    pub name: String,
+
+    pub minimum: f64,
+    pub default: f64,
+    pub maximum: f64,
+}
+
+impl From<font_axis_t> for FontAxis {
+    fn from(a: font_axis_t) -> Self {
+        FontAxis {
+            tag: a.tag,
+            name: a.name,
+            minimum: a.minimum,
+            default: a.default,
+            maximum: a.maximum,
+        }
+    }
+}
+
+/// A font that the engine can find by name, as reported by
+/// [`TexEngine::list_fonts`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontDescription {
+    pub family_name: String,
+    pub style_name: String,
+    pub full_name: String,
+    pub postscript_name: String,
+
+    /// The file that the font is loaded from: a path, or a file name in the
+    /// bundle.
+    pub file: String,
+
+    /// The index of the face within the file, if it is a font collection.
+    pub index: u32,
+
+    /// The OpenType scripts that the font has layout rules for.
+    pub scripts: Vec<String>,
+
+    /// The OpenType features that the font offers for any script.
+    pub features: Vec<String>,
+
+    /// The variation axes of the font, if it is a variable font.
+    pub axes: Vec<FontAxis>,
+}
+
+impl From<font_description_t> for FontDescription {
+    fn from(d: font_description_t) -> Self {
+        FontDescription {
+            family_name: d.family_name,
+            style_name: d.style_name,
+            full_name: d.full_name,
+            postscript_name: d.ps_name,
+            file: d.file,
+            index: d.index,
+            scripts: d.scripts,
+            features: d.features,
+            axes: d.axes.into_iter().map(FontAxis::from).collect(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct TexEngine {
     // One day, the engine will hold its own state. For the time being,
@@ -35,6 +109,8 @@ pub struct TexEngine {
     initex_mode: bool,
     synctex_enabled: bool,
     semantic_pagination_enabled: bool,
+    limits: EngineLimits,
+    font_index: Option<FontIndex>,
 }
 
 impl Default for TexEngine {
@@ -44,6 +120,8 @@ impl Default for TexEngine {
             initex_mode: false,
             synctex_enabled: false,
             semantic_pagination_enabled: false,
+            limits: EngineLimits::default(),
+            font_index: None,
         }
     }
 }
@@ -87,6 +165,63 @@ impl TexEngine {
         self
     }
 
+    /// Configure cancellation and resource limits for the engine run.
+    ///
+    /// This is how a runaway document, such as one containing
+    /// `\def\a{\a}\a`, can be stopped without killing the whole process.
+    pub fn limits(&mut self, limits: EngineLimits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Configure the fonts that documents can request by name.
+    ///
+    /// With `None`, the default, font names are looked up among the fonts
+    /// installed on the host system. Otherwise they are resolved from the
+    /// given index alone, so that a document finds the same fonts on every
+    /// machine.
+    pub fn font_index(&mut self, index: Option<FontIndex>) -> &mut Self {
+        self.font_index = index;
+        self
+    }
+
+    /// List every font that documents can request by name, given the font
+    /// index configured with [`TexEngine::font_index`].
+    ///
+    /// This is the engine's own view of the fonts, so it's the place to look
+    /// when a font can't be found. Font files are opened through `io`, which
+    /// should be able to open absolute paths as well as bundle files. Every
+    /// font is opened to find its OpenType layout features, so this is slow
+    /// when many fonts are installed.
+    pub fn list_fonts(
+        &mut self,
+        io: &mut IoStack,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<FontDescription>> {
+        let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
+
+        let mut events = NoopIoEventBackend::new();
+        let mut progress = NoopProgressEventBackend::new();
+        let state = ExecutionState::new(io, EngineKind::Tex, &mut events, &mut progress, status);
+        let bridge = TectonicBridgeApi::new(&state);
+
+        let fonts = unsafe {
+            super::tt_xetex_set_font_index(self.font_index.as_ref().map(FontIndex::to_engine));
+            let fonts = super::tex_list_fonts(&*bridge);
+            super::tt_xetex_set_font_index(None);
+            fonts
+        };
+
+        match fonts {
+            Some(fonts) => Ok(fonts.into_iter().map(FontDescription::from).collect()),
+            None => unsafe {
+                let ptr = super::tt_get_error_message();
+                let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+                Err(ErrorKind::Msg(msg).into())
+            },
+        }
+    }
+
     // This function can't be generic across the IoProvider trait, for now,
     // since the global pointer that stashes the ExecutionState must have a
     // complete type.
@@ -98,13 +233,34 @@ impl TexEngine {
         status: &mut dyn StatusBackend,
         format_file_name: &str,
         input_file_name: &str,
+    ) -> Result<TexResult> {
+        self.process_with_progress(
+            io,
+            events,
+            &mut NoopProgressEventBackend::new(),
+            status,
+            format_file_name,
+            input_file_name,
+        )
+    }
+
+    /// Like `process`, but also reports the pages shipped out and the files
+    /// opened to a ProgressEventBackend.
+    pub fn process_with_progress(
+        &mut self,
+        io: &mut IoStack,
+        events: &mut dyn IoEventBackend,
+        progress: &mut dyn ProgressEventBackend,
+        status: &mut dyn StatusBackend,
+        format_file_name: &str,
+        input_file_name: &str,
     ) -> Result<TexResult> {
         let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
 
         let cformat = CString::new(format_file_name)?;
         let cinput = CString::new(input_file_name)?;
 
-        let /*mut*/ state = ExecutionState::new(io, events, status);
+        let /*mut*/ state = ExecutionState::new(io, EngineKind::Tex, events, progress, status);
         let bridge = TectonicBridgeApi::new(&state);
 
         // initialize globals
@@ -128,8 +284,11 @@ impl TexEngine {
         unsafe {
             super::tt_xetex_set_int_variable(b"semantic_pagination_enabled\0".as_ptr() as _, v);
         }
-
         unsafe {
+            super::tt_xetex_set_font_index(self.font_index.as_ref().map(FontIndex::to_engine));
+        }
+
+        let result = self.limits.enforce(|| unsafe {
             match super::tex_simple_main(&*bridge, cformat.as_ptr(), cinput.as_ptr()) {
                 0 => Ok(TexResult::Spotless),
                 1 => Ok(TexResult::Warnings),
@@ -145,6 +304,12 @@ impl TexEngine {
                 ))
                 .into()),
             }
+        });
+
+        unsafe {
+            super::tt_xetex_set_font_index(None);
         }
+
+        result
     }
 }
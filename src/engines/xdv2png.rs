@@ -0,0 +1,854 @@
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Rasterize XDV files to PNG images.
+//!
+//! This is meant for previews and thumbnails rather than print-quality
+//! output: glyphs are rendered with FreeType and placed on whole pixels,
+//! rules are snapped to the pixel grid, and PNG images included with
+//! `\XeTeXpicfile` and friends are resampled with nearest-neighbor
+//! interpolation. Color specials are obeyed. Other graphics specials, and
+//! images in formats other than PNG, are skipped with a warning.
+
+use freetype::face::LoadFlag;
+use freetype::freetype_sys::{
+    FT_Face, FT_Get_Char_Index, FT_Matrix, FT_Select_Charmap, FT_Set_Transform, FT_ULong,
+    FT_ENCODING_ADOBE_CUSTOM, FT_ENCODING_ADOBE_STANDARD,
+};
+use freetype::{Face, Library};
+use libpng_sys::ffi::{
+    png_image, png_image_begin_read_from_memory, png_image_finish_read, png_image_free,
+    png_image_write_to_memory,
+};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, OsStr};
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::{mem, ptr};
+use tectonic_xdv::{FontDefinition, GlyphRun, NativeFontDefinition, XdvEvents, XdvParser};
+
+use super::xdv2svg::{parse_papersize, Color, ORIGIN_BP, SP_TO_BP};
+use super::xdv2text::Tfm;
+use super::IoEventBackend;
+use crate::errors::{Error, Result};
+use crate::io::{IoProvider, IoStack, OpenResult};
+use crate::status::StatusBackend;
+use crate::{errmsg, tt_warning};
+
+/// The paper size used if the document doesn't specify one: US Letter, as
+/// in xdvipdfmx.
+const DEFAULT_PAPER_BP: (f64, f64) = (612.0, 792.0);
+
+/// libpng's pixel formats for its simplified API.
+const PNG_FORMAT_RGB: u32 = 2;
+const PNG_FORMAT_RGBA: u32 = 3;
+const PNG_IMAGE_VERSION: u32 = 1;
+
+pub struct Xdv2PngEngine {
+    dpi: f64,
+}
+
+impl Default for Xdv2PngEngine {
+    fn default() -> Self {
+        Xdv2PngEngine { dpi: 96.0 }
+    }
+}
+
+impl Xdv2PngEngine {
+    pub fn new() -> Xdv2PngEngine {
+        Default::default()
+    }
+
+    /// Set the resolution of the output images, in pixels per inch. The
+    /// default is 96.
+    pub fn with_dpi(mut self, dpi: f64) -> Self {
+        self.dpi = dpi;
+        self
+    }
+
+    /// Rasterize the XDV file *xdv*. A single-page document is written to
+    /// `<stem>.png`; otherwise the pages are written to `<stem>-1.png`,
+    /// `<stem>-2.png`, and so on. Each image covers the whole paper, on a
+    /// white background.
+    pub fn process(
+        &mut self,
+        io: &mut IoStack,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+        xdv: &str,
+    ) -> Result<()> {
+        if !(self.dpi > 0.0 && self.dpi <= 2400.0) {
+            return Err(errmsg!("unsupported PNG resolution {} dpi", self.dpi));
+        }
+
+        let mut input = io.input_open_name(OsStr::new(xdv), status).must_exist()?;
+        events.input_opened(input.name(), input.origin());
+
+        let stem = xdv.strip_suffix(".xdv").unwrap_or(xdv);
+
+        let pages = {
+            let state = State::new(io, status, self.dpi)?;
+            let (state, _n_bytes) = XdvParser::process(&mut input, state)?;
+            state.pages
+        };
+
+        let (name, digest_opt) = input.into_name_digest();
+        events.input_closed(name, digest_opt);
+
+        let n_pages = pages.len();
+
+        for (i, png) in pages.iter().enumerate() {
+            let outname = if n_pages == 1 {
+                format!("{}.png", stem)
+            } else {
+                format!("{}-{}.png", stem, i + 1)
+            };
+
+            let mut output = match io.output_open_name(OsStr::new(&outname)) {
+                OpenResult::Ok(h) => h,
+                OpenResult::NotAvailable => {
+                    return Err(errmsg!("no way to write output file \"{}\"", outname));
+                }
+                OpenResult::Err(e) => {
+                    return Err(e);
+                }
+            };
+
+            events.output_opened(OsStr::new(&outname));
+            output.write_all(png)?;
+            let (name, digest) = output.into_name_digest();
+            events.output_closed(name, digest);
+        }
+
+        Ok(())
+    }
+}
+
+/// An RGB image with a white background.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Canvas {
+            width,
+            height,
+            pixels: vec![0xFF; width * height * 3],
+        }
+    }
+
+    /// Blend *rgb* into the pixel at (*x*, *y*) with opacity *alpha*, out
+    /// of 255.
+    fn blend(&mut self, x: i64, y: i64, rgb: [u8; 3], alpha: u32) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height || alpha == 0 {
+            return;
+        }
+
+        let i = 3 * (y as usize * self.width + x as usize);
+
+        for (c, new) in self.pixels[i..i + 3].iter_mut().zip(&rgb) {
+            *c = ((u32::from(*c) * (255 - alpha) + u32::from(*new) * alpha + 127) / 255) as u8;
+        }
+    }
+
+    fn fill_rect(&mut self, x0: i64, y0: i64, x1: i64, y1: i64, rgb: [u8; 3], alpha: u32) {
+        for y in y0.max(0)..y1.min(self.height as i64) {
+            for x in x0.max(0)..x1.min(self.width as i64) {
+                self.blend(x, y, rgb, alpha);
+            }
+        }
+    }
+
+    /// Encode the canvas as a PNG file.
+    fn to_png(&self) -> Result<Vec<u8>> {
+        unsafe {
+            let mut image: png_image = mem::zeroed();
+            image.version = PNG_IMAGE_VERSION;
+            image.width = self.width as u32;
+            image.height = self.height as u32;
+            image.format = PNG_FORMAT_RGB;
+
+            let mut size = 0;
+            let buffer = self.pixels.as_ptr() as *const libc::c_void;
+
+            if png_image_write_to_memory(
+                &mut image,
+                ptr::null_mut(),
+                &mut size,
+                0,
+                buffer,
+                0,
+                ptr::null(),
+            ) == 0
+            {
+                return Err(png_error(&image));
+            }
+
+            let mut data = vec![0u8; size as usize];
+
+            if png_image_write_to_memory(
+                &mut image,
+                data.as_mut_ptr() as *mut libc::c_void,
+                &mut size,
+                0,
+                buffer,
+                0,
+                ptr::null(),
+            ) == 0
+            {
+                return Err(png_error(&image));
+            }
+
+            data.truncate(size as usize);
+            Ok(data)
+        }
+    }
+}
+
+fn png_error(image: &png_image) -> Error {
+    let message = unsafe { CStr::from_ptr(image.message.as_ptr()) };
+    errmsg!("failed to encode PNG: {}", message.to_string_lossy())
+}
+
+/// A decoded PNG image included in the document.
+struct Picture {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+    /// The natural size of the image in big points, computed as XeTeX does.
+    natural_size: (f64, f64),
+}
+
+impl Picture {
+    fn decode(data: &[u8]) -> Option<Self> {
+        let (width, height, rgba) = unsafe {
+            let mut image: png_image = mem::zeroed();
+            image.version = PNG_IMAGE_VERSION;
+
+            if png_image_begin_read_from_memory(
+                &mut image,
+                data.as_ptr() as *const libc::c_void,
+                data.len() as _,
+            ) == 0
+            {
+                return None;
+            }
+
+            image.format = PNG_FORMAT_RGBA;
+            let (w, h) = (image.width as usize, image.height as usize);
+            let mut rgba = vec![0u8; w * h * 4];
+
+            if png_image_finish_read(
+                &mut image,
+                ptr::null(),
+                rgba.as_mut_ptr() as *mut libc::c_void,
+                0,
+                ptr::null_mut(),
+            ) == 0
+            {
+                png_image_free(&mut image);
+                return None;
+            }
+
+            (w, h, rgba)
+        };
+
+        // Like XeTeX, use the resolution in the pHYs chunk, or 72 dpi.
+        let (xppm, yppm) = png_pixels_per_meter(data).unwrap_or((0, 0));
+        let density = |ppm: u32| {
+            if ppm != 0 {
+                72.0 / 0.0254 / f64::from(ppm)
+            } else {
+                1.0
+            }
+        };
+
+        Some(Picture {
+            width,
+            height,
+            rgba,
+            natural_size: (width as f64 * density(xppm), height as f64 * density(yppm)),
+        })
+    }
+}
+
+/// Find the resolution of a PNG file in pixels per meter, if it is given.
+fn png_pixels_per_meter(data: &[u8]) -> Option<(u32, u32)> {
+    let be32 = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    let mut pos = 8;
+
+    while pos + 8 <= data.len() {
+        let len = be32(&data[pos..]) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data.get(pos + 8..pos + 8 + len)?;
+
+        match kind {
+            b"pHYs" if len >= 9 => {
+                return if body[8] == 1 {
+                    Some((be32(&body[0..]), be32(&body[4..])))
+                } else {
+                    None
+                };
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => {}
+        }
+
+        pos += 12 + len;
+    }
+
+    None
+}
+
+/// A rendered glyph: an 8-bit coverage map and its offset from the glyph
+/// origin, in pixels with y pointing down.
+struct GlyphBitmap {
+    left: i64,
+    top: i64,
+    width: usize,
+    rows: usize,
+    coverage: Vec<u8>,
+}
+
+struct Font {
+    face: Option<Face>,
+    tfm: Option<Tfm>,
+    size: i32,
+    color: Option<Color>,
+    glyphs: HashMap<u32, Option<Rc<GlyphBitmap>>>,
+}
+
+impl Font {
+    fn raw_face(&self) -> Option<FT_Face> {
+        self.face.as_ref().map(|f| f.raw() as *const _ as FT_Face)
+    }
+
+    fn glyph(&mut self, gid: u32) -> Option<Rc<GlyphBitmap>> {
+        if let Some(g) = self.glyphs.get(&gid) {
+            return g.clone();
+        }
+
+        let g = self.render_glyph(gid).map(Rc::new);
+        self.glyphs.insert(gid, g.clone());
+        g
+    }
+
+    fn render_glyph(&self, gid: u32) -> Option<GlyphBitmap> {
+        let face = self.face.as_ref()?;
+        face.load_glyph(gid, LoadFlag::RENDER | LoadFlag::NO_BITMAP)
+            .ok()?;
+        let slot = face.glyph();
+        let bitmap = slot.bitmap();
+        let (width, rows) = (
+            bitmap.width().max(0) as usize,
+            bitmap.rows().max(0) as usize,
+        );
+        let pitch = bitmap.pitch();
+        let buffer = bitmap.buffer();
+        let mut coverage = vec![0u8; width * rows];
+
+        for y in 0..rows {
+            let row = if pitch >= 0 {
+                y * pitch as usize
+            } else {
+                (rows - 1 - y) * (-pitch) as usize
+            };
+
+            for x in 0..width {
+                coverage[y * width + x] = *buffer.get(row + x)?;
+            }
+        }
+
+        Some(GlyphBitmap {
+            left: i64::from(slot.bitmap_left()),
+            top: i64::from(slot.bitmap_top()),
+            width,
+            rows,
+            coverage,
+        })
+    }
+}
+
+struct State<'a, 'b: 'a> {
+    io: &'a mut IoStack<'b>,
+    status: &'a mut dyn StatusBackend,
+    library: Library,
+    dpi: f64,
+    fonts: HashMap<i32, Font>,
+    pictures: HashMap<String, Option<Rc<Picture>>>,
+    pages: Vec<Vec<u8>>,
+    canvas: Canvas,
+    paper: (f64, f64),
+    color_stack: Vec<Color>,
+    warned: HashSet<String>,
+}
+
+impl<'a, 'b: 'a> State<'a, 'b> {
+    fn new(io: &'a mut IoStack<'b>, status: &'a mut dyn StatusBackend, dpi: f64) -> Result<Self> {
+        let library = match Library::init() {
+            Ok(l) => l,
+            Err(e) => return Err(errmsg!("failed to initialize FreeType: {}", e)),
+        };
+
+        Ok(State {
+            io,
+            status,
+            library,
+            dpi,
+            fonts: HashMap::new(),
+            pictures: HashMap::new(),
+            pages: Vec::new(),
+            canvas: Canvas::new(0, 0),
+            paper: DEFAULT_PAPER_BP,
+            color_stack: Vec::new(),
+            warned: HashSet::new(),
+        })
+    }
+
+    fn warn_once(&mut self, key: &str, message: &str) {
+        if self.warned.insert(key.to_owned()) {
+            tt_warning!(self.status, "{}", message);
+        }
+    }
+
+    fn read_file(&mut self, name: &str) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+
+        match self.io.input_open_name(OsStr::new(name), self.status) {
+            OpenResult::Ok(mut h) => h.read_to_end(&mut data).ok().map(|_| data),
+            _ => None,
+        }
+    }
+
+    fn open_face(&mut self, name: &str, face_index: u32) -> Option<Face> {
+        let data = self.read_file(name)?;
+        self.library
+            .new_memory_face(Rc::new(data), face_index as isize)
+            .ok()
+    }
+
+    /// Size a face for a font of *size* scaled points at our resolution.
+    fn size_face(&self, face: &Face, size: i32, extend: f64, slant: f64) -> bool {
+        let size_bp_64 = (f64::from(size) * SP_TO_BP * 64.0).round() as isize;
+        let dpi = self.dpi.round() as u32;
+
+        if face.set_char_size(0, size_bp_64.max(1), dpi, dpi).is_err() {
+            return false;
+        }
+
+        if extend != 1.0 || slant != 0.0 {
+            let mut matrix = FT_Matrix {
+                xx: (extend * 65536.0) as _,
+                xy: (slant * 65536.0) as _,
+                yx: 0,
+                yy: 0x10000,
+            };
+
+            unsafe {
+                FT_Set_Transform(
+                    face.raw() as *const _ as FT_Face,
+                    &mut matrix,
+                    ptr::null_mut(),
+                );
+            }
+        }
+
+        true
+    }
+
+    fn new_canvas(&self) -> Canvas {
+        let k = self.dpi / 72.0;
+        let width = (self.paper.0 * k).round().max(1.0) as usize;
+        let height = (self.paper.1 * k).round().max(1.0) as usize;
+        Canvas::new(width, height)
+    }
+
+    fn to_pixels(&self, h: i32, v: i32) -> (f64, f64) {
+        let k = self.dpi / 72.0;
+        (
+            (ORIGIN_BP + f64::from(h) * SP_TO_BP) * k,
+            (ORIGIN_BP + f64::from(v) * SP_TO_BP) * k,
+        )
+    }
+
+    fn current_rgb(&self, font_color: Option<&Color>) -> ([u8; 3], u32) {
+        let color = font_color.or_else(|| self.color_stack.last());
+        match color {
+            Some(c) => {
+                let byte = |x: f64| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+                ([byte(c.r), byte(c.g), byte(c.b)], u32::from(byte(c.alpha)))
+            }
+            None => ([0, 0, 0], 255),
+        }
+    }
+
+    fn draw_glyph(&mut self, font_num: i32, gid: u32, h: i32, v: i32) {
+        let (x, y) = self.to_pixels(h, v);
+
+        let (glyph, font_color) = match self.fonts.get_mut(&font_num) {
+            Some(font) => (font.glyph(gid), font.color.clone()),
+            None => return,
+        };
+
+        let glyph = match glyph {
+            Some(g) => g,
+            None => return,
+        };
+
+        let (rgb, alpha) = self.current_rgb(font_color.as_ref());
+        let x0 = x.round() as i64 + glyph.left;
+        let y0 = y.round() as i64 - glyph.top;
+
+        for row in 0..glyph.rows {
+            for col in 0..glyph.width {
+                let cov = u32::from(glyph.coverage[row * glyph.width + col]);
+                self.canvas
+                    .blend(x0 + col as i64, y0 + row as i64, rgb, cov * alpha / 255);
+            }
+        }
+    }
+
+    fn picture(&mut self, name: &str) -> Option<Rc<Picture>> {
+        if let Some(p) = self.pictures.get(name) {
+            return p.clone();
+        }
+
+        let pic = self.read_file(name).and_then(|data| {
+            if data.starts_with(b"\x89PNG") {
+                Picture::decode(&data).map(Rc::new)
+            } else {
+                None
+            }
+        });
+
+        if pic.is_none() {
+            self.warn_once(
+                &format!("image:{}", name),
+                &format!(
+                    "couldn't draw image \"{}\"; only PNG images are supported in PNG output",
+                    name
+                ),
+            );
+        }
+
+        self.pictures.insert(name.to_owned(), pic.clone());
+        pic
+    }
+
+    /// Handle the `pdf:image` specials that XeTeX emits for included
+    /// pictures.
+    fn handle_image_special(&mut self, h: i32, v: i32, args: &str) {
+        let (before, name) = match (args.find('('), args.rfind(')')) {
+            (Some(i), Some(j)) if i < j => (&args[..i], &args[i + 1..j]),
+            _ => return,
+        };
+
+        let mut matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+        let mut words = before.split_whitespace();
+
+        while let Some(w) = words.next() {
+            if w == "matrix" {
+                for m in matrix.iter_mut() {
+                    *m = match words.next().and_then(|n| n.parse().ok()) {
+                        Some(n) => n,
+                        None => return,
+                    };
+                }
+            }
+        }
+
+        let pic = match self.picture(name) {
+            Some(p) => p,
+            None => return,
+        };
+
+        // Image space runs from (0, 0) at the bottom left to the natural
+        // size at the top right; the matrix takes it to PDF-style page
+        // space, relative to the current point. Work backwards from each
+        // device pixel to the image pixel that covers it.
+        let k = self.dpi / 72.0;
+        let (ox, oy) = self.to_pixels(h, v);
+        let dev = [
+            matrix[0] * k,
+            -matrix[1] * k,
+            matrix[2] * k,
+            -matrix[3] * k,
+            ox + matrix[4] * k,
+            oy - matrix[5] * k,
+        ];
+        let det = dev[0] * dev[3] - dev[1] * dev[2];
+
+        if det.abs() < 1e-12 {
+            return;
+        }
+
+        let (nw, nh) = pic.natural_size;
+        let corners = [(0.0, 0.0), (nw, 0.0), (0.0, nh), (nw, nh)];
+        let xs = corners
+            .iter()
+            .map(|(x, y)| dev[0] * x + dev[2] * y + dev[4]);
+        let ys = corners
+            .iter()
+            .map(|(x, y)| dev[1] * x + dev[3] * y + dev[5]);
+        let x_min = xs.clone().fold(f64::INFINITY, f64::min).floor().max(0.0) as i64;
+        let x_max = xs.fold(f64::NEG_INFINITY, f64::max).ceil() as i64;
+        let y_min = ys.clone().fold(f64::INFINITY, f64::min).floor().max(0.0) as i64;
+        let y_max = ys.fold(f64::NEG_INFINITY, f64::max).ceil() as i64;
+        let x_max = x_max.min(self.canvas.width as i64);
+        let y_max = y_max.min(self.canvas.height as i64);
+
+        for py in y_min..y_max {
+            for px in x_min..x_max {
+                let (dx, dy) = (px as f64 + 0.5 - dev[4], py as f64 + 0.5 - dev[5]);
+                let ix = (dev[3] * dx - dev[2] * dy) / det;
+                let iy = (-dev[1] * dx + dev[0] * dy) / det;
+
+                if ix < 0.0 || iy < 0.0 || ix >= nw || iy >= nh {
+                    continue;
+                }
+
+                let sx = ((ix / nw) * pic.width as f64) as usize;
+                let sy = ((1.0 - iy / nh) * pic.height as f64) as usize;
+                let i = 4 * (sy.min(pic.height - 1) * pic.width + sx.min(pic.width - 1));
+                let p = &pic.rgba[i..i + 4];
+                self.canvas
+                    .blend(px, py, [p[0], p[1], p[2]], u32::from(p[3]));
+            }
+        }
+    }
+}
+
+impl<'a, 'b: 'a> XdvEvents for State<'a, 'b> {
+    type Error = Error;
+
+    fn handle_begin_page(&mut self, _counters: &[i32], _previous_bop: i32) -> Result<()> {
+        self.canvas = self.new_canvas();
+        Ok(())
+    }
+
+    fn handle_end_page(&mut self) -> Result<()> {
+        let png = self.canvas.to_png()?;
+        self.pages.push(png);
+        Ok(())
+    }
+
+    fn handle_font_definition(&mut self, font_num: i32, def: &FontDefinition) -> Result<()> {
+        if self.fonts.contains_key(&font_num) {
+            return Ok(());
+        }
+
+        let size = def.scale_factor as i32;
+        let tfm = self
+            .read_file(&format!("{}.tfm", def.name))
+            .and_then(|d| Tfm::parse(&d));
+        let mut face = self.open_face(&format!("{}.pfb", def.name), 0);
+
+        if let Some(ref f) = face {
+            let raw = f.raw() as *const _ as FT_Face;
+
+            unsafe {
+                if FT_Select_Charmap(raw, FT_ENCODING_ADOBE_CUSTOM) != 0 {
+                    FT_Select_Charmap(raw, FT_ENCODING_ADOBE_STANDARD);
+                }
+            }
+
+            if !self.size_face(f, size, 1.0, 0.0) {
+                face = None;
+            }
+        }
+
+        if face.is_none() {
+            let name = def.name.clone();
+            self.warn_once(
+                &format!("font:{}", name),
+                &format!(
+                    "no outlines for font \"{}\"; its characters will be missing from the PNG output",
+                    name
+                ),
+            );
+        }
+
+        self.fonts.insert(
+            font_num,
+            Font {
+                face,
+                tfm,
+                size,
+                color: None,
+                glyphs: HashMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    fn handle_native_font_definition(
+        &mut self,
+        font_num: i32,
+        def: &NativeFontDefinition,
+    ) -> Result<()> {
+        if self.fonts.contains_key(&font_num) {
+            return Ok(());
+        }
+
+        let extend = def.extend.map_or(1.0, |e| f64::from(e) / 65536.0);
+        let slant = def.slant.map_or(0.0, |s| f64::from(s) / 65536.0);
+        let mut face = self.open_face(&def.path, def.face_index);
+
+        if let Some(ref f) = face {
+            if !self.size_face(f, def.size, extend, slant) {
+                face = None;
+            }
+        }
+
+        if face.is_none() {
+            let path = def.path.clone();
+            self.warn_once(
+                &format!("font:{}", path),
+                &format!(
+                    "couldn't load font \"{}\"; its glyphs will be missing from the PNG output",
+                    path
+                ),
+            );
+        }
+
+        self.fonts.insert(
+            font_num,
+            Font {
+                face,
+                tfm: None,
+                size: def.size,
+                color: def.color_rgba.map(Color::from_rgba),
+                glyphs: HashMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    fn char_width(&mut self, font_num: i32, char_num: i32) -> Result<i32> {
+        Ok(self
+            .fonts
+            .get(&font_num)
+            .and_then(|f| f.tfm.as_ref()?.metrics(char_num, f.size))
+            .map_or(0, |m| m.0))
+    }
+
+    fn handle_char(&mut self, font_num: i32, char_num: i32, h: i32, v: i32) -> Result<()> {
+        let gid = match self.fonts.get(&font_num).and_then(|f| f.raw_face()) {
+            Some(raw) => unsafe { FT_Get_Char_Index(raw, char_num as FT_ULong) },
+            None => return Ok(()),
+        };
+
+        if gid != 0 {
+            self.draw_glyph(font_num, gid, h, v);
+        }
+
+        Ok(())
+    }
+
+    fn handle_glyph_run(&mut self, font_num: i32, run: &GlyphRun) -> Result<()> {
+        for ((gid, h), v) in run.glyphs.iter().zip(run.h).zip(run.v) {
+            self.draw_glyph(font_num, u32::from(*gid), *h, *v);
+        }
+
+        Ok(())
+    }
+
+    fn handle_rule(&mut self, h: i32, v: i32, height: i32, width: i32) -> Result<()> {
+        if height <= 0 || width <= 0 {
+            return Ok(());
+        }
+
+        // Like TeX's DVI drivers, make sure that thin rules don't vanish.
+        let (x0, y1) = self.to_pixels(h, v);
+        let (x1, y0) = self.to_pixels(h.saturating_add(width), v.saturating_sub(height));
+        let x0 = x0.round() as i64;
+        let y1 = y1.round() as i64;
+        let x1 = (x1.round() as i64).max(x0 + 1);
+        let y0 = (y0.round() as i64).min(y1 - 1);
+
+        let (rgb, alpha) = self.current_rgb(None);
+        self.canvas.fill_rect(x0, y0, x1, y1, rgb, alpha);
+        Ok(())
+    }
+
+    fn handle_positioned_special(&mut self, h: i32, v: i32, contents: &[u8]) -> Result<()> {
+        let text = String::from_utf8_lossy(contents);
+        let text = text.trim_start();
+
+        if let Some(args) = text.strip_prefix("color ") {
+            let mut words = args.split_whitespace();
+
+            match words.next() {
+                Some("push") => {
+                    let spec = words.collect::<Vec<_>>().join(" ");
+                    let color = Color::parse(&spec)
+                        .or_else(|| self.color_stack.last().cloned())
+                        .unwrap_or_else(Color::black);
+                    self.color_stack.push(color);
+                }
+                Some("pop") => {
+                    self.color_stack.pop();
+                }
+                Some(_) => {
+                    if let Some(c) = Color::parse(args.trim()) {
+                        self.color_stack.pop();
+                        self.color_stack.push(c);
+                    }
+                }
+                None => {}
+            }
+        } else if let Some(spec) = text.strip_prefix("papersize=") {
+            match parse_papersize(spec) {
+                Some(p) => {
+                    // The special normally comes at the very start of the
+                    // first page, before anything has been drawn.
+                    if p != self.paper {
+                        self.paper = p;
+                        self.canvas = self.new_canvas();
+                    }
+                }
+                None => self.warn_once("papersize", "couldn't parse papersize special"),
+            }
+        } else if let Some(body) = text.strip_prefix("pdf:") {
+            let body = body.trim_start();
+            let cmd: String = body
+                .chars()
+                .take_while(|c| !c.is_whitespace() && *c != '[')
+                .collect();
+            let rest = body[cmd.len()..].trim_start();
+
+            match cmd.as_str() {
+                "image" => self.handle_image_special(h, v, rest),
+                "bc" | "bcolor" => {
+                    let color = Color::parse_pdf_array(rest)
+                        .or_else(|| self.color_stack.last().cloned())
+                        .unwrap_or_else(Color::black);
+                    self.color_stack.push(color);
+                }
+                "ec" | "ecolor" => {
+                    self.color_stack.pop();
+                }
+                "pagesize" => {}
+                _ => self.warn_once(
+                    &format!("pdf:{}", cmd),
+                    &format!("ignoring unsupported special \"pdf:{}\" in PNG output", cmd),
+                ),
+            }
+        } else {
+            let prefix: String = text
+                .chars()
+                .take_while(|c| !c.is_whitespace() && *c != '=')
+                .take(32)
+                .collect();
+            self.warn_once(
+                &format!("special:{}", prefix),
+                &format!("ignoring unsupported special \"{}\" in PNG output", prefix),
+            );
+        }
+
+        Ok(())
+    }
+}
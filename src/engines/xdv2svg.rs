@@ -0,0 +1,1403 @@
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Render XDV files as SVG images.
+//!
+//! Every page becomes a standalone SVG document. Glyphs are drawn as paths
+//! taken from the font outlines with FreeType, so that viewers don't need
+//! access to the fonts. Rules, color specials and a basic subset of the PDF
+//! graphics operators used in `pdf:literal`-style specials are supported too;
+//! anything else is skipped with a warning.
+
+use freetype::face::LoadFlag;
+use freetype::freetype_sys::{
+    FT_Error, FT_Face, FT_Fixed, FT_Get_Char_Index, FT_Library, FT_Select_Charmap, FT_String,
+    FT_UInt, FT_ULong, FT_ENCODING_ADOBE_CUSTOM, FT_ENCODING_ADOBE_STANDARD,
+};
+use freetype::outline::Curve;
+use freetype::{Face, Library};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt::Write as FmtWrite;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::{ptr, slice};
+use tectonic_xdv::{FontDefinition, GlyphRun, NativeFontDefinition, XdvEvents, XdvParser};
+
+use super::IoEventBackend;
+use crate::errors::{Error, Result};
+use crate::io::{IoProvider, IoStack, OpenResult};
+use crate::status::StatusBackend;
+use crate::{errmsg, tt_warning};
+
+/// The size of a TeX scaled point in PostScript (“big”) points, which are
+/// the user units of the SVG output.
+pub(crate) const SP_TO_BP: f64 = 72.0 / 72.27 / 65536.0;
+
+/// Like xdvipdfmx, we put the DVI origin one inch in from the top left of
+/// the paper.
+pub(crate) const ORIGIN_BP: f64 = 72.0;
+
+/// The paper height assumed by `pdf:literal direct` and friends when the
+/// document doesn't specify one: US Letter, as in xdvipdfmx.
+const DEFAULT_PAPER_HEIGHT_BP: f64 = 792.0;
+
+#[derive(Default)]
+pub struct Xdv2SvgEngine {}
+
+impl Xdv2SvgEngine {
+    pub fn new() -> Xdv2SvgEngine {
+        Default::default()
+    }
+
+    /// Render the XDV file *xdv* to SVG. A single-page document is written
+    /// to `<stem>.svg`; otherwise the pages are written to `<stem>-1.svg`,
+    /// `<stem>-2.svg`, and so on.
+    ///
+    /// If the document sets its paper size with a `papersize` special, each
+    /// image covers the whole page. Otherwise the images are cropped to the
+    /// marks on each page, which is what you want for equations and
+    /// diagrams that will be embedded elsewhere.
+    pub fn process(
+        &mut self,
+        io: &mut IoStack,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+        xdv: &str,
+    ) -> Result<()> {
+        let mut input = io.input_open_name(OsStr::new(xdv), status).must_exist()?;
+        events.input_opened(input.name(), input.origin());
+
+        let stem = xdv.strip_suffix(".xdv").unwrap_or(xdv);
+
+        let pages = {
+            let state = State::new(io, status)?;
+            let (state, _n_bytes) = XdvParser::process(&mut input, state)?;
+            state.pages
+        };
+
+        let (name, digest_opt) = input.into_name_digest();
+        events.input_closed(name, digest_opt);
+
+        let n_pages = pages.len();
+
+        for (i, page) in pages.iter().enumerate() {
+            let outname = if n_pages == 1 {
+                format!("{}.svg", stem)
+            } else {
+                format!("{}-{}.svg", stem, i + 1)
+            };
+
+            let mut output = match io.output_open_name(OsStr::new(&outname)) {
+                OpenResult::Ok(h) => h,
+                OpenResult::NotAvailable => {
+                    return Err(errmsg!("no way to write output file \"{}\"", outname));
+                }
+                OpenResult::Err(e) => {
+                    return Err(e);
+                }
+            };
+
+            events.output_opened(OsStr::new(&outname));
+            output.write_all(page.as_bytes())?;
+            let (name, digest) = output.into_name_digest();
+            events.output_closed(name, digest);
+        }
+
+        Ok(())
+    }
+}
+
+/// A glyph outline, in font units with the y axis pointing up.
+struct Glyph {
+    path: String,
+    bbox: BBox,
+    advance: f64,
+}
+
+struct Font {
+    /// None if the font file couldn't be loaded; we've warned about it.
+    face: Option<Face>,
+    units_per_em: f64,
+    /// The font size, in DVI units.
+    size: f64,
+    extend: f64,
+    slant: f64,
+    color: Option<Color>,
+    glyphs: HashMap<u32, Option<Rc<Glyph>>>,
+}
+
+impl Font {
+    fn unloaded(size: f64) -> Self {
+        Font {
+            face: None,
+            units_per_em: 1000.0,
+            size,
+            extend: 1.0,
+            slant: 0.0,
+            color: None,
+            glyphs: HashMap::new(),
+        }
+    }
+
+    /// Get the glyph ID for character *c* of a traditional font.
+    fn char_glyph(&self, c: i32) -> Option<u32> {
+        let face = self.face.as_ref()?;
+        let raw = face.raw() as *const _ as FT_Face;
+        let gid = unsafe { FT_Get_Char_Index(raw, c as FT_ULong) };
+
+        if gid == 0 {
+            None
+        } else {
+            Some(gid)
+        }
+    }
+
+    fn glyph(&mut self, gid: u32) -> Option<Rc<Glyph>> {
+        if let Some(g) = self.glyphs.get(&gid) {
+            return g.clone();
+        }
+
+        let g = self.load_glyph(gid).map(Rc::new);
+        self.glyphs.insert(gid, g.clone());
+        g
+    }
+
+    fn load_glyph(&self, gid: u32) -> Option<Glyph> {
+        let face = self.face.as_ref()?;
+        face.load_glyph(gid, LoadFlag::NO_HINTING | LoadFlag::NO_BITMAP)
+            .ok()?;
+        let slot = face.glyph();
+        let advance = slot.advance().x as f64 / 64.0;
+        let mut path = String::new();
+        let mut bbox = BBox::default();
+
+        // The face is sized so that one pixel is one font unit, and outline
+        // coordinates are 26.6 fixed-point pixels.
+        if let Some(outline) = slot.outline() {
+            let mut pt = |path: &mut String, op: &str, points: &[(f64, f64)]| {
+                path.push_str(op);
+
+                for (x, y) in points {
+                    let (x, y) = (*x / 64.0, *y / 64.0);
+                    bbox.add(x, y);
+                    let _ = write!(path, "{} {} ", num(x), num(y));
+                }
+            };
+
+            for contour in outline.contours_iter() {
+                let start = contour.start();
+                pt(&mut path, "M", &[(start.x as f64, start.y as f64)]);
+
+                for curve in contour {
+                    match curve {
+                        Curve::Line(p) => pt(&mut path, "L", &[(p.x as f64, p.y as f64)]),
+                        Curve::Bezier2(c, p) => pt(
+                            &mut path,
+                            "Q",
+                            &[(c.x as f64, c.y as f64), (p.x as f64, p.y as f64)],
+                        ),
+                        Curve::Bezier3(c1, c2, p) => pt(
+                            &mut path,
+                            "C",
+                            &[
+                                (c1.x as f64, c1.y as f64),
+                                (c2.x as f64, c2.y as f64),
+                                (p.x as f64, p.y as f64),
+                            ],
+                        ),
+                    }
+                }
+
+                path.push('Z');
+            }
+        }
+
+        Some(Glyph {
+            path,
+            bbox,
+            advance,
+        })
+    }
+
+    /// The transform that places a glyph of this font with its origin at
+    /// (*x*, *y*) on the page.
+    fn glyph_matrix(&self, x: f64, y: f64) -> [f64; 6] {
+        let s = self.size * SP_TO_BP / self.units_per_em;
+        [s * self.extend, 0.0, s * self.slant, -s, x, y]
+    }
+}
+
+/// The subset of the PDF graphics state that we track for literal
+/// specials. Colors of None mean “the current TeX color”.
+#[derive(Clone, Debug)]
+struct GState {
+    ctm: [f64; 6],
+    fill: Option<Color>,
+    stroke: Option<Color>,
+    line_width: f64,
+    line_cap: u8,
+    line_join: u8,
+    miter_limit: f64,
+    dash: Option<(Vec<f64>, f64)>,
+}
+
+impl Default for GState {
+    fn default() -> Self {
+        GState {
+            ctm: IDENTITY,
+            fill: None,
+            stroke: None,
+            line_width: 1.0,
+            line_cap: 0,
+            line_join: 0,
+            miter_limit: 10.0,
+            dash: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Page {
+    body: String,
+    bbox: BBox,
+    glyphs: BTreeSet<(i32, u32)>,
+    gstate: GState,
+    gstack: Vec<GState>,
+    /// Origins pushed by `pdf:bcontent`, in PDF page coordinates.
+    origins: Vec<(f64, f64)>,
+}
+
+struct State<'a, 'b: 'a> {
+    io: &'a mut IoStack<'b>,
+    status: &'a mut dyn StatusBackend,
+    library: Library,
+    fonts: HashMap<i32, Font>,
+    pages: Vec<String>,
+    page: Page,
+    paper: Option<(f64, f64)>,
+    color_stack: Vec<Color>,
+    warned: HashSet<String>,
+}
+
+impl<'a, 'b: 'a> State<'a, 'b> {
+    fn new(io: &'a mut IoStack<'b>, status: &'a mut dyn StatusBackend) -> Result<Self> {
+        let library = match Library::init() {
+            Ok(l) => l,
+            Err(e) => return Err(errmsg!("failed to initialize FreeType: {}", e)),
+        };
+
+        Ok(State {
+            io,
+            status,
+            library,
+            fonts: HashMap::new(),
+            pages: Vec::new(),
+            page: Page::default(),
+            paper: None,
+            color_stack: Vec::new(),
+            warned: HashSet::new(),
+        })
+    }
+
+    fn warn_once(&mut self, key: &str, message: &str) {
+        if self.warned.insert(key.to_owned()) {
+            tt_warning!(self.status, "{}", message);
+        }
+    }
+
+    /// Read a font file through the I/O stack and hand it to FreeType.
+    fn open_face(&mut self, name: &str, face_index: u32) -> Option<Face> {
+        let mut data = Vec::new();
+
+        match self.io.input_open_name(OsStr::new(name), self.status) {
+            OpenResult::Ok(mut h) => {
+                if h.read_to_end(&mut data).is_err() {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+
+        self.library
+            .new_memory_face(Rc::new(data), face_index as isize)
+            .ok()
+    }
+
+    /// Size a face so that one pixel corresponds to one font unit, making
+    /// the outlines come out in font units, and return that number.
+    fn prepare_face(face: &Face) -> Option<f64> {
+        let upem = face.raw().units_per_EM as f64;
+
+        if upem <= 0.0 {
+            return None;
+        }
+
+        face.set_char_size(upem as isize * 64, 0, 72, 72).ok()?;
+        Some(upem)
+    }
+
+    fn current_color(&self) -> Option<&Color> {
+        self.color_stack.last()
+    }
+
+    fn to_page(h: i32, v: i32) -> (f64, f64) {
+        (
+            ORIGIN_BP + h as f64 * SP_TO_BP,
+            ORIGIN_BP + v as f64 * SP_TO_BP,
+        )
+    }
+
+    fn paper_height(&self) -> f64 {
+        self.paper.map_or(DEFAULT_PAPER_HEIGHT_BP, |p| p.1)
+    }
+
+    fn fill_attr(color: Option<&Color>) -> String {
+        match color {
+            Some(c) => c.fill_attrs(),
+            None => String::new(),
+        }
+    }
+
+    fn finish_page(&mut self) {
+        let page = std::mem::take(&mut self.page);
+        let mut svg = String::new();
+
+        let (x, y, w, h) = match (self.paper, page.bbox.0) {
+            (Some((w, h)), _) => (0.0, 0.0, w, h),
+            (None, Some([x0, y0, x1, y1])) => (x0, y0, x1 - x0, y1 - y0),
+            (None, None) => (0.0, 0.0, 0.0, 0.0),
+        };
+
+        svg.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+             xmlns:xlink=\"http://www.w3.org/1999/xlink\" version=\"1.1\" \
+             width=\"{}pt\" height=\"{}pt\" viewBox=\"{} {} {} {}\">",
+            num(w),
+            num(h),
+            num(x),
+            num(y),
+            num(w),
+            num(h)
+        );
+
+        if !page.glyphs.is_empty() {
+            svg.push_str("<defs>\n");
+
+            for (font_num, gid) in &page.glyphs {
+                let glyph = self
+                    .fonts
+                    .get(font_num)
+                    .and_then(|f| f.glyphs.get(gid))
+                    .and_then(|g| g.clone());
+
+                if let Some(glyph) = glyph {
+                    let _ = writeln!(
+                        svg,
+                        "<path id=\"g{}-{}\" d=\"{}\"/>",
+                        font_num,
+                        gid,
+                        glyph.path.trim_end()
+                    );
+                }
+            }
+
+            svg.push_str("</defs>\n");
+        }
+
+        svg.push_str(&page.body);
+        svg.push_str("</svg>\n");
+        self.pages.push(svg);
+    }
+
+    /// Draw glyph *gid* of a font at the page position (*x*, *y*).
+    fn draw_glyph(&mut self, font_num: i32, gid: u32, x: f64, y: f64) {
+        let font = match self.fonts.get_mut(&font_num) {
+            Some(f) => f,
+            None => return,
+        };
+
+        let glyph = match font.glyph(gid) {
+            Some(g) => g,
+            None => return,
+        };
+
+        if glyph.path.is_empty() {
+            return;
+        }
+
+        let m = font.glyph_matrix(x, y);
+
+        if let Some([x0, y0, x1, y1]) = glyph.bbox.0 {
+            for (gx, gy) in &[(x0, y0), (x0, y1), (x1, y0), (x1, y1)] {
+                let (px, py) = apply(&m, *gx, *gy);
+                self.page.bbox.add(px, py);
+            }
+        }
+
+        let _ = writeln!(
+            self.page.body,
+            "<use xlink:href=\"#g{}-{}\" transform=\"{}\"/>",
+            font_num,
+            gid,
+            matrix_attr(&m)
+        );
+        self.page.glyphs.insert((font_num, gid));
+    }
+
+    /// Open a group with the fill color for text in a font.
+    fn begin_text_group(&mut self, font_num: i32) {
+        let font_color = self.fonts.get(&font_num).and_then(|f| f.color.clone());
+        let attrs = Self::fill_attr(font_color.as_ref().or_else(|| self.current_color()));
+        let _ = writeln!(self.page.body, "<g{}>", attrs);
+    }
+
+    fn handle_color_special(&mut self, args: &str) {
+        let mut words = args.split_whitespace();
+
+        match words.next() {
+            Some("push") => {
+                let spec = words.collect::<Vec<_>>().join(" ");
+                let color = match Color::parse(&spec) {
+                    Some(c) => c,
+                    None => {
+                        self.warn_once(
+                            &format!("color:{}", spec),
+                            &format!("unsupported color \"{}\" in SVG output", spec),
+                        );
+                        self.current_color().cloned().unwrap_or_else(Color::black)
+                    }
+                };
+                self.color_stack.push(color);
+            }
+
+            Some("pop") => {
+                if self.color_stack.pop().is_none() {
+                    self.warn_once("color:underflow", "color stack underflow");
+                }
+            }
+
+            Some(_) => {
+                let spec = args.trim();
+
+                if let Some(c) = Color::parse(spec) {
+                    self.color_stack.pop();
+                    self.color_stack.push(c);
+                } else {
+                    self.warn_once(
+                        &format!("color:{}", spec),
+                        &format!("unsupported color \"{}\" in SVG output", spec),
+                    );
+                }
+            }
+
+            None => {}
+        }
+
+        self.page.gstate.fill = None;
+        self.page.gstate.stroke = None;
+    }
+
+    fn handle_pdf_special(&mut self, h: i32, v: i32, body: &str) {
+        let body = body.trim_start();
+        let (cmd, rest) = match body.find(|c: char| c.is_whitespace() || c == '[') {
+            Some(i) => (&body[..i], &body[i..]),
+            None => (body, ""),
+        };
+        let rest = rest.trim_start();
+
+        // PDF page coordinates of the current point, relative to the
+        // innermost bcontent origin.
+        let (px, py) = Self::to_page(h, v);
+        let py = self.paper_height() - py;
+        let (ox, oy) = self.page.origins.last().cloned().unwrap_or((0.0, 0.0));
+        let (rx, ry) = (px - ox, py - oy);
+
+        match cmd {
+            "content" => {
+                self.page.gstack.push(self.page.gstate.clone());
+                self.concat([1.0, 0.0, 0.0, 1.0, rx, ry]);
+                self.run_pdf_ops(rest);
+                if let Some(g) = self.page.gstack.pop() {
+                    self.page.gstate = g;
+                }
+            }
+
+            "literal" => {
+                let mut rest = rest;
+                let mut direct = false;
+
+                loop {
+                    if let Some(r) = rest.strip_prefix("direct") {
+                        direct = true;
+                        rest = r.trim_start();
+                    } else if let Some(r) = rest.strip_prefix("reverse") {
+                        rest = r.trim_start();
+                    } else {
+                        break;
+                    }
+                }
+
+                if direct {
+                    self.run_pdf_ops(rest);
+                } else {
+                    self.concat([1.0, 0.0, 0.0, 1.0, rx, ry]);
+                    self.run_pdf_ops(rest);
+                    self.concat([1.0, 0.0, 0.0, 1.0, -rx, -ry]);
+                }
+            }
+
+            "code" => self.run_pdf_ops(rest),
+
+            "bcontent" => {
+                self.page.gstack.push(self.page.gstate.clone());
+                self.concat([1.0, 0.0, 0.0, 1.0, rx, ry]);
+                self.page.origins.push((px, py));
+            }
+
+            "econtent" => {
+                self.page.origins.pop();
+                if let Some(g) = self.page.gstack.pop() {
+                    self.page.gstate = g;
+                }
+            }
+
+            "bc" | "bcolor" => {
+                let color = Color::parse_pdf_array(rest)
+                    .unwrap_or_else(|| self.current_color().cloned().unwrap_or_else(Color::black));
+                self.color_stack.push(color);
+                self.page.gstate.fill = None;
+                self.page.gstate.stroke = None;
+            }
+
+            "ec" | "ecolor" => {
+                self.color_stack.pop();
+                self.page.gstate.fill = None;
+                self.page.gstate.stroke = None;
+            }
+
+            // Page sizes are taken from `papersize` specials.
+            "pagesize" => {}
+
+            _ => {
+                self.warn_once(
+                    &format!("pdf:{}", cmd),
+                    &format!("ignoring unsupported special \"pdf:{}\" in SVG output", cmd),
+                );
+            }
+        }
+    }
+
+    fn concat(&mut self, m: [f64; 6]) {
+        self.page.gstate.ctm = multiply(&m, &self.page.gstate.ctm);
+    }
+
+    /// Interpret a sequence of PDF content-stream operators. Only path
+    /// construction and painting, the graphics state operators that affect
+    /// them, and the simple color operators are supported.
+    fn run_pdf_ops(&mut self, ops: &str) {
+        let mut operands: Vec<Operand> = Vec::new();
+        let mut array: Option<Vec<f64>> = None;
+        let mut path = String::new();
+        let mut points: Vec<(f64, f64)> = Vec::new();
+        let mut cur = (0.0, 0.0);
+
+        for token in tokenize(ops) {
+            if token == "[" {
+                array = Some(Vec::new());
+                continue;
+            }
+
+            if token == "]" {
+                if let Some(a) = array.take() {
+                    operands.push(Operand::Array(a));
+                }
+                continue;
+            }
+
+            if let Ok(n) = token.parse::<f64>() {
+                match array {
+                    Some(ref mut a) => a.push(n),
+                    None => operands.push(Operand::Number(n)),
+                }
+                continue;
+            }
+
+            if token.starts_with('/') || token.starts_with('(') || token.starts_with('<') {
+                operands.push(Operand::Other);
+                continue;
+            }
+
+            let nums: Vec<f64> = operands
+                .iter()
+                .filter_map(|o| match o {
+                    Operand::Number(n) => Some(*n),
+                    _ => None,
+                })
+                .collect();
+            let gs = &mut self.page.gstate;
+            let mut paint = None;
+
+            match (token, nums.len()) {
+                ("q", _) => self.page.gstack.push(gs.clone()),
+                ("Q", _) => {
+                    if let Some(g) = self.page.gstack.pop() {
+                        self.page.gstate = g;
+                    }
+                }
+                ("cm", 6) => {
+                    let m = [nums[0], nums[1], nums[2], nums[3], nums[4], nums[5]];
+                    gs.ctm = multiply(&m, &gs.ctm);
+                }
+                ("m", 2) => {
+                    cur = (nums[0], nums[1]);
+                    let _ = write!(path, "M{} {} ", num(nums[0]), num(nums[1]));
+                    points.push(cur);
+                }
+                ("l", 2) => {
+                    cur = (nums[0], nums[1]);
+                    let _ = write!(path, "L{} {} ", num(nums[0]), num(nums[1]));
+                    points.push(cur);
+                }
+                ("c", 6) => {
+                    cur = (nums[4], nums[5]);
+                    path.push('C');
+                    for p in nums.chunks(2) {
+                        let _ = write!(path, "{} {} ", num(p[0]), num(p[1]));
+                        points.push((p[0], p[1]));
+                    }
+                }
+                ("v", 4) => {
+                    let _ = write!(path, "C{} {} ", num(cur.0), num(cur.1));
+                    for p in nums.chunks(2) {
+                        let _ = write!(path, "{} {} ", num(p[0]), num(p[1]));
+                        points.push((p[0], p[1]));
+                    }
+                    cur = (nums[2], nums[3]);
+                }
+                ("y", 4) => {
+                    path.push('C');
+                    for p in nums.chunks(2) {
+                        let _ = write!(path, "{} {} ", num(p[0]), num(p[1]));
+                        points.push((p[0], p[1]));
+                    }
+                    let _ = write!(path, "{} {} ", num(nums[2]), num(nums[3]));
+                    cur = (nums[2], nums[3]);
+                }
+                ("h", _) => path.push_str("Z "),
+                ("re", 4) => {
+                    let (x, y, w, h) = (nums[0], nums[1], nums[2], nums[3]);
+                    let _ = write!(
+                        path,
+                        "M{} {} h{} v{} h{} Z ",
+                        num(x),
+                        num(y),
+                        num(w),
+                        num(h),
+                        num(-w)
+                    );
+                    points.push((x, y));
+                    points.push((x + w, y + h));
+                    cur = (x, y);
+                }
+                ("f", _) | ("F", _) => paint = Some((true, false, false, false)),
+                ("f*", _) => paint = Some((true, false, true, false)),
+                ("S", _) => paint = Some((false, true, false, false)),
+                ("s", _) => paint = Some((false, true, false, true)),
+                ("B", _) => paint = Some((true, true, false, false)),
+                ("B*", _) => paint = Some((true, true, true, false)),
+                ("b", _) => paint = Some((true, true, false, true)),
+                ("b*", _) => paint = Some((true, true, true, true)),
+                ("n", _) => {
+                    path.clear();
+                    points.clear();
+                }
+                ("W", _) | ("W*", _) => {
+                    self.warn_once("pdf-op:W", "clipping is not supported in SVG output");
+                }
+                ("w", 1) => gs.line_width = nums[0],
+                ("J", 1) => gs.line_cap = nums[0] as u8,
+                ("j", 1) => gs.line_join = nums[0] as u8,
+                ("M", 1) => gs.miter_limit = nums[0],
+                ("d", _) => {
+                    gs.dash = match (operands.first(), nums.first()) {
+                        (Some(Operand::Array(a)), Some(phase)) if !a.is_empty() => {
+                            Some((a.clone(), *phase))
+                        }
+                        _ => None,
+                    };
+                }
+                ("g", 1) => gs.fill = Some(Color::gray(nums[0])),
+                ("G", 1) => gs.stroke = Some(Color::gray(nums[0])),
+                ("rg", 3) => gs.fill = Some(Color::rgb(nums[0], nums[1], nums[2])),
+                ("RG", 3) => gs.stroke = Some(Color::rgb(nums[0], nums[1], nums[2])),
+                ("k", 4) => gs.fill = Some(Color::cmyk(nums[0], nums[1], nums[2], nums[3])),
+                ("K", 4) => gs.stroke = Some(Color::cmyk(nums[0], nums[1], nums[2], nums[3])),
+                (op, _) => {
+                    self.warn_once(
+                        &format!("pdf-op:{}", op),
+                        &format!("ignoring unsupported PDF operator \"{}\" in SVG output", op),
+                    );
+                }
+            }
+
+            operands.clear();
+
+            if let Some((fill, stroke, even_odd, close)) = paint {
+                if close {
+                    path.push_str("Z ");
+                }
+
+                self.paint_path(&path, &points, fill, stroke, even_odd);
+                path.clear();
+                points.clear();
+            }
+        }
+    }
+
+    fn paint_path(
+        &mut self,
+        path: &str,
+        points: &[(f64, f64)],
+        fill: bool,
+        stroke: bool,
+        even_odd: bool,
+    ) {
+        if path.is_empty() {
+            return;
+        }
+
+        // From PDF user space to PDF page space to SVG.
+        let h = self.paper_height();
+        let m = multiply(&self.page.gstate.ctm, &[1.0, 0.0, 0.0, -1.0, 0.0, h]);
+
+        for (x, y) in points {
+            let (px, py) = apply(&m, *x, *y);
+            self.page.bbox.add(px, py);
+        }
+
+        let gs = &self.page.gstate;
+        let tex_color = self.color_stack.last();
+        let mut attrs = String::new();
+
+        if fill {
+            if let Some(c) = gs.fill.as_ref().or(tex_color) {
+                attrs.push_str(&c.fill_attrs());
+            }
+
+            if even_odd {
+                attrs.push_str(" fill-rule=\"evenodd\"");
+            }
+        } else {
+            attrs.push_str(" fill=\"none\"");
+        }
+
+        if stroke {
+            let color = gs.stroke.as_ref().or(tex_color);
+            let _ = write!(
+                attrs,
+                " stroke=\"{}\" stroke-width=\"{}\"",
+                color.map_or_else(|| "#000000".to_owned(), |c| c.hex()),
+                num(gs.line_width)
+            );
+
+            if let Some(c) = color {
+                if c.alpha < 1.0 {
+                    let _ = write!(attrs, " stroke-opacity=\"{}\"", num(c.alpha));
+                }
+            }
+
+            attrs.push_str(match gs.line_cap {
+                1 => " stroke-linecap=\"round\"",
+                2 => " stroke-linecap=\"square\"",
+                _ => "",
+            });
+            attrs.push_str(match gs.line_join {
+                1 => " stroke-linejoin=\"round\"",
+                2 => " stroke-linejoin=\"bevel\"",
+                _ => "",
+            });
+
+            if (gs.miter_limit - 4.0).abs() > 1e-6 && gs.miter_limit >= 1.0 {
+                let _ = write!(attrs, " stroke-miterlimit=\"{}\"", num(gs.miter_limit));
+            }
+
+            if let Some((ref dashes, phase)) = gs.dash {
+                let list: Vec<String> = dashes.iter().map(|d| num(*d)).collect();
+                let _ = write!(
+                    attrs,
+                    " stroke-dasharray=\"{}\" stroke-dashoffset=\"{}\"",
+                    list.join(" "),
+                    num(phase)
+                );
+            }
+        }
+
+        let _ = writeln!(
+            self.page.body,
+            "<path d=\"{}\" transform=\"{}\"{}/>",
+            path.trim_end(),
+            matrix_attr(&m),
+            attrs
+        );
+    }
+}
+
+impl<'a, 'b: 'a> XdvEvents for State<'a, 'b> {
+    type Error = Error;
+
+    fn handle_begin_page(&mut self, _counters: &[i32], _previous_bop: i32) -> Result<()> {
+        self.page = Page::default();
+        Ok(())
+    }
+
+    fn handle_end_page(&mut self) -> Result<()> {
+        self.finish_page();
+        Ok(())
+    }
+
+    fn handle_font_definition(&mut self, font_num: i32, def: &FontDefinition) -> Result<()> {
+        if self.fonts.contains_key(&font_num) {
+            return Ok(());
+        }
+
+        // We can only draw traditional fonts if there's a Type 1 version
+        // with the same name, as there is for the common ones.
+        let mut font = Font::unloaded(def.scale_factor as f64);
+        let face = self.open_face(&format!("{}.pfb", def.name), 0);
+
+        match face {
+            Some(face) => {
+                if let Some(upem) = Self::prepare_face(&face) {
+                    let raw = face.raw() as *const _ as FT_Face;
+
+                    unsafe {
+                        if FT_Select_Charmap(raw, FT_ENCODING_ADOBE_CUSTOM) != 0 {
+                            FT_Select_Charmap(raw, FT_ENCODING_ADOBE_STANDARD);
+                        }
+                    }
+
+                    font.units_per_em = upem;
+                    font.face = Some(face);
+                }
+            }
+
+            None => {
+                let name = def.name.clone();
+                self.warn_once(
+                    &format!("font:{}", name),
+                    &format!(
+                        "no outlines for font \"{}\"; its characters will be missing from the SVG output",
+                        name
+                    ),
+                );
+            }
+        }
+
+        self.fonts.insert(font_num, font);
+        Ok(())
+    }
+
+    fn handle_native_font_definition(
+        &mut self,
+        font_num: i32,
+        def: &NativeFontDefinition,
+    ) -> Result<()> {
+        if self.fonts.contains_key(&font_num) {
+            return Ok(());
+        }
+
+        let mut font = Font::unloaded(def.size as f64);
+        font.extend = def.extend.map_or(1.0, |e| e as f64 / 65536.0);
+        font.slant = def.slant.map_or(0.0, |s| s as f64 / 65536.0);
+        font.color = def.color_rgba.map(Color::from_rgba);
+
+        if def.vertical {
+            self.warn_once(
+                "vertical",
+                "vertical text is drawn horizontally in SVG output",
+            );
+        }
+
+        if def.embolden.is_some() {
+            self.warn_once(
+                "embolden",
+                "emboldened fonts are drawn at normal weight in SVG output",
+            );
+        }
+
+        match self.open_face(&def.path, def.face_index) {
+            Some(mut face) => {
+                if !def.variations.is_empty() {
+                    set_variations(&self.library, &mut face, &def.variations);
+                }
+
+                if let Some(upem) = Self::prepare_face(&face) {
+                    font.units_per_em = upem;
+                    font.face = Some(face);
+                }
+            }
+
+            None => {
+                let path = def.path.clone();
+                self.warn_once(
+                    &format!("font:{}", path),
+                    &format!(
+                        "couldn't load font \"{}\"; its glyphs will be missing from the SVG output",
+                        path
+                    ),
+                );
+            }
+        }
+
+        self.fonts.insert(font_num, font);
+        Ok(())
+    }
+
+    fn char_width(&mut self, font_num: i32, char_num: i32) -> Result<i32> {
+        let font = match self.fonts.get_mut(&font_num) {
+            Some(f) => f,
+            None => return Ok(0),
+        };
+
+        let gid = match font.char_glyph(char_num) {
+            Some(g) => g,
+            None => return Ok(0),
+        };
+
+        let (size, upem) = (font.size, font.units_per_em);
+        Ok(font
+            .glyph(gid)
+            .map_or(0, |g| (g.advance * size / upem).round() as i32))
+    }
+
+    fn handle_char(&mut self, font_num: i32, char_num: i32, h: i32, v: i32) -> Result<()> {
+        let gid = match self
+            .fonts
+            .get(&font_num)
+            .and_then(|f| f.char_glyph(char_num))
+        {
+            Some(g) => g,
+            None => return Ok(()),
+        };
+
+        let (x, y) = Self::to_page(h, v);
+        self.begin_text_group(font_num);
+        self.draw_glyph(font_num, gid, x, y);
+        self.page.body.push_str("</g>\n");
+        Ok(())
+    }
+
+    fn handle_glyph_run(&mut self, font_num: i32, run: &GlyphRun) -> Result<()> {
+        if run.glyphs.is_empty() {
+            return Ok(());
+        }
+
+        self.begin_text_group(font_num);
+
+        for ((gid, h), v) in run.glyphs.iter().zip(run.h).zip(run.v) {
+            let (x, y) = Self::to_page(*h, *v);
+            self.draw_glyph(font_num, u32::from(*gid), x, y);
+        }
+
+        self.page.body.push_str("</g>\n");
+        Ok(())
+    }
+
+    fn handle_rule(&mut self, h: i32, v: i32, height: i32, width: i32) -> Result<()> {
+        if height <= 0 || width <= 0 {
+            return Ok(());
+        }
+
+        let (x0, y1) = Self::to_page(h, v);
+        let (x1, y0) = Self::to_page(h.saturating_add(width), v.saturating_sub(height));
+        self.page.bbox.add(x0, y0);
+        self.page.bbox.add(x1, y1);
+
+        let attrs = Self::fill_attr(self.current_color());
+        let _ = writeln!(
+            self.page.body,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"{}/>",
+            num(x0),
+            num(y0),
+            num(x1 - x0),
+            num(y1 - y0),
+            attrs
+        );
+        Ok(())
+    }
+
+    fn handle_positioned_special(&mut self, h: i32, v: i32, contents: &[u8]) -> Result<()> {
+        let text = String::from_utf8_lossy(contents);
+        let text = text.trim_start();
+
+        if let Some(args) = text.strip_prefix("color ") {
+            self.handle_color_special(args);
+        } else if let Some(spec) = text.strip_prefix("papersize=") {
+            match parse_papersize(spec) {
+                Some(p) => self.paper = Some(p),
+                None => self.warn_once("papersize", "couldn't parse papersize special"),
+            }
+        } else if let Some(body) = text.strip_prefix("pdf:") {
+            self.handle_pdf_special(h, v, body);
+        } else {
+            let prefix: String = text
+                .chars()
+                .take_while(|c| !c.is_whitespace() && *c != '=')
+                .take(32)
+                .collect();
+            self.warn_once(
+                &format!("special:{}", prefix),
+                &format!("ignoring unsupported special \"{}\" in SVG output", prefix),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Apply OpenType variation settings to a FreeType face. Axes that aren't
+/// mentioned keep their default values.
+fn set_variations(library: &Library, face: &mut Face, variations: &[(u32, i32)]) {
+    #[allow(non_camel_case_types)]
+    #[repr(C)]
+    struct FT_Var_Axis {
+        name: *mut FT_String,
+        minimum: FT_Fixed,
+        def: FT_Fixed,
+        maximum: FT_Fixed,
+        tag: FT_ULong,
+        strid: FT_UInt,
+    }
+
+    #[allow(non_camel_case_types)]
+    #[repr(C)]
+    struct FT_MM_Var {
+        num_axis: FT_UInt,
+        num_designs: FT_UInt,
+        num_namedstyles: FT_UInt,
+        axis: *mut FT_Var_Axis,
+        namedstyle: *mut libc::c_void,
+    }
+
+    extern "C" {
+        fn FT_Get_MM_Var(face: FT_Face, amaster: *mut *mut FT_MM_Var) -> FT_Error;
+        fn FT_Done_MM_Var(library: FT_Library, amaster: *mut FT_MM_Var) -> FT_Error;
+        fn FT_Set_Var_Design_Coordinates(
+            face: FT_Face,
+            num_coords: FT_UInt,
+            coords: *mut FT_Fixed,
+        ) -> FT_Error;
+    }
+
+    let raw = face.raw_mut() as *mut _ as FT_Face;
+
+    unsafe {
+        let mut mm: *mut FT_MM_Var = ptr::null_mut();
+
+        if FT_Get_MM_Var(raw, &mut mm) != 0 || mm.is_null() {
+            return;
+        }
+
+        let axes = slice::from_raw_parts((*mm).axis, (*mm).num_axis as usize);
+        let mut coords: Vec<FT_Fixed> = axes
+            .iter()
+            .map(|axis| {
+                let value = variations
+                    .iter()
+                    .rev()
+                    .find(|(tag, _)| *tag as FT_ULong == axis.tag)
+                    .map_or(axis.def, |(_, value)| *value as FT_Fixed);
+                value.max(axis.minimum).min(axis.maximum)
+            })
+            .collect();
+
+        FT_Set_Var_Design_Coordinates(raw, coords.len() as FT_UInt, coords.as_mut_ptr());
+        FT_Done_MM_Var(library.raw(), mm);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Color {
+    pub(crate) r: f64,
+    pub(crate) g: f64,
+    pub(crate) b: f64,
+    pub(crate) alpha: f64,
+}
+
+impl Color {
+    pub(crate) fn black() -> Self {
+        Color::gray(0.0)
+    }
+
+    fn gray(g: f64) -> Self {
+        Color::rgb(g, g, g)
+    }
+
+    fn rgb(r: f64, g: f64, b: f64) -> Self {
+        Color {
+            r,
+            g,
+            b,
+            alpha: 1.0,
+        }
+    }
+
+    fn cmyk(c: f64, m: f64, y: f64, k: f64) -> Self {
+        Color::rgb(
+            (1.0 - c) * (1.0 - k),
+            (1.0 - m) * (1.0 - k),
+            (1.0 - y) * (1.0 - k),
+        )
+    }
+
+    pub(crate) fn from_rgba(rgba: u32) -> Self {
+        Color {
+            r: (rgba >> 24) as f64 / 255.0,
+            g: ((rgba >> 16) & 0xFF) as f64 / 255.0,
+            b: ((rgba >> 8) & 0xFF) as f64 / 255.0,
+            alpha: (rgba & 0xFF) as f64 / 255.0,
+        }
+    }
+
+    /// Parse a color in the syntax of the `color` special.
+    pub(crate) fn parse(spec: &str) -> Option<Self> {
+        let mut words = spec.split_whitespace();
+        let model = words.next()?;
+        let values: Vec<f64> = words.map(|w| w.parse().ok()).collect::<Option<_>>()?;
+
+        match (model, values.len()) {
+            ("gray", 1) => Some(Color::gray(values[0])),
+            ("rgb", 3) => Some(Color::rgb(values[0], values[1], values[2])),
+            ("cmyk", 4) => Some(Color::cmyk(values[0], values[1], values[2], values[3])),
+            ("hsb", 3) => Some(Color::hsb(values[0], values[1], values[2])),
+            (name, 0) => match name {
+                "Black" => Some(Color::black()),
+                "White" => Some(Color::gray(1.0)),
+                "Red" => Some(Color::rgb(1.0, 0.0, 0.0)),
+                "Green" => Some(Color::rgb(0.0, 1.0, 0.0)),
+                "Blue" => Some(Color::rgb(0.0, 0.0, 1.0)),
+                "Cyan" => Some(Color::cmyk(1.0, 0.0, 0.0, 0.0)),
+                "Magenta" => Some(Color::cmyk(0.0, 1.0, 0.0, 0.0)),
+                "Yellow" => Some(Color::cmyk(0.0, 0.0, 1.0, 0.0)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Parse a color given as a PDF array, as in `pdf:bc [1 0 0]`.
+    pub(crate) fn parse_pdf_array(spec: &str) -> Option<Self> {
+        let inner = spec.trim().strip_prefix('[')?.split(']').next()?;
+        let values: Vec<f64> = inner
+            .split_whitespace()
+            .map(|w| w.parse().ok())
+            .collect::<Option<_>>()?;
+
+        match values.len() {
+            1 => Some(Color::gray(values[0])),
+            3 => Some(Color::rgb(values[0], values[1], values[2])),
+            4 => Some(Color::cmyk(values[0], values[1], values[2], values[3])),
+            _ => None,
+        }
+    }
+
+    fn hsb(h: f64, s: f64, b: f64) -> Self {
+        let h6 = (h - h.floor()) * 6.0;
+        let i = h6.floor();
+        let f = h6 - i;
+        let (p, q, t) = (b * (1.0 - s), b * (1.0 - s * f), b * (1.0 - s * (1.0 - f)));
+
+        match i as u8 {
+            0 => Color::rgb(b, t, p),
+            1 => Color::rgb(q, b, p),
+            2 => Color::rgb(p, b, t),
+            3 => Color::rgb(p, q, b),
+            4 => Color::rgb(t, p, b),
+            _ => Color::rgb(b, p, q),
+        }
+    }
+
+    fn hex(&self) -> String {
+        let c = |x: f64| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+        format!("#{:02x}{:02x}{:02x}", c(self.r), c(self.g), c(self.b))
+    }
+
+    fn fill_attrs(&self) -> String {
+        if self.alpha < 1.0 {
+            format!(
+                " fill=\"{}\" fill-opacity=\"{}\"",
+                self.hex(),
+                num(self.alpha)
+            )
+        } else {
+            format!(" fill=\"{}\"", self.hex())
+        }
+    }
+}
+
+/// A bounding box as [x0, y0, x1, y1], empty until a point is added.
+#[derive(Clone, Copy, Debug, Default)]
+struct BBox(Option<[f64; 4]>);
+
+impl BBox {
+    fn add(&mut self, x: f64, y: f64) {
+        self.0 = Some(match self.0 {
+            None => [x, y, x, y],
+            Some([x0, y0, x1, y1]) => [x0.min(x), y0.min(y), x1.max(x), y1.max(y)],
+        });
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Number(f64),
+    Array(Vec<f64>),
+    Other,
+}
+
+const IDENTITY: [f64; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// Compose two transforms, applying *a* first.
+fn multiply(a: &[f64; 6], b: &[f64; 6]) -> [f64; 6] {
+    [
+        a[0] * b[0] + a[1] * b[2],
+        a[0] * b[1] + a[1] * b[3],
+        a[2] * b[0] + a[3] * b[2],
+        a[2] * b[1] + a[3] * b[3],
+        a[4] * b[0] + a[5] * b[2] + b[4],
+        a[4] * b[1] + a[5] * b[3] + b[5],
+    ]
+}
+
+fn apply(m: &[f64; 6], x: f64, y: f64) -> (f64, f64) {
+    (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5])
+}
+
+fn matrix_attr(m: &[f64; 6]) -> String {
+    format!(
+        "matrix({} {} {} {} {} {})",
+        num(m[0]),
+        num(m[1]),
+        num(m[2]),
+        num(m[3]),
+        num(m[4]),
+        num(m[5])
+    )
+}
+
+/// Format a number compactly, with at most four decimal places.
+fn num(x: f64) -> String {
+    let s = format!("{:.4}", x);
+    let s = if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &s
+    };
+
+    if s == "-0" {
+        "0".to_owned()
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Split PDF content-stream text into tokens. Strings, hex strings and
+/// dictionaries are returned as single (unsupported) tokens.
+fn tokenize(ops: &str) -> Vec<&str> {
+    let bytes = ops.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c == b'%' {
+            while i < bytes.len() && bytes[i] != b'\n' && bytes[i] != b'\r' {
+                i += 1;
+            }
+        } else if c == b'[' || c == b']' {
+            tokens.push(&ops[i..i + 1]);
+            i += 1;
+        } else if c == b'(' || c == b'<' {
+            let (open, close) = if c == b'(' {
+                (b'(', b')')
+            } else {
+                (b'<', b'>')
+            };
+            let start = i;
+            let mut depth = 0;
+
+            while i < bytes.len() {
+                if bytes[i] == b'\\' && open == b'(' {
+                    i += 2;
+                    continue;
+                }
+                if bytes[i] == open {
+                    depth += 1;
+                } else if bytes[i] == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        i += 1;
+                        break;
+                    }
+                }
+                i += 1;
+            }
+
+            tokens.push(&ops[start..i.min(bytes.len())]);
+        } else {
+            let start = i;
+
+            while i < bytes.len()
+                && !bytes[i].is_ascii_whitespace()
+                && !b"[]()<>%".contains(&bytes[i])
+                && (i == start || bytes[i] != b'/')
+            {
+                i += 1;
+            }
+
+            tokens.push(&ops[start..i]);
+        }
+    }
+
+    tokens
+}
+
+/// Parse the argument of a `papersize=W,H` special into big points.
+pub(crate) fn parse_papersize(spec: &str) -> Option<(f64, f64)> {
+    let mut parts = spec.trim().splitn(2, ',');
+    let w = parse_length(parts.next()?)?;
+    let h = parse_length(parts.next()?)?;
+    Some((w, h))
+}
+
+fn parse_length(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let s = s.strip_prefix("true").unwrap_or(s);
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(s.len());
+    let value: f64 = s[..split].parse().ok()?;
+    let unit = s[split..].trim();
+    let unit = unit.strip_prefix("true").unwrap_or(unit);
+
+    let factor = match unit {
+        "pt" => 72.0 / 72.27,
+        "bp" | "" => 1.0,
+        "in" => 72.0,
+        "cm" => 72.0 / 2.54,
+        "mm" => 72.0 / 25.4,
+        "pc" => 12.0 * 72.0 / 72.27,
+        "dd" => 1238.0 / 1157.0 * 72.0 / 72.27,
+        "cc" => 12.0 * 1238.0 / 1157.0 * 72.0 / 72.27,
+        "sp" => SP_TO_BP,
+        _ => return None,
+    };
+
+    Some(value * factor)
+}
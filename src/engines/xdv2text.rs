@@ -0,0 +1,1039 @@
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Extract Unicode text from XDV files.
+//!
+//! Working from the XDV rather than the final PDF means that we know exactly
+//! which font and glyph produced each piece of text, and for native fonts
+//! XeTeX usually records the original characters of each word alongside its
+//! glyphs. Where it doesn't, glyphs are mapped back to Unicode by the same
+//! code that builds xdvipdfmx's ToUnicode CMaps: for native fonts, the
+//! inverted Unicode cmap and then the glyph names; for traditional TeX
+//! fonts, the glyph names of the built-in encoding of their Type 1 versions.
+//! Ligatures come out as their component letters.
+//!
+//! The page contents are grouped into lines, words and paragraphs based on
+//! their positions. Words hyphenated across line breaks are rejoined in the
+//! text output.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fmt::Write as FmtWrite;
+use std::io::{Read, Write};
+use tectonic_engine::T1GlyphText;
+use tectonic_xdv::{FontDefinition, GlyphRun, NativeFontDefinition, XdvEvents, XdvParser};
+
+use super::xdv2svg::{parse_papersize, ORIGIN_BP, SP_TO_BP};
+use super::{
+    EngineKind, ExecutionState, IoEventBackend, NoopIoEventBackend, NoopProgressEventBackend,
+    TectonicBridgeApi,
+};
+use crate::errors::{Error, Result};
+use crate::io::{IoProvider, IoStack, OpenResult};
+use crate::status::StatusBackend;
+use crate::{errmsg, tt_warning};
+
+pub struct Xdv2TextEngine {
+    word_boxes: bool,
+    dehyphenate: bool,
+}
+
+impl Default for Xdv2TextEngine {
+    fn default() -> Self {
+        Xdv2TextEngine {
+            word_boxes: false,
+            dehyphenate: true,
+        }
+    }
+}
+
+impl Xdv2TextEngine {
+    pub fn new() -> Xdv2TextEngine {
+        Default::default()
+    }
+
+    /// If set to `true`, also write the position of every word on its page
+    /// as JSON, to `<stem>.words.json`.
+    pub fn with_word_boxes(mut self, word_boxes: bool) -> Self {
+        self.word_boxes = word_boxes;
+        self
+    }
+
+    /// If set to `true` (the default), words that were hyphenated at a line
+    /// break are put back together in the text output.
+    pub fn with_dehyphenation(mut self, dehyphenate: bool) -> Self {
+        self.dehyphenate = dehyphenate;
+        self
+    }
+
+    /// Extract the text of the XDV file *xdv* to `<stem>.txt`. Lines are
+    /// separated by newlines, paragraphs by blank lines, and pages by form
+    /// feeds.
+    pub fn process(
+        &mut self,
+        io: &mut IoStack,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+        xdv: &str,
+    ) -> Result<()> {
+        let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
+
+        let mut input = io.input_open_name(OsStr::new(xdv), status).must_exist()?;
+        events.input_opened(input.name(), input.origin());
+
+        let stem = xdv.strip_suffix(".xdv").unwrap_or(xdv);
+
+        let (pages, paper) = {
+            let state = State::new(io, status);
+            let (state, _n_bytes) = XdvParser::process(&mut input, state)?;
+            (state.pages, state.paper)
+        };
+
+        let (name, digest_opt) = input.into_name_digest();
+        events.input_closed(name, digest_opt);
+
+        let mut text = String::new();
+
+        for (i, page) in pages.iter().enumerate() {
+            if i > 0 {
+                text.push('\x0c');
+            }
+
+            page.write_text(&mut text, self.dehyphenate);
+        }
+
+        write_output(io, events, &format!("{}.txt", stem), text.as_bytes())?;
+
+        if self.word_boxes {
+            let json = words_json(&pages, paper);
+            write_output(io, events, &format!("{}.words.json", stem), json.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_output(
+    io: &mut IoStack,
+    events: &mut dyn IoEventBackend,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let mut output = match io.output_open_name(OsStr::new(name)) {
+        OpenResult::Ok(h) => h,
+        OpenResult::NotAvailable => {
+            return Err(errmsg!("no way to write output file \"{}\"", name));
+        }
+        OpenResult::Err(e) => {
+            return Err(e);
+        }
+    };
+
+    events.output_opened(OsStr::new(name));
+    output.write_all(data)?;
+    let (name, digest) = output.into_name_digest();
+    events.output_closed(name, digest);
+    Ok(())
+}
+
+/// A piece of text with a position on the page, in DVI units.
+#[derive(Clone, Debug)]
+struct Item {
+    text: String,
+    x0: i32,
+    x1: i32,
+    baseline: i32,
+    top: i32,
+    bottom: i32,
+    size: i32,
+    /// Whether this is a standalone TeX accent that should be attached to
+    /// the character it's placed over.
+    accent: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Word {
+    text: String,
+    x0: i32,
+    x1: i32,
+    top: i32,
+    bottom: i32,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Line {
+    words: Vec<Word>,
+    baseline: i32,
+    size: i32,
+    starts_paragraph: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+struct PageText {
+    lines: Vec<Line>,
+}
+
+impl PageText {
+    /// Group the items of a page, in the order that they were typeset, into
+    /// words, lines and paragraphs.
+    fn from_items(items: Vec<Item>) -> Self {
+        let items = attach_accents(items);
+        let mut lines: Vec<Line> = Vec::new();
+        let mut last: Option<&Item> = None;
+
+        for item in &items {
+            let new_line = match last {
+                None => true,
+                Some(prev) => {
+                    let size = prev.size.max(item.size).max(1);
+                    (item.baseline - prev.baseline).abs() > size * 3 / 5
+                        || item.x0 < prev.x1 - size / 2
+                }
+            };
+
+            if new_line {
+                lines.push(Line {
+                    baseline: item.baseline,
+                    size: item.size,
+                    ..Line::default()
+                });
+            }
+
+            let line = lines.last_mut().unwrap();
+            line.size = line.size.max(item.size);
+
+            // Interword spaces in TeX are at least a fifth of an em or so,
+            // even when shrunk; kerns are much smaller.
+            let space_before = match last {
+                Some(prev) if !new_line => {
+                    item.x0 - prev.x1 > prev.size.min(item.size).max(1) / 8
+                        || item.text.starts_with(char::is_whitespace)
+                }
+                _ => true,
+            };
+
+            let mut first = true;
+
+            for (text, x0, x1) in split_item(item) {
+                let word = Word {
+                    text: text.to_owned(),
+                    x0,
+                    x1,
+                    top: item.top,
+                    bottom: item.bottom,
+                };
+
+                match line.words.last_mut() {
+                    Some(w) if first && !space_before => {
+                        w.text.push_str(&word.text);
+                        w.x0 = w.x0.min(word.x0);
+                        w.x1 = w.x1.max(word.x1);
+                        w.top = w.top.min(word.top);
+                        w.bottom = w.bottom.max(word.bottom);
+                    }
+                    _ => line.words.push(word),
+                }
+
+                first = false;
+            }
+
+            last = Some(item);
+        }
+
+        lines.retain(|l| !l.words.is_empty());
+        mark_paragraphs(&mut lines);
+        PageText { lines }
+    }
+
+    fn write_text(&self, out: &mut String, dehyphenate: bool) {
+        let mut texts: Vec<String> = self
+            .lines
+            .iter()
+            .map(|l| {
+                l.words
+                    .iter()
+                    .map(|w| w.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+
+        // Move the rest of a hyphenated word up to the line where it
+        // started.
+        if dehyphenate {
+            for i in 1..texts.len() {
+                if self.lines[i].starts_paragraph || !ends_with_hyphen(&texts[i - 1]) {
+                    continue;
+                }
+
+                let next = texts[i].clone();
+                let (head, tail) = match next.find(' ') {
+                    Some(j) => (&next[..j], next[j + 1..].to_owned()),
+                    None => (&next[..], String::new()),
+                };
+
+                if !matches!(head.chars().next(), Some(c) if c.is_lowercase()) {
+                    continue;
+                }
+
+                texts[i - 1].pop();
+                texts[i - 1].push_str(head);
+                texts[i] = tail;
+            }
+        }
+
+        for (i, (line, text)) in self.lines.iter().zip(&texts).enumerate() {
+            if i > 0 && line.starts_paragraph {
+                out.push('\n');
+            }
+
+            // A line can be left empty if it only held the end of a
+            // hyphenated word.
+            if !text.is_empty() {
+                out.push_str(text);
+                out.push('\n');
+            }
+        }
+    }
+}
+
+fn ends_with_hyphen(text: &str) -> bool {
+    let mut chars = text.chars().rev();
+    chars.next() == Some('-') && matches!(chars.next(), Some(c) if c.is_alphabetic())
+}
+
+/// Split an item at any whitespace in its text. Each piece gets a share of
+/// the item's width in proportion to its length.
+fn split_item(item: &Item) -> Vec<(&str, i32, i32)> {
+    let n_chars = item.text.chars().count().max(1) as i64;
+    let width = i64::from(item.x1 - item.x0);
+    let x_at = |char_index: usize| item.x0 + (width * char_index as i64 / n_chars) as i32;
+    let mut pieces = Vec::new();
+    let mut start: Option<(usize, usize)> = None;
+
+    for (ci, (bi, c)) in item.text.char_indices().enumerate() {
+        if c.is_whitespace() {
+            if let Some((sb, sc)) = start.take() {
+                pieces.push((&item.text[sb..bi], x_at(sc), x_at(ci)));
+            }
+        } else if start.is_none() {
+            start = Some((bi, ci));
+        }
+    }
+
+    if let Some((sb, sc)) = start {
+        pieces.push((&item.text[sb..], x_at(sc), item.x1));
+    }
+
+    pieces
+}
+
+/// TeX places an accent before the character that it goes over. Attach it
+/// to that character as a combining mark.
+fn attach_accents(items: Vec<Item>) -> Vec<Item> {
+    let mut result: Vec<Item> = Vec::with_capacity(items.len());
+    let mut pending: Option<Item> = None;
+
+    for mut item in items {
+        if let Some(accent) = pending.take() {
+            let center = (accent.x0 + accent.x1) / 2;
+
+            if center >= item.x0 && center <= item.x1 && !item.accent {
+                item.text.push_str(&accent.text);
+                item.x0 = item.x0.min(accent.x0);
+                item.top = item.top.min(accent.top);
+                result.push(item);
+                continue;
+            }
+
+            result.push(accent);
+        }
+
+        if item.accent {
+            pending = Some(item);
+        } else {
+            result.push(item);
+        }
+    }
+
+    result.extend(pending);
+    result
+}
+
+/// Decide which lines start new paragraphs: those that follow a larger
+/// vertical gap than usual, and those that are indented relative to the
+/// line before.
+fn mark_paragraphs(lines: &mut [Line]) {
+    let mut gaps: Vec<i32> = lines
+        .windows(2)
+        .map(|w| w[1].baseline - w[0].baseline)
+        .filter(|g| *g > 0)
+        .collect();
+    gaps.sort_unstable();
+    let typical_gap = gaps.get(gaps.len() / 2).cloned();
+    let left_margin = lines
+        .iter()
+        .filter_map(|l| l.words.first().map(|w| w.x0))
+        .min()
+        .unwrap_or(0);
+
+    for i in 0..lines.len() {
+        if i == 0 {
+            lines[i].starts_paragraph = true;
+            continue;
+        }
+
+        let prev = &lines[i - 1];
+        let line = &lines[i];
+        let em = line.size.max(1);
+        let gap = line.baseline - prev.baseline;
+        let x0 = line.words[0].x0;
+        let prev_x0 = prev.words[0].x0;
+
+        let wide_gap = match typical_gap {
+            Some(t) => gap < 0 || i64::from(gap) * 10 > i64::from(t) * 13,
+            None => false,
+        };
+        let indented = x0 - prev_x0 > em / 2 && x0 - left_margin > em / 2;
+
+        lines[i].starts_paragraph = wide_gap || indented;
+    }
+}
+
+/// Format the word boxes of all pages as JSON. Coordinates are in big
+/// points, measured from the top left corner of the paper.
+fn words_json(pages: &[PageText], paper: Option<(f64, f64)>) -> String {
+    let mut out = String::from("{\n  \"pages\": [");
+
+    for (i, page) in pages.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+
+        let _ = write!(out, "\n    {{\n      \"page\": {},", i + 1);
+
+        if let Some((w, h)) = paper {
+            let _ = write!(
+                out,
+                "\n      \"width\": {},\n      \"height\": {},",
+                num(w),
+                num(h)
+            );
+        }
+
+        out.push_str("\n      \"words\": [");
+        let mut first = true;
+        let mut paragraph = 0;
+
+        for (line_num, line) in page.lines.iter().enumerate() {
+            if line.starts_paragraph && line_num > 0 {
+                paragraph += 1;
+            }
+
+            for word in &line.words {
+                if !first {
+                    out.push(',');
+                }
+
+                first = false;
+                let bp = |x: i32| ORIGIN_BP + f64::from(x) * SP_TO_BP;
+                let _ = write!(
+                    out,
+                    "\n        {{\"text\": {}, \"line\": {}, \"paragraph\": {}, \
+                     \"x0\": {}, \"y0\": {}, \"x1\": {}, \"y1\": {}}}",
+                    json_string(&word.text),
+                    line_num,
+                    paragraph,
+                    num(bp(word.x0)),
+                    num(bp(word.top)),
+                    num(bp(word.x1)),
+                    num(bp(word.bottom))
+                );
+            }
+        }
+
+        out.push_str(if first {
+            "]\n    }"
+        } else {
+            "\n      ]\n    }"
+        });
+    }
+
+    out.push_str(if pages.is_empty() {
+        "]\n}\n"
+    } else {
+        "\n  ]\n}\n"
+    });
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// Format a number compactly, with at most two decimal places.
+fn num(x: f64) -> String {
+    let s = format!("{:.2}", x);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+
+    if s == "-0" {
+        "0".to_owned()
+    } else {
+        s.to_owned()
+    }
+}
+
+/// The parts of a TFM file that we need.
+pub(crate) struct Tfm {
+    first_char: i32,
+    /// Width, height and depth of each character, as fractions of the
+    /// design size in units of 2^-20.
+    metrics: Vec<(i32, i32, i32)>,
+}
+
+impl Tfm {
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        let half = |i: usize| -> Option<usize> {
+            Some(usize::from(u16::from_be_bytes([
+                *data.get(2 * i)?,
+                *data.get(2 * i + 1)?,
+            ])))
+        };
+        let word = |i: usize| -> Option<i32> {
+            let b = data.get(4 * i..4 * i + 4)?;
+            Some(i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        };
+
+        let (lh, bc, ec, nw, nh) = (half(1)?, half(2)?, half(3)?, half(4)?, half(5)?);
+        let char_info = 6 + lh;
+        let widths = char_info + (ec + 1).saturating_sub(bc);
+        let heights = widths + nw;
+        let depths = heights + nh;
+        let mut metrics = Vec::new();
+
+        for c in bc..=ec {
+            let info = data.get(4 * (char_info + c - bc)..4 * (char_info + c - bc) + 2)?;
+            let wi = usize::from(info[0]);
+            let (hi, di) = (usize::from(info[1] >> 4), usize::from(info[1] & 0xF));
+
+            if wi == 0 {
+                metrics.push((0, 0, 0));
+            } else {
+                metrics.push((word(widths + wi)?, word(heights + hi)?, word(depths + di)?));
+            }
+        }
+
+        Some(Tfm {
+            first_char: bc as i32,
+            metrics,
+        })
+    }
+
+    /// Get the width, height and depth of a character, scaled to a font
+    /// of size *size*.
+    pub(crate) fn metrics(&self, c: i32, size: i32) -> Option<(i32, i32, i32)> {
+        let (w, h, d) = *self
+            .metrics
+            .get(usize::try_from(c - self.first_char).ok()?)?;
+        let scale = |x: i32| ((i64::from(x) * i64::from(size)) >> 20) as i32;
+        Some((scale(w), scale(h), scale(d)))
+    }
+}
+
+struct Font {
+    size: i32,
+    tfm: Option<Tfm>,
+    /// Ascender and descender as fractions of an em, for glyphs whose
+    /// extents we don't know.
+    ascent: f64,
+    descent: f64,
+    /// For traditional TeX fonts, the glyph name and text of each character.
+    encoding: Option<Vec<Option<T1GlyphText>>>,
+    /// For native fonts, the text of each glyph.
+    glyph_text: HashMap<u16, String>,
+}
+
+impl Font {
+    fn new(size: i32) -> Self {
+        Font {
+            size,
+            tfm: None,
+            ascent: 0.75,
+            descent: 0.25,
+            encoding: None,
+            glyph_text: HashMap::new(),
+        }
+    }
+
+    /// Get the text of character *c* of a traditional TeX font, and whether
+    /// it is an accent.
+    fn char_text(&self, c: i32) -> (String, bool) {
+        let glyph = self
+            .encoding
+            .as_ref()
+            .and_then(|e| e.get(usize::try_from(c).ok()?))
+            .and_then(Option::as_ref);
+
+        if let Some((name, text)) = glyph {
+            if let Some(mark) = accent_mark(name) {
+                return (mark.to_string(), true);
+            }
+
+            if let Some(text) = text {
+                return (expand_ligatures(text), false);
+            }
+        }
+
+        // Without glyph names, the best we can do is to assume ASCII.
+        match c {
+            0x21..=0x7E => ((c as u8 as char).to_string(), false),
+            _ => (String::new(), false),
+        }
+    }
+
+    /// Get the text of glyph *gid* of a native font.
+    fn glyph_text(&self, gid: u16) -> &str {
+        self.glyph_text.get(&gid).map_or("", |t| t.as_str())
+    }
+}
+
+/// The Adobe Glyph List maps names like `fi` to the ligature characters of
+/// the Alphabetic Presentation Forms block. Spell those out as letters.
+fn expand_ligatures(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\u{FB00}' => out.push_str("ff"),
+            '\u{FB01}' => out.push_str("fi"),
+            '\u{FB02}' => out.push_str("fl"),
+            '\u{FB03}' => out.push_str("ffi"),
+            '\u{FB04}' => out.push_str("ffl"),
+            '\u{FB05}' => out.push_str("\u{017F}t"),
+            '\u{FB06}' => out.push_str("st"),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// If a glyph name is that of a spacing accent, get the corresponding
+/// combining character.
+fn accent_mark(name: &str) -> Option<char> {
+    Some(match name {
+        "grave" => '\u{0300}',
+        "acute" => '\u{0301}',
+        "circumflex" => '\u{0302}',
+        "tilde" => '\u{0303}',
+        "macron" => '\u{0304}',
+        "breve" => '\u{0306}',
+        "dotaccent" => '\u{0307}',
+        "dieresis" => '\u{0308}',
+        "ring" => '\u{030A}',
+        "hungarumlaut" => '\u{030B}',
+        "caron" => '\u{030C}',
+        "cedilla" => '\u{0327}',
+        "ogonek" => '\u{0328}',
+        _ => return None,
+    })
+}
+
+struct State<'a, 'b: 'a> {
+    io: &'a mut IoStack<'b>,
+    status: &'a mut dyn StatusBackend,
+    fonts: HashMap<i32, Font>,
+    items: Vec<Item>,
+    pages: Vec<PageText>,
+    paper: Option<(f64, f64)>,
+    warned: HashSet<String>,
+}
+
+impl<'a, 'b: 'a> State<'a, 'b> {
+    fn new(io: &'a mut IoStack<'b>, status: &'a mut dyn StatusBackend) -> Self {
+        State {
+            io,
+            status,
+            fonts: HashMap::new(),
+            items: Vec::new(),
+            pages: Vec::new(),
+            paper: None,
+            warned: HashSet::new(),
+        }
+    }
+
+    fn warn_once(&mut self, key: &str, message: &str) {
+        if self.warned.insert(key.to_owned()) {
+            tt_warning!(self.status, "{}", message);
+        }
+    }
+
+    fn read_file(&mut self, name: &str) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+
+        match self.io.input_open_name(OsStr::new(name), self.status) {
+            OpenResult::Ok(mut h) => h.read_to_end(&mut data).ok().map(|_| data),
+            _ => None,
+        }
+    }
+
+    /// Run *f* with a bridge to our I/O, so that it can call into the
+    /// xdvipdfmx font code.
+    fn with_bridge<T>(&mut self, f: impl FnOnce(&TectonicBridgeApi) -> T) -> T {
+        let mut events = NoopIoEventBackend::new();
+        let mut progress = NoopProgressEventBackend::new();
+        let state = ExecutionState::new(
+            &mut *self.io,
+            EngineKind::Xdv2Text,
+            &mut events,
+            &mut progress,
+            &mut *self.status,
+        );
+        let bridge = TectonicBridgeApi::new(&state);
+        f(&bridge)
+    }
+
+    fn push_item(&mut self, font_num: i32, text: String, h: i32, v: i32, width: i32) {
+        self.push_item_with_extents(font_num, text, h, v, width, None, false);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push_item_with_extents(
+        &mut self,
+        font_num: i32,
+        text: String,
+        h: i32,
+        v: i32,
+        width: i32,
+        extents: Option<(i32, i32)>,
+        accent: bool,
+    ) {
+        if text.is_empty() {
+            return;
+        }
+
+        let font = match self.fonts.get(&font_num) {
+            Some(f) => f,
+            None => return,
+        };
+
+        let (height, depth) = extents.unwrap_or_else(|| {
+            (
+                (f64::from(font.size) * font.ascent) as i32,
+                (f64::from(font.size) * font.descent) as i32,
+            )
+        });
+
+        self.items.push(Item {
+            text,
+            x0: h,
+            x1: h.saturating_add(width),
+            baseline: v,
+            top: v.saturating_sub(height),
+            bottom: v.saturating_add(depth),
+            size: font.size,
+            accent,
+        });
+    }
+}
+
+impl<'a, 'b: 'a> XdvEvents for State<'a, 'b> {
+    type Error = Error;
+
+    fn handle_begin_page(&mut self, _counters: &[i32], _previous_bop: i32) -> Result<()> {
+        self.items.clear();
+        Ok(())
+    }
+
+    fn handle_end_page(&mut self) -> Result<()> {
+        let items = std::mem::take(&mut self.items);
+        self.pages.push(PageText::from_items(items));
+        Ok(())
+    }
+
+    fn handle_font_definition(&mut self, font_num: i32, def: &FontDefinition) -> Result<()> {
+        if self.fonts.contains_key(&font_num) {
+            return Ok(());
+        }
+
+        let mut font = Font::new(def.scale_factor as i32);
+        font.tfm = self
+            .read_file(&format!("{}.tfm", def.name))
+            .and_then(|d| Tfm::parse(&d));
+
+        // The glyph names in the Type 1 version of the font tell us what its
+        // characters are.
+        let encoding = self.with_bridge(|bridge| unsafe {
+            super::dvipdfmx_type1_encoding_text(&**bridge, &def.name)
+        });
+
+        if encoding.is_some() {
+            font.encoding = encoding;
+        } else {
+            let name = def.name.clone();
+            self.warn_once(
+                &format!("font:{}", name),
+                &format!(
+                    "no glyph names for font \"{}\"; assuming its characters are ASCII",
+                    name
+                ),
+            );
+        }
+
+        self.fonts.insert(font_num, font);
+        Ok(())
+    }
+
+    fn handle_native_font_definition(
+        &mut self,
+        font_num: i32,
+        def: &NativeFontDefinition,
+    ) -> Result<()> {
+        if self.fonts.contains_key(&font_num) {
+            return Ok(());
+        }
+
+        let mut font = Font::new(def.size);
+
+        let glyphs = self.with_bridge(|bridge| unsafe {
+            super::dvipdfmx_otf_glyph_text(&**bridge, &def.path, def.face_index)
+        });
+
+        match glyphs {
+            Some(glyphs) => {
+                if glyphs.ascent > 0. || glyphs.descent > 0. {
+                    font.ascent = glyphs.ascent;
+                    font.descent = glyphs.descent;
+                }
+
+                font.glyph_text = glyphs
+                    .unicodes
+                    .iter()
+                    .map(|(gid, text)| (*gid, expand_ligatures(text)))
+                    .collect();
+            }
+
+            None => {
+                let path = def.path.clone();
+                self.warn_once(
+                    &format!("font:{}", path),
+                    &format!(
+                        "couldn't load font \"{}\"; only text recorded in the XDV file will be extracted",
+                        path
+                    ),
+                );
+            }
+        }
+
+        self.fonts.insert(font_num, font);
+        Ok(())
+    }
+
+    fn char_width(&mut self, font_num: i32, char_num: i32) -> Result<i32> {
+        Ok(self
+            .fonts
+            .get(&font_num)
+            .and_then(|f| f.tfm.as_ref()?.metrics(char_num, f.size))
+            .map_or(0, |m| m.0))
+    }
+
+    fn handle_char(&mut self, font_num: i32, char_num: i32, h: i32, v: i32) -> Result<()> {
+        let (text, accent, metrics) = match self.fonts.get(&font_num) {
+            Some(font) => {
+                let (text, accent) = font.char_text(char_num);
+                let metrics = font
+                    .tfm
+                    .as_ref()
+                    .and_then(|t| t.metrics(char_num, font.size));
+                (text, accent, metrics)
+            }
+            None => return Ok(()),
+        };
+
+        let (width, extents) = match metrics {
+            Some((w, ht, dp)) => (w, Some((ht, dp))),
+            None => (0, None),
+        };
+
+        self.push_item_with_extents(font_num, text, h, v, width, extents, accent);
+        Ok(())
+    }
+
+    fn handle_glyph_run(&mut self, font_num: i32, run: &GlyphRun) -> Result<()> {
+        if run.glyphs.is_empty() {
+            return Ok(());
+        }
+
+        let text = match run.text {
+            Some(t) => String::from_utf16_lossy(t),
+            None => match self.fonts.get(&font_num) {
+                Some(font) => run.glyphs.iter().map(|g| font.glyph_text(*g)).collect(),
+                None => return Ok(()),
+            },
+        };
+
+        self.push_item(font_num, text, run.h[0], run.v[0], run.width);
+        Ok(())
+    }
+
+    fn handle_special(&mut self, contents: &[u8]) -> Result<()> {
+        let text = String::from_utf8_lossy(contents);
+
+        if let Some(spec) = text.trim_start().strip_prefix("papersize=") {
+            self.paper = parse_papersize(spec);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PT: i32 = 65536;
+    const SIZE: i32 = 10 * PT;
+
+    /// An item in a 10pt font, with positions in points.
+    fn item(text: &str, x0: i32, x1: i32, baseline: i32) -> Item {
+        Item {
+            text: text.to_owned(),
+            x0: x0 * PT,
+            x1: x1 * PT,
+            baseline: baseline * PT,
+            top: baseline * PT - 7 * SIZE / 10,
+            bottom: baseline * PT + 2 * SIZE / 10,
+            size: SIZE,
+            accent: false,
+        }
+    }
+
+    fn words(page: &PageText) -> Vec<Vec<&str>> {
+        page.lines
+            .iter()
+            .map(|l| l.words.iter().map(|w| w.text.as_str()).collect())
+            .collect()
+    }
+
+    fn text(page: &PageText, dehyphenate: bool) -> String {
+        let mut out = String::new();
+        page.write_text(&mut out, dehyphenate);
+        out
+    }
+
+    #[test]
+    fn words_and_lines() {
+        let page = PageText::from_items(vec![
+            item("Hello", 0, 25, 100),
+            item("world", 29, 55, 100),
+            // Kerned against the previous item, so part of the same word.
+            item("A", 59, 66, 100),
+            item("V", 65, 72, 100),
+            item("second line", 0, 50, 112),
+        ]);
+
+        assert_eq!(
+            words(&page),
+            vec![vec!["Hello", "world", "AV"], vec!["second", "line"]]
+        );
+        assert_eq!(page.lines[0].words[2].x0, 59 * PT);
+        assert_eq!(page.lines[0].words[2].x1, 72 * PT);
+        assert_eq!(text(&page, true), "Hello world AV\nsecond line\n");
+    }
+
+    #[test]
+    fn lines_start_when_moving_back() {
+        // Same baseline, but the second item starts well to the left of
+        // where the first one ended, as in a two-column layout.
+        let page =
+            PageText::from_items(vec![item("right", 100, 130, 100), item("left", 0, 20, 100)]);
+        assert_eq!(words(&page), vec![vec!["right"], vec!["left"]]);
+    }
+
+    #[test]
+    fn accents_attach_to_the_next_character() {
+        let mut accent = item("\u{301}", 16, 19, 100);
+        accent.accent = true;
+        let page = PageText::from_items(vec![
+            item("caf", 0, 15, 100),
+            accent,
+            item("e", 15, 20, 100),
+        ]);
+        assert_eq!(words(&page), vec![vec!["cafe\u{301}"]]);
+    }
+
+    #[test]
+    fn paragraphs() {
+        let page = PageText::from_items(vec![
+            item("one", 0, 20, 100),
+            item("two", 0, 20, 112),
+            item("three", 0, 20, 124),
+            // A wider gap than usual.
+            item("four", 0, 20, 148),
+            // An indented line.
+            item("five", 15, 35, 160),
+            item("six", 0, 20, 172),
+        ]);
+
+        let starts: Vec<bool> = page.lines.iter().map(|l| l.starts_paragraph).collect();
+        assert_eq!(starts, vec![true, false, false, true, true, false]);
+        assert_eq!(text(&page, true), "one\ntwo\nthree\n\nfour\n\nfive\nsix\n");
+    }
+
+    #[test]
+    fn dehyphenation() {
+        let page = PageText::from_items(vec![
+            item("a con-", 0, 50, 100),
+            item("tinued b", 0, 50, 112),
+            item("hy-", 0, 50, 124),
+            item("phen", 0, 50, 136),
+            item("Smith-", 0, 50, 148),
+            item("Jones 1-", 0, 50, 160),
+            item("2", 0, 50, 172),
+        ]);
+
+        assert_eq!(
+            text(&page, true),
+            "a continued\nb\nhyphen\nSmith-\nJones 1-\n2\n"
+        );
+        assert_eq!(
+            text(&page, false),
+            "a con-\ntinued b\nhy-\nphen\nSmith-\nJones 1-\n2\n"
+        );
+    }
+
+    #[test]
+    fn no_dehyphenation_across_paragraphs() {
+        let page = PageText::from_items(vec![
+            item("well-", 0, 50, 100),
+            item("known", 0, 50, 112),
+            item("ex-", 0, 50, 124),
+            item("ample", 15, 50, 136),
+        ]);
+
+        assert_eq!(text(&page, true), "wellknown\nex-\n\nample\n");
+    }
+
+    #[test]
+    fn ligatures_are_spelled_out() {
+        assert_eq!(expand_ligatures("\u{FB01}nd \u{FB04}"), "find ffl");
+    }
+}
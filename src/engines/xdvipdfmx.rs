@@ -4,14 +4,22 @@
 
 use std::ffi::{CStr, CString};
 
-use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
+use super::{
+    EngineKind, EngineLimits, ExecutionState, IoEventBackend, NoopProgressEventBackend,
+    ProgressEventBackend, TectonicBridgeApi,
+};
 use crate::errors::{ErrorKind, Result};
 use crate::io::IoStack;
 use crate::status::StatusBackend;
 
+pub use tectonic_engine::PdfAConformance;
+
 pub struct XdvipdfmxEngine {
     enable_compression: bool,
     deterministic_tags: bool,
+    pdfa: Option<PdfAConformance>,
+    linearize: bool,
+    limits: EngineLimits,
 }
 
 impl XdvipdfmxEngine {
@@ -19,6 +27,9 @@ impl XdvipdfmxEngine {
         XdvipdfmxEngine {
             enable_compression: true,
             deterministic_tags: false,
+            pdfa: None,
+            linearize: false,
+            limits: EngineLimits::default(),
         }
     }
 
@@ -32,6 +43,30 @@ impl XdvipdfmxEngine {
         self
     }
 
+    /// Produce PDF/A output at the given conformance level. This embeds
+    /// every font in full with a ToUnicode map, adds XMP metadata and an
+    /// sRGB output intent, and drops features the level forbids, such as
+    /// encryption and (for PDF/A-1) transparency, with a warning.
+    pub fn with_pdfa(mut self, level: Option<PdfAConformance>) -> Self {
+        self.pdfa = level;
+        self
+    }
+
+    /// Produce linearized ("fast web view") PDF, which viewers can start
+    /// displaying before the whole file has arrived. The file is assembled
+    /// in memory, and encrypted output is never linearized.
+    pub fn with_linearize(mut self, linearize: bool) -> Self {
+        self.linearize = linearize;
+        self
+    }
+
+    /// Configure cancellation and resource limits for the conversion. These
+    /// are checked as each page is processed.
+    pub fn with_limits(mut self, limits: EngineLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
     pub fn process(
         &mut self,
         io: &mut IoStack,
@@ -39,22 +74,46 @@ impl XdvipdfmxEngine {
         status: &mut dyn StatusBackend,
         dvi: &str,
         pdf: &str,
+    ) -> Result<i32> {
+        self.process_with_progress(
+            io,
+            events,
+            &mut NoopProgressEventBackend::new(),
+            status,
+            dvi,
+            pdf,
+        )
+    }
+
+    /// Like `process`, but also reports the pages written and the files
+    /// opened to a ProgressEventBackend.
+    pub fn process_with_progress(
+        &mut self,
+        io: &mut IoStack,
+        events: &mut dyn IoEventBackend,
+        progress: &mut dyn ProgressEventBackend,
+        status: &mut dyn StatusBackend,
+        dvi: &str,
+        pdf: &str,
     ) -> Result<i32> {
         let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
 
         let cdvi = CString::new(dvi)?;
         let cpdf = CString::new(pdf)?;
 
-        let /*mut*/ state = ExecutionState::new(io, events, status);
+        let /*mut*/ state =
+            ExecutionState::new(io, EngineKind::Xdvipdfmx, events, progress, status);
         let bridge = TectonicBridgeApi::new(&state);
 
-        unsafe {
+        self.limits.enforce(|| unsafe {
             match super::dvipdfmx_simple_main(
                 &*bridge,
                 cdvi.as_ptr(),
                 cpdf.as_ptr(),
                 self.enable_compression,
                 self.deterministic_tags,
+                self.pdfa,
+                self.linearize,
             ) {
                 99 => {
                     let ptr = super::tt_get_error_message();
@@ -63,7 +122,7 @@ impl XdvipdfmxEngine {
                 }
                 x => Ok(x as i32),
             }
-        }
+        })
     }
 }
 
@@ -20,6 +20,8 @@ use tempfile;
 use toml;
 use zip::result::ZipError;
 
+use crate::engines::ResourceLimit;
+
 error_chain! {
     types {
         Error, ErrorKind, ResultExt, Result;
@@ -65,6 +67,16 @@ error_chain! {
             display("the {} engine had an unrecoverable error", engine)
         }
 
+        Cancelled {
+            description("processing was cancelled")
+            display("processing was cancelled")
+        }
+
+        LimitExceeded(limit: ResourceLimit) {
+            description("an engine exceeded one of its resource limits")
+            display("the engine exceeded its limit on {}", limit)
+        }
+
         UnexpectedHttpResponse(url: String, status: StatusCode) {
             description("unexpected HTTP response to URL")
             display("unexpected HTTP response to URL {}: {}", url, status)
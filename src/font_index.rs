@@ -0,0 +1,614 @@
+// src/font_index.rs -- an index of the fonts in the bundle and local directories
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! An index of font faces for resolving native font names without the host
+//! system.
+//!
+//! By default, XeTeX resolves a font request like `\font\x="Linux Libertine
+//! O"` through fontconfig or CoreText, so whether a document builds depends
+//! on the fonts that happen to be installed on the machine building it. A
+//! [`FontIndex`] lists the faces found in the bundle, and optionally in some
+//! local directories, along with the names that the engine matches requests
+//! against. Handing one to [`crate::TexEngine::font_index`] makes the engine
+//! resolve font names from the index alone.
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use tectonic_engine::{check_for_woff, font_index_entry_t, woff_to_sfnt};
+
+use crate::errors::Result;
+use crate::io::{Bundle, OpenResult};
+use crate::status::StatusBackend;
+use crate::{ctry, errmsg, tt_note, tt_warning};
+
+/// Where the TeX engine looks for fonts that documents request by name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FontSources {
+    /// Use the fonts installed on the host system, as reported by fontconfig
+    /// or CoreText. This is the default.
+    HostSystem,
+
+    /// Use only the fonts in the bundle.
+    BundleOnly,
+
+    /// Use the fonts in the bundle and in the given directories, which are
+    /// searched recursively.
+    BundleAndDirectories(Vec<PathBuf>),
+}
+
+impl Default for FontSources {
+    fn default() -> Self {
+        FontSources::HostSystem
+    }
+}
+
+/// One face of a font file, with the names that native font lookup matches.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FontFace {
+    /// The name under which the engine opens the file: a file name within
+    /// the bundle, or an absolute path.
+    pub file: String,
+
+    /// The index of the face within a font collection; zero otherwise.
+    pub index: u32,
+
+    /// The family names of the face, in all of the languages it provides.
+    pub family_names: Vec<String>,
+
+    /// The style names of the face.
+    pub style_names: Vec<String>,
+
+    /// The full names of the face.
+    pub full_names: Vec<String>,
+
+    /// The PostScript name of the face.
+    pub postscript_name: String,
+}
+
+impl FontFace {
+    fn to_engine(&self) -> font_index_entry_t {
+        font_index_entry_t {
+            file: self.file.clone(),
+            index: self.index,
+            family_names: self.family_names.clone(),
+            style_names: self.style_names.clone(),
+            full_names: self.full_names.clone(),
+            ps_name: self.postscript_name.clone(),
+        }
+    }
+}
+
+/// The file extensions of the font formats covered by the index.
+const FONT_EXTENSIONS: &[&str] = &["otf", "ttf", "otc", "ttc", "woff", "woff2"];
+
+/// Bump this whenever the way names are read from fonts changes, so that
+/// cached indexes are rebuilt.
+const FONT_INDEX_SERIAL: u32 = 1;
+
+/// Names are stored in cached indexes separated by this character, which
+/// never appears in the names themselves.
+const NAME_SEPARATOR: char = '\u{1f}';
+
+/// An index of the font faces available to a processing session.
+#[derive(Clone, Debug, Default)]
+pub struct FontIndex {
+    faces: Vec<FontFace>,
+}
+
+impl FontIndex {
+    /// Create an empty index.
+    pub fn new() -> FontIndex {
+        FontIndex::default()
+    }
+
+    /// Get the faces in the index, in the order in which they were added.
+    pub fn faces(&self) -> &[FontFace] {
+        &self.faces
+    }
+
+    /// Build the index that implements the font policy `sources`, or `None`
+    /// if the engine should use the host fonts.
+    ///
+    /// Reading every font in a bundle is slow, so if `cache_dir` is given,
+    /// the part of the index covering the bundle is cached there, keyed on
+    /// the bundle digest. Directories are rescanned every time, since their
+    /// contents can change under us.
+    pub fn for_sources(
+        sources: &FontSources,
+        bundle: Option<&mut dyn Bundle>,
+        cache_dir: Option<&Path>,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Option<FontIndex>> {
+        let dirs: &[PathBuf] = match sources {
+            FontSources::HostSystem => return Ok(None),
+            FontSources::BundleOnly => &[],
+            FontSources::BundleAndDirectories(dirs) => dirs,
+        };
+
+        let mut index = match bundle {
+            Some(bundle) => FontIndex::from_bundle(bundle, cache_dir, status)?,
+            None => FontIndex::new(),
+        };
+
+        for dir in dirs {
+            index.add_directory(dir, status)?;
+        }
+
+        Ok(Some(index))
+    }
+
+    fn from_bundle(
+        bundle: &mut dyn Bundle,
+        cache_dir: Option<&Path>,
+        status: &mut dyn StatusBackend,
+    ) -> Result<FontIndex> {
+        let cache_path = match cache_dir {
+            Some(dir) => {
+                let digest = bundle.get_digest(status)?;
+                Some(dir.join(format!(
+                    "{}-fonts-{}.txt",
+                    digest.to_string(),
+                    FONT_INDEX_SERIAL
+                )))
+            }
+            None => None,
+        };
+
+        if let Some(ref path) = cache_path {
+            if path.exists() {
+                return FontIndex::load(path);
+            }
+        }
+
+        let mut index = FontIndex::new();
+        index.add_bundle(bundle, status)?;
+
+        if let Some(ref path) = cache_path {
+            if let Err(e) = index.save(path) {
+                tt_warning!(status, "failed to save the font index to \"{}\"", path.display(); e);
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Add the faces of every font file in `bundle`.
+    pub fn add_bundle(
+        &mut self,
+        bundle: &mut dyn Bundle,
+        status: &mut dyn StatusBackend,
+    ) -> Result<()> {
+        let mut names = bundle.all_files(status)?;
+        names.retain(|n| is_font_file(Path::new(n)));
+        names.sort();
+
+        if !names.is_empty() {
+            tt_note!(status, "indexing {} fonts in the bundle", names.len());
+        }
+
+        for name in names {
+            let mut ih = match bundle.input_open_name(OsStr::new(&name), status) {
+                OpenResult::Ok(ih) => ih,
+                OpenResult::NotAvailable => continue,
+                OpenResult::Err(e) => {
+                    tt_warning!(status, "couldn't open font \"{}\" in the bundle", name; e);
+                    continue;
+                }
+            };
+
+            let mut data = Vec::new();
+            ih.read_to_end(&mut data)?;
+            self.faces.extend(read_faces(&name, &data));
+        }
+
+        Ok(())
+    }
+
+    /// Add the faces of every font file in `dir` and its subdirectories.
+    pub fn add_directory(&mut self, dir: &Path, status: &mut dyn StatusBackend) -> Result<()> {
+        let dir = ctry!(dir.canonicalize(); "couldn't open font directory \"{}\"", dir.display());
+        let mut paths = Vec::new();
+        collect_font_files(&dir, &mut paths)?;
+        paths.sort();
+
+        for path in paths {
+            let file = match path.to_str() {
+                Some(s) => s.to_owned(),
+                None => {
+                    tt_warning!(
+                        status,
+                        "ignoring font \"{}\" with a non-Unicode path",
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+
+            match fs::read(&path) {
+                Ok(data) => self.faces.extend(read_faces(&file, &data)),
+                Err(e) => tt_warning!(status, "couldn't read font \"{}\"", file; e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load an index previously written with [`FontIndex::save`].
+    pub fn load(path: &Path) -> Result<FontIndex> {
+        let f = ctry!(File::open(path); "couldn't open font index \"{}\"", path.display());
+        let mut faces = Vec::new();
+
+        for line in BufReader::new(f).lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+
+            if fields.len() != 6 {
+                return Err(errmsg!("malformed font index line \"{}\"", line));
+            }
+
+            let names = |s: &str| {
+                s.split(NAME_SEPARATOR)
+                    .filter(|n| !n.is_empty())
+                    .map(|n| n.to_owned())
+                    .collect()
+            };
+
+            faces.push(FontFace {
+                file: fields[0].to_owned(),
+                index: ctry!(fields[1].parse(); "malformed font index line \"{}\"", line),
+                postscript_name: fields[2].to_owned(),
+                family_names: names(fields[3]),
+                style_names: names(fields[4]),
+                full_names: names(fields[5]),
+            });
+        }
+
+        Ok(FontIndex { faces })
+    }
+
+    /// Save the index to `path`, one face per line.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let sep = NAME_SEPARATOR.to_string();
+        let mut text = String::new();
+
+        for face in &self.faces {
+            text.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\n",
+                face.file,
+                face.index,
+                face.postscript_name,
+                face.family_names.join(&sep),
+                face.style_names.join(&sep),
+                face.full_names.join(&sep),
+            ));
+        }
+
+        // Write to a temporary file and rename it into place, so that
+        // concurrent sessions never see a partial index.
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+        temp.write_all(text.as_bytes())?;
+        temp.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Convert the index into the form used by the engine.
+    pub fn to_engine(&self) -> Vec<font_index_entry_t> {
+        self.faces.iter().map(FontFace::to_engine).collect()
+    }
+}
+
+fn is_font_file(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => FONT_EXTENSIONS
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(ext)),
+        None => false,
+    }
+}
+
+fn collect_font_files(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in ctry!(fs::read_dir(dir); "couldn't read font directory \"{}\"", dir.display()) {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_font_files(&path, paths)?;
+        } else if is_font_file(&path) {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let b = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Read the names of every face in the sfnt or sfnt collection `data`,
+/// unpacking WOFF and WOFF2 files first. Files that can't be parsed, and faces without a PostScript name, are
+/// silently skipped, just as fontconfig skips them.
+fn read_faces(file: &str, data: &[u8]) -> Vec<FontFace> {
+    if check_for_woff(data) {
+        return match woff_to_sfnt(data) {
+            Ok(sfnt) => read_faces(file, &sfnt),
+            Err(_) => Vec::new(),
+        };
+    }
+
+    let offsets = match data.get(0..4) {
+        Some(b"ttcf") => {
+            let mut offsets = Vec::new();
+
+            for i in 0..be_u32(data, 8).unwrap_or(0) as usize {
+                match be_u32(data, 12 + 4 * i) {
+                    Some(o) => offsets.push(o as usize),
+                    None => break,
+                }
+            }
+
+            offsets
+        }
+        Some(b"OTTO") | Some(b"true") | Some([0, 1, 0, 0]) => vec![0],
+        _ => return Vec::new(),
+    };
+
+    offsets
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, offset)| {
+            let mut face = read_face_names(data, offset)?;
+            face.file = file.to_owned();
+            face.index = i as u32;
+            Some(face)
+        })
+        .collect()
+}
+
+/// Read the `name` table of the face whose table directory is at `offset`,
+/// mirroring how the fontconfig font manager reads names from FreeType:
+/// Macintosh Roman names come first, followed by the Unicode ones, and the
+/// typographic family and subfamily names override the legacy ones.
+fn read_face_names(data: &[u8], offset: usize) -> Option<FontFace> {
+    let num_tables = be_u16(data, offset + 4)? as usize;
+    let mut base = None;
+
+    for i in 0..num_tables {
+        let rec = offset + 12 + 16 * i;
+
+        if data.get(rec..rec + 4)? == b"name" {
+            base = Some(be_u32(data, rec + 8)? as usize);
+            break;
+        }
+    }
+
+    let base = base?;
+    let count = be_u16(data, base + 2)? as usize;
+    let strings = base + be_u16(data, base + 4)? as usize;
+
+    let mut face = FontFace::default();
+    let mut typo_family = Vec::new();
+    let mut typo_style = Vec::new();
+    let mut ps_mac = None;
+    let mut ps_win = None;
+
+    for i in 0..count {
+        let rec = base + 6 + 12 * i;
+        let platform = be_u16(data, rec)?;
+        let encoding = be_u16(data, rec + 2)?;
+        let language = be_u16(data, rec + 4)?;
+        let name_id = be_u16(data, rec + 6)?;
+        let length = be_u16(data, rec + 8)? as usize;
+        let start = strings + be_u16(data, rec + 10)? as usize;
+
+        let bytes = match data.get(start..start + length) {
+            Some(b) => b,
+            None => continue,
+        };
+
+        let (name, is_mac) = match (platform, encoding, language) {
+            (1, 0, 0) => (decode_mac_roman(bytes), true),
+            (0, _, _) | (3, _, _) => (decode_utf16be(bytes), false),
+            _ => continue,
+        };
+
+        if name.is_empty() {
+            continue;
+        }
+
+        let list = match name_id {
+            1 => &mut face.family_names,
+            2 => &mut face.style_names,
+            4 => &mut face.full_names,
+            16 => &mut typo_family,
+            17 => &mut typo_style,
+            6 => {
+                if is_mac {
+                    ps_mac = Some(name);
+                } else if (platform, encoding, language) == (3, 1, 0x409) || ps_win.is_none() {
+                    ps_win = Some(name);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        if let Some(pos) = list.iter().position(|n| *n == name) {
+            if !is_mac {
+                continue;
+            }
+            list.remove(pos);
+        }
+
+        if is_mac {
+            list.insert(0, name);
+        } else {
+            list.push(name);
+        }
+    }
+
+    if !typo_family.is_empty() {
+        face.family_names = typo_family;
+    }
+
+    if !typo_style.is_empty() {
+        face.style_names = typo_style;
+    }
+
+    // Like FreeType, keep only the printable ASCII of the PostScript name.
+    face.postscript_name = ps_win
+        .or(ps_mac)?
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .collect();
+
+    if face.postscript_name.is_empty() {
+        return None;
+    }
+
+    Some(face)
+}
+
+fn decode_utf16be(bytes: &[u8]) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]));
+
+    std::char::decode_utf16(units)
+        .filter_map(|c| c.ok())
+        .filter(|c| !c.is_control())
+        .collect()
+}
+
+/// The characters for bytes 0x80 through 0xFF in the Mac OS Roman encoding.
+const MAC_ROMAN_HIGH: &str = "ÄÅÇÉÑÖÜáàâäãåçéèêëíìîïñóòôöõúùûü†°¢£§•¶ß®©™´¨≠ÆØ∞±≤≥¥µ∂∑∏π∫ªºΩæø\
+                              ¿¡¬√ƒ≈∆«»…\u{a0}ÀÃÕŒœ–—“”‘’÷◊ÿŸ⁄€‹›ﬁﬂ‡·‚„‰ÂÊÁËÈÍÎÏÌÓÔ\u{f8ff}ÒÚÛÙıˆ˜¯˘˙˚¸˝˛ˇ";
+
+fn decode_mac_roman(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .filter_map(|&b| {
+            if b < 0x80 {
+                Some(b as char)
+            } else {
+                MAC_ROMAN_HIGH.chars().nth(b as usize - 0x80)
+            }
+        })
+        .filter(|c| !c.is_control())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a minimal sfnt with only a `name` table holding `names`, given
+    /// as (platform, encoding, language, name ID, encoded string).
+    fn make_sfnt(names: &[(u16, u16, u16, u16, Vec<u8>)]) -> Vec<u8> {
+        let mut name = Vec::new();
+        name.extend_from_slice(&0u16.to_be_bytes());
+        name.extend_from_slice(&(names.len() as u16).to_be_bytes());
+        name.extend_from_slice(&(6 + 12 * names.len() as u16).to_be_bytes());
+
+        let mut strings = Vec::new();
+
+        for (platform, encoding, language, id, s) in names {
+            for v in &[*platform, *encoding, *language, *id, s.len() as u16] {
+                name.extend_from_slice(&v.to_be_bytes());
+            }
+            name.extend_from_slice(&(strings.len() as u16).to_be_bytes());
+            strings.extend_from_slice(s);
+        }
+
+        name.extend_from_slice(&strings);
+
+        let mut font = Vec::new();
+        font.extend_from_slice(b"OTTO");
+        font.extend_from_slice(&1u16.to_be_bytes());
+        font.extend_from_slice(&[0; 6]);
+        font.extend_from_slice(b"name");
+        font.extend_from_slice(&0u32.to_be_bytes());
+        font.extend_from_slice(&28u32.to_be_bytes());
+        font.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        font.extend_from_slice(&name);
+        font
+    }
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .flat_map(|u| u.to_be_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn reads_names() {
+        let font = make_sfnt(&[
+            (1, 0, 0, 1, b"Caf\x8e Sans".to_vec()),
+            (3, 1, 0x409, 1, utf16("Café Sans")),
+            (3, 1, 0x407, 1, utf16("Kaffee Sans")),
+            (3, 1, 0x409, 2, utf16("Bold")),
+            (3, 1, 0x409, 4, utf16("Café Sans Bold")),
+            (3, 1, 0x409, 6, utf16("CafeSans-Bold")),
+        ]);
+
+        let faces = read_faces("cafe.otf", &font);
+        assert_eq!(faces.len(), 1);
+        assert_eq!(faces[0].file, "cafe.otf");
+        assert_eq!(faces[0].family_names, vec!["Café Sans", "Kaffee Sans"]);
+        assert_eq!(faces[0].style_names, vec!["Bold"]);
+        assert_eq!(faces[0].full_names, vec!["Café Sans Bold"]);
+        assert_eq!(faces[0].postscript_name, "CafeSans-Bold");
+    }
+
+    #[test]
+    fn typographic_names_override() {
+        let font = make_sfnt(&[
+            (3, 1, 0x409, 1, utf16("Example Light")),
+            (3, 1, 0x409, 2, utf16("Regular")),
+            (3, 1, 0x409, 6, utf16("Example-Light")),
+            (3, 1, 0x409, 16, utf16("Example")),
+            (3, 1, 0x409, 17, utf16("Light")),
+        ]);
+
+        let faces = read_faces("example.otf", &font);
+        assert_eq!(faces[0].family_names, vec!["Example"]);
+        assert_eq!(faces[0].style_names, vec!["Light"]);
+    }
+
+    #[test]
+    fn skips_unusable_fonts() {
+        assert!(read_faces("junk.ttf", b"not a font").is_empty());
+
+        let font = make_sfnt(&[(3, 1, 0x409, 1, utf16("Anonymous"))]);
+        assert!(read_faces("anon.ttf", &font).is_empty());
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fonts.txt");
+
+        let index = FontIndex {
+            faces: vec![FontFace {
+                file: "cafe.ttc".to_owned(),
+                index: 1,
+                family_names: vec!["Café Sans".to_owned(), "Kaffee Sans".to_owned()],
+                style_names: vec!["Bold".to_owned()],
+                full_names: Vec::new(),
+                postscript_name: "CafeSans-Bold".to_owned(),
+            }],
+        };
+
+        index.save(&path).unwrap();
+        assert_eq!(FontIndex::load(&path).unwrap().faces(), index.faces());
+    }
+}
@@ -692,6 +692,10 @@ impl Bundle for CachedITarBundle {
     fn get_digest(&mut self, _status: &mut dyn StatusBackend) -> Result<DigestData> {
         Ok(self.cached_digest)
     }
+
+    fn all_files(&mut self, _status: &mut dyn StatusBackend) -> Result<Vec<String>> {
+        Ok(self.index.keys().cloned().collect())
+    }
 }
 
 /// A convenience method to provide a better error message when writing to a created file.
@@ -482,12 +482,25 @@ pub trait Bundle: IoProvider {
 
         Ok(ctry!(DigestData::from_str(&digest_text); "corrupted SHA256 digest data"))
     }
+
+    /// List the names of all of the files in this bundle.
+    ///
+    /// This is used to build indexes over the bundle contents, such as the
+    /// font index. The default implementation returns an empty list, for
+    /// backends that have no way of enumerating their contents.
+    fn all_files(&mut self, _status: &mut dyn StatusBackend) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
 }
 
 impl<B: Bundle + ?Sized> Bundle for Box<B> {
     fn get_digest(&mut self, status: &mut dyn StatusBackend) -> Result<DigestData> {
         (**self).get_digest(status)
     }
+
+    fn all_files(&mut self, status: &mut dyn StatusBackend) -> Result<Vec<String>> {
+        (**self).all_files(status)
+    }
 }
 
 // Some generically helpful InputFeatures impls
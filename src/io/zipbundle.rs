@@ -74,4 +74,14 @@ impl<R: Read + Seek> IoProvider for ZipBundle<R> {
     }
 }
 
-impl<R: Read + Seek> Bundle for ZipBundle<R> {}
+impl<R: Read + Seek> Bundle for ZipBundle<R> {
+    fn all_files(&mut self, _status: &mut dyn StatusBackend) -> Result<Vec<String>> {
+        let mut names = Vec::with_capacity(self.zip.len());
+
+        for i in 0..self.zip.len() {
+            names.push(self.zip.by_index(i)?.name().to_owned());
+        }
+
+        Ok(names)
+    }
+}
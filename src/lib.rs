@@ -49,11 +49,13 @@
 //! engines in more realistic circumstances.
 
 mod app_dirs;
+pub mod bib;
 pub mod config;
 pub mod digest;
 pub mod driver;
 pub mod engines;
 pub mod errors;
+pub mod font_index;
 pub mod io;
 pub mod status;
 
@@ -62,10 +64,15 @@ pub mod status;
 #[doc(hidden)]
 pub mod test_util;
 
-pub use crate::engines::bibtex::BibtexEngine;
+pub use crate::engines::bibtex::{
+    BibtexDiagnostic, BibtexDiagnosticKind, BibtexEngine, BibtexLimits,
+};
 pub use crate::engines::spx2html::Spx2HtmlEngine;
-pub use crate::engines::tex::{TexEngine, TexResult};
-pub use crate::engines::xdvipdfmx::XdvipdfmxEngine;
+pub use crate::engines::tex::{FontAxis, FontDescription, TexEngine, TexResult};
+pub use crate::engines::xdv2png::Xdv2PngEngine;
+pub use crate::engines::xdv2svg::Xdv2SvgEngine;
+pub use crate::engines::xdv2text::Xdv2TextEngine;
+pub use crate::engines::xdvipdfmx::{PdfAConformance, XdvipdfmxEngine};
 pub use crate::errors::{Error, ErrorKind, Result};
 
 const FORMAT_SERIAL: u32 = 28; // keep synchronized with tectonic/xetex-constants.h!!
@@ -11,6 +11,7 @@ use std::fmt::Arguments;
 use std::result::Result as StdResult;
 use std::str::FromStr;
 
+use crate::engines::BibtexDiagnostic;
 use crate::errors::Error;
 
 #[repr(usize)]
@@ -77,6 +78,17 @@ pub trait StatusBackend {
             None,
         )
     }
+
+    /// Report a warning or error issued by BibTeX.
+    ///
+    /// [`driver::ProcessingSession::run`] passes each of BibTeX's
+    /// diagnostics through here. The default implementation reports it as a
+    /// message of the same severity; backends that want to act on particular
+    /// kinds of problems, such as missing database entries, can inspect the
+    /// diagnostic itself.
+    fn report_bibtex_diagnostic(&mut self, diag: &BibtexDiagnostic) {
+        self.report(diag.severity, format_args!("BibTeX: {}", diag), None)
+    }
 }
 
 /// Report a formatted informational message to the user.
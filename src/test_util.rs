@@ -38,6 +38,7 @@
 use std::collections::HashSet;
 use std::env;
 use std::ffi::OsStr;
+use std::fs;
 use std::path::PathBuf;
 
 use crate::digest::DigestData;
@@ -130,4 +131,16 @@ impl Bundle for TestBundle {
     fn get_digest(&mut self, _status: &mut dyn StatusBackend) -> Result<DigestData> {
         Ok(DigestData::zeros())
     }
+
+    fn all_files(&mut self, _status: &mut dyn StatusBackend) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+
+        for entry in fs::read_dir(test_path(&["assets"]))? {
+            if let Some(name) = entry?.file_name().to_str() {
+                names.push(name.to_owned());
+            }
+        }
+
+        Ok(names)
+    }
 }
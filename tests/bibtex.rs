@@ -2,12 +2,14 @@
 // Licensed under the MIT License.
 
 use std::collections::HashSet;
+use std::ffi::OsStr;
 
 use tectonic::engines::NoopIoEventBackend;
+use tectonic::errors::Result;
 use tectonic::io::stdstreams::GenuineStdoutIo;
 use tectonic::io::{FilesystemIo, IoProvider, IoStack, MemoryIo};
-use tectonic::status::NoopStatusBackend;
-use tectonic::BibtexEngine;
+use tectonic::status::{MessageKind, NoopStatusBackend};
+use tectonic::{BibtexDiagnostic, BibtexDiagnosticKind, BibtexEngine, BibtexLimits, TexResult};
 
 #[path = "util/mod.rs"]
 mod util;
@@ -68,3 +70,272 @@ impl TestCase {
 fn single_entry() {
     TestCase::new("single_entry").go()
 }
+
+/// Runs BibTeX on `aux` from the test directory, returning its result and the
+/// diagnostics it issued.
+fn diagnose(aux: &str) -> (TexResult, Vec<BibtexDiagnostic>) {
+    util::set_test_root();
+
+    let mut mem = MemoryIo::new(true);
+    let mut assets = FilesystemIo::new(&test_path(&["bibtex"]), false, false, HashSet::new());
+    let io_list: Vec<&mut dyn IoProvider> = vec![&mut mem, &mut assets];
+    let mut io = IoStack::new(io_list);
+    let mut events = NoopIoEventBackend::new();
+    let mut status = NoopStatusBackend::new();
+
+    let mut engine = BibtexEngine::new();
+    let result = engine
+        .process(&mut io, &mut events, &mut status, aux)
+        .unwrap();
+    (result, engine.diagnostics().to_vec())
+}
+
+#[test]
+fn missing_entry_is_diagnosed() {
+    let (result, diagnostics) = diagnose("missing_entry.aux");
+    assert_eq!(result, TexResult::Warnings);
+
+    let missing: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.kind == BibtexDiagnosticKind::MissingEntry)
+        .collect();
+    assert_eq!(missing.len(), 1);
+    assert_eq!(missing[0].severity, MessageKind::Warning);
+    assert_eq!(missing[0].entry.as_deref(), Some("Somebody07"));
+    assert_eq!(
+        missing[0].message,
+        "I didn't find a database entry for \"Somebody07\""
+    );
+}
+
+#[test]
+fn database_problems_are_diagnosed() {
+    let (result, diagnostics) = diagnose("diagnostics.aux");
+    assert_eq!(result, TexResult::Errors);
+
+    let bib = Some("diagnostics.bib".to_owned());
+    assert_eq!(
+        diagnostics[0],
+        BibtexDiagnostic {
+            kind: BibtexDiagnosticKind::Database,
+            severity: MessageKind::Warning,
+            message: "I'm ignoring Repeated08's extra \"title\" field".to_owned(),
+            entry: Some("Repeated08".to_owned()),
+            field: Some("title".to_owned()),
+            file: bib.clone(),
+            line: Some(3),
+        }
+    );
+    assert_eq!(
+        diagnostics[1],
+        BibtexDiagnostic {
+            kind: BibtexDiagnosticKind::Database,
+            severity: MessageKind::Error,
+            message: "I was expecting a `,' or a `}'".to_owned(),
+            entry: None,
+            field: None,
+            file: bib,
+            line: Some(17),
+        }
+    );
+    assert_eq!(
+        diagnostics[2],
+        BibtexDiagnostic {
+            kind: BibtexDiagnosticKind::BadCrossReference,
+            severity: MessageKind::Error,
+            message: "A bad cross reference: entry \"Orphan09\" refers to entry \"Nowhere\", \
+                      which doesn't exist"
+                .to_owned(),
+            entry: Some("Orphan09".to_owned()),
+            field: Some("crossref".to_owned()),
+            file: None,
+            line: None,
+        }
+    );
+
+    let journal = diagnostics
+        .iter()
+        .find(|d| d.kind == BibtexDiagnosticKind::StyleWarning && d.message.contains("journal"))
+        .unwrap();
+    assert_eq!(journal.message, "empty journal in Article10");
+    assert_eq!(journal.entry.as_deref(), Some("Article10"));
+}
+
+/// A style that passes a field the entry lacks to `warning$`.
+#[test]
+fn missing_field_is_diagnosed() {
+    let (result, diagnostics) = diagnose("missing_field.aux");
+    assert_eq!(result, TexResult::Errors);
+    assert_eq!(
+        diagnostics,
+        vec![BibtexDiagnostic {
+            kind: BibtexDiagnosticKind::Style,
+            severity: MessageKind::Error,
+            message: "`journal' is a missing field, not a string".to_owned(),
+            entry: Some("Nobody06".to_owned()),
+            field: Some("journal".to_owned()),
+            file: Some("missing_field.bst".to_owned()),
+            line: Some(4),
+        }]
+    );
+}
+
+/// An `.aux` file that includes itself, under its own name or another
+/// spelling of it, must not be read again.
+#[test]
+fn self_including_aux_is_rejected() {
+    let (result, diagnostics) = diagnose("self_input.aux");
+    assert_eq!(result, TexResult::Errors);
+
+    let error = |message: &str, line| BibtexDiagnostic {
+        kind: BibtexDiagnosticKind::Aux,
+        severity: MessageKind::Error,
+        message: message.to_owned(),
+        entry: None,
+        field: None,
+        file: Some("self_input.aux".to_owned()),
+        line: Some(line),
+    };
+    assert_eq!(
+        diagnostics,
+        vec![
+            error("Already encountered file self_input.aux", 2),
+            error("Auxiliary file ./self_input.aux is already being read", 3),
+        ]
+    );
+}
+
+/// Runs BibTeX with `limits` on a generated database that has more entries,
+/// fields and string text than classic BibTeX has room for, along with a
+/// field longer than its line buffer. Returns the result and the `.bbl` and
+/// `.blg` outputs.
+fn process_large_database(limits: BibtexLimits) -> (Result<TexResult>, String, String) {
+    util::set_test_root();
+
+    let mut bib = String::new();
+    for i in 0..2000 {
+        bib += &format!(
+            "@article{{ Key{0:04},\n  author = \"Author Number {0}\",\n  \
+             title = \"A title long enough to fill up the string pool, number {0}\",\n  \
+             journal = \"Journal of Generated Entries\",\n  year = \"2020\" }}\n\n",
+            i
+        );
+    }
+    bib += &format!(
+        "@misc{{ Long,\n  author = \"Someone\",\n  title = \"Long\",\n  note = \"{}\" }}\n",
+        vec!["word"; 6000].join(" ")
+    );
+
+    let mut mem = MemoryIo::new(true);
+    mem.create_entry(OsStr::new("large.bib"), bib.into_bytes());
+    mem.create_entry(
+        OsStr::new("large.aux"),
+        b"\\citation{*}\n\\bibdata{large}\n\\bibstyle{plain}\n".to_vec(),
+    );
+    let mut assets = FilesystemIo::new(&test_path(&["bibtex"]), false, false, HashSet::new());
+
+    let result = {
+        let io_list: Vec<&mut dyn IoProvider> = vec![&mut mem, &mut assets];
+        let mut io = IoStack::new(io_list);
+        BibtexEngine::new().with_limits(limits).process(
+            &mut io,
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+            "large.aux",
+        )
+    };
+
+    let files = mem.files.borrow();
+    let output = |name: &str| {
+        files
+            .get(OsStr::new(name))
+            .map(|data| String::from_utf8_lossy(data).into_owned())
+            .unwrap_or_default()
+    };
+    (result, output("large.bbl"), output("large.blg"))
+}
+
+#[test]
+fn tables_grow_past_classic_limits() {
+    let (result, bbl, blg) = process_large_database(BibtexLimits::new());
+    assert_eq!(result.unwrap(), TexResult::Spotless, "{}", blg);
+    assert_eq!(bbl.matches("\\bibitem").count(), 2001);
+    assert!(bbl.contains("\\bibitem{Key1999}"));
+}
+
+#[test]
+fn configured_limit_caps_table() {
+    let mut limits = BibtexLimits::new();
+    limits.max_cites(100);
+    let (result, _bbl, blg) = process_large_database(limits);
+    assert!(result.is_err());
+    assert!(blg.contains("Sorry---you've exceeded BibTeX's number of cites 100"));
+}
+
+/// Runs BibTeX with `limits` on a chain of `levels` auxiliary files, each
+/// including the next with `\@input`. Returns the result and the `.blg`
+/// output.
+fn process_nested_aux(levels: usize, limits: BibtexLimits) -> (Result<TexResult>, String) {
+    util::set_test_root();
+
+    let mut mem = MemoryIo::new(true);
+    mem.create_entry(
+        OsStr::new("nested.aux"),
+        b"\\@input{nested1.aux}\n\\citation{Nobody06}\n\
+          \\bibdata{single_entry}\n\\bibstyle{plain}\n"
+            .to_vec(),
+    );
+    for i in 1..levels {
+        mem.create_entry(
+            OsStr::new(&format!("nested{}.aux", i)),
+            format!("\\@input{{nested{}.aux}}\n", i + 1).into_bytes(),
+        );
+    }
+    mem.create_entry(
+        OsStr::new(&format!("nested{}.aux", levels)),
+        b"\\relax\n".to_vec(),
+    );
+    let mut assets = FilesystemIo::new(&test_path(&["bibtex"]), false, false, HashSet::new());
+
+    let result = {
+        let io_list: Vec<&mut dyn IoProvider> = vec![&mut mem, &mut assets];
+        let mut io = IoStack::new(io_list);
+        BibtexEngine::new().with_limits(limits).process(
+            &mut io,
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+            "nested.aux",
+        )
+    };
+
+    let files = mem.files.borrow();
+    let blg = files
+        .get(OsStr::new("nested.blg"))
+        .map(|data| String::from_utf8_lossy(data).into_owned())
+        .unwrap_or_default();
+    (result, blg)
+}
+
+#[test]
+fn aux_nesting_is_capped_by_default() {
+    let (result, blg) = process_nested_aux(20, BibtexLimits::new());
+    assert_eq!(result.unwrap(), TexResult::Spotless, "{}", blg);
+
+    let (result, blg) = process_nested_aux(21, BibtexLimits::new());
+    assert!(result.is_err());
+    assert!(blg.contains("Sorry---you've exceeded BibTeX's auxiliary file depth 20"));
+}
+
+#[test]
+fn configured_limit_changes_aux_nesting() {
+    let mut limits = BibtexLimits::new();
+    limits.max_aux_depth(30);
+    let (result, blg) = process_nested_aux(25, limits);
+    assert_eq!(result.unwrap(), TexResult::Spotless, "{}", blg);
+
+    let mut limits = BibtexLimits::new();
+    limits.max_aux_depth(2);
+    let (result, blg) = process_nested_aux(3, limits);
+    assert!(result.is_err());
+    assert!(blg.contains("Sorry---you've exceeded BibTeX's auxiliary file depth 2"));
+}
@@ -8,8 +8,14 @@
 //! ProcessingSessionBuilder will need to learn how to tell `xdvipdfmx` to
 //! enable the reproducibility options used in the `tex-outputs` test rig.
 
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::rc::Rc;
+
 use tectonic::config::PersistentConfig;
 use tectonic::driver::ProcessingSessionBuilder;
+use tectonic::engines::{EngineKind, ProgressEventBackend};
+use tectonic::font_index::FontSources;
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::status::ChatterLevel;
 
@@ -17,6 +23,90 @@ mod util;
 
 // Keep these alphabetized.
 
+/// BibTeX should see the citations in the `.aux` files that the primary one
+/// pulls in, as with `\include`, and run only once since they don't change.
+#[test]
+fn bibtex_included_aux() {
+    util::set_test_root();
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+    let bundle = util::TestBundle::default();
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    let mut pbuilder = ProcessingSessionBuilder::default();
+    pbuilder
+        .primary_input_path(util::test_path(&["bibtex", "include_aux.tex"]))
+        .tex_input_name("include_aux.tex")
+        .format_name("plain")
+        .format_cache_path(util::test_path(&[]))
+        .do_not_write_output_files()
+        .progress_events(Box::new(RecordingProgress(log.clone())))
+        .bundle(Box::new(bundle));
+
+    let mut session = pbuilder
+        .create(&mut status)
+        .expect("couldn't create processing session");
+
+    session
+        .run(&mut status)
+        .expect("failed to execute processing session");
+
+    let started: Vec<_> = log
+        .borrow()
+        .iter()
+        .filter_map(|p| match p {
+            Progress::Started(engine) => Some(*engine),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        started,
+        vec![
+            EngineKind::Tex,
+            EngineKind::Bibtex,
+            EngineKind::Tex,
+            EngineKind::Xdvipdfmx,
+        ]
+    );
+
+    let files = session.into_file_data();
+    let bbl = String::from_utf8_lossy(&files[OsStr::new("include_aux.bbl")]);
+    assert!(bbl.contains("\\bibitem{Nobody06}"));
+}
+
+/// With bundle-only fonts, a font requested by name should be found in the
+/// bundle, on every platform.
+#[test]
+fn font_by_name_from_bundle() {
+    util::set_test_root();
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+    let bundle = util::TestBundle::default();
+
+    let mut pbuilder = ProcessingSessionBuilder::default();
+    pbuilder
+        .primary_input_path(util::test_path(&["tex-outputs", "font_by_name.tex"]))
+        .tex_input_name("font_by_name.tex")
+        .format_name("plain")
+        .format_cache_path(util::test_path(&[]))
+        .font_sources(FontSources::BundleOnly)
+        .do_not_write_output_files()
+        .bundle(Box::new(bundle));
+
+    let mut session = pbuilder
+        .create(&mut status)
+        .expect("couldn't create processing session");
+
+    session
+        .run(&mut status)
+        .expect("failed to execute processing session");
+
+    // The XDV file names the font file that the request resolved to.
+    let files = session.into_file_data();
+    let xdv = String::from_utf8_lossy(&files[OsStr::new("font_by_name.xdv")]);
+    assert!(xdv.contains("lmroman12-regular.otf"));
+}
+
 #[test]
 fn the_letter_a() {
     util::set_test_root();
@@ -50,3 +140,72 @@ fn the_letter_a() {
         .run(&mut status)
         .expect("failed to execute processing session");
 }
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Progress {
+    Started(EngineKind),
+    Finished(EngineKind, bool),
+    ShippedOut(i32),
+    Written(usize),
+}
+
+struct RecordingProgress(Rc<RefCell<Vec<Progress>>>);
+
+impl ProgressEventBackend for RecordingProgress {
+    fn pass_started(&mut self, engine: EngineKind, _rerun_reason: Option<&str>) {
+        self.0.borrow_mut().push(Progress::Started(engine));
+    }
+
+    fn pass_finished(&mut self, engine: EngineKind, succeeded: bool) {
+        self.0
+            .borrow_mut()
+            .push(Progress::Finished(engine, succeeded));
+    }
+
+    fn page_shipped_out(&mut self, count0: i32) {
+        self.0.borrow_mut().push(Progress::ShippedOut(count0));
+    }
+
+    fn page_written(&mut self, page_number: usize) {
+        self.0.borrow_mut().push(Progress::Written(page_number));
+    }
+}
+
+#[test]
+fn the_letter_a_progress() {
+    util::set_test_root();
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+    let bundle = util::TestBundle::default();
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    let mut pbuilder = ProcessingSessionBuilder::default();
+    pbuilder
+        .primary_input_path(util::test_path(&["tex-outputs", "the_letter_a.tex"]))
+        .tex_input_name("the_letter_a.tex")
+        .format_name("plain")
+        .format_cache_path(util::test_path(&[]))
+        .do_not_write_output_files()
+        .progress_events(Box::new(RecordingProgress(log.clone())))
+        .bundle(Box::new(bundle));
+
+    let mut session = pbuilder
+        .create(&mut status)
+        .expect("couldn't create processing session");
+
+    session
+        .run(&mut status)
+        .expect("failed to execute processing session");
+
+    assert_eq!(
+        *log.borrow(),
+        vec![
+            Progress::Started(EngineKind::Tex),
+            Progress::ShippedOut(1),
+            Progress::Finished(EngineKind::Tex, true),
+            Progress::Started(EngineKind::Xdvipdfmx),
+            Progress::Written(1),
+            Progress::Finished(EngineKind::Xdvipdfmx, true),
+        ]
+    );
+}
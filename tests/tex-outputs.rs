@@ -3,15 +3,26 @@
 
 use std::collections::HashSet;
 use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Error as IoError;
 use std::path::Path;
+use std::time::Duration;
 
 use tectonic::engines::tex::TexResult;
-use tectonic::engines::NoopIoEventBackend;
+use tectonic::engines::{EngineLimits, NoopIoEventBackend, ResourceLimit};
 use tectonic::errors::{DefinitelySame, ErrorKind, Result};
+use tectonic::font_index::FontIndex;
 use tectonic::io::testing::SingleInputFileIo;
 use tectonic::io::{FilesystemIo, FilesystemPrimaryInputIo, IoProvider, IoStack, MemoryIo};
 use tectonic::status::NoopStatusBackend;
-use tectonic::{TexEngine, XdvipdfmxEngine};
+use tectonic::{
+    PdfAConformance, TexEngine, Xdv2PngEngine, Xdv2SvgEngine, Xdv2TextEngine, XdvipdfmxEngine,
+};
+use tectonic_xdv::{
+    FileType, FontDefinition, GlyphRun, NativeFontDefinition, XdvError, XdvEvents, XdvParser,
+    XdvWriter,
+};
 
 #[path = "util/mod.rs"]
 mod util;
@@ -205,6 +216,38 @@ fn redbox_png() {
     TestCase::new("redbox_png").check_pdf(true).go()
 }
 
+/// A runaway macro expansion never returns to the main loop, so this checks
+/// that the limits are also enforced during expansion.
+#[test]
+fn runaway_macro_wall_time() {
+    util::set_test_root();
+
+    let mut fmt =
+        SingleInputFileIo::new(&ensure_plain_format().expect("couldn't write format file"));
+    let mut tex = FilesystemPrimaryInputIo::new(&test_path(&["tex-outputs", "runaway_macro.tex"]));
+    let mut mem = MemoryIo::new(true);
+    let mut io = IoStack::new(vec![&mut mem, &mut tex, &mut fmt]);
+
+    let mut limits = EngineLimits::new();
+    limits.wall_time(Duration::from_millis(200));
+
+    let res = TexEngine::new().limits(limits).process(
+        &mut io,
+        &mut NoopIoEventBackend::new(),
+        &mut NoopStatusBackend::new(),
+        "plain.fmt",
+        "runaway_macro.tex",
+    );
+
+    match res {
+        Err(ref e) => match *e.kind() {
+            ErrorKind::LimitExceeded(ResourceLimit::WallTime) => {}
+            ref k => panic!("expected a wall-time limit error, got {:?}", k),
+        },
+        Ok(r) => panic!("expected a wall-time limit error, got {:?}", r),
+    }
+}
+
 #[test]
 fn synctex() {
     TestCase::new("synctex").check_synctex(true).go()
@@ -259,3 +302,506 @@ fn tectoniccodatokens_ok() {
 fn the_letter_a() {
     TestCase::new("the_letter_a").check_pdf(true).go()
 }
+
+/// Copies an XDV file through `XdvWriter`, using only the absolute positions
+/// reported by the parser. TeX only ever emits the "set" variants of the
+/// character opcodes, so characters can be copied with zero widths on both
+/// sides.
+struct XdvCopier(Option<XdvWriter<Vec<u8>>>);
+
+#[derive(Debug)]
+struct CopyError;
+
+type CopyResult = std::result::Result<(), CopyError>;
+
+impl From<XdvError> for CopyError {
+    fn from(_: XdvError) -> Self {
+        CopyError
+    }
+}
+
+impl From<IoError> for CopyError {
+    fn from(_: IoError) -> Self {
+        CopyError
+    }
+}
+
+impl XdvCopier {
+    fn w(&mut self) -> &mut XdvWriter<Vec<u8>> {
+        self.0.as_mut().unwrap()
+    }
+}
+
+impl XdvEvents for XdvCopier {
+    type Error = CopyError;
+
+    fn handle_header(&mut self, filetype: FileType, comment: &[u8]) -> CopyResult {
+        self.0 = Some(XdvWriter::new(Vec::new(), filetype, comment)?);
+        Ok(())
+    }
+
+    fn handle_begin_page(&mut self, counters: &[i32], _previous_bop: i32) -> CopyResult {
+        Ok(self.w().begin_page(counters)?)
+    }
+
+    fn handle_end_page(&mut self) -> CopyResult {
+        Ok(self.w().end_page()?)
+    }
+
+    fn handle_font_definition(&mut self, font_num: i32, def: &FontDefinition) -> CopyResult {
+        Ok(self.w().define_font(font_num, def)?)
+    }
+
+    fn handle_native_font_definition(
+        &mut self,
+        font_num: i32,
+        def: &NativeFontDefinition,
+    ) -> CopyResult {
+        Ok(self.w().define_native_font(font_num, def)?)
+    }
+
+    fn handle_char(&mut self, font_num: i32, char_num: i32, h: i32, v: i32) -> CopyResult {
+        let w = self.w();
+        w.select_font(font_num)?;
+        w.move_to(h, v)?;
+        Ok(w.set_char(char_num, 0)?)
+    }
+
+    fn handle_glyph_run(&mut self, font_num: i32, run: &GlyphRun) -> CopyResult {
+        let w = self.w();
+        w.select_font(font_num)?;
+        Ok(w.glyph_run(run)?)
+    }
+
+    fn handle_rule(&mut self, h: i32, v: i32, height: i32, width: i32) -> CopyResult {
+        let w = self.w();
+        w.move_to(h, v)?;
+        Ok(w.put_rule(height, width)?)
+    }
+
+    fn handle_positioned_special(&mut self, h: i32, v: i32, contents: &[u8]) -> CopyResult {
+        let w = self.w();
+        w.move_to(h, v)?;
+        Ok(w.special(contents)?)
+    }
+}
+
+/// Rewriting an XDV file with `XdvWriter` should produce something that
+/// xdvipdfmx accepts directly.
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn xdv_rewrite() {
+    util::set_test_root();
+
+    let orig = fs::read(test_path(&["tex-outputs", "the_letter_a.xdv"])).unwrap();
+    let (copier, _) = XdvParser::process(&orig[..], XdvCopier(None)).unwrap();
+    let copy = copier.0.unwrap().finish().unwrap();
+
+    let mut mem = MemoryIo::new(true);
+    mem.create_entry(OsStr::new("rewritten.xdv"), copy);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, false, HashSet::new());
+    let mut io = IoStack::new(vec![&mut mem, &mut assets]);
+
+    env::set_var("SOURCE_DATE_EPOCH", "1456304492");
+    XdvipdfmxEngine::new()
+        .with_compression(false)
+        .with_deterministic_tags(true)
+        .process(
+            &mut io,
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+            "rewritten.xdv",
+            "rewritten.pdf",
+        )
+        .unwrap();
+
+    let files = mem.files.borrow();
+    let pdf = files.get(OsStr::new("rewritten.pdf")).unwrap();
+    assert!(pdf.starts_with(b"%PDF-"));
+}
+
+/// PDF/A output should carry the XMP metadata and output intent that the
+/// standard requires.
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn pdfa_letter_a() {
+    util::set_test_root();
+
+    let xdv = fs::read(test_path(&["tex-outputs", "the_letter_a.xdv"])).unwrap();
+    let mut mem = MemoryIo::new(true);
+    mem.create_entry(OsStr::new("the_letter_a.xdv"), xdv);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, false, HashSet::new());
+    let mut io = IoStack::new(vec![&mut mem, &mut assets]);
+
+    env::set_var("SOURCE_DATE_EPOCH", "1456304492");
+    XdvipdfmxEngine::new()
+        .with_compression(false)
+        .with_deterministic_tags(true)
+        .with_pdfa(Some(PdfAConformance::A1b))
+        .process(
+            &mut io,
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+            "the_letter_a.xdv",
+            "the_letter_a.pdf",
+        )
+        .unwrap();
+
+    let files = mem.files.borrow();
+    let pdf = String::from_utf8_lossy(files.get(OsStr::new("the_letter_a.pdf")).unwrap());
+    assert!(pdf.starts_with("%PDF-1.4"));
+    assert!(pdf.contains("/OutputIntents"));
+    assert!(pdf.contains("/GTS_PDFA1"));
+    assert!(pdf.contains("/Metadata"));
+    assert!(pdf.contains("<pdfaid:part>1</pdfaid:part>"));
+    assert!(pdf.contains("<xmp:CreateDate>2016-02-24T09:01:32"));
+}
+
+/// Marked content that starts and ends in the middle of a line must not end
+/// up inside the text object that holds the line.
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn tagged_pdf_mid_line() {
+    util::set_test_root();
+
+    let mut fmt =
+        SingleInputFileIo::new(&ensure_plain_format().expect("couldn't write format file"));
+    let mut tex =
+        FilesystemPrimaryInputIo::new(&test_path(&["tex-outputs", "tagged_mid_line.tex"]));
+    let mut mem = MemoryIo::new(true);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, false, HashSet::new());
+
+    {
+        let mut io = IoStack::new(vec![&mut mem, &mut tex, &mut fmt, &mut assets]);
+        let mut events = NoopIoEventBackend::new();
+        let mut status = NoopStatusBackend::new();
+
+        TexEngine::new()
+            .process(
+                &mut io,
+                &mut events,
+                &mut status,
+                "plain.fmt",
+                "tagged_mid_line.tex",
+            )
+            .unwrap();
+
+        env::set_var("SOURCE_DATE_EPOCH", "1456304492");
+        XdvipdfmxEngine::new()
+            .with_compression(false)
+            .with_deterministic_tags(true)
+            .process(
+                &mut io,
+                &mut events,
+                &mut status,
+                "tagged_mid_line.xdv",
+                "tagged_mid_line.pdf",
+            )
+            .unwrap();
+    }
+
+    let files = mem.files.borrow();
+    let pdf = String::from_utf8_lossy(files.get(OsStr::new("tagged_mid_line.pdf")).unwrap());
+    assert!(pdf.contains("/StructTreeRoot"));
+
+    let content = pdf
+        .split("endstream")
+        .filter_map(|s| s.splitn(2, "\nstream\n").nth(1))
+        .find(|s| s.contains(" BDC"))
+        .unwrap();
+    let mut in_text = false;
+    let mut n_marked = 0;
+    for op in content.split_whitespace() {
+        match op {
+            "BT" => in_text = true,
+            "ET" => in_text = false,
+            "BDC" | "EMC" => {
+                assert!(!in_text, "{} inside a text object: {}", op, content);
+                n_marked += 1;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(n_marked, 2);
+}
+
+/// "pdf:majorversion 2" should produce a PDF 2.0 file without the ProcSet
+/// resource and with only the dates left in DocInfo.
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn pdf20_output() {
+    util::set_test_root();
+
+    let mut fmt =
+        SingleInputFileIo::new(&ensure_plain_format().expect("couldn't write format file"));
+    let mut tex = FilesystemPrimaryInputIo::new(&test_path(&["tex-outputs", "pdf20.tex"]));
+    let mut mem = MemoryIo::new(true);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, false, HashSet::new());
+
+    {
+        let mut io = IoStack::new(vec![&mut mem, &mut tex, &mut fmt, &mut assets]);
+        let mut events = NoopIoEventBackend::new();
+        let mut status = NoopStatusBackend::new();
+
+        TexEngine::new()
+            .process(&mut io, &mut events, &mut status, "plain.fmt", "pdf20.tex")
+            .unwrap();
+
+        env::set_var("SOURCE_DATE_EPOCH", "1456304492");
+        XdvipdfmxEngine::new()
+            .with_compression(false)
+            .with_deterministic_tags(true)
+            .process(&mut io, &mut events, &mut status, "pdf20.xdv", "pdf20.pdf")
+            .unwrap();
+    }
+
+    let files = mem.files.borrow();
+    let pdf = String::from_utf8_lossy(files.get(OsStr::new("pdf20.pdf")).unwrap());
+    assert!(pdf.starts_with("%PDF-2.0\n"));
+    assert!(!pdf.contains("/ProcSet"));
+    assert!(pdf.contains("/Metadata"));
+    assert!(pdf.contains("/CreationDate"));
+    for key in &["/Title", "/Author", "/Producer", "/Creator"] {
+        assert!(!pdf.contains(key), "{} left in DocInfo", key);
+    }
+}
+
+/// Linearized output should start with the linearization dictionary and
+/// end with a cross-reference pointing back at the first-page section.
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn linearized_letter_a() {
+    util::set_test_root();
+
+    let xdv = fs::read(test_path(&["tex-outputs", "the_letter_a.xdv"])).unwrap();
+    let mut mem = MemoryIo::new(true);
+    mem.create_entry(OsStr::new("the_letter_a.xdv"), xdv);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, false, HashSet::new());
+    let mut io = IoStack::new(vec![&mut mem, &mut assets]);
+
+    env::set_var("SOURCE_DATE_EPOCH", "1456304492");
+    XdvipdfmxEngine::new()
+        .with_compression(false)
+        .with_deterministic_tags(true)
+        .with_linearize(true)
+        .process(
+            &mut io,
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+            "the_letter_a.xdv",
+            "the_letter_a.pdf",
+        )
+        .unwrap();
+
+    let files = mem.files.borrow();
+    let pdf = files.get(OsStr::new("the_letter_a.pdf")).unwrap();
+    let find = |needle: &[u8]| pdf.windows(needle.len()).position(|w| w == needle).unwrap();
+    let head = String::from_utf8_lossy(&pdf[..find(b"endobj")]);
+    assert!(head.contains("<</Linearized 1 /L "));
+    assert!(head.contains("/N 1 "));
+    let tail = format!("startxref\n{}\n%%EOF\n", find(b"xref\n"));
+    assert!(pdf.ends_with(tail.as_bytes()));
+}
+
+#[test]
+fn xdv2svg_letter_a() {
+    util::set_test_root();
+
+    let xdv = fs::read(test_path(&["tex-outputs", "the_letter_a.xdv"])).unwrap();
+    let mut mem = MemoryIo::new(true);
+    mem.create_entry(OsStr::new("the_letter_a.xdv"), xdv);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, false, HashSet::new());
+    let mut io = IoStack::new(vec![&mut mem, &mut assets]);
+
+    Xdv2SvgEngine::new()
+        .process(
+            &mut io,
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+            "the_letter_a.xdv",
+        )
+        .unwrap();
+
+    let files = mem.files.borrow();
+    let svg =
+        String::from_utf8(files.get(OsStr::new("the_letter_a.svg")).unwrap().clone()).unwrap();
+    assert!(svg.contains("<svg "));
+    assert!(svg.contains("<path id=\"g"));
+    assert!(svg.contains("<use xlink:href=\"#g"));
+}
+
+#[test]
+fn xdv2text_letter_a() {
+    util::set_test_root();
+
+    let xdv = fs::read(test_path(&["tex-outputs", "the_letter_a.xdv"])).unwrap();
+    let mut mem = MemoryIo::new(true);
+    mem.create_entry(OsStr::new("the_letter_a.xdv"), xdv);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, false, HashSet::new());
+    let mut io = IoStack::new(vec![&mut mem, &mut assets]);
+
+    Xdv2TextEngine::new()
+        .with_word_boxes(true)
+        .process(
+            &mut io,
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+            "the_letter_a.xdv",
+        )
+        .unwrap();
+
+    let files = mem.files.borrow();
+    let text =
+        String::from_utf8(files.get(OsStr::new("the_letter_a.txt")).unwrap().clone()).unwrap();
+    assert_eq!(text.lines().next(), Some("a"));
+    let json = String::from_utf8(
+        files
+            .get(OsStr::new("the_letter_a.words.json"))
+            .unwrap()
+            .clone(),
+    )
+    .unwrap();
+    assert!(json.contains("\"text\": \"a\""));
+}
+
+#[test]
+fn xdv2png_letter_a() {
+    util::set_test_root();
+
+    let xdv = fs::read(test_path(&["tex-outputs", "the_letter_a.xdv"])).unwrap();
+    let mut mem = MemoryIo::new(true);
+    mem.create_entry(OsStr::new("the_letter_a.xdv"), xdv);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, false, HashSet::new());
+    let mut io = IoStack::new(vec![&mut mem, &mut assets]);
+
+    Xdv2PngEngine::new()
+        .with_dpi(72.0)
+        .process(
+            &mut io,
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+            "the_letter_a.xdv",
+        )
+        .unwrap();
+
+    // At 72 dpi, a US Letter page is 612 by 792 pixels.
+    let files = mem.files.borrow();
+    let png = files.get(OsStr::new("the_letter_a.png")).unwrap();
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..20], &612u32.to_be_bytes());
+    assert_eq!(&png[20..24], &792u32.to_be_bytes());
+}
+
+/// With a font index, the engine should see exactly the indexed fonts, along
+/// with their OpenType layout features.
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn list_indexed_fonts() {
+    util::set_test_root();
+
+    let mut status = NoopStatusBackend::new();
+    let mut index = FontIndex::new();
+    index
+        .add_directory(&test_path(&["assets"]), &mut status)
+        .unwrap();
+
+    // The index refers to the fonts by absolute path.
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, true, HashSet::new());
+    let mut io = IoStack::new(vec![&mut assets]);
+
+    let fonts = TexEngine::new()
+        .font_index(Some(index))
+        .list_fonts(&mut io, &mut status)
+        .unwrap();
+
+    assert_eq!(fonts.len(), 2);
+    assert!(fonts
+        .iter()
+        .any(|f| f.postscript_name == "TectonicFallbackTest-Regular"));
+    let font = fonts
+        .iter()
+        .find(|f| f.postscript_name == "LMRoman12-Regular")
+        .unwrap();
+    assert!(font.file.ends_with("lmroman12-regular.otf"));
+    assert_eq!(font.scripts, vec!["DFLT", "cyrl", "latn"]);
+    assert!(font.features.iter().any(|f| f == "kern"));
+    assert!(font.features.iter().any(|f| f == "liga"));
+    assert!(font.axes.is_empty());
+}
+
+/// A font requested by family name should be found by the fontconfig search,
+/// which must look at the fonts it has not cached yet.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+#[test]
+fn font_by_family_name() {
+    util::set_test_root();
+
+    let mut status = NoopStatusBackend::new();
+    let mut index = FontIndex::new();
+    index
+        .add_directory(&test_path(&["assets"]), &mut status)
+        .unwrap();
+
+    let mut fmt =
+        SingleInputFileIo::new(&ensure_plain_format().expect("couldn't write format file"));
+    let mut tex = FilesystemPrimaryInputIo::new(&test_path(&["tex-outputs", "font_by_family.tex"]));
+    let mut mem = MemoryIo::new(true);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, true, HashSet::new());
+
+    {
+        let mut io = IoStack::new(vec![&mut mem, &mut tex, &mut fmt, &mut assets]);
+        let mut events = NoopIoEventBackend::new();
+
+        TexEngine::new()
+            .font_index(Some(index))
+            .process(
+                &mut io,
+                &mut events,
+                &mut status,
+                "plain.fmt",
+                "font_by_family.tex",
+            )
+            .unwrap();
+    }
+
+    let files = mem.files.borrow();
+    let xdv = String::from_utf8_lossy(files.get(OsStr::new("font_by_family.xdv")).unwrap());
+    assert!(xdv.contains("lmroman12-regular.otf"));
+}
+
+/// Characters that a native font lacks should be set in the first font of
+/// its `fallback=` chain that has them, without "Missing character" warnings.
+#[test]
+fn font_fallback() {
+    util::set_test_root();
+
+    let mut fmt =
+        SingleInputFileIo::new(&ensure_plain_format().expect("couldn't write format file"));
+    let mut tex = FilesystemPrimaryInputIo::new(&test_path(&["tex-outputs", "font_fallback.tex"]));
+    let mut mem = MemoryIo::new(true);
+    let mut assets = FilesystemIo::new(&test_path(&["assets"]), false, false, HashSet::new());
+
+    let res = {
+        let mut io = IoStack::new(vec![&mut mem, &mut tex, &mut fmt, &mut assets]);
+        TexEngine::new().process(
+            &mut io,
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+            "plain.fmt",
+            "font_fallback.tex",
+        )
+    };
+
+    // Only the snowman is missing from every font.
+    assert_eq!(res.unwrap(), TexResult::Warnings);
+
+    let files = mem.files.borrow();
+    let xdv = String::from_utf8_lossy(files.get(OsStr::new("font_fallback.xdv")).unwrap());
+    assert!(xdv.contains("lmroman12-regular"));
+    assert!(xdv.contains("tectonic-fallback-test"));
+
+    let log = String::from_utf8_lossy(files.get(OsStr::new("font_fallback.log")).unwrap());
+    assert_eq!(log.matches("Missing character: There is no ").count(), 1);
+    assert!(log.contains("Fallback font `[no-such-font]' for font"));
+}
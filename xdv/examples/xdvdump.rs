@@ -9,7 +9,7 @@ use std::fs::File;
 use std::io;
 use std::process;
 use std::str;
-use tectonic_xdv::{FileType, XdvError};
+use tectonic_xdv::{FileType, FontDefinition, GlyphRun, NativeFontDefinition, XdvError};
 
 /// We'd like to use String as our error type, but we also would like to
 /// use the `XdvParser::process()` function, which when imposes the requirement
@@ -109,6 +109,52 @@ impl tectonic_xdv::XdvEvents for Stats {
         );
         Ok(())
     }
+
+    fn handle_font_definition(
+        &mut self,
+        font_num: i32,
+        def: &FontDefinition,
+    ) -> Result<(), Self::Error> {
+        println!(
+            "font {}: {}{} scaled {}",
+            font_num, def.area, def.name, def.scale_factor
+        );
+        Ok(())
+    }
+
+    fn handle_native_font_definition(
+        &mut self,
+        font_num: i32,
+        def: &NativeFontDefinition,
+    ) -> Result<(), Self::Error> {
+        println!(
+            "native font {}: {}[{}] size {}",
+            font_num, def.path, def.face_index, def.size
+        );
+        Ok(())
+    }
+
+    fn handle_glyph_run(&mut self, font_num: i32, run: &GlyphRun) -> Result<(), Self::Error> {
+        let (h, v) = match (run.h.first(), run.v.first()) {
+            (Some(h), Some(v)) => (*h, *v),
+            _ => return Ok(()),
+        };
+
+        println!(
+            "glyphs: font {} at ({}, {}) count={} width={}",
+            font_num,
+            h,
+            v,
+            run.glyphs.len(),
+            run.width
+        );
+        Ok(())
+    }
+
+    fn handle_rule(&mut self, h: i32, v: i32, height: i32, width: i32) -> Result<(), Self::Error> {
+        println!("rule: at ({}, {}) {}x{}", h, v, width, height);
+        Ok(())
+    }
 }
 
 fn main() {
@@ -125,7 +171,7 @@ fn main() {
 
     let path = matches.value_of_os("PATH").unwrap();
 
-    let file = match File::open(&path) {
+    let file = match File::open(path) {
         Ok(f) => f,
         Err(e) => {
             eprintln!(
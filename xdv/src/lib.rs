@@ -3,7 +3,8 @@
 
 #![deny(missing_docs)]
 
-//! A decoder for the XDV and SPX file formats used by Tectonic and XeTeX.
+//! A decoder and encoder for the XDV and SPX file formats used by Tectonic
+//! and XeTeX.
 //!
 //! Both of these file formats are derived from the venerable “device
 //! independent” (DVI) format used by TeX. The XDV format (name presumably
@@ -12,13 +13,19 @@
 //! (“semantically-paginated XDV”) is essentially the same as XDV, but
 //! expresses output that is not paginated for print — this is what Tectonic
 //! uses to produce its HTML output.
+//!
+//! Files are decoded with `XdvParser`, which reports their contents to an
+//! implementation of `XdvEvents`, and encoded with `XdvWriter`.
 
 use byteorder::{BigEndian, ByteOrder};
 use std::error;
 use std::fmt::{Debug, Display, Error as FmtError, Formatter};
 use std::io::{Error as IoError, Read};
 use std::marker::PhantomData;
-use std::mem;
+
+mod writer;
+
+pub use writer::XdvWriter;
 
 /// Errors that can occur when parsing XDV/SPX files.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -131,6 +138,172 @@ pub trait XdvEvents {
     fn handle_char_run(&mut self, chars: &[i32]) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    /// End the current page.
+    #[allow(unused)]
+    fn handle_end_page(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle the definition of a traditional (TFM-based) font.
+    ///
+    /// Fonts are defined again in the postamble, so this may be called more
+    /// than once for the same font number.
+    #[allow(unused)]
+    fn handle_font_definition(
+        &mut self,
+        font_num: i32,
+        def: &FontDefinition,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle the definition of an XDV native (OpenType/TrueType) font.
+    ///
+    /// As with `handle_font_definition`, this may be called more than once
+    /// for the same font number.
+    #[allow(unused)]
+    fn handle_native_font_definition(
+        &mut self,
+        font_num: i32,
+        def: &NativeFontDefinition,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a change of the current font.
+    #[allow(unused)]
+    fn handle_select_font(&mut self, font_num: i32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a single character typeset in a traditional font.
+    ///
+    /// The character's reference point is at (*h*, *v*), measured in DVI
+    /// units from the top left of the page with *v* increasing downwards.
+    /// This is called for both the `set` and `put` variants of the opcodes,
+    /// and before the character is included in any `handle_char_run` call.
+    #[allow(unused)]
+    fn handle_char(
+        &mut self,
+        font_num: i32,
+        char_num: i32,
+        h: i32,
+        v: i32,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Report the advance width of a character in a traditional font.
+    ///
+    /// XDV files do not include TFM metrics, so the parser cannot advance
+    /// the horizontal position after a `set` character on its own. Event
+    /// handlers that load the TFM files themselves should return the width
+    /// here, in DVI units, to keep the reported positions accurate. The
+    /// default returns zero.
+    #[allow(unused)]
+    fn char_width(&mut self, font_num: i32, char_num: i32) -> Result<i32, Self::Error> {
+        Ok(0)
+    }
+
+    /// Handle a run of glyphs typeset in an XDV native font.
+    #[allow(unused)]
+    fn handle_glyph_run(&mut self, font_num: i32, run: &GlyphRun) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a rule (filled rectangle).
+    ///
+    /// The bottom left corner of the rule is at (*h*, *v*); it extends
+    /// *width* units to the right and *height* units upwards. Rules with a
+    /// non-positive width or height are not drawn by DVI processors, but are
+    /// still reported here.
+    #[allow(unused)]
+    fn handle_rule(&mut self, h: i32, v: i32, height: i32, width: i32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a `\special` along with the position at which it occurs.
+    ///
+    /// The default implementation forwards to `handle_special`.
+    #[allow(unused)]
+    fn handle_positioned_special(
+        &mut self,
+        h: i32,
+        v: i32,
+        contents: &[u8],
+    ) -> Result<(), Self::Error> {
+        self.handle_special(contents)
+    }
+}
+
+/// The definition of a traditional TeX font, backed by a TFM file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FontDefinition {
+    /// The checksum of the TFM file.
+    pub checksum: u32,
+
+    /// The size at which the font is used, in DVI units.
+    pub scale_factor: u32,
+
+    /// The design size of the font, in DVI units.
+    pub design_size: u32,
+
+    /// The directory in which the font should be looked for; usually empty.
+    pub area: String,
+
+    /// The name of the font.
+    pub name: String,
+}
+
+/// The definition of an XDV native font.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NativeFontDefinition {
+    /// The size of the font, in DVI units.
+    pub size: i32,
+
+    /// The path or name of the font file.
+    pub path: String,
+
+    /// The index of the face within the font file.
+    pub face_index: u32,
+
+    /// Whether the font is used in vertical mode.
+    pub vertical: bool,
+
+    /// The font color, as RGBA, if it is not the default.
+    pub color_rgba: Option<u32>,
+
+    /// OpenType variation axis settings, as (tag, fixed-point value) pairs.
+    pub variations: Vec<(u32, i32)>,
+
+    /// The horizontal extension factor, as a 16.16 fixed-point number.
+    pub extend: Option<i32>,
+
+    /// The slant factor, as a 16.16 fixed-point number.
+    pub slant: Option<i32>,
+
+    /// The emboldening factor, as a 16.16 fixed-point number.
+    pub embolden: Option<i32>,
+}
+
+/// A run of glyphs typeset in an XDV native font.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GlyphRun<'a> {
+    /// The UTF-16 text that the glyphs represent, if the file recorded it.
+    pub text: Option<&'a [u16]>,
+
+    /// The IDs of the glyphs within the font.
+    pub glyphs: &'a [u16],
+
+    /// The absolute horizontal position of each glyph, in DVI units.
+    pub h: &'a [i32],
+
+    /// The absolute vertical position of each glyph, in DVI units.
+    pub v: &'a [i32],
+
+    /// The total advance width of the run.
+    pub width: i32,
 }
 
 /// State for parsing an XDV file.
@@ -221,10 +394,7 @@ impl<T: XdvEvents> XdvParser<T> {
     {
         const BUF_SIZE: usize = 4096;
         let mut parser = Self::new(events);
-        let mut buf = Vec::with_capacity(BUF_SIZE);
-        unsafe {
-            buf.set_len(BUF_SIZE);
-        }
+        let mut buf = vec![0u8; BUF_SIZE];
         let mut n_saved_bytes = 0;
 
         loop {
@@ -252,10 +422,7 @@ impl<T: XdvEvents> XdvParser<T> {
                 // We're going to need a bigger buffer in order to handle whatever
                 // we're reading. Let's double it.
                 let len = buf.len();
-                buf.reserve(len);
-                unsafe {
-                    buf.set_len(2 * len);
-                }
+                buf.resize(2 * len, 0);
             }
 
             if n_read == 0 {
@@ -369,6 +536,14 @@ impl<T: XdvEvents> XdvParser<T> {
                     self.do_set_char(oc, &mut cursor)
                 }
 
+                oc if oc >= Opcode::PutChar1 as u8 && oc <= Opcode::PutChar4 as u8 => {
+                    self.do_put_char(oc, &mut cursor)
+                }
+
+                oc if oc == Opcode::SetRule as u8 || oc == Opcode::PutRule as u8 => {
+                    self.do_rule(oc, &mut cursor)
+                }
+
                 oc if oc == Opcode::SetGlyphs as u8 => self.do_set_glyphs(oc, &mut cursor),
 
                 oc if oc == Opcode::SetTextAndGlyphs as u8 => {
@@ -447,20 +622,23 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let _font_num = cursor.get_compact_i32_smpos(opcode - Opcode::DefineFont1 as u8)?;
-        let _checksum = cursor.get_u32()?;
-        let _scale_factor = cursor.get_u32()?;
-        let _design_size = cursor.get_u32()?;
+        let font_num = cursor.get_compact_i32_smpos(opcode - Opcode::DefineFont1 as u8)?;
+        let checksum = cursor.get_u32()?;
+        let scale_factor = cursor.get_u32()?;
+        let design_size = cursor.get_u32()?;
         let area_len = cursor.get_u8()?;
         let name_len = cursor.get_u8()?;
-        // XXX TEMP
-        use std::str::from_utf8;
-        let _area_str = from_utf8(cursor.get_slice(area_len as usize)?)
-            .unwrap()
-            .to_owned();
-        let _name_str = from_utf8(cursor.get_slice(name_len as usize)?)
-            .unwrap()
-            .to_owned();
+        let area = String::from_utf8_lossy(cursor.get_slice(area_len as usize)?).into_owned();
+        let name = String::from_utf8_lossy(cursor.get_slice(name_len as usize)?).into_owned();
+
+        let def = FontDefinition {
+            checksum,
+            scale_factor,
+            design_size,
+            area,
+            name,
+        };
+        self.events.handle_font_definition(font_num, &def)?;
         Ok(())
     }
 
@@ -473,41 +651,64 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let _font_num = cursor.get_i32()?;
-        let _size = cursor.get_i32()?; // fixed-point
+        let font_num = cursor.get_i32()?;
+        let size = cursor.get_i32()?; // fixed-point
         let flags = cursor.get_u16()?;
         let name_len = cursor.get_u8()?;
-        // XXX TEMP
-        use std::str::from_utf8;
-        let _name_str = from_utf8(cursor.get_slice(name_len as usize)?)
-            .unwrap()
-            .to_owned();
-        let _face_index = cursor.get_u32()?;
-
-        let _color_rgba = if flags & NativeFontFlags::Colored as u16 != 0 {
+        let path = String::from_utf8_lossy(cursor.get_slice(name_len as usize)?).into_owned();
+        let face_index = cursor.get_u32()?;
+
+        let color_rgba = if flags & NativeFontFlags::Colored as u16 != 0 {
             Some(cursor.get_u32()?)
         } else {
             None
         };
 
-        let _extend = if flags & NativeFontFlags::Extend as u16 != 0 {
-            Some(cursor.get_u32()?) // fixed-point
+        let variations = if flags & NativeFontFlags::Variations as u16 != 0 {
+            let n = cursor.get_u16()? as usize;
+            let mut axes = Vec::with_capacity(n);
+            for _ in 0..n {
+                axes.push(cursor.get_u32()?);
+            }
+            let mut variations = Vec::with_capacity(n);
+            for axis in axes {
+                variations.push((axis, cursor.get_i32()?)); // fixed-point
+            }
+            variations
+        } else {
+            Vec::new()
+        };
+
+        let extend = if flags & NativeFontFlags::Extend as u16 != 0 {
+            Some(cursor.get_i32()?) // fixed-point
         } else {
             None
         };
 
-        let _slant = if flags & NativeFontFlags::Slant as u16 != 0 {
-            Some(cursor.get_u32()?) // fixed-point
+        let slant = if flags & NativeFontFlags::Slant as u16 != 0 {
+            Some(cursor.get_i32()?) // fixed-point
         } else {
             None
         };
 
-        let _embolden = if flags & NativeFontFlags::Embolden as u16 != 0 {
-            Some(cursor.get_u32()?) // fixed-point
+        let embolden = if flags & NativeFontFlags::Embolden as u16 != 0 {
+            Some(cursor.get_i32()?) // fixed-point
         } else {
             None
         };
 
+        let def = NativeFontDefinition {
+            size,
+            path,
+            face_index,
+            vertical: flags & NativeFontFlags::Vertical as u16 != 0,
+            color_rgba,
+            variations,
+            extend,
+            slant,
+            embolden,
+        };
+        self.events.handle_native_font_definition(font_num, &def)?;
         Ok(())
     }
 
@@ -549,6 +750,7 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::Malformed(cursor.global_offset()).into_internal());
         }
 
+        self.events.handle_end_page()?;
         self.state = ParserState::BetweenPages;
         Ok(())
     }
@@ -706,7 +908,9 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        self.cur_font_num = Some(i32::from(opcode - Opcode::SetFontNumber0 as u8));
+        let font_num = i32::from(opcode - Opcode::SetFontNumber0 as u8);
+        self.cur_font_num = Some(font_num);
+        self.events.handle_select_font(font_num)?;
         Ok(())
     }
 
@@ -716,7 +920,9 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        self.cur_font_num = Some(cursor.get_compact_i32_smpos(opcode - Opcode::SetFont1 as u8)?);
+        let font_num = cursor.get_compact_i32_smpos(opcode - Opcode::SetFont1 as u8)?;
+        self.cur_font_num = Some(font_num);
+        self.events.handle_select_font(font_num)?;
         Ok(())
     }
 
@@ -730,8 +936,9 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let char_num = opcode - Opcode::SetCharNumber0 as u8;
-        self.cur_char_run.push(i32::from(char_num));
+        let char_num = i32::from(opcode - Opcode::SetCharNumber0 as u8);
+        self.emit_char(char_num, true, cursor)?;
+        self.cur_char_run.push(char_num);
         Ok(())
     }
 
@@ -742,32 +949,80 @@ impl<T: XdvEvents> XdvParser<T> {
         }
 
         let char_num = cursor.get_compact_i32_smpos(opcode - Opcode::SetChar1 as u8)?;
-        self.cur_char_run.push(char_num as i32);
+        self.emit_char(char_num, true, cursor)?;
+        self.cur_char_run.push(char_num);
         Ok(())
     }
 
-    fn do_set_glyphs(
+    /// Typeset a character without moving the current position.
+    fn do_put_char(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
+        if self.state != ParserState::InPage {
+            return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
+        }
+
+        let char_num = cursor.get_compact_i32_smpos(opcode - Opcode::PutChar1 as u8)?;
+        self.emit_char(char_num, false, cursor)?;
+        Ok(())
+    }
+
+    /// Report a character at the current position, optionally advancing
+    /// past it.
+    fn emit_char(
         &mut self,
-        opcode: u8,
-        cursor: &mut Cursor<T>,
+        char_num: i32,
+        advance: bool,
+        cursor: &Cursor<T>,
     ) -> InternalResult<(), T::Error> {
+        let font_num = match self.cur_font_num {
+            Some(f) => f,
+            None => {
+                return Err(XdvError::Malformed(cursor.global_offset()).into_internal());
+            }
+        };
+
+        let (h, v) = {
+            let state = self.stack.last().unwrap();
+            (state.h, state.v)
+        };
+        self.events.handle_char(font_num, char_num, h, v)?;
+
+        if advance {
+            let width = self.events.char_width(font_num, char_num)?;
+            self.stack.last_mut().unwrap().h += width;
+        }
+
+        Ok(())
+    }
+
+    /// Handles both the `set` and `put` variants of rules.
+    fn do_rule(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
         if self.state != ParserState::InPage {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let _width = cursor.get_i32()?;
-        let n_glyphs = cursor.get_u16()?;
+        let height = cursor.get_i32()?;
+        let width = cursor.get_i32()?;
+        let state = self.stack.last_mut().unwrap();
+        let (h, v) = (state.h, state.v);
 
-        for _ in 0..n_glyphs {
-            let _x = cursor.get_u32()?;
-            let _y = cursor.get_u32()?;
+        if opcode == Opcode::SetRule as u8 {
+            state.h += width;
         }
 
-        for _ in 0..n_glyphs {
-            let _glyph_id = cursor.get_i16()?;
+        self.events.handle_rule(h, v, height, width)?;
+        Ok(())
+    }
+
+    fn do_set_glyphs(
+        &mut self,
+        opcode: u8,
+        cursor: &mut Cursor<T>,
+    ) -> InternalResult<(), T::Error> {
+        if self.state != ParserState::InPage {
+            return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        Ok(())
+        self.do_glyphs(None, cursor)
     }
 
     fn do_set_text_and_glyphs(
@@ -780,24 +1035,58 @@ impl<T: XdvEvents> XdvParser<T> {
         }
 
         let n_chars = cursor.get_u16()?;
-        let mut chars = Vec::new();
+        let mut chars = Vec::with_capacity(n_chars as usize);
 
         for _ in 0..n_chars {
             chars.push(cursor.get_u16()?);
         }
 
-        let _width = cursor.get_i32()?;
-        let n_glyphs = cursor.get_u16()?;
+        self.do_glyphs(Some(&chars), cursor)
+    }
+
+    /// The shared part of the two native-font glyph opcodes.
+    fn do_glyphs(
+        &mut self,
+        text: Option<&[u16]>,
+        cursor: &mut Cursor<T>,
+    ) -> InternalResult<(), T::Error> {
+        let width = cursor.get_i32()?;
+        let n_glyphs = cursor.get_u16()? as usize;
+        let (h0, v0) = {
+            let state = self.stack.last().unwrap();
+            (state.h, state.v)
+        };
+
+        let mut h = Vec::with_capacity(n_glyphs);
+        let mut v = Vec::with_capacity(n_glyphs);
 
         for _ in 0..n_glyphs {
-            let _x = cursor.get_u32()?;
-            let _y = cursor.get_u32()?;
+            h.push(h0.wrapping_add(cursor.get_i32()?));
+            v.push(v0.wrapping_add(cursor.get_i32()?));
         }
 
+        let mut glyphs = Vec::with_capacity(n_glyphs);
+
         for _ in 0..n_glyphs {
-            let _glyph_id = cursor.get_i16()?;
+            glyphs.push(cursor.get_u16()?);
         }
 
+        let font_num = match self.cur_font_num {
+            Some(f) => f,
+            None => {
+                return Err(XdvError::Malformed(cursor.global_offset()).into_internal());
+            }
+        };
+
+        let run = GlyphRun {
+            text,
+            glyphs: &glyphs,
+            h: &h,
+            v: &v,
+            width,
+        };
+        self.events.handle_glyph_run(font_num, &run)?;
+        self.stack.last_mut().unwrap().h += width;
         Ok(())
     }
 
@@ -807,7 +1096,12 @@ impl<T: XdvEvents> XdvParser<T> {
         }
 
         let n = cursor.get_compact_u32(opcode - Opcode::Special1 as u8)?;
-        self.events.handle_special(cursor.get_slice(n as usize)?)?;
+        let (h, v) = {
+            let state = self.stack.last().unwrap();
+            (state.h, state.v)
+        };
+        self.events
+            .handle_positioned_special(h, v, cursor.get_slice(n as usize)?)?;
 
         Ok(())
     }
@@ -963,7 +1257,7 @@ impl<'a, T: XdvEvents> Cursor<'a, T> {
             return Err(InternalError::NeedMoreData);
         }
 
-        let rv = unsafe { mem::transmute(self.buf[0]) };
+        let rv = self.buf[0] as i8;
         self.buf = &self.buf[1..];
         self.offset += 1;
         Ok(rv)
@@ -1095,6 +1389,10 @@ enum Opcode {
     SetCharNumber127 = 127,
     SetChar1 = 128,
     SetChar4 = 131,
+    SetRule = 132,
+    PutChar1 = 133,
+    PutChar4 = 136,
+    PutRule = 137,
     Noop = 138,
     BeginningOfPage = 139,
     EndOfPage = 140,
@@ -1147,7 +1445,527 @@ enum IdByte {
 enum NativeFontFlags {
     Vertical = 0x0100,
     Colored = 0x0200,
+    Variations = 0x0800,
     Extend = 0x1000,
     Slant = 0x2000,
     Embolden = 0x4000,
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Everything the parser reports, in order.
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) enum Event {
+        Header(FileType, Vec<u8>),
+        BeginPage(Vec<i32>, i32),
+        EndPage,
+        FontDefinition(i32, FontDefinition),
+        NativeFontDefinition(i32, NativeFontDefinition),
+        SelectFont(i32),
+        Char(i32, i32, i32, i32),
+        CharRun(Vec<i32>),
+        Rule(i32, i32, i32, i32),
+        GlyphRun(i32, Option<Vec<u16>>, Vec<u16>, Vec<i32>, Vec<i32>, i32),
+        Special(i32, i32, Vec<u8>),
+    }
+
+    /// The width that `Recorder` gives every traditional character.
+    pub(crate) const CHAR_WIDTH: i32 = 7;
+
+    #[derive(Debug, Default)]
+    pub(crate) struct Recorder {
+        pub(crate) events: Vec<Event>,
+    }
+
+    impl XdvEvents for Recorder {
+        type Error = XdvError;
+
+        fn handle_header(&mut self, filetype: FileType, comment: &[u8]) -> Result<(), XdvError> {
+            self.events.push(Event::Header(filetype, comment.to_vec()));
+            Ok(())
+        }
+
+        fn handle_begin_page(
+            &mut self,
+            counters: &[i32],
+            previous_bop: i32,
+        ) -> Result<(), XdvError> {
+            self.events
+                .push(Event::BeginPage(counters.to_vec(), previous_bop));
+            Ok(())
+        }
+
+        fn handle_end_page(&mut self) -> Result<(), XdvError> {
+            self.events.push(Event::EndPage);
+            Ok(())
+        }
+
+        fn handle_font_definition(
+            &mut self,
+            font_num: i32,
+            def: &FontDefinition,
+        ) -> Result<(), XdvError> {
+            self.events
+                .push(Event::FontDefinition(font_num, def.clone()));
+            Ok(())
+        }
+
+        fn handle_native_font_definition(
+            &mut self,
+            font_num: i32,
+            def: &NativeFontDefinition,
+        ) -> Result<(), XdvError> {
+            self.events
+                .push(Event::NativeFontDefinition(font_num, def.clone()));
+            Ok(())
+        }
+
+        fn handle_select_font(&mut self, font_num: i32) -> Result<(), XdvError> {
+            self.events.push(Event::SelectFont(font_num));
+            Ok(())
+        }
+
+        fn handle_char(
+            &mut self,
+            font_num: i32,
+            char_num: i32,
+            h: i32,
+            v: i32,
+        ) -> Result<(), XdvError> {
+            self.events.push(Event::Char(font_num, char_num, h, v));
+            Ok(())
+        }
+
+        fn char_width(&mut self, _font_num: i32, _char_num: i32) -> Result<i32, XdvError> {
+            Ok(CHAR_WIDTH)
+        }
+
+        fn handle_char_run(&mut self, chars: &[i32]) -> Result<(), XdvError> {
+            self.events.push(Event::CharRun(chars.to_vec()));
+            Ok(())
+        }
+
+        fn handle_rule(&mut self, h: i32, v: i32, height: i32, width: i32) -> Result<(), XdvError> {
+            self.events.push(Event::Rule(h, v, height, width));
+            Ok(())
+        }
+
+        fn handle_glyph_run(&mut self, font_num: i32, run: &GlyphRun) -> Result<(), XdvError> {
+            self.events.push(Event::GlyphRun(
+                font_num,
+                run.text.map(|t| t.to_vec()),
+                run.glyphs.to_vec(),
+                run.h.to_vec(),
+                run.v.to_vec(),
+                run.width,
+            ));
+            Ok(())
+        }
+
+        fn handle_positioned_special(
+            &mut self,
+            h: i32,
+            v: i32,
+            contents: &[u8],
+        ) -> Result<(), XdvError> {
+            self.events.push(Event::Special(h, v, contents.to_vec()));
+            Ok(())
+        }
+    }
+
+    /// Parse a complete file in one chunk, and again one byte at a time,
+    /// checking that both give the same events.
+    pub(crate) fn parse(data: &[u8]) -> Result<Vec<Event>, XdvError> {
+        let mut parser = XdvParser::new(Recorder::default());
+        let n = parser.parse(data)?;
+        let events = parser.finish()?.events;
+        assert_eq!(n, data.len());
+
+        let mut parser = XdvParser::new(Recorder::default());
+        let mut pending = Vec::new();
+
+        for b in data {
+            pending.push(*b);
+            let n = parser.parse(&pending)?;
+            pending.drain(..n);
+        }
+
+        assert_eq!(parser.finish()?.events, events);
+        Ok(events)
+    }
+
+    const COUNTERS: [i32; 10] = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    /// A hand-assembled XDV file.
+    struct Assembler(Vec<u8>);
+
+    impl Assembler {
+        fn new() -> Self {
+            let mut a = Assembler(Vec::new());
+            a.op(Opcode::Preamble).u8(IdByte::Xdv as u8);
+            a.u32(25_400_000).u32(473_628_672).u32(1000);
+            a.u8(2).bytes(b"hi");
+            a
+        }
+
+        fn op(&mut self, opcode: Opcode) -> &mut Self {
+            self.u8(opcode as u8)
+        }
+
+        fn u8(&mut self, b: u8) -> &mut Self {
+            self.0.push(b);
+            self
+        }
+
+        fn u16(&mut self, n: u16) -> &mut Self {
+            self.bytes(&n.to_be_bytes())
+        }
+
+        fn u32(&mut self, n: u32) -> &mut Self {
+            self.bytes(&n.to_be_bytes())
+        }
+
+        fn i32(&mut self, n: i32) -> &mut Self {
+            self.bytes(&n.to_be_bytes())
+        }
+
+        fn bytes(&mut self, b: &[u8]) -> &mut Self {
+            self.0.extend_from_slice(b);
+            self
+        }
+
+        fn begin_page(&mut self) -> &mut Self {
+            self.op(Opcode::BeginningOfPage);
+            for c in &COUNTERS {
+                self.i32(*c);
+            }
+            self.i32(-1)
+        }
+
+        fn tfm_font(&mut self, font_num: u8) -> &mut Self {
+            self.op(Opcode::DefineFont1).u8(font_num);
+            self.u32(0x1234_5678).u32(655_360).u32(655_360);
+            self.u8(0).u8(5).bytes(b"cmr10")
+        }
+
+        fn native_font(&mut self, font_num: i32, flags: u16, extra: &[u8]) -> &mut Self {
+            self.op(Opcode::DefineNativeFont).i32(font_num).i32(655_360);
+            self.u16(flags).u8(6).bytes(b"lm.otf").u32(2);
+            self.bytes(extra)
+        }
+
+        /// Add a postamble without any font definitions.
+        fn finish(&mut self) -> Vec<u8> {
+            let post = self.0.len() as u32;
+            self.op(Opcode::Postamble).i32(-1);
+            self.u32(25_400_000).u32(473_628_672).u32(1000);
+            self.u32(0).u32(0).u16(0).u16(1);
+            self.op(Opcode::DoublePostamble).u32(post);
+            self.u8(IdByte::Xdv as u8).bytes(&[0xDF; 4]);
+            self.0.clone()
+        }
+    }
+
+    /// The events of the first page of `events`.
+    fn page(events: &[Event]) -> &[Event] {
+        let start = events
+            .iter()
+            .position(|e| matches!(e, Event::BeginPage(..)))
+            .unwrap();
+        let end = events.iter().position(|e| *e == Event::EndPage).unwrap();
+        &events[start + 1..end]
+    }
+
+    #[test]
+    fn positions_follow_moves_and_registers() {
+        let mut a = Assembler::new();
+        a.tfm_font(3).begin_page();
+        a.u8(Opcode::SetFontNumber0 as u8 + 3);
+        a.op(Opcode::Right1).u8(10);
+        a.u8(Opcode::Down1 as u8 + 1).u16(256);
+        a.u8(b'A'); // set, advancing by CHAR_WIDTH to h = 17
+        a.op(Opcode::PushStack);
+        a.op(Opcode::SetW1).u8(5); // h = 22
+        a.op(Opcode::SetChar1).u8(200); // h = 29
+        a.op(Opcode::RightByW); // h = 34
+        a.op(Opcode::SetX1).u8(4); // h = 38
+        a.op(Opcode::RightByX); // h = 42
+        a.op(Opcode::SetY1).u8(3); // v = 259
+        a.op(Opcode::DownByY); // v = 262
+        a.op(Opcode::SetZ1).u8(0xFE); // v = 260
+        a.op(Opcode::DownByZ); // v = 258
+        a.op(Opcode::Right1).u8(0xFB); // h = 37
+        a.op(Opcode::PutChar1).u8(b'C');
+        a.op(Opcode::Special1).u8(3).bytes(b"foo");
+        a.op(Opcode::PopStack); // back to (17, 256), with w = x = y = z = 0
+        a.op(Opcode::RightByW).op(Opcode::RightByX);
+        a.op(Opcode::DownByY).op(Opcode::DownByZ);
+        a.u8(b'D');
+        a.op(Opcode::EndOfPage);
+        let events = parse(&a.finish()).unwrap();
+
+        assert_eq!(events[0], Event::Header(FileType::Xdv, b"hi".to_vec()));
+        assert_eq!(events[2], Event::BeginPage(COUNTERS.to_vec(), -1));
+        assert_eq!(
+            page(&events),
+            &[
+                Event::SelectFont(3),
+                Event::Char(3, 65, 10, 256),
+                Event::CharRun(vec![65]),
+                Event::Char(3, 200, 22, 256),
+                Event::CharRun(vec![200]),
+                Event::Char(3, 67, 37, 258),
+                Event::Special(37, 258, b"foo".to_vec()),
+                Event::Char(3, 68, 17, 256),
+            ][..]
+        );
+    }
+
+    #[test]
+    fn set_and_put_rules() {
+        let mut a = Assembler::new();
+        a.begin_page();
+        a.op(Opcode::Down1).u8(50);
+        a.op(Opcode::SetRule).i32(20).i32(30);
+        a.op(Opcode::PutRule).i32(5).i32(6);
+        a.op(Opcode::SetRule).i32(-1).i32(8);
+        a.op(Opcode::PutRule).i32(1).i32(1);
+        a.op(Opcode::EndOfPage);
+        let events = parse(&a.finish()).unwrap();
+
+        assert_eq!(
+            page(&events),
+            &[
+                Event::Rule(0, 50, 20, 30),
+                Event::Rule(30, 50, 5, 6),
+                Event::Rule(30, 50, -1, 8),
+                Event::Rule(38, 50, 1, 1),
+            ][..]
+        );
+    }
+
+    #[test]
+    fn set_and_put_chars() {
+        let mut a = Assembler::new();
+        a.tfm_font(0).begin_page();
+        a.op(Opcode::SetFont1).u8(0);
+        a.u8(b'a').u8(b'b'); // one run
+        a.op(Opcode::PutChar1).u8(b'c'); // not part of any run
+        a.u8(Opcode::SetChar1 as u8 + 1).u16(0x1234);
+        a.u8(Opcode::PutChar1 as u8 + 3).i32(0x0012_3456);
+        a.u8(b'e');
+        a.op(Opcode::EndOfPage);
+        let events = parse(&a.finish()).unwrap();
+
+        // A run ends once the command after it has been handled, so the `put`
+        // shows up before the run that it ends, and the final run follows
+        // the end of the page.
+        assert_eq!(
+            page(&events),
+            &[
+                Event::SelectFont(0),
+                Event::Char(0, 97, 0, 0),
+                Event::Char(0, 98, 7, 0),
+                Event::Char(0, 99, 14, 0),
+                Event::CharRun(vec![97, 98]),
+                Event::Char(0, 0x1234, 14, 0),
+                Event::Char(0, 0x12_3456, 21, 0),
+                Event::CharRun(vec![0x1234]),
+                Event::Char(0, 101, 21, 0),
+            ][..]
+        );
+        assert_eq!(events[events.len() - 1], Event::CharRun(vec![101]));
+    }
+
+    #[test]
+    fn chars_need_a_font() {
+        let mut a = Assembler::new();
+        a.begin_page().u8(b'a').op(Opcode::EndOfPage);
+        assert!(matches!(parse(&a.finish()), Err(XdvError::Malformed(_))));
+    }
+
+    #[test]
+    fn native_font_definitions() {
+        let plain = NativeFontDefinition {
+            size: 655_360,
+            path: "lm.otf".to_owned(),
+            face_index: 2,
+            vertical: false,
+            color_rgba: None,
+            variations: Vec::new(),
+            extend: None,
+            slant: None,
+            embolden: None,
+        };
+
+        let wght = u32::from_be_bytes(*b"wght");
+        let wdth = u32::from_be_bytes(*b"wdth");
+        let mut variations = Vec::new();
+        variations.extend_from_slice(&2u16.to_be_bytes());
+        variations.extend_from_slice(&wght.to_be_bytes());
+        variations.extend_from_slice(&wdth.to_be_bytes());
+        variations.extend_from_slice(&(700i32 << 16).to_be_bytes());
+        variations.extend_from_slice(&(-1i32).to_be_bytes());
+
+        let cases: Vec<(u16, Vec<u8>, NativeFontDefinition)> = vec![
+            (0, Vec::new(), plain.clone()),
+            (
+                NativeFontFlags::Vertical as u16,
+                Vec::new(),
+                NativeFontDefinition {
+                    vertical: true,
+                    ..plain.clone()
+                },
+            ),
+            (
+                NativeFontFlags::Colored as u16,
+                0xFF00_80C0u32.to_be_bytes().to_vec(),
+                NativeFontDefinition {
+                    color_rgba: Some(0xFF00_80C0),
+                    ..plain.clone()
+                },
+            ),
+            (
+                NativeFontFlags::Variations as u16,
+                variations.clone(),
+                NativeFontDefinition {
+                    variations: vec![(wght, 700 << 16), (wdth, -1)],
+                    ..plain.clone()
+                },
+            ),
+            (
+                NativeFontFlags::Extend as u16,
+                0x0001_8000u32.to_be_bytes().to_vec(),
+                NativeFontDefinition {
+                    extend: Some(0x0001_8000),
+                    ..plain.clone()
+                },
+            ),
+            (
+                NativeFontFlags::Slant as u16,
+                (-0x4000i32).to_be_bytes().to_vec(),
+                NativeFontDefinition {
+                    slant: Some(-0x4000),
+                    ..plain.clone()
+                },
+            ),
+            (
+                NativeFontFlags::Embolden as u16,
+                0x0000_2000u32.to_be_bytes().to_vec(),
+                NativeFontDefinition {
+                    embolden: Some(0x2000),
+                    ..plain.clone()
+                },
+            ),
+            (
+                0x7B00,
+                [
+                    &0x1122_3344u32.to_be_bytes()[..],
+                    &variations,
+                    &3i32.to_be_bytes(),
+                    &4i32.to_be_bytes(),
+                    &5i32.to_be_bytes(),
+                ]
+                .concat(),
+                NativeFontDefinition {
+                    vertical: true,
+                    color_rgba: Some(0x1122_3344),
+                    variations: vec![(wght, 700 << 16), (wdth, -1)],
+                    extend: Some(3),
+                    slant: Some(4),
+                    embolden: Some(5),
+                    ..plain.clone()
+                },
+            ),
+        ];
+
+        for (flags, extra, expected) in cases {
+            let mut a = Assembler::new();
+            a.native_font(300, flags, &extra);
+            let events = parse(&a.finish()).unwrap();
+            assert_eq!(
+                events[1],
+                Event::NativeFontDefinition(300, expected),
+                "flags {:#x}",
+                flags
+            );
+        }
+    }
+
+    #[test]
+    fn glyph_runs_with_and_without_text() {
+        let mut a = Assembler::new();
+        a.native_font(70, 0, &[]).begin_page();
+        a.u8(Opcode::SetFont1 as u8).u8(70);
+        a.op(Opcode::Right1).u8(100).op(Opcode::Down1).u8(120);
+        a.op(Opcode::SetGlyphs).i32(50).u16(2);
+        a.i32(0).i32(0).i32(20).i32(-1);
+        a.u16(3).u16(4);
+        a.op(Opcode::SetTextAndGlyphs)
+            .u16(2)
+            .u16(u16::from(b'h'))
+            .u16(u16::from(b'i'));
+        a.i32(30).u16(1).i32(5).i32(0).u16(9);
+        a.op(Opcode::PutRule).i32(1).i32(1);
+        a.op(Opcode::EndOfPage);
+        let events = parse(&a.finish()).unwrap();
+
+        assert_eq!(
+            page(&events),
+            &[
+                Event::SelectFont(70),
+                Event::GlyphRun(70, None, vec![3, 4], vec![100, 120], vec![120, 119], 50),
+                Event::GlyphRun(
+                    70,
+                    Some(vec![u16::from(b'h'), u16::from(b'i')]),
+                    vec![9],
+                    vec![155],
+                    vec![120],
+                    30
+                ),
+                Event::Rule(180, 120, 1, 1),
+            ][..]
+        );
+    }
+
+    #[test]
+    fn unbalanced_stack_is_malformed() {
+        let mut a = Assembler::new();
+        a.begin_page().op(Opcode::PopStack);
+        assert!(matches!(parse(&a.finish()), Err(XdvError::Malformed(_))));
+
+        let mut a = Assembler::new();
+        a.begin_page().op(Opcode::PushStack).op(Opcode::EndOfPage);
+        assert!(matches!(parse(&a.finish()), Err(XdvError::Malformed(_))));
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let mut a = Assembler::new();
+        a.tfm_font(0).native_font(1, 0x7B00, &[0; 4 + 2 + 12]);
+        a.begin_page();
+        a.u8(Opcode::SetFontNumber0 as u8).u8(b'x');
+        a.u8(Opcode::SetFontNumber0 as u8 + 1);
+        a.op(Opcode::SetTextAndGlyphs).u16(1).u16(1);
+        a.i32(1).u16(1).i32(0).i32(0).u16(1);
+        a.op(Opcode::Special1).u8(2).bytes(b"ab");
+        a.op(Opcode::EndOfPage);
+        let data = a.finish();
+
+        // The variation count in the all-flags font is zero.
+        assert!(parse(&data).is_ok());
+
+        for n in 0..data.len() {
+            let mut parser = XdvParser::new(Recorder::default());
+            let result = parser.parse(&data[..n]).and_then(|_| parser.finish());
+            assert_eq!(
+                result.map(|_| ()),
+                Err(XdvError::UnexpectedEndOfStream),
+                "{} bytes",
+                n
+            );
+        }
+    }
+}
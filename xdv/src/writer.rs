@@ -0,0 +1,952 @@
+// Copyright 2018-2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! An encoder for the XDV and SPX file formats.
+
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult, Write};
+
+use super::{
+    FileType, FontDefinition, GlyphRun, IdByte, NativeFontDefinition, NativeFontFlags, Opcode,
+};
+
+/// The numerator and denominator of the DVI unit, which is the TeX scaled
+/// point.
+const UNIT_NUMERATOR: u32 = 25_400_000;
+const UNIT_DENOMINATOR: u32 = 473_628_672;
+
+/// The largest stack depth that xdvipdfmx will accept.
+const MAX_STACK_DEPTH: usize = 256;
+
+/// A font that has been defined in the output, remembered so that it can be
+/// defined again in the postamble.
+#[derive(Clone, Debug)]
+enum DefinedFont {
+    Tfm(FontDefinition),
+    Native(NativeFontDefinition),
+}
+
+/// State for writing an XDV or SPX file.
+///
+/// The writer emits the preamble when it is created and the postamble when
+/// `finish()` is called; in between, pages are written with `begin_page()`,
+/// drawing operations, and `end_page()`. The page back-pointers, the
+/// postamble font definitions, and the trailing padding are all handled
+/// automatically, so that the output can be fed directly to xdvipdfmx.
+///
+/// The writer keeps track of the current position in the same way as
+/// `XdvParser`, so the absolute positions reported by the parser events can
+/// be reproduced with `move_to()`. Note, however, that neither side knows
+/// the widths of characters in traditional TFM fonts: a filter that copies
+/// such characters should either supply their widths to both the parser and
+/// `set_char()`, or position them with `move_to()` and `put_char()`.
+///
+/// When several documents are merged into one, it is up to the caller to
+/// make sure that their font numbers do not collide.
+#[derive(Debug)]
+pub struct XdvWriter<W: Write> {
+    inner: W,
+    filetype: FileType,
+    mag: u32,
+    offset: u64,
+    last_bop: i32,
+    n_pages: u32,
+    in_page: bool,
+    h: i32,
+    v: i32,
+    stack: Vec<(i32, i32)>,
+    max_stack_depth: usize,
+    max_h: i32,
+    max_v: i32,
+    cur_font_num: Option<i32>,
+    fonts: Vec<(i32, DefinedFont)>,
+}
+
+impl<W: Write> XdvWriter<W> {
+    /// Create a new writer, emitting the file preamble.
+    ///
+    /// The *comment* is truncated to 255 bytes if it is longer.
+    pub fn new(inner: W, filetype: FileType, comment: &[u8]) -> IoResult<Self> {
+        Self::new_with_mag(inner, filetype, comment, 1000)
+    }
+
+    /// Like `new()`, but with a magnification factor other than 1000.
+    pub fn new_with_mag(inner: W, filetype: FileType, comment: &[u8], mag: u32) -> IoResult<Self> {
+        let mut w = XdvWriter {
+            inner,
+            filetype,
+            mag,
+            offset: 0,
+            last_bop: -1,
+            n_pages: 0,
+            in_page: false,
+            h: 0,
+            v: 0,
+            stack: Vec::new(),
+            max_stack_depth: 0,
+            max_h: 0,
+            max_v: 0,
+            cur_font_num: None,
+            fonts: Vec::new(),
+        };
+
+        let comment = &comment[..comment.len().min(255)];
+        w.put_u8(Opcode::Preamble as u8)?;
+        w.put_u8(w.id_byte())?;
+        w.put_u32(UNIT_NUMERATOR)?;
+        w.put_u32(UNIT_DENOMINATOR)?;
+        w.put_u32(mag)?;
+        w.put_u8(comment.len() as u8)?;
+        w.put(comment)?;
+        Ok(w)
+    }
+
+    /// Get the number of bytes written so far.
+    pub fn current_offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Get the number of pages written so far.
+    pub fn n_pages(&self) -> u32 {
+        self.n_pages
+    }
+
+    /// Get the current position on the page, in DVI units.
+    pub fn position(&self) -> (i32, i32) {
+        (self.h, self.v)
+    }
+
+    /// Define a traditional TeX font.
+    ///
+    /// Fonts may be defined between pages or within them, but must be
+    /// defined before they are selected. All defined fonts are repeated in
+    /// the postamble.
+    pub fn define_font(&mut self, font_num: i32, def: &FontDefinition) -> IoResult<()> {
+        let area = def.area.as_bytes();
+        let name = def.name.as_bytes();
+
+        if area.len() > 255 || name.len() > 255 {
+            return Err(invalid("font area or name is longer than 255 bytes"));
+        }
+
+        self.write_font_definition(font_num, def)?;
+        self.remember_font(font_num, DefinedFont::Tfm(def.clone()));
+        Ok(())
+    }
+
+    /// Define an XDV native font.
+    ///
+    /// The same rules apply as for `define_font()`.
+    pub fn define_native_font(
+        &mut self,
+        font_num: i32,
+        def: &NativeFontDefinition,
+    ) -> IoResult<()> {
+        if def.path.len() > 255 {
+            return Err(invalid("native font path is longer than 255 bytes"));
+        }
+
+        if def.variations.len() > 0xFFFF {
+            return Err(invalid("too many font variation settings"));
+        }
+
+        self.write_native_font_definition(font_num, def)?;
+        self.remember_font(font_num, DefinedFont::Native(def.clone()));
+        Ok(())
+    }
+
+    /// Begin a new page with the given ten TeX counter values.
+    pub fn begin_page(&mut self, counters: &[i32]) -> IoResult<()> {
+        if self.in_page {
+            return Err(invalid("cannot begin a page inside another page"));
+        }
+
+        if counters.len() != 10 {
+            return Err(invalid("pages must have exactly ten counters"));
+        }
+
+        if self.offset > i32::MAX as u64 {
+            return Err(invalid("XDV output exceeds 2 GiB"));
+        }
+
+        let bop = self.offset as i32;
+        self.put_u8(Opcode::BeginningOfPage as u8)?;
+
+        for c in counters {
+            self.put_i32(*c)?;
+        }
+
+        self.put_i32(self.last_bop)?;
+        self.last_bop = bop;
+        self.in_page = true;
+        self.h = 0;
+        self.v = 0;
+        self.stack.clear();
+        self.cur_font_num = None;
+        Ok(())
+    }
+
+    /// End the current page. The stack must be balanced.
+    pub fn end_page(&mut self) -> IoResult<()> {
+        self.check_in_page()?;
+
+        if !self.stack.is_empty() {
+            return Err(invalid("unbalanced push/pop at end of page"));
+        }
+
+        self.put_u8(Opcode::EndOfPage as u8)?;
+        self.in_page = false;
+        self.n_pages += 1;
+        Ok(())
+    }
+
+    /// Save the current position on the stack.
+    pub fn push(&mut self) -> IoResult<()> {
+        self.check_in_page()?;
+
+        if self.stack.len() >= MAX_STACK_DEPTH {
+            return Err(invalid("XDV stack depth exceeded"));
+        }
+
+        self.put_u8(Opcode::PushStack as u8)?;
+        self.stack.push((self.h, self.v));
+        self.max_stack_depth = self.max_stack_depth.max(self.stack.len());
+        Ok(())
+    }
+
+    /// Restore the position most recently saved with `push()`.
+    pub fn pop(&mut self) -> IoResult<()> {
+        self.check_in_page()?;
+
+        let (h, v) = match self.stack.pop() {
+            Some(p) => p,
+            None => return Err(invalid("pop without matching push")),
+        };
+
+        self.put_u8(Opcode::PopStack as u8)?;
+        self.h = h;
+        self.v = v;
+        Ok(())
+    }
+
+    /// Move right by *n* DVI units (left if negative).
+    pub fn right(&mut self, n: i32) -> IoResult<()> {
+        self.check_in_page()?;
+
+        if n != 0 {
+            self.put_compact_signed(Opcode::Right1 as u8, n)?;
+            self.set_h(self.h.wrapping_add(n));
+        }
+
+        Ok(())
+    }
+
+    /// Move down by *n* DVI units (up if negative).
+    pub fn down(&mut self, n: i32) -> IoResult<()> {
+        self.check_in_page()?;
+
+        if n != 0 {
+            self.put_compact_signed(Opcode::Down1 as u8, n)?;
+            self.set_v(self.v.wrapping_add(n));
+        }
+
+        Ok(())
+    }
+
+    /// Move to the absolute position (*h*, *v*).
+    pub fn move_to(&mut self, h: i32, v: i32) -> IoResult<()> {
+        self.right(h.wrapping_sub(self.h))?;
+        self.down(v.wrapping_sub(self.v))
+    }
+
+    /// Make *font_num* the current font. Nothing is written if it already
+    /// is.
+    pub fn select_font(&mut self, font_num: i32) -> IoResult<()> {
+        self.check_in_page()?;
+
+        if self.cur_font_num == Some(font_num) {
+            return Ok(());
+        }
+
+        if !self.fonts.iter().any(|(n, _)| *n == font_num) {
+            return Err(invalid("selected an undefined font"));
+        }
+
+        if (0..64).contains(&font_num) {
+            self.put_u8(Opcode::SetFontNumber0 as u8 + font_num as u8)?;
+        } else {
+            self.put_compact_unsigned(Opcode::SetFont1 as u8, font_num as u32)?;
+        }
+
+        self.cur_font_num = Some(font_num);
+        Ok(())
+    }
+
+    /// Typeset a character in the current font and advance by *width*,
+    /// which should be the character's width according to the font's TFM
+    /// file.
+    pub fn set_char(&mut self, char_num: i32, width: i32) -> IoResult<()> {
+        self.check_font()?;
+
+        if (0..128).contains(&char_num) {
+            self.put_u8(Opcode::SetCharNumber0 as u8 + char_num as u8)?;
+        } else {
+            self.put_compact_unsigned(Opcode::SetChar1 as u8, char_num as u32)?;
+        }
+
+        self.set_h(self.h.wrapping_add(width));
+        Ok(())
+    }
+
+    /// Typeset a character in the current font without moving.
+    pub fn put_char(&mut self, char_num: i32) -> IoResult<()> {
+        self.check_font()?;
+        self.put_compact_unsigned(Opcode::PutChar1 as u8, char_num as u32)
+    }
+
+    /// Draw a rule with its bottom left corner at the current position, and
+    /// advance past it.
+    pub fn set_rule(&mut self, height: i32, width: i32) -> IoResult<()> {
+        self.check_in_page()?;
+        self.put_u8(Opcode::SetRule as u8)?;
+        self.put_i32(height)?;
+        self.put_i32(width)?;
+        self.set_h(self.h.wrapping_add(width));
+        Ok(())
+    }
+
+    /// Draw a rule with its bottom left corner at the current position,
+    /// without moving.
+    pub fn put_rule(&mut self, height: i32, width: i32) -> IoResult<()> {
+        self.check_in_page()?;
+        self.put_u8(Opcode::PutRule as u8)?;
+        self.put_i32(height)?;
+        self.put_i32(width)
+    }
+
+    /// Typeset a run of glyphs in the current font, which must be a native
+    /// font, and advance by the run's width.
+    ///
+    /// The glyph positions in *run* are absolute, as reported by the parser;
+    /// they are written relative to the current position.
+    pub fn glyph_run(&mut self, run: &GlyphRun) -> IoResult<()> {
+        self.check_font()?;
+
+        let n = run.glyphs.len();
+
+        if run.h.len() != n || run.v.len() != n {
+            return Err(invalid("glyph run has mismatched position arrays"));
+        }
+
+        if n > 0xFFFF {
+            return Err(invalid("too many glyphs in one run"));
+        }
+
+        if let Some(text) = run.text {
+            if text.len() > 0xFFFF {
+                return Err(invalid("too much text in one glyph run"));
+            }
+
+            self.put_u8(Opcode::SetTextAndGlyphs as u8)?;
+            self.put_u16(text.len() as u16)?;
+
+            for c in text {
+                self.put_u16(*c)?;
+            }
+        } else {
+            self.put_u8(Opcode::SetGlyphs as u8)?;
+        }
+
+        self.put_i32(run.width)?;
+        self.put_u16(n as u16)?;
+
+        for i in 0..n {
+            self.put_i32(run.h[i].wrapping_sub(self.h))?;
+            self.put_i32(run.v[i].wrapping_sub(self.v))?;
+        }
+
+        for g in run.glyphs {
+            self.put_u16(*g)?;
+        }
+
+        self.set_h(self.h.wrapping_add(run.width));
+        Ok(())
+    }
+
+    /// Emit a `\special` at the current position.
+    pub fn special(&mut self, contents: &[u8]) -> IoResult<()> {
+        self.check_in_page()?;
+
+        if contents.len() > u32::MAX as usize {
+            return Err(invalid("special is too long"));
+        }
+
+        self.put_compact_unsigned(Opcode::Special1 as u8, contents.len() as u32)?;
+        self.put(contents)
+    }
+
+    /// Write the postamble and return the underlying writer.
+    ///
+    /// Note that xdvipdfmx refuses to process files without any pages.
+    pub fn finish(mut self) -> IoResult<W> {
+        if self.in_page {
+            return Err(invalid("cannot finish the output inside a page"));
+        }
+
+        if self.n_pages > 0xFFFF {
+            return Err(invalid("too many pages for the XDV format"));
+        }
+
+        let post = self.offset as u32;
+        self.put_u8(Opcode::Postamble as u8)?;
+        self.put_i32(self.last_bop)?;
+        self.put_u32(UNIT_NUMERATOR)?;
+        self.put_u32(UNIT_DENOMINATOR)?;
+        self.put_u32(self.mag)?;
+        self.put_i32(self.max_v)?;
+        self.put_i32(self.max_h)?;
+        self.put_u16(self.max_stack_depth as u16)?;
+        self.put_u16(self.n_pages as u16)?;
+
+        let fonts = std::mem::take(&mut self.fonts);
+
+        for (font_num, font) in &fonts {
+            match font {
+                DefinedFont::Tfm(def) => self.write_font_definition(*font_num, def)?,
+                DefinedFont::Native(def) => self.write_native_font_definition(*font_num, def)?,
+            }
+        }
+
+        self.put_u8(Opcode::DoublePostamble as u8)?;
+        self.put_u32(post)?;
+        self.put_u8(self.id_byte())?;
+
+        // At least four padding bytes, bringing the total length to a
+        // multiple of four.
+        let n_pad = 4 + (4 - ((self.offset + 4) % 4) as usize) % 4;
+        self.put(&[0xDF; 7][..n_pad])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn id_byte(&self) -> u8 {
+        match self.filetype {
+            FileType::Xdv => IdByte::Xdv as u8,
+            FileType::Spx => IdByte::Spx as u8,
+        }
+    }
+
+    fn remember_font(&mut self, font_num: i32, font: DefinedFont) {
+        if let Some(entry) = self.fonts.iter_mut().find(|(n, _)| *n == font_num) {
+            entry.1 = font;
+        } else {
+            self.fonts.push((font_num, font));
+        }
+    }
+
+    fn write_font_definition(&mut self, font_num: i32, def: &FontDefinition) -> IoResult<()> {
+        self.put_compact_unsigned(Opcode::DefineFont1 as u8, font_num as u32)?;
+        self.put_u32(def.checksum)?;
+        self.put_u32(def.scale_factor)?;
+        self.put_u32(def.design_size)?;
+        self.put_u8(def.area.len() as u8)?;
+        self.put_u8(def.name.len() as u8)?;
+        self.put(def.area.as_bytes())?;
+        self.put(def.name.as_bytes())
+    }
+
+    fn write_native_font_definition(
+        &mut self,
+        font_num: i32,
+        def: &NativeFontDefinition,
+    ) -> IoResult<()> {
+        let mut flags = 0;
+
+        if def.vertical {
+            flags |= NativeFontFlags::Vertical as u16;
+        }
+        if def.color_rgba.is_some() {
+            flags |= NativeFontFlags::Colored as u16;
+        }
+        if !def.variations.is_empty() {
+            flags |= NativeFontFlags::Variations as u16;
+        }
+        if def.extend.is_some() {
+            flags |= NativeFontFlags::Extend as u16;
+        }
+        if def.slant.is_some() {
+            flags |= NativeFontFlags::Slant as u16;
+        }
+        if def.embolden.is_some() {
+            flags |= NativeFontFlags::Embolden as u16;
+        }
+
+        self.put_u8(Opcode::DefineNativeFont as u8)?;
+        self.put_i32(font_num)?;
+        self.put_i32(def.size)?;
+        self.put_u16(flags)?;
+        self.put_u8(def.path.len() as u8)?;
+        self.put(def.path.as_bytes())?;
+        self.put_u32(def.face_index)?;
+
+        if let Some(rgba) = def.color_rgba {
+            self.put_u32(rgba)?;
+        }
+
+        if !def.variations.is_empty() {
+            self.put_u16(def.variations.len() as u16)?;
+
+            for (tag, _) in &def.variations {
+                self.put_u32(*tag)?;
+            }
+
+            for (_, value) in &def.variations {
+                self.put_i32(*value)?;
+            }
+        }
+
+        for value in [def.extend, def.slant, def.embolden].iter().flatten() {
+            self.put_i32(*value)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_in_page(&self) -> IoResult<()> {
+        if self.in_page {
+            Ok(())
+        } else {
+            Err(invalid("drawing operation outside of a page"))
+        }
+    }
+
+    fn check_font(&self) -> IoResult<()> {
+        self.check_in_page()?;
+
+        if self.cur_font_num.is_none() {
+            return Err(invalid("no font selected"));
+        }
+
+        Ok(())
+    }
+
+    fn set_h(&mut self, h: i32) {
+        self.h = h;
+        self.max_h = self.max_h.max(h);
+    }
+
+    fn set_v(&mut self, v: i32) {
+        self.v = v;
+        self.max_v = self.max_v.max(v);
+    }
+
+    fn put(&mut self, buf: &[u8]) -> IoResult<()> {
+        self.inner.write_all(buf)?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    fn put_u8(&mut self, b: u8) -> IoResult<()> {
+        self.put(&[b])
+    }
+
+    fn put_u16(&mut self, n: u16) -> IoResult<()> {
+        self.put(&n.to_be_bytes())
+    }
+
+    fn put_u32(&mut self, n: u32) -> IoResult<()> {
+        self.put(&n.to_be_bytes())
+    }
+
+    fn put_i32(&mut self, n: i32) -> IoResult<()> {
+        self.put(&n.to_be_bytes())
+    }
+
+    /// Write one of a family of opcodes whose unsigned argument may be 1 to
+    /// 4 bytes wide, using the shortest form.
+    fn put_compact_unsigned(&mut self, base: u8, n: u32) -> IoResult<()> {
+        let size = if n < 0x100 {
+            1
+        } else if n < 0x1_0000 {
+            2
+        } else if n < 0x100_0000 {
+            3
+        } else {
+            4
+        };
+
+        self.put_u8(base + size as u8 - 1)?;
+        self.put(&n.to_be_bytes()[4 - size..])
+    }
+
+    /// Like `put_compact_unsigned`, but for signed arguments.
+    fn put_compact_signed(&mut self, base: u8, n: i32) -> IoResult<()> {
+        let size = if (-0x80..0x80).contains(&n) {
+            1
+        } else if (-0x8000..0x8000).contains(&n) {
+            2
+        } else if (-0x80_0000..0x80_0000).contains(&n) {
+            3
+        } else {
+            4
+        };
+
+        self.put_u8(base + size as u8 - 1)?;
+        self.put(&n.to_be_bytes()[4 - size..])
+    }
+}
+
+fn invalid(msg: &str) -> IoError {
+    IoError::new(IoErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{parse, Event};
+    use std::fmt::Debug;
+
+    fn counters(page: i32) -> Vec<i32> {
+        let mut c = vec![0; 10];
+        c[0] = page;
+        c
+    }
+
+    fn tfm_font() -> FontDefinition {
+        FontDefinition {
+            checksum: 0x1234_5678,
+            scale_factor: 655_360,
+            design_size: 655_360,
+            area: String::new(),
+            name: "cmr10".to_owned(),
+        }
+    }
+
+    fn native_font() -> NativeFontDefinition {
+        NativeFontDefinition {
+            size: 655_360,
+            path: "lmroman10-regular.otf".to_owned(),
+            face_index: 0,
+            vertical: false,
+            color_rgba: Some(0xFF00_00FF),
+            variations: vec![(u32::from_be_bytes(*b"wght"), 700 << 16)],
+            extend: None,
+            slant: Some(0x3000),
+            embolden: None,
+        }
+    }
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn i32_at(data: &[u8], offset: usize) -> i32 {
+        let mut b = [0; 4];
+        b.copy_from_slice(&data[offset..offset + 4]);
+        i32::from_be_bytes(b)
+    }
+
+    fn assert_invalid<T: Debug>(result: IoResult<T>, msg: &str) {
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), IoErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), msg);
+    }
+
+    fn writer() -> XdvWriter<Vec<u8>> {
+        XdvWriter::new(Vec::new(), FileType::Xdv, b"test").unwrap()
+    }
+
+    /// A writer with a page begun and font 0 defined.
+    fn writer_in_page() -> XdvWriter<Vec<u8>> {
+        let mut w = writer();
+        w.define_font(0, &tfm_font()).unwrap();
+        w.begin_page(&counters(1)).unwrap();
+        w
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut w = writer();
+        w.define_font(5, &tfm_font()).unwrap();
+        w.define_native_font(300, &native_font()).unwrap();
+
+        let bop1 = w.current_offset() as i32;
+        w.begin_page(&counters(1)).unwrap();
+        w.select_font(5).unwrap();
+        w.move_to(100, 200).unwrap();
+        w.set_char(i32::from(b'A'), 7).unwrap();
+        w.push().unwrap();
+        w.right(1000).unwrap();
+        w.down(-50).unwrap();
+        w.set_char(200, 7).unwrap();
+        w.put_char(i32::from(b'B')).unwrap();
+        w.pop().unwrap();
+        assert_eq!(w.position(), (107, 200));
+        w.set_rule(10, 20).unwrap();
+        w.put_rule(3, 4).unwrap();
+        w.special(b"color push").unwrap();
+        w.end_page().unwrap();
+
+        let bop2 = w.current_offset() as i32;
+        w.begin_page(&counters(2)).unwrap();
+        w.select_font(300).unwrap();
+        w.move_to(40, 70).unwrap();
+        w.glyph_run(&GlyphRun {
+            text: None,
+            glyphs: &[1, 2],
+            h: &[40, 50],
+            v: &[70, 72],
+            width: 30,
+        })
+        .unwrap();
+        w.glyph_run(&GlyphRun {
+            text: Some(&[0x48, 0x69]),
+            glyphs: &[3],
+            h: &[75],
+            v: &[70],
+            width: 20,
+        })
+        .unwrap();
+        assert_eq!(w.position(), (90, 70));
+        w.end_page().unwrap();
+        assert_eq!(w.n_pages(), 2);
+
+        let data = w.finish().unwrap();
+        let events = parse(&data).unwrap();
+
+        let fonts = vec![
+            Event::FontDefinition(5, tfm_font()),
+            Event::NativeFontDefinition(300, native_font()),
+        ];
+        let mut expected = vec![Event::Header(FileType::Xdv, b"test".to_vec())];
+        expected.extend(fonts.iter().cloned());
+        expected.extend(vec![
+            Event::BeginPage(counters(1), -1),
+            Event::SelectFont(5),
+            Event::Char(5, 65, 100, 200),
+            Event::CharRun(vec![65]),
+            Event::Char(5, 200, 1107, 150),
+            Event::Char(5, 66, 1114, 150),
+            Event::CharRun(vec![200]),
+            Event::Rule(107, 200, 10, 20),
+            Event::Rule(127, 200, 3, 4),
+            Event::Special(127, 200, b"color push".to_vec()),
+            Event::EndPage,
+            Event::BeginPage(counters(2), bop1),
+            Event::SelectFont(300),
+            Event::GlyphRun(300, None, vec![1, 2], vec![40, 50], vec![70, 72], 30),
+            Event::GlyphRun(300, Some(vec![0x48, 0x69]), vec![3], vec![75], vec![70], 20),
+            Event::EndPage,
+        ]);
+        expected.extend(fonts);
+        assert_eq!(events, expected);
+
+        // The file ends with the postamble pointer, the ID byte, and four to
+        // seven bytes of padding that bring it to a multiple of four bytes.
+        assert_eq!(data.len() % 4, 0);
+        let n_pad = data.iter().rev().take_while(|b| **b == 223).count();
+        assert!((4..=7).contains(&n_pad), "{} bytes of padding", n_pad);
+        let id = data.len() - n_pad - 1;
+        assert_eq!(data[id], IdByte::Xdv as u8);
+        assert_eq!(data[id - 5], Opcode::DoublePostamble as u8);
+
+        let post = i32_at(&data, id - 4) as usize;
+        assert_eq!(data[post], Opcode::Postamble as u8);
+        assert_eq!(i32_at(&data, post + 1), bop2);
+        assert_eq!(i32_at(&data, post + 5) as u32, UNIT_NUMERATOR);
+        assert_eq!(i32_at(&data, post + 9) as u32, UNIT_DENOMINATOR);
+        assert_eq!(i32_at(&data, post + 13), 1000);
+        assert_eq!(i32_at(&data, post + 17), 200); // max v
+        assert_eq!(i32_at(&data, post + 21), 1114); // max h
+        assert_eq!(u16_at(&data, post + 25), 1); // max stack depth
+        assert_eq!(u16_at(&data, post + 27), 2); // pages
+
+        // Each page points back at the one before it.
+        assert_eq!(data[bop2 as usize], Opcode::BeginningOfPage as u8);
+        assert_eq!(i32_at(&data, bop2 as usize + 41), bop1);
+        assert_eq!(data[bop1 as usize], Opcode::BeginningOfPage as u8);
+        assert_eq!(i32_at(&data, bop1 as usize + 41), -1);
+    }
+
+    #[test]
+    fn padding_depends_on_length() {
+        for n in 0..4 {
+            let mut w = XdvWriter::new(Vec::new(), FileType::Spx, &b"xyz"[..n]).unwrap();
+            w.begin_page(&counters(1)).unwrap();
+            w.end_page().unwrap();
+            let data = w.finish().unwrap();
+
+            assert_eq!(data.len() % 4, 0);
+            let n_pad = data.iter().rev().take_while(|b| **b == 223).count();
+            assert!((4..=7).contains(&n_pad), "{} bytes of padding", n_pad);
+            assert_eq!(data[data.len() - n_pad - 1], IdByte::Spx as u8);
+        }
+    }
+
+    #[test]
+    fn font_name_too_long() {
+        let mut def = tfm_font();
+        def.area = "a".repeat(256);
+        assert_invalid(
+            writer().define_font(0, &def),
+            "font area or name is longer than 255 bytes",
+        );
+    }
+
+    #[test]
+    fn native_font_path_too_long() {
+        let mut def = native_font();
+        def.path = "a".repeat(256);
+        assert_invalid(
+            writer().define_native_font(0, &def),
+            "native font path is longer than 255 bytes",
+        );
+    }
+
+    #[test]
+    fn too_many_variations() {
+        let mut def = native_font();
+        def.variations = vec![(0, 0); 0x10000];
+        assert_invalid(
+            writer().define_native_font(0, &def),
+            "too many font variation settings",
+        );
+    }
+
+    #[test]
+    fn nested_page() {
+        assert_invalid(
+            writer_in_page().begin_page(&counters(2)),
+            "cannot begin a page inside another page",
+        );
+    }
+
+    #[test]
+    fn wrong_number_of_counters() {
+        assert_invalid(
+            writer().begin_page(&[1, 2, 3]),
+            "pages must have exactly ten counters",
+        );
+    }
+
+    #[test]
+    fn output_too_large() {
+        let mut w = writer();
+        w.offset = i32::MAX as u64 + 1;
+        assert_invalid(w.begin_page(&counters(1)), "XDV output exceeds 2 GiB");
+    }
+
+    #[test]
+    fn unbalanced_page() {
+        let mut w = writer_in_page();
+        w.push().unwrap();
+        assert_invalid(w.end_page(), "unbalanced push/pop at end of page");
+    }
+
+    #[test]
+    fn stack_too_deep() {
+        let mut w = writer_in_page();
+
+        for _ in 0..MAX_STACK_DEPTH {
+            w.push().unwrap();
+        }
+
+        assert_invalid(w.push(), "XDV stack depth exceeded");
+    }
+
+    #[test]
+    fn unmatched_pop() {
+        assert_invalid(writer_in_page().pop(), "pop without matching push");
+    }
+
+    #[test]
+    fn undefined_font() {
+        assert_invalid(
+            writer_in_page().select_font(1),
+            "selected an undefined font",
+        );
+    }
+
+    #[test]
+    fn mismatched_glyph_positions() {
+        let mut w = writer_in_page();
+        w.select_font(0).unwrap();
+        let run = GlyphRun {
+            text: None,
+            glyphs: &[1, 2],
+            h: &[0, 0],
+            v: &[0],
+            width: 0,
+        };
+        assert_invalid(
+            w.glyph_run(&run),
+            "glyph run has mismatched position arrays",
+        );
+    }
+
+    #[test]
+    fn too_many_glyphs() {
+        let mut w = writer_in_page();
+        w.select_font(0).unwrap();
+        let glyphs = vec![0; 0x10000];
+        let positions = vec![0; 0x10000];
+        let run = GlyphRun {
+            text: None,
+            glyphs: &glyphs,
+            h: &positions,
+            v: &positions,
+            width: 0,
+        };
+        assert_invalid(w.glyph_run(&run), "too many glyphs in one run");
+    }
+
+    #[test]
+    fn too_much_text() {
+        let mut w = writer_in_page();
+        w.select_font(0).unwrap();
+        let text = vec![0x20; 0x10000];
+        let run = GlyphRun {
+            text: Some(&text),
+            glyphs: &[1],
+            h: &[0],
+            v: &[0],
+            width: 0,
+        };
+        assert_invalid(w.glyph_run(&run), "too much text in one glyph run");
+    }
+
+    /// This allocates 4 GiB of zeros, which the OS should only map lazily.
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn special_too_long() {
+        let contents = vec![0u8; u32::MAX as usize + 1];
+        assert_invalid(writer_in_page().special(&contents), "special is too long");
+    }
+
+    #[test]
+    fn finish_inside_page() {
+        assert_invalid(
+            writer_in_page().finish(),
+            "cannot finish the output inside a page",
+        );
+    }
+
+    #[test]
+    fn too_many_pages() {
+        let mut w = writer();
+        w.n_pages = 0x10000;
+        assert_invalid(w.finish(), "too many pages for the XDV format");
+    }
+
+    #[test]
+    fn drawing_outside_page() {
+        assert_invalid(
+            writer().set_rule(1, 1),
+            "drawing operation outside of a page",
+        );
+    }
+
+    #[test]
+    fn char_without_font() {
+        assert_invalid(writer_in_page().set_char(65, 7), "no font selected");
+    }
+}